@@ -119,6 +119,7 @@ let logBuffer = [];
 let failedBatches = [];
 let sessionId = generateSessionId();
 let clientId = null;
+let username = null; // set by an admin (or managed storage) for directory lookups
 let scheduledFlushTimeout = null;
 
 // Statistics
@@ -167,7 +168,7 @@ chrome.storage.local.get([
   'blockList', 'enableBlocking', 'whitelistedYouTubeChannels', 'youtubeChannelWhitelistEnabled',
   'serverUrl', 'maxBufferSize', 'enableLocalBackup', 'domainWhitelist', 'enableDomainWhitelist',
   'enableReportUrls', 'enableJsExecution', 'enableClickfix', 'extensionMonitoring',
-  'enableFileUploadMonitor', 'clientId'
+  'enableFileUploadMonitor', 'clientId', 'username'
 ], (result) => {
   if (result.blockList) CONFIG.blockList = result.blockList;
   if (result.enableBlocking !== undefined) CONFIG.enableBlocking = result.enableBlocking;
@@ -204,6 +205,7 @@ chrome.storage.local.get([
     clientId = generateClientId();
     chrome.storage.local.set({ clientId });
   }
+  if (result.username) username = result.username;
   
   console.log('✅ Configuration loaded:', CONFIG);
   console.log(`📋 Whitelist: ${CONFIG.enableDomainWhitelist ? 'ENABLED' : 'DISABLED'} (${PREDEFINED_WHITELIST.length} predefined domains + ${CONFIG.domainWhitelist.length} user-defined)`);
@@ -232,6 +234,10 @@ chrome.storage.onChanged.addListener((changes) => {
       clientId = changes[key].newValue;
       console.log(`🆔 Client ID updated: ${clientId}`);
     }
+    if (key === 'username') {
+      username = changes[key].newValue || null;
+      console.log(`👤 Username updated: ${username}`);
+    }
   });
 });
 
@@ -275,6 +281,68 @@ async function sendFileUploadDetectionToServer(event) {
   }
 }
 
+// Report the extension's own uncaught errors to /api/errors (the server groups them by stack).
+// The same error is sent at most once a minute.
+const reportedErrors = new Map();
+async function reportExtensionError(error, source) {
+  const message = String((error && error.message) || error || 'Unknown error');
+  const stack = error && error.stack ? String(error.stack) : null;
+  const key = stack || message;
+  const now = Date.now();
+  if (now - (reportedErrors.get(key) || 0) < 60000) return;
+  reportedErrors.set(key, now);
+  if (reportedErrors.size > 100) reportedErrors.delete(reportedErrors.keys().next().value);
+  const errorsUrl = CONFIG.serverUrl.replace('/api/logs', '/api/errors');
+  try {
+    await fetchWithTimeout(errorsUrl, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        client_id: clientId || null,
+        message,
+        stack,
+        source,
+        extension_version: chrome.runtime.getManifest().version
+      })
+    }, CONFIG.fetchTimeoutMs);
+  } catch (_) {
+    // Never report a failure to report.
+  }
+}
+self.addEventListener('error', e => reportExtensionError(e.error || e.message, 'background'));
+self.addEventListener('unhandledrejection', e => reportExtensionError(e.reason, 'background'));
+
+// Performance samples for /api/telemetry: how long list matching and batch building take, and
+// memory use. Kept to the last 500 of each and sent every 5 minutes.
+const perfSamples = { blocklist_match_ms: [], batch_build_ms: [], memory_mb: [] };
+function recordPerfSample(metric, value) {
+  const samples = perfSamples[metric];
+  samples.push(Math.max(0, value));
+  if (samples.length > 500) samples.shift();
+}
+async function sendPerfTelemetry() {
+  const memory = performance.memory && performance.memory.usedJSHeapSize;
+  if (memory) recordPerfSample('memory_mb', memory / (1024 * 1024));
+  if (!Object.values(perfSamples).some(s => s.length > 0)) return;
+  const samples = {};
+  for (const [metric, values] of Object.entries(perfSamples)) samples[metric] = values.splice(0);
+  const telemetryUrl = CONFIG.serverUrl.replace('/api/logs', '/api/telemetry');
+  try {
+    await fetchWithTimeout(telemetryUrl, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        client_id: clientId || null,
+        extension_version: chrome.runtime.getManifest().version,
+        blocklist_rules: CONFIG.blockList.length,
+        samples
+      })
+    }, CONFIG.fetchTimeoutMs);
+  } catch (_) {
+    // Samples are best effort; drop them rather than pile them up.
+  }
+}
+
 // Test server connection
 async function testServerConnection(url) {
   try {
@@ -315,7 +383,15 @@ function generateClientId() {
 
 function isWhitelisted(url) {
   if (!CONFIG.enableDomainWhitelist) return false;
-  
+  const started = performance.now();
+  try {
+    return matchesWhitelist(url);
+  } finally {
+    recordPerfSample('blocklist_match_ms', performance.now() - started);
+  }
+}
+
+function matchesWhitelist(url) {
   try {
     const urlObj = new URL(url);
     const domain = urlObj.hostname.toLowerCase();
@@ -983,8 +1059,10 @@ async function sendLogBatch() {
   // Send network logs to /api/logs
   if (networkLogs.length > 0) {
     console.log(`📤 Sending ${networkLogs.length} network log(s) to /api/logs:`, networkLogs.map(l => l.type).join(', '));
+    const buildStarted = performance.now();
     const payload = {
       client_id: clientId || null,
+      username: username || null,
       session_id: sessionId,  // Server expects session_id (snake_case)
       timestamp: new Date().toISOString(),
       user_agent: navigator.userAgent,
@@ -1001,6 +1079,7 @@ async function sendLogBatch() {
     const payloadBody = CONFIG.enableCompression 
       ? await compressData(payload) 
       : JSON.stringify(payload);
+    recordPerfSample('batch_build_ms', performance.now() - buildStarted);
     
     const originalSize = JSON.stringify(payload).length;
     const compressedSize = typeof payloadBody === 'string' ? payloadBody.length : payloadBody.byteLength;
@@ -1085,6 +1164,7 @@ try {
     chrome.alarms.create('flushLogs', { periodInMinutes: 1 });
     chrome.alarms.create('refreshExtensions', { periodInMinutes: 5 });
     chrome.alarms.create('scanExtensions', { periodInMinutes: 30 });
+    chrome.alarms.create('sendTelemetry', { periodInMinutes: 5 });
 
     chrome.alarms.onAlarm.addListener((alarm) => {
       if (alarm.name === 'flushLogs') {
@@ -1102,6 +1182,10 @@ try {
       if (alarm.name === 'scanExtensions') {
         scanAllExtensions();
       }
+
+      if (alarm.name === 'sendTelemetry') {
+        sendPerfTelemetry();
+      }
     });
   } else {
     // Fallback (should rarely happen)
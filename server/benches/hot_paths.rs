@@ -0,0 +1,225 @@
+//! Benchmarks of the paths every ingest or blocklist request goes through:
+//! gunzipping and parsing a batch, the in-memory store under concurrent
+//! writers, matching URLs against the blocklist, and serializing batches.
+//!
+//! Run with `cargo bench --bench hot_paths`. To compare a change against
+//! the current code, save a baseline first and compare against it after:
+//!
+//! ```text
+//! cargo bench --bench hot_paths -- --save-baseline before
+//! cargo bench --bench hot_paths -- --baseline before
+//! ```
+
+use actix_web::test::TestRequest;
+use actix_web::web::Bytes;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use network_logger_server::bloom::BloomFilter;
+use network_logger_server::handlers::common::decompress_body_if_needed;
+use network_logger_server::simple::SimpleState;
+use network_logger_server::stream_parse::parse_log_batch;
+use network_logger_server::types::{Blocklist, LogEntry, NetworkLog};
+use std::hint::black_box;
+use std::io::Write;
+use std::sync::Arc;
+
+fn network_log(i: usize) -> NetworkLog {
+    NetworkLog {
+        request_id: format!("req-{}", i),
+        url: format!(
+            "https://cdn{}.example{}.com/assets/app.js?v={}",
+            i % 7,
+            i % 50,
+            i
+        ),
+        method: "GET".to_string(),
+        request_type: if i.is_multiple_of(10) {
+            "main_frame"
+        } else {
+            "script"
+        }
+        .to_string(),
+        blocked: i.is_multiple_of(20),
+        block_reason: None,
+        reputation_score: None,
+        request_size: Some(512),
+        response_size: Some(16_384),
+        category: None,
+    }
+}
+
+fn batch(size: usize) -> LogEntry {
+    LogEntry {
+        client_id: Some("bench-client".to_string()),
+        profile_id: None,
+        session_id: "bench-session".to_string(),
+        timestamp: "2024-01-01T00:00:00Z".to_string(),
+        user_agent: "Mozilla/5.0 (bench)".to_string(),
+        logs: (0..size).map(network_log).collect(),
+        username: None,
+        clock_skew_ms: None,
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gzip_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gzip_parse");
+    for size in [10, 100, 1000] {
+        let json = serde_json::to_vec(&batch(size)).unwrap();
+        let body = Bytes::from(gzip(&json));
+        let req = TestRequest::post()
+            .insert_header(("content-encoding", "gzip"))
+            .to_http_request();
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_with_input(BenchmarkId::new("buffered", size), &body, |b, body| {
+            b.iter(|| {
+                let text = decompress_body_if_needed(&req, body).unwrap();
+                let entry: LogEntry = serde_json::from_str(&text).unwrap();
+                black_box(entry)
+            })
+        });
+        // What /api/logs does.
+        group.bench_with_input(BenchmarkId::new("streaming", size), &body, |b, body| {
+            b.iter(|| black_box(parse_log_batch(&req, body, None).unwrap().entry))
+        });
+    }
+    group.finish();
+}
+
+fn simple_state(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simple_state");
+    let entry = batch(20);
+    group.bench_function("add_log", |b| {
+        let state = SimpleState::new();
+        b.iter_batched(
+            || entry.clone(),
+            |e| state.add_log(e),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("get_logs_full", |b| {
+        let state = SimpleState::new();
+        for _ in 0..1000 {
+            state.add_log(entry.clone());
+        }
+        b.iter(|| black_box(state.get_logs()))
+    });
+    // Each iteration: `threads` writers adding 50 batches each while one
+    // reader keeps listing, all on one store.
+    for threads in [2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("contended", threads),
+            &threads,
+            |b, &threads| {
+                let state = Arc::new(SimpleState::new());
+                b.iter(|| {
+                    std::thread::scope(|s| {
+                        for _ in 0..threads {
+                            let state = &state;
+                            let entry = &entry;
+                            s.spawn(move || {
+                                for _ in 0..50 {
+                                    state.add_log(entry.clone());
+                                }
+                            });
+                        }
+                        s.spawn(|| {
+                            for _ in 0..10 {
+                                black_box(state.get_logs());
+                            }
+                        });
+                    });
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn blocklist(size: usize) -> Blocklist {
+    let mut url_patterns: Vec<String> = (0..size)
+        .map(|i| Blocklist::domain_pattern(&format!("blocked{}.example.org", i)))
+        .collect();
+    url_patterns.push(r".*/ads/.*".to_string());
+    url_patterns.push(r".*[?&]utm_source=.*".to_string());
+    Blocklist {
+        url_patterns,
+        youtube_channels: Vec::new(),
+        profiles: Default::default(),
+    }
+}
+
+/// Half blocked, half not.
+fn urls() -> Vec<String> {
+    (0..100)
+        .map(|i| match i % 2 {
+            0 => format!("https://www.blocked{}.example.org/index.html", i * 3),
+            _ => format!("https://allowed{}.example.net/page?id={}", i, i),
+        })
+        .collect()
+}
+
+fn blocklist_matching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blocklist_match");
+    let urls = urls();
+    group.throughput(Throughput::Elements(urls.len() as u64));
+    for size in [100, 500, 1000] {
+        let blocklist = blocklist(size);
+        // What an extension compiles the served patterns into; thousands
+        // of patterns need more than the default compiled size limit.
+        let set = regex::RegexSetBuilder::new(&blocklist.url_patterns)
+            .size_limit(1 << 30)
+            .build()
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("regex_set", size), &urls, |b, urls| {
+            b.iter(|| urls.iter().filter(|u| set.is_match(u)).count())
+        });
+        let mut filter = BloomFilter::new(size, 0.001);
+        for pattern in &blocklist.url_patterns {
+            if let Some(domain) = Blocklist::pattern_domain(pattern) {
+                filter.insert(&domain);
+            }
+        }
+        let hosts: Vec<&str> = urls
+            .iter()
+            .map(|u| u.split('/').nth(2).unwrap_or_default())
+            .collect();
+        group.bench_with_input(BenchmarkId::new("bloom", size), &hosts, |b, hosts| {
+            b.iter(|| hosts.iter().filter(|h| filter.blocks_host(h)).count())
+        });
+    }
+    group.finish();
+}
+
+fn batch_serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_serialize");
+    for size in [10, 100, 1000] {
+        let entry = batch(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("to_vec", size), &entry, |b, entry| {
+            b.iter(|| black_box(serde_json::to_vec(entry).unwrap()))
+        });
+        let entries: Vec<LogEntry> = (0..10).map(|_| entry.clone()).collect();
+        group.bench_with_input(
+            BenchmarkId::new("export_10", size),
+            &entries,
+            |b, entries| b.iter(|| black_box(serde_json::to_vec(entries).unwrap())),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    gzip_parse,
+    simple_state,
+    blocklist_matching,
+    batch_serialization
+);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Arbitrary request bodies, sent both with `Content-Encoding: gzip` and
+//! without it (where a body starting with the gzip magic number is still
+//! gunzipped), then parsed as the event handlers do, and through the
+//! streaming parser `/api/logs` uses, which must take every batch object
+//! the buffered parse takes and read it the same.
+
+#![no_main]
+
+use actix_web::test::TestRequest;
+use actix_web::web::Bytes;
+use actix_web::HttpRequest;
+use libfuzzer_sys::fuzz_target;
+use network_logger_server::handlers::common::decompress_body_if_needed;
+use network_logger_server::stream_parse::parse_log_batch;
+use network_logger_server::types::{ExtensionEvent, LogEntry};
+
+thread_local! {
+    // Built once, before leak checking starts: an actix request holds on
+    // to its pool, which the leak checker reports.
+    static REQUESTS: [HttpRequest; 2] = [
+        TestRequest::post().to_http_request(),
+        TestRequest::post()
+            .insert_header(("content-encoding", "gzip"))
+            .to_http_request(),
+    ];
+}
+
+fuzz_target!(
+    init: {
+        REQUESTS.with(|_| ());
+    },
+    |data: &[u8]| {
+        let body = Bytes::copy_from_slice(data);
+        REQUESTS.with(|requests| {
+            for req in requests {
+                let streamed = parse_log_batch(req, &body, None);
+                let _ = parse_log_batch(req, &body, Some(2));
+                let Ok(text) = decompress_body_if_needed(req, &body) else {
+                    continue;
+                };
+                let _ = serde_json::from_str::<ExtensionEvent>(&text);
+                // The derived Deserialize also takes the fields as an array,
+                // which no client sends.
+                if let (Ok(buffered), true) = (
+                    serde_json::from_str::<LogEntry>(&text),
+                    text.trim_start().starts_with('{'),
+                ) {
+                    let streamed = streamed.expect("streaming parse refused a batch");
+                    assert_eq!(
+                        serde_json::to_string(&buffered).unwrap(),
+                        serde_json::to_string(&streamed.entry).unwrap()
+                    );
+                }
+            }
+        });
+    }
+);
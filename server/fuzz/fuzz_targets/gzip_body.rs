@@ -0,0 +1,55 @@
+//! JSON-ish content in hostile gzip framing: the input is compressed, then
+//! the stream is truncated and trailing bytes appended as the first two
+//! bytes say, so the fuzzer reaches the decompressor's error paths with
+//! well-formed headers rather than stopping at the magic number.
+
+#![no_main]
+
+use actix_web::test::TestRequest;
+use actix_web::web::Bytes;
+use actix_web::HttpRequest;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use libfuzzer_sys::fuzz_target;
+use network_logger_server::handlers::common::decompress_body_if_needed;
+use network_logger_server::types::{ExtensionEvent, LogEntry};
+use std::io::Write;
+
+thread_local! {
+    // See decompress_body.rs.
+    static REQUESTS: [HttpRequest; 2] = [
+        TestRequest::post().to_http_request(),
+        TestRequest::post()
+            .insert_header(("content-encoding", "gzip"))
+            .to_http_request(),
+    ];
+}
+
+fuzz_target!(
+    init: {
+        REQUESTS.with(|_| ());
+    },
+    |data: &[u8]| {
+        let [cut, trailing, content @ ..] = data else {
+            return;
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(content).unwrap();
+        let mut stream = encoder.finish().unwrap();
+        // Drop up to 255 bytes off the end (a truncated upload), then
+        // append up to 255 bytes of the content again (junk after the
+        // member).
+        stream.truncate(stream.len().saturating_sub(*cut as usize));
+        stream.extend(content.iter().take(*trailing as usize));
+
+        let body = Bytes::from(stream);
+        REQUESTS.with(|requests| {
+            for req in requests {
+                if let Ok(text) = decompress_body_if_needed(req, &body) {
+                    let _ = serde_json::from_str::<LogEntry>(&text);
+                    let _ = serde_json::from_str::<ExtensionEvent>(&text);
+                }
+            }
+        });
+    }
+);
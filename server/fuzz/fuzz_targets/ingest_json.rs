@@ -0,0 +1,69 @@
+//! Deserialization of `/api/logs` and `/api/extension/*` bodies, and what
+//! the handlers do right after with a batch that parsed.
+//!
+//! The first byte picks the type (lowest bit) and a nesting depth (the
+//! rest, times four): the remaining input is wrapped that many arrays deep
+//! under the event's `data`, or under an unknown key of a log batch, to
+//! check that deeply nested bodies fail cleanly instead of overflowing
+//! the stack.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use network_logger_server::counters::BatchSummary;
+use network_logger_server::handlers::common::domain_from_url;
+use network_logger_server::types::{ExtensionEvent, LogEntry};
+
+fn nested(value: &[u8], depth: usize, prefix: &str, suffix: &str) -> Vec<u8> {
+    let mut body = Vec::with_capacity(value.len() + 2 * depth + prefix.len() + suffix.len());
+    body.extend_from_slice(prefix.as_bytes());
+    body.extend(std::iter::repeat_n(b'[', depth));
+    body.extend_from_slice(value);
+    body.extend(std::iter::repeat_n(b']', depth));
+    body.extend_from_slice(suffix.as_bytes());
+    body
+}
+
+fn log_entry(body: &[u8]) {
+    let Ok(entry) = serde_json::from_slice::<LogEntry>(body) else {
+        return;
+    };
+    let _ = BatchSummary::of(&entry, 0);
+    for log in &entry.logs {
+        let _ = domain_from_url(&log.url);
+    }
+    let text = serde_json::to_string(&entry).unwrap();
+    serde_json::from_str::<LogEntry>(&text).unwrap();
+}
+
+fn extension_event(body: &[u8]) {
+    let Ok(event) = serde_json::from_slice::<ExtensionEvent>(body) else {
+        return;
+    };
+    let _ = event.data.get("packet_id");
+    let _ = event.data.to_string();
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, rest)) = data.split_first() else {
+        return;
+    };
+    let depth = (selector >> 1) as usize * 4;
+    if selector & 1 == 0 {
+        log_entry(rest);
+        log_entry(&nested(
+            rest,
+            depth,
+            r#"{"session_id":"s","timestamp":"t","user_agent":"u","logs":[],"extra":"#,
+            "}",
+        ));
+    } else {
+        extension_event(rest);
+        extension_event(&nested(
+            rest,
+            depth,
+            r#"{"session_id":"s","timestamp":"t","user_agent":"u","event_type":"e","data":"#,
+            "}",
+        ));
+    }
+});
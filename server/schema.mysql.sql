@@ -0,0 +1,212 @@
+-- ============================================================================
+-- Network Logger Server - MySQL / MariaDB Schema
+-- For Production Mode with --features mysql and --database-url mysql://...
+-- Timestamps are stored in UTC.
+-- ============================================================================
+
+-- Network Logs Table
+CREATE TABLE IF NOT EXISTS network_logs (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    client_id VARCHAR(200),
+    session_id VARCHAR(100) NOT NULL,
+    timestamp DATETIME(6) NOT NULL,
+    user_agent TEXT,
+    request_id VARCHAR(100) NOT NULL,
+    url TEXT NOT NULL,
+    method VARCHAR(20) NOT NULL,
+    request_type VARCHAR(50),
+    blocked BOOLEAN DEFAULT false,
+    block_reason TEXT,
+    reputation_score SMALLINT,
+    request_size BIGINT,
+    response_size BIGINT,
+    category VARCHAR(50),
+    profile_id VARCHAR(100),
+    clock_skew_ms BIGINT,
+    created_at DATETIME(6) DEFAULT CURRENT_TIMESTAMP(6),
+
+    INDEX idx_session_id (session_id),
+    INDEX idx_timestamp (timestamp),
+    INDEX idx_client_timestamp (client_id, timestamp),
+    INDEX idx_blocked (blocked),
+    INDEX idx_created_at (created_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Blocklist Patterns Table
+CREATE TABLE IF NOT EXISTS blocklist_patterns (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    pattern VARCHAR(512) NOT NULL UNIQUE,
+    type VARCHAR(20) NOT NULL CHECK (type IN ('url', 'youtube')),
+    description TEXT,
+    added_by VARCHAR(100),
+    added_at DATETIME(6) DEFAULT CURRENT_TIMESTAMP(6),
+    active BOOLEAN DEFAULT true,
+
+    INDEX idx_type_active (type, active)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Per-Profile Blocklist Entries (added to the shared list for that profile)
+CREATE TABLE IF NOT EXISTS profile_blocklist_patterns (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    profile_id VARCHAR(100) NOT NULL,
+    pattern VARCHAR(512) NOT NULL,
+    type VARCHAR(20) NOT NULL CHECK (type IN ('url', 'youtube')),
+
+    UNIQUE KEY uniq_profile_pattern (profile_id, type, pattern)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Extension Events Table
+CREATE TABLE IF NOT EXISTS extension_events (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    client_id VARCHAR(200),
+    session_id VARCHAR(100) NOT NULL,
+    timestamp DATETIME(6) NOT NULL,
+    user_agent TEXT,
+    event_type VARCHAR(50) NOT NULL,
+    data JSON NOT NULL,
+    profile_id VARCHAR(100),
+    -- Indexed copy of data.packet_id for dashboard lookups
+    packet_id VARCHAR(100) AS (JSON_UNQUOTE(JSON_EXTRACT(data, '$.packet_id'))) STORED,
+    created_at DATETIME(6) DEFAULT CURRENT_TIMESTAMP(6),
+
+    INDEX idx_session_id (session_id),
+    INDEX idx_timestamp (timestamp),
+    INDEX idx_event_type (event_type),
+    INDEX idx_packet_id (packet_id),
+    INDEX idx_created_at (created_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Observed Domains Table (passive DNS-style first/last seen)
+CREATE TABLE IF NOT EXISTS observed_domains (
+    domain VARCHAR(255) PRIMARY KEY,
+    first_seen DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    last_seen DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    request_count BIGINT NOT NULL DEFAULT 0,
+
+    INDEX idx_first_seen (first_seen)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS observed_domain_clients (
+    domain VARCHAR(255) NOT NULL,
+    client_id VARCHAR(200) NOT NULL,
+    PRIMARY KEY (domain, client_id),
+    FOREIGN KEY (domain) REFERENCES observed_domains(domain)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Archived clients: their history stays queryable, new ingest is refused
+CREATE TABLE IF NOT EXISTS archived_clients (
+    client_id VARCHAR(200) PRIMARY KEY,
+    archived_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    archived_by VARCHAR(100),
+    reason TEXT
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Analyst labels and notes on sessions and clients
+CREATE TABLE IF NOT EXISTS annotations (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    scope VARCHAR(10) NOT NULL CHECK (scope IN ('session', 'client')),
+    target VARCHAR(200) NOT NULL,
+    label VARCHAR(100),
+    note TEXT,
+    created_by VARCHAR(100),
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+
+    INDEX idx_target (scope, target)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Triage status of dashboard events; only the latest decision is kept
+CREATE TABLE IF NOT EXISTS event_triage (
+    packet_id VARCHAR(100) PRIMARY KEY,
+    status VARCHAR(20) NOT NULL CHECK (status IN ('new', 'acknowledged', 'false-positive', 'escalated')),
+    note TEXT,
+    assignee VARCHAR(100),
+    updated_by VARCHAR(100),
+    updated_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Analyst comments on events; replies point at the comment they answer
+CREATE TABLE IF NOT EXISTS event_comments (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    packet_id VARCHAR(100) NOT NULL,
+    parent_id BIGINT,
+    author VARCHAR(100),
+    body TEXT NOT NULL,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+
+    INDEX idx_packet (packet_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Issue-tracker tickets opened for escalated events
+CREATE TABLE IF NOT EXISTS case_tickets (
+    packet_id VARCHAR(100) PRIMARY KEY,
+    provider VARCHAR(20) NOT NULL,
+    ticket_key VARCHAR(100) NOT NULL,
+    url TEXT NOT NULL,
+    closed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME(6) NOT NULL,
+    closed_at DATETIME(6),
+    synced_at DATETIME(6)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Exceptions the extension reported about itself, one row per stack hash
+CREATE TABLE IF NOT EXISTS extension_errors (
+    hash VARCHAR(64) PRIMARY KEY,
+    message TEXT NOT NULL,
+    stack MEDIUMTEXT,
+    source VARCHAR(100),
+    extension_version VARCHAR(50),
+    last_client_id VARCHAR(200),
+    count BIGINT NOT NULL DEFAULT 1,
+    first_seen DATETIME(6) NOT NULL,
+    last_seen DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Local dashboard and API accounts; secrets are stored hashed
+CREATE TABLE IF NOT EXISTS local_users (
+    username VARCHAR(64) PRIMARY KEY,
+    display_name VARCHAR(100),
+    role VARCHAR(20) NOT NULL CHECK (role IN ('viewer', 'analyst', 'admin')),
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    password_hash TEXT,
+    token_hash VARCHAR(64) UNIQUE,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    updated_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Dashboard aggregates for /api/stats. MySQL has no materialized views, so
+-- the server rebuilds these summary tables on --stats-refresh-interval.
+CREATE TABLE IF NOT EXISTS domain_stats (
+    domain VARCHAR(255) PRIMARY KEY,
+    requests BIGINT NOT NULL,
+    blocked BIGINT NOT NULL,
+    clients BIGINT NOT NULL,
+    last_seen DATETIME(6),
+
+    INDEX idx_requests (requests)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS client_stats (
+    client_id VARCHAR(200) PRIMARY KEY,
+    requests BIGINT NOT NULL,
+    blocked BIGINT NOT NULL,
+    domains BIGINT NOT NULL,
+    sessions BIGINT NOT NULL,
+    last_seen DATETIME(6),
+
+    INDEX idx_requests (requests)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS category_stats (
+    category VARCHAR(50) PRIMARY KEY,
+    requests BIGINT NOT NULL,
+    blocked BIGINT NOT NULL,
+    domains BIGINT NOT NULL,
+    clients BIGINT NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Insert default blocklist patterns
+INSERT IGNORE INTO blocklist_patterns (pattern, type, description) VALUES
+    ('.*tracker\\..*', 'url', 'Block tracking domains'),
+    ('.*analytics\\..*', 'url', 'Block analytics domains'),
+    ('.*doubleclick\\..*', 'url', 'Block ad networks'),
+    ('.*ads\\..*', 'url', 'Block ad domains');
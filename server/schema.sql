@@ -16,6 +16,12 @@ CREATE TABLE IF NOT EXISTS network_logs (
     request_type VARCHAR(50),
     blocked BOOLEAN DEFAULT false,
     block_reason TEXT,
+    reputation_score SMALLINT,
+    request_size BIGINT,
+    response_size BIGINT,
+    category VARCHAR(50),
+    profile_id VARCHAR(100),
+    clock_skew_ms BIGINT,
     created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
     
     INDEX idx_session_id (session_id),
@@ -24,6 +30,18 @@ CREATE TABLE IF NOT EXISTS network_logs (
     INDEX idx_url_hash (MD5(url))
 );
 
+-- Columns added after the first release; CREATE TABLE IF NOT EXISTS leaves an
+-- existing table as it was.
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS reputation_score SMALLINT;
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS request_size BIGINT;
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS response_size BIGINT;
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS category VARCHAR(50);
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS profile_id VARCHAR(100);
+ALTER TABLE network_logs ADD COLUMN IF NOT EXISTS clock_skew_ms BIGINT;
+
+-- Per-client summaries scan one client's logs by time.
+CREATE INDEX IF NOT EXISTS idx_client_timestamp ON network_logs (client_id, timestamp);
+
 -- Blocklist Patterns Table
 CREATE TABLE IF NOT EXISTS blocklist_patterns (
     id SERIAL PRIMARY KEY,
@@ -39,6 +57,16 @@ CREATE TABLE IF NOT EXISTS blocklist_patterns (
     INDEX idx_type_active (type, active)
 );
 
+-- Per-Profile Blocklist Entries (added to the shared list for that profile)
+CREATE TABLE IF NOT EXISTS profile_blocklist_patterns (
+    id BIGSERIAL PRIMARY KEY,
+    profile_id VARCHAR(100) NOT NULL,
+    pattern TEXT NOT NULL,
+    type VARCHAR(20) NOT NULL CHECK (type IN ('url', 'youtube')),
+    
+    UNIQUE (profile_id, type, pattern)
+);
+
 -- Extension Events Table
 CREATE TABLE IF NOT EXISTS extension_events (
     id BIGSERIAL PRIMARY KEY,
@@ -48,6 +76,7 @@ CREATE TABLE IF NOT EXISTS extension_events (
     user_agent TEXT,
     event_type VARCHAR(50) NOT NULL,
     data JSONB NOT NULL,
+    profile_id VARCHAR(100),
     created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
     
     INDEX idx_session_id (session_id),
@@ -56,6 +85,142 @@ CREATE TABLE IF NOT EXISTS extension_events (
     INDEX idx_data_gin (data) USING GIN
 );
 
+-- Observed Domains Table (passive DNS-style first/last seen)
+CREATE TABLE IF NOT EXISTS observed_domains (
+    domain TEXT PRIMARY KEY,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    request_count BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_first_seen ON observed_domains (first_seen);
+
+CREATE TABLE IF NOT EXISTS observed_domain_clients (
+    domain TEXT NOT NULL REFERENCES observed_domains(domain),
+    client_id VARCHAR(200) NOT NULL,
+    PRIMARY KEY (domain, client_id)
+);
+
+-- Archived clients: their history stays queryable, new ingest is refused
+CREATE TABLE IF NOT EXISTS archived_clients (
+    client_id VARCHAR(200) PRIMARY KEY,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    archived_by VARCHAR(100),
+    reason TEXT
+);
+
+-- Analyst labels and notes on sessions and clients
+CREATE TABLE IF NOT EXISTS annotations (
+    id BIGSERIAL PRIMARY KEY,
+    scope VARCHAR(10) NOT NULL CHECK (scope IN ('session', 'client')),
+    target VARCHAR(200) NOT NULL,
+    label VARCHAR(100),
+    note TEXT,
+    created_by VARCHAR(100),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_annotations_target ON annotations (scope, target);
+
+-- Triage status of dashboard events; only the latest decision is kept
+CREATE TABLE IF NOT EXISTS event_triage (
+    packet_id VARCHAR(100) PRIMARY KEY,
+    status VARCHAR(20) NOT NULL CHECK (status IN ('new', 'acknowledged', 'false-positive', 'escalated')),
+    note TEXT,
+    assignee VARCHAR(100),
+    updated_by VARCHAR(100),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Analyst comments on events; replies point at the comment they answer
+CREATE TABLE IF NOT EXISTS event_comments (
+    id BIGSERIAL PRIMARY KEY,
+    packet_id VARCHAR(100) NOT NULL,
+    parent_id BIGINT,
+    author VARCHAR(100),
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_comments_packet ON event_comments (packet_id);
+
+-- Issue-tracker tickets opened for escalated events
+CREATE TABLE IF NOT EXISTS case_tickets (
+    packet_id VARCHAR(100) PRIMARY KEY,
+    provider VARCHAR(20) NOT NULL,
+    ticket_key VARCHAR(100) NOT NULL,
+    url TEXT NOT NULL,
+    closed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL,
+    closed_at TIMESTAMPTZ,
+    synced_at TIMESTAMPTZ
+);
+
+-- Exceptions the extension reported about itself, one row per stack hash
+CREATE TABLE IF NOT EXISTS extension_errors (
+    hash VARCHAR(64) PRIMARY KEY,
+    message TEXT NOT NULL,
+    stack TEXT,
+    source VARCHAR(100),
+    extension_version VARCHAR(50),
+    last_client_id VARCHAR(200),
+    count BIGINT NOT NULL DEFAULT 1,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL
+);
+
+-- Local dashboard and API accounts; secrets are stored hashed
+CREATE TABLE IF NOT EXISTS local_users (
+    username VARCHAR(64) PRIMARY KEY,
+    display_name VARCHAR(100),
+    role VARCHAR(20) NOT NULL CHECK (role IN ('viewer', 'analyst', 'admin')),
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    password_hash TEXT,
+    token_hash VARCHAR(64) UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Dashboard aggregates for /api/stats, refreshed by the server on
+-- --stats-refresh-interval. The unique indexes are required by
+-- REFRESH MATERIALIZED VIEW CONCURRENTLY.
+CREATE MATERIALIZED VIEW IF NOT EXISTS domain_stats AS
+SELECT lower(substring(url from '^[a-zA-Z][a-zA-Z0-9+.-]*://([^/:?#]+)')) AS domain,
+       COUNT(*) AS requests,
+       COUNT(*) FILTER (WHERE blocked) AS blocked,
+       COUNT(DISTINCT client_id) AS clients,
+       MAX(timestamp) AS last_seen
+FROM network_logs
+WHERE url ~ '^[a-zA-Z][a-zA-Z0-9+.-]*://[^/:?#]+'
+GROUP BY 1;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_domain_stats_domain ON domain_stats (domain);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS client_stats AS
+SELECT client_id,
+       COUNT(*) AS requests,
+       COUNT(*) FILTER (WHERE blocked) AS blocked,
+       COUNT(DISTINCT lower(substring(url from '^[a-zA-Z][a-zA-Z0-9+.-]*://([^/:?#]+)'))) AS domains,
+       COUNT(DISTINCT session_id) AS sessions,
+       MAX(timestamp) AS last_seen
+FROM network_logs
+WHERE client_id IS NOT NULL
+GROUP BY client_id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_client_stats_client_id ON client_stats (client_id);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS category_stats AS
+SELECT category,
+       COUNT(*) AS requests,
+       COUNT(*) FILTER (WHERE blocked) AS blocked,
+       COUNT(DISTINCT lower(substring(url from '^[a-zA-Z][a-zA-Z0-9+.-]*://([^/:?#]+)'))) AS domains,
+       COUNT(DISTINCT client_id) AS clients
+FROM network_logs
+WHERE category IS NOT NULL
+GROUP BY category;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_category_stats_category ON category_stats (category);
+
 -- Insert default blocklist patterns
 INSERT INTO blocklist_patterns (pattern, type, description) VALUES
     ('.*tracker\\..*', 'url', 'Block tracking domains'),
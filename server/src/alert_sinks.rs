@@ -0,0 +1,452 @@
+//! Alert delivery. Every channel in the alert rules file has a `type`, and
+//! the [`SinkRegistry`] turns the channel's other settings into an
+//! [`AlertSink`] when the rules are loaded. The built-in types are
+//! `webhook`, `slack`, `telegram`, `email` and `syslog`; a program embedding
+//! the server adds its own with
+//! [`AlertRouter::register_sink`](crate::alerts::AlertRouter::register_sink),
+//! and the routing in [`alerts`](crate::alerts) doesn't change.
+
+use crate::external_url;
+use crate::telegram;
+use crate::types::ExtensionEvent;
+use crate::worm;
+use futures_util::future::BoxFuture;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A security event on its way to one channel.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub channel: String,
+    /// The rules that sent it there.
+    pub rules: Vec<String>,
+    pub event: ExtensionEvent,
+}
+
+impl Alert {
+    /// The chat message: what happened, the rules it matched, and a link to
+    /// the event with `--external-url`.
+    pub fn summary(&self) -> String {
+        let event = &self.event;
+        let packet_id = event
+            .data
+            .get("packet_id")
+            .and_then(|v| v.as_str())
+            .unwrap_or("-");
+        let severity = crate::alerts::severity(event).map_or("-".to_string(), |s| s.to_string());
+        let mut text = format!(
+            "🚨 {} (severity {}) from client {}, packet {}",
+            event.event_type,
+            severity,
+            event.client_id.as_deref().unwrap_or("-"),
+            packet_id
+        );
+        for rule in &self.rules {
+            text.push_str(&format!("\n> {}", rule));
+        }
+        if let Some(link) = self.link() {
+            text.push_str(&format!("\n{}", link));
+        }
+        text
+    }
+
+    /// The event on the dashboard, with `--external-url`.
+    pub fn link(&self) -> Option<String> {
+        external_url::packet_link(self.event.data.get("packet_id")?.as_str()?)
+    }
+}
+
+/// A destination for alerts. `send` resolves once the alert was handed
+/// over; errors are logged and counted by the router, not retried.
+pub trait AlertSink: Send + Sync {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// What sinks may share besides their own settings.
+#[derive(Clone)]
+pub struct SinkContext {
+    pub http: reqwest::Client,
+    /// `--telegram-bot-token`, for Telegram channels without their own.
+    pub telegram_token: Option<String>,
+}
+
+/// Builds a sink from a channel's settings (everything but `type`); the
+/// error says what is wrong with them, e.g. `needs an http(s) URL`.
+pub type SinkFactory = Box<
+    dyn Fn(
+            &serde_json::Map<String, serde_json::Value>,
+            &SinkContext,
+        ) -> Result<Arc<dyn AlertSink>, String>
+        + Send
+        + Sync,
+>;
+
+/// Channel types and how to build them.
+pub struct SinkRegistry {
+    factories: BTreeMap<String, SinkFactory>,
+}
+
+impl Default for SinkRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// A channel's settings as `T`.
+pub fn settings<T: DeserializeOwned>(
+    settings: &serde_json::Map<String, serde_json::Value>,
+) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::Object(settings.clone()))
+        .map_err(|e| format!("has invalid settings: {}", e))
+}
+
+fn http_url(url: &str) -> Result<(), String> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err("needs an http(s) URL".into())
+    }
+}
+
+impl SinkRegistry {
+    pub fn builtin() -> Self {
+        let mut registry = SinkRegistry {
+            factories: BTreeMap::new(),
+        };
+        registry.register("webhook", |s, ctx| {
+            Ok(Arc::new(Webhook::new(settings(s)?, ctx)?))
+        });
+        registry.register("slack", |s, ctx| {
+            Ok(Arc::new(Slack::new(settings(s)?, ctx)?))
+        });
+        registry.register("telegram", |s, ctx| {
+            Ok(Arc::new(Telegram::new(settings(s)?, ctx)?))
+        });
+        registry.register("email", |s, _| Ok(Arc::new(Email::new(settings(s)?)?)));
+        registry.register("syslog", |s, _| {
+            Ok(Arc::new(SyslogSink::new(settings(s)?)?))
+        });
+        registry
+    }
+
+    /// Adds a channel type, or replaces the one of that name.
+    pub fn register<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(
+                &serde_json::Map<String, serde_json::Value>,
+                &SinkContext,
+            ) -> Result<Arc<dyn AlertSink>, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories.insert(kind.to_string(), Box::new(factory));
+    }
+
+    pub fn kinds(&self) -> Vec<&str> {
+        self.factories.keys().map(String::as_str).collect()
+    }
+
+    pub fn build(
+        &self,
+        kind: &str,
+        settings: &serde_json::Map<String, serde_json::Value>,
+        ctx: &SinkContext,
+    ) -> Result<Arc<dyn AlertSink>, String> {
+        match self.factories.get(kind) {
+            Some(factory) => factory(settings, ctx),
+            None => Err(format!(
+                "has unknown type '{}' ({})",
+                kind,
+                self.kinds().join(", ")
+            )),
+        }
+    }
+}
+
+/// Sends a built request; the error leaves out the URL, which holds the
+/// webhook secret or bot token.
+async fn post(request: reqwest::RequestBuilder) -> Result<(), String> {
+    request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map(|_| ())
+        .map_err(|e| e.without_url().to_string())
+}
+
+#[derive(Deserialize)]
+struct UrlSettings {
+    url: String,
+}
+
+/// Any URL; `{ "channel", "rules", "event", "link" }` is POSTed as JSON.
+struct Webhook {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Webhook {
+    fn new(settings: UrlSettings, ctx: &SinkContext) -> Result<Self, String> {
+        http_url(&settings.url)?;
+        Ok(Webhook {
+            http: ctx.http.clone(),
+            url: settings.url,
+        })
+    }
+}
+
+impl AlertSink for Webhook {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>> {
+        let mut body = serde_json::json!({
+            "channel": alert.channel,
+            "rules": alert.rules,
+            "event": alert.event
+        });
+        if let Some(link) = alert.link() {
+            body["link"] = link.into();
+        }
+        Box::pin(post(self.http.post(&self.url).json(&body)))
+    }
+}
+
+/// A Slack incoming webhook. A channel named `slack#<name>` posts to
+/// `#<name>`, for webhooks that allow overriding it.
+struct Slack {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Slack {
+    fn new(settings: UrlSettings, ctx: &SinkContext) -> Result<Self, String> {
+        http_url(&settings.url)?;
+        Ok(Slack {
+            http: ctx.http.clone(),
+            url: settings.url,
+        })
+    }
+}
+
+impl AlertSink for Slack {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>> {
+        let mut body = serde_json::json!({ "text": alert.summary() });
+        if let Some((_, slack_channel)) = alert.channel.split_once('#') {
+            body["channel"] = format!("#{}", slack_channel).into();
+        }
+        Box::pin(post(self.http.post(&self.url).json(&body)))
+    }
+}
+
+#[derive(Deserialize)]
+struct TelegramSettings {
+    chat_id: String,
+    #[serde(default)]
+    bot_token: Option<String>,
+}
+
+/// A Telegram chat, through `bot_token` or else `--telegram-bot-token`.
+struct Telegram {
+    http: reqwest::Client,
+    chat_id: String,
+    token: String,
+}
+
+impl Telegram {
+    fn new(settings: TelegramSettings, ctx: &SinkContext) -> Result<Self, String> {
+        if settings.chat_id.is_empty() {
+            return Err("needs a chat_id".into());
+        }
+        let token = settings
+            .bot_token
+            .filter(|t| !t.is_empty())
+            .or_else(|| ctx.telegram_token.clone())
+            .ok_or("needs a bot_token (or start the server with --telegram-bot-token)")?;
+        Ok(Telegram {
+            http: ctx.http.clone(),
+            chat_id: settings.chat_id,
+            token,
+        })
+    }
+}
+
+impl AlertSink for Telegram {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>> {
+        let request =
+            telegram::send_request(&self.http, &self.token, &self.chat_id, &alert.summary());
+        Box::pin(post(request))
+    }
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS, which the server must offer.
+    #[default]
+    Starttls,
+    /// TLS from the first byte (SMTPS, usually port 465).
+    Tls,
+    /// No encryption, for a relay on the same host; never with credentials.
+    None,
+}
+
+#[derive(Deserialize)]
+struct EmailSettings {
+    /// `host:port` of the SMTP server.
+    smtp: String,
+    #[serde(default)]
+    tls: SmtpTls,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
+}
+
+/// Mail through an SMTP server: the summary, then the event as JSON.
+struct Email {
+    smtp: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Email {
+    fn new(settings: EmailSettings) -> Result<Self, String> {
+        let Some((host, port)) = settings
+            .smtp
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        else {
+            return Err("needs smtp as host:port".into());
+        };
+        let from = settings
+            .from
+            .parse()
+            .map_err(|_| format!("has an invalid from address '{}'", settings.from))?;
+        if settings.to.is_empty() {
+            return Err("needs at least one to address".into());
+        }
+        let to = settings
+            .to
+            .iter()
+            .map(|a| {
+                a.parse()
+                    .map_err(|_| format!("has an invalid to address '{}'", a))
+            })
+            .collect::<Result<Vec<Mailbox>, String>>()?;
+        let builder = match settings.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                host,
+            )),
+        }
+        .map_err(|e| format!("has an unusable smtp host '{}': {}", host, e))?;
+        let hostname = crate::instance::id()
+            .map(str::to_string)
+            .unwrap_or_else(crate::instance::default_id);
+        let mut builder = builder
+            .port(port)
+            .hello_name(ClientId::Domain(hostname))
+            .timeout(Some(DELIVERY_TIMEOUT));
+        match (settings.username, settings.password) {
+            (Some(_), _) if settings.tls == SmtpTls::None => {
+                return Err("won't send credentials without tls".into())
+            }
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(username, password));
+            }
+            (None, None) => {}
+            _ => return Err("needs both username and password, or neither".into()),
+        }
+        Ok(Email {
+            smtp: settings.smtp,
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+
+    fn message(&self, alert: &Alert) -> Result<Message, String> {
+        let subject: String = format!(
+            "[CanIGoIn] {} from client {}",
+            alert.event.event_type,
+            alert.event.client_id.as_deref().unwrap_or("-")
+        )
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+        let body = format!(
+            "{}\n\n{}\n",
+            alert.summary(),
+            serde_json::to_string_pretty(&alert.event).unwrap_or_default()
+        );
+        let mut message = Message::builder().from(self.from.clone());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        message
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl AlertSink for Email {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let message = self.message(alert)?;
+            tokio::time::timeout(DELIVERY_TIMEOUT, self.transport.send(message))
+                .await
+                .map_err(|_| format!("{} timed out", self.smtp))?
+                .map(|_| ())
+                .map_err(|e| format!("{}: {}", self.smtp, e))
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SyslogSettings {
+    /// `udp://host:port`, `tcp://host:port` or `host:port` (UDP).
+    address: String,
+}
+
+/// The event as an RFC 5424 message, like `--worm-syslog` sends.
+struct SyslogSink {
+    syslog: tokio::sync::Mutex<worm::Syslog>,
+}
+
+impl SyslogSink {
+    fn new(settings: SyslogSettings) -> Result<Self, String> {
+        let syslog = worm::Syslog::parse(&settings.address)
+            .map_err(|e| format!("has an invalid address: {}", e))?;
+        Ok(SyslogSink {
+            syslog: tokio::sync::Mutex::new(syslog),
+        })
+    }
+}
+
+impl AlertSink for SyslogSink {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let mut syslog = self.syslog.lock().await;
+            tokio::time::timeout(DELIVERY_TIMEOUT, syslog.send(&alert.event))
+                .await
+                .map_err(|_| "timed out".to_string())?
+                .map_err(|e| e.to_string())
+        })
+    }
+}
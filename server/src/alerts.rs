@@ -0,0 +1,601 @@
+//! Notification routing for security events. Every security event, posted
+//! to `/api/security` or raised by the server's own detectors, is checked
+//! against the alert rules, and each matching rule sends it to its channels:
+//!
+//! ```text
+//! when event_type == clickfix_detection and severity > 70 then notify slack#sec-alerts
+//! ```
+//!
+//! The rules and the channels they name live in one JSON file
+//! (`--alert-rules`), which is re-read when it changes and rewritten by
+//! `PUT /api/admin/alert-rules`. Delivery to each kind of channel is an
+//! [`AlertSink`] (see [`alert_sinks`](crate::alert_sinks)).
+
+use crate::alert_sinks::{Alert, AlertSink, SinkContext, SinkRegistry, DELIVERY_TIMEOUT};
+use crate::features::{Feature, FeatureFlags};
+use crate::metrics;
+use crate::types::ExtensionEvent;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often the rules file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+static ROUTER: OnceLock<web::Data<AlertRouter>> = OnceLock::new();
+
+/// A channel: `{ "type": "slack", "url": "..." }`. The settings besides
+/// `type` are up to the [`SinkRegistry`] entry of that type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelConfig {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The contents of the rules file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub channels: BTreeMap<String, ChannelConfig>,
+    #[serde(default)]
+    pub rules: Vec<String>,
+}
+
+/// Why a configuration was refused; `rule` is the index of the bad rule.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub rule: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rule {
+            Some(i) => write!(f, "rule {}: {}", i, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    EventType,
+    ClientId,
+    SessionId,
+    Category,
+    /// `data.detection.riskScore`, or `data.severity`.
+    Severity,
+    /// `data.<key>.<key>...`
+    Data(Vec<String>),
+}
+
+impl Field {
+    fn parse(s: &str) -> Option<Field> {
+        Some(match s {
+            "event_type" => Field::EventType,
+            "client_id" => Field::ClientId,
+            "session_id" => Field::SessionId,
+            "category" => Field::Category,
+            "severity" => Field::Severity,
+            _ => {
+                let path = s.strip_prefix("data.")?;
+                if path.is_empty() || path.split('.').any(str::is_empty) {
+                    return None;
+                }
+                Field::Data(path.split('.').map(str::to_string).collect())
+            }
+        })
+    }
+
+    fn lookup(&self, event: &ExtensionEvent) -> Option<serde_json::Value> {
+        use serde_json::Value;
+        match self {
+            Field::EventType => Some(Value::String(event.event_type.clone())),
+            Field::ClientId => event.client_id.clone().map(Value::String),
+            Field::SessionId => Some(Value::String(event.session_id.clone())),
+            Field::Category => event.data.get("category").cloned(),
+            Field::Severity => severity(event).map(Value::from),
+            Field::Data(path) => path
+                .iter()
+                .try_fold(&event.data, |v, key| v.get(key))
+                .cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+}
+
+impl Op {
+    fn parse(s: &str) -> Option<Op> {
+        Some(match s {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            "contains" => Op::Contains,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: Field,
+    op: Op,
+    value: String,
+    number: Option<f64>,
+}
+
+impl Condition {
+    /// A condition on a field the event doesn't have is false.
+    fn matches(&self, event: &ExtensionEvent) -> bool {
+        let Some(actual) = self.field.lookup(event) else {
+            return false;
+        };
+        let actual_number = actual
+            .as_f64()
+            .or_else(|| actual.as_str().and_then(|s| s.parse().ok()));
+        let actual_text = match &actual {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let equal = match (actual_number, self.number) {
+            (Some(a), Some(b)) => a == b,
+            _ => actual_text == self.value,
+        };
+        let compare = |f: fn(f64, f64) -> bool| match (actual_number, self.number) {
+            (Some(a), Some(b)) => f(a, b),
+            _ => false,
+        };
+        match self.op {
+            Op::Eq => equal,
+            Op::Ne => !equal,
+            Op::Gt => compare(|a, b| a > b),
+            Op::Ge => compare(|a, b| a >= b),
+            Op::Lt => compare(|a, b| a < b),
+            Op::Le => compare(|a, b| a <= b),
+            Op::Contains => actual_text.contains(&self.value),
+        }
+    }
+}
+
+/// One parsed rule: all conditions must hold (none means every event).
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub source: String,
+    conditions: Vec<Condition>,
+    pub channels: Vec<String>,
+}
+
+impl Rule {
+    /// `when <field> <op> <value> [and ...] then notify <channel>[, <channel>...]`,
+    /// or `when any then notify ...`. Values with spaces go in double quotes.
+    pub fn parse(source: &str) -> Result<Rule, String> {
+        let tokens = tokenize(source)?;
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+        if tokens.next() != Some("when") {
+            return Err("a rule starts with 'when'".into());
+        }
+        let mut conditions = Vec::new();
+        if tokens.peek() == Some(&"any") {
+            tokens.next();
+        } else {
+            loop {
+                let field = tokens.next().ok_or("expected a field after 'when'/'and'")?;
+                let field = Field::parse(field).ok_or_else(|| {
+                    format!(
+                        "unknown field '{}' (event_type, client_id, session_id, category, severity or data.<key>)",
+                        field
+                    )
+                })?;
+                let op = tokens.next().ok_or("expected an operator")?;
+                let op = Op::parse(op).ok_or_else(|| {
+                    format!("unknown operator '{}' (==, !=, >, >=, <, <=, contains)", op)
+                })?;
+                let value = tokens.next().ok_or("expected a value")?.to_string();
+                let number = value.parse::<f64>().ok();
+                if matches!(op, Op::Gt | Op::Ge | Op::Lt | Op::Le) && number.is_none() {
+                    return Err(format!("'{}' needs a number to compare with", value));
+                }
+                conditions.push(Condition {
+                    field,
+                    op,
+                    value,
+                    number,
+                });
+                if tokens.peek() != Some(&"and") {
+                    break;
+                }
+                tokens.next();
+            }
+        }
+        if tokens.next() != Some("then") || tokens.next() != Some("notify") {
+            return Err("expected 'then notify <channel>' after the conditions".into());
+        }
+        let mut channels = Vec::new();
+        loop {
+            let channel = tokens.next().ok_or("expected a channel after 'notify'")?;
+            channels.push(channel.to_string());
+            match tokens.next() {
+                None => break,
+                Some(",") => continue,
+                Some(other) => return Err(format!("unexpected '{}' after the channels", other)),
+            }
+        }
+        Ok(Rule {
+            source: source.trim().to_string(),
+            conditions,
+            channels,
+        })
+    }
+
+    pub fn matches(&self, event: &ExtensionEvent) -> bool {
+        self.conditions.iter().all(|c| c.matches(event))
+    }
+}
+
+/// Words, double-quoted strings and commas.
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ',' {
+            chars.next();
+            tokens.push(",".to_string());
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => word.push(c),
+                    None => return Err("unterminated quoted value".into()),
+                }
+            }
+            tokens.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        }
+    }
+    Ok(tokens)
+}
+
+/// The event's risk score: `detection.riskScore`, else a numeric `severity`.
+pub fn severity(event: &ExtensionEvent) -> Option<f64> {
+    event
+        .data
+        .get("detection")
+        .and_then(|d| d.get("riskScore"))
+        .and_then(|v| v.as_f64())
+        .or_else(|| event.data.get("severity").and_then(|v| v.as_f64()))
+}
+
+/// A validated configuration, with a sink built for every channel.
+pub struct RuleSet {
+    config: AlertConfig,
+    rules: Vec<Rule>,
+    sinks: BTreeMap<String, Arc<dyn AlertSink>>,
+}
+
+impl RuleSet {
+    fn compile(
+        config: AlertConfig,
+        registry: &SinkRegistry,
+        ctx: &SinkContext,
+    ) -> Result<RuleSet, ConfigError> {
+        let mut sinks = BTreeMap::new();
+        for (name, channel) in &config.channels {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ',') {
+                return Err(ConfigError {
+                    rule: None,
+                    message: format!(
+                        "channel name '{}' must be non-empty, without spaces or commas",
+                        name
+                    ),
+                });
+            }
+            let sink = registry
+                .build(&channel.kind, &channel.settings, ctx)
+                .map_err(|problem| ConfigError {
+                    rule: None,
+                    message: format!("channel '{}' {}", name, problem),
+                })?;
+            sinks.insert(name.clone(), sink);
+        }
+        let mut rules = Vec::with_capacity(config.rules.len());
+        for (i, source) in config.rules.iter().enumerate() {
+            let rule = Rule::parse(source).map_err(|message| ConfigError {
+                rule: Some(i),
+                message,
+            })?;
+            if let Some(missing) = rule
+                .channels
+                .iter()
+                .find(|c| !config.channels.contains_key(*c))
+            {
+                return Err(ConfigError {
+                    rule: Some(i),
+                    message: format!("channel '{}' is not defined", missing),
+                });
+            }
+            rules.push(rule);
+        }
+        Ok(RuleSet {
+            config,
+            rules,
+            sinks,
+        })
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Indexes of the rules that match `event`.
+    pub fn matching(&self, event: &ExtensionEvent) -> Vec<usize> {
+        (0..self.rules.len())
+            .filter(|&i| self.rules[i].matches(event))
+            .collect()
+    }
+}
+
+/// Evaluates the current rules against security events and delivers the
+/// notifications.
+pub struct AlertRouter {
+    path: Option<PathBuf>,
+    rules: RwLock<Arc<RuleSet>>,
+    /// Modification time of the file as last loaded or written.
+    modified: Mutex<Option<SystemTime>>,
+    registry: SinkRegistry,
+    ctx: SinkContext,
+    /// Delivery stops while `webhooks` is disabled.
+    features: Arc<FeatureFlags>,
+}
+
+impl Default for AlertRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlertRouter {
+    /// No rules, changes kept in memory only, and the built-in channel types.
+    pub fn new() -> Self {
+        AlertRouter {
+            path: None,
+            rules: RwLock::new(Arc::new(RuleSet {
+                config: AlertConfig::default(),
+                rules: Vec::new(),
+                sinks: BTreeMap::new(),
+            })),
+            modified: Mutex::new(None),
+            registry: SinkRegistry::builtin(),
+            ctx: SinkContext {
+                http: reqwest::Client::builder()
+                    .timeout(DELIVERY_TIMEOUT)
+                    .build()
+                    .expect("Failed to build HTTP client"),
+                telegram_token: None,
+            },
+            features: Arc::new(FeatureFlags::default()),
+        }
+    }
+
+    pub fn with_telegram_token(mut self, token: Option<String>) -> Self {
+        self.ctx.telegram_token = token.filter(|t| !t.is_empty());
+        self
+    }
+
+    /// The feature flags whose `webhooks` switch turns delivery off.
+    pub fn with_features(mut self, features: Arc<FeatureFlags>) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Adds a channel type that rules files can use; call before
+    /// [`AlertRouter::load`].
+    pub fn register_sink<F>(mut self, kind: &str, factory: F) -> Self
+    where
+        F: Fn(
+                &serde_json::Map<String, serde_json::Value>,
+                &SinkContext,
+            ) -> Result<Arc<dyn AlertSink>, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.registry.register(kind, factory);
+        self
+    }
+
+    /// The channel types rules files can use.
+    pub fn sink_kinds(&self) -> Vec<&str> {
+        self.registry.kinds()
+    }
+
+    /// Rules from `path`; a missing file means no rules yet.
+    pub fn load(mut self, path: &Path) -> Result<Self, String> {
+        self.path = Some(path.to_path_buf());
+        if path.exists() {
+            self.reload()?;
+        }
+        Ok(self)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn current(&self) -> Arc<RuleSet> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Checks `config` against what this server can deliver to.
+    pub fn compile(&self, config: AlertConfig) -> Result<RuleSet, ConfigError> {
+        RuleSet::compile(config, &self.registry, &self.ctx)
+    }
+
+    fn reload(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: AlertConfig = serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?;
+        let rules = self.compile(config).map_err(|e| e.to_string())?;
+        *self.rules.write().unwrap() = Arc::new(rules);
+        *self.modified.lock().unwrap() = modified;
+        Ok(())
+    }
+
+    /// Re-reads the file if it changed since it was last loaded. A file that
+    /// doesn't parse leaves the current rules in place.
+    fn reload_if_changed(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let Ok(modified) = std::fs::metadata(path).and_then(|m| m.modified()) else {
+            return;
+        };
+        if *self.modified.lock().unwrap() == Some(modified) {
+            return;
+        }
+        match self.reload() {
+            Ok(()) => {
+                let rules = self.current();
+                log::info!(
+                    "🔔 Alert rules reloaded from {}: {} rule(s), {} channel(s)",
+                    path.display(),
+                    rules.rules.len(),
+                    rules.config.channels.len()
+                );
+            }
+            Err(e) => {
+                // Don't report the same broken file every few seconds.
+                *self.modified.lock().unwrap() = Some(modified);
+                log::error!(
+                    "❌ Alert rules not reloaded, keeping the previous ones: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Makes `rules` current and, with `--alert-rules`, writes them to the file.
+    pub fn replace(&self, rules: RuleSet) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut text =
+                serde_json::to_vec_pretty(&rules.config).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+            *self.modified.lock().unwrap() =
+                std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        }
+        *self.rules.write().unwrap() = Arc::new(rules);
+        Ok(())
+    }
+
+    /// Sends `event` to every channel named by a matching rule, once per
+    /// channel however many rules name it.
+    pub fn dispatch(&self, event: &ExtensionEvent) {
+        if !self.features.is_enabled(Feature::Webhooks) {
+            return;
+        }
+        let rules = self.current();
+        let mut by_channel: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for i in rules.matching(event) {
+            let rule = &rules.rules[i];
+            for channel in &rule.channels {
+                by_channel.entry(channel).or_default().push(&rule.source);
+            }
+        }
+        for (name, matched) in by_channel {
+            let Some(sink) = rules.sinks.get(name).cloned() else {
+                continue;
+            };
+            let alert = Alert {
+                channel: name.to_string(),
+                rules: matched.iter().map(|r| r.to_string()).collect(),
+                event: event.clone(),
+            };
+            actix_web::rt::spawn(async move {
+                match sink.send(&alert).await {
+                    Ok(()) => {
+                        metrics::ALERTS_SENT.inc();
+                        log::info!(
+                            "🔔 {} alert sent to {}",
+                            alert.event.event_type,
+                            alert.channel
+                        );
+                    }
+                    Err(e) => {
+                        metrics::ALERT_DELIVERY_FAILURES.inc();
+                        log::error!(
+                            "❌ {} alert to {} failed: {}",
+                            alert.event.event_type,
+                            alert.channel,
+                            e
+                        );
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Routes every security event through `router` from now on, and follows
+/// changes to its file.
+pub fn install(router: web::Data<AlertRouter>) {
+    if ROUTER.set(router.clone()).is_err() || router.path.is_none() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+            router.reload_if_changed();
+        }
+    });
+}
+
+/// Hands a security event to the installed router, if any, and to the
+/// certificate transparency cross-check.
+pub fn raise(event: &ExtensionEvent) {
+    crate::ct::enrich(event);
+    if let Some(router) = ROUTER.get() {
+        router.dispatch(event);
+    }
+}
@@ -0,0 +1,137 @@
+//! Analyst labels and notes on sessions and clients ("this was the incident
+//! on Tuesday"), returned alongside the dashboard events they apply to.
+
+use crate::types::Annotation;
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::RwLock;
+
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+pub const MAX_LABEL_LEN: usize = 100;
+pub const MAX_NOTE_LEN: usize = 4000;
+
+/// How often production replicas re-read the annotations table.
+#[cfg(feature = "production")]
+const RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub struct AnnotationStore {
+    annotations: RwLock<Vec<Annotation>>,
+    /// Id source for simple mode; the database assigns ids otherwise.
+    next_id: AtomicI64,
+    #[cfg(feature = "production")]
+    storage: Option<Arc<crate::production::ProductionState>>,
+}
+
+impl Default for AnnotationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnnotationStore {
+    /// In-memory only (simple mode): annotations don't survive a restart.
+    pub fn new() -> Self {
+        AnnotationStore {
+            annotations: RwLock::new(Vec::new()),
+            next_id: AtomicI64::new(1),
+            #[cfg(feature = "production")]
+            storage: None,
+        }
+    }
+
+    /// Persists annotations in the `annotations` table and keeps the
+    /// in-memory copy in sync with it.
+    #[cfg(feature = "production")]
+    pub fn spawn(state: Arc<crate::production::ProductionState>) -> Arc<AnnotationStore> {
+        let store = Arc::new(AnnotationStore {
+            annotations: RwLock::new(Vec::new()),
+            next_id: AtomicI64::new(1),
+            storage: Some(state.clone()),
+        });
+        let s = store.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match state.get_annotations().await {
+                    Ok(list) => *s.annotations.write().unwrap() = list,
+                    Err(e) => log::error!("❌ Loading annotations failed: {}", e),
+                }
+                tokio::time::sleep(RESYNC_INTERVAL).await;
+            }
+        });
+        store
+    }
+
+    /// Annotations on one session or client, oldest first.
+    pub fn list(&self, scope: &str, target: &str) -> Vec<Annotation> {
+        self.annotations
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|a| a.scope == scope && a.target == target)
+            .cloned()
+            .collect()
+    }
+
+    /// Annotations that apply to an event: those on its session, then those on
+    /// its client.
+    pub fn for_event(&self, session_id: &str, client_id: Option<&str>) -> Vec<Annotation> {
+        let all = self.annotations.read().unwrap();
+        let session = all
+            .iter()
+            .filter(|a| a.scope == "session" && a.target == session_id);
+        let client = all
+            .iter()
+            .filter(|a| a.scope == "client" && Some(a.target.as_str()) == client_id);
+        session.chain(client).cloned().collect()
+    }
+
+    pub async fn add(
+        &self,
+        scope: &str,
+        target: &str,
+        label: Option<String>,
+        note: Option<String>,
+        created_by: Option<String>,
+    ) -> Result<Annotation, String> {
+        let mut annotation = Annotation {
+            id: 0,
+            scope: scope.to_string(),
+            target: target.to_string(),
+            label,
+            note,
+            created_by,
+            created_at: Utc::now(),
+        };
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            annotation.id = state
+                .add_annotation(&annotation)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        if annotation.id == 0 {
+            annotation.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        }
+        self.annotations.write().unwrap().push(annotation.clone());
+        Ok(annotation)
+    }
+
+    /// Returns whether the annotation existed.
+    pub async fn delete(&self, id: i64) -> Result<bool, String> {
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut found = false;
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            found = state
+                .delete_annotation(id)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let mut all = self.annotations.write().unwrap();
+        let before = all.len();
+        all.retain(|a| a.id != id);
+        Ok(all.len() < before || found)
+    }
+}
@@ -0,0 +1,1583 @@
+//! The actix `App`: middleware, shared services and the route table for each
+//! storage backend. The binary builds one per worker with [`build_app`];
+//! integration tests and embedding crates do the same with their own
+//! [`AppState`].
+
+use crate::alerts::AlertRouter;
+use crate::annotations::AnnotationStore;
+use crate::archive::ClientArchive;
+use crate::asn::AsnEnricher;
+use crate::auth::AdminAuth;
+use crate::backup::Backups;
+use crate::bans::BanList;
+use crate::batch::BatchLimit;
+use crate::beaconing::BeaconDetector;
+use crate::blocklist_history::BlocklistHistory;
+use crate::blocklist_rollout::BlocklistRollout;
+use crate::blocklist_signing::BlocklistSigner;
+use crate::blocklist_sync::BlocklistSync;
+use crate::bundle::BundleSigner;
+use crate::capture::Capture;
+use crate::categories::Categories;
+use crate::chain::EventChain;
+use crate::chaos::Chaos;
+use crate::client_names::ClientNames;
+use crate::clock_skew::ClockSkew;
+use crate::counters::IngestCounters;
+use crate::detection::DetectionRules;
+use crate::distinct::DistinctCounters;
+use crate::enrollment::Enrollment;
+use crate::error::ApiError;
+use crate::event_data::EventDataLimit;
+use crate::exfil::ExfilMonitor;
+use crate::experiments::Experiments;
+use crate::extension_errors::ErrorReports;
+use crate::features::FeatureFlags;
+use crate::focus::FocusSessions;
+use crate::groups::Groups;
+use crate::handlers::admin::RuntimeConfig;
+use crate::homograph::{self, HomographDetector};
+use crate::honeypot::Honeypot;
+use crate::ip_filter::{IpFilter, TrustedProxies};
+use crate::ldap::LdapDirectory;
+use crate::maintenance::Maintenance;
+use crate::metering::Metering;
+use crate::quotas::{QuotaLimits, Quotas};
+use crate::rdap::RdapService;
+use crate::reputation::ReputationService;
+use crate::request_types::RequestTypeFilter;
+use crate::rescan::Rescans;
+use crate::response_cache::ResponseCache;
+use crate::scanning::ScanQueue;
+use crate::scheduler::Scheduler;
+use crate::screen_time::ScreenTime;
+use crate::security_headers::SecurityHeaders;
+use crate::sessions::SessionTracker;
+use crate::simple::SimpleState;
+use crate::tail::LogTail;
+use crate::telemetry::PerfTelemetry;
+use crate::tickets::Ticketing;
+use crate::triage::TriageStore;
+use crate::uploads::UploadStore;
+use crate::url_normalize::UrlNormalizer;
+use crate::users::UserStore;
+use crate::{
+    bans, capture, chaos, features, handlers, instance, ip_filter, maintenance, metrics,
+    security_headers, versioning,
+};
+use actix_cors::Cors;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{from_fn, Condition, Logger};
+use actix_web::{web, App, HttpResponse, ResponseError};
+use std::sync::Arc;
+
+#[cfg(feature = "production")]
+use crate::pool_monitor::PoolMonitor;
+#[cfg(feature = "production")]
+use crate::production::ProductionState;
+#[cfg(feature = "production")]
+use crate::redis_conn::SharedRedis;
+#[cfg(feature = "production")]
+use crate::stats::StatsRefresher;
+
+/// Where ingested data goes and where reads come from.
+#[derive(Clone)]
+pub enum Backend {
+    /// In-memory only.
+    Simple(web::Data<SimpleState>),
+    #[cfg(feature = "production")]
+    Production {
+        state: web::Data<ProductionState>,
+        pool_monitor: web::Data<PoolMonitor>,
+        stats_refresher: web::Data<StatsRefresher>,
+    },
+    /// Recent data in `hot`, everything forwarded to `warm`.
+    #[cfg(feature = "production")]
+    Hybrid {
+        hot: web::Data<SimpleState>,
+        warm: web::Data<ProductionState>,
+        pool_monitor: web::Data<PoolMonitor>,
+        stats_refresher: web::Data<StatsRefresher>,
+    },
+}
+
+impl Backend {
+    /// Starts the pool monitor and the `/api/stats` refresher for `state`.
+    #[cfg(feature = "production")]
+    pub fn production(state: Arc<ProductionState>, stats_interval: std::time::Duration) -> Self {
+        Backend::Production {
+            pool_monitor: web::Data::from(PoolMonitor::spawn(state.clone())),
+            stats_refresher: web::Data::from(StatsRefresher::spawn(state.clone(), stats_interval)),
+            state: web::Data::from(state),
+        }
+    }
+
+    /// `hot` should already forward to `warm` (see `SimpleState::with_warm_tier`).
+    #[cfg(feature = "production")]
+    pub fn hybrid(
+        warm: Arc<ProductionState>,
+        hot: web::Data<SimpleState>,
+        stats_interval: std::time::Duration,
+    ) -> Self {
+        Backend::Hybrid {
+            pool_monitor: web::Data::from(PoolMonitor::spawn(warm.clone())),
+            stats_refresher: web::Data::from(StatsRefresher::spawn(warm.clone(), stats_interval)),
+            warm: web::Data::from(warm),
+            hot,
+        }
+    }
+
+    /// The database, if there is one.
+    #[cfg(feature = "production")]
+    fn database(&self) -> Option<Arc<ProductionState>> {
+        match self {
+            Backend::Simple(_) => None,
+            Backend::Production { state, .. } | Backend::Hybrid { warm: state, .. } => {
+                Some(state.clone().into_inner())
+            }
+        }
+    }
+}
+
+/// Everything the handlers and middleware read from app data. Fields are
+/// public so callers can replace the defaults from [`AppState::new`].
+#[derive(Clone)]
+pub struct AppState {
+    pub backend: Backend,
+    pub reputation: web::Data<ReputationService>,
+    pub beacons: web::Data<BeaconDetector>,
+    pub exfil: web::Data<ExfilMonitor>,
+    pub homographs: web::Data<HomographDetector>,
+    /// Off unless `--rdap-max-age` is set.
+    pub rdap: web::Data<RdapService>,
+    /// CSP and friends on every answer; the defaults unless
+    /// `--security-headers` says otherwise.
+    pub security_headers: web::Data<SecurityHeaders>,
+    /// Off unless `--asn-db` is set.
+    pub asn: web::Data<AsnEnricher>,
+    pub ip_filter: web::Data<IpFilter>,
+    /// Whose `X-Forwarded-For` names the client; nobody's unless
+    /// `--trusted-proxy` is set.
+    pub trusted_proxies: web::Data<TrustedProxies>,
+    pub capture: web::Data<Capture>,
+    pub honeypot: web::Data<Honeypot>,
+    pub admin_auth: web::Data<AdminAuth>,
+    /// Local accounts `admin_auth` also accepts.
+    pub users: web::Data<UserStore>,
+    pub bundle_signer: web::Data<BundleSigner>,
+    pub runtime_config: web::Data<RuntimeConfig>,
+    pub ban_list: web::Data<BanList>,
+    /// The `--redis-url` connection the stores below share across replicas;
+    /// `None` keeps their state per replica.
+    #[cfg(feature = "production")]
+    pub redis: Option<Arc<SharedRedis>>,
+    pub quotas: web::Data<Quotas>,
+    /// Per-org daily usage for `/api/admin/usage`.
+    pub metering: web::Data<Metering>,
+    pub batch_limit: web::Data<BatchLimit>,
+    pub request_types: web::Data<RequestTypeFilter>,
+    /// Off unless `--normalize-urls`.
+    pub url_normalizer: web::Data<UrlNormalizer>,
+    pub event_data_limit: web::Data<EventDataLimit>,
+    pub client_archive: web::Data<ClientArchive>,
+    pub annotations: web::Data<AnnotationStore>,
+    pub triage: web::Data<TriageStore>,
+    pub error_reports: web::Data<ErrorReports>,
+    pub uploads: web::Data<UploadStore>,
+    pub scan_queue: web::Data<ScanQueue>,
+    /// Shared with `alerts`, which stops delivery while `webhooks` is off.
+    pub alerts: web::Data<AlertRouter>,
+    pub features: web::Data<FeatureFlags>,
+    pub screen_time: web::Data<ScreenTime>,
+    pub focus: web::Data<FocusSessions>,
+    pub categories: web::Data<Categories>,
+    /// Shared with `quotas`, which applies the group limits.
+    pub groups: web::Data<Groups>,
+    pub client_names: web::Data<ClientNames>,
+    /// Names clients after their users (`--ldap-config`); off by default.
+    pub ldap: web::Data<LdapDirectory>,
+    /// Followers of `/api/logs/tail?follow=true`.
+    pub tail: web::Data<LogTail>,
+    pub maintenance: web::Data<Maintenance>,
+    /// Injected failures on ingest (`--chaos`); off unless the binary sets it.
+    pub chaos: web::Data<Chaos>,
+    pub backups: web::Data<Backups>,
+    /// Installed by the binary with `chain::install`, which links events.
+    pub chain: web::Data<EventChain>,
+    pub clock_skew: web::Data<ClockSkew>,
+    pub telemetry: web::Data<PerfTelemetry>,
+    pub sessions: web::Data<SessionTracker>,
+    pub enrollment: web::Data<Enrollment>,
+    /// Versions of the shared blocklist, for `/api/blocklist/delta`.
+    pub blocklist_history: web::Data<BlocklistHistory>,
+    /// Signs served blocklists with `--blocklist-signing-key`.
+    pub blocklist_signer: web::Data<BlocklistSigner>,
+    /// A new blocklist being tried on a share of the clients.
+    pub blocklist_rollout: web::Data<BlocklistRollout>,
+    /// Policy variants being compared on groups of clients.
+    pub experiments: web::Data<Experiments>,
+    /// Checked against every batch and event; raise `detection_rule_match`.
+    pub detection_rules: web::Data<DetectionRules>,
+    /// What the `rescan` job checks stored logs and events against next.
+    pub rescans: web::Data<Rescans>,
+    /// Verifies pushes to `/api/integrations/blocklist-sync`.
+    pub blocklist_sync: web::Data<BlocklistSync>,
+    /// Opens tracker tickets for escalated events.
+    pub ticketing: web::Data<Ticketing>,
+    /// Approximate distinct URLs, domains, sessions and clients for
+    /// `/api/stats`; shared through Redis in production.
+    pub distinct: web::Data<DistinctCounters>,
+    /// Running ingest totals, overall and per client, for `/api/stats`.
+    pub ingest_counters: web::Data<IngestCounters>,
+    /// Cached `/api/stats` and client summary responses.
+    pub stats_cache: web::Data<ResponseCache>,
+    /// The binary registers its periodic jobs here.
+    pub scheduler: web::Data<Scheduler>,
+    /// Log every request (actix `Logger`).
+    pub access_log: bool,
+}
+
+impl AppState {
+    /// `backend` with the command line's defaults for everything else: no
+    /// admin token, bundle key, quotas, orgs (all usage metered as unassigned), disabled features, batch limit, bans, CIDR filter,
+    /// capture, uploads, upload scanning, alert rules, detection rules, screen-time budgets or
+    /// device groups, maintenance pause, backup directory, event chain, client
+    /// enrollment, issue tracker, scheduled jobs or stats cache, the built-in honeypot paths, focus domains and domain categories, a
+    /// 50 MB/hour exfiltration threshold, event data truncated past 32 levels or 256 KB, 2m / 1m-per-day clock skew limits and two IPs per session. The archive, annotations, triage statuses, case tickets, extension error reports and local users are kept in
+    /// the database when there is one, which starts their resync tasks.
+    pub fn new(backend: Backend) -> Self {
+        #[cfg(feature = "production")]
+        let (client_archive, annotations, triage, error_reports, users) = match backend.database() {
+            Some(db) => (
+                web::Data::from(ClientArchive::spawn(db.clone())),
+                web::Data::from(AnnotationStore::spawn(db.clone())),
+                web::Data::from(TriageStore::spawn(db.clone())),
+                web::Data::from(ErrorReports::spawn(db.clone())),
+                web::Data::from(UserStore::spawn(db)),
+            ),
+            None => (
+                web::Data::new(ClientArchive::new()),
+                web::Data::new(AnnotationStore::new()),
+                web::Data::new(TriageStore::new()),
+                web::Data::new(ErrorReports::new()),
+                web::Data::new(UserStore::new()),
+            ),
+        };
+        #[cfg(not(feature = "production"))]
+        let (client_archive, annotations, triage, error_reports, users) = (
+            web::Data::new(ClientArchive::new()),
+            web::Data::new(AnnotationStore::new()),
+            web::Data::new(TriageStore::new()),
+            web::Data::new(ErrorReports::new()),
+            web::Data::new(UserStore::new()),
+        );
+        // Production mode doesn't log every request.
+        let (mode, access_log) = match backend {
+            Backend::Simple(_) => ("simple", true),
+            #[cfg(feature = "production")]
+            Backend::Production { .. } => ("production", false),
+            #[cfg(feature = "production")]
+            Backend::Hybrid { .. } => ("hybrid", true),
+        };
+        let groups = Arc::new(Groups::new());
+        let features = Arc::new(FeatureFlags::default());
+        AppState {
+            access_log,
+            backend,
+            reputation: web::Data::new(
+                ReputationService::new(None, None, 3600).expect("no reputation list to read"),
+            ),
+            beacons: web::Data::new(BeaconDetector::new()),
+            exfil: web::Data::new(ExfilMonitor::new(
+                50 * 1024 * 1024,
+                chrono::Duration::hours(1),
+                Vec::new(),
+            )),
+            homographs: web::Data::new(HomographDetector::new(
+                &homograph::DEFAULT_BRANDS
+                    .iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>(),
+            )),
+            rdap: web::Data::new(RdapService::disabled()),
+            security_headers: web::Data::new(SecurityHeaders::default()),
+            asn: web::Data::new(AsnEnricher::disabled()),
+            ip_filter: web::Data::new(IpFilter::new(&[], &[]).expect("empty CIDR filter")),
+            trusted_proxies: web::Data::new(TrustedProxies::default()),
+            capture: web::Data::new(Capture::disabled()),
+            honeypot: web::Data::new(Honeypot::new(true, &[])),
+            admin_auth: web::Data::new(AdminAuth::new(None)),
+            users,
+            bundle_signer: web::Data::new(BundleSigner::new(None)),
+            runtime_config: web::Data::new(RuntimeConfig {
+                mode: mode.to_string(),
+                settings: serde_json::json!({}),
+            }),
+            ban_list: web::Data::new(BanList::new(None)),
+            #[cfg(feature = "production")]
+            redis: None,
+            quotas: web::Data::new(Quotas::new(QuotaLimits::default()).with_groups(groups.clone())),
+            metering: web::Data::new(Metering::default()),
+            batch_limit: web::Data::new(BatchLimit::default()),
+            request_types: web::Data::new(RequestTypeFilter::default()),
+            url_normalizer: web::Data::new(UrlNormalizer::default()),
+            event_data_limit: web::Data::new(EventDataLimit::default()),
+            client_archive,
+            annotations,
+            triage,
+            error_reports,
+            uploads: web::Data::new(UploadStore::disabled()),
+            scan_queue: web::Data::new(ScanQueue::disabled()),
+            alerts: web::Data::new(AlertRouter::new().with_features(features.clone())),
+            features: web::Data::from(features),
+            screen_time: web::Data::new(ScreenTime::new()),
+            focus: web::Data::new(FocusSessions::default()),
+            categories: web::Data::new(Categories::default()),
+            groups: web::Data::from(groups),
+            client_names: web::Data::new(ClientNames::new()),
+            ldap: web::Data::new(LdapDirectory::disabled()),
+            tail: web::Data::new(LogTail::new()),
+            maintenance: web::Data::new(Maintenance::new()),
+            chaos: web::Data::new(Chaos::disabled()),
+            backups: web::Data::new(Backups::disabled()),
+            chain: web::Data::new(EventChain::disabled()),
+            clock_skew: web::Data::new(ClockSkew::default()),
+            telemetry: web::Data::new(PerfTelemetry::default()),
+            sessions: web::Data::new(SessionTracker::default()),
+            enrollment: web::Data::new(Enrollment::disabled()),
+            blocklist_history: web::Data::new(BlocklistHistory::new()),
+            blocklist_signer: web::Data::new(BlocklistSigner::disabled()),
+            blocklist_rollout: web::Data::new(BlocklistRollout::new()),
+            experiments: web::Data::new(Experiments::new()),
+            detection_rules: web::Data::new(DetectionRules::new()),
+            rescans: web::Data::new(Rescans::new()),
+            blocklist_sync: web::Data::new(BlocklistSync::new(None)),
+            ticketing: web::Data::new(Ticketing::disabled()),
+            distinct: web::Data::new(DistinctCounters::new()),
+            ingest_counters: web::Data::new(IngestCounters::new()),
+            stats_cache: web::Data::new(ResponseCache::disabled()),
+            scheduler: web::Data::new(Scheduler::new()),
+        }
+    }
+
+    /// Simple mode with a fresh in-memory store and the defaults from
+    /// [`AppState::new`].
+    pub fn simple() -> Self {
+        AppState::new(Backend::Simple(web::Data::new(SimpleState::new())))
+    }
+
+    fn configure(self, cfg: &mut web::ServiceConfig) {
+        let honeypot_paths = self.honeypot.paths().to_vec();
+        cfg.app_data(self.reputation)
+            .app_data(self.beacons)
+            .app_data(self.exfil)
+            .app_data(self.homographs)
+            .app_data(self.rdap)
+            .app_data(self.security_headers)
+            .app_data(self.asn)
+            .app_data(self.ip_filter)
+            .app_data(self.trusted_proxies)
+            .app_data(self.capture)
+            .app_data(self.honeypot)
+            .app_data(self.admin_auth)
+            .app_data(self.users)
+            .app_data(self.bundle_signer)
+            .app_data(self.runtime_config)
+            .app_data(self.ban_list)
+            .app_data(self.quotas)
+            .app_data(self.metering)
+            .app_data(self.batch_limit)
+            .app_data(self.request_types)
+            .app_data(self.url_normalizer)
+            .app_data(self.event_data_limit)
+            .app_data(self.client_archive)
+            .app_data(self.annotations)
+            .app_data(self.triage)
+            .app_data(self.error_reports)
+            .app_data(self.uploads)
+            .app_data(self.scan_queue)
+            .app_data(self.alerts)
+            .app_data(self.features)
+            .app_data(self.screen_time)
+            .app_data(self.focus)
+            .app_data(self.categories)
+            .app_data(self.groups)
+            .app_data(self.client_names)
+            .app_data(self.ldap)
+            .app_data(self.tail)
+            .app_data(self.maintenance)
+            .app_data(self.chaos)
+            .app_data(self.backups)
+            .app_data(self.chain)
+            .app_data(self.clock_skew)
+            .app_data(self.telemetry)
+            .app_data(self.sessions)
+            .app_data(self.enrollment)
+            .app_data(self.blocklist_history)
+            .app_data(self.blocklist_signer)
+            .app_data(self.blocklist_rollout)
+            .app_data(self.experiments)
+            .app_data(self.detection_rules)
+            .app_data(self.rescans)
+            .app_data(self.blocklist_sync)
+            .app_data(self.ticketing)
+            .app_data(self.distinct)
+            .app_data(self.ingest_counters)
+            .app_data(self.stats_cache)
+            .app_data(self.scheduler)
+            .app_data(json_config())
+            .app_data(path_config())
+            .app_data(handlers::query::config());
+        match self.backend {
+            Backend::Simple(state) => {
+                cfg.app_data(state).configure(simple_routes);
+                handlers::honeypot::configure_simple(cfg, &honeypot_paths);
+            }
+            #[cfg(feature = "production")]
+            Backend::Production {
+                state,
+                pool_monitor,
+                stats_refresher,
+            } => {
+                cfg.app_data(state)
+                    .app_data(pool_monitor)
+                    .app_data(stats_refresher)
+                    .configure(production_routes);
+                handlers::honeypot::configure_production(cfg, &honeypot_paths);
+            }
+            #[cfg(feature = "production")]
+            Backend::Hybrid {
+                hot,
+                warm,
+                pool_monitor,
+                stats_refresher,
+            } => {
+                cfg.app_data(hot)
+                    .app_data(warm)
+                    .app_data(pool_monitor)
+                    .app_data(stats_refresher)
+                    .configure(hybrid_routes)
+                    .configure(simple_routes);
+                handlers::honeypot::configure_simple(cfg, &honeypot_paths);
+            }
+        }
+    }
+}
+
+/// Builds the application for one worker:
+///
+/// ```no_run
+/// use network_logger_server::{build_app, AppState};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let state = AppState::simple();
+/// actix_web::HttpServer::new(move || build_app(state.clone()))
+///     .bind("127.0.0.1:8080")?
+///     .run()
+///     .await
+/// # }
+/// ```
+pub fn build_app(
+    state: AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let access_log = state.access_log;
+    App::new()
+        .configure(|cfg| state.configure(cfg))
+        .wrap(from_fn(chaos::inject))
+        .wrap(from_fn(features::enforce))
+        .wrap(from_fn(maintenance::enforce))
+        .wrap(Cors::permissive())
+        .wrap(from_fn(capture::record))
+        .wrap(from_fn(ip_filter::enforce))
+        .wrap(from_fn(bans::enforce))
+        .wrap(from_fn(versioning::route))
+        .wrap(from_fn(security_headers::apply))
+        .wrap(Condition::new(access_log, Logger::default()))
+}
+
+async fn health_check(req: actix_web::HttpRequest) -> impl actix_web::Responder {
+    let client_ip = handlers::common::get_client_ip(&req);
+    log::debug!("🏥 Health check requested from IP: {}", client_ip);
+    #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+    let mut body = serde_json::json!({
+        "status": "healthy",
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "instance_id": instance::id(),
+        "client_ip": client_ip
+    });
+
+    #[cfg(feature = "production")]
+    if let Some(monitor) = req.app_data::<web::Data<PoolMonitor>>() {
+        let (healthy, database) = monitor.report();
+        let status = match database["status"].as_str() {
+            Some("ok") => "healthy",
+            Some("exhausted") => "degraded",
+            _ => "unhealthy",
+        };
+        body["status"] = status.into();
+        body["database"] = database;
+        if !healthy {
+            return HttpResponse::ServiceUnavailable().json(body);
+        }
+    }
+
+    HttpResponse::Ok().json(body)
+}
+
+fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let error_msg = format!("{}", err);
+        log::error!("❌ JSON parsing error: {}", error_msg);
+        let resp = ApiError::InvalidJson(error_msg).error_response();
+        actix_web::error::InternalError::from_response(err, resp).into()
+    })
+}
+
+/// Malformed path segments (e.g. a non-numeric annotation id) get the usual
+/// error envelope instead of actix's plain-text 404.
+fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err, _req| {
+        let resp = ApiError::NotFound(err.to_string()).error_response();
+        actix_web::error::InternalError::from_response(err, resp).into()
+    })
+}
+
+/// Routes served from `SimpleState`. Hybrid mode reuses these and registers its
+/// merged read handlers first, since actix picks the first matching route.
+fn simple_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(handlers::dashboard::serve_dashboard))
+        .route(
+            "/dashboard",
+            web::get().to(handlers::dashboard::serve_dashboard),
+        )
+        .route(
+            "/dashboard.js",
+            web::get().to(handlers::dashboard::serve_dashboard_script),
+        )
+        .route(
+            "/dashboard-theme.js",
+            web::get().to(handlers::dashboard::serve_theme_script),
+        )
+        .route("/logo.png", web::get().to(handlers::dashboard::serve_logo))
+        .route(
+            "/api/dashboard/login",
+            web::post().to(handlers::dashboard::post_login),
+        )
+        .route(
+            "/api/dashboard/session",
+            web::get().to(handlers::dashboard::get_session),
+        )
+        .route(
+            "/api/dashboard/logout",
+            web::post().to(handlers::dashboard::post_logout),
+        )
+        .route(
+            "/api/auth/oidc/login",
+            web::get().to(handlers::oidc::get_login),
+        )
+        .route(
+            "/api/auth/oidc/callback",
+            web::get().to(handlers::oidc::get_callback),
+        )
+        .route("/health", web::get().to(health_check))
+        .route("/metrics", web::get().to(metrics::get_metrics))
+        .route(
+            "/api/logs",
+            web::post().to(handlers::logs::post_logs_simple),
+        )
+        .route("/api/logs", web::get().to(handlers::logs::get_logs_simple))
+        .route(
+            "/api/logs/tail",
+            web::get().to(handlers::logs::tail_logs_simple),
+        )
+        .route(
+            "/api/logs/import",
+            web::post().to(handlers::import::import_logs_simple),
+        )
+        .route(
+            "/api/domains",
+            web::get().to(handlers::domains::get_domains_simple),
+        )
+        .route(
+            "/api/stats",
+            web::get().to(handlers::stats::get_stats_simple),
+        )
+        .route(
+            "/api/stats/providers",
+            web::get().to(handlers::stats::get_provider_stats),
+        )
+        .route(
+            "/api/clients/clock-skew",
+            web::get().to(handlers::clients::get_clock_skew),
+        )
+        .route(
+            "/api/clients/{client_id}/usage",
+            web::get().to(handlers::clients::get_usage),
+        )
+        .route(
+            "/api/clients/{client_id}/clock-skew",
+            web::get().to(handlers::clients::get_client_clock_skew),
+        )
+        .route(
+            "/api/clients/{client_id}/screen-time",
+            web::get().to(handlers::clients::get_screen_time),
+        )
+        .route(
+            "/api/clients/{client_id}/summary",
+            web::get().to(handlers::clients::get_summary_simple),
+        )
+        .route(
+            "/api/clients/{client_id}/effective-policy",
+            web::get().to(handlers::clients::get_effective_policy_simple),
+        )
+        .route(
+            "/api/sessions/{session_id}/annotations",
+            web::get().to(handlers::annotations::get_session_annotations),
+        )
+        .route(
+            "/api/sessions/{session_id}/annotations",
+            web::post().to(handlers::annotations::post_session_annotation),
+        )
+        .route(
+            "/api/clients/{client_id}/annotations",
+            web::get().to(handlers::annotations::get_client_annotations),
+        )
+        .route(
+            "/api/clients/{client_id}/annotations",
+            web::post().to(handlers::annotations::post_client_annotation),
+        )
+        .route(
+            "/api/annotations/{id}",
+            web::delete().to(handlers::annotations::delete_annotation),
+        )
+        .route(
+            "/api/events/{packet_id}/status",
+            web::get().to(handlers::triage::get_event_status),
+        )
+        .route(
+            "/api/events/{packet_id}/status",
+            web::post().to(handlers::triage::post_event_status),
+        )
+        .route(
+            "/api/events/{packet_id}/assignee",
+            web::post().to(handlers::triage::post_event_assignee),
+        )
+        .route(
+            "/api/events/{packet_id}/comments",
+            web::get().to(handlers::triage::get_event_comments),
+        )
+        .route(
+            "/api/events/{packet_id}/comments",
+            web::post().to(handlers::triage::post_event_comment),
+        )
+        .route(
+            "/api/admin/clients/archived",
+            web::get().to(handlers::clients::get_archived_clients),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/archive",
+            web::post().to(handlers::clients::archive_client),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/archive",
+            web::delete().to(handlers::clients::restore_client),
+        )
+        .route(
+            "/api/clients/names",
+            web::get().to(handlers::clients::get_client_names),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/name",
+            web::put().to(handlers::clients::put_client_name),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/name",
+            web::delete().to(handlers::clients::delete_client_name),
+        )
+        .route("/api/admin/bans", web::get().to(handlers::admin::get_bans))
+        .route(
+            "/api/admin/usage",
+            web::get().to(handlers::metering::get_usage),
+        )
+        .route(
+            "/api/admin/bans/{ip}",
+            web::delete().to(handlers::admin::delete_ban),
+        )
+        .route(
+            "/api/admin/session-conflicts",
+            web::get().to(handlers::admin::get_session_conflicts),
+        )
+        .route(
+            "/api/admin/enrollment",
+            web::get().to(handlers::enrollment::get_enrollment),
+        )
+        .route(
+            "/api/clients/{client_id}/approve",
+            web::post().to(handlers::enrollment::post_approve_simple),
+        )
+        .route(
+            "/api/clients/{client_id}/reject",
+            web::post().to(handlers::enrollment::post_reject),
+        )
+        .route("/api/admin/jobs", web::get().to(handlers::jobs::get_jobs))
+        .route(
+            "/api/admin/jobs/{name}/run",
+            web::post().to(handlers::jobs::post_run_job),
+        )
+        .route(
+            "/api/admin/jobs/{name}/cancel",
+            web::post().to(handlers::jobs::post_cancel_job),
+        )
+        .route(
+            "/api/admin/jobs/{name}/history",
+            web::get().to(handlers::jobs::get_job_history),
+        )
+        .route(
+            "/api/admin/rescan",
+            web::post().to(handlers::jobs::post_rescan),
+        )
+        .route(
+            "/api/admin/stats-cache",
+            web::delete().to(handlers::stats::delete_stats_cache),
+        )
+        .route(
+            "/api/admin/state",
+            web::get().to(handlers::admin::get_state),
+        )
+        .route(
+            "/api/admin/export-bundle",
+            web::get().to(handlers::bundle::export_bundle_simple),
+        )
+        .route(
+            "/api/export/training-set",
+            web::get().to(handlers::training::get_training_set_simple),
+        )
+        .route(
+            "/api/admin/import-bundle",
+            web::post().to(handlers::bundle::import_bundle_simple),
+        )
+        .route(
+            "/api/admin/loglevel",
+            web::post().to(handlers::admin::post_loglevel),
+        )
+        .route(
+            "/api/admin/alert-rules",
+            web::get().to(handlers::alerts::get_alert_rules),
+        )
+        .route(
+            "/api/admin/alert-rules",
+            web::put().to(handlers::alerts::put_alert_rules),
+        )
+        .route(
+            "/api/admin/alert-rules/test",
+            web::post().to(handlers::alerts::test_alert_rules),
+        )
+        .route(
+            "/api/blocklist",
+            web::get().to(handlers::blocklist::get_blocklist_simple),
+        )
+        .route(
+            "/api/blocklist",
+            web::post().to(handlers::blocklist::post_blocklist_simple),
+        )
+        .route(
+            "/api/blocklist/delta",
+            web::get().to(handlers::blocklist::get_blocklist_delta_simple),
+        )
+        .route(
+            "/api/blocklist/keys",
+            web::get().to(handlers::blocklist::get_blocklist_keys),
+        )
+        .route(
+            "/api/admin/blocklist-keys/rotate",
+            web::post().to(handlers::blocklist::post_rotate_blocklist_key),
+        )
+        .route(
+            "/api/admin/blocklist-keys/{key_id}",
+            web::delete().to(handlers::blocklist::delete_blocklist_key),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::get().to(handlers::blocklist::get_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::post().to(handlers::blocklist::post_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::put().to(handlers::blocklist::put_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout/promote",
+            web::post().to(handlers::blocklist::post_promote_simple),
+        )
+        .route(
+            "/api/admin/blocklist-rollout/rollback",
+            web::post().to(handlers::blocklist::post_rollback),
+        )
+        .route(
+            "/api/integrations/blocklist-sync",
+            web::post().to(handlers::integrations::post_blocklist_sync_simple),
+        )
+        .route(
+            "/api/experiments",
+            web::get().to(handlers::experiments::get_experiments),
+        )
+        .route(
+            "/api/experiments",
+            web::post().to(handlers::experiments::post_experiment),
+        )
+        .route(
+            "/api/experiments/{id}",
+            web::delete().to(handlers::experiments::delete_experiment),
+        )
+        .route(
+            "/api/experiments/{id}/results",
+            web::get().to(handlers::experiments::get_results),
+        )
+        .route(
+            "/api/experiments/{id}/stop",
+            web::post().to(handlers::experiments::post_stop),
+        )
+        .route("/api/rules", web::get().to(handlers::detection::get_rules))
+        .route("/api/rules", web::post().to(handlers::detection::post_rule))
+        .route(
+            "/api/rules/test",
+            web::post().to(handlers::detection::test_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::get().to(handlers::detection::get_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::put().to(handlers::detection::put_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::delete().to(handlers::detection::delete_rule),
+        )
+        .route(
+            "/api/rules/{id}/restore",
+            web::post().to(handlers::detection::post_restore),
+        )
+        .route(
+            "/api/rules/{id}/test",
+            web::post().to(handlers::detection::test_saved_rule_simple),
+        )
+        .route(
+            "/api/categories",
+            web::get().to(handlers::categories::get_categories_simple),
+        )
+        .route(
+            "/api/admin/categories/{category}",
+            web::put().to(handlers::categories::put_category_simple),
+        )
+        .route(
+            "/api/admin/features",
+            web::get().to(handlers::features::get_features),
+        )
+        .route(
+            "/api/admin/features/{feature}",
+            web::put().to(handlers::features::put_feature),
+        )
+        .route(
+            "/api/admin/features/{feature}",
+            web::delete().to(handlers::features::delete_feature),
+        )
+        .route(
+            "/api/admin/users",
+            web::get().to(handlers::users::get_users),
+        )
+        .route(
+            "/api/admin/users",
+            web::post().to(handlers::users::post_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::get().to(handlers::users::get_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::patch().to(handlers::users::patch_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::delete().to(handlers::users::delete_user),
+        )
+        .route(
+            "/api/admin/users/{username}/token",
+            web::post().to(handlers::users::post_token),
+        )
+        .route(
+            "/api/admin/users/{username}/token",
+            web::delete().to(handlers::users::delete_token),
+        )
+        .route(
+            "/api/admin/maintenance",
+            web::get().to(handlers::maintenance::get_maintenance),
+        )
+        .route(
+            "/api/admin/maintenance/pause",
+            web::post().to(handlers::maintenance::post_pause),
+        )
+        .route(
+            "/api/admin/maintenance/resume",
+            web::post().to(handlers::maintenance::post_resume),
+        )
+        .route(
+            "/api/admin/backup",
+            web::post().to(handlers::backup::post_backup_simple),
+        )
+        .route(
+            "/api/admin/backup/status",
+            web::get().to(handlers::backup::get_backup_status),
+        )
+        .route(
+            "/api/admin/backups",
+            web::get().to(handlers::backup::get_backups),
+        )
+        .route(
+            "/api/admin/restore",
+            web::post().to(handlers::backup::post_restore_simple),
+        )
+        .route(
+            "/api/admin/seed",
+            web::post().to(handlers::seed::seed_simple),
+        )
+        .route(
+            "/api/admin/chain",
+            web::get().to(handlers::chain::get_chain),
+        )
+        .route(
+            "/api/admin/chain/verify",
+            web::get().to(handlers::chain::verify_simple),
+        )
+        .route(
+            "/api/admin/groups",
+            web::get().to(handlers::groups::get_groups),
+        )
+        .route(
+            "/api/admin/groups",
+            web::put().to(handlers::groups::put_groups),
+        )
+        .route(
+            "/api/admin/groups/{group}/clients/{client_id}",
+            web::put().to(handlers::groups::put_group_member),
+        )
+        .route(
+            "/api/admin/groups/{group}/clients/{client_id}",
+            web::delete().to(handlers::groups::delete_group_member),
+        )
+        .route("/api/focus", web::post().to(handlers::focus::post_focus))
+        .route(
+            "/api/focus/{client_id}",
+            web::get().to(handlers::focus::get_focus),
+        )
+        .route(
+            "/api/focus/{client_id}",
+            web::delete().to(handlers::focus::delete_focus),
+        )
+        .route(
+            "/api/dashboard/events",
+            web::get().to(handlers::dashboard::get_dashboard_events_simple),
+        )
+        .route(
+            "/api/dashboard/events/export",
+            web::get().to(handlers::dashboard::export_dashboard_events_simple),
+        )
+        .route(
+            "/api/dashboard/events/{packet_id}",
+            web::get().to(handlers::dashboard::get_dashboard_packet_simple),
+        )
+        .route(
+            "/api/dashboard/clients",
+            web::get().to(handlers::dashboard::get_dashboard_clients_simple),
+        )
+        .route(
+            "/api/extensions",
+            web::post().to(handlers::extensions::post_extensions_simple),
+        )
+        .route(
+            "/api/security",
+            web::post().to(handlers::extensions::post_security_simple),
+        )
+        .route(
+            "/api/security/upload",
+            web::post().to(handlers::uploads::post_security_upload),
+        )
+        .route(
+            "/api/errors",
+            web::post().to(handlers::extension_errors::post_error),
+        )
+        .route(
+            "/api/errors",
+            web::get().to(handlers::extension_errors::get_errors),
+        )
+        .route(
+            "/api/errors/{hash}",
+            web::get().to(handlers::extension_errors::get_error),
+        )
+        .route(
+            "/api/telemetry",
+            web::post().to(handlers::telemetry::post_telemetry),
+        )
+        .route(
+            "/api/telemetry",
+            web::get().to(handlers::telemetry::get_telemetry),
+        )
+        .route(
+            "/api/telemetry/{version}",
+            web::get().to(handlers::telemetry::get_version_telemetry),
+        )
+        .route(
+            "/api/security/uploads/{packet_id}",
+            web::get().to(handlers::uploads::get_packet_uploads),
+        );
+}
+
+#[cfg(feature = "production")]
+fn production_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(handlers::dashboard::serve_dashboard))
+        .route(
+            "/dashboard",
+            web::get().to(handlers::dashboard::serve_dashboard),
+        )
+        .route(
+            "/dashboard.js",
+            web::get().to(handlers::dashboard::serve_dashboard_script),
+        )
+        .route(
+            "/dashboard-theme.js",
+            web::get().to(handlers::dashboard::serve_theme_script),
+        )
+        .route("/logo.png", web::get().to(handlers::dashboard::serve_logo))
+        .route(
+            "/api/dashboard/login",
+            web::post().to(handlers::dashboard::post_login),
+        )
+        .route(
+            "/api/dashboard/session",
+            web::get().to(handlers::dashboard::get_session),
+        )
+        .route(
+            "/api/dashboard/logout",
+            web::post().to(handlers::dashboard::post_logout),
+        )
+        .route(
+            "/api/auth/oidc/login",
+            web::get().to(handlers::oidc::get_login),
+        )
+        .route(
+            "/api/auth/oidc/callback",
+            web::get().to(handlers::oidc::get_callback),
+        )
+        .route("/health", web::get().to(health_check))
+        .route("/metrics", web::get().to(metrics::get_metrics))
+        .route(
+            "/api/logs",
+            web::post().to(handlers::logs::post_logs_production),
+        )
+        .route(
+            "/api/logs/tail",
+            web::get().to(handlers::logs::tail_logs_production),
+        )
+        .route(
+            "/api/logs/import",
+            web::post().to(handlers::import::import_logs_production),
+        )
+        .route(
+            "/api/domains",
+            web::get().to(handlers::domains::get_domains_production),
+        )
+        .route(
+            "/api/stats",
+            web::get().to(handlers::stats::get_stats_production),
+        )
+        .route(
+            "/api/stats/providers",
+            web::get().to(handlers::stats::get_provider_stats),
+        )
+        .route(
+            "/api/clients/clock-skew",
+            web::get().to(handlers::clients::get_clock_skew),
+        )
+        .route(
+            "/api/clients/{client_id}/usage",
+            web::get().to(handlers::clients::get_usage),
+        )
+        .route(
+            "/api/clients/{client_id}/clock-skew",
+            web::get().to(handlers::clients::get_client_clock_skew),
+        )
+        .route(
+            "/api/clients/{client_id}/screen-time",
+            web::get().to(handlers::clients::get_screen_time),
+        )
+        .route(
+            "/api/clients/{client_id}/summary",
+            web::get().to(handlers::clients::get_summary_production),
+        )
+        .route(
+            "/api/clients/{client_id}/effective-policy",
+            web::get().to(handlers::clients::get_effective_policy_production),
+        )
+        .route(
+            "/api/sessions/{session_id}/annotations",
+            web::get().to(handlers::annotations::get_session_annotations),
+        )
+        .route(
+            "/api/sessions/{session_id}/annotations",
+            web::post().to(handlers::annotations::post_session_annotation),
+        )
+        .route(
+            "/api/clients/{client_id}/annotations",
+            web::get().to(handlers::annotations::get_client_annotations),
+        )
+        .route(
+            "/api/clients/{client_id}/annotations",
+            web::post().to(handlers::annotations::post_client_annotation),
+        )
+        .route(
+            "/api/annotations/{id}",
+            web::delete().to(handlers::annotations::delete_annotation),
+        )
+        .route(
+            "/api/events/{packet_id}/status",
+            web::get().to(handlers::triage::get_event_status),
+        )
+        .route(
+            "/api/events/{packet_id}/status",
+            web::post().to(handlers::triage::post_event_status),
+        )
+        .route(
+            "/api/events/{packet_id}/assignee",
+            web::post().to(handlers::triage::post_event_assignee),
+        )
+        .route(
+            "/api/events/{packet_id}/comments",
+            web::get().to(handlers::triage::get_event_comments),
+        )
+        .route(
+            "/api/events/{packet_id}/comments",
+            web::post().to(handlers::triage::post_event_comment),
+        )
+        .route(
+            "/api/admin/clients/archived",
+            web::get().to(handlers::clients::get_archived_clients),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/archive",
+            web::post().to(handlers::clients::archive_client),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/archive",
+            web::delete().to(handlers::clients::restore_client),
+        )
+        .route(
+            "/api/clients/names",
+            web::get().to(handlers::clients::get_client_names),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/name",
+            web::put().to(handlers::clients::put_client_name),
+        )
+        .route(
+            "/api/admin/clients/{client_id}/name",
+            web::delete().to(handlers::clients::delete_client_name),
+        )
+        .route("/api/admin/bans", web::get().to(handlers::admin::get_bans))
+        .route(
+            "/api/admin/usage",
+            web::get().to(handlers::metering::get_usage),
+        )
+        .route(
+            "/api/admin/bans/{ip}",
+            web::delete().to(handlers::admin::delete_ban),
+        )
+        .route(
+            "/api/admin/session-conflicts",
+            web::get().to(handlers::admin::get_session_conflicts),
+        )
+        .route(
+            "/api/admin/enrollment",
+            web::get().to(handlers::enrollment::get_enrollment),
+        )
+        .route(
+            "/api/clients/{client_id}/approve",
+            web::post().to(handlers::enrollment::post_approve_production),
+        )
+        .route(
+            "/api/clients/{client_id}/reject",
+            web::post().to(handlers::enrollment::post_reject),
+        )
+        .route("/api/admin/jobs", web::get().to(handlers::jobs::get_jobs))
+        .route(
+            "/api/admin/jobs/{name}/run",
+            web::post().to(handlers::jobs::post_run_job),
+        )
+        .route(
+            "/api/admin/jobs/{name}/cancel",
+            web::post().to(handlers::jobs::post_cancel_job),
+        )
+        .route(
+            "/api/admin/jobs/{name}/history",
+            web::get().to(handlers::jobs::get_job_history),
+        )
+        .route(
+            "/api/admin/rescan",
+            web::post().to(handlers::jobs::post_rescan),
+        )
+        .route(
+            "/api/admin/stats-cache",
+            web::delete().to(handlers::stats::delete_stats_cache),
+        )
+        .route(
+            "/api/admin/state",
+            web::get().to(handlers::admin::get_state),
+        )
+        .route(
+            "/api/admin/export-bundle",
+            web::get().to(handlers::bundle::export_bundle_production),
+        )
+        .route(
+            "/api/export/training-set",
+            web::get().to(handlers::training::get_training_set_production),
+        )
+        .route(
+            "/api/admin/import-bundle",
+            web::post().to(handlers::bundle::import_bundle_production),
+        )
+        .route(
+            "/api/admin/loglevel",
+            web::post().to(handlers::admin::post_loglevel),
+        )
+        .route(
+            "/api/admin/alert-rules",
+            web::get().to(handlers::alerts::get_alert_rules),
+        )
+        .route(
+            "/api/admin/alert-rules",
+            web::put().to(handlers::alerts::put_alert_rules),
+        )
+        .route(
+            "/api/admin/alert-rules/test",
+            web::post().to(handlers::alerts::test_alert_rules),
+        )
+        .route(
+            "/api/blocklist",
+            web::get().to(handlers::blocklist::get_blocklist_production),
+        )
+        .route(
+            "/api/blocklist",
+            web::post().to(handlers::blocklist::post_blocklist_production),
+        )
+        .route(
+            "/api/blocklist/delta",
+            web::get().to(handlers::blocklist::get_blocklist_delta_production),
+        )
+        .route(
+            "/api/blocklist/keys",
+            web::get().to(handlers::blocklist::get_blocklist_keys),
+        )
+        .route(
+            "/api/admin/blocklist-keys/rotate",
+            web::post().to(handlers::blocklist::post_rotate_blocklist_key),
+        )
+        .route(
+            "/api/admin/blocklist-keys/{key_id}",
+            web::delete().to(handlers::blocklist::delete_blocklist_key),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::get().to(handlers::blocklist::get_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::post().to(handlers::blocklist::post_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout",
+            web::put().to(handlers::blocklist::put_rollout),
+        )
+        .route(
+            "/api/admin/blocklist-rollout/promote",
+            web::post().to(handlers::blocklist::post_promote_production),
+        )
+        .route(
+            "/api/admin/blocklist-rollout/rollback",
+            web::post().to(handlers::blocklist::post_rollback),
+        )
+        .route(
+            "/api/integrations/blocklist-sync",
+            web::post().to(handlers::integrations::post_blocklist_sync_production),
+        )
+        .route(
+            "/api/experiments",
+            web::get().to(handlers::experiments::get_experiments),
+        )
+        .route(
+            "/api/experiments",
+            web::post().to(handlers::experiments::post_experiment),
+        )
+        .route(
+            "/api/experiments/{id}",
+            web::delete().to(handlers::experiments::delete_experiment),
+        )
+        .route(
+            "/api/experiments/{id}/results",
+            web::get().to(handlers::experiments::get_results),
+        )
+        .route(
+            "/api/experiments/{id}/stop",
+            web::post().to(handlers::experiments::post_stop),
+        )
+        .route("/api/rules", web::get().to(handlers::detection::get_rules))
+        .route("/api/rules", web::post().to(handlers::detection::post_rule))
+        .route(
+            "/api/rules/test",
+            web::post().to(handlers::detection::test_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::get().to(handlers::detection::get_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::put().to(handlers::detection::put_rule),
+        )
+        .route(
+            "/api/rules/{id}",
+            web::delete().to(handlers::detection::delete_rule),
+        )
+        .route(
+            "/api/rules/{id}/restore",
+            web::post().to(handlers::detection::post_restore),
+        )
+        .route(
+            "/api/rules/{id}/test",
+            web::post().to(handlers::detection::test_saved_rule_production),
+        )
+        .route(
+            "/api/categories",
+            web::get().to(handlers::categories::get_categories_production),
+        )
+        .route(
+            "/api/admin/categories/{category}",
+            web::put().to(handlers::categories::put_category_production),
+        )
+        .route(
+            "/api/admin/features",
+            web::get().to(handlers::features::get_features),
+        )
+        .route(
+            "/api/admin/features/{feature}",
+            web::put().to(handlers::features::put_feature),
+        )
+        .route(
+            "/api/admin/features/{feature}",
+            web::delete().to(handlers::features::delete_feature),
+        )
+        .route(
+            "/api/admin/users",
+            web::get().to(handlers::users::get_users),
+        )
+        .route(
+            "/api/admin/users",
+            web::post().to(handlers::users::post_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::get().to(handlers::users::get_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::patch().to(handlers::users::patch_user),
+        )
+        .route(
+            "/api/admin/users/{username}",
+            web::delete().to(handlers::users::delete_user),
+        )
+        .route(
+            "/api/admin/users/{username}/token",
+            web::post().to(handlers::users::post_token),
+        )
+        .route(
+            "/api/admin/users/{username}/token",
+            web::delete().to(handlers::users::delete_token),
+        )
+        .route(
+            "/api/admin/maintenance",
+            web::get().to(handlers::maintenance::get_maintenance),
+        )
+        .route(
+            "/api/admin/maintenance/pause",
+            web::post().to(handlers::maintenance::post_pause),
+        )
+        .route(
+            "/api/admin/maintenance/resume",
+            web::post().to(handlers::maintenance::post_resume),
+        )
+        .route(
+            "/api/admin/backup",
+            web::post().to(handlers::backup::post_backup_production),
+        )
+        .route(
+            "/api/admin/backup/status",
+            web::get().to(handlers::backup::get_backup_status),
+        )
+        .route(
+            "/api/admin/backups",
+            web::get().to(handlers::backup::get_backups),
+        )
+        .route(
+            "/api/admin/restore",
+            web::post().to(handlers::backup::post_restore_production),
+        )
+        .route(
+            "/api/admin/seed",
+            web::post().to(handlers::seed::seed_production),
+        )
+        .route(
+            "/api/admin/chain",
+            web::get().to(handlers::chain::get_chain),
+        )
+        .route(
+            "/api/admin/chain/verify",
+            web::get().to(handlers::chain::verify_production),
+        )
+        .route(
+            "/api/admin/groups",
+            web::get().to(handlers::groups::get_groups),
+        )
+        .route(
+            "/api/admin/groups",
+            web::put().to(handlers::groups::put_groups),
+        )
+        .route(
+            "/api/admin/groups/{group}/clients/{client_id}",
+            web::put().to(handlers::groups::put_group_member),
+        )
+        .route(
+            "/api/admin/groups/{group}/clients/{client_id}",
+            web::delete().to(handlers::groups::delete_group_member),
+        )
+        .route("/api/focus", web::post().to(handlers::focus::post_focus))
+        .route(
+            "/api/focus/{client_id}",
+            web::get().to(handlers::focus::get_focus),
+        )
+        .route(
+            "/api/focus/{client_id}",
+            web::delete().to(handlers::focus::delete_focus),
+        )
+        .route(
+            "/api/extensions",
+            web::post().to(handlers::extensions::post_extensions_production),
+        )
+        .route(
+            "/api/security",
+            web::post().to(handlers::extensions::post_security_production),
+        )
+        .route(
+            "/api/security/upload",
+            web::post().to(handlers::uploads::post_security_upload),
+        )
+        .route(
+            "/api/errors",
+            web::post().to(handlers::extension_errors::post_error),
+        )
+        .route(
+            "/api/errors",
+            web::get().to(handlers::extension_errors::get_errors),
+        )
+        .route(
+            "/api/errors/{hash}",
+            web::get().to(handlers::extension_errors::get_error),
+        )
+        .route(
+            "/api/telemetry",
+            web::post().to(handlers::telemetry::post_telemetry),
+        )
+        .route(
+            "/api/telemetry",
+            web::get().to(handlers::telemetry::get_telemetry),
+        )
+        .route(
+            "/api/telemetry/{version}",
+            web::get().to(handlers::telemetry::get_version_telemetry),
+        )
+        .route(
+            "/api/security/uploads/{packet_id}",
+            web::get().to(handlers::uploads::get_packet_uploads),
+        );
+}
+
+/// Hybrid mode's merged reads; registered ahead of [`simple_routes`].
+#[cfg(feature = "production")]
+fn hybrid_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/api/logs",
+        web::get().to(handlers::hybrid::get_logs_hybrid),
+    )
+    .route(
+        "/api/logs/tail",
+        web::get().to(handlers::hybrid::tail_logs_hybrid),
+    )
+    // Every batch reaches the warm tier, so its aggregates cover both tiers.
+    .route(
+        "/api/stats",
+        web::get().to(handlers::stats::get_stats_production),
+    )
+    .route(
+        "/api/stats/providers",
+        web::get().to(handlers::stats::get_provider_stats),
+    )
+    .route(
+        "/api/clients/{client_id}/summary",
+        web::get().to(handlers::clients::get_summary_production),
+    )
+    .route(
+        "/api/dashboard/events",
+        web::get().to(handlers::hybrid::get_dashboard_events_hybrid),
+    )
+    .route(
+        "/api/dashboard/events/export",
+        web::get().to(handlers::hybrid::export_dashboard_events_hybrid),
+    )
+    .route(
+        "/api/dashboard/events/{packet_id}",
+        web::get().to(handlers::hybrid::get_dashboard_packet_hybrid),
+    )
+    // Logs and events are read from, and restored or approved into, the warm tier.
+    .route(
+        "/api/admin/backup",
+        web::post().to(handlers::backup::post_backup_hybrid),
+    )
+    .route(
+        "/api/admin/restore",
+        web::post().to(handlers::backup::post_restore_hybrid),
+    )
+    .route(
+        "/api/admin/seed",
+        web::post().to(handlers::seed::seed_hybrid),
+    )
+    .route(
+        "/api/clients/{client_id}/approve",
+        web::post().to(handlers::enrollment::post_approve_hybrid),
+    )
+    .route(
+        "/api/admin/chain/verify",
+        web::get().to(handlers::chain::verify_production),
+    );
+}
@@ -0,0 +1,140 @@
+//! Client lifecycle: an archived client keeps its stored history, but new
+//! batches from it are refused and it is hidden from the dashboard unless
+//! `include_archived=true` is passed.
+
+use crate::error::ApiError;
+use crate::metrics;
+use crate::types::ArchivedClient;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+/// How often production replicas re-read the archive table, so a change made
+/// through another replica takes effect here too.
+#[cfg(feature = "production")]
+const RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub struct ClientArchive {
+    archived: RwLock<HashMap<String, ArchivedClient>>,
+    #[cfg(feature = "production")]
+    storage: Option<Arc<crate::production::ProductionState>>,
+}
+
+impl Default for ClientArchive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientArchive {
+    /// In-memory only (simple mode): archives don't survive a restart.
+    pub fn new() -> Self {
+        ClientArchive {
+            archived: RwLock::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            storage: None,
+        }
+    }
+
+    /// Persists archive changes in `archived_clients` and keeps the in-memory
+    /// copy in sync with it.
+    #[cfg(feature = "production")]
+    pub fn spawn(state: Arc<crate::production::ProductionState>) -> Arc<ClientArchive> {
+        let archive = Arc::new(ClientArchive {
+            archived: RwLock::new(HashMap::new()),
+            storage: Some(state.clone()),
+        });
+        let a = archive.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match state.get_archived_clients().await {
+                    Ok(list) => {
+                        *a.archived.write().unwrap() =
+                            list.into_iter().map(|c| (c.client_id.clone(), c)).collect();
+                    }
+                    Err(e) => log::error!("❌ Loading archived clients failed: {}", e),
+                }
+                tokio::time::sleep(RESYNC_INTERVAL).await;
+            }
+        });
+        archive
+    }
+
+    pub fn is_archived(&self, client_id: &str) -> bool {
+        self.archived.read().unwrap().contains_key(client_id)
+    }
+
+    /// Archived clients, most recently archived first.
+    pub fn list(&self) -> Vec<ArchivedClient> {
+        let mut list: Vec<ArchivedClient> =
+            self.archived.read().unwrap().values().cloned().collect();
+        list.sort_by_key(|c| std::cmp::Reverse(c.archived_at));
+        list
+    }
+
+    /// Refuses ingest from an archived client with `410 Gone`, which is
+    /// distinct from a ban (403) or an exhausted quota (429).
+    pub fn check(&self, client_id: Option<&str>) -> Result<(), ApiError> {
+        let Some(client_id) = client_id else {
+            return Ok(());
+        };
+        let archived = self.archived.read().unwrap();
+        let Some(entry) = archived.get(client_id) else {
+            return Ok(());
+        };
+        metrics::ARCHIVED_CLIENT_REJECTED.inc();
+        log::debug!("🗄️ Refusing ingest from archived client_id={}", client_id);
+        Err(ApiError::ClientArchived {
+            client_id: client_id.to_string(),
+            archived_at: entry.archived_at,
+        })
+    }
+
+    pub async fn archive(
+        &self,
+        client_id: &str,
+        archived_by: Option<String>,
+        reason: Option<String>,
+    ) -> Result<ArchivedClient, String> {
+        self.put(ArchivedClient {
+            client_id: client_id.to_string(),
+            archived_at: Utc::now(),
+            archived_by,
+            reason,
+        })
+        .await
+    }
+
+    /// Stores `entry` as is, e.g. one read back from a backup.
+    pub async fn put(&self, entry: ArchivedClient) -> Result<ArchivedClient, String> {
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            state
+                .archive_client(&entry)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        self.archived
+            .write()
+            .unwrap()
+            .insert(entry.client_id.clone(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Lifts the archive; returns whether the client was archived.
+    pub async fn restore(&self, client_id: &str) -> Result<bool, String> {
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut found = false;
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            found = state
+                .unarchive_client(client_id)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(self.archived.write().unwrap().remove(client_id).is_some() || found)
+    }
+}
@@ -0,0 +1,400 @@
+//! IP / ASN enrichment of requested hosts (`--asn-db`). Each host is mapped
+//! to the autonomous system (hosting provider, ISP) its address belongs to,
+//! from an ip2asn-style TSV file (`range_start  range_end  AS_number
+//! country  AS_description`, as published by iptoasn.com), so
+//! `/api/stats/providers` can count requests per provider and requests to a
+//! `--bad-asn` raise a `bad_asn_host` security event, once per client and
+//! host a day.
+//!
+//! Hosts that are IP addresses are mapped right away. Names are only
+//! resolved with `--resolve-hosts`: deployments that must not leak the
+//! browsing of their clients to a DNS resolver leave it off. Resolution
+//! runs in the background, at most `--resolve-rate` lookups a second, and
+//! its answers are cached for [`CACHE_TTL`]; requests to a name that isn't
+//! resolved yet count as unresolved. Totals are per replica and start at
+//! zero with the process.
+
+use crate::handlers::common::domain_from_url;
+use crate::metrics;
+use crate::types::LogEntry;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a resolved address is trusted.
+pub const CACHE_TTL: Duration = Duration::from_secs(3600);
+/// How soon a name that didn't resolve is tried again.
+pub const RETRY_AFTER: Duration = Duration::from_secs(600);
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+/// `--resolve-rate` default.
+pub const DEFAULT_RATE: u32 = 20;
+/// Hosts kept in the cache; expired ones go first, then all of them.
+const MAX_CACHED: usize = 100_000;
+/// Client/host pairs remembered to alert once a day.
+const MAX_ALERTED: usize = 50_000;
+/// Hosts remembered per provider for its `hosts` count.
+const MAX_PROVIDER_HOSTS: usize = 10_000;
+
+/// An autonomous system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Asn {
+    pub asn: u32,
+    pub country: String,
+    pub name: String,
+}
+
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+/// IP ranges to autonomous systems, sorted by range start.
+#[derive(Debug, Default)]
+pub struct AsnDb {
+    ranges: Vec<(u128, u128, Asn)>,
+}
+
+impl AsnDb {
+    /// Reads ip2asn TSV (v4 and v6 ranges may be mixed). Ranges of AS 0
+    /// ("Not routed") are skipped; a line that doesn't parse is an error.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut ranges = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let bad = || {
+                format!(
+                    "line {}: expected start, end, AS number, country, name",
+                    n + 1
+                )
+            };
+            if fields.len() < 3 {
+                return Err(bad());
+            }
+            let start: IpAddr = fields[0].trim().parse().map_err(|_| bad())?;
+            let end: IpAddr = fields[1].trim().parse().map_err(|_| bad())?;
+            let asn: u32 = fields[2]
+                .trim()
+                .trim_start_matches("AS")
+                .parse()
+                .map_err(|_| bad())?;
+            if asn == 0 {
+                continue;
+            }
+            ranges.push((
+                key(start),
+                key(end),
+                Asn {
+                    asn,
+                    country: fields.get(3).map_or("", |c| c.trim()).to_string(),
+                    name: fields.get(4).map_or("", |c| c.trim()).to_string(),
+                },
+            ));
+        }
+        ranges.sort_by_key(|r| r.0);
+        Ok(AsnDb { ranges })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&Asn> {
+        let ip = key(ip);
+        let i = self.ranges.partition_point(|r| r.0 <= ip);
+        let (_, end, asn) = self.ranges.get(i.checked_sub(1)?)?;
+        (ip <= *end).then_some(asn)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BadAsnFinding {
+    pub client_id: Option<String>,
+    pub url: String,
+    pub host: String,
+    pub ip: IpAddr,
+    pub asn: Asn,
+}
+
+impl BadAsnFinding {
+    pub fn to_event_data(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "host": self.host,
+            "ip": self.ip.to_string(),
+            "asn": self.asn.asn,
+            "as_name": self.asn.name,
+            "country": self.asn.country,
+            "detection": { "riskScore": 80 }
+        })
+    }
+}
+
+/// One provider in `/api/stats/providers`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderCount {
+    #[serde(flatten)]
+    pub asn: Asn,
+    pub requests: u64,
+    /// Distinct hosts requested.
+    pub hosts: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub since: DateTime<Utc>,
+    pub resolving: bool,
+    /// Requests to names not resolved (yet), or to addresses in no range.
+    pub unresolved: u64,
+    pub providers: Vec<ProviderCount>,
+}
+
+struct Resolved {
+    /// The first address, `None` when the name didn't resolve.
+    ip: Option<IpAddr>,
+    fresh_for: Duration,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Provider {
+    requests: u64,
+    hosts: HashSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    resolved: HashMap<String, Resolved>,
+    pending: HashSet<String>,
+    /// Lookups started in the current second.
+    window: Option<(Instant, u32)>,
+    providers: HashMap<u32, (Asn, Provider)>,
+    unresolved: u64,
+    alerted: HashMap<(String, String), DateTime<Utc>>,
+}
+
+pub struct AsnEnricher {
+    db: Option<AsnDb>,
+    resolve: bool,
+    rate: u32,
+    bad_asns: BTreeSet<u32>,
+    since: DateTime<Utc>,
+    state: Mutex<State>,
+}
+
+impl Default for AsnEnricher {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl AsnEnricher {
+    pub fn disabled() -> Self {
+        AsnEnricher {
+            db: None,
+            resolve: false,
+            rate: DEFAULT_RATE,
+            bad_asns: BTreeSet::new(),
+            since: Utc::now(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Resolves names only when `resolve` is set, at most `rate` a second.
+    pub fn new(db: AsnDb, resolve: bool, rate: u32, bad_asns: &[u32]) -> Self {
+        AsnEnricher {
+            db: Some(db),
+            resolve,
+            rate: rate.max(1),
+            bad_asns: bad_asns.iter().copied().collect(),
+            since: Utc::now(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.db.is_some()
+    }
+
+    pub fn is_resolving(&self) -> bool {
+        self.resolve
+    }
+
+    /// The address of `host` when it is one or is cached, and whether a
+    /// lookup should be started.
+    fn address(&self, state: &mut State, host: &str) -> (Option<IpAddr>, bool) {
+        if let Ok(ip) = host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+        {
+            return (Some(ip), false);
+        }
+        if let Some(resolved) = state.resolved.get(host) {
+            if resolved.at.elapsed() < resolved.fresh_for {
+                return (resolved.ip, false);
+            }
+        }
+        if !self.resolve || state.pending.contains(host) {
+            return (None, false);
+        }
+        let now = Instant::now();
+        let (started, count) = match state.window {
+            Some((started, count)) if started.elapsed() < Duration::from_secs(1) => {
+                (started, count)
+            }
+            _ => (now, 0),
+        };
+        if count >= self.rate {
+            return (None, false);
+        }
+        state.window = Some((started, count + 1));
+        state.pending.insert(host.to_string());
+        (None, true)
+    }
+
+    fn remember(&self, host: &str, ip: Option<IpAddr>, fresh_for: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(host);
+        if state.resolved.len() >= MAX_CACHED {
+            state.resolved.retain(|_, r| r.at.elapsed() < r.fresh_for);
+            if state.resolved.len() >= MAX_CACHED {
+                state.resolved.clear();
+            }
+        }
+        state.resolved.insert(
+            host.to_string(),
+            Resolved {
+                ip,
+                fresh_for,
+                at: Instant::now(),
+            },
+        );
+    }
+
+    /// Requests per provider, busiest first.
+    pub fn snapshot(&self, limit: usize) -> Snapshot {
+        let state = self.state.lock().unwrap();
+        let mut providers: Vec<ProviderCount> = state
+            .providers
+            .values()
+            .map(|(asn, p)| ProviderCount {
+                asn: asn.clone(),
+                requests: p.requests,
+                hosts: p.hosts.len(),
+            })
+            .collect();
+        providers.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.asn.asn.cmp(&b.asn.asn)));
+        providers.truncate(limit);
+        Snapshot {
+            since: self.since,
+            resolving: self.resolve,
+            unresolved: state.unresolved,
+            providers,
+        }
+    }
+}
+
+async fn resolve(host: &str) -> Result<Option<IpAddr>, String> {
+    let lookup = tokio::net::lookup_host((host, 443));
+    match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+        Ok(Ok(mut addrs)) => Ok(addrs.next().map(|a| a.ip())),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// Called for every stored batch: counts its requests per provider, returns
+/// findings for hosts in a `--bad-asn`, and resolves new names in the
+/// background.
+pub fn observe(enricher: &web::Data<AsnEnricher>, entry: &LogEntry) -> Vec<BadAsnFinding> {
+    let Some(db) = &enricher.db else {
+        return Vec::new();
+    };
+    let mut findings = Vec::new();
+    let mut lookups = Vec::new();
+    {
+        let mut state = enricher.state.lock().unwrap();
+        let now = Utc::now();
+        for log in &entry.logs {
+            let Some(host) = domain_from_url(&log.url) else {
+                continue;
+            };
+            let host = host.to_ascii_lowercase();
+            let (ip, lookup) = enricher.address(&mut state, &host);
+            if lookup {
+                lookups.push(host.clone());
+            }
+            let Some((ip, asn)) = ip.and_then(|ip| db.lookup(ip).map(|asn| (ip, asn))) else {
+                state.unresolved += 1;
+                continue;
+            };
+            let (_, provider) = state
+                .providers
+                .entry(asn.asn)
+                .or_insert_with(|| (asn.clone(), Provider::default()));
+            provider.requests += 1;
+            if provider.hosts.len() < MAX_PROVIDER_HOSTS {
+                provider.hosts.insert(host.clone());
+            }
+            if !enricher.bad_asns.contains(&asn.asn) {
+                continue;
+            }
+            let key = (entry.client_id.clone().unwrap_or_default(), host.clone());
+            if state
+                .alerted
+                .get(&key)
+                .is_some_and(|at| now - *at < chrono::Duration::days(1))
+            {
+                continue;
+            }
+            if state.alerted.len() >= MAX_ALERTED {
+                state
+                    .alerted
+                    .retain(|_, at| now - *at < chrono::Duration::days(1));
+            }
+            state.alerted.insert(key, now);
+            findings.push(BadAsnFinding {
+                client_id: entry.client_id.clone(),
+                url: log.url.clone(),
+                host,
+                ip,
+                asn: asn.clone(),
+            });
+        }
+    }
+    for host in lookups {
+        let enricher = enricher.clone();
+        actix_web::rt::spawn(async move {
+            metrics::HOSTS_RESOLVED.inc();
+            match resolve(&host).await {
+                Ok(ip) => enricher.remember(&host, ip, CACHE_TTL),
+                Err(e) => {
+                    metrics::HOST_RESOLUTION_FAILURES.inc();
+                    log::debug!("⚠️ Resolving {} failed: {}", host, e);
+                    enricher.remember(&host, None, RETRY_AFTER);
+                }
+            }
+        });
+    }
+    findings
+}
@@ -0,0 +1,634 @@
+//! Admin authentication: the `--admin-token` sent as a header by scripts and
+//! the Telegram bot, a local user's API token (see [`users`](crate::users)),
+//! an identity provider's token with `--oidc-config` (see
+//! [`oidc`](crate::oidc)), or a dashboard login session. Each caller has a
+//! [`Role`] that decides what it may change; the admin token is always
+//! `admin`.
+//!
+//! Logging in to the dashboard trades the token, a local user's password or
+//! a single sign-on for a session cookie (`HttpOnly`, `SameSite=Strict`, `Secure` when the server
+//! is reached over HTTPS) and a CSRF token. Browsers attach the cookie on
+//! their own, so a request authenticated by it must not come from another
+//! origin, and one that changes something (anything but `GET`, `HEAD` and
+//! `OPTIONS`) must also echo the CSRF token in `X-CSRF-Token`. Requests
+//! carrying a token themselves need neither: no browser adds those headers
+//! by itself.
+//!
+//! Failed logins are counted per client address and per username: past
+//! [`MAX_FAILED_LOGINS_PER_IP`] or [`MAX_FAILED_LOGINS_PER_USER`] within
+//! [`LOGIN_WINDOW`], further attempts are refused with `429` until the
+//! window has passed, right or wrong.
+//!
+//! With `--redis-url`, sessions and failed logins are kept in Redis as well,
+//! so every replica knows them: a login on one replica is good on all, and
+//! a logout or a user's sessions ended anywhere end everywhere. While Redis
+//! can't be reached each replica goes by what it saw itself.
+
+use crate::error::ApiError;
+use crate::external_url;
+use crate::handlers::common::get_client_ip;
+use crate::oidc::Oidc;
+use crate::roles::Role;
+use crate::users::UserStore;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpRequest, ResponseError};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use redis::AsyncCommands;
+#[cfg(feature = "production")]
+use sha2::{Digest, Sha256};
+
+/// Name of the dashboard session cookie.
+pub const SESSION_COOKIE: &str = "canigoin_session";
+/// Header a cookie-authenticated request echoes its CSRF token in.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+/// How long a dashboard login lasts unless set otherwise.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::hours(12);
+/// How long failed logins are counted for.
+pub const LOGIN_WINDOW: Duration = Duration::minutes(15);
+pub const MAX_FAILED_LOGINS_PER_IP: u32 = 20;
+pub const MAX_FAILED_LOGINS_PER_USER: u32 = 5;
+/// Addresses and usernames counted at once; past it new ones aren't, until
+/// older windows pass.
+const MAX_LOGIN_KEYS: usize = 100_000;
+
+/// Who made a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    /// `admin-token`, or the local or single sign-on user's name.
+    pub user: String,
+    pub role: Role,
+}
+
+impl Identity {
+    fn admin_token() -> Identity {
+        Identity {
+            user: "admin-token".to_string(),
+            role: Role::Admin,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DashboardSession {
+    identity: Identity,
+    csrf_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// A new dashboard login: the cookie value stays in the cookie, the CSRF
+/// token goes to the page.
+pub struct Login {
+    pub session_id: String,
+    pub csrf_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The session a request's cookie names, as the page sees it.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub identity: Identity,
+    pub csrf_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Protection for admin and state-changing endpoints. Without an admin
+/// token, single sign-on or any local user those endpoints stay open,
+/// matching simple mode's zero-config behaviour.
+pub struct AdminAuth {
+    token: Option<String>,
+    oidc: Option<Arc<Oidc>>,
+    session_ttl: Duration,
+    sessions: RwLock<HashMap<String, DashboardSession>>,
+    /// Failed logins per `ip:` or `user:` key, with when the window began.
+    failed_logins: Mutex<HashMap<String, (u32, DateTime<Utc>)>>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl AdminAuth {
+    pub fn new(token: Option<String>) -> Self {
+        AdminAuth {
+            token: token.filter(|t| !t.is_empty()),
+            oidc: None,
+            session_ttl: DEFAULT_SESSION_TTL,
+            sessions: RwLock::new(HashMap::new()),
+            failed_logins: Mutex::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Shares sessions across replicas through Redis keys `session:<hash>`
+    /// (the cookie value's SHA-256, so Redis doesn't hold usable cookies)
+    /// with a set `user-sessions:<user>` of them per user, and failed logins
+    /// through `login-failures:<key>`. All of them expire on their own.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    /// How long a dashboard login lasts.
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    /// Also accepts users of the identity provider behind `oidc`.
+    pub fn with_oidc(mut self, oidc: Arc<Oidc>) -> Self {
+        self.oidc = Some(oidc);
+        self
+    }
+
+    pub fn oidc(&self) -> Option<&Arc<Oidc>> {
+        self.oidc.as_ref()
+    }
+
+    /// Whether admin endpoints need credentials: an admin token or single
+    /// sign-on is configured, or `users` has any account.
+    pub fn is_enabled(&self, users: Option<&UserStore>) -> bool {
+        self.token.is_some() || self.oidc.is_some() || users.is_some_and(|u| !u.is_empty())
+    }
+
+    /// Accepts `Authorization: Bearer <token>` or `X-Admin-Token: <token>`
+    /// with the admin token or an active local user's, a provider token as
+    /// `Authorization: Bearer`, or a dashboard session
+    /// cookie with the checks described above, from a caller whose role
+    /// allows the request.
+    pub async fn check(&self, req: &HttpRequest) -> Result<(), ApiError> {
+        let users = req.app_data::<web::Data<UserStore>>();
+        if !self.is_enabled(users.map(|u| u.as_ref())) {
+            return Ok(());
+        }
+        let identity = self.authenticate(req).await?;
+        let required = Role::required(req.method(), req.path());
+        if identity.role < required {
+            log::warn!(
+                "🚫 {} ({}) may not {} {}",
+                identity.user,
+                identity.role.name(),
+                req.method(),
+                req.path()
+            );
+            return Err(ApiError::Forbidden(format!(
+                "the {} role is needed for this",
+                required.name()
+            ))
+            .with("role", identity.role));
+        }
+        Ok(())
+    }
+
+    async fn authenticate(&self, req: &HttpRequest) -> Result<Identity, ApiError> {
+        let bearer = req
+            .headers()
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+        let presented = bearer.or_else(|| {
+            req.headers()
+                .get("x-admin-token")
+                .and_then(|h| h.to_str().ok())
+        });
+        if let Some(token) = presented {
+            if self
+                .token
+                .as_ref()
+                .is_some_and(|expected| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+            {
+                return Ok(Identity::admin_token());
+            }
+            if let Some(user) = req
+                .app_data::<web::Data<UserStore>>()
+                .and_then(|users| users.by_token(token))
+            {
+                return Ok(Identity {
+                    user: user.username,
+                    role: user.role,
+                });
+            }
+            if let (Some(oidc), Some(jwt)) = (&self.oidc, bearer) {
+                return match oidc.verify_bearer(jwt) {
+                    Ok(user) => Ok(Identity {
+                        user: user.name,
+                        role: user.role,
+                    }),
+                    Err(e) if e.status_code() == StatusCode::UNAUTHORIZED => {
+                        Err(self.refuse(req, e))
+                    }
+                    Err(e) => Err(e),
+                };
+            }
+            return Err(self.refuse(req, ApiError::Unauthorized));
+        }
+        if req.cookie(SESSION_COOKIE).is_some() {
+            return self.check_session(req).await;
+        }
+        Err(self.refuse(req, ApiError::Unauthorized))
+    }
+
+    fn refuse(&self, req: &HttpRequest, error: ApiError) -> ApiError {
+        let client_ip = get_client_ip(req);
+        log::warn!(
+            "🔑 Admin authentication failed from IP {} ({})",
+            client_ip,
+            req.path()
+        );
+        error.with("client_ip", client_ip)
+    }
+
+    async fn check_session(&self, req: &HttpRequest) -> Result<Identity, ApiError> {
+        let Some(session) = self.live_session(req).await else {
+            return Err(self.refuse(req, ApiError::Unauthorized));
+        };
+        let reason = if !same_origin(req) {
+            "request from another origin"
+        } else if is_safe(req.method()) {
+            return Ok(session.identity);
+        } else {
+            match req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+                Some(sent) if constant_time_eq(sent.as_bytes(), session.csrf_token.as_bytes()) => {
+                    return Ok(session.identity)
+                }
+                Some(_) => "wrong X-CSRF-Token",
+                None => "missing X-CSRF-Token",
+            }
+        };
+        log::warn!(
+            "🛡️ CSRF check failed from IP {} ({} {}): {}",
+            get_client_ip(req),
+            req.method(),
+            req.path(),
+            reason
+        );
+        crate::metrics::CSRF_REJECTED.inc();
+        Err(ApiError::CsrfFailed(reason))
+    }
+
+    async fn live_session(&self, req: &HttpRequest) -> Option<SessionInfo> {
+        let cookie = req.cookie(SESSION_COOKIE)?;
+        let info = |session: &DashboardSession| {
+            (session.expires_at > Utc::now()).then(|| SessionInfo {
+                identity: session.identity.clone(),
+                csrf_token: session.csrf_token.clone(),
+                expires_at: session.expires_at,
+            })
+        };
+        #[cfg(feature = "production")]
+        if let Some(shared) = self.redis_session(cookie.value()).await {
+            return info(&shared?);
+        }
+        let sessions = self.sessions.read().unwrap();
+        info(sessions.get(cookie.value())?)
+    }
+
+    /// The live session `req`'s cookie names, unless `req` comes from
+    /// another origin (which could read the CSRF token, since CORS is
+    /// permissive).
+    pub async fn session(&self, req: &HttpRequest) -> Option<SessionInfo> {
+        self.live_session(req).await.filter(|_| same_origin(req))
+    }
+
+    /// Starts a dashboard session for whoever presents the admin token.
+    pub async fn login(&self, req: &HttpRequest, token: &str) -> Result<Login, ApiError> {
+        let Some(ref expected) = self.token else {
+            return Err(ApiError::BadRequest(match self.oidc {
+                Some(_) => "no --admin-token is set; log in with /api/auth/oidc/login".to_string(),
+                None => "no --admin-token is set, so admin endpoints need no login".to_string(),
+            }));
+        };
+        let keys = [(
+            format!("ip:{}", get_client_ip(req)),
+            MAX_FAILED_LOGINS_PER_IP,
+        )];
+        self.login_allowed(req, &keys).await?;
+        if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            self.login_failed(&keys).await;
+            return Err(self.refuse(req, ApiError::Unauthorized));
+        }
+        Ok(self.start_session(Identity::admin_token()).await)
+    }
+
+    /// Starts a dashboard session for the active local user `username`. The
+    /// password is checked on the blocking thread pool: PBKDF2 would stall
+    /// every other request on this worker.
+    pub async fn login_user(
+        &self,
+        req: &HttpRequest,
+        users: web::Data<UserStore>,
+        username: &str,
+        password: &str,
+    ) -> Result<Login, ApiError> {
+        if !self.is_enabled(Some(&users)) {
+            return Err(ApiError::BadRequest(
+                "no --admin-token is set, so admin endpoints need no login".to_string(),
+            ));
+        }
+        let user_key = format!("user:{}", username);
+        let keys = [
+            (
+                format!("ip:{}", get_client_ip(req)),
+                MAX_FAILED_LOGINS_PER_IP,
+            ),
+            (user_key.clone(), MAX_FAILED_LOGINS_PER_USER),
+        ];
+        self.login_allowed(req, &keys).await?;
+        let (username, password) = (username.to_string(), password.to_string());
+        let user = web::block(move || users.by_password(&username, &password))
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("❌ Password check failed to run: {}", e);
+                None
+            });
+        let Some(user) = user else {
+            self.login_failed(&keys).await;
+            return Err(self.refuse(req, ApiError::Unauthorized));
+        };
+        self.failed_logins.lock().unwrap().remove(&user_key);
+        #[cfg(feature = "production")]
+        if let Some(mut conn) = self.redis().await {
+            timed(conn.del::<_, ()>(format!("login-failures:{}", user_key))).await;
+        }
+        Ok(self
+            .start_session(Identity {
+                user: user.username,
+                role: user.role,
+            })
+            .await)
+    }
+
+    /// Refuses a login while any of `keys` has used up its failures.
+    async fn login_allowed(
+        &self,
+        req: &HttpRequest,
+        keys: &[(String, u32)],
+    ) -> Result<(), ApiError> {
+        let now = Utc::now();
+        #[cfg(feature = "production")]
+        let shared = self.redis_failures(keys).await;
+        #[cfg(not(feature = "production"))]
+        let shared: Option<Vec<(u32, DateTime<Utc>)>> = None;
+        let failed: Vec<Option<(u32, DateTime<Utc>)>> = match shared {
+            Some(shared) => shared.into_iter().map(Some).collect(),
+            None => {
+                let failed = self.failed_logins.lock().unwrap();
+                keys.iter()
+                    .map(|(key, _)| failed.get(key).copied())
+                    .collect()
+            }
+        };
+        let retry_at = keys
+            .iter()
+            .zip(failed)
+            .filter_map(|((_, max), failed)| {
+                let (count, since) = failed?;
+                (count >= *max && since + LOGIN_WINDOW > now).then_some(since + LOGIN_WINDOW)
+            })
+            .max();
+        match retry_at {
+            Some(retry_at) => {
+                log::warn!(
+                    "🔑 Login from IP {} throttled after repeated failures",
+                    get_client_ip(req)
+                );
+                Err(ApiError::LoginThrottled { retry_at })
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn login_failed(&self, keys: &[(String, u32)]) {
+        let now = Utc::now();
+        {
+            let mut failed = self.failed_logins.lock().unwrap();
+            if failed.len() >= MAX_LOGIN_KEYS {
+                failed.retain(|_, (_, since)| *since + LOGIN_WINDOW > now);
+            }
+            for (key, _) in keys {
+                if !failed.contains_key(key) && failed.len() >= MAX_LOGIN_KEYS {
+                    continue;
+                }
+                let (count, since) = failed.entry(key.clone()).or_insert((0, now));
+                if *since + LOGIN_WINDOW <= now {
+                    (*count, *since) = (0, now);
+                }
+                *count += 1;
+            }
+        }
+        #[cfg(feature = "production")]
+        if let Some(mut conn) = self.redis().await {
+            // The key is created with the window's expiry, then counted.
+            let mut pipe = redis::pipe();
+            for (key, _) in keys {
+                let key = format!("login-failures:{}", key);
+                pipe.cmd("SET")
+                    .arg(&key)
+                    .arg(0)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(LOGIN_WINDOW.num_seconds())
+                    .ignore()
+                    .incr(&key, 1)
+                    .ignore();
+            }
+            timed(pipe.query_async::<_, ()>(&mut conn)).await;
+        }
+    }
+
+    /// Starts a dashboard session for an already authenticated `identity`.
+    pub async fn start_session(&self, identity: Identity) -> Login {
+        let login = Login {
+            session_id: random_token(),
+            csrf_token: random_token(),
+            expires_at: Utc::now() + self.session_ttl,
+        };
+        let session = DashboardSession {
+            identity,
+            csrf_token: login.csrf_token.clone(),
+            expires_at: login.expires_at,
+        };
+        #[cfg(feature = "production")]
+        self.redis_start(&login.session_id, &session).await;
+        let mut sessions = self.sessions.write().unwrap();
+        let now = Utc::now();
+        sessions.retain(|_, s| s.expires_at > now);
+        sessions.insert(login.session_id.clone(), session);
+        login
+    }
+
+    /// Ends the session `req`'s cookie names, if any.
+    pub async fn logout(&self, req: &HttpRequest) {
+        let Some(cookie) = req.cookie(SESSION_COOKIE) else {
+            return;
+        };
+        self.sessions.write().unwrap().remove(cookie.value());
+        #[cfg(feature = "production")]
+        if let Some(mut conn) = self.redis().await {
+            timed(conn.del::<_, ()>(session_key(cookie.value()))).await;
+        }
+    }
+
+    /// Ends every dashboard session of `user`, e.g. once the account is
+    /// deactivated or its role changes.
+    pub async fn end_sessions(&self, user: &str) {
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|_, s| s.identity.user != user);
+        #[cfg(feature = "production")]
+        if let Some(mut conn) = self.redis().await {
+            let index = format!("user-sessions:{}", user);
+            if let Some(mut keys) = timed(conn.smembers::<_, Vec<String>>(&index)).await {
+                keys.push(index);
+                timed(conn.del::<_, ()>(keys)).await;
+            }
+        }
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis(&self) -> Option<redis::aio::ConnectionManager> {
+        self.redis.as_ref()?.conn().await
+    }
+
+    /// The session Redis holds for `session_id`, if any; `None` without an
+    /// answer from Redis.
+    #[cfg(feature = "production")]
+    async fn redis_session(&self, session_id: &str) -> Option<Option<DashboardSession>> {
+        let mut conn = self.redis().await?;
+        let raw: Option<String> = timed(conn.get(session_key(session_id))).await?;
+        Some(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis_start(&self, session_id: &str, session: &DashboardSession) {
+        let (Some(mut conn), Ok(raw)) = (self.redis().await, serde_json::to_string(session)) else {
+            return;
+        };
+        let key = session_key(session_id);
+        let index = format!("user-sessions:{}", session.identity.user);
+        let ttl = self.session_ttl.num_seconds().max(1);
+        let mut pipe = redis::pipe();
+        pipe.cmd("SET")
+            .arg(&key)
+            .arg(raw)
+            .arg("EX")
+            .arg(ttl)
+            .ignore()
+            .sadd(&index, &key)
+            .ignore()
+            .expire(&index, ttl)
+            .ignore();
+        timed(pipe.query_async::<_, ()>(&mut conn)).await;
+    }
+
+    /// The failure count of each of `keys` and when its window began, in one
+    /// round trip; `None` without an answer from Redis.
+    #[cfg(feature = "production")]
+    async fn redis_failures(&self, keys: &[(String, u32)]) -> Option<Vec<(u32, DateTime<Utc>)>> {
+        let mut conn = self.redis().await?;
+        let mut pipe = redis::pipe();
+        for (key, _) in keys {
+            let key = format!("login-failures:{}", key);
+            pipe.get(&key).ttl(&key);
+        }
+        let answers: Vec<(Option<u32>, i64)> = timed(pipe.query_async(&mut conn)).await?;
+        let now = Utc::now();
+        Some(
+            answers
+                .into_iter()
+                .map(|(count, ttl)| {
+                    let since = now + Duration::seconds(ttl.max(0)) - LOGIN_WINDOW;
+                    (count.unwrap_or(0), since)
+                })
+                .collect(),
+        )
+    }
+
+    /// The session cookie for `login`, `Secure` when `req` came over HTTPS
+    /// (directly, per the forwarding headers, or per `--external-url`).
+    pub fn session_cookie(&self, req: &HttpRequest, login: &Login) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, login.session_id.clone())
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .secure(is_https(req))
+            .max_age(actix_web::cookie::time::Duration::seconds(
+                self.session_ttl.num_seconds(),
+            ))
+            .finish()
+    }
+}
+
+#[cfg(feature = "production")]
+fn session_key(session_id: &str) -> String {
+    format!(
+        "session:{}",
+        hex::encode(Sha256::digest(session_id.as_bytes()))
+    )
+}
+
+/// Clears the session cookie in the browser.
+pub fn expired_session_cookie() -> Cookie<'static> {
+    let mut cookie = Cookie::build(SESSION_COOKIE, "")
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    cookie.make_removal();
+    cookie
+}
+
+/// Whether the browser reached the server over HTTPS.
+pub(crate) fn is_https(req: &HttpRequest) -> bool {
+    external_url::for_request(req).starts_with("https://")
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// False when `Origin` (or, without it, `Referer`) names another origin
+/// than the one the request was sent to. Requests with neither pass; the
+/// CSRF token still has to match.
+fn same_origin(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    let Some(source) = headers
+        .get("origin")
+        .or_else(|| headers.get("referer"))
+        .and_then(|h| h.to_str().ok())
+    else {
+        return true;
+    };
+    let host = req.connection_info().host().to_ascii_lowercase();
+    let source_host = source
+        .split_once("://")
+        .map_or(source, |(_, rest)| rest)
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    source_host == host
+}
+
+/// 32 random bytes, hex-encoded.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("no system random number generator");
+    hex::encode(bytes)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
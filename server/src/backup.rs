@@ -0,0 +1,355 @@
+//! Backups (`--backup-dir`): `POST /api/admin/backup` writes the blocklist,
+//! archived clients, logs and extension events to a gzip-compressed NDJSON
+//! file, and `POST /api/admin/restore` loads one back. Both run in the
+//! background, one at a time, with their progress at
+//! `GET /api/admin/backup/status`.
+//!
+//! The file is one record per line: a header, then the data, then an `end`
+//! record with the counts, so a truncated file is refused on restore. In
+//! production the export pages through the database and stops at the time
+//! the backup started; pause ingest for maintenance first for a backup that
+//! matches the database exactly.
+
+use crate::error::ApiError;
+use crate::types::{ArchivedClient, Blocklist, ExtensionEvent, LogEntry};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const FORMAT: &str = "canigoin-backup";
+pub const VERSION: u32 = 1;
+/// Rows read from the database per query.
+pub const PAGE: i64 = 5000;
+/// Progress is updated after this many records.
+pub const PROGRESS_EVERY: u64 = 1000;
+const EXTENSION: &str = ".ndjson.gz";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// Data stored after this isn't in the backup.
+    pub as_of: DateTime<Utc>,
+    pub mode: String,
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counts {
+    pub archived_clients: u64,
+    pub logs: u64,
+    pub events: u64,
+}
+
+/// One line of a backup file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Header(Header),
+    Blocklist(Blocklist),
+    ArchivedClient(ArchivedClient),
+    Log(LogEntry),
+    Event(ExtensionEvent),
+    End(Counts),
+}
+
+/// Writes `<dir>/<name>.partial`, renamed to `<name>` once complete.
+pub struct Writer {
+    path: PathBuf,
+    partial: PathBuf,
+    out: GzEncoder<io::BufWriter<std::fs::File>>,
+    counts: Counts,
+}
+
+impl Writer {
+    pub fn create(dir: &Path, header: Header) -> io::Result<Writer> {
+        let name = format!(
+            "{}-{}{}",
+            FORMAT,
+            header.created_at.format("%Y%m%dT%H%M%S%.3fZ"),
+            EXTENSION
+        );
+        let path = dir.join(name);
+        let partial = path.with_extension("gz.partial");
+        let file = std::fs::File::create(&partial)?;
+        let mut writer = Writer {
+            path,
+            partial,
+            out: GzEncoder::new(io::BufWriter::new(file), flate2::Compression::default()),
+            counts: Counts::default(),
+        };
+        writer.line(&Record::Header(header))?;
+        Ok(writer)
+    }
+
+    pub fn file_name(&self) -> String {
+        let name = self.path.file_name().unwrap_or_default();
+        name.to_string_lossy().into_owned()
+    }
+
+    pub fn counts(&self) -> Counts {
+        self.counts
+    }
+
+    fn line(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match record {
+            Record::ArchivedClient(_) => self.counts.archived_clients += 1,
+            Record::Log(_) => self.counts.logs += 1,
+            Record::Event(_) => self.counts.events += 1,
+            _ => {}
+        }
+        self.line(record)
+    }
+
+    pub fn finish(mut self) -> io::Result<(PathBuf, Counts)> {
+        let counts = self.counts;
+        self.line(&Record::End(counts))?;
+        self.out.finish()?.into_inner()?.sync_all()?;
+        std::fs::rename(&self.partial, &self.path)?;
+        Ok((self.path, counts))
+    }
+
+    /// Deletes the partial file of a backup that failed.
+    pub fn abandon(self) {
+        let _ = std::fs::remove_file(&self.partial);
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a backup file record by record.
+pub struct Reader {
+    lines: io::Lines<io::BufReader<GzDecoder<std::fs::File>>>,
+    line: usize,
+}
+
+impl Reader {
+    pub fn open(path: &Path) -> io::Result<Reader> {
+        let file = std::fs::File::open(path)?;
+        Ok(Reader {
+            lines: io::BufReader::new(GzDecoder::new(file)).lines(),
+            line: 0,
+        })
+    }
+}
+
+impl Iterator for Reader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(&line)
+                    .map_err(|e| invalid(format!("line {}: {}", self.line, e))),
+            );
+        }
+    }
+}
+
+/// Checks that a backup file is complete, i.e. every line parses and the
+/// `end` counts match, and returns its header and counts.
+pub fn verify(path: &Path) -> io::Result<(Header, Counts)> {
+    let mut header = None;
+    let mut seen = Counts::default();
+    let mut end = None;
+    for record in Reader::open(path)? {
+        match record? {
+            Record::Header(h) => header = Some(h),
+            Record::ArchivedClient(_) => seen.archived_clients += 1,
+            Record::Log(_) => seen.logs += 1,
+            Record::Event(_) => seen.events += 1,
+            Record::End(counts) => end = Some(counts),
+            Record::Blocklist(_) => {}
+        }
+    }
+    let header = header.ok_or_else(|| invalid("no header".into()))?;
+    if header.format != FORMAT || header.version > VERSION {
+        return Err(invalid(format!(
+            "not a {} file of version {} or older",
+            FORMAT, VERSION
+        )));
+    }
+    match end {
+        Some(counts) if counts == seen => Ok((header, counts)),
+        Some(_) => Err(invalid(
+            "the record counts don't match the end marker".into(),
+        )),
+        None => Err(invalid("the file is truncated (no end marker)".into())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Backup,
+    Restore,
+}
+
+impl JobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Backup => "backup",
+            JobKind::Restore => "restore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// The backup or restore running now, or the last one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub file: String,
+    pub started_by: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Records written or restored so far.
+    pub progress: Counts,
+    /// For a restore, what the file holds.
+    pub total: Option<Counts>,
+    /// Restored records that were already present and were skipped.
+    pub skipped: Counts,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupFile {
+    pub file: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+pub struct Backups {
+    dir: Option<PathBuf>,
+    job: Mutex<Option<Job>>,
+}
+
+impl Backups {
+    pub fn disabled() -> Self {
+        Backups {
+            dir: None,
+            job: Mutex::new(None),
+        }
+    }
+
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Backups {
+            dir: Some(dir.to_path_buf()),
+            job: Mutex::new(None),
+        })
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    pub fn job(&self) -> Option<Job> {
+        self.job.lock().unwrap().clone()
+    }
+
+    /// Registers a new job; `409` while another one is running.
+    pub fn start(&self, kind: JobKind, file: &str, started_by: &str) -> Result<Job, ApiError> {
+        let mut current = self.job.lock().unwrap();
+        if let Some(job) = current.as_ref().filter(|j| j.status == JobStatus::Running) {
+            return Err(
+                ApiError::Conflict(format!("a {} is already running", job.kind.as_str()))
+                    .with("job", job),
+            );
+        }
+        let job = Job {
+            id: current.as_ref().map_or(1, |j| j.id + 1),
+            kind,
+            status: JobStatus::Running,
+            file: file.to_string(),
+            started_by: started_by.to_string(),
+            started_at: Utc::now(),
+            finished_at: None,
+            progress: Counts::default(),
+            total: None,
+            skipped: Counts::default(),
+            error: None,
+        };
+        *current = Some(job.clone());
+        Ok(job)
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.job.lock().unwrap().as_mut() {
+            f(job);
+        }
+    }
+
+    pub fn finish(&self, result: Result<(), String>) {
+        self.update(|job| {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => job.status = JobStatus::Done,
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e);
+                }
+            }
+        });
+    }
+
+    /// Completed backups in the directory, newest first.
+    pub fn list(&self) -> io::Result<Vec<BackupFile>> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let mut out = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if !file.starts_with(FORMAT) || !file.ends_with(EXTENSION) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            out.push(BackupFile {
+                file,
+                size: meta.len(),
+                modified: meta.modified().map(DateTime::<Utc>::from)?,
+            });
+        }
+        out.sort_by(|a, b| b.file.cmp(&a.file));
+        Ok(out)
+    }
+
+    /// The path of a backup in the directory, by file name; `None` for names
+    /// that aren't a backup file or would leave the directory.
+    pub fn resolve(&self, file: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let plain = !file.contains(['/', '\\']) && !file.starts_with('.');
+        (plain && file.ends_with(EXTENSION)).then(|| dir.join(file))
+    }
+}
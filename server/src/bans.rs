@@ -0,0 +1,267 @@
+use crate::error::ApiError;
+use crate::handlers::common::client_addr;
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use redis::AsyncCommands;
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offense {
+    AuthFailure,
+    MalformedPayload,
+}
+
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct Ban {
+    pub ip: String,
+    pub reason: String,
+    pub banned_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct BanPolicy {
+    pub auth_failures: usize,
+    pub malformed: usize,
+    pub window: Duration,
+    pub ban_duration: Duration,
+}
+
+#[derive(Default)]
+struct Strikes {
+    auth: VecDeque<DateTime<Utc>>,
+    malformed: VecDeque<DateTime<Utc>>,
+}
+
+/// Fail2ban-style tracker: offenses inside the window accumulate per IP until a
+/// threshold is crossed, then the IP is refused until the ban expires.
+pub struct BanList {
+    policy: Option<BanPolicy>,
+    strikes: Mutex<HashMap<String, Strikes>>,
+    bans: Mutex<HashMap<String, Ban>>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl BanList {
+    /// `policy: None` disables automatic banning entirely.
+    pub fn new(policy: Option<BanPolicy>) -> Self {
+        BanList {
+            policy,
+            strikes: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Shares bans across replicas through Redis keys `ban:<ip>` that expire
+    /// with the ban.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    pub async fn active_ban(&self, ip: &str) -> Option<Ban> {
+        let now = Utc::now();
+        {
+            let mut bans = self.bans.lock().unwrap();
+            match bans.get(ip) {
+                Some(ban) if ban.expires_at > now => return Some(ban.clone()),
+                Some(_) => {
+                    bans.remove(ip);
+                }
+                None => {}
+            }
+        }
+        #[cfg(feature = "production")]
+        if let Some(ban) = self.redis_get(ip).await {
+            self.bans
+                .lock()
+                .unwrap()
+                .insert(ip.to_string(), ban.clone());
+            return Some(ban);
+        }
+        None
+    }
+
+    pub async fn record(&self, ip: &str, offense: Offense) {
+        let Some(ref policy) = self.policy else {
+            return;
+        };
+        let now = Utc::now();
+        let cutoff = now - policy.window;
+        let crossed = {
+            let mut strikes = self.strikes.lock().unwrap();
+            let s = strikes.entry(ip.to_string()).or_default();
+            let (queue, limit) = match offense {
+                Offense::AuthFailure => (&mut s.auth, policy.auth_failures),
+                Offense::MalformedPayload => (&mut s.malformed, policy.malformed),
+            };
+            queue.push_back(now);
+            while queue.front().is_some_and(|t| *t < cutoff) {
+                queue.pop_front();
+            }
+            let crossed = limit > 0 && queue.len() >= limit;
+            if crossed {
+                strikes.remove(ip);
+            }
+            if strikes.len() > 50_000 {
+                strikes.retain(|_, s| {
+                    s.auth.back().is_some_and(|t| *t >= cutoff)
+                        || s.malformed.back().is_some_and(|t| *t >= cutoff)
+                });
+            }
+            crossed
+        };
+        if !crossed {
+            return;
+        }
+        let reason = match offense {
+            Offense::AuthFailure => "too many authentication failures",
+            Offense::MalformedPayload => "too many malformed payloads",
+        };
+        let ban = Ban {
+            ip: ip.to_string(),
+            reason: reason.to_string(),
+            banned_at: now,
+            expires_at: now + policy.ban_duration,
+        };
+        log::warn!(
+            "🔨 Banning IP {} until {}: {}",
+            ip,
+            ban.expires_at.to_rfc3339(),
+            reason
+        );
+        metrics::IPS_BANNED.inc();
+        #[cfg(feature = "production")]
+        self.redis_set(&ban).await;
+        self.bans.lock().unwrap().insert(ip.to_string(), ban);
+    }
+
+    pub async fn list(&self) -> Vec<Ban> {
+        let now = Utc::now();
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut out: HashMap<String, Ban> = {
+            let mut bans = self.bans.lock().unwrap();
+            bans.retain(|_, b| b.expires_at > now);
+            bans.clone()
+        };
+        #[cfg(feature = "production")]
+        for ban in self.redis_list().await {
+            out.entry(ban.ip.clone()).or_insert(ban);
+        }
+        let mut out: Vec<Ban> = out.into_values().collect();
+        out.sort_by_key(|b| std::cmp::Reverse(b.banned_at));
+        out
+    }
+
+    pub async fn unban(&self, ip: &str) -> bool {
+        let removed = self.bans.lock().unwrap().remove(ip).is_some();
+        #[cfg(feature = "production")]
+        if let Some(mut conn) = self.redis().await {
+            let n: i64 = timed(conn.del(format!("ban:{}", ip))).await.unwrap_or(0);
+            return removed || n > 0;
+        }
+        removed
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis(&self) -> Option<redis::aio::ConnectionManager> {
+        self.redis.as_ref()?.conn().await
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis_get(&self, ip: &str) -> Option<Ban> {
+        let mut conn = self.redis().await?;
+        let raw: Option<String> = timed(conn.get(format!("ban:{}", ip))).await?;
+        serde_json::from_str(&raw?).ok()
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis_set(&self, ban: &Ban) {
+        let ttl = (ban.expires_at - ban.banned_at).num_seconds().max(1) as u64;
+        if let (Some(mut conn), Ok(raw)) = (self.redis().await, serde_json::to_string(ban)) {
+            timed(conn.set_ex::<_, _, ()>(format!("ban:{}", ban.ip), raw, ttl)).await;
+        }
+    }
+
+    /// Walks the keys with `SCAN`, which unlike `KEYS` doesn't hold up Redis
+    /// for everyone else while it runs, then reads them in one `MGET`.
+    #[cfg(feature = "production")]
+    async fn redis_list(&self) -> Vec<Ban> {
+        let Some(mut conn) = self.redis().await else {
+            return Vec::new();
+        };
+        let keys: Vec<String> = timed(async {
+            let iter = conn.scan_match::<_, String>("ban:*").await?;
+            Ok(futures_util::StreamExt::collect(iter).await)
+        })
+        .await
+        .unwrap_or_default();
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        let raws: Vec<Option<String>> = timed(redis::cmd("MGET").arg(&keys).query_async(&mut conn))
+            .await
+            .unwrap_or_default();
+        raws.into_iter()
+            .flatten()
+            .filter_map(|r| serde_json::from_str(&r).ok())
+            .collect()
+    }
+}
+
+/// Refuses banned IPs and watches responses: 401s count as authentication
+/// failures and 400s as malformed payloads.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(bans) = req.app_data::<web::Data<BanList>>().cloned() else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+    if !bans.is_enabled() || crate::ip_filter::EXEMPT_PATHS.contains(&req.path()) {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    }
+    // Without an address every such caller would share one ban.
+    let Some(client_ip) = client_addr(req.request()).map(|ip| ip.to_string()) else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+    if let Some(ban) = bans.active_ban(&client_ip).await {
+        metrics::BANNED_REQUESTS_REJECTED.inc();
+        let err = ApiError::Banned {
+            reason: ban.reason,
+            expires_at: ban.expires_at,
+        }
+        .with("client_ip", client_ip);
+        return Ok(req
+            .into_response(err.error_response())
+            .map_into_right_body());
+    }
+
+    let res = next.call(req).await?;
+    match res.status() {
+        StatusCode::UNAUTHORIZED => bans.record(&client_ip, Offense::AuthFailure).await,
+        StatusCode::BAD_REQUEST => bans.record(&client_ip, Offense::MalformedPayload).await,
+        _ => {}
+    }
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,113 @@
+//! Limit on log entries per `/api/logs` batch (`--max-batch-logs`). A batch
+//! over the limit is refused or stored as several smaller batches, depending
+//! on `--oversized-batch`.
+
+use crate::error::ApiError;
+use crate::metrics;
+use crate::types::LogEntry;
+use actix_web::{web, HttpRequest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedBatch {
+    /// Refuse the whole batch with 413.
+    #[default]
+    Reject,
+    /// Store it as consecutive batches of at most the limit, same envelope.
+    Split,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchLimit {
+    /// 0 means unlimited.
+    pub max_logs: usize,
+    pub oversized: OversizedBatch,
+}
+
+impl BatchLimit {
+    /// The limit registered as app data; unlimited when there is none.
+    pub fn for_request(req: &HttpRequest) -> Self {
+        req.app_data::<web::Data<BatchLimit>>()
+            .map(|limit| *limit.get_ref())
+            .unwrap_or_default()
+    }
+
+    pub fn exceeded_by(&self, entry: &LogEntry) -> bool {
+        self.max_logs > 0 && entry.logs.len() > self.max_logs
+    }
+
+    /// Where parsing a batch can stop: past the limit in `reject` mode,
+    /// since the batch will be refused anyway.
+    pub fn parse_limit(&self) -> Option<usize> {
+        (self.max_logs > 0 && self.oversized == OversizedBatch::Reject).then_some(self.max_logs)
+    }
+
+    /// Records the batch size and refuses it if it's over the limit in
+    /// `reject` mode. Runs before quotas, so a refused batch costs nothing.
+    pub fn admit(&self, entry: &LogEntry, client_ip: &str) -> Result<(), ApiError> {
+        let logs_count = entry.logs.len();
+        metrics::BATCH_LOGS.observe(logs_count as u64);
+        if !self.exceeded_by(entry) || self.oversized != OversizedBatch::Reject {
+            return Ok(());
+        }
+        metrics::OVERSIZED_BATCHES_REJECTED.inc();
+        log::warn!(
+            "📦 Oversized batch from IP {} refused: {} logs, limit {} (client_id={:?})",
+            client_ip,
+            logs_count,
+            self.max_logs,
+            entry.client_id
+        );
+        Err(ApiError::PayloadTooLarge(format!(
+            "Batch has {} logs; the limit is {} per request",
+            logs_count, self.max_logs
+        ))
+        .with("logs_count", logs_count)
+        .with("max_batch_logs", self.max_logs))
+    }
+
+    /// The batches to store: `entry` itself, or in `split` mode consecutive
+    /// slices of at most the limit with the same envelope.
+    pub fn split(&self, mut entry: LogEntry, client_ip: &str) -> Vec<LogEntry> {
+        if !self.exceeded_by(&entry) {
+            return vec![entry];
+        }
+        metrics::OVERSIZED_BATCHES_SPLIT.inc();
+        let logs = std::mem::take(&mut entry.logs);
+        let parts: Vec<LogEntry> = logs
+            .chunks(self.max_logs)
+            .map(|chunk| LogEntry {
+                logs: chunk.to_vec(),
+                ..entry.clone()
+            })
+            .collect();
+        log::warn!(
+            "📦 Oversized batch from IP {} split: {} logs into {} batches (client_id={:?})",
+            client_ip,
+            logs.len(),
+            parts.len(),
+            entry.client_id
+        );
+        parts
+    }
+
+    /// What a dry run reports about the batch size.
+    pub fn warning(&self, entry: &LogEntry) -> Option<String> {
+        if !self.exceeded_by(entry) {
+            return None;
+        }
+        let outcome = match self.oversized {
+            OversizedBatch::Reject => "it would be refused with 413".to_string(),
+            OversizedBatch::Split => format!(
+                "it would be stored as {} batches",
+                entry.logs.len().div_ceil(self.max_logs)
+            ),
+        };
+        Some(format!(
+            "batch has {} logs, over --max-batch-logs {}; {}",
+            entry.logs.len(),
+            self.max_logs,
+            outcome
+        ))
+    }
+}
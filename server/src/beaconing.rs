@@ -0,0 +1,171 @@
+use crate::handlers::common::domain_from_url;
+use crate::types::LogEntry;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Observations kept per (client, domain) pair.
+const MAX_SAMPLES: usize = 32;
+/// Fewer contacts than this cannot establish a period.
+const MIN_SAMPLES: usize = 8;
+/// The pattern must span at least this long before it is reported.
+const MIN_DURATION_SECS: i64 = 600;
+/// Coefficient of variation (stddev / mean) of the intervals above which the
+/// traffic is considered human-driven.
+const MAX_JITTER: f64 = 0.15;
+/// Requests in a batch share its timestamp, so contact times are only as
+/// fine as the extension's 5 second batches: a domain busy in every batch
+/// looks perfectly periodic at that interval. Periods have to be well above
+/// it to tell a beacon from a busy site.
+const MIN_PERIOD_SECS: f64 = 60.0;
+/// Re-alerting on the same pair is suppressed for this long.
+const ALERT_COOLDOWN_SECS: i64 = 3600;
+/// Pairs not contacted for this long are forgotten.
+const TRACK_TTL_SECS: i64 = 24 * 3600;
+/// Forgotten pairs are swept every this many batches.
+const SWEEP_EVERY: u64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct BeaconFinding {
+    pub client_id: String,
+    pub domain: String,
+    pub period_secs: f64,
+    pub jitter: f64,
+    pub confidence: f64,
+    pub samples: usize,
+    pub duration_secs: i64,
+}
+
+impl BeaconFinding {
+    pub fn to_event_data(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": format!("https://{}/", self.domain),
+            "host": self.domain,
+            "period_secs": (self.period_secs * 10.0).round() / 10.0,
+            "jitter": (self.jitter * 1000.0).round() / 1000.0,
+            "confidence": (self.confidence * 100.0).round() / 100.0,
+            "samples": self.samples,
+            "duration_secs": self.duration_secs,
+            "detection": {
+                "riskScore": (self.confidence * 100.0).round() as u64
+            }
+        })
+    }
+}
+
+#[derive(Default)]
+struct Track {
+    seen: VecDeque<DateTime<Utc>>,
+    last_alert: Option<DateTime<Utc>>,
+    /// Server time of the last contact, for expiry; `seen` is the client's.
+    touched: Option<DateTime<Utc>>,
+}
+
+/// client_id -> domain -> contact times
+type Tracks = HashMap<String, HashMap<String, Track>>;
+
+#[derive(Default)]
+pub struct BeaconDetector {
+    // batches observed, and the tracks
+    tracks: Mutex<(u64, Tracks)>,
+}
+
+impl BeaconDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one contact per domain for this batch (requests in a batch share
+    /// the batch timestamp) and returns any newly detected beacons.
+    pub fn observe(&self, entry: &LogEntry) -> Vec<BeaconFinding> {
+        let Some(client_id) = entry.client_id.as_deref().filter(|c| !c.is_empty()) else {
+            return Vec::new();
+        };
+        let at = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now());
+
+        let batch_domains: HashSet<String> = entry
+            .logs
+            .iter()
+            .filter_map(|l| domain_from_url(&l.url))
+            .map(|d| d.to_ascii_lowercase())
+            .collect();
+
+        let now = Utc::now();
+        let mut guard = self.tracks.lock().unwrap();
+        let (observations, tracks) = &mut *guard;
+        *observations += 1;
+        if *observations % SWEEP_EVERY == 0 {
+            let cutoff = now - chrono::Duration::seconds(TRACK_TTL_SECS);
+            for per_client in tracks.values_mut() {
+                per_client.retain(|_, t| t.touched.is_some_and(|at| at >= cutoff));
+            }
+            tracks.retain(|_, per_client| !per_client.is_empty());
+        }
+        let per_client = tracks.entry(client_id.to_string()).or_default();
+        let mut findings = Vec::new();
+
+        for domain in batch_domains {
+            let track = per_client.entry(domain.clone()).or_default();
+            track.touched = Some(now);
+            if track.seen.back().is_some_and(|last| *last >= at) {
+                continue;
+            }
+            track.seen.push_back(at);
+            if track.seen.len() > MAX_SAMPLES {
+                track.seen.pop_front();
+            }
+            if track
+                .last_alert
+                .is_some_and(|t| (at - t).num_seconds() < ALERT_COOLDOWN_SECS)
+            {
+                continue;
+            }
+            if let Some(finding) = analyze(client_id, &domain, &track.seen) {
+                track.last_alert = Some(at);
+                findings.push(finding);
+            }
+        }
+        findings
+    }
+}
+
+fn analyze(client_id: &str, domain: &str, seen: &VecDeque<DateTime<Utc>>) -> Option<BeaconFinding> {
+    if seen.len() < MIN_SAMPLES {
+        return None;
+    }
+    let duration_secs = (*seen.back()? - *seen.front()?).num_seconds();
+    if duration_secs < MIN_DURATION_SECS {
+        return None;
+    }
+    let intervals: Vec<f64> = seen
+        .iter()
+        .zip(seen.iter().skip(1))
+        .map(|(a, b)| (*b - *a).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    if mean < MIN_PERIOD_SECS {
+        return None;
+    }
+    let variance =
+        intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+    let jitter = variance.sqrt() / mean;
+    if jitter > MAX_JITTER {
+        return None;
+    }
+    // Tighter timing and more samples both raise confidence.
+    let regularity = 1.0 - jitter / MAX_JITTER;
+    let coverage = (seen.len() as f64 / MAX_SAMPLES as f64).min(1.0);
+    let confidence = (0.5 + 0.5 * regularity) * (0.6 + 0.4 * coverage);
+
+    Some(BeaconFinding {
+        client_id: client_id.to_string(),
+        domain: domain.to_string(),
+        period_secs: mean,
+        jitter,
+        confidence,
+        samples: seen.len(),
+        duration_secs,
+    })
+}
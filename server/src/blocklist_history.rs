@@ -0,0 +1,255 @@
+//! Numbered versions of the shared blocklist, for
+//! `GET /api/blocklist/delta?from_version=N`: an extension that has version
+//! N downloads only the patterns and channels added and removed since, not
+//! the whole list again.
+//!
+//! Every time the blocklist is served or updated it is compared with the
+//! last version seen; a difference becomes a new version holding what was
+//! added and removed. Changes made any other way (category toggles, bundle
+//! imports, restores, another replica writing the database) therefore get
+//! their version the next time the list is read. The last
+//! [`MAX_VERSIONS`] are kept, in `--blocklist-history` when given so the
+//! numbers survive a restart.
+
+use crate::types::Blocklist;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Versions a delta can start from; older ones get the full list.
+pub const MAX_VERSIONS: usize = 1000;
+
+/// The lists a version covers: the shared `urlPatterns` and
+/// `youtubeChannels`, without profile entries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lists {
+    #[serde(rename = "urlPatterns")]
+    pub url_patterns: Vec<String>,
+    #[serde(rename = "youtubeChannels")]
+    pub youtube_channels: Vec<String>,
+}
+
+impl Lists {
+    fn of(blocklist: &Blocklist) -> Lists {
+        Lists {
+            url_patterns: blocklist.url_patterns.clone(),
+            youtube_channels: blocklist.youtube_channels.clone(),
+        }
+    }
+
+    /// What `self` has that `other` lacks, in `self`'s order.
+    fn minus(&self, other: &Lists) -> Lists {
+        let missing = |mine: &[String], theirs: &[String]| {
+            let theirs: HashSet<&String> = theirs.iter().collect();
+            mine.iter()
+                .filter(|p| !theirs.contains(p))
+                .cloned()
+                .collect()
+        };
+        Lists {
+            url_patterns: missing(&self.url_patterns, &other.url_patterns),
+            youtube_channels: missing(&self.youtube_channels, &other.youtube_channels),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Version {
+    version: u64,
+    at: DateTime<Utc>,
+    added: Lists,
+    removed: Lists,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    /// The lists as of the newest version.
+    current: Option<Lists>,
+    version: u64,
+    versions: VecDeque<Version>,
+}
+
+/// What `/api/blocklist/delta` answers: the changes since `from_version`,
+/// or with `full` (`from_version` is too old, or from before a restart
+/// without `--blocklist-history`) the whole lists to start over from.
+#[derive(Debug, Serialize)]
+pub struct Delta {
+    pub from_version: u64,
+    pub version: u64,
+    pub full: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<Lists>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<Lists>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub lists: Option<Lists>,
+}
+
+impl Delta {
+    /// The whole of `blocklist`'s lists, labelled `version`, for a client
+    /// whose copy can't be patched (a blocklist rollout serves it something
+    /// other than the shared lists).
+    pub fn full(blocklist: &Blocklist, from_version: u64, version: u64) -> Delta {
+        Delta {
+            from_version,
+            version,
+            full: true,
+            added: None,
+            removed: None,
+            lists: Some(Lists::of(blocklist)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BlocklistHistory {
+    path: Option<PathBuf>,
+    saved: Mutex<Saved>,
+}
+
+impl BlocklistHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the versions in `path`, starting from the ones already there.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let saved = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Saved::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(BlocklistHistory {
+            path: Some(path.to_path_buf()),
+            saved: Mutex::new(saved),
+        })
+    }
+
+    fn persist(&self, saved: &Saved) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let text = serde_json::to_vec(saved).map_err(std::io::Error::other)?;
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// The newest version observed.
+    pub fn version(&self) -> u64 {
+        self.saved.lock().unwrap().version
+    }
+
+    /// The version of `blocklist`, recording a new one if it changed since
+    /// the last call.
+    pub fn observe(&self, blocklist: &Blocklist) -> u64 {
+        let lists = Lists::of(blocklist);
+        let mut saved = self.saved.lock().unwrap();
+        let previous = saved.current.clone().unwrap_or_default();
+        if saved.current.is_some() && previous == lists {
+            return saved.version;
+        }
+        let (added, removed) = (lists.minus(&previous), previous.minus(&lists));
+        saved.version += 1;
+        log::info!(
+            "📋 Blocklist version {}: +{} / -{} patterns, +{} / -{} channels",
+            saved.version,
+            added.url_patterns.len(),
+            removed.url_patterns.len(),
+            added.youtube_channels.len(),
+            removed.youtube_channels.len()
+        );
+        let version = Version {
+            version: saved.version,
+            at: Utc::now(),
+            added,
+            removed,
+        };
+        saved.versions.push_back(version);
+        if saved.versions.len() > MAX_VERSIONS {
+            saved.versions.pop_front();
+        }
+        saved.current = Some(lists);
+        if let Err(e) = self.persist(&saved) {
+            log::error!("❌ Writing the blocklist history failed: {}", e);
+        }
+        saved.version
+    }
+
+    /// The changes from `from_version` to `blocklist` (observed first).
+    pub fn delta(&self, blocklist: &Blocklist, from_version: u64) -> Delta {
+        let version = self.observe(blocklist);
+        let saved = self.saved.lock().unwrap();
+        let current = saved.current.clone().unwrap_or_default();
+        // Version v holds the changes from v - 1, so every version after
+        // `from_version` must still be kept.
+        let oldest_kept = saved.versions.front().map_or(version, |v| v.version);
+        if from_version > version || from_version + 1 < oldest_kept {
+            return Delta {
+                from_version,
+                version,
+                full: true,
+                added: None,
+                removed: None,
+                lists: Some(current),
+            };
+        }
+        // Undo the newer versions to get the lists as of `from_version`.
+        let mut patterns: HashSet<String> = current.url_patterns.iter().cloned().collect();
+        let mut channels: HashSet<String> = current.youtube_channels.iter().cloned().collect();
+        for v in saved
+            .versions
+            .iter()
+            .rev()
+            .take_while(|v| v.version > from_version)
+        {
+            for p in &v.added.url_patterns {
+                patterns.remove(p);
+            }
+            for c in &v.added.youtube_channels {
+                channels.remove(c);
+            }
+            patterns.extend(v.removed.url_patterns.iter().cloned());
+            channels.extend(v.removed.youtube_channels.iter().cloned());
+        }
+        let old = Lists {
+            url_patterns: patterns.into_iter().collect(),
+            youtube_channels: channels.into_iter().collect(),
+        };
+        let mut removed = old.minus(&current);
+        removed.url_patterns.sort();
+        removed.youtube_channels.sort();
+        Delta {
+            from_version,
+            version,
+            full: false,
+            added: Some(current.minus(&old)),
+            removed: Some(removed),
+            lists: None,
+        }
+    }
+
+    /// The URL patterns of `blocklist` (observed first) added after `at`.
+    /// Version 1, the list as first seen, added nothing.
+    pub fn patterns_added_since(&self, blocklist: &Blocklist, at: DateTime<Utc>) -> Vec<String> {
+        self.observe(blocklist);
+        let saved = self.saved.lock().unwrap();
+        let current = saved.current.clone().unwrap_or_default();
+        let mut added: Vec<String> = Vec::new();
+        for v in saved.versions.iter().filter(|v| v.version > 1 && v.at > at) {
+            for p in &v.added.url_patterns {
+                if current.url_patterns.contains(p) && !added.contains(p) {
+                    added.push(p.clone());
+                }
+            }
+        }
+        added
+    }
+}
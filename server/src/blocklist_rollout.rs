@@ -0,0 +1,281 @@
+//! Staged blocklist rollouts: a new blocklist goes to a percentage of
+//! clients first, so a bad pattern breaks a few browsers rather than the
+//! whole fleet.
+//!
+//! `POST /api/admin/blocklist-rollout` starts one with the candidate list
+//! and a percentage. A client is in the canary when a hash of its
+//! client_id (salted with the rollout id) falls below the percentage, so it
+//! stays in as the percentage grows; requests without a client_id get the
+//! current list. Canary clients are served the candidate from
+//! `GET /api/blocklist` and a full list from `/api/blocklist/delta`, marked
+//! `X-Blocklist-Rollout: canary`.
+//!
+//! Each arm counts the clients served, the requests they logged, how many
+//! of those were blocked, and the extension events whose type contains
+//! `error`. Comparing the two arms at `GET /api/admin/blocklist-rollout`
+//! shows whether the candidate over-blocks or breaks the extension; then
+//! `promote` makes it the blocklist for everyone, or `rollback` drops it
+//! and gets the canary clients a full list on their next delta.
+//!
+//! The rollout lives in memory: a restart ends it (everyone gets the
+//! current list again), and each replica runs its own.
+
+use crate::types::Blocklist;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// Header marking responses built from the candidate.
+pub const ROLLOUT_HEADER: &str = "X-Blocklist-Rollout";
+
+/// Which list a client gets during a rollout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Arm {
+    Stable,
+    Canary,
+}
+
+/// `client_id`'s bucket in 0..100 for `salt`; stable across calls, so a
+/// client keeps its arm as the percentage changes.
+pub fn bucket(salt: &str, client_id: &str) -> u8 {
+    let digest = Sha256::digest(format!("{}\n{}", salt, client_id).as_bytes());
+    let n = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (n % 100) as u8
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ArmStats {
+    /// Distinct clients served this arm's list.
+    pub clients: usize,
+    pub logs: u64,
+    pub blocked: u64,
+    pub errors: u64,
+    /// `blocked / logs`.
+    pub block_rate: f64,
+    /// `errors / logs`.
+    pub error_rate: f64,
+}
+
+#[derive(Default)]
+struct Tally {
+    clients: HashSet<String>,
+    logs: u64,
+    blocked: u64,
+    errors: u64,
+}
+
+impl Tally {
+    fn stats(&self) -> ArmStats {
+        let rate = |n: u64| {
+            if self.logs == 0 {
+                0.0
+            } else {
+                n as f64 / self.logs as f64
+            }
+        };
+        ArmStats {
+            clients: self.clients.len(),
+            logs: self.logs,
+            blocked: self.blocked,
+            errors: self.errors,
+            block_rate: rate(self.blocked),
+            error_rate: rate(self.errors),
+        }
+    }
+}
+
+struct Active {
+    id: String,
+    candidate: Blocklist,
+    percent: u8,
+    /// The blocklist version the candidate was made against.
+    base_version: u64,
+    started_at: DateTime<Utc>,
+    started_by: String,
+    stable: Tally,
+    canary: Tally,
+}
+
+/// A rollout as the admin endpoint shows it.
+#[derive(Debug, Serialize)]
+pub struct RolloutStatus {
+    pub id: String,
+    pub percent: u8,
+    pub base_version: u64,
+    pub started_at: DateTime<Utc>,
+    pub started_by: String,
+    pub url_patterns: usize,
+    pub youtube_channels: usize,
+    pub stable: ArmStats,
+    pub canary: ArmStats,
+}
+
+impl Active {
+    fn status(&self) -> RolloutStatus {
+        RolloutStatus {
+            id: self.id.clone(),
+            percent: self.percent,
+            base_version: self.base_version,
+            started_at: self.started_at,
+            started_by: self.started_by.clone(),
+            url_patterns: self.candidate.url_patterns.len(),
+            youtube_channels: self.candidate.youtube_channels.len(),
+            stable: self.stable.stats(),
+            canary: self.canary.stats(),
+        }
+    }
+
+    fn arm(&self, client_id: Option<&str>) -> Arm {
+        match client_id {
+            Some(id) if bucket(&self.id, id) < self.percent => Arm::Canary,
+            _ => Arm::Stable,
+        }
+    }
+
+    fn tally(&mut self, arm: Arm) -> &mut Tally {
+        match arm {
+            Arm::Stable => &mut self.stable,
+            Arm::Canary => &mut self.canary,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BlocklistRollout {
+    active: RwLock<Option<Active>>,
+    /// Canary clients of a rolled-back rollout that still hold the
+    /// candidate, owed a full list on their next delta.
+    resync: RwLock<HashSet<String>>,
+}
+
+impl BlocklistRollout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> Option<RolloutStatus> {
+        self.active.read().unwrap().as_ref().map(Active::status)
+    }
+
+    /// Starts a rollout of `candidate` to `percent`% of clients; `Err` when
+    /// one is already running.
+    pub fn start(
+        &self,
+        candidate: Blocklist,
+        percent: u8,
+        base_version: u64,
+        started_by: &str,
+    ) -> Result<RolloutStatus, String> {
+        let mut active = self.active.write().unwrap();
+        if let Some(running) = active.as_ref() {
+            return Err(format!("rollout {} is still running", running.id));
+        }
+        let rollout = Active {
+            id: crate::auth::random_token()[..12].to_string(),
+            candidate,
+            percent,
+            base_version,
+            started_at: Utc::now(),
+            started_by: started_by.to_string(),
+            stable: Tally::default(),
+            canary: Tally::default(),
+        };
+        let status = rollout.status();
+        *active = Some(rollout);
+        Ok(status)
+    }
+
+    /// Moves the running rollout to `percent`%. Clients that leave the
+    /// canary get a full list on their next delta.
+    pub fn set_percent(&self, percent: u8) -> Option<RolloutStatus> {
+        let mut active = self.active.write().unwrap();
+        let rollout = active.as_mut()?;
+        rollout.percent = percent;
+        let (left, stayed): (HashSet<String>, HashSet<String>) =
+            std::mem::take(&mut rollout.canary.clients)
+                .into_iter()
+                .partition(|id| rollout.arm(Some(id)) == Arm::Stable);
+        rollout.canary.clients = stayed;
+        rollout.stable.clients.extend(left.iter().cloned());
+        self.resync.write().unwrap().extend(left);
+        Some(rollout.status())
+    }
+
+    /// The list being rolled out.
+    pub fn candidate(&self) -> Option<Blocklist> {
+        self.active
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|r| r.candidate.clone())
+    }
+
+    /// Ends the rollout once the caller has stored the candidate as the
+    /// blocklist.
+    pub fn promote(&self) -> Option<RolloutStatus> {
+        let rollout = self.active.write().unwrap().take()?;
+        Some(rollout.status())
+    }
+
+    /// Ends the rollout without using the candidate.
+    pub fn rollback(&self) -> Option<RolloutStatus> {
+        let rollout = self.active.write().unwrap().take()?;
+        self.resync
+            .write()
+            .unwrap()
+            .extend(rollout.canary.clients.iter().cloned());
+        Some(rollout.status())
+    }
+
+    /// The list `client_id` gets instead of `blocklist`, and its arm;
+    /// `blocklist` itself outside a rollout.
+    pub fn select(
+        &self,
+        blocklist: Blocklist,
+        client_id: Option<&str>,
+    ) -> (Blocklist, Option<Arm>) {
+        let mut active = self.active.write().unwrap();
+        let Some(rollout) = active.as_mut() else {
+            return (blocklist, None);
+        };
+        let arm = rollout.arm(client_id);
+        if let Some(id) = client_id {
+            rollout.tally(arm).clients.insert(id.to_string());
+        }
+        match arm {
+            Arm::Canary => (rollout.candidate.clone(), Some(arm)),
+            Arm::Stable => (blocklist, Some(arm)),
+        }
+    }
+
+    /// Whether `client_id` still holds a rolled-back candidate; true once.
+    pub fn take_resync(&self, client_id: Option<&str>) -> bool {
+        match client_id {
+            Some(id) => self.resync.write().unwrap().remove(id),
+            None => false,
+        }
+    }
+
+    /// Counts a batch of logs towards the client's arm.
+    pub fn record_logs(&self, client_id: Option<&str>, logs: u64, blocked: u64) {
+        if let Some(rollout) = self.active.write().unwrap().as_mut() {
+            let tally = rollout.tally(rollout.arm(client_id));
+            tally.logs += logs;
+            tally.blocked += blocked;
+        }
+    }
+
+    /// Counts an extension event towards the client's arm when its type
+    /// reports an error.
+    pub fn record_event(&self, client_id: Option<&str>, event_type: &str) {
+        if !event_type.to_ascii_lowercase().contains("error") {
+            return;
+        }
+        if let Some(rollout) = self.active.write().unwrap().as_mut() {
+            rollout.tally(rollout.arm(client_id)).errors += 1;
+        }
+    }
+}
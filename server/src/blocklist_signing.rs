@@ -0,0 +1,246 @@
+//! Ed25519 signatures on the served blocklist, so an extension can tell
+//! the list came from this server and not from a captive portal or a
+//! TLS-intercepting proxy in between.
+//!
+//! With `--blocklist-signing-key <FILE>` every `GET /api/blocklist` and
+//! `/api/blocklist/delta` response carries `X-Blocklist-Signature` (base64)
+//! and `X-Blocklist-Key-Id`. The signature covers
+//! `canigoin-blocklist-v1\n<X-Blocklist-Version>\n<body>`, so an old list
+//! can't be passed off under a newer version. Public keys are listed at
+//! `GET /api/blocklist/keys`; extensions should pin them rather than fetch
+//! them over the same connection they are guarding.
+//!
+//! The file holds the private keys and is created with a first key when
+//! missing. Rotating adds a key and signs with it from then on; earlier
+//! keys stay listed as retired until removed, so extensions can pick up the
+//! new key before the old one goes away. Replicas must share the file.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Header the signature is sent in.
+pub const SIGNATURE_HEADER: &str = "X-Blocklist-Signature";
+/// Header naming the key that made the signature.
+pub const KEY_ID_HEADER: &str = "X-Blocklist-Key-Id";
+const CONTEXT: &str = "canigoin-blocklist-v1";
+
+/// A key as kept in the file.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredKey {
+    key_id: String,
+    created_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+    /// PKCS#8 document, base64.
+    private_key: String,
+}
+
+struct SigningKey {
+    stored: StoredKey,
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    fn generate() -> Result<SigningKey, String> {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(|e| e.to_string())?;
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|e| e.to_string())?;
+        Ok(SigningKey {
+            stored: StoredKey {
+                key_id: key_id(pair.public_key().as_ref()),
+                created_at: Utc::now(),
+                retired_at: None,
+                private_key: STANDARD.encode(pkcs8.as_ref()),
+            },
+            pair,
+        })
+    }
+
+    fn open(stored: StoredKey) -> Result<SigningKey, String> {
+        let pkcs8 = STANDARD
+            .decode(&stored.private_key)
+            .map_err(|e| format!("key {}: {}", stored.key_id, e))?;
+        let pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+            .map_err(|e| format!("key {}: {}", stored.key_id, e))?;
+        Ok(SigningKey { stored, pair })
+    }
+
+    fn public(&self, active: bool) -> PublicKey {
+        PublicKey {
+            key_id: self.stored.key_id.clone(),
+            algorithm: "ed25519",
+            public_key: STANDARD.encode(self.pair.public_key().as_ref()),
+            active,
+            created_at: self.stored.created_at,
+            retired_at: self.stored.retired_at,
+        }
+    }
+}
+
+/// A verification key as `/api/blocklist/keys` lists it.
+#[derive(Debug, Serialize)]
+pub struct PublicKey {
+    pub key_id: String,
+    pub algorithm: &'static str,
+    /// The raw 32-byte Ed25519 public key, base64.
+    pub public_key: String,
+    /// The key new responses are signed with.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// The first 16 hex digits of the public key's SHA-256.
+fn key_id(public_key: &[u8]) -> String {
+    hex::encode(Sha256::digest(public_key))[..16].to_string()
+}
+
+/// What the signature covers.
+pub fn signed_message(version: u64, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n{}\n", CONTEXT, version).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+pub struct BlocklistSigner {
+    path: Option<PathBuf>,
+    /// Oldest first; the last one signs.
+    keys: RwLock<Vec<SigningKey>>,
+}
+
+impl BlocklistSigner {
+    /// Responses go out unsigned.
+    pub fn disabled() -> Self {
+        BlocklistSigner {
+            path: None,
+            keys: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// The keys in `path`, which is created with a new key if it doesn't
+    /// exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let signer = BlocklistSigner {
+            path: Some(path.to_path_buf()),
+            keys: RwLock::new(Vec::new()),
+        };
+        if path.exists() {
+            let raw =
+                std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let stored: Vec<StoredKey> =
+                serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let keys = stored
+                .into_iter()
+                .map(SigningKey::open)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if keys.is_empty() {
+                return Err(format!("{}: no keys", path.display()));
+            }
+            *signer.keys.write().unwrap() = keys;
+        } else {
+            signer.rotate()?;
+        }
+        Ok(signer)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.read().unwrap().is_empty()
+    }
+
+    /// The key id and base64 signature for a response of `version` with
+    /// `body`; `None` when signing is off.
+    pub fn sign(&self, version: u64, body: &[u8]) -> Option<(String, String)> {
+        let keys = self.keys.read().unwrap();
+        let key = keys.last()?;
+        let signature = key.pair.sign(&signed_message(version, body));
+        Some((
+            key.stored.key_id.clone(),
+            STANDARD.encode(signature.as_ref()),
+        ))
+    }
+
+    /// Every key, the active one last.
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        let keys = self.keys.read().unwrap();
+        let last = keys.len().saturating_sub(1);
+        keys.iter()
+            .enumerate()
+            .map(|(i, k)| k.public(i == last))
+            .collect()
+    }
+
+    fn persist(&self, keys: &[StoredKey]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut text = serde_json::to_vec_pretty(keys).map_err(|e| e.to_string())?;
+        text.push(b'\n');
+        let tmp = path.with_extension("tmp");
+        write_private(&tmp, &text).map_err(|e| format!("{}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Signs with a new key from now on and retires the current one.
+    pub fn rotate(&self) -> Result<PublicKey, String> {
+        if self.path.is_none() {
+            return Err("blocklist signing is off (--blocklist-signing-key)".to_string());
+        }
+        let mut keys = self.keys.write().unwrap();
+        let new = SigningKey::generate()?;
+        let now = new.stored.created_at;
+        let mut stored: Vec<StoredKey> = keys
+            .iter()
+            .map(|k| StoredKey {
+                retired_at: k.stored.retired_at.or(Some(now)),
+                ..k.stored.clone()
+            })
+            .collect();
+        stored.push(new.stored.clone());
+        self.persist(&stored)?;
+        for key in keys.iter_mut() {
+            key.stored.retired_at.get_or_insert(now);
+        }
+        let public = new.public(true);
+        keys.push(new);
+        Ok(public)
+    }
+
+    /// Drops a retired key. Returns whether it existed; the active key
+    /// can't be removed.
+    pub fn remove(&self, key_id: &str) -> Result<bool, String> {
+        let mut keys = self.keys.write().unwrap();
+        let Some(index) = keys.iter().position(|k| k.stored.key_id == key_id) else {
+            return Ok(false);
+        };
+        if index + 1 == keys.len() {
+            return Err("the active key can't be removed; rotate first".to_string());
+        }
+        let stored: Vec<StoredKey> = keys
+            .iter()
+            .filter(|k| k.stored.key_id != key_id)
+            .map(|k| k.stored.clone())
+            .collect();
+        self.persist(&stored)?;
+        keys.remove(index);
+        Ok(true)
+    }
+}
+
+/// Writes `contents` readable by the owner only, where the platform allows.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
@@ -0,0 +1,153 @@
+//! Blocklist pushes from an external policy system
+//! (`POST /api/integrations/blocklist-sync`), so policy can be managed
+//! elsewhere with this server as the enforcement point.
+//!
+//! A push adds and removes URL patterns and YouTube channels in the shared
+//! blocklist. It is authenticated by an HMAC-SHA256 under
+//! `--blocklist-sync-secret` rather than the admin token, the way webhook
+//! senders usually sign: `X-CanIGoIn-Signature: sha256=<hex>` over
+//! `<X-CanIGoIn-Timestamp>.<X-CanIGoIn-Delivery>.<body>`, with the
+//! timestamp in Unix seconds and no more than [`TOLERANCE_SECS`] away from
+//! the server's clock. The delivery id is required and signed, so a push is
+//! applied once however often it is retried or replayed within that window.
+
+use crate::types::{Blocklist, BlocklistEntries};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub const SIGNATURE_HEADER: &str = "X-CanIGoIn-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-CanIGoIn-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-CanIGoIn-Delivery";
+/// How far a push's timestamp may be from now, either way.
+pub const TOLERANCE_SECS: i64 = 300;
+
+/// What a push asks for.
+#[derive(Debug, Deserialize)]
+pub struct SyncPush {
+    /// The sending system, for the logs and server events.
+    pub source: String,
+    #[serde(default)]
+    pub add: BlocklistEntries,
+    #[serde(default)]
+    pub remove: BlocklistEntries,
+}
+
+/// What a push changed; entries already present (or already gone) aren't
+/// counted.
+#[derive(Debug, Default, Serialize)]
+pub struct SyncChanges {
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Applies `push` to `blocklist`: removals first, so an entry in both lists
+/// ends up blocked.
+pub fn apply(blocklist: &mut Blocklist, push: &SyncPush) -> SyncChanges {
+    let len = |b: &Blocklist| b.url_patterns.len() + b.youtube_channels.len();
+    let before = len(blocklist);
+    blocklist
+        .url_patterns
+        .retain(|p| !push.remove.url_patterns.contains(p));
+    blocklist
+        .youtube_channels
+        .retain(|c| !push.remove.youtube_channels.contains(c));
+    let removed = before - len(blocklist);
+    let before = len(blocklist);
+    blocklist.add(push.add.clone());
+    SyncChanges {
+        added: len(blocklist) - before,
+        removed,
+    }
+}
+
+/// The `X-CanIGoIn-Signature` value for `body` sent at `timestamp` as
+/// `delivery`.
+pub fn signature(secret: &str, timestamp: &str, delivery: &str, body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(
+            mac(secret, timestamp, delivery, body)
+                .finalize()
+                .into_bytes()
+        )
+    )
+}
+
+fn mac(secret: &str, timestamp: &str, delivery: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(delivery.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+pub struct BlocklistSync {
+    secret: Option<String>,
+    /// Delivery ids seen within the tolerance window, with when.
+    deliveries: Mutex<HashMap<String, i64>>,
+}
+
+impl BlocklistSync {
+    pub fn new(secret: Option<String>) -> Self {
+        BlocklistSync {
+            secret: secret.filter(|s| !s.is_empty()),
+            deliveries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Checks the signature, timestamp and delivery id of a push received
+    /// at `now` (Unix seconds).
+    pub fn verify(
+        &self,
+        timestamp: Option<&str>,
+        delivery: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+        now: i64,
+    ) -> Result<(), &'static str> {
+        let secret = self.secret.as_deref().ok_or("blocklist sync is off")?;
+        let timestamp = timestamp.ok_or("missing timestamp")?;
+        let delivery = delivery
+            .filter(|d| !d.is_empty() && !d.contains('.'))
+            .ok_or("missing or malformed delivery id")?;
+        let signature = signature
+            .and_then(|s| s.strip_prefix("sha256="))
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or("missing or malformed signature")?;
+        mac(secret, timestamp, delivery, body)
+            .verify_slice(&signature)
+            .map_err(|_| "signature mismatch")?;
+        // Only a signed timestamp is worth parsing; `abs_diff` can't
+        // overflow however far off it is.
+        let sent: i64 = timestamp
+            .parse()
+            .map_err(|_| "timestamp is not Unix seconds")?;
+        if now.abs_diff(sent) > TOLERANCE_SECS as u64 {
+            return Err("timestamp too far from the server's clock");
+        }
+        Ok(())
+    }
+
+    /// Whether `delivery` is new, remembering it if so. Ids older than the
+    /// tolerance window are forgotten: their timestamps no longer verify.
+    pub fn first_delivery(&self, delivery: &str, now: i64) -> bool {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        deliveries.retain(|_, at| now - *at <= 2 * TOLERANCE_SECS);
+        deliveries.insert(delivery.to_string(), now).is_none()
+    }
+
+    /// Lets a delivery that couldn't be applied be retried.
+    pub fn forget(&self, delivery: &str) {
+        self.deliveries.lock().unwrap().remove(delivery);
+    }
+}
@@ -0,0 +1,143 @@
+//! `GET /api/blocklist?format=bloom`: the blocked domains as a Bloom filter
+//! instead of one regex each, for blocklists too large to download and
+//! evaluate pattern by pattern. Patterns of the `.*example\.com.*` form
+//! ([`Blocklist::domain_pattern`]) go into the filter; every other pattern
+//! is served as a regex next to it.
+//!
+//! The filter is `bits` bits, LSB first within each byte, base64-encoded.
+//! A domain is in it when all `hashes` bits `(h1 + i * h2) mod bits` are
+//! set, for `i` in `0..hashes`, where `h1` and `h2` are the 32-bit FNV-1a
+//! hashes of the lowercased domain with offset bases [`BASIS_1`] and
+//! [`BASIS_2`] (`h2` with its lowest bit set). An extension checks a
+//! request's host and each of its parent domains (`a.b.example.com`,
+//! `b.example.com`, `example.com`, `com`); a hit is a block, wrong at
+//! `fp_rate` at most.
+
+use crate::types::Blocklist;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Serialize;
+
+/// The standard FNV-1a offset basis.
+pub const BASIS_1: u32 = 0x811c_9dc5;
+pub const BASIS_2: u32 = 0x5bd1_e995;
+const PRIME: u32 = 0x0100_0193;
+
+/// False-positive rate unless `?fp_rate=` says otherwise.
+pub const DEFAULT_FP_RATE: f64 = 0.001;
+
+fn fnv1a(value: &str, basis: u32) -> u32 {
+    value
+        .bytes()
+        .fold(basis, |hash, b| (hash ^ b as u32).wrapping_mul(PRIME))
+}
+
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    len: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sized for `count` values at false-positive rate `fp_rate`.
+    pub fn new(count: usize, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let n = count.max(1) as f64;
+        let len = (-n * fp_rate.ln() / (ln2 * ln2)).ceil().max(8.0) as u64;
+        let hashes = ((len as f64 / n) * ln2).round().clamp(1.0, 30.0) as u32;
+        BloomFilter {
+            bits: vec![0; len.div_ceil(8) as usize],
+            len,
+            hashes,
+        }
+    }
+
+    fn positions(&self, value: &str) -> impl Iterator<Item = u64> + '_ {
+        let value = value.to_ascii_lowercase();
+        let h1 = fnv1a(&value, BASIS_1) as u64;
+        let h2 = (fnv1a(&value, BASIS_2) | 1) as u64;
+        (0..self.hashes as u64).map(move |i| (h1 + i * h2) % self.len)
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        for bit in self.positions(value).collect::<Vec<_>>() {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, value: &str) -> bool {
+        self.positions(value)
+            .all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(&self.bits)
+    }
+
+    /// Whether `host` or one of its parent domains is in the filter, as an
+    /// extension checks it.
+    pub fn blocks_host(&self, host: &str) -> bool {
+        let mut rest = host.trim_end_matches('.');
+        loop {
+            if self.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// The filter part of the response.
+#[derive(Debug, Serialize)]
+pub struct Filter {
+    pub hash: &'static str,
+    pub bits: u64,
+    pub hashes: u32,
+    pub domains: usize,
+    pub fp_rate: f64,
+    /// Base64.
+    pub filter: String,
+}
+
+/// `GET /api/blocklist?format=bloom`.
+#[derive(Debug, Serialize)]
+pub struct BloomBlocklist {
+    pub format: &'static str,
+    pub bloom: Filter,
+    /// The patterns that aren't plain domains, still as regexes.
+    #[serde(rename = "urlPatterns")]
+    pub url_patterns: Vec<String>,
+    #[serde(rename = "youtubeChannels")]
+    pub youtube_channels: Vec<String>,
+}
+
+/// Splits `blocklist` (already resolved for the client) into the filter of
+/// its domain patterns and the remaining regexes.
+pub fn compress(blocklist: Blocklist, fp_rate: f64) -> BloomBlocklist {
+    let (domains, url_patterns): (Vec<_>, Vec<_>) = blocklist
+        .url_patterns
+        .into_iter()
+        .partition(|p| Blocklist::pattern_domain(p).is_some());
+    let mut filter = BloomFilter::new(domains.len(), fp_rate);
+    for pattern in &domains {
+        if let Some(domain) = Blocklist::pattern_domain(pattern) {
+            filter.insert(&domain);
+        }
+    }
+    BloomBlocklist {
+        format: "bloom",
+        bloom: Filter {
+            hash: "fnv1a32-double",
+            bits: filter.len,
+            hashes: filter.hashes,
+            domains: domains.len(),
+            fp_rate,
+            filter: filter.to_base64(),
+        },
+        url_patterns,
+        youtube_channels: blocklist.youtube_channels,
+    }
+}
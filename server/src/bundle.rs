@@ -0,0 +1,125 @@
+//! Configuration bundles (`/api/admin/export-bundle`, `/api/admin/import-bundle`)
+//! for copying a server's setup to another one, e.g. staging → production.
+//!
+//! A bundle is one JSON document whose `contents` are signed with
+//! HMAC-SHA256 under `--bundle-key`; servers that should exchange bundles
+//! share the key.
+
+use crate::types::{ArchivedClient, Blocklist};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const FORMAT: &str = "canigoin-bundle";
+pub const VERSION: u32 = 1;
+
+/// Sections this server knows how to apply. Others found in a bundle (from a
+/// newer server) are skipped and reported back.
+const SECTIONS: &[&str] = &["blocklist", "archived_clients", "settings"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Contents {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Instance id, mode and version of the exporting server.
+    pub source: serde_json::Value,
+    pub blocklist: Option<Blocklist>,
+    pub archived_clients: Option<Vec<ArchivedClient>>,
+    /// Startup settings of the exporting server. They can't be changed at
+    /// runtime, so importing only reports where they differ.
+    pub settings: Option<serde_json::Value>,
+}
+
+pub struct BundleSigner {
+    key: Option<String>,
+}
+
+impl BundleSigner {
+    pub fn new(key: Option<String>) -> Self {
+        BundleSigner {
+            key: key.filter(|k| !k.is_empty()),
+        }
+    }
+
+    fn mac(&self, contents: &serde_json::Value) -> Result<Hmac<Sha256>, String> {
+        let key = self
+            .key
+            .as_deref()
+            .ok_or("no --bundle-key configured on this server")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| e.to_string())?;
+        mac.update(&serde_json::to_vec(contents).map_err(|e| e.to_string())?);
+        Ok(mac)
+    }
+
+    pub fn seal(&self, contents: &Contents) -> Result<serde_json::Value, String> {
+        let contents = serde_json::to_value(contents).map_err(|e| e.to_string())?;
+        let signature = hex::encode(self.mac(&contents)?.finalize().into_bytes());
+        Ok(serde_json::json!({
+            "format": FORMAT,
+            "version": VERSION,
+            "signature": format!("hmac-sha256:{}", signature),
+            "contents": contents
+        }))
+    }
+
+    /// Checks format, version and signature. Returns the contents and the
+    /// names of sections this server doesn't know.
+    pub fn open(&self, bundle: &serde_json::Value) -> Result<(Contents, Vec<String>), String> {
+        if bundle.get("format").and_then(|v| v.as_str()) != Some(FORMAT) {
+            return Err(format!("not a {} document", FORMAT));
+        }
+        let version = bundle.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version != VERSION as u64 {
+            return Err(format!(
+                "unsupported bundle version {} (this server reads version {})",
+                version, VERSION
+            ));
+        }
+        let contents = bundle.get("contents").ok_or("bundle has no contents")?;
+        let signature = bundle
+            .get("signature")
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.strip_prefix("hmac-sha256:"))
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or("bundle is not signed")?;
+        self.mac(contents)?.verify_slice(&signature).map_err(|_| {
+            "signature does not match; the bundle was modified or signed with a different key"
+        })?;
+
+        let skipped = contents
+            .as_object()
+            .map(|o| {
+                o.keys()
+                    .filter(|k| {
+                        !SECTIONS.contains(&k.as_str()) && *k != "created_at" && *k != "source"
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let contents = serde_json::from_value(contents.clone())
+            .map_err(|e| format!("invalid bundle contents: {}", e))?;
+        Ok((contents, skipped))
+    }
+}
+
+/// Settings whose value in the bundle differs from this server's, as
+/// `{setting, bundle, local}`.
+pub fn settings_diff(
+    bundle: &serde_json::Value,
+    local: &serde_json::Value,
+) -> Vec<serde_json::Value> {
+    let (Some(bundle), Some(local)) = (bundle.as_object(), local.as_object()) else {
+        return Vec::new();
+    };
+    bundle
+        .iter()
+        .filter(|(k, v)| local.get(*k) != Some(*v))
+        .map(|(k, v)| {
+            serde_json::json!({
+                "setting": k,
+                "bundle": v,
+                "local": local.get(k)
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,355 @@
+//! Raw request capture (`--capture-dir`) and the `replay` subcommand, for
+//! reproducing parsing bugs reported from the field.
+//!
+//! Each captured request is one `capture-<seq>.http` file holding the request
+//! line, headers and the body exactly as received (still gzip-compressed if it
+//! was sent that way). Only the newest `--capture-max-files` are kept.
+//! Credentials stay out of them: the auth headers are masked, logins and user
+//! management aren't captured, and in plain JSON bodies any field named like
+//! a token, password or secret is masked.
+
+use crate::cli::ReplayArgs;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::import::MAX_IMPORT_BYTES;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const PREFIX: &str = "capture-";
+const SUFFIX: &str = ".http";
+
+/// Added to every capture; `replay` doesn't send them.
+const CAPTURED_AT: &str = "x-captured-at";
+const CAPTURED_FROM: &str = "x-captured-from";
+
+/// Credentials are not written to disk.
+const REDACTED_HEADERS: &[&str] = &["authorization", "x-admin-token", "cookie"];
+const REDACTED: &str = "***";
+/// JSON fields whose name contains one of these are masked.
+const REDACTED_FIELDS: &[&str] = &["token", "password", "secret"];
+/// Path prefixes whose bodies are mostly credentials: never captured.
+const UNCAPTURED_PATHS: &[&str] = &["/api/dashboard/login", "/api/auth/", "/api/admin/users"];
+
+/// Headers the HTTP client sets itself when replaying.
+const HOP_HEADERS: &[&str] = &["host", "content-length", "connection", "transfer-encoding"];
+
+pub struct Capture {
+    dir: Option<PathBuf>,
+    max_files: u64,
+    next_seq: AtomicU64,
+}
+
+impl Capture {
+    pub fn disabled() -> Self {
+        Capture {
+            dir: None,
+            max_files: 0,
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates `dir` if needed and continues numbering after the captures
+    /// already in it.
+    pub fn new(dir: &Path, max_files: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let last = list_dir(dir)?.last().map(|(seq, _)| *seq);
+        Ok(Capture {
+            dir: Some(dir.to_path_buf()),
+            max_files: max_files.max(1),
+            next_seq: AtomicU64::new(last.map_or(0, |s| s + 1)),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Requests that carry a payload; dashboard polling and probes would only
+    /// push the interesting captures out of the ring. File uploads are
+    /// streamed to disk and not buffered for a capture, and logins and user
+    /// management aren't captured at all.
+    fn wants(&self, req: &ServiceRequest) -> bool {
+        let multipart = req
+            .headers()
+            .get("content-type")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("multipart/form-data"));
+        self.is_active()
+            && !multipart
+            && ![Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method())
+            && !UNCAPTURED_PATHS.iter().any(|p| req.path().starts_with(p))
+    }
+
+    /// Writes the capture in the background and drops the oldest one once the
+    /// ring is full.
+    fn store(&self, raw: Vec<u8>) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let max_files = self.max_files;
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = std::fs::write(dir.join(file_name(seq)), raw) {
+                log::error!("❌ Writing request capture {} failed: {}", seq, e);
+            }
+            if let Some(expired) = seq.checked_sub(max_files) {
+                let _ = std::fs::remove_file(dir.join(file_name(expired)));
+            }
+        });
+    }
+}
+
+fn file_name(seq: u64) -> String {
+    format!("{}{:08}{}", PREFIX, seq, SUFFIX)
+}
+
+/// Captures in `dir`, oldest first.
+fn list_dir(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut found: Vec<(u64, PathBuf)> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let seq = name
+                .strip_prefix(PREFIX)?
+                .strip_suffix(SUFFIX)?
+                .parse()
+                .ok()?;
+            Some((seq, e.path()))
+        })
+        .collect();
+    found.sort();
+    Ok(found)
+}
+
+fn serialize(req: &ServiceRequest, body: &[u8]) -> Vec<u8> {
+    let target = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method(), target);
+    head.push_str(&format!(
+        "{}: {}\r\n",
+        CAPTURED_AT,
+        chrono::Utc::now().to_rfc3339()
+    ));
+    head.push_str(&format!(
+        "{}: {}\r\n",
+        CAPTURED_FROM,
+        get_client_ip(req.request())
+    ));
+    for (name, value) in req.headers() {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            REDACTED
+        } else {
+            value.to_str().unwrap_or(REDACTED)
+        };
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut raw = head.into_bytes();
+    match redact_body(req, body) {
+        Some(redacted) => raw.extend_from_slice(&redacted),
+        None => raw.extend_from_slice(body),
+    }
+    raw
+}
+
+/// `body` with its credential fields masked, if it is uncompressed JSON that
+/// has any; `None` to keep it as received.
+fn redact_body(req: &ServiceRequest, body: &[u8]) -> Option<Vec<u8>> {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+    if header("content-encoding").is_some_and(|e| e != "identity")
+        || !header("content-type").is_some_and(|ct| ct.contains("json"))
+    {
+        return None;
+    }
+    let mut value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    redact_fields(&mut value).then(|| serde_json::to_vec(&value).ok())?
+}
+
+/// Masks every string or number under a credential-named key; whether any was.
+fn redact_fields(value: &mut serde_json::Value) -> bool {
+    let mut redacted = false;
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields {
+                let key = key.to_ascii_lowercase();
+                if REDACTED_FIELDS.iter().any(|f| key.contains(f))
+                    && (field.is_string() || field.is_number())
+                {
+                    *field = REDACTED.into();
+                    redacted = true;
+                } else {
+                    redacted |= redact_fields(field);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redacted |= redact_fields(item);
+            }
+        }
+        _ => {}
+    }
+    redacted
+}
+
+/// Middleware: buffers the body, stores the capture and hands the same bytes
+/// on to the handler. A no-op unless `--capture-dir` is set.
+pub async fn record(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let capture = req.app_data::<web::Data<Capture>>().cloned();
+    if let Some(capture) = capture.filter(|c| c.wants(&req)) {
+        let payload = req.extract::<web::Payload>().await?;
+        let body = match payload.to_bytes_limited(MAX_IMPORT_BYTES).await {
+            Ok(body) => body?,
+            Err(_) => {
+                let err = ApiError::PayloadTooLarge(format!(
+                    "Request body is larger than {} bytes",
+                    MAX_IMPORT_BYTES
+                ));
+                return Ok(req
+                    .into_response(err.error_response())
+                    .map_into_right_body());
+            }
+        };
+        capture.store(serialize(&req, &body));
+        req.set_payload(body.into());
+    }
+    next.call(req).await.map(|res| res.map_into_left_body())
+}
+
+struct Captured {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+fn parse(raw: &[u8]) -> Result<Captured, String> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("no end of headers")?;
+    let head = std::str::from_utf8(&raw[..split]).map_err(|_| "headers are not UTF-8")?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err("malformed request line".to_string());
+    };
+    let headers = lines
+        .filter_map(|l| l.split_once(": "))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Ok(Captured {
+        method: method.to_string(),
+        target: target.to_string(),
+        headers,
+        body: raw[split + 4..].to_vec(),
+    })
+}
+
+/// Paths given on the command line, directories expanded to their captures.
+fn replay_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(list_dir(path)?.into_iter().map(|(_, p)| p));
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+pub async fn replay(args: &ReplayArgs) -> io::Result<()> {
+    let files = replay_files(&args.paths)?;
+    if files.is_empty() {
+        return Err(io::Error::other("no captures found"));
+    }
+    let target = args.target.trim_end_matches('/');
+    let client = reqwest::Client::new();
+    let delay = std::time::Duration::from_millis(args.delay_ms);
+    let (mut ok, mut failed) = (0, 0);
+
+    for (i, file) in files.iter().enumerate() {
+        if i > 0 && !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let captured = match std::fs::read(file)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| parse(&raw))
+        {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("❌ {}: {}", file.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        let method = reqwest::Method::from_bytes(captured.method.as_bytes())
+            .map_err(|e| io::Error::other(format!("{}: {}", file.display(), e)))?;
+        let mut request = client.request(method, format!("{}{}", target, captured.target));
+        for (name, value) in &captured.headers {
+            let lower = name.to_ascii_lowercase();
+            if HOP_HEADERS.contains(&lower.as_str())
+                || lower == CAPTURED_AT
+                || lower == CAPTURED_FROM
+                || value == REDACTED
+            {
+                continue;
+            }
+            request = request.header(name, value);
+        }
+        if let Some(token) = &args.admin_token {
+            request = request.header("x-admin-token", token);
+        }
+
+        match request.body(captured.body).send().await {
+            Ok(resp) => {
+                let status = resp.status();
+                if status.is_success() {
+                    ok += 1;
+                    log::info!(
+                        "✅ {} {} {} → {}",
+                        file.display(),
+                        captured.method,
+                        captured.target,
+                        status
+                    );
+                } else {
+                    failed += 1;
+                    let body = resp.text().await.unwrap_or_default();
+                    log::warn!(
+                        "⚠️  {} {} {} → {}: {}",
+                        file.display(),
+                        captured.method,
+                        captured.target,
+                        status,
+                        body
+                    );
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                log::error!("❌ {} {}: {}", file.display(), captured.target, e);
+            }
+        }
+    }
+    log::info!(
+        "🔁 Replayed {} capture(s): {} succeeded, {} failed",
+        files.len(),
+        ok,
+        failed
+    );
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! Domain categories (social, video, news, dev-tools, ads, ...): a built-in
+//! list of well-known sites, extended or replaced with `--category-list`.
+//! Every logged request is tagged with its domain's category on ingest, which
+//! feeds the per-category stats, screen-time budgets and the category
+//! blocklist toggles.
+
+use crate::handlers::common::domain_from_url;
+use crate::types::LogEntry;
+use std::collections::{BTreeMap, HashMap};
+
+/// `(domain, category)`; an entry also covers the domain's subdomains.
+pub const DEFAULT_CATEGORIES: &[(&str, &str)] = &[
+    ("facebook.com", "social"),
+    ("instagram.com", "social"),
+    ("tiktok.com", "social"),
+    ("twitter.com", "social"),
+    ("x.com", "social"),
+    ("reddit.com", "social"),
+    ("snapchat.com", "social"),
+    ("pinterest.com", "social"),
+    ("linkedin.com", "social"),
+    ("discord.com", "social"),
+    ("tumblr.com", "social"),
+    ("youtube.com", "video"),
+    ("youtu.be", "video"),
+    ("googlevideo.com", "video"),
+    ("netflix.com", "video"),
+    ("twitch.tv", "video"),
+    ("vimeo.com", "video"),
+    ("dailymotion.com", "video"),
+    ("disneyplus.com", "video"),
+    ("primevideo.com", "video"),
+    ("hulu.com", "video"),
+    ("spotify.com", "music"),
+    ("soundcloud.com", "music"),
+    ("deezer.com", "music"),
+    ("bbc.co.uk", "news"),
+    ("bbc.com", "news"),
+    ("cnn.com", "news"),
+    ("nytimes.com", "news"),
+    ("theguardian.com", "news"),
+    ("reuters.com", "news"),
+    ("lemonde.fr", "news"),
+    ("washingtonpost.com", "news"),
+    ("apnews.com", "news"),
+    ("news.ycombinator.com", "news"),
+    ("github.com", "dev-tools"),
+    ("githubusercontent.com", "dev-tools"),
+    ("gitlab.com", "dev-tools"),
+    ("stackoverflow.com", "dev-tools"),
+    ("stackexchange.com", "dev-tools"),
+    ("crates.io", "dev-tools"),
+    ("docs.rs", "dev-tools"),
+    ("npmjs.com", "dev-tools"),
+    ("pypi.org", "dev-tools"),
+    ("developer.mozilla.org", "dev-tools"),
+    ("doubleclick.net", "ads"),
+    ("googlesyndication.com", "ads"),
+    ("googleadservices.com", "ads"),
+    ("adnxs.com", "ads"),
+    ("criteo.com", "ads"),
+    ("taboola.com", "ads"),
+    ("outbrain.com", "ads"),
+    ("amazon-adsystem.com", "ads"),
+    ("google-analytics.com", "analytics"),
+    ("googletagmanager.com", "analytics"),
+    ("hotjar.com", "analytics"),
+    ("segment.io", "analytics"),
+    ("amazon.com", "shopping"),
+    ("ebay.com", "shopping"),
+    ("aliexpress.com", "shopping"),
+    ("etsy.com", "shopping"),
+    ("google.com", "search"),
+    ("bing.com", "search"),
+    ("duckduckgo.com", "search"),
+    ("gmail.com", "email"),
+    ("mail.google.com", "email"),
+    ("outlook.com", "email"),
+    ("proton.me", "email"),
+    ("roblox.com", "gaming"),
+    ("steampowered.com", "gaming"),
+    ("epicgames.com", "gaming"),
+    ("minecraft.net", "gaming"),
+    ("wikipedia.org", "reference"),
+];
+
+pub struct Categories {
+    domains: HashMap<String, String>,
+}
+
+impl Default for Categories {
+    fn default() -> Self {
+        Categories::new(true, None).expect("no category list to read")
+    }
+}
+
+impl Categories {
+    /// The built-in list (unless `use_defaults` is false) with the entries
+    /// of `list_path` on top: one `domain category` per line, comma or
+    /// whitespace separated, `#` for comments.
+    pub fn new(use_defaults: bool, list_path: Option<&std::path::Path>) -> std::io::Result<Self> {
+        let mut domains: HashMap<String, String> = if use_defaults {
+            DEFAULT_CATEGORIES
+                .iter()
+                .map(|(d, c)| (d.to_string(), c.to_string()))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        if let Some(path) = list_path {
+            domains.extend(parse_list(&std::fs::read_to_string(path)?));
+        }
+        Ok(Categories { domains })
+    }
+
+    /// No categories at all.
+    pub fn empty() -> Self {
+        Categories {
+            domains: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// The category of `domain`, from the most specific entry that covers it.
+    pub fn classify(&self, domain: &str) -> Option<&str> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let mut candidate = domain.as_str();
+        loop {
+            if let Some(category) = self.domains.get(candidate) {
+                return Some(category);
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+
+    /// Sets `category` on every log in the batch; `None` for unknown domains.
+    pub fn tag(&self, entry: &mut LogEntry) {
+        for log in &mut entry.logs {
+            log.category = domain_from_url(&log.url)
+                .and_then(|d| self.classify(&d))
+                .map(str::to_string);
+        }
+    }
+
+    pub fn contains(&self, category: &str) -> bool {
+        self.domains.values().any(|c| c == category)
+    }
+
+    /// Every category with its domains, sorted.
+    pub fn all(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut out: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (domain, category) in &self.domains {
+            out.entry(category.as_str())
+                .or_default()
+                .push(domain.as_str());
+        }
+        for domains in out.values_mut() {
+            domains.sort_unstable();
+        }
+        out
+    }
+
+    pub fn domains_in(&self, category: &str) -> Vec<&str> {
+        self.all().remove(category).unwrap_or_default()
+    }
+}
+
+fn parse_list(contents: &str) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty());
+        let (Some(domain), Some(category)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        out.insert(
+            domain.trim_end_matches('.').to_ascii_lowercase(),
+            category.to_ascii_lowercase(),
+        );
+    }
+    out
+}
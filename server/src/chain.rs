@@ -0,0 +1,455 @@
+//! Tamper-evident chain of security events (`--event-chain`): every security
+//! event gets a `chain` record with a sequence number per client, the hash
+//! of that client's previous event and its own hash over both and the
+//! event's content. Deleting, reordering or editing a stored event breaks
+//! the chain, which `GET /api/admin/chain/verify` reports.
+//!
+//! The current head of every chain is kept in the `--event-chain` file, and
+//! copied into a checkpoint by the `chain_checkpoint` job, every
+//! `--chain-checkpoint-interval` by default (also recorded as a
+//! `chain_checkpoint` server event), so events removed from
+//! the end of a chain are noticed too.
+//!
+//! With several replicas each one keeps its own chains: the chain key is
+//! `<instance>/<client_id>`.
+
+use crate::server_events;
+use crate::types::ExtensionEvent;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// The `prev` of the first event in a chain.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Checkpoints kept in the file; the oldest are dropped first.
+pub const MAX_CHECKPOINTS: usize = 1000;
+/// Events without a client_id are chained under this name.
+const NO_CLIENT: &str = "-";
+
+static CHAIN: OnceLock<web::Data<EventChain>> = OnceLock::new();
+
+/// The `chain` field of a linked event's data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    pub key: String,
+    pub seq: u64,
+    pub prev: String,
+    pub hash: String,
+}
+
+impl Link {
+    pub fn of(event: &ExtensionEvent) -> Option<Link> {
+        let chain = event.data.get("chain")?;
+        serde_json::from_value(chain.clone()).ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    pub seq: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub at: DateTime<Utc>,
+    pub heads: BTreeMap<String, Head>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    heads: BTreeMap<String, Head>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+pub struct EventChain {
+    enabled: bool,
+    path: Option<PathBuf>,
+    state: Mutex<Saved>,
+}
+
+/// The chain an event belongs to.
+pub fn key_of(event: &ExtensionEvent) -> String {
+    let client = event.client_id.as_deref().unwrap_or(NO_CLIENT);
+    match crate::instance::id() {
+        Some(instance) => format!("{}/{}", instance, client),
+        None => client.to_string(),
+    }
+}
+
+/// The hash of `event` at position `seq` after `prev`. Covers everything
+/// stored for the event but its `chain` field; timestamps are compared as
+/// instants, since the database may store them in another notation.
+pub fn hash(key: &str, seq: u64, prev: &str, event: &ExtensionEvent) -> String {
+    let mut data = event.data.clone();
+    if let Some(obj) = data.as_object_mut() {
+        obj.remove("chain");
+    }
+    let timestamp = match DateTime::parse_from_rfc3339(&event.timestamp) {
+        Ok(t) => t.timestamp_micros().to_string(),
+        Err(_) => event.timestamp.clone(),
+    };
+    // serde_json sorts object keys, so this serializes the same way every time.
+    let content = serde_json::json!({
+        "key": key,
+        "seq": seq,
+        "prev": prev,
+        "client_id": event.client_id,
+        "profile_id": event.profile_id,
+        "session_id": event.session_id,
+        "timestamp": timestamp,
+        "user_agent": event.user_agent,
+        "event_type": event.event_type,
+        "data": data,
+    });
+    let bytes = serde_json::to_vec(&content).unwrap_or_default();
+    hex::encode(Sha256::digest(bytes))
+}
+
+impl Default for EventChain {
+    fn default() -> Self {
+        EventChain::disabled()
+    }
+}
+
+impl EventChain {
+    /// Events aren't chained.
+    pub fn disabled() -> Self {
+        EventChain {
+            enabled: false,
+            path: None,
+            state: Mutex::new(Saved::default()),
+        }
+    }
+
+    /// Chains events, with the heads kept in memory only.
+    pub fn new() -> Self {
+        EventChain {
+            enabled: true,
+            ..EventChain::disabled()
+        }
+    }
+
+    /// Chains events and carries on the chains saved in `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let saved = if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            serde_json::from_str(&raw).map_err(|e| e.to_string())?
+        } else {
+            Saved::default()
+        };
+        Ok(EventChain {
+            enabled: true,
+            path: Some(path.to_path_buf()),
+            state: Mutex::new(saved),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn heads(&self) -> BTreeMap<String, Head> {
+        self.state.lock().unwrap().heads.clone()
+    }
+
+    pub fn checkpoints(&self) -> Vec<Checkpoint> {
+        self.state.lock().unwrap().checkpoints.clone()
+    }
+
+    fn persist(&self, saved: &Saved) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut text = serde_json::to_vec_pretty(saved).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Appends `event` to its client's chain and adds the `chain` field.
+    pub fn link(&self, event: &mut ExtensionEvent) {
+        if !self.enabled {
+            return;
+        }
+        let key = key_of(event);
+        let mut state = self.state.lock().unwrap();
+        let (seq, prev) = match state.heads.get(&key) {
+            Some(head) => (head.seq + 1, head.hash.clone()),
+            None => (1, GENESIS.to_string()),
+        };
+        let hash = hash(&key, seq, &prev, event);
+        let link = Link {
+            key: key.clone(),
+            seq,
+            prev,
+            hash: hash.clone(),
+        };
+        if let Some(obj) = event.data.as_object_mut() {
+            obj.insert(
+                "chain".into(),
+                serde_json::to_value(link).unwrap_or_default(),
+            );
+        }
+        state.heads.insert(key, Head { seq, hash });
+        if let Err(e) = self.persist(&state) {
+            log::error!("❌ Writing the event chain heads failed: {}", e);
+        }
+    }
+
+    /// Copies the heads into a new checkpoint, unless nothing was linked
+    /// since the last one. A checkpoint that can't be written is dropped, so
+    /// the next call tries again.
+    pub fn checkpoint(&self) -> std::io::Result<Option<Checkpoint>> {
+        let mut state = self.state.lock().unwrap();
+        if state.heads.is_empty()
+            || state
+                .checkpoints
+                .last()
+                .is_some_and(|c| c.heads == state.heads)
+        {
+            return Ok(None);
+        }
+        let checkpoint = Checkpoint {
+            at: Utc::now(),
+            heads: state.heads.clone(),
+        };
+        state.checkpoints.push(checkpoint.clone());
+        if state.checkpoints.len() > MAX_CHECKPOINTS {
+            let excess = state.checkpoints.len() - MAX_CHECKPOINTS;
+            state.checkpoints.drain(0..excess);
+        }
+        if let Err(e) = self.persist(&state) {
+            state.checkpoints.pop();
+            return Err(e);
+        }
+        Ok(Some(checkpoint))
+    }
+}
+
+/// Links every security event through `chain` from now on.
+pub fn install(chain: web::Data<EventChain>) {
+    if chain.is_enabled() {
+        let _ = CHAIN.set(chain);
+    }
+}
+
+/// The `chain_checkpoint` job, run every `--chain-checkpoint-interval`
+/// unless `--job-schedule` says otherwise.
+pub async fn checkpoint_job(chain: web::Data<EventChain>) -> Result<String, String> {
+    let checkpoint = chain
+        .checkpoint()
+        .map_err(|e| format!("writing the checkpoint failed: {}", e))?;
+    let Some(checkpoint) = checkpoint else {
+        return Ok("nothing linked since the last checkpoint".to_string());
+    };
+    let chains = checkpoint.heads.len();
+    log::info!("🔗 Event chain checkpoint: {} chains", chains);
+    server_events::record(
+        "chain_checkpoint",
+        serde_json::json!({ "heads": checkpoint.heads }),
+    );
+    Ok(format!("{} chains checkpointed", chains))
+}
+
+/// Adds `event` to the installed chain, if any.
+pub fn link(event: &mut ExtensionEvent) {
+    if let Some(chain) = CHAIN.get() {
+        chain.link(event);
+    }
+}
+
+/// Something wrong with a stored chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub key: String,
+    pub seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_id: Option<String>,
+    /// `modified`, `broken_link`, `duplicate`, `missing` or `missing_tail`.
+    pub problem: &'static str,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainSummary {
+    pub key: String,
+    pub events: usize,
+    pub first_seq: u64,
+    pub last_seq: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub ok: bool,
+    pub verified_at: DateTime<Utc>,
+    pub events: usize,
+    /// Security events without a `chain` record, e.g. stored before
+    /// chaining was turned on.
+    pub unchained: usize,
+    pub chains: Vec<ChainSummary>,
+    pub issues: Vec<Issue>,
+}
+
+/// Checks the stored `events` against each other and against `heads` and
+/// `checkpoints`. A chain whose oldest events are gone (trimmed from the
+/// memory buffer, or deleted by retention) is checked from its first stored
+/// event; events missing after that are reported.
+pub fn verify(
+    events: &[ExtensionEvent],
+    heads: &BTreeMap<String, Head>,
+    checkpoints: &[Checkpoint],
+    only: Option<&str>,
+) -> Verification {
+    let mut chains: BTreeMap<String, Vec<(Link, &ExtensionEvent)>> = BTreeMap::new();
+    let mut unchained = 0;
+    let selected = |key: &str| {
+        only.is_none_or(|c| key == c || key.rsplit_once('/').is_some_and(|(_, k)| k == c))
+    };
+    for event in events.iter().filter(|e| e.data["category"] == "security") {
+        match Link::of(event) {
+            Some(link) if selected(&link.key) => chains
+                .entry(link.key.clone())
+                .or_default()
+                .push((link, event)),
+            Some(_) => {}
+            None => unchained += 1,
+        }
+    }
+
+    let mut issues = Vec::new();
+    let mut summaries = Vec::new();
+    let packet_id = |e: &ExtensionEvent| {
+        e.data
+            .get("packet_id")
+            .and_then(|p| p.as_str())
+            .map(str::to_string)
+    };
+    for (key, links) in &mut chains {
+        links.sort_by_key(|(l, _)| l.seq);
+        let mut issue = |seq, event: Option<&ExtensionEvent>, problem, detail: String| {
+            issues.push(Issue {
+                key: key.clone(),
+                seq,
+                packet_id: event.and_then(packet_id),
+                problem,
+                detail,
+            })
+        };
+        let mut previous: Option<&Link> = None;
+        for (link, event) in links.iter() {
+            if hash(key, link.seq, &link.prev, event) != link.hash {
+                issue(
+                    link.seq,
+                    Some(event),
+                    "modified",
+                    "the content doesn't match the hash".into(),
+                );
+            }
+            match previous {
+                Some(p) if p.seq == link.seq => issue(
+                    link.seq,
+                    Some(event),
+                    "duplicate",
+                    "two events share this position".into(),
+                ),
+                Some(p) if p.seq + 1 < link.seq => issue(
+                    p.seq + 1,
+                    None,
+                    "missing",
+                    format!(
+                        "{} events between {} and {} are gone",
+                        link.seq - p.seq - 1,
+                        p.seq,
+                        link.seq
+                    ),
+                ),
+                Some(p) if p.hash != link.prev => issue(
+                    link.seq,
+                    Some(event),
+                    "broken_link",
+                    "prev doesn't match the hash of the event before".into(),
+                ),
+                None if link.seq == 1 && link.prev != GENESIS => issue(
+                    1,
+                    Some(event),
+                    "broken_link",
+                    "the first event doesn't start from the genesis hash".into(),
+                ),
+                _ => {}
+            }
+            previous = Some(link);
+        }
+        let (first, last) = (links[0].0.seq, links[links.len() - 1].0.seq);
+        let anchors = heads
+            .get(key.as_str())
+            .map(|h| ("head", h))
+            .into_iter()
+            .chain(
+                checkpoints
+                    .iter()
+                    .filter_map(|c| c.heads.get(key.as_str()).map(|h| ("checkpoint", h))),
+            );
+        for (source, head) in anchors {
+            match links.iter().find(|(l, _)| l.seq == head.seq) {
+                Some((l, e)) if l.hash != head.hash => issue(
+                    head.seq,
+                    Some(e),
+                    "modified",
+                    format!("the hash doesn't match the saved {}", source),
+                ),
+                None if head.seq > last => issue(
+                    last + 1,
+                    None,
+                    "missing_tail",
+                    format!(
+                        "the saved {} is at {}, the last stored event at {}",
+                        source, head.seq, last
+                    ),
+                ),
+                _ => {}
+            }
+        }
+        summaries.push(ChainSummary {
+            key: key.clone(),
+            events: links.len(),
+            first_seq: first,
+            last_seq: last,
+        });
+    }
+    // Chains whose every event is gone.
+    for (key, head) in heads
+        .iter()
+        .filter(|(k, _)| selected(k) && !chains.contains_key(*k))
+    {
+        issues.push(Issue {
+            key: key.clone(),
+            seq: head.seq,
+            packet_id: None,
+            problem: "missing_tail",
+            detail: format!(
+                "the saved head is at {}, but no events are stored",
+                head.seq
+            ),
+        });
+    }
+    issues.dedup_by(|a, b| a.key == b.key && a.seq == b.seq && a.problem == b.problem);
+
+    Verification {
+        ok: issues.is_empty(),
+        verified_at: Utc::now(),
+        events: chains.values().map(Vec::len).sum(),
+        unchained,
+        chains: summaries,
+        issues,
+    }
+}
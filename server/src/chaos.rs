@@ -0,0 +1,191 @@
+//! `--chaos`: a development mode that makes the ingest endpoints misbehave
+//! the way a struggling server does, so the extension's retry and backoff
+//! can be tried out against something other than a healthy local server.
+//!
+//! Only `POST`s to the ingest endpoints ([`INGEST_PATHS`]) are affected.
+//! Each one waits a random time from the `latency` range, then:
+//!
+//! - with probability `error` is refused with `500`, code `chaos`, before
+//!   anything is stored;
+//! - with probability `drop` is handled and stored as usual, but the answer
+//!   is held back for `hold` and replaced with a `504` — to a client with a
+//!   shorter timeout the response is lost, and a retry sends the same data
+//!   again.
+//!
+//! The spec is comma-separated `key=value`s, e.g.
+//! `latency=50ms..2s,error=0.1,drop=0.05,hold=30s`; a key left out injects
+//! nothing of its kind (`hold` defaults to 30s). Never turn it on in front
+//! of real users.
+
+use crate::error::ApiError;
+use crate::maintenance::INGEST_PATHS;
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use std::time::Duration;
+
+/// What `--chaos` without a value injects.
+pub const DEFAULT_SPEC: &str = "latency=100ms..1s,error=0.1,drop=0.05";
+const DEFAULT_HOLD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chaos {
+    enabled: bool,
+    latency: (Duration, Duration),
+    error_rate: f64,
+    drop_rate: f64,
+    hold: Duration,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+/// `250ms`, `2s` or `1m`.
+fn parse_delay(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    match s.strip_suffix("ms") {
+        Some(ms) => ms.parse().ok().map(Duration::from_millis),
+        None => crate::handlers::common::parse_duration(s)?.to_std().ok(),
+    }
+}
+
+fn parse_rate(key: &str, value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|r| (0.0..=1.0).contains(r))
+        .ok_or_else(|| format!("{} must be a probability from 0 to 1, got '{}'", key, value))
+}
+
+/// A uniform number in `[0, 1)`.
+fn roll() -> f64 {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).expect("no system random number generator");
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Chaos {
+    pub fn disabled() -> Self {
+        Chaos {
+            enabled: false,
+            latency: (Duration::ZERO, Duration::ZERO),
+            error_rate: 0.0,
+            drop_rate: 0.0,
+            hold: DEFAULT_HOLD,
+        }
+    }
+
+    /// Parses a spec like `latency=50ms..2s,error=0.1,drop=0.05,hold=30s`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut chaos = Chaos {
+            enabled: true,
+            ..Chaos::disabled()
+        };
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+            let delay = |v: &str| {
+                parse_delay(v).ok_or_else(|| {
+                    format!("{} must look like 250ms, 2s or 1m, got '{}'", key.trim(), v)
+                })
+            };
+            match key.trim() {
+                "latency" => {
+                    chaos.latency = match value.split_once("..") {
+                        Some((min, max)) => (delay(min)?, delay(max)?),
+                        None => (delay(value)?, delay(value)?),
+                    };
+                    if chaos.latency.0 > chaos.latency.1 {
+                        return Err(format!("latency range '{}' is backwards", value));
+                    }
+                }
+                "error" => chaos.error_rate = parse_rate("error", value)?,
+                "drop" => chaos.drop_rate = parse_rate("drop", value)?,
+                "hold" => chaos.hold = delay(value)?,
+                other => {
+                    return Err(format!(
+                        "unknown key '{}' (expected latency, error, drop or hold)",
+                        other
+                    ))
+                }
+            }
+        }
+        if chaos.error_rate + chaos.drop_rate > 1.0 {
+            return Err("error and drop add up to more than 1".to_string());
+        }
+        Ok(chaos)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The spec in a form `parse` reads back, for logs and settings.
+    pub fn describe(&self) -> String {
+        format!(
+            "latency={}ms..{}ms,error={},drop={},hold={}ms",
+            self.latency.0.as_millis(),
+            self.latency.1.as_millis(),
+            self.error_rate,
+            self.drop_rate,
+            self.hold.as_millis()
+        )
+    }
+
+    fn delay(&self) -> Duration {
+        let (min, max) = self.latency;
+        min + (max - min).mul_f64(roll())
+    }
+}
+
+/// Delays, fails or drops ingest requests as configured.
+pub async fn inject(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let chaos = req
+        .app_data::<web::Data<Chaos>>()
+        .filter(|c| c.enabled)
+        .filter(|_| req.method() == Method::POST && INGEST_PATHS.contains(&req.path()))
+        .cloned();
+    let Some(chaos) = chaos else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+
+    let delay = chaos.delay();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    let dice = roll();
+    if dice < chaos.error_rate {
+        metrics::CHAOS_INJECTED.inc();
+        log::warn!("🐒 Chaos: failing {} {}", req.method(), req.path());
+        let err = ApiError::Chaos { dropped: false };
+        return Ok(req
+            .into_response(err.error_response())
+            .map_into_right_body());
+    }
+    if dice < chaos.error_rate + chaos.drop_rate {
+        metrics::CHAOS_INJECTED.inc();
+        log::warn!(
+            "🐒 Chaos: dropping the answer to {} {} after {:?}",
+            req.method(),
+            req.path(),
+            chaos.hold
+        );
+        let res = next.call(req).await?;
+        tokio::time::sleep(chaos.hold).await;
+        let (req, _) = res.into_parts();
+        let err = ApiError::Chaos { dropped: true };
+        return Ok(ServiceResponse::new(req, err.error_response()).map_into_right_body());
+    }
+    next.call(req).await.map(|res| res.map_into_left_body())
+}
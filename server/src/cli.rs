@@ -0,0 +1,805 @@
+//! Command-line interface of the `network-logger-server` binary.
+
+use crate::handlers::common::parse_duration;
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ServerMode {
+    Simple,
+    Production,
+    /// Recent data in memory, everything persisted to PostgreSQL
+    Hybrid,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Copy a simple-mode server's data (or a log export file) into the production database
+    MigrateStorage(MigrateArgs),
+    /// Re-send requests recorded with --capture-dir to a server
+    Replay(ReplayArgs),
+    /// Watch a server's live logs, security events and counters in the terminal
+    Tui(TuiArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MigrateArgs {
+    /// Source: base URL of a running simple-mode server (http://host:port) or
+    /// a log export file (NDJSON, JSON array or CSV)
+    #[arg(long)]
+    pub from: String,
+
+    /// Target database (postgresql:// or, with the mysql feature, mysql://)
+    #[arg(long)]
+    pub database_url: String,
+
+    /// Don't copy extension/security events
+    #[arg(long)]
+    pub no_events: bool,
+
+    /// Don't copy the blocklist
+    #[arg(long)]
+    pub no_blocklist: bool,
+
+    /// Sent as X-Admin-Token when reading from a server that has one set
+    #[arg(long)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Capture files, or directories whose captures are replayed oldest first
+    #[arg(required = true)]
+    pub paths: Vec<std::path::PathBuf>,
+
+    /// Base URL of the server to send them to
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub target: String,
+
+    /// Sent as X-Admin-Token, since captures don't keep credentials
+    #[arg(long)]
+    pub admin_token: Option<String>,
+
+    /// Pause between requests, in milliseconds
+    #[arg(long, default_value = "0")]
+    pub delay_ms: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TuiArgs {
+    /// Base URL of the server to watch
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub server: String,
+
+    /// Sent as X-Admin-Token
+    #[arg(long)]
+    pub admin_token: Option<String>,
+
+    /// Only this client's logs and events
+    #[arg(long)]
+    pub client_id: Option<String>,
+
+    /// Log batches to show from before the viewer started (at most 1000)
+    #[arg(long, default_value = "100")]
+    pub lines: usize,
+
+    /// How often the counters and security events are polled
+    #[arg(long, default_value = "2s")]
+    pub refresh: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "network-logger-server")]
+#[command(about = "Network logging server with simple and production modes", long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(short, long, value_enum, default_value = "simple")]
+    pub mode: ServerMode,
+
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    #[arg(short, long, default_value = "8080")]
+    pub port: u16,
+
+    /// Listen address overriding --host/--port: `host:port` or `unix:/path/to.sock`.
+    /// Sockets passed by systemd socket activation take priority.
+    #[arg(long)]
+    pub bind: Option<String>,
+
+    /// Permissions (octal) applied to a unix socket created with --bind
+    #[arg(long, default_value = "660")]
+    pub socket_mode: String,
+
+    /// HTTP worker threads [default: one per CPU core]
+    #[arg(long)]
+    pub workers: Option<usize>,
+
+    /// How long idle connections stay open (0 = close after each response)
+    /// [default: 5s simple, 75s production/hybrid]
+    #[arg(long)]
+    pub keep_alive: Option<String>,
+
+    /// Time a client has to send request headers before a 408
+    /// [default: 5s simple, 10s production/hybrid]
+    #[arg(long)]
+    pub client_request_timeout: Option<String>,
+
+    /// Time a closing connection gets to shut down cleanly
+    /// [default: 1s simple, 5s production/hybrid]
+    #[arg(long)]
+    pub client_disconnect_timeout: Option<String>,
+
+    /// Concurrent connections per worker [default: 25000]
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Accept HTTP/2 without TLS (h2c) on TCP listeners
+    /// [default: false simple, true production/hybrid]
+    #[arg(long)]
+    pub http2: Option<bool>,
+
+    /// Only accept requests from this range (CIDR or address); repeatable
+    #[arg(long = "allow-cidr")]
+    pub allow_cidrs: Vec<String>,
+
+    /// Reject requests from this range (CIDR or address); repeatable
+    #[arg(long = "deny-cidr")]
+    pub deny_cidrs: Vec<String>,
+
+    /// Believe X-Forwarded-For from this proxy (CIDR, address, or `unix` for
+    /// the Unix socket peer); repeatable
+    #[arg(long = "trusted-proxy")]
+    pub trusted_proxies: Vec<String>,
+
+    /// Token required (Bearer or X-Admin-Token) for admin and blocklist-update endpoints
+    #[arg(long)]
+    pub admin_token: Option<String>,
+
+    /// OpenID Connect single sign-on for the dashboard and admin API: the
+    /// provider's discovery URL, client id/secret and group-to-role mapping (JSON)
+    #[arg(long)]
+    pub oidc_config: Option<std::path::PathBuf>,
+
+    /// How long a dashboard login (session cookie) lasts
+    #[arg(long, default_value = "12h")]
+    pub dashboard_session_ttl: String,
+
+    /// File local users created through /api/admin/users are kept in, in
+    /// simple mode (production and hybrid keep them in the database)
+    #[arg(long)]
+    pub users_file: Option<std::path::PathBuf>,
+
+    /// Shared secret for signing and verifying configuration bundles
+    #[arg(long)]
+    pub bundle_key: Option<String>,
+
+    /// Temporarily ban IPs that repeatedly fail authentication or send malformed payloads
+    #[arg(long)]
+    pub auto_ban: bool,
+
+    /// Authentication failures within --ban-window that trigger a ban (0 = never)
+    #[arg(long, default_value = "5")]
+    pub ban_auth_failures: usize,
+
+    /// Malformed payloads (400 responses) within --ban-window that trigger a ban (0 = never)
+    #[arg(long, default_value = "20")]
+    pub ban_malformed: usize,
+
+    #[arg(long, default_value = "10m")]
+    pub ban_window: String,
+
+    #[arg(long, default_value = "1h")]
+    pub ban_duration: String,
+
+    /// Extra decoy path that raises a honeypot alert when requested; repeatable
+    #[arg(long = "honeypot-path")]
+    pub honeypot_paths: Vec<String>,
+
+    /// Don't register the built-in decoy paths (/wp-login.php, /.env, ...)
+    #[arg(long)]
+    pub no_default_honeypots: bool,
+
+    #[arg(long)]
+    pub database_url: Option<String>,
+
+    /// Read-only replica for query endpoints (dashboard, domains, blocklist
+    /// reads); ingest and other writes stay on --database-url
+    #[arg(long)]
+    pub database_read_url: Option<String>,
+
+    #[arg(long)]
+    pub redis_url: Option<String>,
+
+    /// Maximum database connections in the pool
+    #[arg(long, default_value = "20")]
+    pub db_max_connections: u32,
+
+    /// Connections kept open even when idle
+    #[arg(long, default_value = "0")]
+    pub db_min_connections: u32,
+
+    /// How long a query waits for a free connection before failing
+    #[arg(long, default_value = "30s")]
+    pub db_acquire_timeout: String,
+
+    /// Close connections idle for this long (0 = never)
+    #[arg(long, default_value = "10m")]
+    pub db_idle_timeout: String,
+
+    /// Extra connection attempts at startup, with exponential backoff
+    #[arg(long, default_value = "5")]
+    pub db_connect_retries: u32,
+
+    /// Server-side limit per database statement; slower reads get a 504 (0 = no limit)
+    #[arg(long, default_value = "30s")]
+    pub db_statement_timeout: String,
+
+    /// How often production and hybrid modes recompute the /api/stats aggregates
+    #[arg(long, default_value = "5m")]
+    pub stats_refresh_interval: String,
+
+    /// How long /api/stats and client summaries are answered from cache
+    /// (0 = always computed)
+    #[arg(long, default_value = "15s")]
+    pub stats_cache_ttl: String,
+
+    /// Drop cached stats and summaries once this many log entries have
+    /// arrived since they were computed (0 = only the TTL applies)
+    #[arg(long, default_value_t = 500)]
+    pub stats_cache_invalidate_after: u64,
+
+    /// Replica identifier added to log lines and packet IDs; defaults to
+    /// $HOSTNAME in production and hybrid modes
+    #[arg(long)]
+    pub instance_id: Option<String>,
+
+    /// How much recent data hybrid mode keeps in memory (e.g. 15m, 1h)
+    #[arg(long, default_value = "15m")]
+    pub hot_window: String,
+
+    /// Local domain reputation list (`domain score` per line)
+    #[arg(long)]
+    pub reputation_list: Option<String>,
+
+    /// External reputation API; `{domain}` is replaced with the looked-up domain
+    #[arg(long)]
+    pub reputation_api_url: Option<String>,
+
+    #[arg(long, default_value = "3600")]
+    pub reputation_cache_ttl: u64,
+
+    /// Upload volume (MB) to a single non-allowlisted domain that triggers an exfiltration alert
+    #[arg(long, default_value = "50")]
+    pub exfil_threshold_mb: u64,
+
+    /// Sliding window for the exfiltration threshold (e.g. 30m, 1h)
+    #[arg(long, default_value = "1h")]
+    pub exfil_window: String,
+
+    /// Domain (and its subdomains) exempt from exfiltration alerts; repeatable
+    #[arg(long = "exfil-allow-domain")]
+    pub exfil_allow_domains: Vec<String>,
+
+    /// Brand (name or domain) whose look-alike IDN hosts raise homograph alerts; repeatable,
+    /// replaces the built-in list
+    #[arg(long = "homograph-brand", default_values = crate::homograph::DEFAULT_BRANDS)]
+    pub homograph_brands: Vec<String>,
+
+    /// Flag requests to domains registered less than this long ago (e.g. 30d), looked up
+    /// over RDAP; off when unset
+    #[arg(long)]
+    pub rdap_max_age: Option<String>,
+
+    /// RDAP endpoint the domain is appended to for --rdap-max-age lookups
+    #[arg(long, default_value = crate::rdap::DEFAULT_URL)]
+    pub rdap_url: String,
+
+    /// Look up the certificate transparency history of domains flagged by detections on crt.sh
+    /// and leave a summary comment on their events
+    #[arg(long)]
+    pub ct_lookups: bool,
+
+    /// crt.sh (or compatible) search URL for --ct-lookups
+    #[arg(long, default_value = crate::ct::DEFAULT_URL)]
+    pub ct_url: String,
+
+    /// Security event type whose domain --ct-lookups cross-checks; repeatable, replaces the
+    /// built-in detector list
+    #[arg(long = "ct-event-type", default_values = crate::ct::DEFAULT_EVENT_TYPES)]
+    pub ct_event_types: Vec<String>,
+
+    /// ip2asn TSV (range start, range end, AS number, country, name) to map requested hosts to
+    /// their autonomous system with; off when unset
+    #[arg(long)]
+    pub asn_db: Option<std::path::PathBuf>,
+
+    /// Resolve requested host names over DNS for --asn-db; without it only hosts that are IP
+    /// addresses are mapped
+    #[arg(long)]
+    pub resolve_hosts: bool,
+
+    /// DNS lookups --resolve-hosts may start per second
+    #[arg(long, default_value_t = crate::asn::DEFAULT_RATE)]
+    pub resolve_rate: u32,
+
+    /// AS number whose hosts raise bad_asn_host events (e.g. 64500); repeatable
+    #[arg(long = "bad-asn")]
+    pub bad_asns: Vec<u32>,
+
+    /// Log entries a single client_id may send per UTC day (0 = unlimited)
+    #[arg(long, default_value = "0")]
+    pub quota_logs_per_day: u64,
+
+    /// Extension/security events a single client_id may send per UTC day (0 = unlimited)
+    #[arg(long, default_value = "0")]
+    pub quota_events_per_day: u64,
+
+    /// Uncompressed upload volume (MB) a single client_id may send per UTC day (0 = unlimited)
+    #[arg(long, default_value = "0")]
+    pub quota_mb_per_day: u64,
+
+    /// Log entries allowed in one /api/logs batch (0 = unlimited)
+    #[arg(long, default_value = "0")]
+    pub max_batch_logs: usize,
+
+    /// What to do with a batch over --max-batch-logs
+    #[arg(long, value_enum, default_value = "reject")]
+    pub oversized_batch: crate::batch::OversizedBatch,
+
+    /// Request type (image, font, media, ...) whose logs are dropped at ingest; repeatable
+    #[arg(long = "drop-request-type")]
+    pub drop_request_types: Vec<String>,
+
+    /// Store log URLs in canonical form: lowercase host, no default port or fragment,
+    /// query parameters sorted and without --strip-query-param ones
+    #[arg(long)]
+    pub normalize_urls: bool,
+
+    /// Query parameter removed by --normalize-urls (`utm_*` matches a prefix); repeatable,
+    /// replaces the default tracking parameters
+    #[arg(long = "strip-query-param", default_values = crate::url_normalize::DEFAULT_STRIPPED)]
+    pub strip_query_params: Vec<String>,
+
+    /// Levels of nested arrays/objects allowed in an event's data (0 = unlimited)
+    #[arg(long, default_value = "32")]
+    pub max_event_data_depth: usize,
+
+    /// Serialized size of an event's data in bytes (0 = unlimited)
+    #[arg(long, default_value = "262144")]
+    pub max_event_data_bytes: usize,
+
+    /// What to do with event data over --max-event-data-depth or --max-event-data-bytes
+    #[arg(long, value_enum, default_value = "truncate")]
+    pub oversized_event_data: crate::event_data::OversizedEventData,
+
+    /// Directory for files sent to /api/security/upload (uploads are refused without it)
+    #[arg(long)]
+    pub upload_dir: Option<std::path::PathBuf>,
+
+    /// Largest file accepted by /api/security/upload, in MB
+    #[arg(long, default_value = "25")]
+    pub max_upload_mb: u64,
+
+    /// Scan each upload with this command; `{path}` is the file. Exit 0 = clean, 1 = infected
+    #[arg(long)]
+    pub scan_command: Option<String>,
+
+    /// Scan each upload with clamd: a Unix socket path or host:port
+    #[arg(long)]
+    pub clamd: Option<String>,
+
+    /// Scan each upload with the YARA rules (.yar/.yara) in this directory
+    #[arg(long)]
+    pub yara_rules: Option<std::path::PathBuf>,
+
+    /// JSON file of alert channels and routing rules; re-read when it changes, rewritten by the API
+    #[arg(long)]
+    pub alert_rules: Option<std::path::PathBuf>,
+
+    /// JSON file of detection rules and their versions; rewritten by /api/rules
+    #[arg(long)]
+    pub detection_rules: Option<std::path::PathBuf>,
+
+    /// Extra `domain category` lines for the domain classifier
+    #[arg(long)]
+    pub category_list: Option<std::path::PathBuf>,
+
+    /// Don't load the built-in domain categories
+    #[arg(long)]
+    pub no_default_categories: bool,
+
+    /// JSON file of domain categories and per-client daily time budgets for them
+    #[arg(long)]
+    pub screen_time: Option<std::path::PathBuf>,
+
+    /// Extra domain blocked during focus sessions; repeatable
+    #[arg(long = "focus-domain")]
+    pub focus_domains: Vec<String>,
+
+    /// Don't block the built-in social media and video domains during focus sessions
+    #[arg(long)]
+    pub no_default_focus_domains: bool,
+
+    /// JSON file of device groups with their blocklists, schedules and quotas;
+    /// rewritten when groups are changed through the API
+    #[arg(long)]
+    pub groups: Option<std::path::PathBuf>,
+
+    /// JSON file of client names (owner, hostname, department); rewritten
+    /// when names are changed through the API
+    #[arg(long)]
+    pub client_names: Option<std::path::PathBuf>,
+
+    /// CSV file or URL with client_id,name,owner,hostname,department columns,
+    /// synced into the client names every 15 minutes
+    #[arg(long)]
+    pub client_names_csv: Option<String>,
+
+    /// JSON file naming an LDAP / Active Directory server to look up the
+    /// usernames clients report in (display name, department, OU)
+    #[arg(long)]
+    pub ldap_config: Option<std::path::PathBuf>,
+
+    /// Start with a feature switched off (dashboard, export, import, uploads,
+    /// webhooks, anomaly_detection); repeatable
+    #[arg(long = "disable-feature", value_enum)]
+    pub disabled_features: Vec<crate::features::Feature>,
+
+    /// File feature flag changes made through /api/admin/features are kept
+    /// in, so they survive a restart
+    #[arg(long)]
+    pub feature_flags: Option<std::path::PathBuf>,
+
+    /// JSON file of orgs and the client_ids (or client_id prefixes) that
+    /// belong to them, to meter usage per org (see /api/admin/usage)
+    #[arg(long)]
+    pub orgs: Option<std::path::PathBuf>,
+
+    /// File the daily per-org usage is kept in, so it survives a restart
+    #[arg(long)]
+    pub metering_file: Option<std::path::PathBuf>,
+
+    /// File the maintenance pause is kept in, so it survives a restart
+    #[arg(long)]
+    pub maintenance_file: Option<std::path::PathBuf>,
+
+    /// Development only: delay, fail and drop answers to ingest requests,
+    /// e.g. latency=50ms..2s,error=0.1,drop=0.05,hold=30s (without a value:
+    /// latency=100ms..1s,error=0.1,drop=0.05)
+    #[arg(long, value_name = "SPEC", num_args = 0..=1, default_missing_value = crate::chaos::DEFAULT_SPEC)]
+    pub chaos: Option<String>,
+
+    /// Directory backups are written to and restored from (/api/admin/backup
+    /// is refused without it)
+    #[arg(long)]
+    pub backup_dir: Option<std::path::PathBuf>,
+
+    /// Chain security events per client with hashes, keeping the chain heads
+    /// in this file (see /api/admin/chain/verify)
+    #[arg(long)]
+    pub event_chain: Option<std::path::PathBuf>,
+
+    /// How often the chain heads are saved as a checkpoint
+    #[arg(long, default_value = "15m")]
+    pub chain_checkpoint_interval: String,
+
+    /// Also send every security event to this syslog collector as it is
+    /// received: udp://host:514 or tcp://host:601
+    #[arg(long)]
+    pub worm_syslog: Option<String>,
+
+    /// Also write every security event to this S3 Object Lock bucket as it is
+    /// received: https://host/bucket[/prefix] (credentials from AWS_* variables)
+    #[arg(long)]
+    pub worm_s3: Option<String>,
+
+    /// Region the --worm-s3 requests are signed for
+    #[arg(long, default_value = "us-east-1")]
+    pub worm_s3_region: String,
+
+    /// Days each --worm-s3 object is locked in compliance mode (0 = the
+    /// bucket's default retention)
+    #[arg(long, default_value_t = 365)]
+    pub worm_retention_days: u32,
+
+    /// Also copy every accepted log batch and event, as NDJSON, to this
+    /// S3-compatible bucket (AWS, MinIO): https://host/bucket[/prefix]
+    /// (credentials from AWS_* variables)
+    #[arg(long)]
+    pub lake_s3: Option<String>,
+
+    /// Region the --lake-s3 requests are signed for
+    #[arg(long, default_value = "us-east-1")]
+    pub lake_s3_region: String,
+
+    /// Flag clients whose clock is off by more than this (see
+    /// /api/clients/clock-skew)
+    #[arg(long, default_value = "2m")]
+    pub max_clock_skew: String,
+
+    /// Flag clients whose clock offset changes by more than this per day
+    #[arg(long, default_value = "1m")]
+    pub max_clock_drift: String,
+
+    /// Flag extension versions whose recent blocklist-match p95 is over this
+    /// many milliseconds (see /api/telemetry)
+    #[arg(long, default_value_t = 5.0)]
+    pub slow_blocklist_match: f64,
+
+    /// Raise a session_conflict event when one session_id is reported from
+    /// more source IPs than this (several client_ids always do)
+    #[arg(long, default_value_t = 2)]
+    pub session_max_ips: usize,
+
+    /// Hold data from client_ids an admin hasn't approved in this directory
+    /// until `POST /api/clients/{id}/approve`; every client is trusted without it
+    #[arg(long)]
+    pub enrollment_dir: Option<std::path::PathBuf>,
+
+    /// Base URL users reach the server on behind a proxy, e.g.
+    /// https://logs.example.com; used for links in alerts and responses
+    #[arg(long)]
+    pub external_url: Option<String>,
+
+    /// Replace a job's default schedule, e.g. `chain_checkpoint=0 * * * *`
+    /// or `chain_checkpoint=@every 30m ~5m`; repeatable
+    #[arg(long = "job-schedule")]
+    pub job_schedules: Vec<String>,
+
+    /// Retry a job's failed runs, e.g. `chain_checkpoint=5/1m` for five
+    /// retries starting a minute apart and doubling; repeatable
+    #[arg(long = "job-retries")]
+    pub job_retries: Vec<String>,
+
+    /// Keep every job's run history in this file so it survives restarts
+    #[arg(long)]
+    pub job_history: Option<std::path::PathBuf>,
+
+    /// Raise a job_failed security event when a critical job has failed
+    /// this many runs in a row
+    #[arg(long, default_value_t = 3)]
+    pub job_alert_after: u32,
+
+    /// Keep the numbered blocklist versions behind /api/blocklist/delta in
+    /// this file so they survive restarts
+    #[arg(long)]
+    pub blocklist_history: Option<std::path::PathBuf>,
+
+    /// Ed25519 keys to sign served blocklists with (created if missing);
+    /// see /api/blocklist/keys
+    #[arg(long)]
+    pub blocklist_signing_key: Option<std::path::PathBuf>,
+
+    /// Shared secret external policy systems sign pushes to
+    /// /api/integrations/blocklist-sync with (HMAC-SHA256); off without it
+    #[arg(long)]
+    pub blocklist_sync_secret: Option<String>,
+
+    /// Issue tracker (Jira or GitHub, JSON) to open tickets in when an event
+    /// is escalated; closures are synced back by the ticket_sync job
+    #[arg(long)]
+    pub ticket_config: Option<std::path::PathBuf>,
+
+    /// Telegram bot token, for `telegram` alert channels and the command bot
+    #[arg(long)]
+    pub telegram_bot_token: Option<String>,
+
+    /// Chat (numeric id or @username) the bot answers commands from
+    #[arg(long)]
+    pub telegram_chat: Option<String>,
+
+    /// Base URL the bot calls this server's API on (default: http://127.0.0.1:<port>)
+    #[arg(long)]
+    pub telegram_server_url: Option<String>,
+
+    /// Directory to record every incoming request with a body (headers + raw body) to, for `replay`
+    #[arg(long)]
+    pub capture_dir: Option<std::path::PathBuf>,
+
+    /// Captures kept in --capture-dir; the oldest is deleted when a new one is written
+    #[arg(long, default_value = "1000")]
+    pub capture_max_files: u64,
+
+    /// JSON file overriding the Content-Security-Policy and other security
+    /// headers sent on every response (on with built-in defaults without it)
+    #[arg(long)]
+    pub security_headers: Option<std::path::PathBuf>,
+}
+
+impl Args {
+    #[cfg(feature = "production")]
+    pub fn pool_config(&self) -> crate::storage::PoolConfig {
+        let duration = |value: &str, flag: &str| {
+            parse_duration(value)
+                .and_then(|d| d.to_std().ok())
+                .unwrap_or_else(|| panic!("{} must look like 30s or 10m", flag))
+        };
+        let idle_timeout = duration(&self.db_idle_timeout, "--db-idle-timeout");
+        let statement_timeout = duration(&self.db_statement_timeout, "--db-statement-timeout");
+        crate::storage::PoolConfig {
+            max_connections: self.db_max_connections,
+            min_connections: self.db_min_connections,
+            acquire_timeout: duration(&self.db_acquire_timeout, "--db-acquire-timeout"),
+            idle_timeout: (!idle_timeout.is_zero()).then_some(idle_timeout),
+            connect_retries: self.db_connect_retries,
+            statement_timeout: (!statement_timeout.is_zero()).then_some(statement_timeout),
+        }
+    }
+
+    #[cfg(feature = "production")]
+    pub fn stats_interval(&self) -> std::time::Duration {
+        parse_duration(&self.stats_refresh_interval)
+            .and_then(|d| d.to_std().ok())
+            .filter(|d| !d.is_zero())
+            .expect("--stats-refresh-interval must look like 30s or 5m")
+    }
+
+    pub fn stats_cache(&self) -> crate::response_cache::ResponseCache {
+        let ttl = parse_duration(&self.stats_cache_ttl)
+            .and_then(|d| d.to_std().ok())
+            .expect("--stats-cache-ttl must look like 15s or 1m");
+        crate::response_cache::ResponseCache::new(ttl, self.stats_cache_invalidate_after)
+    }
+
+    /// The HTTP server settings: the mode's defaults with any flags given.
+    pub fn http_tuning(&self) -> crate::listen::Tuning {
+        let mut tuning = match self.mode {
+            ServerMode::Simple => crate::listen::Tuning::simple(),
+            _ => crate::listen::Tuning::production(),
+        };
+        let duration = |value: &Option<String>, flag: &str, default: std::time::Duration| {
+            value.as_deref().map_or(default, |v| {
+                parse_duration(v)
+                    .and_then(|d| d.to_std().ok())
+                    .unwrap_or_else(|| panic!("{} must look like 5s or 1m", flag))
+            })
+        };
+        if let Some(workers) = self.workers {
+            assert!(workers > 0, "--workers must be at least 1");
+            tuning.workers = Some(workers);
+        }
+        tuning.keep_alive = duration(&self.keep_alive, "--keep-alive", tuning.keep_alive);
+        tuning.client_request_timeout = duration(
+            &self.client_request_timeout,
+            "--client-request-timeout",
+            tuning.client_request_timeout,
+        );
+        tuning.client_disconnect_timeout = duration(
+            &self.client_disconnect_timeout,
+            "--client-disconnect-timeout",
+            tuning.client_disconnect_timeout,
+        );
+        tuning.max_connections = self.max_connections.unwrap_or(tuning.max_connections);
+        tuning.http2 = self.http2.unwrap_or(tuning.http2);
+        tuning
+    }
+
+    /// Startup settings for `/api/admin/state`. Tokens are reported as set/unset
+    /// and URLs lose their passwords.
+    pub fn config_snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "bind": self.bind.clone().unwrap_or_else(|| format!("{}:{}", self.host, self.port)),
+            "allow_cidrs": self.allow_cidrs,
+            "deny_cidrs": self.deny_cidrs,
+            "trusted_proxies": self.trusted_proxies,
+            "http": self.http_tuning().to_json(),
+            "admin_token_set": self.admin_token.is_some(),
+            "oidc_config": self.oidc_config,
+            "dashboard_session_ttl": self.dashboard_session_ttl,
+            "users_file": self.users_file,
+            "bundle_key_set": self.bundle_key.is_some(),
+            "auto_ban": self.auto_ban,
+            "ban_auth_failures": self.ban_auth_failures,
+            "ban_malformed": self.ban_malformed,
+            "ban_window": self.ban_window,
+            "ban_duration": self.ban_duration,
+            "honeypot_paths": self.honeypot_paths,
+            "no_default_honeypots": self.no_default_honeypots,
+            "database_url": self.database_url.as_deref().map(redact_url),
+            "database_read_url": self.database_read_url.as_deref().map(redact_url),
+            "redis_url": self.redis_url.as_deref().map(redact_url),
+            "db_max_connections": self.db_max_connections,
+            "db_min_connections": self.db_min_connections,
+            "db_acquire_timeout": self.db_acquire_timeout,
+            "db_idle_timeout": self.db_idle_timeout,
+            "db_connect_retries": self.db_connect_retries,
+            "db_statement_timeout": self.db_statement_timeout,
+            "stats_refresh_interval": self.stats_refresh_interval,
+            "stats_cache_ttl": self.stats_cache_ttl,
+            "stats_cache_invalidate_after": self.stats_cache_invalidate_after,
+            "hot_window": self.hot_window,
+            "reputation_list": self.reputation_list,
+            "reputation_api_url": self.reputation_api_url,
+            "reputation_cache_ttl": self.reputation_cache_ttl,
+            "exfil_threshold_mb": self.exfil_threshold_mb,
+            "exfil_window": self.exfil_window,
+            "exfil_allow_domains": self.exfil_allow_domains,
+            "homograph_brands": self.homograph_brands,
+            "rdap_max_age": self.rdap_max_age,
+            "rdap_url": self.rdap_url,
+            "ct_lookups": self.ct_lookups,
+            "ct_url": self.ct_url,
+            "ct_event_types": self.ct_event_types,
+            "asn_db": self.asn_db,
+            "resolve_hosts": self.resolve_hosts,
+            "resolve_rate": self.resolve_rate,
+            "bad_asns": self.bad_asns,
+            "quota_logs_per_day": self.quota_logs_per_day,
+            "quota_events_per_day": self.quota_events_per_day,
+            "quota_mb_per_day": self.quota_mb_per_day,
+            "max_batch_logs": self.max_batch_logs,
+            "oversized_batch": self.oversized_batch,
+            "drop_request_types": self.drop_request_types,
+            "normalize_urls": self.normalize_urls,
+            "strip_query_params": self.strip_query_params,
+            "max_event_data_depth": self.max_event_data_depth,
+            "max_event_data_bytes": self.max_event_data_bytes,
+            "oversized_event_data": self.oversized_event_data,
+            "upload_dir": self.upload_dir,
+            "max_upload_mb": self.max_upload_mb,
+            "scan_command": self.scan_command,
+            "clamd": self.clamd,
+            "yara_rules": self.yara_rules,
+            "alert_rules": self.alert_rules,
+            "detection_rules": self.detection_rules,
+            "category_list": self.category_list,
+            "no_default_categories": self.no_default_categories,
+            "screen_time": self.screen_time,
+            "focus_domains": self.focus_domains,
+            "no_default_focus_domains": self.no_default_focus_domains,
+            "groups": self.groups,
+            "client_names": self.client_names,
+            "client_names_csv": self.client_names_csv,
+            "ldap_config": self.ldap_config,
+            "disabled_features": self.disabled_features,
+            "feature_flags": self.feature_flags,
+            "orgs": self.orgs,
+            "metering_file": self.metering_file,
+            "maintenance_file": self.maintenance_file,
+            "chaos": self.chaos,
+            "backup_dir": self.backup_dir,
+            "event_chain": self.event_chain,
+            "chain_checkpoint_interval": self.chain_checkpoint_interval,
+            "worm_syslog": self.worm_syslog,
+            "worm_s3": self.worm_s3,
+            "worm_s3_region": self.worm_s3_region,
+            "worm_retention_days": self.worm_retention_days,
+            "lake_s3": self.lake_s3,
+            "lake_s3_region": self.lake_s3_region,
+            "max_clock_skew": self.max_clock_skew,
+            "max_clock_drift": self.max_clock_drift,
+            "slow_blocklist_match": self.slow_blocklist_match,
+            "session_max_ips": self.session_max_ips,
+            "enrollment_dir": self.enrollment_dir,
+            "external_url": self.external_url,
+            "job_schedules": self.job_schedules,
+            "job_retries": self.job_retries,
+            "job_history": self.job_history,
+            "job_alert_after": self.job_alert_after,
+            "blocklist_history": self.blocklist_history,
+            "blocklist_signing_key": self.blocklist_signing_key,
+            "blocklist_sync_secret_set": self.blocklist_sync_secret.is_some(),
+            "ticket_config": self.ticket_config,
+            "telegram_bot_token_set": self.telegram_bot_token.is_some(),
+            "telegram_chat": self.telegram_chat,
+            "telegram_server_url": self.telegram_server_url,
+            "capture_dir": self.capture_dir,
+            "capture_max_files": self.capture_max_files,
+            "security_headers": self.security_headers,
+        })
+    }
+}
+
+/// Drops the password from a connection URL before it is shown anywhere.
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut u) if u.password().is_some() => {
+            let _ = u.set_password(Some("***"));
+            u.to_string()
+        }
+        Ok(u) => u.to_string(),
+        Err(_) => "<unparseable>".to_string(),
+    }
+}
@@ -0,0 +1,354 @@
+//! Human-friendly names for client_ids: a `name`, the `owner`, the
+//! `hostname` and the `department` of the machine an extension runs on, so
+//! the dashboard shows "Dana's laptop" rather than a UUID.
+//!
+//! Names are set one client at a time through
+//! `PUT /api/admin/clients/{id}/name`, or synced from a directory: with
+//! `--client-names-csv` the `client_names_sync` job re-reads a CSV file (or
+//! URL) and replaces every name it synced before; with `--ldap-config` a
+//! client is named after the user its batches report (see [`crate::ldap`]).
+//! A name set through the API wins over a synced one for the same client,
+//! and both win over one from LDAP. With `--client-names` all of them are
+//! kept in that file and survive a restart.
+//!
+//! The resolved name (the `name`, else the hostname, else the owner) is
+//! returned as `client_name` next to `client_id` in the dashboard payloads.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Longest `name`, `owner`, `hostname` or `department`.
+pub const MAX_FIELD_LEN: usize = 200;
+
+/// Where a name came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// `PUT /api/admin/clients/{id}/name`.
+    Manual,
+    /// The `--client-names-csv` sync.
+    Csv,
+    /// The user the client reports, looked up with `--ldap-config`.
+    Ldap,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientName {
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub department: Option<String>,
+    /// The user's OUs, outermost first (`Staff/Finance`); from LDAP only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ou: Option<String>,
+    pub source: Source,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ClientName {
+    /// What the dashboard shows for the client.
+    pub fn display(&self) -> Option<&str> {
+        self.name
+            .as_deref()
+            .or(self.hostname.as_deref())
+            .or(self.owner.as_deref())
+    }
+}
+
+/// The body of `PUT /api/admin/clients/{id}/name`.
+#[derive(Debug, Default, Deserialize)]
+pub struct NameUpdate {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+}
+
+fn field(value: Option<String>) -> Result<Option<String>, String> {
+    match value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    {
+        Some(v) if v.chars().count() > MAX_FIELD_LEN => {
+            Err(format!("fields are at most {} characters", MAX_FIELD_LEN))
+        }
+        other => Ok(other),
+    }
+}
+
+impl NameUpdate {
+    /// The entry for `client_id`; it needs at least one non-empty field.
+    pub fn into_name(
+        self,
+        client_id: &str,
+        source: Source,
+        now: DateTime<Utc>,
+    ) -> Result<ClientName, String> {
+        let name = ClientName {
+            client_id: client_id.to_string(),
+            name: field(self.name)?,
+            owner: field(self.owner)?,
+            hostname: field(self.hostname)?,
+            department: field(self.department)?,
+            ou: None,
+            source,
+            updated_at: now,
+        };
+        if name.display().is_none() && name.department.is_none() {
+            return Err("give at least one of name, owner, hostname or department".to_string());
+        }
+        Ok(name)
+    }
+}
+
+/// Where synced names come from.
+#[derive(Debug, Clone)]
+pub enum Directory {
+    /// A CSV file or `http(s)://` URL with a header row naming `client_id`
+    /// and any of `name`, `owner`, `hostname`, `department`.
+    Csv(String),
+}
+
+/// What one sync changed.
+#[derive(Debug, Default, Serialize)]
+pub struct SyncCounts {
+    pub synced: usize,
+    pub removed: usize,
+    /// Rows without a client_id or with nothing to name it by.
+    pub skipped: usize,
+    /// Rows for clients named through the API, which keep that name.
+    pub overridden: usize,
+}
+
+/// Parses the CSV of a [`Directory::Csv`]; returns the names and how many
+/// rows were skipped.
+pub fn parse_csv(text: &str, now: DateTime<Utc>) -> Result<(Vec<ClientName>, usize), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let id_column = column("client_id").ok_or("the CSV has no client_id column")?;
+    let columns = ["name", "owner", "hostname", "department"].map(column);
+    let (mut names, mut skipped) = (Vec::new(), 0);
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let cell = |i: Option<usize>| i.and_then(|i| record.get(i)).map(str::to_string);
+        let client_id = cell(Some(id_column)).unwrap_or_default();
+        let update = NameUpdate {
+            name: cell(columns[0]),
+            owner: cell(columns[1]),
+            hostname: cell(columns[2]),
+            department: cell(columns[3]),
+        };
+        match update.into_name(&client_id, Source::Csv, now) {
+            Ok(name) if !client_id.is_empty() => names.push(name),
+            _ => skipped += 1,
+        }
+    }
+    Ok((names, skipped))
+}
+
+impl Directory {
+    pub fn parse(spec: &str) -> Self {
+        Directory::Csv(spec.to_string())
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Directory::Csv(location) => format!("CSV {}", location),
+        }
+    }
+
+    /// The names the directory has now, and how many of its rows were
+    /// skipped.
+    pub async fn fetch(&self) -> Result<(Vec<ClientName>, usize), String> {
+        match self {
+            Directory::Csv(location) => {
+                let text = if location.starts_with("http://") || location.starts_with("https://") {
+                    let resp = reqwest::get(location).await.map_err(|e| e.to_string())?;
+                    let resp = resp.error_for_status().map_err(|e| e.to_string())?;
+                    resp.text().await.map_err(|e| e.to_string())?
+                } else {
+                    tokio::fs::read_to_string(location)
+                        .await
+                        .map_err(|e| format!("{}: {}", location, e))?
+                };
+                parse_csv(&text, Utc::now())
+            }
+        }
+    }
+}
+
+pub struct ClientNames {
+    path: Option<PathBuf>,
+    names: RwLock<BTreeMap<String, ClientName>>,
+}
+
+impl Default for ClientNames {
+    fn default() -> Self {
+        ClientNames::new()
+    }
+}
+
+impl ClientNames {
+    /// No names, changes kept in memory only.
+    pub fn new() -> Self {
+        ClientNames {
+            path: None,
+            names: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Reads `path` if it exists; changes are written back to it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let names: Vec<ClientName> = if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            serde_json::from_str(&raw).map_err(|e| e.to_string())?
+        } else {
+            Vec::new()
+        };
+        Ok(ClientNames {
+            path: Some(path.to_path_buf()),
+            names: RwLock::new(
+                names
+                    .into_iter()
+                    .map(|n| (n.client_id.clone(), n))
+                    .collect(),
+            ),
+        })
+    }
+
+    fn persist(&self, names: &BTreeMap<String, ClientName>) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let list: Vec<&ClientName> = names.values().collect();
+            let mut text = serde_json::to_vec_pretty(&list).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Every name, by client_id.
+    pub fn list(&self) -> Vec<ClientName> {
+        self.names.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, client_id: &str) -> Option<ClientName> {
+        self.names.read().unwrap().get(client_id).cloned()
+    }
+
+    /// The name the dashboard shows for `client_id`, if it has one.
+    pub fn resolve(&self, client_id: &str) -> Option<String> {
+        let names = self.names.read().unwrap();
+        names.get(client_id)?.display().map(str::to_string)
+    }
+
+    /// Sets a name, replacing whatever the client had.
+    pub fn set(&self, name: ClientName) -> std::io::Result<()> {
+        let mut names = self.names.write().unwrap();
+        let mut next = names.clone();
+        next.insert(name.client_id.clone(), name);
+        self.persist(&next)?;
+        *names = next;
+        Ok(())
+    }
+
+    /// Forgets the client's name; false when it had none.
+    pub fn remove(&self, client_id: &str) -> std::io::Result<bool> {
+        let mut names = self.names.write().unwrap();
+        if !names.contains_key(client_id) {
+            return Ok(false);
+        }
+        let mut next = names.clone();
+        next.remove(client_id);
+        self.persist(&next)?;
+        *names = next;
+        Ok(true)
+    }
+
+    /// Names a client after what the directory says about its user, unless
+    /// it has a name from the API or a CSV; false when nothing changed.
+    pub fn enrich(&self, name: ClientName) -> std::io::Result<bool> {
+        let mut names = self.names.write().unwrap();
+        if let Some(current) = names.get(&name.client_id) {
+            let same = (
+                &current.name,
+                &current.owner,
+                &current.department,
+                &current.ou,
+            ) == (&name.name, &name.owner, &name.department, &name.ou);
+            if current.source != Source::Ldap || same {
+                return Ok(false);
+            }
+        }
+        let mut next = names.clone();
+        next.insert(name.client_id.clone(), name);
+        self.persist(&next)?;
+        *names = next;
+        Ok(true)
+    }
+
+    /// Replaces every name from `source` with `synced`, leaving names set
+    /// through the API alone; synced names replace ones from LDAP.
+    pub fn sync(&self, source: Source, synced: Vec<ClientName>) -> std::io::Result<SyncCounts> {
+        let mut names = self.names.write().unwrap();
+        let mut next = names.clone();
+        let before = next.values().filter(|n| n.source == source).count();
+        next.retain(|_, n| n.source != source);
+        let mut counts = SyncCounts::default();
+        for name in synced {
+            if next
+                .get(&name.client_id)
+                .is_some_and(|n| n.source == Source::Manual)
+            {
+                counts.overridden += 1;
+                continue;
+            }
+            counts.synced += 1;
+            next.insert(name.client_id.clone(), name);
+        }
+        counts.removed = before.saturating_sub(counts.synced);
+        self.persist(&next)?;
+        *names = next;
+        Ok(counts)
+    }
+}
+
+/// The `client_names_sync` job: one pass over the directory.
+pub async fn sync_job(
+    names: actix_web::web::Data<ClientNames>,
+    directory: std::sync::Arc<Directory>,
+) -> Result<String, String> {
+    let (synced, skipped) = directory.fetch().await?;
+    let mut counts = names
+        .sync(Source::Csv, synced)
+        .map_err(|e| format!("writing --client-names failed: {}", e))?;
+    counts.skipped = skipped;
+    log::info!(
+        "🏷️ Client names synced from {}: {} names, {} gone, {} rows skipped",
+        directory.describe(),
+        counts.synced,
+        counts.removed,
+        counts.skipped
+    );
+    Ok(format!(
+        "{} names synced, {} removed, {} rows skipped, {} kept their API name",
+        counts.synced, counts.removed, counts.skipped, counts.overridden
+    ))
+}
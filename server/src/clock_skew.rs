@@ -0,0 +1,208 @@
+//! Client clock skew: every batch's `timestamp` is compared with the time the
+//! server received it, per client_id. The offset is estimated as the median
+//! of recent samples, so one batch that sat in the extension's queue doesn't
+//! move it, and stored on each log (`clock_skew_ms`) and event
+//! (`data.clock_skew_ms`) so time-range filters can use corrected times.
+//!
+//! A client is flagged `large` when the offset is over `--max-clock-skew`,
+//! and `drifting` when the offset changes faster than `--max-clock-drift`
+//! per day. A client becoming flagged is recorded as a `clock_skew` server
+//! event. At most [`MAX_CLIENTS`] clients are tracked.
+
+use crate::server_events;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Samples kept per client, at most one per [`SAMPLE_EVERY`].
+pub const MAX_SAMPLES: usize = 64;
+/// Batches arriving closer together than this add no new sample.
+pub const SAMPLE_EVERY: std::time::Duration = std::time::Duration::from_secs(60);
+/// Clients tracked; past it the least recently seen tenth is dropped.
+pub const MAX_CLIENTS: usize = 100_000;
+/// Drift is only judged once the samples span this long.
+pub const MIN_DRIFT_SPAN: std::time::Duration = std::time::Duration::from_secs(3600);
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewFlag {
+    Large,
+    Drifting,
+}
+
+/// What is known about one client's clock.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientSkew {
+    pub client_id: String,
+    /// Estimated offset: client time minus server time, in milliseconds.
+    pub offset_ms: i64,
+    /// The offset of the last batch alone.
+    pub last_ms: i64,
+    /// How fast the offset changes, once there is enough history.
+    pub drift_ms_per_day: Option<f64>,
+    pub samples: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub flags: Vec<SkewFlag>,
+}
+
+struct Track {
+    /// (received at, client time minus received at in ms), oldest first.
+    samples: VecDeque<(DateTime<Utc>, i64)>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    last_ms: i64,
+    flagged: bool,
+}
+
+impl Track {
+    fn offset_ms(&self) -> i64 {
+        let mut offsets: Vec<i64> = self.samples.iter().map(|(_, ms)| *ms).collect();
+        offsets.sort_unstable();
+        offsets[offsets.len() / 2]
+    }
+
+    /// Least-squares slope of the samples, in ms per day.
+    fn drift_ms_per_day(&self) -> Option<f64> {
+        let (first, _) = self.samples.front()?;
+        let (last, _) = self.samples.back()?;
+        if (*last - *first).to_std().ok()? < MIN_DRIFT_SPAN {
+            return None;
+        }
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|(at, ms)| ((*at - *first).num_milliseconds() as f64, *ms as f64))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        (variance > 0.0).then(|| covariance / variance * MS_PER_DAY)
+    }
+}
+
+pub struct ClockSkew {
+    max_skew: Duration,
+    max_drift_per_day: Duration,
+    clients: Mutex<HashMap<String, Track>>,
+}
+
+impl Default for ClockSkew {
+    fn default() -> Self {
+        Self::new(Duration::minutes(2), Duration::minutes(1))
+    }
+}
+
+impl ClockSkew {
+    pub fn new(max_skew: Duration, max_drift_per_day: Duration) -> Self {
+        ClockSkew {
+            max_skew,
+            max_drift_per_day,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn summary(&self, client_id: &str, track: &Track) -> ClientSkew {
+        let offset_ms = track.offset_ms();
+        let drift_ms_per_day = track.drift_ms_per_day();
+        let mut flags = Vec::new();
+        if offset_ms.abs() > self.max_skew.num_milliseconds() {
+            flags.push(SkewFlag::Large);
+        }
+        if drift_ms_per_day
+            .is_some_and(|d| d.abs() > self.max_drift_per_day.num_milliseconds() as f64)
+        {
+            flags.push(SkewFlag::Drifting);
+        }
+        ClientSkew {
+            client_id: client_id.to_string(),
+            offset_ms,
+            last_ms: track.last_ms,
+            drift_ms_per_day,
+            samples: track.samples.len(),
+            first_seen: track.first_seen,
+            last_seen: track.last_seen,
+            flags,
+        }
+    }
+
+    /// Records a batch stamped `timestamp` arriving now and returns the
+    /// client's estimated offset; `None` when the timestamp isn't RFC 3339.
+    pub fn observe(&self, client_id: &str, timestamp: &str) -> Option<i64> {
+        let now = Utc::now();
+        let sent = DateTime::parse_from_rfc3339(timestamp).ok()?;
+        let ms = (sent.with_timezone(&Utc) - now).num_milliseconds();
+        let (summary, newly_flagged) = {
+            let mut clients = self.clients.lock().unwrap();
+            if clients.len() >= MAX_CLIENTS && !clients.contains_key(client_id) {
+                let mut seen: Vec<_> = clients
+                    .iter()
+                    .map(|(id, t)| (t.last_seen, id.clone()))
+                    .collect();
+                let (oldest, _, _) = seen.select_nth_unstable(MAX_CLIENTS / 10);
+                for (_, id) in oldest.iter() {
+                    clients.remove(id);
+                }
+            }
+            let track = clients
+                .entry(client_id.to_string())
+                .or_insert_with(|| Track {
+                    samples: VecDeque::new(),
+                    first_seen: now,
+                    last_seen: now,
+                    last_ms: ms,
+                    flagged: false,
+                });
+            track.last_seen = now;
+            track.last_ms = ms;
+            let due = track
+                .samples
+                .back()
+                .is_none_or(|(at, _)| (now - *at).to_std().is_ok_and(|d| d >= SAMPLE_EVERY));
+            if due {
+                track.samples.push_back((now, ms));
+                if track.samples.len() > MAX_SAMPLES {
+                    track.samples.pop_front();
+                }
+            }
+            let summary = self.summary(client_id, track);
+            let flagged = !summary.flags.is_empty();
+            let newly_flagged = flagged && !track.flagged;
+            track.flagged = flagged;
+            (summary, newly_flagged)
+        };
+        if newly_flagged {
+            log::warn!(
+                "⏰ Clock of client_id={} is off by {}ms ({:?})",
+                client_id,
+                summary.offset_ms,
+                summary.flags
+            );
+            server_events::record(
+                "clock_skew",
+                serde_json::to_value(&summary).unwrap_or_default(),
+            );
+        }
+        Some(summary.offset_ms)
+    }
+
+    pub fn get(&self, client_id: &str) -> Option<ClientSkew> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(client_id).map(|t| self.summary(client_id, t))
+    }
+
+    /// Every client seen, by client_id.
+    pub fn clients(&self) -> Vec<ClientSkew> {
+        let clients = self.clients.lock().unwrap();
+        let mut out: Vec<ClientSkew> = clients.iter().map(|(id, t)| self.summary(id, t)).collect();
+        out.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        out
+    }
+}
@@ -0,0 +1,360 @@
+//! Running totals of what `/api/logs` accepted, kept as batches arrive
+//! instead of recomputed from stored logs: requests, blocked requests,
+//! per-batch unique URLs, suspicious domains (reputation score at or above
+//! the threshold) and page views, for the whole server and per client.
+//!
+//! The whole-server totals are the `canigoin_ingested_*` Prometheus
+//! counters; `/api/stats` reports them with the busiest clients under
+//! `ingest`. Totals start at zero with the process; with `--redis-url` every
+//! replica also adds them to Redis, and `/api/stats` reports those, covering
+//! all replicas since the first one counted. At most [`MAX_CLIENTS`] clients
+//! are counted separately; past it the tenth with the fewest logs is dropped
+//! to make room.
+
+use crate::metrics;
+use crate::types::LogEntry;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use redis::AsyncCommands;
+
+/// Clients with totals of their own.
+pub const MAX_CLIENTS: usize = 100_000;
+
+/// What one batch adds to the totals.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct BatchSummary {
+    pub logs: u64,
+    pub blocked: u64,
+    /// Distinct URLs within the batch.
+    pub unique_urls: u64,
+    pub suspicious: u64,
+    /// `main_frame` requests.
+    pub page_views: u64,
+}
+
+impl BatchSummary {
+    /// `suspicious` is what reputation annotation counted.
+    pub fn of(entry: &LogEntry, suspicious: usize) -> BatchSummary {
+        let urls: HashSet<&str> = entry.logs.iter().map(|l| l.url.as_str()).collect();
+        BatchSummary {
+            logs: entry.logs.len() as u64,
+            blocked: entry.logs.iter().filter(|l| l.blocked).count() as u64,
+            unique_urls: urls.len() as u64,
+            suspicious: suspicious as u64,
+            page_views: entry
+                .logs
+                .iter()
+                .filter(|l| l.request_type == "main_frame")
+                .count() as u64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    batches: AtomicU64,
+    logs: AtomicU64,
+    blocked: AtomicU64,
+    unique_urls: AtomicU64,
+    suspicious: AtomicU64,
+    page_views: AtomicU64,
+}
+
+impl Totals {
+    fn add(&self, batch: &BatchSummary) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.logs.fetch_add(batch.logs, Ordering::Relaxed);
+        self.blocked.fetch_add(batch.blocked, Ordering::Relaxed);
+        self.unique_urls
+            .fetch_add(batch.unique_urls, Ordering::Relaxed);
+        self.suspicious
+            .fetch_add(batch.suspicious, Ordering::Relaxed);
+        self.page_views
+            .fetch_add(batch.page_views, Ordering::Relaxed);
+    }
+
+    fn read(&self) -> Count {
+        Count {
+            batches: self.batches.load(Ordering::Relaxed),
+            logs: self.logs.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            unique_urls: self.unique_urls.load(Ordering::Relaxed),
+            suspicious: self.suspicious.load(Ordering::Relaxed),
+            page_views: self.page_views.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Totals as read at one moment.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Count {
+    pub batches: u64,
+    pub logs: u64,
+    pub blocked: u64,
+    /// The sum of each batch's distinct URLs; `distinct` in `/api/stats`
+    /// estimates them across batches.
+    pub unique_urls: u64,
+    pub suspicious: u64,
+    pub page_views: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientCount {
+    pub client_id: String,
+    #[serde(flatten)]
+    pub count: Count,
+}
+
+/// The `ingest` object of `/api/stats`.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    /// `redis` for every replica's totals, `memory` for this one's.
+    pub source: &'static str,
+    pub since: DateTime<Utc>,
+    #[serde(flatten)]
+    pub total: Count,
+    /// Clients with the most logs, at most the `limit` of the request.
+    pub clients: Vec<ClientCount>,
+}
+
+pub struct IngestCounters {
+    since: DateTime<Utc>,
+    total: Totals,
+    clients: RwLock<HashMap<String, Arc<Totals>>>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl Default for IngestCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IngestCounters {
+    pub fn new() -> Self {
+        IngestCounters {
+            since: Utc::now(),
+            total: Totals::default(),
+            clients: RwLock::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Also adds to Redis: the totals in hash `ingest:total`, each client's
+    /// in `ingest:client:<client_id>` and the clients by logs in sorted set
+    /// `ingest:clients`.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    /// Adds an accepted batch, to `client_id` too when it has one.
+    pub fn record(&self, client_id: Option<&str>, batch: &BatchSummary) {
+        self.total.add(batch);
+        metrics::INGESTED_BATCHES.inc();
+        metrics::INGESTED_LOGS.add(batch.logs);
+        metrics::INGESTED_BLOCKED.add(batch.blocked);
+        metrics::INGESTED_SUSPICIOUS.add(batch.suspicious);
+        let client_id = client_id.filter(|c| !c.is_empty());
+        #[cfg(feature = "production")]
+        if let Some(redis) = self.redis.clone() {
+            let (client_id, batch, since) = (client_id.map(str::to_string), *batch, self.since);
+            actix_web::rt::spawn(async move {
+                if redis_add(&redis, client_id.as_deref(), &batch, since)
+                    .await
+                    .is_none()
+                {
+                    log::debug!("Adding to the ingest totals in Redis failed");
+                }
+            });
+        }
+        let Some(client_id) = client_id else {
+            return;
+        };
+        let existing = self.clients.read().unwrap().get(client_id).cloned();
+        let totals = match existing {
+            Some(totals) => totals,
+            None => {
+                let mut clients = self.clients.write().unwrap();
+                if clients.len() >= MAX_CLIENTS && !clients.contains_key(client_id) {
+                    let mut counted: Vec<_> = clients
+                        .iter()
+                        .map(|(id, t)| (t.logs.load(Ordering::Relaxed), id.clone()))
+                        .collect();
+                    let (fewest, _, _) = counted.select_nth_unstable(MAX_CLIENTS / 10);
+                    for (_, id) in fewest.iter() {
+                        clients.remove(id);
+                    }
+                }
+                clients.entry(client_id.to_string()).or_default().clone()
+            }
+        };
+        totals.add(batch);
+    }
+
+    /// One client's totals, if it sent anything.
+    pub fn client(&self, client_id: &str) -> Option<Count> {
+        self.clients
+            .read()
+            .unwrap()
+            .get(client_id)
+            .map(|t| t.read())
+    }
+
+    /// The totals from Redis when it answers, else this replica's.
+    pub async fn snapshot(&self, limit: usize) -> Snapshot {
+        #[cfg(feature = "production")]
+        if let Some(redis) = &self.redis {
+            if let Some(snapshot) = redis_snapshot(redis, limit).await {
+                return snapshot;
+            }
+        }
+        let mut clients: Vec<ClientCount> = self
+            .clients
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(client_id, totals)| ClientCount {
+                client_id: client_id.clone(),
+                count: totals.read(),
+            })
+            .collect();
+        // Only the top `limit` are sorted, not every client.
+        if limit < clients.len() {
+            clients.select_nth_unstable_by(limit, busiest);
+            clients.truncate(limit);
+        }
+        clients.sort_by(busiest);
+        Snapshot {
+            source: "memory",
+            since: self.since,
+            total: self.total.read(),
+            clients,
+        }
+    }
+}
+
+fn busiest(a: &ClientCount, b: &ClientCount) -> std::cmp::Ordering {
+    b.count
+        .logs
+        .cmp(&a.count.logs)
+        .then_with(|| a.client_id.cmp(&b.client_id))
+}
+
+#[cfg(feature = "production")]
+const TOTAL_KEY: &str = "ingest:total";
+#[cfg(feature = "production")]
+const CLIENTS_KEY: &str = "ingest:clients";
+
+#[cfg(feature = "production")]
+fn client_key(client_id: &str) -> String {
+    format!("ingest:client:{}", client_id)
+}
+
+#[cfg(feature = "production")]
+fn fields(batch: &BatchSummary) -> [(&'static str, u64); 6] {
+    [
+        ("batches", 1),
+        ("logs", batch.logs),
+        ("blocked", batch.blocked),
+        ("unique_urls", batch.unique_urls),
+        ("suspicious", batch.suspicious),
+        ("page_views", batch.page_views),
+    ]
+}
+
+#[cfg(feature = "production")]
+fn count(hash: &HashMap<String, String>) -> Count {
+    let n = |field: &str| hash.get(field).and_then(|n| n.parse().ok()).unwrap_or(0);
+    Count {
+        batches: n("batches"),
+        logs: n("logs"),
+        blocked: n("blocked"),
+        unique_urls: n("unique_urls"),
+        suspicious: n("suspicious"),
+        page_views: n("page_views"),
+    }
+}
+
+#[cfg(feature = "production")]
+async fn redis_add(
+    redis: &SharedRedis,
+    client_id: Option<&str>,
+    batch: &BatchSummary,
+    since: DateTime<Utc>,
+) -> Option<()> {
+    let mut conn = redis.conn().await?;
+    let mut pipe = redis::pipe();
+    pipe.hset_nx(TOTAL_KEY, "since", since.to_rfc3339())
+        .ignore();
+    for (field, n) in fields(batch) {
+        pipe.hincr(TOTAL_KEY, field, n).ignore();
+    }
+    if let Some(client_id) = client_id {
+        let key = client_key(client_id);
+        for (field, n) in fields(batch) {
+            pipe.hincr(&key, field, n).ignore();
+        }
+        pipe.zincr(CLIENTS_KEY, client_id, batch.logs)
+            .ignore()
+            .zcard(CLIENTS_KEY);
+    }
+    let clients: Vec<usize> = timed(pipe.query_async(&mut conn)).await?;
+    if clients.first().is_some_and(|n| *n > MAX_CLIENTS) {
+        let fewest: Vec<String> =
+            timed(conn.zrange(CLIENTS_KEY, 0, (MAX_CLIENTS / 10) as isize - 1)).await?;
+        let keys: Vec<String> = fewest.iter().map(|id| client_key(id)).collect();
+        let mut pipe = redis::pipe();
+        pipe.zrem(CLIENTS_KEY, &fewest).ignore().del(keys).ignore();
+        timed(pipe.query_async::<_, ()>(&mut conn)).await?;
+    }
+    Some(())
+}
+
+#[cfg(feature = "production")]
+async fn redis_snapshot(redis: &SharedRedis, limit: usize) -> Option<Snapshot> {
+    let mut conn = redis.conn().await?;
+    let mut pipe = redis::pipe();
+    pipe.hgetall(TOTAL_KEY)
+        .zrevrange(CLIENTS_KEY, 0, limit.max(1) as isize - 1);
+    let (total, mut ids): (HashMap<String, String>, Vec<String>) =
+        timed(pipe.query_async(&mut conn)).await?;
+    ids.truncate(limit);
+    let mut clients = Vec::new();
+    if !ids.is_empty() {
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            pipe.hgetall(client_key(id));
+        }
+        let counts: Vec<HashMap<String, String>> = timed(pipe.query_async(&mut conn)).await?;
+        clients = ids
+            .into_iter()
+            .zip(counts)
+            .map(|(client_id, hash)| ClientCount {
+                client_id,
+                count: count(&hash),
+            })
+            .collect();
+        clients.sort_by(busiest);
+    }
+    let since = total
+        .get("since")
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map_or_else(Utc::now, |s| s.with_timezone(&Utc));
+    Some(Snapshot {
+        source: "redis",
+        since,
+        total: count(&total),
+        clients,
+    })
+}
@@ -0,0 +1,367 @@
+//! Certificate transparency cross-check (`--ct-lookups`). When a detection
+//! flags a domain (beaconing, exfiltration, homograph and newly registered
+//! domains by default, `--ct-event-type`), the certificates logged for it
+//! are looked up on crt.sh in the background and summarized in a comment on
+//! the event's case: how many, since when, by which issuers and for how
+//! many names. A domain whose first certificate is days old, or that never
+//! had one, is more likely throwaway infrastructure than an established
+//! site.
+//!
+//! Summaries are cached per domain for [`CACHE_TTL`], so a burst of events
+//! for the same domain makes one lookup; at most [`MAX_PENDING`] domains are
+//! looked up at once and events for further ones are left alone.
+
+use crate::handlers::common::domain_from_url;
+use crate::metrics;
+use crate::rdap::registered_domain;
+use crate::triage::TriageStore;
+use crate::types::ExtensionEvent;
+use actix_web::web;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// `--ct-url` default.
+pub const DEFAULT_URL: &str = "https://crt.sh/";
+/// `--ct-event-type` when none is given.
+pub const DEFAULT_EVENT_TYPES: &[&str] = &[
+    "beaconing_detection",
+    "data_exfiltration_suspected",
+    "homograph_domain",
+    "newly_registered_domain",
+];
+/// Author of the summary comments.
+pub const AUTHOR: &str = "ct-check";
+pub const CACHE_TTL: Duration = Duration::from_secs(24 * 3600);
+/// crt.sh is slow on busy domains.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MAX_PENDING: usize = 32;
+const MAX_CACHED: usize = 10_000;
+/// A first certificate younger than this is called out in the summary.
+const RECENT_DAYS: i64 = 30;
+
+static CT: OnceLock<web::Data<CtLookups>> = OnceLock::new();
+
+/// One crt.sh result row.
+#[derive(Debug, Deserialize)]
+struct CrtShEntry {
+    #[serde(default)]
+    issuer_name: String,
+    #[serde(default)]
+    name_value: String,
+    not_before: Option<String>,
+    serial_number: Option<String>,
+    id: Option<u64>,
+}
+
+/// crt.sh timestamps are UTC without an offset.
+fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// The `O=` of an issuer DN, or its `CN=`.
+fn issuer_org(dn: &str) -> String {
+    let part = |key: &str| {
+        dn.split(',')
+            .map(str::trim)
+            .find_map(|p| p.strip_prefix(key))
+            .map(|v| v.trim_matches('"').to_string())
+    };
+    part("O=")
+        .or_else(|| part("CN="))
+        .unwrap_or_else(|| dn.to_string())
+}
+
+/// What the certificate transparency logs say about a domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CtSummary {
+    pub domain: String,
+    pub certificates: usize,
+    pub first_issued: Option<DateTime<Utc>>,
+    pub last_issued: Option<DateTime<Utc>>,
+    pub issuers: BTreeSet<String>,
+    pub names: usize,
+    pub wildcard: bool,
+}
+
+impl CtSummary {
+    /// Summarizes a crt.sh JSON answer; precertificates and their
+    /// certificates are counted once.
+    pub fn from_crtsh(domain: &str, answer: &serde_json::Value) -> Result<Self, String> {
+        let entries: Vec<CrtShEntry> =
+            serde_json::from_value(answer.clone()).map_err(|e| e.to_string())?;
+        let mut serials = BTreeSet::new();
+        let mut names = BTreeSet::new();
+        let mut summary = CtSummary {
+            domain: domain.to_string(),
+            certificates: 0,
+            first_issued: None,
+            last_issued: None,
+            issuers: BTreeSet::new(),
+            names: 0,
+            wildcard: false,
+        };
+        for entry in entries {
+            let serial = entry
+                .serial_number
+                .clone()
+                .unwrap_or_else(|| entry.id.unwrap_or_default().to_string());
+            if !serials.insert(serial) {
+                continue;
+            }
+            summary.certificates += 1;
+            if !entry.issuer_name.is_empty() {
+                summary.issuers.insert(issuer_org(&entry.issuer_name));
+            }
+            for name in entry
+                .name_value
+                .lines()
+                .map(|n| n.trim().to_ascii_lowercase())
+            {
+                summary.wildcard |= name.starts_with("*.");
+                if !name.is_empty() {
+                    names.insert(name);
+                }
+            }
+            if let Some(issued) = entry.not_before.as_deref().and_then(parse_timestamp) {
+                summary.first_issued = Some(summary.first_issued.map_or(issued, |t| t.min(issued)));
+                summary.last_issued = Some(summary.last_issued.map_or(issued, |t| t.max(issued)));
+            }
+        }
+        summary.names = names.len();
+        Ok(summary)
+    }
+
+    /// Days since the first certificate was issued.
+    pub fn age_days(&self) -> Option<i64> {
+        self.first_issued
+            .map(|t| (Utc::now() - t).num_days().max(0))
+    }
+
+    /// The comment left on the case.
+    pub fn describe(&self) -> String {
+        if self.certificates == 0 {
+            return format!(
+                "Certificate transparency: no certificate was ever logged for {}, so it has \
+                 never served HTTPS with a publicly trusted certificate.",
+                self.domain
+            );
+        }
+        let mut text = format!(
+            "Certificate transparency: {} certificate{} logged for {}",
+            self.certificates,
+            if self.certificates == 1 { "" } else { "s" },
+            self.domain
+        );
+        if let (Some(first), Some(last)) = (self.first_issued, self.last_issued) {
+            text.push_str(&format!(
+                ", first issued {}, latest {}",
+                first.date_naive(),
+                last.date_naive()
+            ));
+        }
+        text.push_str(&format!(
+            ". Issuers: {}. {} name{}{}.",
+            self.issuers.iter().cloned().collect::<Vec<_>>().join(", "),
+            self.names,
+            if self.names == 1 { "" } else { "s" },
+            if self.wildcard {
+                " including a wildcard"
+            } else {
+                ""
+            }
+        ));
+        if let Some(age) = self.age_days().filter(|age| *age < RECENT_DAYS) {
+            text.push_str(&format!(
+                " The first certificate is only {} day{} old: likely new infrastructure.",
+                age,
+                if age == 1 { "" } else { "s" }
+            ));
+        }
+        text
+    }
+}
+
+struct Cached {
+    /// The comment.
+    summary: String,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Cache {
+    answers: HashMap<String, Cached>,
+    /// Domains being looked up, with the events waiting for them.
+    pending: HashMap<String, Vec<String>>,
+}
+
+pub struct CtLookups {
+    base_url: String,
+    event_types: BTreeSet<String>,
+    client: reqwest::Client,
+    triage: web::Data<TriageStore>,
+    cache: Mutex<Cache>,
+}
+
+impl CtLookups {
+    /// `base_url` is the crt.sh (or compatible) search page; summaries are
+    /// left as comments in `triage`.
+    pub fn new(
+        base_url: &str,
+        event_types: &[String],
+        triage: web::Data<TriageStore>,
+    ) -> Result<Self, String> {
+        reqwest::Url::parse(base_url).map_err(|e| format!("{}: {}", base_url, e))?;
+        Ok(CtLookups {
+            base_url: base_url.to_string(),
+            event_types: event_types
+                .iter()
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            client: reqwest::Client::builder()
+                .timeout(LOOKUP_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?,
+            triage,
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    /// The certificates logged for `domain`, bypassing the cache.
+    pub async fn lookup(&self, domain: &str) -> Result<CtSummary, String> {
+        let resp = self
+            .client
+            .get(&self.base_url)
+            .query(&[("q", domain), ("output", "json")])
+            .send()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("crt.sh answered {}", resp.status()));
+        }
+        let answer: serde_json::Value =
+            resp.json().await.map_err(|e| e.without_url().to_string())?;
+        CtSummary::from_crtsh(domain, &answer)
+    }
+
+    /// The domain `event` flagged, when it is one the cross-check covers.
+    fn flagged_domain(&self, event: &ExtensionEvent) -> Option<String> {
+        if !self.event_types.contains(&event.event_type) {
+            return None;
+        }
+        event.data["host"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| event.data["url"].as_str().and_then(domain_from_url))
+            .and_then(|host| registered_domain(&host))
+    }
+
+    async fn comment(&self, packet_id: &str, summary: &str) {
+        if let Err(e) = self
+            .triage
+            .add_comment(
+                packet_id,
+                None,
+                Some(AUTHOR.to_string()),
+                summary.to_string(),
+            )
+            .await
+        {
+            log::warn!("⚠️ CT summary for event {} not saved: {}", packet_id, e);
+        }
+    }
+}
+
+/// Cross-checks every flagged domain of later security events.
+pub fn install(ct: web::Data<CtLookups>) {
+    let _ = CT.set(ct);
+}
+
+/// Queues the cross-check of the domain `event` flagged, if any.
+pub fn enrich(event: &ExtensionEvent) {
+    let Some(ct) = CT.get() else {
+        return;
+    };
+    let Some(domain) = ct.flagged_domain(event) else {
+        return;
+    };
+    let Some(packet_id) = event.data["packet_id"].as_str().map(str::to_string) else {
+        return;
+    };
+    let cached = {
+        let mut cache = ct.cache.lock().unwrap();
+        let cached = cache
+            .answers
+            .get(&domain)
+            .filter(|c| c.at.elapsed() < CACHE_TTL)
+            .map(|c| c.summary.clone());
+        match cached {
+            Some(summary) => Some(summary),
+            None => {
+                if let Some(waiting) = cache.pending.get_mut(&domain) {
+                    waiting.push(packet_id);
+                    return;
+                }
+                if cache.pending.len() >= MAX_PENDING {
+                    log::debug!("🔏 CT lookup of {} skipped, too many in flight", domain);
+                    return;
+                }
+                cache
+                    .pending
+                    .insert(domain.clone(), vec![packet_id.clone()]);
+                None
+            }
+        }
+    };
+    let ct = ct.clone();
+    actix_web::rt::spawn(async move {
+        if let Some(summary) = cached {
+            ct.comment(&packet_id, &summary).await;
+            return;
+        }
+        metrics::CT_LOOKUPS.inc();
+        let summary = match ct.lookup(&domain).await {
+            Ok(summary) => {
+                log::info!(
+                    "🔏 CT: {} has {} certificates, first {:?}",
+                    domain,
+                    summary.certificates,
+                    summary.first_issued
+                );
+                Ok(summary.describe())
+            }
+            Err(e) => {
+                metrics::CT_FAILURES.inc();
+                log::warn!("⚠️ CT lookup of {} failed: {}", domain, e);
+                Err(e)
+            }
+        };
+        let waiting = {
+            let mut cache = ct.cache.lock().unwrap();
+            if cache.answers.len() >= MAX_CACHED {
+                cache.answers.retain(|_, c| c.at.elapsed() < CACHE_TTL);
+            }
+            // Failures aren't cached: the next event for the domain retries.
+            if let Ok(summary) = &summary {
+                cache.answers.insert(
+                    domain.clone(),
+                    Cached {
+                        summary: summary.clone(),
+                        at: Instant::now(),
+                    },
+                );
+            }
+            cache.pending.remove(&domain).unwrap_or_default()
+        };
+        if let Ok(summary) = summary {
+            for packet_id in waiting {
+                ct.comment(&packet_id, &summary).await;
+            }
+        }
+    });
+}
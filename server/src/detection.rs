@@ -0,0 +1,1027 @@
+//! Detection rules: conditions written by an admin and checked against
+//! every network log and extension event as it is ingested. A match raises
+//! a `detection_rule_match` security event, which goes through the alert
+//! rules like the built-in detectors' findings.
+//!
+//! ```text
+//! alert when url matches /pastebin\.com/ and method == "POST"
+//! alert on events when event_type == clipboard_read and not (data.length < 1000) severity 80
+//! ```
+//!
+//! A rule checks `logs` (the default) or `events`. A condition compares a
+//! field with a value: `==`, `!=`, `>`, `>=`, `<`, `<=`, `contains`,
+//! `starts_with`, `ends_with`, or `matches` and a `/regex/`. Conditions
+//! combine with `and`, `or`, `not` and parentheses; a condition on a field
+//! the log or event doesn't have is false. `severity` (0 to 100, default
+//! 50) is the risk score of the events a rule raises.
+//!
+//! A log batch raises one event per matching rule, however many of its
+//! requests match. Every change to a rule's source or switch is a new
+//! version, kept with who made it and when; with `--detection-rules` the
+//! rules and all their versions are kept in that file.
+
+use crate::error::ApiError;
+use crate::handlers::common::domain_from_url;
+use crate::types::{ExtensionEvent, LogEntry, NetworkLog};
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The event type of what a rule raises.
+pub const MATCH_EVENT: &str = "detection_rule_match";
+pub const DEFAULT_SEVERITY: u8 = 50;
+pub const MAX_SOURCE_LEN: usize = 2000;
+/// Matching URLs listed in the event for one batch.
+const SAMPLE_URLS: usize = 5;
+/// Compiled size limit of a rule's regex.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// Stored logs or events a test over a time range reads, newest first.
+pub const MAX_TEST_RECORDS: usize = 50_000;
+/// Matches a test over a time range lists.
+const MAX_TEST_MATCHES: usize = 100;
+
+/// What a rule is checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Logs,
+    Events,
+}
+
+/// A network log with its batch, or an event.
+#[derive(Debug, Clone, Copy)]
+pub enum Subject<'a> {
+    Log(&'a LogEntry, &'a NetworkLog),
+    Event(&'a ExtensionEvent),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    ClientId,
+    ProfileId,
+    SessionId,
+    UserAgent,
+    /// The log's URL, or an event's `data.url`.
+    Url,
+    /// The host of [`Field::Url`].
+    Domain,
+    /// The log's domain category, or an event's `data.category`.
+    Category,
+    Method,
+    Type,
+    Blocked,
+    BlockReason,
+    Reputation,
+    RequestSize,
+    ResponseSize,
+    EventType,
+    /// `data.detection.riskScore`, or `data.severity`.
+    Severity,
+    /// `data.<key>.<key>...`
+    Data(Vec<String>),
+}
+
+const LOG_FIELDS: &str = "client_id, profile_id, session_id, user_agent, url, domain, category, method, type, blocked, block_reason, reputation, request_size, response_size";
+const EVENT_FIELDS: &str = "client_id, profile_id, session_id, user_agent, url, domain, category, event_type, severity, data.<key>";
+
+impl Field {
+    fn parse(name: &str, target: Target) -> Result<Field, String> {
+        let field = match name {
+            "client_id" => Some(Field::ClientId),
+            "profile_id" => Some(Field::ProfileId),
+            "session_id" => Some(Field::SessionId),
+            "user_agent" => Some(Field::UserAgent),
+            "url" => Some(Field::Url),
+            "domain" => Some(Field::Domain),
+            "category" => Some(Field::Category),
+            _ if target == Target::Logs => match name {
+                "method" => Some(Field::Method),
+                "type" => Some(Field::Type),
+                "blocked" => Some(Field::Blocked),
+                "block_reason" => Some(Field::BlockReason),
+                "reputation" => Some(Field::Reputation),
+                "request_size" => Some(Field::RequestSize),
+                "response_size" => Some(Field::ResponseSize),
+                _ => None,
+            },
+            "event_type" => Some(Field::EventType),
+            "severity" => Some(Field::Severity),
+            _ => name
+                .strip_prefix("data.")
+                .filter(|path| !path.split('.').any(str::is_empty))
+                .map(|path| Field::Data(path.split('.').map(str::to_string).collect())),
+        };
+        field.ok_or_else(|| match target {
+            Target::Logs => format!("unknown log field '{}' ({})", name, LOG_FIELDS),
+            Target::Events => format!("unknown event field '{}' ({})", name, EVENT_FIELDS),
+        })
+    }
+
+    fn lookup(&self, subject: Subject) -> Option<Value> {
+        let text = |s: &str| Some(Value::String(s.to_string()));
+        match subject {
+            Subject::Log(entry, log) => match self {
+                Field::ClientId => entry.client_id.as_deref().and_then(text),
+                Field::ProfileId => entry.profile_id.as_deref().and_then(text),
+                Field::SessionId => text(&entry.session_id),
+                Field::UserAgent => text(&entry.user_agent),
+                Field::Url => text(&log.url),
+                Field::Domain => domain_from_url(&log.url).map(Value::String),
+                Field::Category => log.category.as_deref().and_then(text),
+                Field::Method => text(&log.method),
+                Field::Type => text(&log.request_type),
+                Field::Blocked => Some(Value::Bool(log.blocked)),
+                Field::BlockReason => log.block_reason.as_deref().and_then(text),
+                Field::Reputation => log.reputation_score.map(Value::from),
+                Field::RequestSize => log.request_size.map(Value::from),
+                Field::ResponseSize => log.response_size.map(Value::from),
+                Field::EventType | Field::Severity | Field::Data(_) => None,
+            },
+            Subject::Event(event) => match self {
+                Field::ClientId => event.client_id.as_deref().and_then(text),
+                Field::ProfileId => event.profile_id.as_deref().and_then(text),
+                Field::SessionId => text(&event.session_id),
+                Field::UserAgent => text(&event.user_agent),
+                Field::Url => event.data.get("url").cloned(),
+                Field::Domain => event
+                    .data
+                    .get("url")
+                    .and_then(Value::as_str)
+                    .and_then(domain_from_url)
+                    .map(Value::String),
+                Field::Category => event.data.get("category").cloned(),
+                Field::EventType => text(&event.event_type),
+                Field::Severity => crate::alerts::severity(event).map(Value::from),
+                Field::Data(path) => path
+                    .iter()
+                    .try_fold(&event.data, |v, key| v.get(key))
+                    .cloned(),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Matches,
+}
+
+impl Op {
+    fn parse(token: &Token) -> Option<Op> {
+        Some(match token {
+            Token::Symbol("==") => Op::Eq,
+            Token::Symbol("!=") => Op::Ne,
+            Token::Symbol(">") => Op::Gt,
+            Token::Symbol(">=") => Op::Ge,
+            Token::Symbol("<") => Op::Lt,
+            Token::Symbol("<=") => Op::Le,
+            Token::Word(w) => match w.as_str() {
+                "contains" => Op::Contains,
+                "starts_with" => Op::StartsWith,
+                "ends_with" => Op::EndsWith,
+                "matches" => Op::Matches,
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    Pattern(Regex),
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: Field,
+    op: Op,
+    value: Literal,
+}
+
+impl Condition {
+    fn matches(&self, subject: Subject) -> bool {
+        let Some(actual) = self.field.lookup(subject).filter(|v| !v.is_null()) else {
+            return false;
+        };
+        let number = actual
+            .as_f64()
+            .or_else(|| actual.as_str().and_then(|s| s.parse().ok()));
+        let text = match &actual {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let equal = || match &self.value {
+            Literal::Number(n) => number == Some(*n),
+            Literal::Bool(b) => actual.as_bool() == Some(*b) || text == b.to_string(),
+            Literal::Text(t) => text == *t,
+            Literal::Pattern(_) => false,
+        };
+        let compare = |f: fn(f64, f64) -> bool| match (number, &self.value) {
+            (Some(a), Literal::Number(b)) => f(a, *b),
+            _ => false,
+        };
+        let with_text = |f: fn(&str, &str) -> bool| match &self.value {
+            Literal::Text(t) => f(&text, t),
+            _ => false,
+        };
+        match self.op {
+            Op::Eq => equal(),
+            Op::Ne => !equal(),
+            Op::Gt => compare(|a, b| a > b),
+            Op::Ge => compare(|a, b| a >= b),
+            Op::Lt => compare(|a, b| a < b),
+            Op::Le => compare(|a, b| a <= b),
+            Op::Contains => with_text(|a, b| a.contains(b)),
+            Op::StartsWith => with_text(|a, b| a.starts_with(b)),
+            Op::EndsWith => with_text(|a, b| a.ends_with(b)),
+            Op::Matches => match &self.value {
+                Literal::Pattern(re) => re.is_match(&text),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+}
+
+impl Expr {
+    fn matches(&self, subject: Subject) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(subject) && b.matches(subject),
+            Expr::Or(a, b) => a.matches(subject) || b.matches(subject),
+            Expr::Not(e) => !e.matches(subject),
+            Expr::Condition(c) => c.matches(subject),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// A double-quoted string.
+    Text(String),
+    /// A `/regex/`.
+    Pattern(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => f.write_str(w),
+            Token::Text(t) => write!(f, "\"{}\"", t),
+            Token::Pattern(p) => write!(f, "/{}/", p),
+            Token::Symbol(s) => f.write_str(s),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    const SYMBOLS: [&str; 8] = ["==", "!=", ">=", "<=", ">", "<", "(", ")"];
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c == '"' || c == '/' {
+            // `\"` in a string and `\/` in a regex stand for the delimiter;
+            // other escapes in a regex are the regex's.
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, d)) if d == c => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, d)) if d == c || (c == '"' && d == '\\') => value.push(d),
+                        Some((_, d)) => {
+                            value.push('\\');
+                            value.push(d);
+                        }
+                        None => value.push('\\'),
+                    },
+                    Some((_, d)) => value.push(d),
+                    None if c == '"' => return Err("unterminated string".into()),
+                    None => return Err("unterminated /regex/".into()),
+                }
+            };
+            tokens.push(if c == '"' {
+                Token::Text(value)
+            } else {
+                Token::Pattern(value)
+            });
+            rest = &rest[end..];
+        } else if c == '=' || c == '!' {
+            return Err(format!("unexpected '{}' (compare with == or !=)", c));
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()\"=!<>".contains(c))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    target: Target,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn expect_word(&mut self, word: &str, context: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Word(w)) if w == word => Ok(()),
+            Some(other) => Err(format!(
+                "expected '{}' {}, found '{}'",
+                word, context, other
+            )),
+            None => Err(format!("expected '{}' {}", word, context)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.at_word("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.at_word("and") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.at_word("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek() == Some(&Token::Symbol("(")) {
+            self.pos += 1;
+            let expr = self.or()?;
+            if self.next() != Some(Token::Symbol(")")) {
+                return Err("expected ')'".into());
+            }
+            return Ok(expr);
+        }
+        self.condition().map(Expr::Condition)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let field = match self.next() {
+            Some(Token::Word(w)) => Field::parse(&w, self.target)?,
+            Some(other) => return Err(format!("expected a field, found '{}'", other)),
+            None => return Err("expected a condition".into()),
+        };
+        let op = self.next().ok_or("expected an operator")?;
+        let op = Op::parse(&op).ok_or_else(|| {
+            format!(
+                "unknown operator '{}' (==, !=, >, >=, <, <=, contains, starts_with, ends_with, matches)",
+                op
+            )
+        })?;
+        let value = match self.next().ok_or("expected a value")? {
+            Token::Pattern(p) => Literal::Pattern(
+                RegexBuilder::new(&p)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| format!("bad regex /{}/: {}", p, e))?,
+            ),
+            Token::Text(t) => Literal::Text(t),
+            Token::Word(w) => match w.as_str() {
+                "true" => Literal::Bool(true),
+                "false" => Literal::Bool(false),
+                _ => match w.parse::<f64>() {
+                    Ok(n) => Literal::Number(n),
+                    Err(_) => Literal::Text(w),
+                },
+            },
+            Token::Symbol(s) => return Err(format!("expected a value, found '{}'", s)),
+        };
+        let fits = match (op, &value) {
+            (Op::Matches, Literal::Pattern(_)) => true,
+            (Op::Matches, _) => return Err("'matches' takes a /regex/".into()),
+            (_, Literal::Pattern(_)) => return Err("a /regex/ goes with 'matches'".into()),
+            (Op::Gt | Op::Ge | Op::Lt | Op::Le, value) => matches!(value, Literal::Number(_)),
+            _ => true,
+        };
+        if !fits {
+            return Err("'>', '>=', '<' and '<=' need a number to compare with".into());
+        }
+        if let (Op::Contains | Op::StartsWith | Op::EndsWith, Literal::Number(n)) = (op, &value) {
+            return Ok(Condition {
+                field,
+                op,
+                value: Literal::Text(n.to_string()),
+            });
+        }
+        Ok(Condition { field, op, value })
+    }
+}
+
+/// One parsed rule.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub target: Target,
+    pub severity: u8,
+    expr: Expr,
+}
+
+impl Rule {
+    /// `alert [on logs|events] when <conditions> [severity <0-100>]`.
+    pub fn parse(source: &str) -> Result<Rule, String> {
+        if source.len() > MAX_SOURCE_LEN {
+            return Err(format!("a rule is at most {} bytes", MAX_SOURCE_LEN));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            target: Target::Logs,
+        };
+        parser.expect_word("alert", "to start the rule")?;
+        if parser.at_word("on") {
+            parser.pos += 1;
+            parser.target = match parser.next() {
+                Some(Token::Word(w)) if w == "logs" => Target::Logs,
+                Some(Token::Word(w)) if w == "events" => Target::Events,
+                _ => return Err("expected 'logs' or 'events' after 'on'".into()),
+            };
+        }
+        parser.expect_word("when", "before the conditions")?;
+        let expr = parser.or()?;
+        let mut severity = DEFAULT_SEVERITY;
+        if parser.at_word("severity") {
+            parser.pos += 1;
+            severity = match parser.next() {
+                Some(Token::Word(w)) => w.parse().ok().filter(|s| *s <= 100),
+                _ => None,
+            }
+            .ok_or("severity is a number from 0 to 100")?;
+        }
+        if let Some(extra) = parser.next() {
+            return Err(format!("unexpected '{}' after the conditions", extra));
+        }
+        Ok(Rule {
+            target: parser.target,
+            severity,
+            expr,
+        })
+    }
+
+    pub fn matches(&self, subject: Subject) -> bool {
+        let checks = match subject {
+            Subject::Log(..) => Target::Logs,
+            Subject::Event(_) => Target::Events,
+        };
+        checks == self.target && self.expr.matches(subject)
+    }
+}
+
+/// One version of a rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleVersion {
+    pub version: u32,
+    pub source: String,
+    pub enabled: bool,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// A rule with its history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectionRule {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Oldest first; the last is in effect.
+    pub versions: Vec<RuleVersion>,
+}
+
+impl DetectionRule {
+    pub fn current(&self) -> &RuleVersion {
+        self.versions.last().expect("a rule has a version")
+    }
+
+    pub fn version(&self, version: u32) -> Option<&RuleVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    /// The rule as the API lists it; `with_versions` adds the history.
+    pub fn view(&self, with_versions: bool) -> Value {
+        let current = self.current();
+        let parsed = Rule::parse(&current.source).ok();
+        let mut view = serde_json::json!({
+            "id": self.id,
+            "description": self.description,
+            "version": current.version,
+            "source": current.source,
+            "enabled": current.enabled,
+            "target": parsed.as_ref().map(|r| r.target),
+            "severity": parsed.as_ref().map(|r| r.severity),
+            "changed_by": current.changed_by,
+            "changed_at": current.changed_at
+        });
+        if with_versions {
+            view["versions"] = serde_json::json!(self.versions);
+        }
+        view
+    }
+}
+
+/// `POST /api/rules`.
+#[derive(Debug, Deserialize)]
+pub struct NewRule {
+    pub id: String,
+    pub description: Option<String>,
+    pub source: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// `PUT /api/rules/{id}`: what changes.
+#[derive(Debug, Default, Deserialize)]
+pub struct RuleChange {
+    pub description: Option<String>,
+    pub source: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// Why a new rule's id or description can't be used, if they can't.
+fn validate(id: &str, description: Option<&str>) -> Result<(), String> {
+    let valid_id = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-".contains(&b));
+    if !valid_id {
+        return Err("ids are up to 64 lowercase letters, digits, '_' and '-'".to_string());
+    }
+    if description.is_some_and(|d| d.chars().count() > 500) {
+        return Err("description is at most 500 characters".to_string());
+    }
+    Ok(())
+}
+
+/// An enabled rule, as ingest checks it.
+#[derive(Debug, Clone)]
+pub struct ActiveRule {
+    pub id: String,
+    pub version: u32,
+    pub source: String,
+    pub rule: Rule,
+}
+
+/// What one rule matched in a batch or an event.
+#[derive(Debug, Clone)]
+pub struct RuleMatch {
+    pub rule: Arc<ActiveRule>,
+    /// Matching requests of a batch; 1 for an event.
+    pub matches: usize,
+    /// The first few matching URLs.
+    pub urls: Vec<String>,
+    /// The packet id and type of the event that matched.
+    pub event: Option<(Option<String>, String)>,
+}
+
+impl RuleMatch {
+    pub fn to_event_data(&self) -> Value {
+        let mut data = serde_json::json!({
+            "url": self.urls.first(),
+            "host": self.urls.first().and_then(|u| domain_from_url(u)),
+            "rule_id": self.rule.id,
+            "rule_version": self.rule.version,
+            "rule": self.rule.source,
+            "target": self.rule.rule.target,
+            "matches": self.matches,
+            "urls": self.urls,
+            "detection": { "riskScore": self.rule.rule.severity }
+        });
+        if let Some((packet_id, event_type)) = &self.event {
+            data["source_packet_id"] = serde_json::json!(packet_id);
+            data["source_event_type"] = serde_json::json!(event_type);
+        }
+        data
+    }
+}
+
+#[derive(Default)]
+struct State {
+    rules: BTreeMap<String, DetectionRule>,
+    active: Arc<Vec<Arc<ActiveRule>>>,
+}
+
+impl State {
+    fn new(rules: BTreeMap<String, DetectionRule>) -> Result<State, String> {
+        let mut active = Vec::new();
+        for rule in rules.values() {
+            let current = rule.current();
+            let parsed = Rule::parse(&current.source)
+                .map_err(|e| format!("rule {} v{}: {}", rule.id, current.version, e))?;
+            if current.enabled {
+                active.push(Arc::new(ActiveRule {
+                    id: rule.id.clone(),
+                    version: current.version,
+                    source: current.source.clone(),
+                    rule: parsed,
+                }));
+            }
+        }
+        Ok(State {
+            rules,
+            active: Arc::new(active),
+        })
+    }
+}
+
+/// The detection rules, and the enabled ones compiled for ingest.
+#[derive(Default)]
+pub struct DetectionRules {
+    path: Option<PathBuf>,
+    state: RwLock<State>,
+}
+
+impl DetectionRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the rules in `path`, starting from the ones already there.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let rules: Vec<DetectionRule> = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
+        if rules.iter().any(|r| r.versions.is_empty()) {
+            return Err(format!("{}: a rule without versions", path.display()));
+        }
+        let rules = rules.into_iter().map(|r| (r.id.clone(), r)).collect();
+        Ok(DetectionRules {
+            path: Some(path.to_path_buf()),
+            state: RwLock::new(State::new(rules)?),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn list(&self) -> Vec<DetectionRule> {
+        self.state.read().unwrap().rules.values().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<DetectionRule> {
+        self.state.read().unwrap().rules.get(id).cloned()
+    }
+
+    /// The enabled rules.
+    pub fn active(&self) -> Arc<Vec<Arc<ActiveRule>>> {
+        self.state.read().unwrap().active.clone()
+    }
+
+    fn persist(&self, rules: &BTreeMap<String, DetectionRule>) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let list: Vec<&DetectionRule> = rules.values().collect();
+            let mut text = serde_json::to_vec_pretty(&list).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `change` to a copy of the rules, and makes the copy current
+    /// once it compiles and is written.
+    fn modify<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, DetectionRule>) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let mut state = self.state.write().unwrap();
+        let mut rules = state.rules.clone();
+        let result = change(&mut rules)?;
+        let next = State::new(rules).map_err(ApiError::BadRequest)?;
+        self.persist(&next.rules).map_err(|e| {
+            log::error!("❌ Writing detection rules failed: {}", e);
+            ApiError::Storage(e.to_string())
+        })?;
+        *state = next;
+        Ok(result)
+    }
+
+    pub fn create(&self, new: NewRule, changed_by: &str) -> Result<DetectionRule, ApiError> {
+        validate(&new.id, new.description.as_deref())
+            .map_err(|e| ApiError::BadRequest(e).with("rule", &new.id))?;
+        Rule::parse(&new.source).map_err(|e| ApiError::BadRequest(e).with("rule", &new.id))?;
+        self.modify(|rules| {
+            if rules.contains_key(&new.id) {
+                return Err(
+                    ApiError::Conflict(format!("rule {} already exists", new.id))
+                        .with("rule", &new.id),
+                );
+            }
+            let rule = DetectionRule {
+                id: new.id.clone(),
+                description: new.description,
+                versions: vec![RuleVersion {
+                    version: 1,
+                    source: new.source.trim().to_string(),
+                    enabled: new.enabled,
+                    changed_by: changed_by.to_string(),
+                    changed_at: Utc::now(),
+                }],
+            };
+            rules.insert(new.id, rule.clone());
+            Ok(rule)
+        })
+    }
+
+    /// A new version with the changed source or switch; a change of the
+    /// description alone doesn't make one. `Ok(None)` for an unknown id.
+    pub fn update(
+        &self,
+        id: &str,
+        change: RuleChange,
+        changed_by: &str,
+    ) -> Result<Option<DetectionRule>, ApiError> {
+        validate(id, change.description.as_deref())
+            .map_err(|e| ApiError::BadRequest(e).with("rule", id))?;
+        if let Some(source) = &change.source {
+            Rule::parse(source).map_err(|e| ApiError::BadRequest(e).with("rule", id))?;
+        }
+        self.modify(|rules| {
+            let Some(rule) = rules.get_mut(id) else {
+                return Ok(None);
+            };
+            if let Some(description) = change.description {
+                rule.description = Some(description).filter(|d| !d.is_empty());
+            }
+            let current = rule.current();
+            let source = change
+                .source
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|| current.source.clone());
+            let enabled = change.enabled.unwrap_or(current.enabled);
+            if source != current.source || enabled != current.enabled {
+                let version = current.version + 1;
+                rule.versions.push(RuleVersion {
+                    version,
+                    source,
+                    enabled,
+                    changed_by: changed_by.to_string(),
+                    changed_at: Utc::now(),
+                });
+            }
+            Ok(Some(rule.clone()))
+        })
+    }
+
+    /// Makes the source of an earlier version current again, as a new
+    /// version. `Ok(None)` for an unknown id.
+    pub fn restore(
+        &self,
+        id: &str,
+        version: u32,
+        changed_by: &str,
+    ) -> Result<Option<DetectionRule>, ApiError> {
+        let Some(rule) = self.get(id) else {
+            return Ok(None);
+        };
+        let old = rule.version(version).ok_or_else(|| {
+            ApiError::NotFound(format!("rule {} has no version {}", id, version)).with("rule", id)
+        })?;
+        let change = RuleChange {
+            description: None,
+            source: Some(old.source.clone()),
+            enabled: Some(old.enabled),
+        };
+        self.update(id, change, changed_by)
+    }
+
+    /// Whether there was such a rule.
+    pub fn delete(&self, id: &str) -> Result<bool, ApiError> {
+        self.modify(|rules| Ok(rules.remove(id).is_some()))
+    }
+
+    /// The enabled log rules that match requests of `entry`.
+    pub fn check_logs(&self, entry: &LogEntry) -> Vec<RuleMatch> {
+        match_logs(&self.active(), entry)
+    }
+
+    /// The enabled event rules that match `event`.
+    pub fn check_event(&self, event: &ExtensionEvent) -> Vec<RuleMatch> {
+        match_event(&self.active(), event)
+    }
+}
+
+/// The log rules of `rules` that match requests of `entry`.
+pub fn match_logs(rules: &[Arc<ActiveRule>], entry: &LogEntry) -> Vec<RuleMatch> {
+    let mut found = Vec::new();
+    for rule in rules.iter().filter(|r| r.rule.target == Target::Logs) {
+        let matching: Vec<&NetworkLog> = entry
+            .logs
+            .iter()
+            .filter(|log| rule.rule.matches(Subject::Log(entry, log)))
+            .collect();
+        if matching.is_empty() {
+            continue;
+        }
+        found.push(RuleMatch {
+            rule: rule.clone(),
+            matches: matching.len(),
+            urls: matching
+                .iter()
+                .take(SAMPLE_URLS)
+                .map(|l| l.url.clone())
+                .collect(),
+            event: None,
+        });
+    }
+    found
+}
+
+/// The event rules of `rules` that match `event`.
+pub fn match_event(rules: &[Arc<ActiveRule>], event: &ExtensionEvent) -> Vec<RuleMatch> {
+    rules
+        .iter()
+        .filter(|r| r.rule.matches(Subject::Event(event)))
+        .map(|rule| RuleMatch {
+            rule: rule.clone(),
+            matches: 1,
+            urls: event
+                .data
+                .get("url")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+            event: Some((
+                event
+                    .data
+                    .get("packet_id")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                event.event_type.clone(),
+            )),
+        })
+        .collect()
+}
+
+/// When a stored log or event was sent: RFC 3339, or PostgreSQL's text form
+/// of a timestamp (`2026-01-01 12:00:00.5+00`).
+pub fn sent_at(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f%#z"))
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// What a rule matched among stored logs or events sent in `[from, to)`.
+#[derive(Debug, Serialize)]
+pub struct HistoryTest {
+    #[serde(skip)]
+    from: DateTime<Utc>,
+    #[serde(skip)]
+    to: DateTime<Utc>,
+    /// Logs or events in the range that were checked.
+    pub checked: usize,
+    pub matched: usize,
+    /// The newest matches, at most [`MAX_TEST_MATCHES`].
+    pub matches: Vec<Value>,
+    /// Whether there may be logs or events in the range that weren't
+    /// checked, past [`MAX_TEST_RECORDS`].
+    pub truncated: bool,
+}
+
+impl HistoryTest {
+    pub fn new(from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        HistoryTest {
+            from,
+            to,
+            checked: 0,
+            matched: 0,
+            matches: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn in_range(&self, timestamp: &str) -> bool {
+        sent_at(timestamp).is_some_and(|at| at >= self.from && at < self.to)
+    }
+
+    fn full(&mut self) -> bool {
+        if self.checked >= MAX_TEST_RECORDS {
+            self.truncated = true;
+        }
+        self.truncated
+    }
+
+    fn matched(&mut self, record: Value) {
+        self.matched += 1;
+        if self.matches.len() < MAX_TEST_MATCHES {
+            self.matches.push(record);
+        }
+    }
+
+    /// Checks the requests of `entries`, newest first.
+    pub fn check_logs(&mut self, rule: &Rule, entries: &[LogEntry]) {
+        for entry in entries.iter().rev() {
+            if !self.in_range(&entry.timestamp) {
+                continue;
+            }
+            for log in entry.logs.iter().rev() {
+                if self.full() {
+                    return;
+                }
+                self.checked += 1;
+                if rule.matches(Subject::Log(entry, log)) {
+                    self.matched(serde_json::json!({
+                        "timestamp": entry.timestamp,
+                        "client_id": entry.client_id,
+                        "session_id": entry.session_id,
+                        "request_id": log.request_id,
+                        "url": log.url,
+                        "method": log.method
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Checks `events`, newest first.
+    pub fn check_events(&mut self, rule: &Rule, events: &[ExtensionEvent]) {
+        for event in events.iter().rev() {
+            if !self.in_range(&event.timestamp) {
+                continue;
+            }
+            if self.full() {
+                return;
+            }
+            self.checked += 1;
+            if rule.matches(Subject::Event(event)) {
+                self.matched(serde_json::json!({
+                    "timestamp": event.timestamp,
+                    "client_id": event.client_id,
+                    "session_id": event.session_id,
+                    "packet_id": event.data.get("packet_id"),
+                    "event_type": event.event_type,
+                    "url": event.data.get("url")
+                }));
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Approximate distinct counts of the URLs, domains, sessions and clients
+//! seen on `/api/logs`, for `/api/stats`. A HyperLogLog sketch per set
+//! takes 16 KB whatever the number of values, where an exact count would
+//! keep every value; the price is an estimate with a standard error of
+//! [`STANDARD_ERROR`].
+//!
+//! Counts run from server start, plus a second set of sketches for the
+//! current UTC day. With Redis (production and hybrid modes, `--redis-url`)
+//! every replica also adds to shared Redis HyperLogLogs (`PFADD`), which
+//! `/api/stats` reads when they are reachable, so the numbers cover the
+//! whole deployment and survive restarts.
+
+use crate::handlers::common::domain_from_url;
+use crate::types::LogEntry;
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+/// Register index bits: 2^14 one-byte registers.
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// Relative standard error of an estimate, 1.04 / sqrt(2^14); Redis uses
+/// the same precision.
+pub const STANDARD_ERROR: f64 = 0.0081;
+
+/// How long Redis keeps a day's sketches.
+#[cfg(feature = "production")]
+const DAY_KEY_TTL_SECS: i64 = 8 * 86_400;
+
+/// A HyperLogLog sketch (Flajolet et al.) with linear counting for small
+/// cardinalities.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Box<[u8]>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS].into_boxed_slice(),
+        }
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        // SipHash with fixed keys: the same value always lands in the same
+        // register.
+        let mut hasher = std::hash::DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - PRECISION)) as usize;
+        let rest = hash << PRECISION;
+        let rank = (rest.leading_zeros().min(64 - PRECISION) + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// The estimated number of distinct values inserted.
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sketches {
+    urls: HyperLogLog,
+    domains: HyperLogLog,
+    sessions: HyperLogLog,
+    clients: HyperLogLog,
+}
+
+impl Sketches {
+    fn counts(&self) -> Counts {
+        Counts {
+            urls: self.urls.count(),
+            domains: self.domains.count(),
+            sessions: self.sessions.count(),
+            clients: self.clients.count(),
+        }
+    }
+}
+
+/// Estimated distinct values in one window.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Counts {
+    pub urls: u64,
+    pub domains: u64,
+    pub sessions: u64,
+    pub clients: u64,
+}
+
+/// The `distinct` object of `/api/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub approximate: bool,
+    /// `memory` (this replica since it started) or `redis`.
+    pub source: &'static str,
+    pub standard_error: f64,
+    pub note: String,
+    pub day: NaiveDate,
+    pub today: Counts,
+    pub total: Counts,
+}
+
+/// The distinct values of one batch, as Redis `PFADD` arguments.
+struct Values {
+    urls: Vec<String>,
+    domains: Vec<String>,
+    sessions: Vec<String>,
+    clients: Vec<String>,
+}
+
+impl Values {
+    fn of(entry: &LogEntry) -> Values {
+        let mut urls: Vec<String> = entry.logs.iter().map(|l| l.url.clone()).collect();
+        let mut domains: Vec<String> = entry
+            .logs
+            .iter()
+            .filter_map(|l| domain_from_url(&l.url).map(|d| d.to_ascii_lowercase()))
+            .collect();
+        urls.sort();
+        urls.dedup();
+        domains.sort();
+        domains.dedup();
+        Values {
+            urls,
+            domains,
+            sessions: Some(entry.session_id.clone())
+                .filter(|s| !s.is_empty())
+                .into_iter()
+                .collect(),
+            clients: entry
+                .client_id
+                .clone()
+                .filter(|c| !c.is_empty())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+struct Windows {
+    day: NaiveDate,
+    today: Sketches,
+    total: Sketches,
+}
+
+pub struct DistinctCounters {
+    windows: Mutex<Windows>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl Default for DistinctCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DistinctCounters {
+    pub fn new() -> Self {
+        DistinctCounters {
+            windows: Mutex::new(Windows {
+                day: Utc::now().date_naive(),
+                today: Sketches::default(),
+                total: Sketches::default(),
+            }),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Also counts into Redis keys `distinct:<set>` and
+    /// `distinct:<set>:<day>`, shared by every replica.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    /// Adds the URLs, domains, session and client of `entry`.
+    pub fn observe(&self, entry: &LogEntry) {
+        let values = Values::of(entry);
+        {
+            let mut guard = self.windows.lock().unwrap();
+            let windows = &mut *guard;
+            let today = Utc::now().date_naive();
+            if windows.day != today {
+                windows.day = today;
+                windows.today = Sketches::default();
+            }
+            for sketches in [&mut windows.today, &mut windows.total] {
+                let sets = [
+                    (&mut sketches.urls, &values.urls),
+                    (&mut sketches.domains, &values.domains),
+                    (&mut sketches.sessions, &values.sessions),
+                    (&mut sketches.clients, &values.clients),
+                ];
+                for (sketch, values) in sets {
+                    for value in values {
+                        sketch.insert(value);
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "production")]
+        if let Some(redis) = self.redis.clone() {
+            actix_web::rt::spawn(async move {
+                if redis_add(&redis, values).await.is_none() {
+                    log::debug!("Counting distinct values in Redis failed");
+                }
+            });
+        }
+    }
+
+    /// Current estimates, from Redis when it answers.
+    pub async fn snapshot(&self) -> Snapshot {
+        let day = Utc::now().date_naive();
+        let (today, total) = {
+            let windows = self.windows.lock().unwrap();
+            // Nothing was logged yet today if the sketches are yesterday's.
+            let today = match windows.day == day {
+                true => windows.today.counts(),
+                false => Counts::default(),
+            };
+            (today, windows.total.counts())
+        };
+        #[cfg(feature = "production")]
+        let shared = match &self.redis {
+            Some(redis) => redis_counts(redis, day).await,
+            None => None,
+        };
+        #[cfg(not(feature = "production"))]
+        let shared: Option<(Counts, Counts)> = None;
+        let (source, today, total) = match shared {
+            Some((today, total)) => ("redis", today, total),
+            None => ("memory", today, total),
+        };
+        Snapshot {
+            approximate: true,
+            source,
+            standard_error: STANDARD_ERROR,
+            note: format!(
+                "HyperLogLog estimates: typically within {:.1}% of the exact count, almost always within {:.1}%",
+                STANDARD_ERROR * 100.0,
+                STANDARD_ERROR * 300.0
+            ),
+            day,
+            today,
+            total,
+        }
+    }
+}
+
+#[cfg(feature = "production")]
+const SETS: [&str; 4] = ["urls", "domains", "sessions", "clients"];
+
+#[cfg(feature = "production")]
+async fn redis_add(redis: &SharedRedis, values: Values) -> Option<()> {
+    let mut conn = redis.conn().await?;
+    let day = Utc::now().date_naive();
+    let mut pipe = redis::pipe();
+    let sets = [values.urls, values.domains, values.sessions, values.clients];
+    for (name, values) in SETS.iter().zip(sets) {
+        if values.is_empty() {
+            continue;
+        }
+        let day_key = format!("distinct:{}:{}", name, day);
+        pipe.cmd("PFADD")
+            .arg(format!("distinct:{}", name))
+            .arg(&values)
+            .ignore()
+            .cmd("PFADD")
+            .arg(&day_key)
+            .arg(&values)
+            .ignore()
+            .expire(&day_key, DAY_KEY_TTL_SECS)
+            .ignore();
+    }
+    timed(pipe.query_async(&mut conn)).await
+}
+
+#[cfg(feature = "production")]
+async fn redis_counts(redis: &SharedRedis, day: NaiveDate) -> Option<(Counts, Counts)> {
+    let mut conn = redis.conn().await?;
+    let mut pipe = redis::pipe();
+    for name in SETS {
+        pipe.cmd("PFCOUNT")
+            .arg(format!("distinct:{}:{}", name, day));
+    }
+    for name in SETS {
+        pipe.cmd("PFCOUNT").arg(format!("distinct:{}", name));
+    }
+    let n: Vec<u64> = timed(pipe.query_async(&mut conn)).await?;
+    let counts = |n: &[u64]| Counts {
+        urls: n[0],
+        domains: n[1],
+        sessions: n[2],
+        clients: n[3],
+    };
+    Some((counts(&n[0..4]), counts(&n[4..8])))
+}
@@ -0,0 +1,530 @@
+//! Client enrollment (`--enrollment-dir`): batches from a client_id an admin
+//! hasn't approved are answered `202` and set aside in a pending area instead
+//! of being stored, so stray installs and internet noise don't end up in the
+//! dataset. `POST /api/clients/{id}/approve` moves what the client sent so
+//! far into the store and lets it through from then on;
+//! `POST /api/clients/{id}/reject` throws it away.
+//!
+//! `<dir>/enrollment.json` holds the approved and pending clients, and
+//! `<dir>/pending/` one NDJSON file of held records per pending client.
+//! With `--redis-url` the registry is kept in Redis instead, so every replica
+//! holds, approves and rejects the same clients; approvals already in
+//! `enrollment.json` still count.
+
+use crate::error::ApiError;
+use crate::server_events;
+use crate::types::{ExtensionEvent, LogEntry};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use redis::AsyncCommands;
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+/// Clients waiting for approval at once; newcomers past it are refused.
+pub const MAX_PENDING_CLIENTS: usize = 1000;
+/// Data held per pending client; batches past it are refused.
+pub const MAX_PENDING_BYTES: u64 = 10 * 1024 * 1024;
+/// Source IPs remembered per pending client.
+const MAX_IPS: usize = 20;
+const INDEX: &str = "enrollment.json";
+#[cfg(feature = "production")]
+const APPROVED_KEY: &str = "enrollment:approved";
+#[cfg(feature = "production")]
+const PENDING_KEY: &str = "enrollment:pending";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Approval {
+    pub client_id: String,
+    pub approved_at: DateTime<Utc>,
+    pub approved_by: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingClient {
+    pub client_id: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub logs: u64,
+    pub events: u64,
+    pub bytes: u64,
+    pub ips: BTreeSet<String>,
+}
+
+impl PendingClient {
+    fn new(client_id: &str, now: DateTime<Utc>) -> Self {
+        PendingClient {
+            client_id: client_id.to_string(),
+            first_seen: now,
+            last_seen: now,
+            logs: 0,
+            events: 0,
+            bytes: 0,
+            ips: BTreeSet::new(),
+        }
+    }
+
+    /// Refuses `line` when the pending area is full: `pending_clients` are
+    /// waiting already, and this client holds its share.
+    fn admit(&self, newcomer: bool, pending_clients: usize, line: &[u8]) -> Result<(), ApiError> {
+        if newcomer && pending_clients >= MAX_PENDING_CLIENTS {
+            return Err(ApiError::Forbidden(
+                "client is not enrolled, and too many clients are waiting for approval".into(),
+            )
+            .with("client_id", &self.client_id));
+        }
+        if self.bytes + line.len() as u64 > MAX_PENDING_BYTES {
+            return Err(ApiError::Forbidden(
+                "client is not enrolled, and holds as much pending data as it may".into(),
+            )
+            .with("client_id", &self.client_id));
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, record: &HeldRef, line: &[u8], ip: &str, now: DateTime<Utc>) {
+        self.last_seen = now;
+        self.bytes += line.len() as u64;
+        match record {
+            HeldRef::Log(entry) => self.logs += entry.logs.len() as u64,
+            HeldRef::Event(_) => self.events += 1,
+        }
+        if self.ips.len() < MAX_IPS {
+            self.ips.insert(ip.to_string());
+        }
+    }
+}
+
+/// A record as sent, to be stored on approval.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Held {
+    Log(LogEntry),
+    Event(ExtensionEvent),
+}
+
+/// A borrowed [`Held`], written to the pending file.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeldRef<'a> {
+    Log(&'a LogEntry),
+    Event(&'a ExtensionEvent),
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    approved: BTreeMap<String, Approval>,
+    pending: BTreeMap<String, PendingClient>,
+}
+
+pub struct Enrollment {
+    dir: Option<PathBuf>,
+    index: Mutex<Index>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl Default for Enrollment {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+/// Pending files (and lists) are named after a hash, as client_ids can
+/// hold anything.
+fn digest(client_id: &str) -> String {
+    hex::encode(Sha256::digest(client_id.as_bytes()))[..32].to_string()
+}
+
+fn file_name(client_id: &str) -> String {
+    format!("{}.ndjson", digest(client_id))
+}
+
+#[cfg(feature = "production")]
+fn held_key(client_id: &str) -> String {
+    format!("enrollment:held:{}", digest(client_id))
+}
+
+#[cfg(feature = "production")]
+fn redis_unavailable() -> io::Error {
+    io::Error::other("Redis unavailable")
+}
+
+fn by_client<T>(records: impl IntoIterator<Item = (String, T)>) -> Vec<T> {
+    records
+        .into_iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+impl Enrollment {
+    /// Every client is let through.
+    pub fn disabled() -> Self {
+        Enrollment {
+            dir: None,
+            index: Mutex::new(Index::default()),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir.join("pending"))?;
+        let index = match std::fs::read(dir.join(INDEX)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Enrollment {
+            dir: Some(dir.to_path_buf()),
+            index: Mutex::new(index),
+            #[cfg(feature = "production")]
+            redis: None,
+        })
+    }
+
+    /// Keeps the registry in Redis, shared by every replica: approvals in
+    /// hash `enrollment:approved`, pending clients in hash
+    /// `enrollment:pending` and what each sent in list
+    /// `enrollment:held:<hash>`.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    pub async fn is_approved(&self, client_id: &str) -> bool {
+        if !self.is_enabled() || self.index.lock().unwrap().approved.contains_key(client_id) {
+            return true;
+        }
+        #[cfg(feature = "production")]
+        if let Some(approval) = self.redis_approval(client_id).await {
+            // Approvals aren't withdrawn, so this replica can remember it.
+            self.index
+                .lock()
+                .unwrap()
+                .approved
+                .insert(client_id.to_string(), approval);
+            return true;
+        }
+        false
+    }
+
+    fn pending_path(&self, client_id: &str) -> Option<PathBuf> {
+        Some(
+            self.dir
+                .as_ref()?
+                .join("pending")
+                .join(file_name(client_id)),
+        )
+    }
+
+    fn save(&self, index: &Index) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let path = dir.join(INDEX);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(index)?)?;
+        std::fs::rename(&tmp, &path)
+    }
+
+    /// Sets `record` aside for `client_id`, which isn't approved. Refused
+    /// without a client_id, or when the pending area is full.
+    pub async fn hold(
+        &self,
+        client_id: Option<&str>,
+        ip: &str,
+        record: HeldRef<'_>,
+    ) -> Result<PendingClient, ApiError> {
+        let Some(client_id) = client_id.filter(|c| !c.is_empty()) else {
+            return Err(ApiError::Forbidden(
+                "a client_id is required to enroll with this server".into(),
+            ));
+        };
+        let mut line = serde_json::to_vec(&record).map_err(|e| ApiError::Storage(e.to_string()))?;
+        line.push(b'\n');
+        let now = Utc::now();
+        #[cfg(feature = "production")]
+        let shared = match &self.redis {
+            Some(redis) => Some(
+                redis_hold(redis, client_id, ip, &record, &line, now)
+                    .await
+                    .map_err(|e| match e {
+                        HoldError::Refused(e) => e,
+                        HoldError::Redis => {
+                            ApiError::Storage("pending area: Redis unavailable".into())
+                        }
+                    })?,
+            ),
+            None => None,
+        };
+        #[cfg(not(feature = "production"))]
+        let shared: Option<(PendingClient, bool)> = None;
+        let (pending, newcomer) = match shared {
+            Some(shared) => shared,
+            None => self.file_hold(client_id, ip, &record, &line, now)?,
+        };
+
+        if newcomer {
+            log::warn!(
+                "🆕 Unknown client_id={} from IP {} held for approval",
+                client_id,
+                ip
+            );
+            server_events::record(
+                "client_pending",
+                serde_json::json!({ "client_id": client_id, "ip": ip }),
+            );
+        }
+        Ok(pending)
+    }
+
+    /// Appends `line` to the client's pending file; the client as it is now,
+    /// and whether it is new.
+    fn file_hold(
+        &self,
+        client_id: &str,
+        ip: &str,
+        record: &HeldRef,
+        line: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(PendingClient, bool), ApiError> {
+        let path = self
+            .pending_path(client_id)
+            .expect("hold is only called when enabled");
+        let mut index = self.index.lock().unwrap();
+        let existing = index.pending.get(client_id).cloned();
+        let newcomer = existing.is_none();
+        let mut pending = existing.unwrap_or_else(|| PendingClient::new(client_id, now));
+        pending.admit(newcomer, index.pending.len(), line)?;
+        let storage_error = |e: io::Error| ApiError::Storage(format!("pending area: {}", e));
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(storage_error)?;
+        file.write_all(line).map_err(storage_error)?;
+        pending.add(record, line, ip, now);
+        index.pending.insert(client_id.to_string(), pending.clone());
+        self.save(&index).map_err(storage_error)?;
+        Ok((pending, newcomer))
+    }
+
+    /// Marks `client_id` approved and hands back what it sent while pending.
+    /// The records are deleted from the pending area: whatever fails to
+    /// store from here is lost, as with any other ingest.
+    pub async fn approve(&self, client_id: &str, by: &str) -> io::Result<(Approval, Vec<Held>)> {
+        let approval = Approval {
+            client_id: client_id.to_string(),
+            approved_at: Utc::now(),
+            approved_by: by.to_string(),
+        };
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut held = Vec::new();
+        #[cfg(feature = "production")]
+        if let Some(redis) = &self.redis {
+            held = redis_approve(redis, &approval).await?;
+        }
+        let mut index = self.index.lock().unwrap();
+        if index.pending.contains_key(client_id) {
+            let path = self
+                .pending_path(client_id)
+                .expect("pending implies enabled");
+            match std::fs::File::open(&path) {
+                Ok(file) => {
+                    for line in io::BufReader::new(file).lines() {
+                        let line = line?;
+                        match serde_json::from_str(&line) {
+                            Ok(record) => held.push(record),
+                            Err(e) => log::error!("❌ Skipping unreadable pending record: {}", e),
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        index.pending.remove(client_id);
+        index
+            .approved
+            .insert(client_id.to_string(), approval.clone());
+        self.save(&index)?;
+        if let Some(path) = self.pending_path(client_id) {
+            let _ = std::fs::remove_file(path);
+        }
+        Ok((approval, held))
+    }
+
+    /// Drops a pending client and what it sent; `None` if it wasn't pending.
+    pub async fn reject(&self, client_id: &str) -> io::Result<Option<PendingClient>> {
+        #[cfg(feature = "production")]
+        let shared = match &self.redis {
+            Some(redis) => redis_reject(redis, client_id).await?,
+            None => None,
+        };
+        #[cfg(not(feature = "production"))]
+        let shared: Option<PendingClient> = None;
+        let mut index = self.index.lock().unwrap();
+        let local = index.pending.remove(client_id);
+        if local.is_some() {
+            self.save(&index)?;
+            if let Some(path) = self.pending_path(client_id) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        Ok(shared.or(local))
+    }
+
+    /// Approved clients, by client_id.
+    pub async fn approved(&self) -> Vec<Approval> {
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut approved: Vec<_> = {
+            let index = self.index.lock().unwrap();
+            index.approved.clone().into_iter().collect()
+        };
+        #[cfg(feature = "production")]
+        approved.extend(redis_list::<Approval>(self.redis.as_deref(), APPROVED_KEY).await);
+        by_client(approved)
+    }
+
+    /// Pending clients, by client_id.
+    pub async fn pending(&self) -> Vec<PendingClient> {
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut pending: Vec<_> = {
+            let index = self.index.lock().unwrap();
+            index.pending.clone().into_iter().collect()
+        };
+        #[cfg(feature = "production")]
+        pending.extend(redis_list::<PendingClient>(self.redis.as_deref(), PENDING_KEY).await);
+        by_client(pending)
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis_approval(&self, client_id: &str) -> Option<Approval> {
+        let mut conn = self.redis.as_ref()?.conn().await?;
+        let raw: Option<String> = timed(conn.hget(APPROVED_KEY, client_id)).await?;
+        serde_json::from_str(&raw?).ok()
+    }
+}
+
+#[cfg(feature = "production")]
+enum HoldError {
+    Refused(ApiError),
+    Redis,
+}
+
+/// [`Enrollment::file_hold`] in Redis.
+#[cfg(feature = "production")]
+async fn redis_hold(
+    redis: &SharedRedis,
+    client_id: &str,
+    ip: &str,
+    record: &HeldRef<'_>,
+    line: &[u8],
+    now: DateTime<Utc>,
+) -> Result<(PendingClient, bool), HoldError> {
+    let mut conn = redis.conn().await.ok_or(HoldError::Redis)?;
+    let mut pipe = redis::pipe();
+    pipe.hget(PENDING_KEY, client_id).hlen(PENDING_KEY);
+    let (raw, pending_clients): (Option<String>, usize) = timed(pipe.query_async(&mut conn))
+        .await
+        .ok_or(HoldError::Redis)?;
+    let existing = raw.and_then(|raw| serde_json::from_str::<PendingClient>(&raw).ok());
+    let newcomer = existing.is_none();
+    let mut pending = existing.unwrap_or_else(|| PendingClient::new(client_id, now));
+    pending
+        .admit(newcomer, pending_clients, line)
+        .map_err(HoldError::Refused)?;
+    pending.add(record, line, ip, now);
+    let raw = serde_json::to_string(&pending).map_err(|_| HoldError::Redis)?;
+    let mut pipe = redis::pipe();
+    pipe.rpush(held_key(client_id), &line[..line.len() - 1])
+        .ignore()
+        .hset(PENDING_KEY, client_id, raw)
+        .ignore();
+    timed(pipe.query_async::<_, ()>(&mut conn))
+        .await
+        .ok_or(HoldError::Redis)?;
+    Ok((pending, newcomer))
+}
+
+/// Records the approval in Redis and takes what the client sent while
+/// pending, in one transaction.
+#[cfg(feature = "production")]
+async fn redis_approve(redis: &SharedRedis, approval: &Approval) -> io::Result<Vec<Held>> {
+    let mut conn = redis.conn().await.ok_or_else(redis_unavailable)?;
+    let client_id = &approval.client_id;
+    let key = held_key(client_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .lrange(&key, 0, -1)
+        .hset(APPROVED_KEY, client_id, serde_json::to_string(approval)?)
+        .ignore()
+        .hdel(PENDING_KEY, client_id)
+        .ignore()
+        .del(&key)
+        .ignore();
+    let (lines,): (Vec<String>,) = timed(pipe.query_async(&mut conn))
+        .await
+        .ok_or_else(redis_unavailable)?;
+    Ok(lines
+        .iter()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                log::error!("❌ Skipping unreadable pending record: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(feature = "production")]
+async fn redis_reject(redis: &SharedRedis, client_id: &str) -> io::Result<Option<PendingClient>> {
+    let mut conn = redis.conn().await.ok_or_else(redis_unavailable)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hget(PENDING_KEY, client_id)
+        .hdel(PENDING_KEY, client_id)
+        .ignore()
+        .del(held_key(client_id))
+        .ignore();
+    let (raw,): (Option<String>,) = timed(pipe.query_async(&mut conn))
+        .await
+        .ok_or_else(redis_unavailable)?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+/// Every value of the hash at `key`, by client_id; none without Redis.
+#[cfg(feature = "production")]
+async fn redis_list<T: serde::de::DeserializeOwned>(
+    redis: Option<&SharedRedis>,
+    key: &str,
+) -> Vec<(String, T)> {
+    let Some(redis) = redis else {
+        return Vec::new();
+    };
+    let Some(mut conn) = redis.conn().await else {
+        return Vec::new();
+    };
+    let records: std::collections::HashMap<String, String> =
+        timed(conn.hgetall(key)).await.unwrap_or_default();
+    records
+        .into_iter()
+        .filter_map(|(client_id, raw)| Some((client_id, serde_json::from_str(&raw).ok()?)))
+        .collect()
+}
@@ -0,0 +1,293 @@
+//! Error responses. Every error body has the same envelope:
+//!
+//! ```json
+//! {"success": false, "error": "human-readable message", "code": "machine_readable"}
+//! ```
+//!
+//! plus fields specific to the error (e.g. `resets_at` for an exhausted
+//! quota) and any added with [`ApiError::with`], such as `client_ip`.
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Seconds a client is asked to wait after a database timeout.
+const DB_TIMEOUT_RETRY_AFTER: i64 = 5;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// A parameter or payload field is invalid.
+    BadRequest(String),
+    /// The body isn't valid JSON or doesn't match the expected shape.
+    InvalidJson(String),
+    /// Missing or wrong admin token.
+    Unauthorized,
+    Forbidden(String),
+    /// A dashboard session request that changes something without the
+    /// session's CSRF token, or from another origin.
+    CsrfFailed(&'static str),
+    /// The source address is temporarily banned.
+    Banned {
+        reason: String,
+        expires_at: DateTime<Utc>,
+    },
+    NotFound(String),
+    /// Another operation that can't run alongside this one is in progress.
+    Conflict(String),
+    ClientArchived {
+        client_id: String,
+        archived_at: DateTime<Utc>,
+    },
+    PayloadTooLarge(String),
+    /// Too many failed logins from this address or for this account.
+    LoginThrottled {
+        retry_at: DateTime<Utc>,
+    },
+    QuotaExceeded {
+        quota: &'static str,
+        limit: u64,
+        client_id: String,
+        resets_at: DateTime<Utc>,
+    },
+    Database(String),
+    /// Reading or writing files on the server (e.g. `--upload-dir`) failed.
+    Storage(String),
+    /// A query hit the statement timeout or waited too long for a connection.
+    #[cfg_attr(not(feature = "production"), allow(dead_code))]
+    DatabaseTimeout {
+        hint: &'static str,
+    },
+    /// Switched off with `--disable-feature` or `/api/admin/features`.
+    FeatureDisabled {
+        feature: &'static str,
+    },
+    /// Paused for maintenance (`/api/admin/maintenance`).
+    Maintenance {
+        ends_at: Option<DateTime<Utc>>,
+        reason: Option<String>,
+    },
+    /// Injected by `--chaos`: a failure, or an answer held back and dropped.
+    Chaos {
+        dropped: bool,
+    },
+    /// Another error with extra fields in its body.
+    WithFields(Box<ApiError>, Map<String, Value>),
+}
+
+impl ApiError {
+    /// Adds a field to the error body.
+    pub fn with(self, key: &str, value: impl serde::Serialize) -> ApiError {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        match self {
+            ApiError::WithFields(inner, mut fields) => {
+                fields.insert(key.to_string(), value);
+                ApiError::WithFields(inner, fields)
+            }
+            other => {
+                let mut fields = Map::new();
+                fields.insert(key.to_string(), value);
+                ApiError::WithFields(Box::new(other), fields)
+            }
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::CsrfFailed(_) => "csrf_failed",
+            ApiError::Banned { .. } => "banned",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::ClientArchived { .. } => "client_archived",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::LoginThrottled { .. } => "login_throttled",
+            ApiError::QuotaExceeded { .. } => "quota_exceeded",
+            ApiError::Database(_) => "database_error",
+            ApiError::Storage(_) => "storage_error",
+            ApiError::DatabaseTimeout { .. } => "database_timeout",
+            ApiError::FeatureDisabled { .. } => "feature_disabled",
+            ApiError::Maintenance { .. } => "maintenance",
+            ApiError::Chaos { .. } => "chaos",
+            ApiError::WithFields(inner, _) => inner.code(),
+        }
+    }
+
+    fn retry_after(&self) -> Option<i64> {
+        let until = match self {
+            ApiError::Banned { expires_at, .. } => *expires_at,
+            ApiError::LoginThrottled { retry_at } => *retry_at,
+            ApiError::QuotaExceeded { resets_at, .. } => *resets_at,
+            ApiError::DatabaseTimeout { .. } => return Some(DB_TIMEOUT_RETRY_AFTER),
+            ApiError::Maintenance { ends_at: None, .. } => {
+                return Some(crate::maintenance::DEFAULT_RETRY_AFTER)
+            }
+            ApiError::Maintenance {
+                ends_at: Some(end), ..
+            } => *end,
+            ApiError::WithFields(inner, _) => return inner.retry_after(),
+            _ => return None,
+        };
+        Some((until - Utc::now()).num_seconds().max(1))
+    }
+
+    /// Fields that belong to the error itself.
+    fn fields(&self) -> Map<String, Value> {
+        let value = match self {
+            ApiError::Banned { expires_at, .. } => serde_json::json!({
+                "expires_at": expires_at.to_rfc3339()
+            }),
+            ApiError::ClientArchived {
+                client_id,
+                archived_at,
+            } => serde_json::json!({
+                "client_id": client_id,
+                "archived_at": archived_at
+            }),
+            ApiError::QuotaExceeded {
+                quota,
+                limit,
+                client_id,
+                resets_at,
+            } => serde_json::json!({
+                "quota": quota,
+                "limit": limit,
+                "client_id": client_id,
+                "resets_at": resets_at.to_rfc3339()
+            }),
+            ApiError::LoginThrottled { retry_at } => serde_json::json!({
+                "retry_at": retry_at.to_rfc3339()
+            }),
+            ApiError::DatabaseTimeout { hint } => serde_json::json!({ "hint": hint }),
+            ApiError::FeatureDisabled { feature } => serde_json::json!({ "feature": feature }),
+            ApiError::Chaos { dropped } => serde_json::json!({ "dropped": dropped }),
+            ApiError::Maintenance { ends_at, reason } => serde_json::json!({
+                "ends_at": ends_at.map(|t| t.to_rfc3339()),
+                "reason": reason
+            }),
+            ApiError::WithFields(inner, extra) => {
+                let mut fields = inner.fields();
+                fields.extend(extra.clone());
+                return fields;
+            }
+            _ => return Map::new(),
+        };
+        match value {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::PayloadTooLarge(msg) => f.write_str(msg),
+            ApiError::InvalidJson(msg) => write!(f, "Invalid JSON: {}", msg),
+            ApiError::Unauthorized => f.write_str("Unauthorized: valid admin token required"),
+            ApiError::CsrfFailed(reason) => write!(f, "Forbidden: CSRF check failed ({})", reason),
+            ApiError::Banned { reason, .. } => {
+                write!(f, "Forbidden: address temporarily banned ({})", reason)
+            }
+            ApiError::ClientArchived { client_id, .. } => write!(
+                f,
+                "Client {} is archived and no longer accepts data",
+                client_id
+            ),
+            ApiError::QuotaExceeded {
+                quota,
+                limit,
+                client_id,
+                ..
+            } => write!(
+                f,
+                "Quota exceeded: {} limit of {} reached for client {}",
+                quota, limit, client_id
+            ),
+            ApiError::LoginThrottled { .. } => {
+                f.write_str("Too many failed logins; try again later")
+            }
+            ApiError::Database(msg) => write!(f, "Database error: {}", msg),
+            ApiError::Storage(msg) => write!(f, "Storage error: {}", msg),
+            ApiError::DatabaseTimeout { .. } => f.write_str("Database query timed out"),
+            ApiError::FeatureDisabled { feature } => {
+                write!(f, "The {} feature is disabled on this server", feature)
+            }
+            ApiError::Maintenance { .. } => f.write_str("Paused for maintenance"),
+            ApiError::Chaos { dropped: false } => f.write_str("Injected failure (--chaos)"),
+            ApiError::Chaos { dropped: true } => {
+                f.write_str("Injected timeout; the request was handled (--chaos)")
+            }
+            ApiError::WithFields(inner, _) => inner.fmt(f),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidJson(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_)
+            | ApiError::CsrfFailed(_)
+            | ApiError::Banned { .. }
+            | ApiError::FeatureDisabled { .. } => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::ClientArchived { .. } => StatusCode::GONE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::QuotaExceeded { .. } | ApiError::LoginThrottled { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::Database(_) | ApiError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::DatabaseTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Maintenance { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Chaos { dropped: false } => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Chaos { dropped: true } => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::WithFields(inner, _) => inner.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = Map::new();
+        body.insert("success".into(), false.into());
+        body.insert("error".into(), self.to_string().into());
+        body.insert("code".into(), self.code().into());
+        body.extend(self.fields());
+
+        let mut resp = HttpResponse::build(self.status_code());
+        if let Some(secs) = self.retry_after() {
+            resp.insert_header(("Retry-After", secs.to_string()));
+        }
+        resp.json(Value::Object(body))
+    }
+}
+
+/// Statement and pool timeouts become [`ApiError::DatabaseTimeout`] so a
+/// dashboard backs off instead of re-sending the same slow query.
+#[cfg(feature = "production")]
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        if crate::storage::is_statement_timeout(&e) {
+            crate::metrics::DB_QUERY_TIMEOUTS.inc();
+            ApiError::DatabaseTimeout {
+                hint: "The query hit --db-statement-timeout. Narrow the time range or lower the limit.",
+            }
+        } else if matches!(e, sqlx::Error::PoolTimedOut) {
+            crate::metrics::DB_QUERY_TIMEOUTS.inc();
+            ApiError::DatabaseTimeout {
+                hint: "All database connections are busy. Retry in a few seconds.",
+            }
+        } else {
+            ApiError::Database(e.to_string())
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Limits on the `data` of `/api/extensions` and `/api/security` events
+//! (`--max-event-data-depth`, `--max-event-data-bytes`). `data` is free-form
+//! JSON that is stored, cloned for alerts, exports and the dashboard, and
+//! logged; a payload over either limit is refused with 413 or cut down
+//! before anything else sees it, depending on `--oversized-event-data`.
+//!
+//! Truncation replaces arrays and objects nested deeper than the limit with
+//! `"[truncated]"`, then drops the largest top-level fields (array
+//! elements from the end, or the end of a string) until the rest fits. What
+//! was cut is described under `data._truncated`, so the marker itself can
+//! take a stored payload slightly over the byte limit. serde_json already
+//! refuses JSON nested more than 128 deep as invalid.
+
+use crate::error::ApiError;
+use crate::metrics;
+use crate::types::ExtensionEvent;
+use actix_web::{web, HttpRequest};
+use serde_json::Value;
+
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+pub const DEFAULT_MAX_BYTES: usize = 256 * 1024;
+
+/// What replaces a value nested too deep.
+const DEPTH_MARKER: &str = "[truncated]";
+/// Dropped field names listed in `_truncated` at most.
+const MAX_DROPPED_LISTED: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedEventData {
+    /// Refuse the event with 413.
+    Reject,
+    /// Store the event with `data` cut down to the limits.
+    #[default]
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EventDataLimit {
+    /// 0 means unlimited.
+    pub max_depth: usize,
+    /// Serialized size of `data`; 0 means unlimited.
+    pub max_bytes: usize,
+    pub oversized: OversizedEventData,
+}
+
+impl Default for EventDataLimit {
+    fn default() -> Self {
+        EventDataLimit {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_bytes: DEFAULT_MAX_BYTES,
+            oversized: OversizedEventData::default(),
+        }
+    }
+}
+
+/// Counts serialized bytes without keeping them.
+struct ByteCount(usize);
+
+impl std::io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn size<T: serde::Serialize + ?Sized>(value: &T) -> usize {
+    let mut count = ByteCount(0);
+    // Writing JSON into a counter can't fail.
+    let _ = serde_json::to_writer(&mut count, value);
+    count.0
+}
+
+/// Levels of arrays and objects: 0 for a scalar, 1 for `{"a": 1}`.
+pub fn depth(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(depth).max().unwrap_or(0),
+        Value::Object(fields) => 1 + fields.values().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Replaces the arrays and objects below `levels` levels with the marker.
+fn prune(value: &mut Value, levels: usize) {
+    if !value.is_array() && !value.is_object() {
+        return;
+    }
+    if levels == 0 {
+        *value = Value::String(DEPTH_MARKER.to_string());
+        return;
+    }
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|v| prune(v, levels - 1)),
+        Value::Object(fields) => fields.values_mut().for_each(|v| prune(v, levels - 1)),
+        _ => {}
+    }
+}
+
+/// Drops the bulk of `value` until it serializes to at most `max_bytes`,
+/// returning the names of the object fields dropped.
+fn shrink(value: &mut Value, max_bytes: usize) -> Vec<String> {
+    let mut dropped = Vec::new();
+    match value {
+        Value::Object(fields) => {
+            // Each field is its key, a colon, its value and a comma.
+            let mut by_size: Vec<(usize, String)> = fields
+                .iter()
+                .map(|(k, v)| (size(k.as_str()) + size(v) + 2, k.clone()))
+                .collect();
+            by_size.sort_by(|a, b| b.cmp(a));
+            let mut total = size(fields);
+            for (field_size, key) in by_size {
+                if total <= max_bytes {
+                    break;
+                }
+                fields.remove(&key);
+                total = total.saturating_sub(field_size);
+                dropped.push(key);
+            }
+        }
+        Value::Array(items) => {
+            let mut total = size(items);
+            while total > max_bytes {
+                let Some(last) = items.pop() else { break };
+                total = total.saturating_sub(size(&last) + 1);
+            }
+        }
+        Value::String(text) => {
+            // Escapes make the JSON longer than the text; every byte cut
+            // shortens it by at least one.
+            let mut cut = text.len().min(max_bytes.saturating_sub(2));
+            loop {
+                while !text.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                let over = size(&text[..cut]).saturating_sub(max_bytes);
+                if over == 0 || cut == 0 {
+                    break;
+                }
+                cut -= over.min(cut);
+            }
+            text.truncate(cut);
+        }
+        _ => {}
+    }
+    dropped
+}
+
+impl EventDataLimit {
+    /// The limit registered as app data; the defaults when there is none.
+    pub fn for_request(req: &HttpRequest) -> Self {
+        req.app_data::<web::Data<EventDataLimit>>()
+            .map(|limit| *limit.get_ref())
+            .unwrap_or_default()
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_depth > 0 || self.max_bytes > 0
+    }
+
+    /// `event` with its `data` within the limits: unchanged, cut down in
+    /// `truncate` mode, or refused in `reject` mode.
+    pub fn apply(
+        &self,
+        mut event: ExtensionEvent,
+        client_ip: &str,
+    ) -> Result<ExtensionEvent, ApiError> {
+        if !self.is_limited() {
+            return Ok(event);
+        }
+        let bytes = size(&event.data);
+        let levels = depth(&event.data);
+        let too_deep = self.max_depth > 0 && levels > self.max_depth;
+        let too_large = self.max_bytes > 0 && bytes > self.max_bytes;
+        if !too_deep && !too_large {
+            return Ok(event);
+        }
+        if self.oversized == OversizedEventData::Reject {
+            metrics::EVENT_DATA_REJECTED.inc();
+            log::warn!(
+                "📦 Event data from IP {} refused: {} bytes, {} levels deep (client_id={:?}, event_type={})",
+                client_ip,
+                bytes,
+                levels,
+                event.client_id,
+                event.event_type
+            );
+            let message = match too_deep {
+                true => format!(
+                    "Event data is nested {} levels deep; the limit is {}",
+                    levels, self.max_depth
+                ),
+                false => format!(
+                    "Event data is {} bytes; the limit is {}",
+                    bytes, self.max_bytes
+                ),
+            };
+            return Err(ApiError::PayloadTooLarge(message)
+                .with("data_bytes", bytes)
+                .with("data_depth", levels)
+                .with("max_event_data_bytes", self.max_bytes)
+                .with("max_event_data_depth", self.max_depth));
+        }
+
+        metrics::EVENT_DATA_TRUNCATED.inc();
+        let mut data = std::mem::take(&mut event.data);
+        if too_deep {
+            prune(&mut data, self.max_depth);
+        }
+        let mut dropped = Vec::new();
+        if self.max_bytes > 0 {
+            dropped = shrink(&mut data, self.max_bytes);
+        }
+        log::warn!(
+            "📦 Event data from IP {} truncated: {} bytes, {} levels deep -> {} bytes (client_id={:?}, event_type={})",
+            client_ip,
+            bytes,
+            levels,
+            size(&data),
+            event.client_id,
+            event.event_type
+        );
+        let dropped_count = dropped.len();
+        dropped.truncate(MAX_DROPPED_LISTED);
+        let marker = serde_json::json!({
+            "original_bytes": bytes,
+            "original_depth": levels,
+            "dropped_fields": dropped,
+            "dropped_count": dropped_count,
+        });
+        let mut fields = match data {
+            Value::Object(fields) => fields,
+            other => {
+                let mut fields = serde_json::Map::new();
+                fields.insert("data".to_string(), other);
+                fields
+            }
+        };
+        fields.insert("_truncated".to_string(), marker);
+        event.data = Value::Object(fields);
+        Ok(event)
+    }
+}
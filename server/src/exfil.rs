@@ -0,0 +1,127 @@
+use crate::handlers::common::domain_from_url;
+use crate::types::LogEntry;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct ExfilFinding {
+    pub client_id: String,
+    pub domain: String,
+    pub bytes: u64,
+    pub requests: usize,
+    pub window_secs: i64,
+}
+
+impl ExfilFinding {
+    pub fn to_event_data(&self, threshold: u64) -> serde_json::Value {
+        let ratio = self.bytes as f64 / threshold.max(1) as f64;
+        serde_json::json!({
+            "url": format!("https://{}/", self.domain),
+            "host": self.domain,
+            "bytes_uploaded": self.bytes,
+            "requests": self.requests,
+            "window_secs": self.window_secs,
+            "threshold_bytes": threshold,
+            "detection": {
+                "riskScore": (50.0 + 25.0 * ratio.log2()).clamp(50.0, 100.0).round() as u64
+            }
+        })
+    }
+}
+
+#[derive(Default)]
+struct Window {
+    uploads: VecDeque<(DateTime<Utc>, u64)>,
+    total: u64,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+/// Tracks per-client upload volume per destination domain over a sliding
+/// window and flags clients that send more than the threshold to a domain
+/// that is not allowlisted.
+pub struct ExfilMonitor {
+    threshold_bytes: u64,
+    window: Duration,
+    allowlist: Vec<String>,
+    windows: Mutex<HashMap<(String, String), Window>>,
+}
+
+impl ExfilMonitor {
+    pub fn new(threshold_bytes: u64, window: Duration, allowlist: Vec<String>) -> Self {
+        ExfilMonitor {
+            threshold_bytes,
+            window,
+            allowlist: allowlist
+                .into_iter()
+                .map(|d| d.trim().trim_start_matches("*.").to_ascii_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn threshold_bytes(&self) -> u64 {
+        self.threshold_bytes
+    }
+
+    fn is_allowlisted(&self, domain: &str) -> bool {
+        self.allowlist
+            .iter()
+            .any(|a| domain == a || domain.ends_with(&format!(".{}", a)))
+    }
+
+    pub fn observe(&self, entry: &LogEntry) -> Vec<ExfilFinding> {
+        let Some(client_id) = entry.client_id.as_deref().filter(|c| !c.is_empty()) else {
+            return Vec::new();
+        };
+        let now = Utc::now();
+        let cutoff = now - self.window;
+
+        let mut batch: HashMap<String, u64> = HashMap::new();
+        for network_log in &entry.logs {
+            let Some(size) = network_log.request_size.filter(|s| *s > 0) else {
+                continue;
+            };
+            let Some(domain) = domain_from_url(&network_log.url) else {
+                continue;
+            };
+            let domain = domain.to_ascii_lowercase();
+            if self.is_allowlisted(&domain) {
+                continue;
+            }
+            let total = batch.entry(domain).or_insert(0);
+            *total = total.saturating_add(size);
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let mut findings = Vec::new();
+        for (domain, bytes) in batch {
+            let w = windows
+                .entry((client_id.to_string(), domain.clone()))
+                .or_default();
+            w.uploads.push_back((now, bytes));
+            w.total = w.total.saturating_add(bytes);
+            while let Some((at, b)) = w.uploads.front().copied() {
+                if at >= cutoff {
+                    break;
+                }
+                w.total = w.total.saturating_sub(b);
+                w.uploads.pop_front();
+            }
+            if w.total < self.threshold_bytes || w.last_alert.is_some_and(|t| t >= cutoff) {
+                continue;
+            }
+            w.last_alert = Some(now);
+            findings.push(ExfilFinding {
+                client_id: client_id.to_string(),
+                domain,
+                bytes: w.total,
+                requests: w.uploads.len(),
+                window_secs: self.window.num_seconds(),
+            });
+        }
+        windows.retain(|_, w| w.uploads.back().is_some_and(|(at, _)| *at >= cutoff));
+        findings
+    }
+}
@@ -0,0 +1,346 @@
+//! A/B policy experiments: clients are split between variants of the
+//! blocklist policy, and what each variant leads to is counted, so a change
+//! can be judged on its effect before it is made for everyone.
+//!
+//! A variant adds URL patterns and channels on top of the client's policy
+//! (the `experiment` layer, see [`crate::policy`]); a variant without
+//! entries is the control. Clients are assigned the way a blocklist rollout
+//! picks its canary, by a hash of the client_id salted with the experiment
+//! id, so they keep their variant for the experiment's life and the weights
+//! (percentages) decide the split.
+//!
+//! Each variant counts, for the clients seen in it: the requests logged and
+//! blocked, page views, browsing time (estimated as screen time is: the gap
+//! between a client's batches, capped at [`IDLE_CAP`]), and override
+//! requests, the extension events of type `override_requested` a user sends
+//! asking for a blocked page. Stopping an experiment stops its variants
+//! from being served and freezes the results. Experiments live in memory,
+//! per replica, like blocklist rollouts; a variant remembers at most
+//! [`MAX_CLIENTS_PER_VARIANT`] clients.
+
+use crate::blocklist_rollout::bucket;
+use crate::counters::BatchSummary;
+use crate::screen_time::IDLE_CAP;
+use crate::types::BlocklistEntries;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+/// The extension event a user sends to ask for a blocked page.
+pub const OVERRIDE_EVENT: &str = "override_requested";
+pub const MAX_VARIANTS: usize = 10;
+/// Clients remembered per variant; past it the least recently active tenth
+/// is forgotten.
+pub const MAX_CLIENTS_PER_VARIANT: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    /// Percentage of clients; the variants' weights add up to 100.
+    pub weight: u8,
+    #[serde(flatten, default)]
+    pub blocklist: BlocklistEntries,
+}
+
+/// `POST /api/experiments`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewExperiment {
+    pub id: String,
+    pub description: Option<String>,
+    pub variants: Vec<Variant>,
+}
+
+/// Why a new experiment can't run, if it can't.
+pub fn validate(experiment: &NewExperiment) -> Result<(), String> {
+    let valid_name = |s: &str| {
+        !s.is_empty()
+            && s.len() <= 64
+            && s.bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-".contains(&b))
+    };
+    if !valid_name(&experiment.id) {
+        return Err("ids are up to 64 lowercase letters, digits, '_' and '-'".to_string());
+    }
+    if !(2..=MAX_VARIANTS).contains(&experiment.variants.len()) {
+        return Err(format!("an experiment has 2 to {} variants", MAX_VARIANTS));
+    }
+    for (i, v) in experiment.variants.iter().enumerate() {
+        if !valid_name(&v.name) {
+            return Err(format!("variant name '{}' is not valid", v.name));
+        }
+        if experiment.variants[..i].iter().any(|w| w.name == v.name) {
+            return Err(format!("variant '{}' is listed twice", v.name));
+        }
+    }
+    let total: u32 = experiment.variants.iter().map(|v| v.weight as u32).sum();
+    if total != 100 {
+        return Err(format!("variant weights add up to {}, not 100", total));
+    }
+    Ok(())
+}
+
+/// A client's variant of one running experiment.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+    pub blocklist: BlocklistEntries,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Outcomes {
+    /// Distinct clients seen in the variant, at most
+    /// [`MAX_CLIENTS_PER_VARIANT`].
+    pub clients: usize,
+    pub logs: u64,
+    pub blocked: u64,
+    /// `blocked / logs`.
+    pub block_rate: f64,
+    pub page_views: u64,
+    pub override_requests: u64,
+    pub overrides_per_client: f64,
+    pub browsing_secs: u64,
+    pub browsing_secs_per_client: f64,
+}
+
+#[derive(Default)]
+struct Tally {
+    /// When each client's last batch came, to estimate browsing time.
+    last_batch: HashMap<String, DateTime<Utc>>,
+    logs: u64,
+    blocked: u64,
+    page_views: u64,
+    override_requests: u64,
+    browsing_secs: u64,
+}
+
+impl Tally {
+    /// Notes a batch from `client_id` at `now`, returning its previous one.
+    fn batch_from(&mut self, client_id: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.last_batch.len() >= MAX_CLIENTS_PER_VARIANT
+            && !self.last_batch.contains_key(client_id)
+        {
+            let mut seen: Vec<_> = self
+                .last_batch
+                .iter()
+                .map(|(id, at)| (*at, id.clone()))
+                .collect();
+            let (oldest, _, _) = seen.select_nth_unstable(MAX_CLIENTS_PER_VARIANT / 10);
+            for (_, id) in oldest.iter() {
+                self.last_batch.remove(id);
+            }
+        }
+        self.last_batch.insert(client_id.to_string(), now)
+    }
+
+    fn outcomes(&self) -> Outcomes {
+        let clients = self.last_batch.len();
+        let per_client = |n: u64| {
+            if clients == 0 {
+                0.0
+            } else {
+                n as f64 / clients as f64
+            }
+        };
+        Outcomes {
+            clients,
+            logs: self.logs,
+            blocked: self.blocked,
+            block_rate: if self.logs == 0 {
+                0.0
+            } else {
+                self.blocked as f64 / self.logs as f64
+            },
+            page_views: self.page_views,
+            override_requests: self.override_requests,
+            overrides_per_client: per_client(self.override_requests),
+            browsing_secs: self.browsing_secs,
+            browsing_secs_per_client: per_client(self.browsing_secs),
+        }
+    }
+}
+
+struct Experiment {
+    description: Option<String>,
+    variants: Vec<Variant>,
+    started_at: DateTime<Utc>,
+    started_by: String,
+    stopped_at: Option<DateTime<Utc>>,
+    /// Parallel to `variants`.
+    tallies: Vec<Tally>,
+}
+
+impl Experiment {
+    fn variant_of(&self, id: &str, client_id: &str) -> usize {
+        let b = bucket(id, client_id) as u32;
+        let mut upper = 0;
+        for (i, v) in self.variants.iter().enumerate() {
+            upper += v.weight as u32;
+            if b < upper {
+                return i;
+            }
+        }
+        self.variants.len() - 1
+    }
+
+    fn results(&self, id: &str) -> ExperimentResults {
+        ExperimentResults {
+            id: id.to_string(),
+            description: self.description.clone(),
+            running: self.stopped_at.is_none(),
+            started_at: self.started_at,
+            started_by: self.started_by.clone(),
+            stopped_at: self.stopped_at,
+            variants: self
+                .variants
+                .iter()
+                .zip(&self.tallies)
+                .map(|(v, t)| VariantResults {
+                    name: v.name.clone(),
+                    weight: v.weight,
+                    url_patterns: v.blocklist.url_patterns.len(),
+                    youtube_channels: v.blocklist.youtube_channels.len(),
+                    outcomes: t.outcomes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantResults {
+    pub name: String,
+    pub weight: u8,
+    pub url_patterns: usize,
+    pub youtube_channels: usize,
+    #[serde(flatten)]
+    pub outcomes: Outcomes,
+}
+
+/// `GET /api/experiments/{id}/results`.
+#[derive(Debug, Serialize)]
+pub struct ExperimentResults {
+    pub id: String,
+    pub description: Option<String>,
+    pub running: bool,
+    pub started_at: DateTime<Utc>,
+    pub started_by: String,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub variants: Vec<VariantResults>,
+}
+
+#[derive(Default)]
+pub struct Experiments {
+    experiments: RwLock<BTreeMap<String, Experiment>>,
+}
+
+impl Experiments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every experiment, running or stopped, by id.
+    pub fn list(&self) -> Vec<ExperimentResults> {
+        let experiments = self.experiments.read().unwrap();
+        experiments.iter().map(|(id, e)| e.results(id)).collect()
+    }
+
+    pub fn results(&self, id: &str) -> Option<ExperimentResults> {
+        self.experiments
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|e| e.results(id))
+    }
+
+    /// Starts a validated experiment; `Err` when the id is taken.
+    pub fn start(&self, new: NewExperiment, started_by: &str) -> Result<ExperimentResults, String> {
+        let mut experiments = self.experiments.write().unwrap();
+        if experiments.contains_key(&new.id) {
+            return Err(format!("experiment {} already exists", new.id));
+        }
+        let experiment = Experiment {
+            description: new.description,
+            tallies: new.variants.iter().map(|_| Tally::default()).collect(),
+            variants: new.variants,
+            started_at: Utc::now(),
+            started_by: started_by.to_string(),
+            stopped_at: None,
+        };
+        let results = experiment.results(&new.id);
+        experiments.insert(new.id, experiment);
+        Ok(results)
+    }
+
+    /// Stops serving the variants and counting outcomes.
+    pub fn stop(&self, id: &str) -> Option<ExperimentResults> {
+        let mut experiments = self.experiments.write().unwrap();
+        let experiment = experiments.get_mut(id)?;
+        experiment.stopped_at.get_or_insert_with(Utc::now);
+        Some(experiment.results(id))
+    }
+
+    /// Returns whether the experiment existed.
+    pub fn delete(&self, id: &str) -> bool {
+        self.experiments.write().unwrap().remove(id).is_some()
+    }
+
+    /// The client's variant in each running experiment.
+    pub fn assignments(&self, client_id: &str) -> Vec<Assignment> {
+        let experiments = self.experiments.read().unwrap();
+        experiments
+            .iter()
+            .filter(|(_, e)| e.stopped_at.is_none())
+            .map(|(id, e)| {
+                let variant = &e.variants[e.variant_of(id, client_id)];
+                Assignment {
+                    experiment: id.clone(),
+                    variant: variant.name.clone(),
+                    blocklist: variant.blocklist.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Counts a batch of logs towards the client's variants.
+    pub fn record_logs(&self, client_id: Option<&str>, batch: &BatchSummary) {
+        let Some(client_id) = client_id.filter(|c| !c.is_empty()) else {
+            return;
+        };
+        let now = Utc::now();
+        let idle_cap = chrono::Duration::from_std(IDLE_CAP).expect("IDLE_CAP fits");
+        let mut experiments = self.experiments.write().unwrap();
+        for (id, e) in experiments
+            .iter_mut()
+            .filter(|(_, e)| e.stopped_at.is_none())
+        {
+            let i = e.variant_of(id, client_id);
+            let tally = &mut e.tallies[i];
+            if let Some(last) = tally.batch_from(client_id, now) {
+                tally.browsing_secs += (now - last).min(idle_cap).num_seconds().max(0) as u64;
+            }
+            tally.logs += batch.logs;
+            tally.blocked += batch.blocked;
+            tally.page_views += batch.page_views;
+        }
+    }
+
+    /// Counts an override request towards the client's variants.
+    pub fn record_event(&self, client_id: Option<&str>, event_type: &str) {
+        let Some(client_id) = client_id.filter(|c| !c.is_empty()) else {
+            return;
+        };
+        if event_type != OVERRIDE_EVENT {
+            return;
+        }
+        let mut experiments = self.experiments.write().unwrap();
+        for (id, e) in experiments
+            .iter_mut()
+            .filter(|(_, e)| e.stopped_at.is_none())
+        {
+            let i = e.variant_of(id, client_id);
+            e.tallies[i].override_requests += 1;
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Exceptions the extension reports about itself (`POST /api/errors`), so
+//! bugs that only happen in the field show up somewhere maintainers look.
+//!
+//! Reports are grouped by a hash of their stack trace: one row per distinct
+//! error with a count and when it was first and last seen. The per-install
+//! part of extension URLs (`chrome-extension://<id>/`,
+//! `moz-extension://<uuid>/`) is taken out before hashing so the same bug
+//! groups across browsers; a report without a stack is grouped by its
+//! message. Times are the server's, not the reporting browser's.
+
+use crate::types::ExtensionError;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+pub const MAX_MESSAGE_LEN: usize = 2000;
+pub const MAX_STACK_LEN: usize = 16 * 1024;
+/// `source`, `extension_version` and `client_id`.
+pub const MAX_FIELD_LEN: usize = 100;
+/// Distinct errors kept in memory; the least recently seen goes first.
+pub const MAX_DISTINCT: usize = 10_000;
+
+/// How often production replicas re-read the extension_errors table.
+#[cfg(feature = "production")]
+const RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+const EXTENSION_SCHEMES: &[&str] = &[
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+/// The body of `POST /api/errors`.
+#[derive(Debug, Deserialize)]
+pub struct ErrorReport {
+    #[serde(default)]
+    pub client_id: Option<String>,
+    pub message: String,
+    #[serde(default)]
+    pub stack: Option<String>,
+    /// Which part of the extension threw, e.g. `background` or `content`.
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub extension_version: Option<String>,
+}
+
+fn truncated(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text.to_string(),
+    }
+}
+
+fn field(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| truncated(v.trim(), MAX_FIELD_LEN))
+        .filter(|v| !v.is_empty())
+}
+
+/// `stack` with extension URLs made install-independent and lines trimmed.
+fn normalized(stack: &str) -> String {
+    let mut out = String::with_capacity(stack.len());
+    for line in stack.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let mut rest = line;
+        while let Some((at, scheme)) = EXTENSION_SCHEMES
+            .iter()
+            .filter_map(|s| rest.find(s).map(|at| (at, s)))
+            .min()
+        {
+            out.push_str(&rest[..at]);
+            out.push_str("extension://");
+            let host = &rest[at + scheme.len()..];
+            rest = &host[host.find('/').unwrap_or(host.len())..];
+        }
+        out.push_str(rest);
+        out.push('\n');
+    }
+    out
+}
+
+/// What reports of the same error share: see the module docs.
+pub fn stack_hash(message: &str, stack: Option<&str>) -> String {
+    let key = match stack.map(normalized).filter(|s| !s.is_empty()) {
+        Some(stack) => format!("stack\n{}", stack),
+        None => format!("message\n{}", message.trim()),
+    };
+    hex::encode(&Sha256::digest(key.as_bytes())[..16])
+}
+
+impl ErrorReport {
+    /// The report as a first sighting at `now`: trimmed, cut to the limits
+    /// and hashed. A report needs a message.
+    pub fn into_error(self, now: DateTime<Utc>) -> Result<ExtensionError, String> {
+        let message = truncated(self.message.trim(), MAX_MESSAGE_LEN);
+        if message.is_empty() {
+            return Err("an error report needs a message".to_string());
+        }
+        let stack = self
+            .stack
+            .map(|s| truncated(&s, MAX_STACK_LEN))
+            .filter(|s| !s.trim().is_empty());
+        Ok(ExtensionError {
+            hash: stack_hash(&message, stack.as_deref()),
+            message,
+            stack,
+            source: field(self.source),
+            extension_version: field(self.extension_version),
+            last_client_id: field(self.client_id),
+            count: 1,
+            first_seen: now,
+            last_seen: now,
+        })
+    }
+}
+
+pub struct ErrorReports {
+    errors: RwLock<HashMap<String, ExtensionError>>,
+    #[cfg(feature = "production")]
+    storage: Option<Arc<crate::production::ProductionState>>,
+}
+
+impl Default for ErrorReports {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorReports {
+    /// In-memory only (simple mode): reports don't survive a restart.
+    pub fn new() -> Self {
+        ErrorReports {
+            errors: RwLock::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            storage: None,
+        }
+    }
+
+    /// Counts reports in the `extension_errors` table and keeps the
+    /// in-memory copy in sync with it.
+    #[cfg(feature = "production")]
+    pub fn spawn(state: Arc<crate::production::ProductionState>) -> Arc<ErrorReports> {
+        let store = Arc::new(ErrorReports {
+            errors: RwLock::new(HashMap::new()),
+            storage: Some(state.clone()),
+        });
+        let s = store.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match state.get_extension_errors().await {
+                    Ok(list) => {
+                        *s.errors.write().unwrap() =
+                            list.into_iter().map(|e| (e.hash.clone(), e)).collect();
+                    }
+                    Err(e) => log::error!("❌ Loading extension errors failed: {}", e),
+                }
+                tokio::time::sleep(RESYNC_INTERVAL).await;
+            }
+        });
+        store
+    }
+
+    /// Adds one report; returns the error with every report so far.
+    pub async fn record(&self, report: ExtensionError) -> Result<ExtensionError, String> {
+        #[cfg(feature = "production")]
+        let stored_count = match &self.storage {
+            Some(state) => Some(
+                state
+                    .record_extension_error(&report)
+                    .await
+                    .map_err(|e| e.to_string())?,
+            ),
+            None => None,
+        };
+        let mut errors = self.errors.write().unwrap();
+        let error = match errors.remove(&report.hash) {
+            Some(seen) => ExtensionError {
+                count: seen.count + 1,
+                first_seen: seen.first_seen.min(report.first_seen),
+                last_seen: seen.last_seen.max(report.last_seen),
+                stack: seen.stack.or(report.stack),
+                source: seen.source.or(report.source),
+                ..report
+            },
+            None => {
+                if errors.len() >= MAX_DISTINCT {
+                    let oldest = errors
+                        .values()
+                        .min_by_key(|e| e.last_seen)
+                        .map(|e| e.hash.clone());
+                    if let Some(hash) = oldest {
+                        errors.remove(&hash);
+                    }
+                }
+                report
+            }
+        };
+        #[cfg(feature = "production")]
+        let error = ExtensionError {
+            count: stored_count.unwrap_or(error.count),
+            ..error
+        };
+        errors.insert(error.hash.clone(), error.clone());
+        Ok(error)
+    }
+
+    /// Every error, most recently seen first.
+    pub fn list(&self) -> Vec<ExtensionError> {
+        let mut list: Vec<ExtensionError> = self.errors.read().unwrap().values().cloned().collect();
+        list.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then(a.hash.cmp(&b.hash)));
+        list
+    }
+
+    pub fn get(&self, hash: &str) -> Option<ExtensionError> {
+        self.errors.read().unwrap().get(hash).cloned()
+    }
+}
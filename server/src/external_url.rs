@@ -0,0 +1,69 @@
+//! Absolute URLs pointing back at this server, for links that leave it:
+//! alert messages and webhook payloads, and the status URLs of accepted
+//! jobs. Behind a TLS-terminating proxy the address the server listens on
+//! isn't the one users reach, so `--external-url` sets it. Without it, links
+//! built while answering a request follow the request's `X-Forwarded-Proto`
+//! / `X-Forwarded-Host` (or `Forwarded`) headers and `Host`, and links built
+//! elsewhere, such as in alerts, are left out.
+
+use actix_web::HttpRequest;
+use std::sync::OnceLock;
+
+static EXTERNAL_URL: OnceLock<String> = OnceLock::new();
+
+/// Checks an `--external-url` value: `http(s)://host[:port][/prefix]`.
+pub fn parse(url: &str) -> Result<String, String> {
+    let url = url.trim().trim_end_matches('/');
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| format!("'{}' must start with http:// or https://", url))?;
+    if rest.is_empty() || rest.starts_with('/') {
+        return Err(format!("'{}' has no host", url));
+    }
+    if url.contains(['?', '#']) {
+        return Err(format!("'{}' can't have a query or fragment", url));
+    }
+    Ok(url.to_string())
+}
+
+/// Uses `url` (already [`parse`]d) for every link from now on.
+pub fn install(url: String) {
+    let _ = EXTERNAL_URL.set(url);
+}
+
+/// The configured `--external-url`, without a trailing slash.
+pub fn configured() -> Option<&'static str> {
+    EXTERNAL_URL.get().map(String::as_str)
+}
+
+/// The base URL the client of `req` reached the server on.
+pub fn for_request(req: &HttpRequest) -> String {
+    if let Some(url) = configured() {
+        return url.to_string();
+    }
+    // actix reads Forwarded, then X-Forwarded-Proto / X-Forwarded-Host
+    // (first value each), then the connection and the Host header.
+    let info = req.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
+
+/// `path` on the server as reached by `req`.
+pub fn absolute(req: &HttpRequest, path: &str) -> String {
+    format!("{}{}", for_request(req), path)
+}
+
+/// The dashboard opened on one packet; `None` without `--external-url`.
+pub fn packet_link(packet_id: &str) -> Option<String> {
+    let base = configured()?;
+    let encoded: String = packet_id
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    Some(format!("{}/dashboard?packet={}", base, encoded))
+}
@@ -0,0 +1,238 @@
+//! Feature flags: parts of the server a deployment can switch off, at
+//! startup with `--disable-feature` or at runtime through
+//! `/api/admin/features`. Runtime changes are kept in `--feature-flags`
+//! when given and override the startup defaults from then on.
+//!
+//! A disabled feature's endpoints are refused before their handlers run:
+//! the dashboard pages with `404` as if they weren't there, API endpoints
+//! with `403 feature_disabled` naming the feature, so a client can tell it
+//! from a typo and stop retrying. Subsystems without endpoints of their own
+//! (alert delivery, the anomaly detectors) just stop doing their work.
+//! Admin endpoints other than the ones listed stay reachable, including the
+//! feature endpoints themselves.
+
+use crate::error::ApiError;
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    clap::ValueEnum,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[clap(rename_all = "snake_case")]
+pub enum Feature {
+    /// The dashboard pages and `/api/dashboard/*`.
+    Dashboard,
+    /// Bulk reads: `GET /api/logs`, configuration bundles and backups.
+    Export,
+    /// `/api/logs/import`, bundle import and restores.
+    Import,
+    /// Security event file uploads and their downloads.
+    Uploads,
+    /// Alert delivery to webhook, Slack, Telegram, email and syslog channels.
+    Webhooks,
+    /// The beaconing and exfiltration detectors run on each batch, and the
+    /// detection rules on each batch and event.
+    AnomalyDetection,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 6] = [
+        Feature::Dashboard,
+        Feature::Export,
+        Feature::Import,
+        Feature::Uploads,
+        Feature::Webhooks,
+        Feature::AnomalyDetection,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Dashboard => "dashboard",
+            Feature::Export => "export",
+            Feature::Import => "import",
+            Feature::Uploads => "uploads",
+            Feature::Webhooks => "webhooks",
+            Feature::AnomalyDetection => "anomaly_detection",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Feature> {
+        Feature::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// The feature an endpoint belongs to, if any.
+    pub fn of(method: &Method, path: &str) -> Option<Feature> {
+        let get = method == Method::GET;
+        let post = method == Method::POST;
+        match path {
+            "/" | "/dashboard" | "/dashboard.js" | "/dashboard-theme.js" | "/logo.png" => {
+                Some(Feature::Dashboard)
+            }
+            p if p.starts_with("/api/dashboard/") => Some(Feature::Dashboard),
+            "/api/logs" | "/api/logs/tail" if get => Some(Feature::Export),
+            "/api/admin/export-bundle" | "/api/admin/backups" => Some(Feature::Export),
+            "/api/admin/backup" if post => Some(Feature::Export),
+            "/api/logs/import" | "/api/admin/import-bundle" | "/api/admin/restore" => {
+                Some(Feature::Import)
+            }
+            "/api/security/upload" => Some(Feature::Uploads),
+            p if p.starts_with("/api/security/uploads/") => Some(Feature::Uploads),
+            _ => None,
+        }
+    }
+
+    /// Whether a disabled feature hides its endpoints (`404`) rather than
+    /// refusing them (`403`).
+    fn hides_endpoints(self) -> bool {
+        self == Feature::Dashboard
+    }
+}
+
+/// One feature as `/api/admin/features` reports it.
+#[derive(Debug, Serialize)]
+pub struct FeatureState {
+    pub feature: Feature,
+    pub enabled: bool,
+    /// What the command line set.
+    pub default: bool,
+    /// Changed at runtime; `DELETE` goes back to the default.
+    pub overridden: bool,
+}
+
+pub struct FeatureFlags {
+    disabled_by_default: BTreeSet<Feature>,
+    path: Option<PathBuf>,
+    overrides: RwLock<BTreeMap<Feature, bool>>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        FeatureFlags::new(&[])
+    }
+}
+
+impl FeatureFlags {
+    /// Every feature on except `disabled`; runtime changes last until
+    /// restart.
+    pub fn new(disabled: &[Feature]) -> Self {
+        FeatureFlags {
+            disabled_by_default: disabled.iter().copied().collect(),
+            path: None,
+            overrides: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Keeps runtime changes in `path`, starting from the ones saved there.
+    pub fn with_file(mut self, path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let overrides: BTreeMap<Feature, bool> =
+                serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+            *self.overrides.get_mut().unwrap() = overrides;
+        }
+        self.path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match self.overrides.read().unwrap().get(&feature) {
+            Some(enabled) => *enabled,
+            None => !self.disabled_by_default.contains(&feature),
+        }
+    }
+
+    /// Whether `feature` is on for the flags registered as app data; every
+    /// feature is when there are none.
+    pub fn enabled_for(req: &HttpRequest, feature: Feature) -> bool {
+        req.app_data::<web::Data<FeatureFlags>>()
+            .is_none_or(|flags| flags.is_enabled(feature))
+    }
+
+    pub fn states(&self) -> Vec<FeatureState> {
+        let overrides = self.overrides.read().unwrap();
+        Feature::ALL
+            .into_iter()
+            .map(|feature| {
+                let default = !self.disabled_by_default.contains(&feature);
+                let overridden = overrides.get(&feature).copied();
+                FeatureState {
+                    feature,
+                    enabled: overridden.unwrap_or(default),
+                    default,
+                    overridden: overridden.is_some(),
+                }
+            })
+            .collect()
+    }
+
+    fn persist(&self, overrides: &BTreeMap<Feature, bool>) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut text = serde_json::to_vec_pretty(overrides).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Turns `feature` on or off, or back to its default with `None`.
+    pub fn set(&self, feature: Feature, enabled: Option<bool>) -> std::io::Result<()> {
+        let mut overrides = self.overrides.write().unwrap();
+        let mut changed = overrides.clone();
+        match enabled {
+            Some(enabled) => changed.insert(feature, enabled),
+            None => changed.remove(&feature),
+        };
+        self.persist(&changed)?;
+        *overrides = changed;
+        Ok(())
+    }
+}
+
+/// Refuses the endpoints of disabled features.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let disabled = Feature::of(req.method(), req.path()).filter(|feature| {
+        req.app_data::<web::Data<FeatureFlags>>()
+            .is_some_and(|flags| !flags.is_enabled(*feature))
+    });
+    let Some(feature) = disabled else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+    metrics::FEATURE_DISABLED_REJECTED.inc();
+    // Hidden endpoints answer like a path that was never routed.
+    let response = match feature.hides_endpoints() {
+        true => HttpResponse::NotFound().finish(),
+        false => ApiError::FeatureDisabled {
+            feature: feature.name(),
+        }
+        .error_response(),
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
@@ -0,0 +1,133 @@
+//! Focus sessions (`POST /api/focus`): for a set time a client is served an
+//! extended blocklist (social media and video sites by default) on top of the
+//! usual one, then it reverts by itself. The extension polls
+//! `GET /api/focus/{client_id}` to show the countdown. Sessions are kept in
+//! memory and don't survive a restart.
+
+use crate::types::Blocklist;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Blocked during focus unless `--no-default-focus-domains` is given.
+pub const DEFAULT_DOMAINS: &[&str] = &[
+    "facebook.com",
+    "instagram.com",
+    "tiktok.com",
+    "twitter.com",
+    "x.com",
+    "reddit.com",
+    "snapchat.com",
+    "youtube.com",
+    "netflix.com",
+    "twitch.tv",
+];
+
+/// Longest focus session one request can start.
+pub const MAX_DURATION: std::time::Duration = std::time::Duration::from_secs(12 * 3600);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FocusSession {
+    pub client_id: String,
+    pub started_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub started_by: String,
+}
+
+impl FocusSession {
+    pub fn remaining_secs(&self) -> i64 {
+        (self.ends_at - Utc::now()).num_seconds().max(0)
+    }
+}
+
+pub struct FocusSessions {
+    domains: Vec<String>,
+    sessions: Mutex<HashMap<String, FocusSession>>,
+}
+
+impl Default for FocusSessions {
+    fn default() -> Self {
+        FocusSessions::new(true, &[])
+    }
+}
+
+impl FocusSessions {
+    pub fn new(use_defaults: bool, extra: &[String]) -> Self {
+        let mut domains: Vec<String> = if use_defaults {
+            DEFAULT_DOMAINS.iter().map(|d| d.to_string()).collect()
+        } else {
+            Vec::new()
+        };
+        for d in extra {
+            let d = d.trim().trim_end_matches('.').to_ascii_lowercase();
+            if !d.is_empty() && !domains.contains(&d) {
+                domains.push(d);
+            }
+        }
+        FocusSessions {
+            domains,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// Starts a session, or extends the running one; a new request never
+    /// shortens a session already under way.
+    pub fn start(
+        &self,
+        client_id: &str,
+        duration: chrono::Duration,
+        started_by: &str,
+    ) -> FocusSession {
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| s.ends_at > now);
+        let session = match sessions.get(client_id) {
+            Some(running) if running.ends_at >= now + duration => running.clone(),
+            Some(running) => FocusSession {
+                ends_at: now + duration,
+                ..running.clone()
+            },
+            None => FocusSession {
+                client_id: client_id.to_string(),
+                started_at: now,
+                ends_at: now + duration,
+                started_by: started_by.to_string(),
+            },
+        };
+        sessions.insert(client_id.to_string(), session.clone());
+        session
+    }
+
+    /// Ends the client's session early; returns it if one was running.
+    pub fn end(&self, client_id: &str) -> Option<FocusSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions
+            .remove(client_id)
+            .filter(|s| s.ends_at > Utc::now())
+    }
+
+    pub fn active(&self, client_id: &str) -> Option<FocusSession> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(client_id)
+            .filter(|s| s.ends_at > Utc::now())
+            .cloned()
+    }
+
+    /// Blocklist patterns while the client is in a session.
+    pub fn blocked_patterns(&self, client_id: &str) -> Vec<String> {
+        if self.active(client_id).is_none() {
+            return Vec::new();
+        }
+        self.domains
+            .iter()
+            .map(|d| Blocklist::domain_pattern(d))
+            .collect()
+    }
+}
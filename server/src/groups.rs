@@ -0,0 +1,252 @@
+//! Device groups (`--groups`): clients are assigned to named groups such as
+//! `kids-laptops` or `dev-workstations`, and a group can carry blocklist
+//! entries, blocking schedules and daily quotas for all of its members. They
+//! are merged into what each member is served (`GET /api/blocklist?client_id=`,
+//! see [`crate::policy`]) and into the quota it is held to.
+//!
+//! The file is rewritten by `PUT /api/admin/groups` and the assignment
+//! endpoints, so changes survive a restart.
+
+use crate::quotas::QuotaLimits;
+use crate::types::BlocklistEntries;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The `--groups` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupsConfig {
+    #[serde(default)]
+    pub groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Group {
+    /// Member client_ids.
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// Blocked for members at all times.
+    #[serde(default)]
+    pub blocklist: BlocklistEntries,
+    /// Blocked for members during a time window.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<Schedule>,
+    /// Daily limits for each member, in place of the server-wide ones.
+    #[serde(default, skip_serializing_if = "GroupQuotas::is_empty")]
+    pub quotas: GroupQuotas,
+}
+
+/// A recurring UTC time window, e.g. `21:00`-`07:00` on school nights. A
+/// window that ends before it starts runs past midnight; `days` (`mon`..`sun`,
+/// empty for every day) is the day it starts on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub from: String,
+    pub to: String,
+    #[serde(flatten)]
+    pub blocklist: BlocklistEntries,
+}
+
+/// Unset fields leave the server-wide limit; 0 means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GroupQuotas {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs_per_day: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events_per_day: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_day: Option<u64>,
+}
+
+impl GroupQuotas {
+    pub fn is_empty(&self) -> bool {
+        self.logs_per_day.is_none() && self.events_per_day.is_none() && self.bytes_per_day.is_none()
+    }
+}
+
+fn parse_day(s: &str) -> Option<Weekday> {
+    Some(match s.to_ascii_lowercase().as_str() {
+        "mon" => Weekday::Mon,
+        "tue" => Weekday::Tue,
+        "wed" => Weekday::Wed,
+        "thu" => Weekday::Thu,
+        "fri" => Weekday::Fri,
+        "sat" => Weekday::Sat,
+        "sun" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M").ok()
+}
+
+impl Schedule {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(day) = self.days.iter().find(|d| parse_day(d).is_none()) {
+            return Err(format!("unknown day '{}' (mon..sun)", day));
+        }
+        for t in [&self.from, &self.to] {
+            if parse_time(t).is_none() {
+                return Err(format!("'{}' is not a time like 21:00", t));
+            }
+        }
+        Ok(())
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.iter().any(|d| parse_day(d) == Some(day))
+    }
+
+    /// Whether the window covers `now`. Assumes a validated schedule.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let (Some(from), Some(to)) = (parse_time(&self.from), parse_time(&self.to)) else {
+            return false;
+        };
+        let time = now.time();
+        let today = now.weekday();
+        if from <= to {
+            self.starts_on(today) && time >= from && time < to
+        } else {
+            (self.starts_on(today) && time >= from) || (self.starts_on(today.pred()) && time < to)
+        }
+    }
+}
+
+impl GroupsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, group) in &self.groups {
+            if name.trim().is_empty() {
+                return Err("group names can't be empty".into());
+            }
+            for (i, schedule) in group.schedules.iter().enumerate() {
+                schedule
+                    .validate()
+                    .map_err(|e| format!("{}: schedule {}: {}", name, i, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Groups {
+    path: Option<PathBuf>,
+    config: RwLock<Arc<GroupsConfig>>,
+}
+
+impl Default for Groups {
+    fn default() -> Self {
+        Groups::new()
+    }
+}
+
+impl Groups {
+    /// No groups, changes kept in memory only.
+    pub fn new() -> Self {
+        Groups {
+            path: None,
+            config: RwLock::new(Arc::new(GroupsConfig::default())),
+        }
+    }
+
+    /// Reads `path` if it exists; changes are written back to it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config = if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let config: GroupsConfig = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+            config.validate()?;
+            config
+        } else {
+            GroupsConfig::default()
+        };
+        Ok(Groups {
+            path: Some(path.to_path_buf()),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn config(&self) -> Arc<GroupsConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    fn persist(&self, config: &GroupsConfig) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut text = serde_json::to_vec_pretty(config).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Swaps in a validated configuration, writing it to the file first.
+    pub fn replace(&self, config: GroupsConfig) -> std::io::Result<()> {
+        let mut current = self.config.write().unwrap();
+        self.persist(&config)?;
+        *current = Arc::new(config);
+        Ok(())
+    }
+
+    /// Adds (or with `member == false` removes) a client. Returns false for
+    /// an unknown group.
+    pub fn set_member(&self, group: &str, client_id: &str, member: bool) -> std::io::Result<bool> {
+        let mut current = self.config.write().unwrap();
+        let mut config = (**current).clone();
+        let Some(g) = config.groups.get_mut(group) else {
+            return Ok(false);
+        };
+        g.clients.retain(|c| c != client_id);
+        if member {
+            g.clients.push(client_id.to_string());
+        }
+        self.persist(&config)?;
+        *current = Arc::new(config);
+        Ok(true)
+    }
+
+    /// The groups `client_id` is in, by name.
+    pub fn groups_of(&self, client_id: &str) -> Vec<String> {
+        self.config()
+            .groups
+            .iter()
+            .filter(|(_, g)| g.clients.iter().any(|c| c == client_id))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// `global` with the client's group quotas applied. In several groups
+    /// that set the same limit, the strictest one wins.
+    pub fn quota_limits(&self, client_id: &str, global: QuotaLimits) -> QuotaLimits {
+        let config = self.config();
+        let quotas: Vec<GroupQuotas> = config
+            .groups
+            .values()
+            .filter(|g| g.clients.iter().any(|c| c == client_id))
+            .map(|g| g.quotas)
+            .collect();
+        let pick = |global: u64, field: fn(&GroupQuotas) -> Option<u64>| {
+            quotas
+                .iter()
+                .filter_map(field)
+                .reduce(|a, b| match (a, b) {
+                    (0, x) | (x, 0) => x,
+                    (a, b) => a.min(b),
+                })
+                .unwrap_or(global)
+        };
+        QuotaLimits {
+            logs_per_day: pick(global.logs_per_day, |q| q.logs_per_day),
+            events_per_day: pick(global.events_per_day, |q| q.events_per_day),
+            bytes_per_day: pick(global.bytes_per_day, |q| q.bytes_per_day),
+        }
+    }
+}
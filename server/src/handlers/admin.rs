@@ -0,0 +1,160 @@
+use crate::auth::AdminAuth;
+use crate::bans::BanList;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::sessions::SessionTracker;
+use crate::{instance, logging, server_events, simple};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+/// Startup configuration reported by `/api/admin/state`, secrets redacted.
+pub struct RuntimeConfig {
+    pub mode: String,
+    pub settings: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct LogLevelRequest {
+    /// RUST_LOG syntax, e.g. `debug` or `info,sqlx=warn`.
+    pub filter: String,
+}
+
+pub async fn get_bans(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    bans: web::Data<BanList>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let list = bans.list().await;
+    log::info!(
+        "🔨 Ban list requested from IP {}: {} active",
+        get_client_ip(&req),
+        list.len()
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "enabled": bans.is_enabled(),
+        "count": list.len(),
+        "bans": list
+    })))
+}
+
+/// Sessions reported by several client_ids or from too many IPs.
+pub async fn get_session_conflicts(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    sessions: web::Data<SessionTracker>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let conflicts = sessions.conflicts();
+    log::info!(
+        "👥 Session conflicts requested from IP {}: {}",
+        get_client_ip(&req),
+        conflicts.len()
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": conflicts.len(),
+        "conflicts": conflicts
+    })))
+}
+
+pub async fn delete_ban(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    bans: web::Data<BanList>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let ip = path.into_inner();
+    if !bans.unban(&ip).await {
+        return Err(ApiError::NotFound("no active ban for this address".into()).with("ip", ip));
+    }
+    log::info!("🔓 IP {} unbanned by {}", ip, get_client_ip(&req));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Ban lifted",
+        "ip": ip
+    })))
+}
+
+/// Buffer and queue occupancy, pool stats, uptime and the startup config.
+/// Sections that don't apply to the running mode are omitted or null.
+pub async fn get_state(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    runtime: web::Data<RuntimeConfig>,
+    bans: web::Data<BanList>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let (started_at, uptime) = instance::uptime().unzip();
+    #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+    let mut body = serde_json::json!({
+        "instance_id": instance::id(),
+        "mode": runtime.mode,
+        "started_at": started_at,
+        "uptime_secs": uptime.map(|u| u.as_secs()),
+        "log_filter": logging::filters(),
+        "buffers": req
+            .app_data::<web::Data<simple::SimpleState>>()
+            .map(|s| s.buffer_stats()),
+        "bans": {
+            "enabled": bans.is_enabled(),
+            "active": bans.list().await.len()
+        },
+        "config": runtime.settings
+    });
+
+    #[cfg(feature = "production")]
+    {
+        if runtime.mode == "hybrid" {
+            body["queues"] = serde_json::json!({
+                "warm_writer": crate::metrics::WARM_QUEUE_DEPTH.get()
+            });
+        }
+        if let Some(monitor) = req.app_data::<web::Data<crate::pool_monitor::PoolMonitor>>() {
+            body["database"] = monitor.report().1;
+        }
+        if let Some(refresher) = req.app_data::<web::Data<crate::stats::StatsRefresher>>() {
+            let (refreshed_at, age_secs, stale) = refresher.staleness();
+            body["stats_refresh"] = serde_json::json!({
+                "refreshed_at": refreshed_at,
+                "age_secs": age_secs,
+                "stale": stale
+            });
+        }
+    }
+
+    log::info!("🔧 Runtime state requested from IP {}", get_client_ip(&req));
+    Ok(HttpResponse::Ok().json(body))
+}
+
+pub async fn post_loglevel(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    body: web::Json<LogLevelRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let filter = body.filter.trim();
+    let previous = logging::set_filters(filter).map_err(ApiError::BadRequest)?;
+    // warn so the change itself shows up even under a quiet filter.
+    let client_ip = get_client_ip(&req);
+    log::warn!(
+        "🔧 Log filter changed from '{}' to '{}' by {}",
+        previous,
+        filter,
+        client_ip
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "log_filter",
+            "previous": previous,
+            "value": filter,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "previous": previous,
+        "filter": filter
+    })))
+}
@@ -0,0 +1,121 @@
+use crate::alerts::{AlertConfig, AlertRouter};
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use crate::types::ExtensionEvent;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+/// An event to try the rules on; only the fields rules can look at.
+#[derive(Deserialize)]
+pub struct TestEvent {
+    pub event_type: String,
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    #[serde(default)]
+    pub session_id: String,
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+pub async fn get_alert_rules(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    router: web::Data<AlertRouter>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rules = router.current();
+    let config = rules.config();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": router.path(),
+        "channels": config.channels,
+        "rules": config.rules
+    })))
+}
+
+/// Replaces every channel and rule. Nothing changes unless all rules parse.
+pub async fn put_alert_rules(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    router: web::Data<AlertRouter>,
+    body: web::Json<AlertConfig>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rules = router.compile(body.into_inner()).map_err(|e| {
+        let rule = e.rule;
+        let err = ApiError::BadRequest(e.to_string());
+        match rule {
+            Some(i) => err.with("rule", i),
+            None => err,
+        }
+    })?;
+    let (rule_count, channel_count) = (rules.rules().len(), rules.config().channels.len());
+    let client_ip = get_client_ip(&req);
+    router.replace(rules).map_err(|e| {
+        log::error!("❌ Writing alert rules failed: {}", e);
+        ApiError::Storage(e.to_string())
+    })?;
+    log::warn!(
+        "🔔 Alert rules replaced by {}: {} rule(s), {} channel(s)",
+        client_ip,
+        rule_count,
+        channel_count
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "alert_rules",
+            "rules": rule_count,
+            "channels": channel_count,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "rules": rule_count,
+        "channels": channel_count,
+        "persisted": router.path().is_some()
+    })))
+}
+
+/// Which rules an event would match, and where it would be sent. Nothing is
+/// sent.
+pub async fn test_alert_rules(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    router: web::Data<AlertRouter>,
+    body: web::Json<TestEvent>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    let event = ExtensionEvent {
+        client_id: body.client_id,
+        profile_id: body.profile_id,
+        session_id: body.session_id,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        user_agent: String::new(),
+        event_type: body.event_type,
+        data: body.data,
+    };
+    let rules = router.current();
+    let indexes = rules.matching(&event);
+    let matched: Vec<serde_json::Value> = indexes
+        .iter()
+        .map(|&i| {
+            let rule = &rules.rules()[i];
+            serde_json::json!({ "index": i, "rule": rule.source, "channels": rule.channels })
+        })
+        .collect();
+    let mut channels: Vec<&str> = indexes
+        .iter()
+        .flat_map(|&i| rules.rules()[i].channels.iter().map(String::as_str))
+        .collect();
+    channels.sort_unstable();
+    channels.dedup();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "matched": matched,
+        "channels": channels
+    })))
+}
@@ -0,0 +1,144 @@
+use crate::annotations::{AnnotationStore, MAX_LABEL_LEN, MAX_NOTE_LEN};
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct AnnotationRequest {
+    pub label: Option<String>,
+    pub note: Option<String>,
+}
+
+fn trimmed(s: Option<String>) -> Option<String> {
+    s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+async fn add(
+    req: actix_web::HttpRequest,
+    scope: &str,
+    target: String,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<AnnotationStore>,
+    body: web::Json<AnnotationRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    let (label, note) = (trimmed(body.label), trimmed(body.note));
+    let invalid = if label.is_none() && note.is_none() {
+        Some("an annotation needs a label or a note".to_string())
+    } else if label
+        .as_ref()
+        .is_some_and(|l| l.chars().count() > MAX_LABEL_LEN)
+    {
+        Some(format!("label is longer than {} characters", MAX_LABEL_LEN))
+    } else if note
+        .as_ref()
+        .is_some_and(|n| n.chars().count() > MAX_NOTE_LEN)
+    {
+        Some(format!("note is longer than {} characters", MAX_NOTE_LEN))
+    } else {
+        None
+    };
+    if let Some(error) = invalid {
+        return Err(ApiError::BadRequest(error));
+    }
+
+    let client_ip = get_client_ip(&req);
+    let annotation = store
+        .add(scope, &target, label, note, Some(client_ip.clone()))
+        .await
+        .map_err(|e| {
+            log::error!(
+                "❌ Storing annotation on {} {} failed: {}",
+                scope,
+                target,
+                e
+            );
+            ApiError::Database(e)
+        })?;
+    log::info!(
+        "📝 Annotation {} added to {} {} by {}",
+        annotation.id,
+        scope,
+        target,
+        client_ip
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "annotation": annotation
+    })))
+}
+
+fn list(scope: &str, target: String, store: &AnnotationStore) -> HttpResponse {
+    let annotations = store.list(scope, &target);
+    HttpResponse::Ok().json(serde_json::json!({
+        "scope": scope,
+        "target": target,
+        "annotations": annotations
+    }))
+}
+
+pub async fn post_session_annotation(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<AnnotationStore>,
+    body: web::Json<AnnotationRequest>,
+) -> Result<HttpResponse, ApiError> {
+    add(req, "session", path.into_inner(), auth, store, body).await
+}
+
+pub async fn post_client_annotation(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<AnnotationStore>,
+    body: web::Json<AnnotationRequest>,
+) -> Result<HttpResponse, ApiError> {
+    add(req, "client", path.into_inner(), auth, store, body).await
+}
+
+pub async fn get_session_annotations(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    store: web::Data<AnnotationStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(list("session", path.into_inner(), &store))
+}
+
+pub async fn get_client_annotations(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    store: web::Data<AnnotationStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(list("client", path.into_inner(), &store))
+}
+
+pub async fn delete_annotation(
+    req: actix_web::HttpRequest,
+    path: web::Path<i64>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<AnnotationStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let id = path.into_inner();
+    let deleted = store.delete(id).await.map_err(|e| {
+        log::error!("❌ Deleting annotation {} failed: {}", id, e);
+        ApiError::Database(e)
+    })?;
+    if !deleted {
+        return Err(ApiError::NotFound("annotation not found".into()).with("id", id));
+    }
+    log::info!("📝 Annotation {} deleted by {}", id, get_client_ip(&req));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Annotation deleted",
+        "id": id
+    })))
+}
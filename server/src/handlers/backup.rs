@@ -0,0 +1,514 @@
+use crate::archive::ClientArchive;
+use crate::auth::AdminAuth;
+use crate::backup::{self, Backups, Counts, Header, Job, JobKind, Reader, Record, Writer};
+use crate::error::ApiError;
+use crate::external_url;
+use crate::handlers::common::get_client_ip;
+use crate::import;
+use crate::server_events;
+use crate::simple::SimpleState;
+use crate::types::{Blocklist, ExtensionEvent, LogEntry};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+#[cfg(feature = "production")]
+use crate::production::ProductionState;
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    /// A file name from `GET /api/admin/backups`.
+    pub file: String,
+}
+
+/// The data a job backs up or restores into, and approved clients' held
+/// records are stored into.
+pub(crate) enum Target {
+    Simple(web::Data<SimpleState>),
+    #[cfg(feature = "production")]
+    Production(web::Data<ProductionState>),
+    /// Logs and events in the warm tier, which holds all of them; the
+    /// blocklist in the hot tier, which serves it.
+    #[cfg(feature = "production")]
+    Hybrid {
+        hot: web::Data<SimpleState>,
+        warm: web::Data<ProductionState>,
+    },
+}
+
+/// Identifies an event for duplicate detection: its packet id, or what it
+/// was sent as for events without one.
+fn event_key(event: &ExtensionEvent) -> String {
+    match event.data.get("packet_id").and_then(|p| p.as_str()) {
+        Some(packet_id) => packet_id.to_string(),
+        None => format!(
+            "{}\u{1f}{}\u{1f}{}",
+            event.session_id, event.timestamp, event.event_type
+        ),
+    }
+}
+
+impl Target {
+    pub(crate) fn mode(&self) -> &'static str {
+        match self {
+            Target::Simple(_) => "simple",
+            #[cfg(feature = "production")]
+            Target::Production(_) => "production",
+            #[cfg(feature = "production")]
+            Target::Hybrid { .. } => "hybrid",
+        }
+    }
+
+    async fn blocklist(&self) -> Result<Blocklist, String> {
+        match self {
+            Target::Simple(state) => Ok(state.get_blocklist()),
+            #[cfg(feature = "production")]
+            Target::Hybrid { hot, .. } => Ok(hot.get_blocklist()),
+            #[cfg(feature = "production")]
+            Target::Production(db) => db.get_blocklist().await.map_err(|e| e.to_string()),
+        }
+    }
+
+    async fn set_blocklist(&self, blocklist: Blocklist) -> Result<(), String> {
+        match self {
+            Target::Simple(state) => state.update_blocklist(blocklist),
+            #[cfg(feature = "production")]
+            Target::Hybrid { hot, .. } => hot.update_blocklist(blocklist),
+            #[cfg(feature = "production")]
+            Target::Production(db) => {
+                db.update_blocklist(blocklist)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The logs and events already stored, for duplicate detection. The
+    /// database catches its own duplicates, so this is empty there.
+    pub(crate) fn stored(&self) -> (HashSet<String>, HashSet<String>) {
+        match self {
+            Target::Simple(state) => (
+                state.log_fingerprints(),
+                state.get_extension_events().iter().map(event_key).collect(),
+            ),
+            #[cfg(feature = "production")]
+            _ => Default::default(),
+        }
+    }
+
+    /// Stores what of `entry` isn't there yet; returns whether anything was.
+    pub(crate) async fn restore_log(
+        &self,
+        mut entry: LogEntry,
+        seen: &mut HashSet<String>,
+    ) -> Result<bool, String> {
+        import::dedupe(&mut entry, seen);
+        if entry.logs.is_empty() {
+            return Ok(false);
+        }
+        match self {
+            Target::Simple(state) => {
+                state.add_log(entry);
+                Ok(true)
+            }
+            #[cfg(feature = "production")]
+            Target::Production(db) | Target::Hybrid { warm: db, .. } => db
+                .import_log(entry)
+                .await
+                .map(|inserted| inserted > 0)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub(crate) async fn restore_event(
+        &self,
+        event: ExtensionEvent,
+        seen: &mut HashSet<String>,
+    ) -> Result<bool, String> {
+        if !seen.insert(event_key(&event)) {
+            return Ok(false);
+        }
+        match self {
+            Target::Simple(state) => state.add_extension_event(event),
+            #[cfg(feature = "production")]
+            Target::Production(db) | Target::Hybrid { warm: db, .. } => {
+                if let Some(packet_id) = event.data.get("packet_id").and_then(|p| p.as_str()) {
+                    let stored = db.get_extension_event_by_packet_id(packet_id).await;
+                    if stored.map_err(|e| e.to_string())?.is_some() {
+                        return Ok(false);
+                    }
+                }
+                db.add_extension_event(event)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    log::error!("❌ Reading the backup directory failed: {}", e);
+    ApiError::Storage(e.to_string())
+}
+
+fn enabled(backups: &Backups) -> Result<(), ApiError> {
+    match backups.dir() {
+        Some(_) => Ok(()),
+        None => Err(ApiError::NotFound(
+            "Backups are disabled; start the server with --backup-dir".into(),
+        )),
+    }
+}
+
+fn accepted(req: &actix_web::HttpRequest, job: Job) -> HttpResponse {
+    let status_url = external_url::absolute(req, "/api/admin/backup/status");
+    HttpResponse::Accepted()
+        .insert_header((actix_web::http::header::LOCATION, status_url.clone()))
+        .json(serde_json::json!({
+            "success": true,
+            "job": job,
+            "status_url": status_url
+        }))
+}
+
+async fn write_backup(
+    writer: &mut Writer,
+    backups: &Backups,
+    archive: &ClientArchive,
+    target: &Target,
+    as_of: DateTime<Utc>,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+    let progress = |writer: &Writer| {
+        let counts = writer.counts();
+        backups.update(|job| job.progress = counts);
+    };
+    writer
+        .write(&Record::Blocklist(target.blocklist().await?))
+        .map_err(io)?;
+    for client in archive.list() {
+        writer.write(&Record::ArchivedClient(client)).map_err(io)?;
+    }
+    match target {
+        Target::Simple(state) => {
+            for entry in state.get_logs() {
+                writer.write(&Record::Log(entry)).map_err(io)?;
+            }
+            for event in state.get_extension_events() {
+                writer.write(&Record::Event(event)).map_err(io)?;
+            }
+        }
+        #[cfg(feature = "production")]
+        Target::Production(db) | Target::Hybrid { warm: db, .. } => {
+            let mut after = 0;
+            loop {
+                let page = db.export_logs(after, as_of, backup::PAGE).await;
+                let page = page.map_err(|e| e.to_string())?;
+                let Some(&(last, _)) = page.last() else {
+                    break;
+                };
+                after = last;
+                for (_, entry) in page {
+                    writer.write(&Record::Log(entry)).map_err(io)?;
+                }
+                progress(writer);
+            }
+            let mut after = 0;
+            loop {
+                let page = db.export_extension_events(after, as_of, backup::PAGE).await;
+                let page = page.map_err(|e| e.to_string())?;
+                let Some(&(last, _)) = page.last() else {
+                    break;
+                };
+                after = last;
+                for (_, event) in page {
+                    writer.write(&Record::Event(event)).map_err(io)?;
+                }
+                progress(writer);
+            }
+        }
+    }
+    #[cfg(not(feature = "production"))]
+    let _ = as_of;
+    progress(writer);
+    Ok(())
+}
+
+async fn run_backup(
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    target: Target,
+    started_by: String,
+) {
+    let now = Utc::now();
+    let header = Header {
+        format: backup::FORMAT.to_string(),
+        version: backup::VERSION,
+        created_at: now,
+        as_of: now,
+        mode: target.mode().to_string(),
+        instance_id: crate::instance::id().unwrap_or_default().to_string(),
+    };
+    let dir = backups.dir().expect("checked by the handler");
+    let mut writer = match Writer::create(dir, header) {
+        Ok(writer) => writer,
+        Err(e) => return failed(&backups, JobKind::Backup, e.to_string()),
+    };
+    let file = writer.file_name();
+    backups.update(|job| job.file = file.clone());
+    if let Err(e) = write_backup(&mut writer, &backups, &archive, &target, now).await {
+        writer.abandon();
+        return failed(&backups, JobKind::Backup, e);
+    }
+    match writer.finish() {
+        Ok((path, counts)) => {
+            log::info!(
+                "💾 Backup written to {}: {} logs, {} events, {} archived clients",
+                path.display(),
+                counts.logs,
+                counts.events,
+                counts.archived_clients
+            );
+            backups.finish(Ok(()));
+            server_events::record(
+                "backup_created",
+                serde_json::json!({
+                    "file": file,
+                    "counts": counts,
+                    "started_by": started_by
+                }),
+            );
+        }
+        Err(e) => failed(&backups, JobKind::Backup, e.to_string()),
+    }
+}
+
+fn failed(backups: &Backups, kind: JobKind, error: String) {
+    log::error!("❌ {} failed: {}", kind.as_str(), error);
+    backups.finish(Err(error.clone()));
+    server_events::record(
+        "backup_failed",
+        serde_json::json!({ "kind": kind, "error": error }),
+    );
+}
+
+async fn apply_restore(
+    path: &Path,
+    backups: &Backups,
+    archive: &ClientArchive,
+    target: &Target,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+    let (header, total) = backup::verify(path).map_err(io)?;
+    log::info!(
+        "💾 Restoring a {} backup taken {}: {} logs, {} events, {} archived clients",
+        header.mode,
+        header.created_at.to_rfc3339(),
+        total.logs,
+        total.events,
+        total.archived_clients
+    );
+    backups.update(|job| job.total = Some(total));
+
+    let (mut seen_logs, mut seen_events) = target.stored();
+    let mut progress = Counts::default();
+    let mut skipped = Counts::default();
+    for (done, record) in Reader::open(path).map_err(io)?.enumerate() {
+        match record.map_err(io)? {
+            Record::Header(_) | Record::End(_) => {}
+            Record::Blocklist(blocklist) => target.set_blocklist(blocklist).await?,
+            Record::ArchivedClient(client) => {
+                archive.put(client).await?;
+                progress.archived_clients += 1;
+            }
+            Record::Log(entry) => {
+                progress.logs += 1;
+                if !target.restore_log(entry, &mut seen_logs).await? {
+                    skipped.logs += 1;
+                }
+            }
+            Record::Event(event) => {
+                progress.events += 1;
+                if !target.restore_event(event, &mut seen_events).await? {
+                    skipped.events += 1;
+                }
+            }
+        }
+        if (done as u64 + 1).is_multiple_of(backup::PROGRESS_EVERY) {
+            backups.update(|job| {
+                job.progress = progress;
+                job.skipped = skipped;
+            });
+        }
+    }
+    backups.update(|job| {
+        job.progress = progress;
+        job.skipped = skipped;
+    });
+    Ok(())
+}
+
+async fn run_restore(
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    target: Target,
+    file: String,
+    started_by: String,
+) {
+    let path = backups.resolve(&file).expect("checked by the handler");
+    if let Err(e) = apply_restore(&path, &backups, &archive, &target).await {
+        return failed(&backups, JobKind::Restore, e);
+    }
+    let job = backups.job();
+    log::info!("💾 Restore of {} finished", file);
+    backups.finish(Ok(()));
+    server_events::record(
+        "backup_restored",
+        serde_json::json!({
+            "file": file,
+            "restored": job.as_ref().map(|j| j.progress),
+            "skipped": job.as_ref().map(|j| j.skipped),
+            "started_by": started_by
+        }),
+    );
+}
+
+async fn start_backup(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    target: Target,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(req).await?;
+    enabled(&backups)?;
+    let client_ip = get_client_ip(req);
+    let job = backups.start(JobKind::Backup, "", &client_ip)?;
+    log::info!("💾 Backup started by {}", client_ip);
+    actix_web::rt::spawn(run_backup(backups, archive, target, client_ip));
+    Ok(accepted(req, job))
+}
+
+async fn start_restore(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    target: Target,
+    body: RestoreRequest,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(req).await?;
+    enabled(&backups)?;
+    let found = backups.resolve(&body.file).filter(|p| p.is_file());
+    if found.is_none() {
+        return Err(ApiError::NotFound("no such backup".into()).with("file", body.file));
+    }
+    let client_ip = get_client_ip(req);
+    let job = backups.start(JobKind::Restore, &body.file, &client_ip)?;
+    log::warn!("💾 Restore of {} started by {}", body.file, client_ip);
+    actix_web::rt::spawn(run_restore(backups, archive, target, body.file, client_ip));
+    Ok(accepted(req, job))
+}
+
+pub async fn get_backups(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&backups)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "backups": backups.list().map_err(storage_error)?,
+        "dir": backups.dir()
+    })))
+}
+
+/// The job running now, or the last one since startup.
+pub async fn get_backup_status(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&backups)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "job": backups.job() })))
+}
+
+pub async fn post_backup_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    data: web::Data<SimpleState>,
+) -> Result<HttpResponse, ApiError> {
+    start_backup(&req, &auth, backups, archive, Target::Simple(data)).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_backup_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    data: web::Data<ProductionState>,
+) -> Result<HttpResponse, ApiError> {
+    start_backup(&req, &auth, backups, archive, Target::Production(data)).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_backup_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    hot: web::Data<SimpleState>,
+    warm: web::Data<ProductionState>,
+) -> Result<HttpResponse, ApiError> {
+    start_backup(&req, &auth, backups, archive, Target::Hybrid { hot, warm }).await
+}
+
+/// Adds the backup's contents to what is stored and replaces the blocklist.
+/// Logs and events already present are skipped, so restoring the same file
+/// twice is harmless.
+pub async fn post_restore_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    data: web::Data<SimpleState>,
+    body: web::Json<RestoreRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let target = Target::Simple(data);
+    start_restore(&req, &auth, backups, archive, target, body.into_inner()).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_restore_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    data: web::Data<ProductionState>,
+    body: web::Json<RestoreRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let target = Target::Production(data);
+    start_restore(&req, &auth, backups, archive, target, body.into_inner()).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_restore_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    backups: web::Data<Backups>,
+    archive: web::Data<ClientArchive>,
+    hot: web::Data<SimpleState>,
+    warm: web::Data<ProductionState>,
+    body: web::Json<RestoreRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let target = Target::Hybrid { hot, warm };
+    start_restore(&req, &auth, backups, archive, target, body.into_inner()).await
+}
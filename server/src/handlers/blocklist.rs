@@ -1,51 +1,230 @@
-use crate::handlers::common::get_client_ip;
-use crate::simple;
+use crate::auth::AdminAuth;
+use crate::blocklist_history::{BlocklistHistory, Delta};
+use crate::blocklist_rollout::{self, Arm, BlocklistRollout};
+use crate::blocklist_signing::{self, BlocklistSigner};
+use crate::error::ApiError;
+use crate::experiments::Experiments;
+use crate::focus::FocusSessions;
+use crate::groups::Groups;
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+use crate::handlers::common::{get_client_ip, meter_api_call};
+use crate::handlers::query::{BlocklistDeltaQuery, BlocklistFormat, BlocklistQuery};
+use crate::screen_time::ScreenTime;
 use crate::types::Blocklist;
+use crate::{bloom, policy};
+use crate::{server_events, simple};
 use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
 
 #[cfg(feature = "production")]
 use crate::production;
 
+/// The blocklist as served to one client and profile, starting from the
+/// candidate when a rollout puts the client in its canary; see [`policy`]
+/// for how the layers combine.
+fn for_client(
+    req: &actix_web::HttpRequest,
+    blocklist: Blocklist,
+    query: &BlocklistQuery,
+    groups: &Groups,
+    screen_time: &ScreenTime,
+    focus: &FocusSessions,
+) -> (Blocklist, Option<Arm>) {
+    let (blocklist, arm) = match req.app_data::<web::Data<BlocklistRollout>>() {
+        Some(rollout) => rollout.select(blocklist, query.client_id.as_deref()),
+        None => (blocklist, None),
+    };
+    let sources = policy::Sources {
+        groups,
+        screen_time,
+        focus,
+        experiments: req
+            .app_data::<web::Data<Experiments>>()
+            .map(|e| e.get_ref()),
+    };
+    let resolved = policy::resolve(
+        blocklist,
+        query.client_id.as_deref(),
+        query.profile_id.as_deref(),
+        &sources,
+        chrono::Utc::now(),
+    );
+    (resolved.blocklist, arm)
+}
+
+/// `body` as JSON with the version it was built from and the client's
+/// rollout arm, signed by the signer registered as app data when
+/// `--blocklist-signing-key` is set.
+fn signed(
+    req: &actix_web::HttpRequest,
+    body: &impl serde::Serialize,
+    version: u64,
+    arm: Option<Arm>,
+) -> HttpResponse {
+    let body = match serde_json::to_vec(body) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("❌ Serializing the blocklist failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("application/json")
+        .insert_header(("X-Blocklist-Version", version.to_string()));
+    match arm {
+        Some(Arm::Canary) => response.insert_header((blocklist_rollout::ROLLOUT_HEADER, "canary")),
+        Some(Arm::Stable) => response.insert_header((blocklist_rollout::ROLLOUT_HEADER, "stable")),
+        None => &mut response,
+    };
+    let signature = req
+        .app_data::<web::Data<BlocklistSigner>>()
+        .and_then(|signer| signer.sign(version, &body));
+    if let Some((key_id, signature)) = signature {
+        response
+            .insert_header((blocklist_signing::SIGNATURE_HEADER, signature))
+            .insert_header((blocklist_signing::KEY_ID_HEADER, key_id));
+    }
+    response.body(body)
+}
+
+/// The resolved blocklist in the format the client asked for, with the
+/// version of the shared lists it was built from.
+fn served(
+    req: &actix_web::HttpRequest,
+    (blocklist, arm): (Blocklist, Option<Arm>),
+    query: &BlocklistQuery,
+    version: u64,
+) -> HttpResponse {
+    match query.format {
+        BlocklistFormat::Json => signed(req, &blocklist, version, arm),
+        BlocklistFormat::Bloom => signed(
+            req,
+            &bloom::compress(blocklist, query.fp_rate.unwrap_or(bloom::DEFAULT_FP_RATE)),
+            version,
+            arm,
+        ),
+    }
+}
+
+fn delta(
+    req: &actix_web::HttpRequest,
+    blocklist: &Blocklist,
+    history: &BlocklistHistory,
+    query: &BlocklistDeltaQuery,
+) -> HttpResponse {
+    let client_id = query.client_id.as_deref();
+    let rollout = req.app_data::<web::Data<BlocklistRollout>>();
+    let (delta, arm) = match rollout.map(|r| r.select(blocklist.clone(), client_id)) {
+        // A canary client's copy isn't the shared lists, so it can't be
+        // patched: it gets the candidate whole, and after a rollback the
+        // shared lists whole.
+        Some((candidate, Some(Arm::Canary))) => (
+            Delta::full(&candidate, query.from_version, history.observe(blocklist)),
+            Some(Arm::Canary),
+        ),
+        _ if rollout.is_some_and(|r| r.take_resync(client_id)) => (
+            Delta::full(blocklist, query.from_version, history.observe(blocklist)),
+            None,
+        ),
+        selected => (
+            history.delta(blocklist, query.from_version),
+            selected.and_then(|(_, arm)| arm),
+        ),
+    };
+    log::info!(
+        "📋 Blocklist delta {} → {} requested from IP {}{}",
+        delta.from_version,
+        delta.version,
+        get_client_ip(req),
+        if delta.full { " (full list)" } else { "" }
+    );
+    signed(req, &delta, delta.version, arm)
+}
+
 pub async fn get_blocklist_simple(
     req: actix_web::HttpRequest,
     data: web::Data<simple::SimpleState>,
+    groups: web::Data<Groups>,
+    screen_time: web::Data<ScreenTime>,
+    focus: web::Data<FocusSessions>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<BlocklistQuery>,
 ) -> impl Responder {
     let client_ip = get_client_ip(&req);
+    meter_api_call(&req, query.client_id.as_deref()).await;
     let blocklist = data.get_blocklist();
+    let version = history.observe(&blocklist);
+    let resolved = for_client(&req, blocklist, &query, &groups, &screen_time, &focus);
     log::info!(
         "📋 Blocklist requested from IP {}: {} URL patterns, {} YouTube channels",
         client_ip,
-        blocklist.url_patterns.len(),
-        blocklist.youtube_channels.len()
+        resolved.0.url_patterns.len(),
+        resolved.0.youtube_channels.len()
     );
-    HttpResponse::Ok().json(blocklist)
+    served(&req, resolved, &query, version)
+}
+
+/// What changed in the shared lists since `from_version`.
+pub async fn get_blocklist_delta_simple(
+    req: actix_web::HttpRequest,
+    data: web::Data<simple::SimpleState>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<BlocklistDeltaQuery>,
+) -> impl Responder {
+    delta(&req, &data.get_blocklist(), &history, &query)
+}
+
+#[cfg(feature = "production")]
+pub async fn get_blocklist_delta_production(
+    req: actix_web::HttpRequest,
+    data: web::Data<production::ProductionState>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<BlocklistDeltaQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&get_client_ip(&req), e))?;
+    Ok(delta(&req, &blocklist, &history, &query))
 }
 
 #[cfg(feature = "production")]
 pub async fn get_blocklist_production(
     req: actix_web::HttpRequest,
     data: web::Data<production::ProductionState>,
-) -> impl Responder {
+    groups: web::Data<Groups>,
+    screen_time: web::Data<ScreenTime>,
+    focus: web::Data<FocusSessions>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<BlocklistQuery>,
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
     log::info!("📋 Blocklist requested from IP: {}", client_ip);
+    meter_api_call(&req, query.client_id.as_deref()).await;
 
-    match data.get_blocklist().await {
-        Ok(blocklist) => HttpResponse::Ok().json(blocklist),
-        Err(e) => {
-            log::error!("❌ Database error from IP {}: {}", client_ip, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Database error: {}", e),
-                "client_ip": client_ip
-            }))
-        }
-    }
+    let blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let version = history.observe(&blocklist);
+    Ok(served(
+        &req,
+        for_client(&req, blocklist, &query, &groups, &screen_time, &focus),
+        &query,
+        version,
+    ))
 }
 
 pub async fn post_blocklist_simple(
     req: actix_web::HttpRequest,
     data: web::Data<simple::SimpleState>,
+    auth: web::Data<AdminAuth>,
+    history: web::Data<BlocklistHistory>,
     blocklist: web::Json<Blocklist>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
     let client_ip = get_client_ip(&req);
     let new_blocklist = blocklist.into_inner();
 
@@ -60,25 +239,35 @@ pub async fn post_blocklist_simple(
         log::debug!("  URL pattern[{}] from IP {}: {}", idx, client_ip, pattern);
     }
     for (idx, channel) in new_blocklist.youtube_channels.iter().enumerate() {
-        log::debug!("  YouTube channel[{}] from IP {}: {}", idx, client_ip, channel);
+        log::debug!(
+            "  YouTube channel[{}] from IP {}: {}",
+            idx,
+            client_ip,
+            channel
+        );
     }
 
+    let version = history.observe(&new_blocklist);
     data.update_blocklist(new_blocklist);
 
     log::info!("✅ Blocklist updated successfully by IP: {}", client_ip);
-    HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "message": "Blocklist updated",
+        "version": version,
         "client_ip": client_ip
-    }))
+    })))
 }
 
 #[cfg(feature = "production")]
 pub async fn post_blocklist_production(
     req: actix_web::HttpRequest,
     data: web::Data<production::ProductionState>,
+    auth: web::Data<AdminAuth>,
+    history: web::Data<BlocklistHistory>,
     blocklist: web::Json<Blocklist>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
     let client_ip = get_client_ip(&req);
     let new_blocklist = blocklist.into_inner();
 
@@ -89,22 +278,273 @@ pub async fn post_blocklist_production(
         new_blocklist.youtube_channels.len()
     );
 
-    match data.update_blocklist(new_blocklist).await {
-        Ok(_) => {
-            log::info!("✅ Blocklist updated successfully by IP: {}", client_ip);
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "message": "Blocklist updated",
-                "client_ip": client_ip
-            }))
+    let stored = new_blocklist.clone();
+    data.update_blocklist(new_blocklist)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let version = history.observe(&stored);
+    log::info!("✅ Blocklist updated successfully by IP: {}", client_ip);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Blocklist updated",
+        "version": version,
+        "client_ip": client_ip
+    })))
+}
+
+/// Public keys that verify blocklist signatures, the active one last.
+pub async fn get_blocklist_keys(signer: web::Data<BlocklistSigner>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "enabled": signer.is_enabled(),
+        "keys": signer.public_keys()
+    }))
+}
+
+/// Signs with a new key from now on; the old one stays listed as retired.
+pub async fn post_rotate_blocklist_key(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BlocklistSigner>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    if !signer.is_enabled() {
+        return Err(ApiError::BadRequest(
+            "blocklist signing is off (--blocklist-signing-key)".to_string(),
+        ));
+    }
+    let key = signer.rotate().map_err(|e| {
+        log::error!("❌ Rotating the blocklist signing key failed: {}", e);
+        ApiError::Storage(e)
+    })?;
+    let client_ip = get_client_ip(&req);
+    log::warn!(
+        "🔏 Blocklist signing key rotated to {} by {}",
+        key.key_id,
+        client_ip
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "blocklist_signing_key",
+            "key_id": key.key_id,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(key))
+}
+
+/// Drops a retired key once extensions no longer need it.
+pub async fn delete_blocklist_key(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BlocklistSigner>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let key_id = path.into_inner();
+    match signer.remove(&key_id) {
+        Ok(true) => {
+            log::warn!(
+                "🔏 Blocklist signing key {} removed by {}",
+                key_id,
+                get_client_ip(&req)
+            );
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true, "key_id": key_id })))
         }
-        Err(e) => {
-            log::error!("❌ Database error from IP {}: {}", client_ip, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Database error: {}", e),
-                "client_ip": client_ip
-            }))
+        Ok(false) => {
+            Err(ApiError::NotFound("no such signing key".to_string()).with("key_id", key_id))
         }
+        Err(e) => Err(ApiError::Conflict(e).with("key_id", key_id)),
     }
 }
+
+#[derive(Deserialize)]
+pub struct NewRollout {
+    pub blocklist: Blocklist,
+    pub percent: u8,
+}
+
+#[derive(Deserialize)]
+pub struct RolloutChange {
+    pub percent: u8,
+}
+
+#[derive(Deserialize)]
+pub struct PromoteQuery {
+    /// Promote even though the blocklist changed since the rollout began.
+    #[serde(default)]
+    pub force: bool,
+}
+
+fn check_percent(percent: u8) -> Result<(), ApiError> {
+    if percent > 100 {
+        return Err(
+            ApiError::BadRequest("percent must be 0-100".to_string()).with("percent", percent)
+        );
+    }
+    Ok(())
+}
+
+fn no_rollout() -> ApiError {
+    ApiError::NotFound("no blocklist rollout is running".to_string())
+}
+
+fn record_rollout(req: &actix_web::HttpRequest, action: &str, id: &str, percent: u8) {
+    let client_ip = get_client_ip(req);
+    log::warn!(
+        "🐤 Blocklist rollout {} {} at {}% by {}",
+        id,
+        action,
+        percent,
+        client_ip
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "blocklist_rollout",
+            "rollout_id": id,
+            "action": action,
+            "percent": percent,
+            "changed_by": client_ip
+        }),
+    );
+}
+
+/// The running rollout and how each arm is doing.
+pub async fn get_rollout(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "rollout": rollout.status() })))
+}
+
+/// Serves `blocklist` to `percent`% of clients.
+pub async fn post_rollout(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+    history: web::Data<BlocklistHistory>,
+    body: web::Json<NewRollout>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    check_percent(body.percent)?;
+    let status = rollout
+        .start(
+            body.blocklist,
+            body.percent,
+            history.version(),
+            &get_client_ip(&req),
+        )
+        .map_err(ApiError::Conflict)?;
+    record_rollout(&req, "started", &status.id, status.percent);
+    Ok(HttpResponse::Created().json(status))
+}
+
+/// Widens or narrows the canary.
+pub async fn put_rollout(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+    body: web::Json<RolloutChange>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    check_percent(body.percent)?;
+    let status = rollout.set_percent(body.percent).ok_or_else(no_rollout)?;
+    record_rollout(&req, "moved", &status.id, status.percent);
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Drops the candidate; canary clients get the current list back.
+pub async fn post_rollback(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let status = rollout.rollback().ok_or_else(no_rollout)?;
+    record_rollout(&req, "rolled back", &status.id, status.percent);
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// The blocklist was changed after the rollout began: promoting would
+/// undo that change unless `force` is given.
+fn check_base(
+    rollout: &BlocklistRollout,
+    version: u64,
+    query: &PromoteQuery,
+) -> Result<(), ApiError> {
+    let Some(status) = rollout.status() else {
+        return Err(no_rollout());
+    };
+    if !query.force && status.base_version != version {
+        return Err(ApiError::Conflict(
+            "the blocklist changed since the rollout began; promote with ?force=true to replace it"
+                .to_string(),
+        )
+        .with("base_version", status.base_version)
+        .with("version", version));
+    }
+    Ok(())
+}
+
+/// Ends the rollout once its candidate is stored as version `version`.
+fn promoted(
+    req: &actix_web::HttpRequest,
+    rollout: &BlocklistRollout,
+    version: u64,
+) -> HttpResponse {
+    let status = rollout.promote();
+    if let Some(status) = &status {
+        record_rollout(req, "promoted", &status.id, 100);
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "version": version,
+        "rollout": status
+    }))
+}
+
+/// Makes the candidate the blocklist for every client.
+pub async fn post_promote_simple(
+    req: actix_web::HttpRequest,
+    data: web::Data<simple::SimpleState>,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<PromoteQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    check_base(&rollout, history.observe(&data.get_blocklist()), &query)?;
+    let candidate = rollout.candidate().ok_or_else(no_rollout)?;
+    let version = history.observe(&candidate);
+    data.update_blocklist(candidate);
+    Ok(promoted(&req, &rollout, version))
+}
+
+#[cfg(feature = "production")]
+pub async fn post_promote_production(
+    req: actix_web::HttpRequest,
+    data: web::Data<production::ProductionState>,
+    auth: web::Data<AdminAuth>,
+    rollout: web::Data<BlocklistRollout>,
+    history: web::Data<BlocklistHistory>,
+    query: web::Query<PromoteQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let current = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    check_base(&rollout, history.observe(&current), &query)?;
+    let candidate = rollout.candidate().ok_or_else(no_rollout)?;
+    let stored = candidate.clone();
+    data.update_blocklist(candidate)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let version = history.observe(&stored);
+    Ok(promoted(&req, &rollout, version))
+}
@@ -0,0 +1,192 @@
+use crate::archive::ClientArchive;
+use crate::auth::AdminAuth;
+use crate::bundle::{self, BundleSigner, Contents};
+use crate::error::ApiError;
+use crate::handlers::admin::RuntimeConfig;
+use crate::handlers::common::get_client_ip;
+use crate::types::Blocklist;
+use crate::{instance, server_events, simple};
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+fn contents(runtime: &RuntimeConfig, blocklist: Blocklist, archive: &ClientArchive) -> Contents {
+    Contents {
+        created_at: chrono::Utc::now(),
+        source: serde_json::json!({
+            "instance_id": instance::id(),
+            "mode": runtime.mode,
+            "version": env!("CARGO_PKG_VERSION")
+        }),
+        blocklist: Some(blocklist),
+        archived_clients: Some(archive.list()),
+        settings: Some(runtime.settings.clone()),
+    }
+}
+
+fn sealed_response(
+    signer: &BundleSigner,
+    contents: &Contents,
+    client_ip: &str,
+) -> Result<HttpResponse, ApiError> {
+    let bundle = signer
+        .seal(contents)
+        .map_err(|e| ApiError::BadRequest(format!("Cannot sign bundle: {}", e)))?;
+    log::info!("📦 Configuration bundle exported to {}", client_ip);
+    let filename = format!(
+        "canigoin-bundle-{}.json",
+        contents.created_at.format("%Y%m%d-%H%M%S")
+    );
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .json(bundle))
+}
+
+fn open(
+    signer: &BundleSigner,
+    body: &serde_json::Value,
+    client_ip: &str,
+) -> Result<(Contents, Vec<String>), ApiError> {
+    signer.open(body).map_err(|e| {
+        log::warn!("📦 Rejected configuration bundle from {}: {}", client_ip, e);
+        ApiError::BadRequest(format!("Invalid bundle: {}", e))
+    })
+}
+
+/// Applies the parts every mode handles the same way and builds the response.
+async fn finish_import(
+    contents: Contents,
+    mut applied: Vec<&'static str>,
+    skipped: Vec<String>,
+    archive: &ClientArchive,
+    runtime: &RuntimeConfig,
+    client_ip: &str,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(clients) = contents.archived_clients {
+        for c in &clients {
+            if archive.is_archived(&c.client_id) {
+                continue;
+            }
+            if let Err(e) = archive
+                .archive(&c.client_id, c.archived_by.clone(), c.reason.clone())
+                .await
+            {
+                log::error!("❌ Bundle import: archiving {} failed: {}", c.client_id, e);
+                return Err(ApiError::Database(e).with("applied", applied));
+            }
+        }
+        applied.push("archived_clients");
+    }
+    let settings_diff = contents
+        .settings
+        .as_ref()
+        .map(|s| bundle::settings_diff(s, &runtime.settings))
+        .unwrap_or_default();
+
+    log::warn!(
+        "📦 Configuration bundle from {} imported by {}: applied {:?}, skipped {:?}",
+        contents.source,
+        client_ip,
+        applied,
+        skipped
+    );
+    server_events::record(
+        "bundle_imported",
+        serde_json::json!({
+            "source": contents.source,
+            "created_at": contents.created_at,
+            "applied": applied,
+            "skipped": skipped,
+            "imported_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "source": contents.source,
+        "created_at": contents.created_at,
+        "applied": applied,
+        "skipped": skipped,
+        "settings_diff": settings_diff
+    })))
+}
+
+pub async fn export_bundle_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BundleSigner>,
+    runtime: web::Data<RuntimeConfig>,
+    data: web::Data<simple::SimpleState>,
+    archive: web::Data<ClientArchive>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let contents = contents(&runtime, data.get_blocklist(), &archive);
+    sealed_response(&signer, &contents, &get_client_ip(&req))
+}
+
+pub async fn import_bundle_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BundleSigner>,
+    runtime: web::Data<RuntimeConfig>,
+    data: web::Data<simple::SimpleState>,
+    archive: web::Data<ClientArchive>,
+    body: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let (mut contents, skipped) = open(&signer, &body, &client_ip)?;
+    let mut applied = Vec::new();
+    if let Some(blocklist) = contents.blocklist.take() {
+        data.update_blocklist(blocklist);
+        applied.push("blocklist");
+    }
+    finish_import(contents, applied, skipped, &archive, &runtime, &client_ip).await
+}
+
+#[cfg(feature = "production")]
+pub async fn export_bundle_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BundleSigner>,
+    runtime: web::Data<RuntimeConfig>,
+    data: web::Data<production::ProductionState>,
+    archive: web::Data<ClientArchive>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let contents = contents(&runtime, blocklist, &archive);
+    sealed_response(&signer, &contents, &client_ip)
+}
+
+#[cfg(feature = "production")]
+pub async fn import_bundle_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    signer: web::Data<BundleSigner>,
+    runtime: web::Data<RuntimeConfig>,
+    data: web::Data<production::ProductionState>,
+    archive: web::Data<ClientArchive>,
+    body: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let (mut contents, skipped) = open(&signer, &body, &client_ip)?;
+    let mut applied = Vec::new();
+    if let Some(blocklist) = contents.blocklist.take() {
+        data.update_blocklist(blocklist)
+            .await
+            .map_err(|e| db_error(&client_ip, e))?;
+        applied.push("blocklist");
+    }
+    finish_import(contents, applied, skipped, &archive, &runtime, &client_ip).await
+}
@@ -0,0 +1,164 @@
+use crate::auth::AdminAuth;
+use crate::categories::Categories;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use crate::simple;
+use crate::types::Blocklist;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+#[derive(Deserialize)]
+pub struct CategoryToggle {
+    pub blocked: bool,
+}
+
+/// Each category with its domains; `blocked` when the blocklist has a
+/// pattern for every one of them.
+fn listing(categories: &Categories, blocklist: &Blocklist) -> serde_json::Value {
+    let list: Vec<serde_json::Value> = categories
+        .all()
+        .into_iter()
+        .map(|(category, domains)| {
+            let blocked = domains.iter().all(|d| {
+                blocklist
+                    .url_patterns
+                    .contains(&Blocklist::domain_pattern(d))
+            });
+            serde_json::json!({
+                "category": category,
+                "domains": domains,
+                "blocked": blocked
+            })
+        })
+        .collect();
+    serde_json::json!({ "categories": list })
+}
+
+/// Adds or removes the category's domain patterns. Returns how many patterns
+/// changed, or 404 for an unknown category.
+fn toggle(
+    categories: &Categories,
+    category: &str,
+    blocked: bool,
+    blocklist: &mut Blocklist,
+) -> Result<usize, ApiError> {
+    let domains = categories.domains_in(category);
+    if domains.is_empty() {
+        return Err(ApiError::NotFound("unknown category".into()).with("category", category));
+    }
+    let patterns: Vec<String> = domains.into_iter().map(Blocklist::domain_pattern).collect();
+    let before = blocklist.url_patterns.len();
+    if blocked {
+        for p in patterns {
+            if !blocklist.url_patterns.contains(&p) {
+                blocklist.url_patterns.push(p);
+            }
+        }
+    } else {
+        blocklist.url_patterns.retain(|p| !patterns.contains(p));
+    }
+    Ok(before.abs_diff(blocklist.url_patterns.len()))
+}
+
+fn toggled(
+    req: &actix_web::HttpRequest,
+    category: &str,
+    blocked: bool,
+    changed: usize,
+) -> HttpResponse {
+    let client_ip = get_client_ip(req);
+    log::info!(
+        "🏷️ Category {} {} by IP {} ({} patterns changed)",
+        category,
+        if blocked { "blocked" } else { "unblocked" },
+        client_ip,
+        changed
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "category_blocked",
+            "category": category,
+            "blocked": blocked,
+            "changed_by": client_ip
+        }),
+    );
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "category": category,
+        "blocked": blocked,
+        "patterns_changed": changed
+    }))
+}
+
+pub async fn get_categories_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    categories: web::Data<Categories>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(listing(&categories, &data.get_blocklist())))
+}
+
+#[cfg(feature = "production")]
+pub async fn get_categories_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<production::ProductionState>,
+    categories: web::Data<Categories>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&get_client_ip(&req), e))?;
+    Ok(HttpResponse::Ok().json(listing(&categories, &blocklist)))
+}
+
+/// `{"blocked": true}` adds a pattern for every domain in the category to
+/// the stored blocklist; `false` takes them out again.
+pub async fn put_category_simple(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<simple::SimpleState>,
+    auth: web::Data<AdminAuth>,
+    categories: web::Data<Categories>,
+    body: web::Json<CategoryToggle>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let category = path.into_inner();
+    let mut blocklist = data.get_blocklist();
+    let changed = toggle(&categories, &category, body.blocked, &mut blocklist)?;
+    data.update_blocklist(blocklist);
+    Ok(toggled(&req, &category, body.blocked, changed))
+}
+
+#[cfg(feature = "production")]
+pub async fn put_category_production(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<production::ProductionState>,
+    auth: web::Data<AdminAuth>,
+    categories: web::Data<Categories>,
+    body: web::Json<CategoryToggle>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let category = path.into_inner();
+    let mut blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let changed = toggle(&categories, &category, body.blocked, &mut blocklist)?;
+    data.update_blocklist(blocklist)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    Ok(toggled(&req, &category, body.blocked, changed))
+}
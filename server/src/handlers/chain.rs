@@ -0,0 +1,96 @@
+use crate::auth::AdminAuth;
+use crate::chain::{self, EventChain};
+use crate::error::ApiError;
+use crate::handlers::query::ChainQuery;
+use crate::simple;
+use crate::types::ExtensionEvent;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::{db_error, get_client_ip};
+#[cfg(feature = "production")]
+use crate::production;
+
+fn enabled(chain: &EventChain) -> Result<(), ApiError> {
+    if chain.is_enabled() {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound(
+            "The event chain is disabled; start the server with --event-chain".into(),
+        ))
+    }
+}
+
+fn verified(chain: &EventChain, events: &[ExtensionEvent], query: &ChainQuery) -> HttpResponse {
+    let heads = chain.heads();
+    let checkpoints = chain.checkpoints();
+    let report = chain::verify(events, &heads, &checkpoints, query.client_id.as_deref());
+    if !report.ok {
+        log::warn!(
+            "🔗 Event chain verification found {} problems in {} chains",
+            report.issues.len(),
+            report.chains.len()
+        );
+    }
+    HttpResponse::Ok().json(report)
+}
+
+/// The current chain heads and the saved checkpoints.
+pub async fn get_chain(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    chain: web::Data<EventChain>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&chain)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "heads": chain.heads(),
+        "checkpoints": chain.checkpoints(),
+        "persisted": chain.path().is_some()
+    })))
+}
+
+pub async fn verify_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    chain: web::Data<EventChain>,
+    data: web::Data<simple::SimpleState>,
+    query: web::Query<ChainQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&chain)?;
+    Ok(verified(&chain, &data.get_extension_events(), &query))
+}
+
+/// Reads every event stored so far, a page at a time.
+#[cfg(feature = "production")]
+pub async fn verify_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    chain: web::Data<EventChain>,
+    data: web::Data<production::ProductionState>,
+    query: web::Query<ChainQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&chain)?;
+    let client_ip = get_client_ip(&req);
+    let as_of = chrono::Utc::now();
+    let mut events = Vec::new();
+    let mut after = 0;
+    loop {
+        let page = data
+            .export_extension_events(after, as_of, crate::backup::PAGE)
+            .await
+            .map_err(|e| db_error(&client_ip, e))?;
+        let Some(&(last, _)) = page.last() else {
+            break;
+        };
+        after = last;
+        events.extend(
+            page.into_iter()
+                .map(|(_, e)| e)
+                .filter(|e| e.data["category"] == "security"),
+        );
+    }
+    Ok(verified(&chain, &events, &query))
+}
@@ -0,0 +1,434 @@
+use crate::archive::ClientArchive;
+use crate::auth::AdminAuth;
+use crate::client_names::{ClientNames, NameUpdate, Source};
+use crate::clock_skew::ClockSkew;
+use crate::error::ApiError;
+use crate::experiments::Experiments;
+use crate::focus::FocusSessions;
+use crate::groups::Groups;
+use crate::handlers::common::{cached_json, get_client_ip};
+use crate::handlers::query::{ClockSkewQuery, PolicyQuery, SummaryQuery};
+use crate::policy;
+use crate::quotas::{QuotaLimits, Quotas};
+use crate::response_cache::ResponseCache;
+use crate::screen_time::{self, ScreenTime};
+use crate::server_events;
+use crate::simple;
+use crate::summary;
+use crate::types::Blocklist;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+/// Most logs read from the database for one summary (both periods).
+#[cfg(feature = "production")]
+pub const MAX_SUMMARY_LOGS: i64 = 200_000;
+
+/// Today's ingest for one client_id against its quotas. `remaining` is null
+/// for a quota that isn't set.
+pub async fn get_usage(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    quotas: web::Data<Quotas>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let usage = quotas.usage(&client_id).await;
+    let limits = quotas.limits_for(&client_id);
+    let remaining = |limit: u64, used: u64| (limit > 0).then(|| limit.saturating_sub(used));
+    log::info!(
+        "🪣 Usage for client_id={} requested from IP {}",
+        client_id,
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "client_id": client_id,
+        "date": chrono::Utc::now().date_naive(),
+        "resets_at": quotas.resets_at(),
+        "usage": usage,
+        "limits": limits,
+        "remaining": {
+            "logs_per_day": remaining(limits.logs_per_day, usage.logs),
+            "events_per_day": remaining(limits.events_per_day, usage.events),
+            "bytes_per_day": remaining(limits.bytes_per_day, usage.bytes)
+        }
+    })))
+}
+
+/// Clock skew of every client seen since startup; `?flagged=true` keeps the
+/// ones whose offset is too large or drifting.
+pub async fn get_clock_skew(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    clock_skew: web::Data<ClockSkew>,
+    query: web::Query<ClockSkewQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut clients = clock_skew.clients();
+    if query.flagged {
+        clients.retain(|c| !c.flags.is_empty());
+    }
+    log::info!(
+        "⏰ Clock skew requested from IP {}: {} clients",
+        get_client_ip(&req),
+        clients.len()
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "clients": clients })))
+}
+
+pub async fn get_client_clock_skew(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    clock_skew: web::Data<ClockSkew>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    match clock_skew.get(&client_id) {
+        Some(skew) => Ok(HttpResponse::Ok().json(skew)),
+        None => Err(
+            ApiError::NotFound("no batches seen from this client".into())
+                .with("client_id", client_id),
+        ),
+    }
+}
+
+/// Today's screen time per budgeted category for one client_id; empty when
+/// the client has no budgets.
+pub async fn get_screen_time(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    screen_time: web::Data<ScreenTime>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let categories = screen_time.usage(&client_id);
+    log::info!(
+        "⏳ Screen time for client_id={} requested from IP {}",
+        client_id,
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "client_id": client_id,
+        "date": chrono::Utc::now().date_naive(),
+        "resets_at": screen_time::resets_at(),
+        "categories": categories,
+        "blocked_patterns": screen_time.blocked_patterns(&client_id)
+    })))
+}
+
+fn summary_cache_key(client_id: &str, query: &SummaryQuery) -> String {
+    format!("summary/{}?period={:?}", client_id, query.period)
+}
+
+/// Browsing summary for one client_id over `?period=` (default `week`),
+/// from the in-memory log buffer.
+pub async fn get_summary_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    data: web::Data<simple::SimpleState>,
+    cache: web::Data<ResponseCache>,
+    query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let key = summary_cache_key(&client_id, &query);
+    if let Some(summary) = cache.get(&key) {
+        return Ok(cached_json(&summary, true));
+    }
+    let summary = summary::summarize(
+        &client_id,
+        query.period,
+        chrono::Utc::now(),
+        &data.get_logs(),
+    );
+    log::info!(
+        "🧾 Summary for client_id={} requested from IP {}",
+        client_id,
+        get_client_ip(&req)
+    );
+    let summary = serde_json::to_value(summary).unwrap_or_default();
+    cache.put(key, &summary);
+    Ok(cached_json(&summary, false))
+}
+
+#[cfg(feature = "production")]
+pub async fn get_summary_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    data: web::Data<production::ProductionState>,
+    cache: web::Data<ResponseCache>,
+    query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let key = summary_cache_key(&client_id, &query);
+    if let Some(summary) = cache.get(&key) {
+        return Ok(cached_json(&summary, true));
+    }
+    let client_ip = get_client_ip(&req);
+    let now = chrono::Utc::now();
+    let (from, _) = query.period.window(now);
+    let logs = data
+        .get_client_logs(&client_id, from, now, MAX_SUMMARY_LOGS)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let mut summary = summary::summarize(&client_id, query.period, now, &logs);
+    summary.truncated = logs.len() as i64 >= MAX_SUMMARY_LOGS;
+    log::info!(
+        "🧾 Summary for client_id={} requested from IP {} ({} logs read)",
+        client_id,
+        client_ip,
+        logs.len()
+    );
+    let summary = serde_json::to_value(summary).unwrap_or_default();
+    cache.put(key, &summary);
+    Ok(cached_json(&summary, false))
+}
+
+fn effective_policy(
+    req: &actix_web::HttpRequest,
+    client_id: &str,
+    blocklist: Blocklist,
+    query: &PolicyQuery,
+    sources: &policy::Sources,
+) -> HttpResponse {
+    let global = req
+        .app_data::<web::Data<Quotas>>()
+        .map_or(QuotaLimits::default(), |q| q.limits());
+    let resolved = policy::EffectivePolicy {
+        policy: policy::resolve(
+            blocklist,
+            Some(client_id),
+            query.profile_id.as_deref(),
+            sources,
+            chrono::Utc::now(),
+        ),
+        quotas: policy::resolve_quotas(sources.groups, client_id, global),
+    };
+    log::info!(
+        "🧭 Effective policy for client_id={} requested from IP {}: {} layer(s), {} URL patterns",
+        client_id,
+        get_client_ip(req),
+        resolved.policy.layers.len(),
+        resolved.policy.blocklist.url_patterns.len()
+    );
+    HttpResponse::Ok().json(resolved)
+}
+
+/// What the client is served by `/api/blocklist?client_id=` and held to by
+/// the quotas, layer by layer.
+pub async fn get_effective_policy_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    data: web::Data<simple::SimpleState>,
+    groups: web::Data<Groups>,
+    (screen_time, focus): (web::Data<ScreenTime>, web::Data<FocusSessions>),
+    query: web::Query<PolicyQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let sources = policy::Sources {
+        groups: &groups,
+        screen_time: &screen_time,
+        focus: &focus,
+        experiments: req
+            .app_data::<web::Data<Experiments>>()
+            .map(|e| e.get_ref()),
+    };
+    Ok(effective_policy(
+        &req,
+        &path,
+        data.get_blocklist(),
+        &query,
+        &sources,
+    ))
+}
+
+#[cfg(feature = "production")]
+pub async fn get_effective_policy_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    data: web::Data<production::ProductionState>,
+    groups: web::Data<Groups>,
+    (screen_time, focus): (web::Data<ScreenTime>, web::Data<FocusSessions>),
+    query: web::Query<PolicyQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let blocklist = data
+        .get_blocklist()
+        .await
+        .map_err(|e| db_error(&get_client_ip(&req), e))?;
+    let sources = policy::Sources {
+        groups: &groups,
+        screen_time: &screen_time,
+        focus: &focus,
+        experiments: req
+            .app_data::<web::Data<Experiments>>()
+            .map(|e| e.get_ref()),
+    };
+    Ok(effective_policy(&req, &path, blocklist, &query, &sources))
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveRequest {
+    pub reason: Option<String>,
+}
+
+pub async fn get_archived_clients(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    archive: web::Data<ClientArchive>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let list = archive.list();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": list.len(),
+        "clients": list
+    })))
+}
+
+/// Archives a client: its data stays queryable, new ingest gets `410 Gone`.
+/// The body (`{"reason": "..."}`) is optional.
+pub async fn archive_client(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    archive: web::Data<ClientArchive>,
+    body: Option<web::Json<ArchiveRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let client_ip = get_client_ip(&req);
+    let reason = body.and_then(|b| b.into_inner().reason);
+    let entry = archive
+        .archive(&client_id, Some(client_ip.clone()), reason.clone())
+        .await
+        .map_err(|e| {
+            log::error!("❌ Archiving client {} failed: {}", client_id, e);
+            ApiError::Database(e)
+        })?;
+    log::info!("🗄️ Client {} archived by {}", client_id, client_ip);
+    server_events::record(
+        "client_archived",
+        serde_json::json!({ "client_id": client_id, "reason": reason, "by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Client archived",
+        "client": entry
+    })))
+}
+
+pub async fn restore_client(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    archive: web::Data<ClientArchive>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let client_ip = get_client_ip(&req);
+    let restored = archive.restore(&client_id).await.map_err(|e| {
+        log::error!("❌ Restoring client {} failed: {}", client_id, e);
+        ApiError::Database(e)
+    })?;
+    if !restored {
+        return Err(
+            ApiError::NotFound("client is not archived".into()).with("client_id", client_id)
+        );
+    }
+    log::info!("🗄️ Client {} restored by {}", client_id, client_ip);
+    server_events::record(
+        "client_restored",
+        serde_json::json!({ "client_id": client_id, "by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Client restored",
+        "client_id": client_id
+    })))
+}
+
+/// Every client name, set through the API or synced.
+pub async fn get_client_names(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    names: web::Data<ClientNames>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "clients": names.list() })))
+}
+
+/// Names a client; a later directory sync leaves the name alone.
+pub async fn put_client_name(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    names: web::Data<ClientNames>,
+    body: web::Json<NameUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let client_ip = get_client_ip(&req);
+    let name = body
+        .into_inner()
+        .into_name(&client_id, Source::Manual, chrono::Utc::now())
+        .map_err(|e| ApiError::BadRequest(e).with("client_id", &client_id))?;
+    names.set(name.clone()).map_err(|e| {
+        log::error!("❌ Naming client {} failed: {}", client_id, e);
+        ApiError::Storage(e.to_string())
+    })?;
+    log::info!(
+        "🏷️ Client {} named {:?} by {}",
+        client_id,
+        name.display(),
+        client_ip
+    );
+    server_events::record(
+        "client_named",
+        serde_json::json!({ "client_id": client_id, "name": name.display(), "by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Client named",
+        "client": name
+    })))
+}
+
+pub async fn delete_client_name(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    names: web::Data<ClientNames>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let client_ip = get_client_ip(&req);
+    let removed = names.remove(&client_id).map_err(|e| {
+        log::error!("❌ Removing the name of client {} failed: {}", client_id, e);
+        ApiError::Storage(e.to_string())
+    })?;
+    if !removed {
+        return Err(ApiError::NotFound("client has no name".into()).with("client_id", client_id));
+    }
+    log::info!("🏷️ Name of client {} removed by {}", client_id, client_ip);
+    server_events::record(
+        "client_unnamed",
+        serde_json::json!({ "client_id": client_id, "by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Client name removed",
+        "client_id": client_id
+    })))
+}
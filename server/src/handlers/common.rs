@@ -1,31 +1,102 @@
-use actix_web::HttpRequest;
+use crate::archive::ClientArchive;
+use crate::blocklist_rollout::BlocklistRollout;
+use crate::client_names::ClientNames;
+use crate::clock_skew::ClockSkew;
+use crate::counters::{BatchSummary, IngestCounters};
+use crate::distinct::DistinctCounters;
+use crate::enrollment::{Enrollment, HeldRef};
+use crate::error::ApiError;
+use crate::experiments::Experiments;
+use crate::handlers::query::ResponseDetail;
+use crate::ip_filter::TrustedProxies;
+use crate::ldap::{self, LdapDirectory};
+use crate::metering::{Meter, Metering};
+use crate::metrics;
+use crate::quotas::{Quotas, Usage};
+use crate::response_cache::ResponseCache;
+use crate::sessions::SessionTracker;
+use crate::tail::LogTail;
+use crate::types::{check_client_id, LogEntry};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::net::IpAddr;
 
 pub fn get_client_ip(req: &HttpRequest) -> String {
-    if let Some(peer_addr) = req.peer_addr() {
-        return peer_addr.ip().to_string();
+    client_addr(req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Where the request came from, seen through `--trusted-proxy` proxies.
+pub fn client_addr(req: &HttpRequest) -> Option<IpAddr> {
+    match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_addr(req),
+        None => TrustedProxies::default().client_addr(req),
     }
-    if let Some(forwarded_for) = req.headers().get("x-forwarded-for") {
-        if let Ok(forwarded_str) = forwarded_for.to_str() {
-            if let Some(first_ip) = forwarded_str.split(',').next() {
-                return first_ip.trim().to_string();
-            }
-        }
+}
+
+pub fn domain_from_url(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
     }
-    if let Some(real_ip) = req.headers().get("x-real-ip") {
-        if let Ok(real_ip_str) = real_ip.to_str() {
-            return real_ip_str.to_string();
-        }
+    let rest = if let Some(pos) = s.find("://") {
+        &s[pos + 3..]
+    } else {
+        s
+    };
+    let host = rest.split(&['/', '?', '#'][..]).next().unwrap_or(rest);
+    let domain = host.split(':').next().unwrap_or(host).trim();
+    if domain.is_empty() {
+        None
+    } else {
+        Some(domain.to_string())
+    }
+}
+
+/// Parses short durations like `30s`, `15m`, `24h` or `7d`. A bare number is seconds.
+pub fn parse_duration(s: &str) -> Option<chrono::Duration> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+    let n: i64 = num.parse().ok()?;
+    match unit {
+        "s" => chrono::Duration::try_seconds(n),
+        "m" => chrono::Duration::try_minutes(n),
+        "h" => chrono::Duration::try_hours(n),
+        "d" => chrono::Duration::try_days(n),
+        "w" => chrono::Duration::try_weeks(n),
+        _ => None,
     }
-    "unknown".to_string()
 }
 
+/// First two bytes of every gzip stream (RFC 1952).
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// How far a body gunzipped here may expand. Bodies sent with
+/// `Content-Encoding: gzip` are decoded by actix within the body limit; this
+/// bounds the ones decoded by [`decompress_body_if_needed`].
+pub const MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// The request body as text. It is gunzipped when `Content-Encoding: gzip` is
+/// sent, or when the header is missing but the body starts with the gzip magic
+/// number (counted in `canigoin_gzip_without_header_total`). A body that fails
+/// to decompress is used as plain UTF-8; one that expands past
+/// [`MAX_DECOMPRESSED_BYTES`] is refused with `413`.
 pub fn decompress_body_if_needed(
     req: &HttpRequest,
     body: &actix_web::web::Bytes,
-) -> Result<String, actix_web::HttpResponse> {
-    use flate2::read::GzDecoder;
-    use std::io::Read;
+) -> Result<String, ApiError> {
+    decompress_body_within(req, body, MAX_DECOMPRESSED_BYTES)
+}
 
+/// [`decompress_body_if_needed`] with another limit on the decompressed size.
+pub fn decompress_body_within(
+    req: &HttpRequest,
+    body: &actix_web::web::Bytes,
+    limit: usize,
+) -> Result<String, ApiError> {
     let content_encoding = req
         .headers()
         .get("content-encoding")
@@ -33,19 +104,305 @@ pub fn decompress_body_if_needed(
         .unwrap_or("");
 
     if content_encoding == "gzip" {
-        let mut decoder = GzDecoder::new(&body[..]);
-        let mut decompressed = String::new();
-        match decoder.read_to_string(&mut decompressed) {
-            Ok(_) => Ok(decompressed),
+        match gunzip(body, limit) {
+            Ok(Some(decompressed)) => Ok(decompressed),
+            Ok(None) => Err(decompressed_too_large(req, limit)),
             Err(e) => {
                 log::warn!(
                     "Failed to decompress gzip body ({}). Falling back to plain body.",
                     e
                 );
-                Ok(String::from_utf8_lossy(&body).to_string())
+                Ok(String::from_utf8_lossy(body).to_string())
+            }
+        }
+    } else if body.starts_with(&GZIP_MAGIC) {
+        match gunzip(body, limit) {
+            Ok(Some(decompressed)) => {
+                metrics::GZIP_WITHOUT_HEADER.inc();
+                log::debug!(
+                    "Gzip body without Content-Encoding from IP {} ({} -> {} bytes)",
+                    get_client_ip(req),
+                    body.len(),
+                    decompressed.len()
+                );
+                Ok(decompressed)
+            }
+            Ok(None) => Err(decompressed_too_large(req, limit)),
+            Err(e) => {
+                log::warn!(
+                    "Body looks gzip-compressed but failed to decompress ({}). Using it as plain body.",
+                    e
+                );
+                Ok(String::from_utf8_lossy(body).to_string())
             }
         }
     } else {
-        Ok(String::from_utf8_lossy(&body).to_string())
+        Ok(String::from_utf8_lossy(body).to_string())
+    }
+}
+
+/// `None` when `body` expands past `limit`; decompression stops there.
+fn gunzip(body: &[u8], limit: usize) -> std::io::Result<Option<String>> {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(body)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > limit {
+        return Ok(None);
+    }
+    String::from_utf8(decompressed)
+        .map(Some)
+        .map_err(std::io::Error::other)
+}
+
+pub fn decompressed_too_large(req: &HttpRequest, limit: usize) -> ApiError {
+    metrics::DECOMPRESSED_TOO_LARGE.inc();
+    log::warn!(
+        "📦 Gzip body from IP {} refused: expands past {} bytes",
+        get_client_ip(req),
+        limit
+    );
+    ApiError::PayloadTooLarge(format!(
+        "Body expands past {} bytes when decompressed",
+        limit
+    ))
+    .with("max_decompressed_bytes", limit)
+}
+
+/// Per-client checks every ingest handler runs after parsing a batch: a
+/// malformed client id or an archived client is refused, then the batch is
+/// charged against the client's daily quotas and metered to its org.
+pub async fn admit_client(
+    req: &HttpRequest,
+    client_id: Option<&str>,
+    usage: Usage,
+) -> Result<(), ApiError> {
+    if let Some(id) = client_id {
+        check_client_id(id).map_err(ApiError::BadRequest)?;
+    }
+    if let Some(archive) = req.app_data::<web::Data<ClientArchive>>() {
+        archive.check(client_id)?;
+    }
+    if let Some(quotas) = req.app_data::<web::Data<Quotas>>() {
+        quotas.charge(client_id, usage).await?;
+    }
+    if let Some(metering) = req.app_data::<web::Data<Metering>>() {
+        let meter = Meter {
+            logs: usage.logs,
+            events: usage.events,
+            stored_bytes: usage.bytes,
+            api_calls: 1,
+        };
+        metering.record(client_id, meter).await;
+    }
+    Ok(())
+}
+
+/// Meters a request that doesn't store anything, such as a blocklist
+/// download, as one API call of the client's org.
+pub async fn meter_api_call(req: &HttpRequest, client_id: Option<&str>) {
+    if let Some(metering) = req.app_data::<web::Data<Metering>>() {
+        let meter = Meter {
+            api_calls: 1,
+            ..Meter::default()
+        };
+        metering.record(client_id, meter).await;
+    }
+}
+
+/// With `--enrollment-dir`, a batch from a client that isn't approved yet is
+/// set aside instead of stored; the `202` to answer it with is returned.
+pub async fn hold_unenrolled(
+    req: &HttpRequest,
+    client_id: Option<&str>,
+    record: HeldRef<'_>,
+) -> Result<Option<HttpResponse>, ApiError> {
+    let Some(enrollment) = req.app_data::<web::Data<Enrollment>>() else {
+        return Ok(None);
+    };
+    if !enrollment.is_enabled() {
+        return Ok(None);
+    }
+    if let Some(client_id) = client_id {
+        if enrollment.is_approved(client_id).await {
+            return Ok(None);
+        }
+    }
+    let client_ip = get_client_ip(req);
+    let pending = enrollment.hold(client_id, &client_ip, record).await?;
+    log::info!(
+        "⏳ Held batch from unenrolled client_id={} (IP {}): {} logs, {} events pending",
+        pending.client_id,
+        client_ip,
+        pending.logs,
+        pending.events
+    );
+    Ok(Some(HttpResponse::Accepted().json(serde_json::json!({
+        "success": true,
+        "pending_approval": true,
+        "message": "Client is not enrolled yet; data held until an admin approves it",
+        "client_id": pending.client_id,
+        "client_ip": client_ip
+    }))))
+}
+
+/// The client's estimated clock offset in milliseconds, updated with a batch
+/// stamped `timestamp` that arrived now; `None` without a client_id or an
+/// RFC 3339 timestamp.
+pub fn observe_clock_skew(
+    req: &HttpRequest,
+    client_id: Option<&str>,
+    timestamp: &str,
+) -> Option<i64> {
+    req.app_data::<web::Data<ClockSkew>>()?
+        .observe(client_id?, timestamp)
+}
+
+/// Records which client and IP reported `session_id`, raising a
+/// `session_conflict` event when it is already another client's.
+pub fn observe_session(req: &HttpRequest, session_id: &str, client_id: Option<&str>) {
+    if let Some(sessions) = req.app_data::<web::Data<SessionTracker>>() {
+        sessions.observe(session_id, client_id, &get_client_ip(req));
+    }
+}
+
+/// With `--ldap-config`, names the client after the user the batch reports.
+pub fn observe_user(req: &HttpRequest, entry: &LogEntry) {
+    let (Some(client_id), Some(username)) = (entry.client_id.as_deref(), entry.username.as_deref())
+    else {
+        return;
+    };
+    if let (Some(directory), Some(names)) = (
+        req.app_data::<web::Data<LdapDirectory>>(),
+        req.app_data::<web::Data<ClientNames>>(),
+    ) {
+        ldap::observe(directory, names, client_id, username);
+    }
+}
+
+/// Hands an accepted batch to the `/api/logs/tail?follow=true` readers.
+pub fn publish_to_tail(req: &HttpRequest, entry: &LogEntry) {
+    if let Some(tail) = req.app_data::<web::Data<LogTail>>() {
+        tail.publish(entry);
+    }
+}
+
+/// Adds the batch to the distinct URL, domain, session and client counts.
+pub fn observe_distinct(req: &HttpRequest, entry: &LogEntry) {
+    if let Some(distinct) = req.app_data::<web::Data<DistinctCounters>>() {
+        distinct.observe(entry);
+    }
+}
+
+/// Counts the batch towards invalidating cached stats and summaries.
+pub fn observe_ingest(req: &HttpRequest, entry: &LogEntry) {
+    if let Some(cache) = req.app_data::<web::Data<ResponseCache>>() {
+        cache.record_ingest(entry.logs.len());
+    }
+}
+
+/// Adds an accepted batch to the running ingest totals and to the
+/// client's arm of a blocklist rollout and variants of experiments.
+pub fn count_batch(req: &HttpRequest, client_id: Option<&str>, batch: &BatchSummary) {
+    if let Some(counters) = req.app_data::<web::Data<IngestCounters>>() {
+        counters.record(client_id, batch);
+    }
+    if let Some(rollout) = req.app_data::<web::Data<BlocklistRollout>>() {
+        rollout.record_logs(client_id, batch.logs, batch.blocked);
+    }
+    if let Some(experiments) = req.app_data::<web::Data<Experiments>>() {
+        experiments.record_logs(client_id, batch);
+    }
+}
+
+/// A response from [`ResponseCache`], marked `X-Cache: HIT` or `MISS`.
+pub fn cached_json(value: &serde_json::Value, hit: bool) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("X-Cache", if hit { "HIT" } else { "MISS" }))
+        .json(value)
+}
+
+/// Warnings shared by every payload kind.
+pub fn envelope_warnings(session_id: &str, timestamp: &str, user_agent: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if session_id.is_empty() {
+        warnings.push("session_id is empty".to_string());
+    }
+    if user_agent.is_empty() {
+        warnings.push("user_agent is empty".to_string());
+    }
+    if chrono::DateTime::parse_from_rfc3339(timestamp).is_err() {
+        warnings.push(format!("timestamp '{}' is not RFC 3339", timestamp));
+    }
+    warnings
+}
+
+/// Response to `?dry_run=true` on an ingest route: the payload has been
+/// decompressed, parsed and enriched as usual, and the resulting record is
+/// returned instead of stored. Dry runs don't count against quotas or feed the
+/// stateful detectors.
+pub fn dry_run_response(
+    req: &HttpRequest,
+    decompressed_bytes: usize,
+    record: &impl serde::Serialize,
+    summary: serde_json::Value,
+    warnings: Vec<String>,
+) -> HttpResponse {
+    let encoding = req
+        .headers()
+        .get("content-encoding")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("identity");
+    log::info!(
+        "🧪 Dry run from IP {}: {} bytes, {} warning(s)",
+        get_client_ip(req),
+        decompressed_bytes,
+        warnings.len()
+    );
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "dry_run": true,
+        "stored": false,
+        "content_encoding": encoding,
+        "decompressed_bytes": decompressed_bytes,
+        "summary": summary,
+        "warnings": warnings,
+        "record": record
+    }))
+}
+
+/// The answer to an accepted ingest request at the `?response=` level:
+/// nothing, `summary`, or for `detailed` `summary` with the fields of
+/// `details` added. Callers build `details` only when it is asked for.
+pub fn ingest_response(
+    detail: ResponseDetail,
+    summary: serde_json::Value,
+    details: Option<serde_json::Value>,
+) -> HttpResponse {
+    if detail == ResponseDetail::Minimal {
+        return HttpResponse::NoContent().finish();
+    }
+    let mut body = summary;
+    if let (Some(body), Some(serde_json::Value::Object(details))) = (body.as_object_mut(), details)
+    {
+        body.extend(details);
+    }
+    HttpResponse::Ok().json(body)
+}
+
+/// Logs a failed database call and turns it into the error response;
+/// timeouts become a 504 with a hint (see [`ApiError::DatabaseTimeout`]).
+#[cfg(feature = "production")]
+pub fn db_error(client_ip: &str, e: sqlx::Error) -> ApiError {
+    let err = ApiError::from(e);
+    match &err {
+        ApiError::DatabaseTimeout { .. } => {
+            log::warn!("⏱️ Database query timed out for IP {}: {}", client_ip, err)
+        }
+        _ => log::error!("❌ Database error from IP {}: {}", client_ip, err),
     }
+    err.with("client_ip", client_ip)
 }
@@ -1,32 +1,29 @@
-use crate::handlers::common::get_client_ip;
+use crate::annotations::AnnotationStore;
+use crate::archive::ClientArchive;
+use crate::auth::AdminAuth;
+use crate::client_names::ClientNames;
+use crate::error::ApiError;
+use crate::handlers::common::{domain_from_url, get_client_ip};
+use crate::handlers::query::{DashboardQuery, FieldsQuery};
+use crate::homograph::unicode_host;
+use crate::reputation::ReputationService;
+use crate::roles::Role;
 use crate::simple;
+use crate::triage::TriageStore;
+use crate::types::ExtensionEvent;
+use crate::users::UserStore;
 use actix_web::{web, HttpResponse, Responder};
 
-fn domain_from_url(s: &str) -> Option<String> {
-    let s = s.trim();
-    if s.is_empty() {
-        return None;
-    }
-    let rest = if let Some(pos) = s.find("://") {
-        &s[pos + 3..]
-    } else {
-        s
-    };
-    let host = rest.split(&['/', '?', '#'][..]).next().unwrap_or(rest);
-    let domain = host.split(':').next().unwrap_or(host).trim();
-    if domain.is_empty() {
-        None
-    } else {
-        Some(domain.to_string())
-    }
-}
-
 fn extract_domains(data: &serde_json::Value) -> (String, String) {
     let page_domain = data
         .get("url")
         .and_then(|v| v.as_str())
         .and_then(domain_from_url)
-        .or_else(|| data.get("host").and_then(|v| v.as_str()).and_then(domain_from_url))
+        .or_else(|| {
+            data.get("host")
+                .and_then(|v| v.as_str())
+                .and_then(domain_from_url)
+        })
         .unwrap_or_default();
     let script_domain = data
         .get("scriptUrl")
@@ -36,21 +33,26 @@ fn extract_domains(data: &serde_json::Value) -> (String, String) {
     (page_domain, script_domain)
 }
 
-pub async fn get_dashboard_events_simple(
-    req: actix_web::HttpRequest,
-    data: web::Data<simple::SimpleState>,
-) -> impl Responder {
-    let _client_ip = get_client_ip(&req);
-    let filter = req
-        .query_string()
-        .split('&')
-        .find_map(|p| {
-            let (k, v) = p.split_once('=')?;
-            if k == "filter" { Some(v) } else { None }
-        })
-        .unwrap_or("all");
+/// Archived clients are left out of dashboard listings unless the request
+/// passes `include_archived=true`. Returns the archive to filter against.
+pub(crate) fn hidden_clients<'a>(
+    query: &DashboardQuery,
+    archive: &'a ClientArchive,
+) -> Option<&'a ClientArchive> {
+    (!query.include_archived).then_some(archive)
+}
 
-    let events = data.get_extension_events();
+/// Builds the dashboard rows (newest first) for `events`, which are in
+/// arrival order.
+pub(crate) fn summarize_events(
+    events: &[ExtensionEvent],
+    query: &DashboardQuery,
+    reputation: &ReputationService,
+    hidden: Option<&ClientArchive>,
+    annotations: &AnnotationStore,
+    triage: &TriageStore,
+    names: &ClientNames,
+) -> Vec<serde_json::Value> {
     let mut out: Vec<serde_json::Value> = Vec::with_capacity(events.len());
 
     for (i, e) in events.iter().rev().enumerate() {
@@ -60,12 +62,18 @@ pub async fn get_dashboard_events_simple(
             .and_then(|v| v.as_str())
             .unwrap_or("general");
 
-        let matches_filter = match filter {
-            "security" => category == "security",
-            "javascript" => category == "javascript",
-            _ => true,
-        };
-        if !matches_filter {
+        if !query.filter.matches(category) {
+            continue;
+        }
+        if let (Some(archive), Some(cid)) = (hidden, e.client_id.as_deref()) {
+            if archive.is_archived(cid) {
+                continue;
+            }
+        }
+        if query.profile_id.is_some() && e.profile_id != query.profile_id {
+            continue;
+        }
+        if !query.in_range(e) {
             continue;
         }
 
@@ -80,80 +88,279 @@ pub async fn get_dashboard_events_simple(
         } else {
             packet_id
         };
+        let status = triage.status(&packet_id);
+        if query.status.is_some_and(|wanted| wanted != status) {
+            continue;
+        }
+        let assignee = triage.assignee(&packet_id);
+        if query
+            .assignee
+            .as_ref()
+            .is_some_and(|wanted| assignee.as_ref() != Some(wanted))
+        {
+            continue;
+        }
         let (page_domain, script_domain) = extract_domains(&e.data);
         let risk_score = e
             .data
             .get("detection")
             .and_then(|d| d.get("riskScore"))
             .and_then(|v| v.as_u64())
-            .map(|n| n as i64);
+            .map(|n| n as i64)
+            .or_else(|| {
+                [&script_domain, &page_domain]
+                    .iter()
+                    .filter(|d| !d.is_empty())
+                    .filter_map(|d| reputation.cached_score(d))
+                    .max()
+                    .map(|n| n as i64)
+            });
         out.push(serde_json::json!({
             "packet_id": packet_id,
             "event_type": e.event_type,
             "category": category,
             "page_domain": page_domain,
             "script_domain": script_domain,
+            "page_domain_unicode": unicode_host(&page_domain),
+            "script_domain_unicode": unicode_host(&script_domain),
             "client_id": e.client_id,
+            "client_name": e.client_id.as_deref().and_then(|cid| names.resolve(cid)),
+            "profile_id": e.profile_id,
             "session_id": e.session_id,
             "timestamp": e.timestamp,
             "user_agent": e.user_agent,
             "risk_score": risk_score,
+            "status": status,
+            "assignee": assignee,
+            "comments": triage.comment_count(&packet_id),
+            "annotations": annotations.for_event(&e.session_id, e.client_id.as_deref()),
         }));
     }
 
-    HttpResponse::Ok().json(serde_json::json!({ "events": out }))
+    out
+}
+
+pub async fn get_dashboard_events_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    reputation: web::Data<ReputationService>,
+    (archive, names): (web::Data<ClientArchive>, web::Data<ClientNames>),
+    (annotations, triage): (web::Data<AnnotationStore>, web::Data<TriageStore>),
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let events = data.get_extension_events();
+    let hidden = hidden_clients(&query, &archive);
+    let mut out = summarize_events(
+        &events,
+        &query,
+        &reputation,
+        hidden,
+        &annotations,
+        &triage,
+        &names,
+    );
+    if let Some(fields) = &query.fields {
+        out = out
+            .into_iter()
+            .map(|row| fields.project_value(row))
+            .collect();
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "events": out })))
+}
+
+/// Columns of `/api/dashboard/events/export`, from the events endpoint's
+/// rows.
+const EXPORT_COLUMNS: [&str; 15] = [
+    "packet_id",
+    "timestamp",
+    "event_type",
+    "category",
+    "client_id",
+    "profile_id",
+    "session_id",
+    "page_domain",
+    "script_domain",
+    "risk_score",
+    "status",
+    "assignee",
+    "comments",
+    "user_agent",
+    "client_name",
+];
+
+/// Rows per chunk of the export's body.
+const EXPORT_CHUNK_ROWS: usize = 500;
+
+/// A cell's text. Text a spreadsheet would run as a formula (`=`, `+`, `-`,
+/// `@`, or a tab or carriage return first) gets a leading `'`.
+fn csv_cell(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::Null => return String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        other => return other.to_string(),
+    };
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text
+    }
+}
+
+fn csv_rows(rows: impl IntoIterator<Item = [String; 15]>) -> web::Bytes {
+    let mut out = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        // Records of one length written to memory can't fail.
+        out.write_record(row).expect("CSV into memory");
+    }
+    web::Bytes::from(out.into_inner().expect("CSV into memory"))
+}
+
+/// The dashboard rows as a CSV download, sent in chunks as it is written.
+pub(crate) fn export_events(rows: Vec<serde_json::Value>, query: &DashboardQuery) -> HttpResponse {
+    use futures_util::StreamExt;
+    let filename = format!(
+        "events-{}-{}.csv",
+        query.filter.name(),
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    );
+    let count = rows.len();
+    let header = csv_rows([EXPORT_COLUMNS.map(String::from)]);
+    let body = futures_util::stream::iter(rows)
+        .chunks(EXPORT_CHUNK_ROWS)
+        .map(|chunk| {
+            let cells = chunk
+                .iter()
+                .map(|row| EXPORT_COLUMNS.map(|c| csv_cell(&row[c])));
+            Ok::<_, std::convert::Infallible>(csv_rows(cells))
+        });
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .insert_header(("X-Export-Rows", count.to_string()))
+        .streaming(futures_util::stream::once(async move { Ok(header) }).chain(body))
+}
+
+/// `/api/dashboard/events` with the same filters, as CSV.
+pub async fn export_dashboard_events_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    reputation: web::Data<ReputationService>,
+    (archive, names): (web::Data<ClientArchive>, web::Data<ClientNames>),
+    (annotations, triage): (web::Data<AnnotationStore>, web::Data<TriageStore>),
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let events = data.get_extension_events();
+    let hidden = hidden_clients(&query, &archive);
+    let rows = summarize_events(
+        &events,
+        &query,
+        &reputation,
+        hidden,
+        &annotations,
+        &triage,
+        &names,
+    );
+    log::info!(
+        "📤 {} dashboard events exported to IP {}",
+        rows.len(),
+        get_client_ip(&req)
+    );
+    Ok(export_events(rows, &query))
 }
 
 pub async fn get_dashboard_packet_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
     path: web::Path<String>,
     data: web::Data<simple::SimpleState>,
-) -> impl Responder {
+    names: web::Data<ClientNames>,
+    query: web::Query<FieldsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
     let packet_id = path.into_inner();
     let events = data.get_extension_events();
+    match find_packet(&events, &packet_id) {
+        Some(e) => Ok(packet(e, &names, &query)),
+        None => Err(ApiError::NotFound("packet not found".into()).with("packet_id", packet_id)),
+    }
+}
+
+/// The whole event with its client's name, or the fields asked for.
+pub(crate) fn packet(
+    event: &ExtensionEvent,
+    names: &ClientNames,
+    query: &FieldsQuery,
+) -> HttpResponse {
+    let mut value = serde_json::to_value(event).unwrap_or_default();
+    if let Some(map) = value.as_object_mut() {
+        let name = event
+            .client_id
+            .as_deref()
+            .and_then(|cid| names.resolve(cid));
+        map.insert("client_name".to_string(), serde_json::json!(name));
+    }
+    match &query.fields {
+        Some(fields) => HttpResponse::Ok().json(fields.project_value(value)),
+        None => HttpResponse::Ok().json(value),
+    }
+}
 
+pub(crate) fn find_packet<'a>(
+    events: &'a [ExtensionEvent],
+    packet_id: &str,
+) -> Option<&'a ExtensionEvent> {
     if let Some(stripped) = packet_id.strip_prefix("evt-") {
         if let Ok(idx) = stripped.parse::<usize>() {
             if idx < events.len() {
                 let rev_idx = events.len() - 1 - idx;
                 if let Some(e) = events.get(rev_idx) {
-                    return HttpResponse::Ok().json(e);
+                    return Some(e);
                 }
             }
         }
     }
 
-    for e in events.iter().rev() {
-        if e.data
-            .get("packet_id")
-            .and_then(|v| v.as_str())
-            == Some(packet_id.as_str())
-        {
-            return HttpResponse::Ok().json(e);
-        }
-    }
-
-    HttpResponse::NotFound().json(serde_json::json!({
-        "error": "packet not found",
-        "packet_id": packet_id
-    }))
+    events
+        .iter()
+        .rev()
+        .find(|e| e.data.get("packet_id").and_then(|v| v.as_str()) == Some(packet_id))
 }
 
 pub async fn get_dashboard_clients_simple(
     req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
     data: web::Data<simple::SimpleState>,
-) -> impl Responder {
-    let _client_ip = get_client_ip(&req);
+    archive: web::Data<ClientArchive>,
+    names: web::Data<ClientNames>,
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let hidden = hidden_clients(&query, &archive);
     let events = data.get_extension_events();
     let mut ids: std::collections::HashSet<String> = std::collections::HashSet::new();
     for e in events.iter() {
+        if query.profile_id.is_some() && e.profile_id != query.profile_id {
+            continue;
+        }
         if let Some(ref cid) = e.client_id {
-            if !cid.is_empty() {
+            if !cid.is_empty() && !hidden.is_some_and(|a| a.is_archived(cid)) {
                 ids.insert(cid.clone());
             }
         }
     }
+    let named: serde_json::Map<String, serde_json::Value> = ids
+        .iter()
+        .filter_map(|id| Some((id.clone(), names.resolve(id)?.into())))
+        .collect();
     let list: Vec<String> = ids.into_iter().collect();
-    HttpResponse::Ok().json(serde_json::json!({ "clients": list }))
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "clients": list, "names": named })))
 }
 
 pub async fn serve_dashboard() -> impl Responder {
@@ -162,8 +369,101 @@ pub async fn serve_dashboard() -> impl Responder {
         .body(include_str!("../../static/dashboard.html"))
 }
 
+pub async fn serve_dashboard_script() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(include_str!("../../static/dashboard.js"))
+}
+
+/// Applies the saved theme before the page paints, from `<head>`.
+pub async fn serve_theme_script() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(include_str!("../../static/dashboard-theme.js"))
+}
+
 pub async fn serve_logo() -> impl Responder {
     HttpResponse::Ok()
         .content_type("image/png")
         .body(include_bytes!("../../static/logo.png").as_slice())
 }
+
+/// Either the admin token or a local user's name and password.
+#[derive(serde::Deserialize)]
+pub struct LoginRequest {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Trades the admin token, or a local user's password, for a session cookie
+/// and the CSRF token the page sends back with every change.
+pub async fn post_login(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    body: web::Json<LoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let (login, user, role) = match (body.token, body.username, body.password) {
+        (Some(token), None, None) => (
+            auth.login(&req, &token).await?,
+            "admin-token".to_string(),
+            Role::Admin,
+        ),
+        (None, Some(username), Some(password)) => {
+            let login = auth
+                .login_user(&req, users.clone(), &username, &password)
+                .await?;
+            let role = users.get(&username).map_or(Role::Viewer, |u| u.role);
+            (login, username, role)
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "send either token, or username and password".to_string(),
+            ))
+        }
+    };
+    log::info!(
+        "🔐 Dashboard login of {} ({}) from IP {}",
+        user,
+        role.name(),
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Ok()
+        .cookie(auth.session_cookie(&req, &login))
+        .json(serde_json::json!({
+            "success": true,
+            "user": user,
+            "role": role,
+            "csrf_token": login.csrf_token,
+            "expires_at": login.expires_at
+        })))
+}
+
+/// The user, role and CSRF token of the current session, so a reloaded page
+/// can pick them up again; `401` without one, with where to log in through
+/// single sign-on when it is configured.
+pub async fn get_session(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+) -> Result<HttpResponse, ApiError> {
+    match auth.session(&req).await {
+        Some(session) => Ok(HttpResponse::Ok().json(session)),
+        None if auth.oidc().is_some() => {
+            Err(ApiError::Unauthorized.with("sso_login", "/api/auth/oidc/login"))
+        }
+        None => Err(ApiError::Unauthorized),
+    }
+}
+
+pub async fn post_logout(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    auth.logout(&req).await;
+    Ok(HttpResponse::Ok()
+        .cookie(crate::auth::expired_session_cookie())
+        .json(serde_json::json!({ "success": true })))
+}
@@ -0,0 +1,360 @@
+use crate::auth::AdminAuth;
+use crate::detection::{DetectionRules, HistoryTest, NewRule, Rule, RuleChange, Subject, Target};
+use crate::error::ApiError;
+use crate::handlers::alerts::TestEvent;
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use crate::simple;
+use crate::types::{ExtensionEvent, LogEntry, NetworkLog};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[cfg(feature = "production")]
+use crate::detection::MAX_TEST_RECORDS;
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound("no such detection rule".to_string()).with("rule", id)
+}
+
+fn record(req: &actix_web::HttpRequest, action: &str, id: &str, version: Option<u32>) {
+    let client_ip = get_client_ip(req);
+    log::warn!("🧩 Detection rule {}: {} by {}", id, action, client_ip);
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "detection_rule",
+            "rule": id,
+            "action": action,
+            "version": version,
+            "changed_by": client_ip
+        }),
+    );
+}
+
+pub async fn get_rules(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let list: Vec<serde_json::Value> = rules.list().iter().map(|r| r.view(false)).collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": rules.path(),
+        "rules": list
+    })))
+}
+
+/// The rule with every version.
+pub async fn get_rule(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rule = rules.get(&path).ok_or_else(|| not_found(&path))?;
+    Ok(HttpResponse::Ok().json(rule.view(true)))
+}
+
+/// Adds a rule as version 1, checked from the next batch or event on.
+pub async fn post_rule(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    body: web::Json<NewRule>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rule = rules.create(body.into_inner(), &get_client_ip(&req))?;
+    record(&req, "created", &rule.id, Some(1));
+    Ok(HttpResponse::Created().json(rule.view(true)))
+}
+
+/// Changes the source, the switch or the description; the first two make a
+/// new version.
+pub async fn put_rule(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    path: web::Path<String>,
+    body: web::Json<RuleChange>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rule = rules
+        .update(&path, body.into_inner(), &get_client_ip(&req))?
+        .ok_or_else(|| not_found(&path))?;
+    record(&req, "updated", &rule.id, Some(rule.current().version));
+    Ok(HttpResponse::Ok().json(rule.view(true)))
+}
+
+#[derive(Deserialize)]
+pub struct RestoreBody {
+    pub version: u32,
+}
+
+/// Brings back an earlier version's source and switch as a new version.
+pub async fn post_restore(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    path: web::Path<String>,
+    body: web::Json<RestoreBody>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let rule = rules
+        .restore(&path, body.version, &get_client_ip(&req))?
+        .ok_or_else(|| not_found(&path))?;
+    let action = format!("restored version {}", body.version);
+    record(&req, &action, &rule.id, Some(rule.current().version));
+    Ok(HttpResponse::Ok().json(rule.view(true)))
+}
+
+pub async fn delete_rule(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    if !rules.delete(&path)? {
+        return Err(not_found(&path));
+    }
+    record(&req, "deleted", &path, None);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true, "rule": *path })))
+}
+
+/// What to try a rule on: requests of a batch for a log rule, an event for
+/// an event rule.
+#[derive(Deserialize)]
+pub struct Sample {
+    #[serde(default)]
+    pub logs: Vec<NetworkLog>,
+    /// The batch's fields the requests are checked with.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub session_id: String,
+    #[serde(default)]
+    pub user_agent: String,
+    pub event: Option<TestEvent>,
+}
+
+impl Sample {
+    /// Which of the requests (or whether the event) `rule` matches.
+    fn try_rule(self, rule: &Rule) -> Result<serde_json::Value, ApiError> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let (checked, matches): (usize, Vec<usize>) = match rule.target {
+            Target::Logs => {
+                let entry = LogEntry {
+                    client_id: self.client_id,
+                    profile_id: None,
+                    session_id: self.session_id,
+                    timestamp: now,
+                    user_agent: self.user_agent,
+                    logs: self.logs,
+                    username: None,
+                    clock_skew_ms: None,
+                };
+                let matches = (0..entry.logs.len())
+                    .filter(|&i| rule.matches(Subject::Log(&entry, &entry.logs[i])))
+                    .collect();
+                (entry.logs.len(), matches)
+            }
+            Target::Events => {
+                let Some(event) = self.event else {
+                    return Err(ApiError::BadRequest(
+                        "an event rule is tried on an 'event'".to_string(),
+                    ));
+                };
+                let event = ExtensionEvent {
+                    client_id: event.client_id,
+                    profile_id: event.profile_id,
+                    session_id: event.session_id,
+                    timestamp: now,
+                    user_agent: String::new(),
+                    event_type: event.event_type,
+                    data: event.data,
+                };
+                let matched = rule.matches(Subject::Event(&event));
+                (1, if matched { vec![0] } else { Vec::new() })
+            }
+        };
+        Ok(serde_json::json!({
+            "target": rule.target,
+            "severity": rule.severity,
+            "checked": checked,
+            "matches": matches
+        }))
+    }
+}
+
+/// `POST /api/rules/test`: a rule that isn't saved, and a sample.
+#[derive(Deserialize)]
+pub struct RuleTest {
+    pub source: String,
+    #[serde(flatten)]
+    pub sample: Sample,
+}
+
+/// Which of the sample's requests (or whether its event) the rule matches.
+/// Nothing is saved or raised.
+pub async fn test_rule(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    body: web::Json<RuleTest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    let rule = Rule::parse(&body.source).map_err(ApiError::BadRequest)?;
+    Ok(HttpResponse::Ok().json(body.sample.try_rule(&rule)?))
+}
+
+/// `POST /api/rules/{id}/test`: a sample, or the logs or events sent from
+/// `from` until `to` (now by default).
+#[derive(Deserialize)]
+pub struct SavedRuleTest {
+    /// The current version by default, enabled or not.
+    pub version: Option<u32>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub sample: Sample,
+}
+
+/// The rule version to test, and the range to test it on if there is one.
+fn saved_rule(
+    rules: &DetectionRules,
+    id: &str,
+    body: &SavedRuleTest,
+) -> Result<(u32, Rule, Option<HistoryTest>), ApiError> {
+    let rule = rules.get(id).ok_or_else(|| not_found(id))?;
+    let version = match body.version {
+        Some(v) => rule.version(v).ok_or_else(|| {
+            ApiError::NotFound(format!("rule {} has no version {}", id, v)).with("rule", id)
+        })?,
+        None => rule.current(),
+    };
+    let parsed = Rule::parse(&version.source).map_err(ApiError::BadRequest)?;
+    let history = match (body.from, body.to) {
+        (None, None) => None,
+        (None, Some(_)) => {
+            return Err(ApiError::BadRequest(
+                "a time range needs 'from'".to_string(),
+            ))
+        }
+        (Some(from), to) => {
+            let to = to.unwrap_or_else(Utc::now);
+            if from >= to {
+                return Err(ApiError::BadRequest(
+                    "'from' must be before 'to'".to_string(),
+                ));
+            }
+            Some(HistoryTest::new(from, to))
+        }
+    };
+    Ok((version.version, parsed, history))
+}
+
+fn tested(
+    req: &actix_web::HttpRequest,
+    id: &str,
+    version: u32,
+    rule: &Rule,
+    history: HistoryTest,
+) -> HttpResponse {
+    log::info!(
+        "🧩 Detection rule {} v{} tried on history by {}: {} of {} matched",
+        id,
+        version,
+        get_client_ip(req),
+        history.matched,
+        history.checked
+    );
+    let mut result = serde_json::to_value(&history).unwrap_or_default();
+    result["rule"] = serde_json::json!(id);
+    result["version"] = serde_json::json!(version);
+    result["target"] = serde_json::json!(rule.target);
+    result["severity"] = serde_json::json!(rule.severity);
+    HttpResponse::Ok().json(result)
+}
+
+fn tested_sample(
+    id: &str,
+    version: u32,
+    rule: &Rule,
+    sample: Sample,
+) -> Result<HttpResponse, ApiError> {
+    let mut result = sample.try_rule(rule)?;
+    result["rule"] = serde_json::json!(id);
+    result["version"] = serde_json::json!(version);
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Runs a saved rule, enabled or not, on a sample or on stored history.
+/// Nothing is raised.
+pub async fn test_saved_rule_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    data: web::Data<simple::SimpleState>,
+    path: web::Path<String>,
+    body: web::Json<SavedRuleTest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    let (version, rule, history) = saved_rule(&rules, &path, &body)?;
+    let Some(mut history) = history else {
+        return tested_sample(&path, version, &rule, body.sample);
+    };
+    match rule.target {
+        Target::Logs => history.check_logs(&rule, &data.get_logs()),
+        Target::Events => history.check_events(&rule, &data.get_extension_events()),
+    }
+    Ok(tested(&req, &path, version, &rule, history))
+}
+
+/// Reads the newest [`MAX_TEST_RECORDS`] logs or events stored before `to`.
+#[cfg(feature = "production")]
+pub async fn test_saved_rule_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    rules: web::Data<DetectionRules>,
+    data: web::Data<production::ProductionState>,
+    path: web::Path<String>,
+    body: web::Json<SavedRuleTest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    let (version, rule, history) = saved_rule(&rules, &path, &body)?;
+    let Some(mut history) = history else {
+        return tested_sample(&path, version, &rule, body.sample);
+    };
+    let client_ip = get_client_ip(&req);
+    let to = body.to.unwrap_or_else(Utc::now);
+    let limit = MAX_TEST_RECORDS as i64;
+    let read = match rule.target {
+        Target::Logs => {
+            let logs = data
+                .get_logs_before(to, limit)
+                .await
+                .map_err(|e| db_error(&client_ip, e))?;
+            history.check_logs(&rule, &logs);
+            logs.len()
+        }
+        Target::Events => {
+            let events = data
+                .get_extension_events_before(to, limit)
+                .await
+                .map_err(|e| db_error(&client_ip, e))?;
+            history.check_events(&rule, &events);
+            events.len()
+        }
+    };
+    // Older rows may still be in the range.
+    history.truncated |= read >= MAX_TEST_RECORDS;
+    Ok(tested(&req, &path, version, &rule, history))
+}
@@ -0,0 +1,53 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::query::DomainsQuery;
+use crate::simple;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+pub async fn get_domains_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    query: web::Query<DomainsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let since = query.new_since;
+    let domains = data.get_domains(since);
+    log::info!(
+        "🌍 Domains requested from IP {}: {} domains (new_since={:?})",
+        client_ip,
+        domains.len(),
+        since
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": domains.len(),
+        "domains": domains
+    })))
+}
+
+#[cfg(feature = "production")]
+pub async fn get_domains_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<production::ProductionState>,
+    query: web::Query<DomainsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let since = query.new_since;
+    let domains = data
+        .get_domains(since)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": domains.len(),
+        "domains": domains
+    })))
+}
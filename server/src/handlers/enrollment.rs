@@ -0,0 +1,191 @@
+use crate::auth::AdminAuth;
+use crate::enrollment::{Enrollment, Held};
+use crate::error::ApiError;
+use crate::handlers::backup::Target;
+use crate::handlers::common::get_client_ip;
+use crate::simple::SimpleState;
+use crate::{alerts, chain, server_events, worm};
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::production::ProductionState;
+
+fn enabled(enrollment: &Enrollment) -> Result<(), ApiError> {
+    if enrollment.is_enabled() {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound(
+            "enrollment is disabled; start the server with --enrollment-dir".to_string(),
+        ))
+    }
+}
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    log::error!("❌ Updating the enrollment directory failed: {}", e);
+    ApiError::Storage(e.to_string())
+}
+
+/// Approved and pending clients.
+pub async fn get_enrollment(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enrollment: web::Data<Enrollment>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&enrollment)?;
+    let pending = enrollment.pending().await;
+    let approved = enrollment.approved().await;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "pending_count": pending.len(),
+        "approved_count": approved.len(),
+        "pending": pending,
+        "approved": approved
+    })))
+}
+
+/// Approves the client and stores what it sent while pending, as it would
+/// have been stored on arrival.
+async fn approve(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    enrollment: &Enrollment,
+    client_id: &str,
+    target: Target,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(req).await?;
+    enabled(enrollment)?;
+    let client_ip = get_client_ip(req);
+    let (approval, held) = enrollment
+        .approve(client_id, &client_ip)
+        .await
+        .map_err(storage_error)?;
+
+    let (mut seen_logs, mut seen_events) = target.stored();
+    let (mut logs, mut events) = (0u64, 0u64);
+    for record in held {
+        let stored = match record {
+            Held::Log(entry) => {
+                let count = entry.logs.len() as u64;
+                let stored = target.restore_log(entry, &mut seen_logs).await;
+                logs += count;
+                stored
+            }
+            Held::Event(mut event) => {
+                if event.data.get("category").and_then(|c| c.as_str()) == Some("security") {
+                    chain::link(&mut event);
+                    worm::append(&event);
+                    alerts::raise(&event);
+                }
+                events += 1;
+                target.restore_event(event, &mut seen_events).await
+            }
+        };
+        if let Err(e) = stored {
+            log::error!(
+                "❌ Storing held data of client_id={} failed: {}",
+                client_id,
+                e
+            );
+            return Err(ApiError::Storage(e).with("client_id", client_id));
+        }
+    }
+
+    log::warn!(
+        "✅ Client {} approved by {} ({} mode): {} held logs, {} held events stored",
+        client_id,
+        client_ip,
+        target.mode(),
+        logs,
+        events
+    );
+    server_events::record(
+        "client_enrolled",
+        serde_json::json!({
+            "client_id": client_id,
+            "approved_by": client_ip,
+            "logs": logs,
+            "events": events
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "approval": approval,
+        "logs_stored": logs,
+        "events_stored": events
+    })))
+}
+
+pub async fn post_approve_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enrollment: web::Data<Enrollment>,
+    data: web::Data<SimpleState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    approve(&req, &auth, &enrollment, &path, Target::Simple(data)).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_approve_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enrollment: web::Data<Enrollment>,
+    data: web::Data<ProductionState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    approve(&req, &auth, &enrollment, &path, Target::Production(data)).await
+}
+
+#[cfg(feature = "production")]
+pub async fn post_approve_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enrollment: web::Data<Enrollment>,
+    hot: web::Data<SimpleState>,
+    warm: web::Data<ProductionState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    approve(
+        &req,
+        &auth,
+        &enrollment,
+        &path,
+        Target::Hybrid { hot, warm },
+    )
+    .await
+}
+
+/// Drops a pending client and everything it sent. It stays unapproved, so
+/// its next batch starts a new pending entry.
+pub async fn post_reject(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enrollment: web::Data<Enrollment>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    enabled(&enrollment)?;
+    let client_id = path.into_inner();
+    let client_ip = get_client_ip(&req);
+    let Some(pending) = enrollment.reject(&client_id).await.map_err(storage_error)? else {
+        return Err(ApiError::NotFound(format!(
+            "client {} is not pending approval",
+            client_id
+        )));
+    };
+    log::warn!(
+        "🗑️ Pending client {} rejected by {}: {} logs, {} events dropped",
+        client_id,
+        client_ip,
+        pending.logs,
+        pending.events
+    );
+    server_events::record(
+        "client_rejected",
+        serde_json::json!({ "client_id": client_id, "rejected_by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "rejected": pending
+    })))
+}
@@ -0,0 +1,91 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::experiments::{self, Experiments, NewExperiment};
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use actix_web::{web, HttpResponse};
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound("no such experiment".to_string()).with("experiment", id)
+}
+
+fn record(req: &actix_web::HttpRequest, action: &str, id: &str) {
+    let client_ip = get_client_ip(req);
+    log::warn!("🧪 Experiment {}: {} by {}", id, action, client_ip);
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "experiment",
+            "experiment": id,
+            "action": action,
+            "changed_by": client_ip
+        }),
+    );
+}
+
+pub async fn get_experiments(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    experiments: web::Data<Experiments>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "experiments": experiments.list() })))
+}
+
+/// Starts splitting clients between the variants.
+pub async fn post_experiment(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    experiments: web::Data<Experiments>,
+    body: web::Json<NewExperiment>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    experiments::validate(&body)
+        .map_err(|e| ApiError::BadRequest(e).with("experiment", &body.id))?;
+    let id = body.id.clone();
+    let results = experiments
+        .start(body, &get_client_ip(&req))
+        .map_err(|e| ApiError::Conflict(e).with("experiment", &id))?;
+    record(&req, "started", &id);
+    Ok(HttpResponse::Created().json(results))
+}
+
+/// Outcome metrics per variant.
+pub async fn get_results(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    experiments: web::Data<Experiments>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let results = experiments.results(&path).ok_or_else(|| not_found(&path))?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Stops serving the variants; the results stay.
+pub async fn post_stop(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    experiments: web::Data<Experiments>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let results = experiments.stop(&path).ok_or_else(|| not_found(&path))?;
+    record(&req, "stopped", &path);
+    Ok(HttpResponse::Ok().json(results))
+}
+
+pub async fn delete_experiment(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    experiments: web::Data<Experiments>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    if !experiments.delete(&path) {
+        return Err(not_found(&path));
+    }
+    record(&req, "deleted", &path);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true, "experiment": *path })))
+}
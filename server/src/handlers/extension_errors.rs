@@ -0,0 +1,64 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::extension_errors::{ErrorReport, ErrorReports};
+use crate::handlers::common::get_client_ip;
+use crate::metrics;
+use actix_web::{web, HttpResponse};
+
+/// Counts one exception the extension hit. Like the other ingest endpoints,
+/// it needs no token.
+pub async fn post_error(
+    req: actix_web::HttpRequest,
+    store: web::Data<ErrorReports>,
+    body: web::Json<ErrorReport>,
+) -> Result<HttpResponse, ApiError> {
+    let client_ip = get_client_ip(&req);
+    let report = body
+        .into_inner()
+        .into_error(chrono::Utc::now())
+        .map_err(ApiError::BadRequest)?;
+    let error = store.record(report).await.map_err(|e| {
+        log::error!(
+            "❌ Storing extension error from {} failed: {}",
+            client_ip,
+            e
+        );
+        ApiError::Database(e)
+    })?;
+    metrics::EXTENSION_ERRORS.inc();
+    log::warn!(
+        "🐛 Extension error {} from {} (seen {} times): {}",
+        error.hash,
+        client_ip,
+        error.count,
+        error.message.lines().next().unwrap_or_default()
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "hash": error.hash,
+        "count": error.count,
+        "new": error.count == 1
+    })))
+}
+
+pub async fn get_errors(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<ErrorReports>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "errors": store.list() })))
+}
+
+pub async fn get_error(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<ErrorReports>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    match store.get(&path) {
+        Some(error) => Ok(HttpResponse::Ok().json(error)),
+        None => Err(ApiError::NotFound("no such extension error".to_string()).with("hash", &*path)),
+    }
+}
@@ -1,9 +1,28 @@
-use crate::handlers::common::{decompress_body_if_needed, get_client_ip};
+use crate::alerts;
+use crate::blocklist_rollout::BlocklistRollout;
+use crate::chain;
+use crate::detection::{self, DetectionRules, RuleMatch};
+use crate::enrollment::HeldRef;
+use crate::error::ApiError;
+use crate::event_data::EventDataLimit;
+use crate::experiments::Experiments;
+use crate::features::{Feature, FeatureFlags};
+use crate::handlers::common::{
+    admit_client, decompress_body_if_needed, dry_run_response, envelope_warnings, get_client_ip,
+    hold_unenrolled, ingest_response, observe_clock_skew, observe_session,
+};
+use crate::handlers::query::{IngestQuery, ResponseDetail};
+use crate::lake;
+use crate::metrics;
 use crate::packet_id;
+use crate::quotas::Usage;
 use crate::simple;
 use crate::types::ExtensionEvent;
-use actix_web::{web, HttpResponse, Responder};
+use crate::worm;
+use actix_web::{web, HttpResponse};
 
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
 #[cfg(feature = "production")]
 use crate::production;
 
@@ -14,13 +33,25 @@ fn insert_packet_and_category(
 ) -> ExtensionEvent {
     match event.data {
         serde_json::Value::Object(ref mut obj) => {
-            obj.insert("packet_id".to_string(), serde_json::Value::String(packet_id.to_string()));
-            obj.insert("category".to_string(), serde_json::Value::String(category.to_string()));
+            obj.insert(
+                "packet_id".to_string(),
+                serde_json::Value::String(packet_id.to_string()),
+            );
+            obj.insert(
+                "category".to_string(),
+                serde_json::Value::String(category.to_string()),
+            );
         }
         other => {
             let mut obj = serde_json::Map::new();
-            obj.insert("packet_id".to_string(), serde_json::Value::String(packet_id.to_string()));
-            obj.insert("category".to_string(), serde_json::Value::String(category.to_string()));
+            obj.insert(
+                "packet_id".to_string(),
+                serde_json::Value::String(packet_id.to_string()),
+            );
+            obj.insert(
+                "category".to_string(),
+                serde_json::Value::String(category.to_string()),
+            );
             obj.insert("data".to_string(), other);
             event.data = serde_json::Value::Object(obj);
         }
@@ -28,28 +59,201 @@ fn insert_packet_and_category(
     event
 }
 
+/// Stores the client's clock offset in `data.clock_skew_ms`, and checks the
+/// session against the other clients'.
+fn record_client_checks(req: &actix_web::HttpRequest, event: &mut ExtensionEvent) {
+    observe_session(req, &event.session_id, event.client_id.as_deref());
+    let skew = observe_clock_skew(req, event.client_id.as_deref(), &event.timestamp);
+    if let (Some(ms), Some(data)) = (skew, event.data.as_object_mut()) {
+        data.insert("clock_skew_ms".to_string(), ms.into());
+    }
+}
+
+/// Counts error events towards the client's arm of a blocklist rollout, and
+/// override requests towards its experiment variants.
+fn observe_outcome_event(req: &actix_web::HttpRequest, event: &ExtensionEvent) {
+    if let Some(rollout) = req.app_data::<web::Data<BlocklistRollout>>() {
+        rollout.record_event(event.client_id.as_deref(), &event.event_type);
+    }
+    if let Some(experiments) = req.app_data::<web::Data<Experiments>>() {
+        experiments.record_event(event.client_id.as_deref(), &event.event_type);
+    }
+}
+
+/// Turns detection rule matches into security events; linking, exporting
+/// and alerting them is up to the caller.
+pub fn rule_match_events(
+    found: Vec<RuleMatch>,
+    client_id: Option<&str>,
+    session_id: &str,
+    client_ip: &str,
+) -> Vec<ExtensionEvent> {
+    let mut events = Vec::new();
+    for found in found {
+        metrics::DETECTION_RULE_MATCHES.inc();
+        let (packet_id, event) = server_security_event(
+            client_id.map(str::to_string),
+            session_id,
+            detection::MATCH_EVENT,
+            found.to_event_data(),
+        );
+        log::error!(
+            "🧩 DETECTION RULE {} v{} MATCHED from IP {}: client_id={}, matches={}, url={} (packet_id={})",
+            found.rule.id,
+            found.rule.version,
+            client_ip,
+            client_id.unwrap_or("-"),
+            found.matches,
+            found.urls.first().map(String::as_str).unwrap_or("-"),
+            packet_id
+        );
+        events.push(event);
+    }
+    events
+}
+
+/// Security events for the detection rules an incoming event matches,
+/// linked, exported and alerted on. None while `anomaly_detection` is
+/// disabled.
+fn event_rule_matches(
+    req: &actix_web::HttpRequest,
+    event: &ExtensionEvent,
+    client_ip: &str,
+) -> Vec<ExtensionEvent> {
+    let Some(rules) = req.app_data::<web::Data<DetectionRules>>() else {
+        return Vec::new();
+    };
+    if !FeatureFlags::enabled_for(req, Feature::AnomalyDetection) {
+        return Vec::new();
+    }
+    let found = rules.check_event(event);
+    let mut events = rule_match_events(
+        found,
+        event.client_id.as_deref(),
+        &event.session_id,
+        client_ip,
+    );
+    for event in &mut events {
+        chain::link(event);
+        worm::append(event);
+        alerts::raise(event);
+    }
+    events
+}
+
+/// Builds a security event raised by the server itself (detections run over
+/// ingested logs), tagged the same way as events posted to /api/security.
+pub fn server_security_event(
+    client_id: Option<String>,
+    session_id: &str,
+    event_type: &str,
+    data: serde_json::Value,
+) -> (String, ExtensionEvent) {
+    server_event("security", client_id, session_id, event_type, data)
+}
+
+/// An event originating from the server rather than an extension, with a
+/// fresh packet id and the instance id of the replica that raised it.
+pub fn server_event(
+    category: &str,
+    client_id: Option<String>,
+    session_id: &str,
+    event_type: &str,
+    data: serde_json::Value,
+) -> (String, ExtensionEvent) {
+    let packet_id = packet_id::next_packet_id();
+    let event = ExtensionEvent {
+        client_id,
+        profile_id: None,
+        session_id: session_id.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        user_agent: "network-logger-server".to_string(),
+        event_type: event_type.to_string(),
+        data,
+    };
+    let mut event = insert_packet_and_category(event, &packet_id, category);
+    if let (Some(instance), Some(obj)) = (crate::instance::id(), event.data.as_object_mut()) {
+        obj.insert(
+            "instance_id".to_string(),
+            serde_json::Value::String(instance.to_string()),
+        );
+    }
+    (packet_id, event)
+}
+
+/// Stands in for the packet id in dry runs, which don't use up real ids.
+const DRY_RUN_PACKET_ID: &str = "dry-run";
+
+fn event_category(event_type: &str) -> &'static str {
+    if event_type == "javascript_execution" {
+        "javascript"
+    } else {
+        "general"
+    }
+}
+
+/// `?response=detailed`: the event's validation warnings.
+fn event_details(event: &ExtensionEvent) -> serde_json::Value {
+    serde_json::json!({ "warnings": event_warnings(event) })
+}
+
+/// What a dry run reports about an event that would still be accepted.
+fn event_warnings(event: &ExtensionEvent) -> Vec<String> {
+    let mut warnings = envelope_warnings(&event.session_id, &event.timestamp, &event.user_agent);
+    if event.event_type.is_empty() {
+        warnings.push("event_type is empty".to_string());
+    }
+    if let Some(cut) = event.data.get("_truncated") {
+        warnings.push(format!(
+            "data was over the event data limits ({} bytes, {} levels deep) and was truncated",
+            cut["original_bytes"], cut["original_depth"]
+        ));
+    } else if !event.data.is_object() {
+        warnings.push(
+            "data is not an object; it will be stored wrapped as {\"data\": ...}".to_string(),
+        );
+    }
+    warnings
+}
+
 pub async fn post_extensions_simple(
     req: actix_web::HttpRequest,
     data: web::Data<simple::SimpleState>,
+    query: web::Query<IngestQuery>,
     body: web::Bytes,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
 
-    let body_str = match decompress_body_if_needed(&req, &body) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    let body_str = decompress_body_if_needed(&req, &body)?;
 
-    let extension_event: ExtensionEvent = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("Failed to parse extension event JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    let extension_event: ExtensionEvent = serde_json::from_str(&body_str).map_err(|e| {
+        log::error!("Failed to parse extension event JSON: {}", e);
+        ApiError::InvalidJson(e.to_string())
+    })?;
+    let extension_event = EventDataLimit::for_request(&req).apply(extension_event, &client_ip)?;
+
+    if query.dry_run {
+        let category = event_category(&extension_event.event_type);
+        let warnings = event_warnings(&extension_event);
+        let record = insert_packet_and_category(extension_event, DRY_RUN_PACKET_ID, category);
+        let summary = serde_json::json!({ "category": category, "event_type": record.event_type });
+        return Ok(dry_run_response(
+            &req,
+            body_str.len(),
+            &record,
+            summary,
+            warnings,
+        ));
+    }
+
+    let usage = Usage {
+        logs: 0,
+        events: 1,
+        bytes: body_str.len() as u64,
     };
+    admit_client(&req, extension_event.client_id.as_deref(), usage).await?;
+    let details =
+        (query.response == ResponseDetail::Detailed).then(|| event_details(&extension_event));
 
     log::info!(
         "📦 Received extension event from IP {}: session_id={}, event_type={}, user_agent={}",
@@ -58,20 +262,40 @@ pub async fn post_extensions_simple(
         extension_event.event_type,
         extension_event.user_agent
     );
-    log::debug!("  Event data from IP {}: {:?}", client_ip, extension_event.data);
+    log::debug!(
+        "  Event data from IP {}: {:?}",
+        client_ip,
+        extension_event.data
+    );
 
     match extension_event.event_type.as_str() {
         "extension_installed" => {
-            log::warn!("🆕 EXTENSION INSTALLED from IP {}: {:?}", client_ip, extension_event.data);
+            log::warn!(
+                "🆕 EXTENSION INSTALLED from IP {}: {:?}",
+                client_ip,
+                extension_event.data
+            );
         }
         "extension_uninstalled" => {
-            log::warn!("🗑️ EXTENSION UNINSTALLED from IP {}: {:?}", client_ip, extension_event.data);
+            log::warn!(
+                "🗑️ EXTENSION UNINSTALLED from IP {}: {:?}",
+                client_ip,
+                extension_event.data
+            );
         }
         "clickfix_detection" => {
-            log::error!("🚨 CLICKFIX DETECTED from IP {}: {:?}", client_ip, extension_event.data);
+            log::error!(
+                "🚨 CLICKFIX DETECTED from IP {}: {:?}",
+                client_ip,
+                extension_event.data
+            );
         }
         "javascript_execution" => {
-            log::info!("📜 JS EXECUTION from IP {}: {:?}", client_ip, extension_event.data);
+            log::info!(
+                "📜 JS EXECUTION from IP {}: {:?}",
+                client_ip,
+                extension_event.data
+            );
         }
         _ => {
             log::info!(
@@ -83,53 +307,85 @@ pub async fn post_extensions_simple(
         }
     }
 
-    let category = if extension_event.event_type == "javascript_execution" {
-        "javascript"
-    } else {
-        "general"
-    };
+    let category = event_category(&extension_event.event_type);
     let packet_id = packet_id::next_packet_id();
-    let extension_event = insert_packet_and_category(extension_event, &packet_id, category);
+    let mut extension_event = insert_packet_and_category(extension_event, &packet_id, category);
+    let client_id = extension_event.client_id.as_deref();
+    if let Some(held) = hold_unenrolled(&req, client_id, HeldRef::Event(&extension_event)).await? {
+        return Ok(held);
+    }
+    record_client_checks(&req, &mut extension_event);
+    observe_outcome_event(&req, &extension_event);
+    lake::append_event(&extension_event, &client_ip);
+    let matches = event_rule_matches(&req, &extension_event, &client_ip);
     data.add_extension_event(extension_event);
+    for event in matches {
+        data.add_extension_event(event);
+    }
 
     log::info!(
         "✅ Extension event stored successfully from IP {} (packet_id={})",
         client_ip,
         packet_id
     );
-    HttpResponse::Ok().json(serde_json::json!({
+    let summary = serde_json::json!({
         "success": true,
         "message": "Extension event stored",
         "packet_id": packet_id,
         "client_ip": client_ip
-    }))
+    });
+    Ok(ingest_response(query.response, summary, details))
 }
 
 pub async fn post_security_simple(
     req: actix_web::HttpRequest,
     data: web::Data<simple::SimpleState>,
+    query: web::Query<IngestQuery>,
     body: web::Bytes,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
 
-    let body_str = match decompress_body_if_needed(&req, &body) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    let body_str = decompress_body_if_needed(&req, &body)?;
 
-    let security_event: ExtensionEvent = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("🔒 SECURITY Failed to parse security event JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    let security_event: ExtensionEvent = serde_json::from_str(&body_str).map_err(|e| {
+        log::error!("🔒 SECURITY Failed to parse security event JSON: {}", e);
+        ApiError::InvalidJson(e.to_string())
+    })?;
+    let security_event = EventDataLimit::for_request(&req).apply(security_event, &client_ip)?;
+
+    if query.dry_run {
+        let category = "security";
+        let warnings = event_warnings(&security_event);
+        let record = insert_packet_and_category(security_event, DRY_RUN_PACKET_ID, category);
+        let summary = serde_json::json!({ "category": category, "event_type": record.event_type });
+        return Ok(dry_run_response(
+            &req,
+            body_str.len(),
+            &record,
+            summary,
+            warnings,
+        ));
+    }
+
+    let usage = Usage {
+        logs: 0,
+        events: 1,
+        bytes: body_str.len() as u64,
     };
+    admit_client(&req, security_event.client_id.as_deref(), usage).await?;
+    let details =
+        (query.response == ResponseDetail::Detailed).then(|| event_details(&security_event));
 
     let packet_id = packet_id::next_packet_id();
-    let security_event = insert_packet_and_category(security_event, &packet_id, "security");
+    let mut security_event = insert_packet_and_category(security_event, &packet_id, "security");
+    let client_id = security_event.client_id.as_deref();
+    if let Some(held) = hold_unenrolled(&req, client_id, HeldRef::Event(&security_event)).await? {
+        return Ok(held);
+    }
+    record_client_checks(&req, &mut security_event);
+    chain::link(&mut security_event);
+    worm::append(&security_event);
+    lake::append_event(&security_event, &client_ip);
 
     log::info!("🔒 SECURITY ─────────── NEW PACKET ───────────");
     log::info!("🔒 SECURITY \tpacket_id:    {}", packet_id);
@@ -158,42 +414,83 @@ pub async fn post_security_simple(
         log::info!("🔒 SECURITY \tdata:         {:?}", security_event.data);
     }
 
+    alerts::raise(&security_event);
+    let matches = event_rule_matches(&req, &security_event, &client_ip);
     data.add_extension_event(security_event);
+    for event in matches {
+        data.add_extension_event(event);
+    }
 
     log::info!("🔒 SECURITY \t→ RESULT:     stored");
     log::info!("🔒 SECURITY ───────────────────────────────────");
-    HttpResponse::Ok().json(serde_json::json!({
+    let summary = serde_json::json!({
         "success": true,
         "message": "Security event stored",
         "packet_id": packet_id,
         "client_ip": client_ip
-    }))
+    });
+    Ok(ingest_response(query.response, summary, details))
+}
+
+/// Stores the events raised by detection rules; a failure is logged, the
+/// event that matched is already stored.
+#[cfg(feature = "production")]
+async fn store_rule_matches(
+    data: &production::ProductionState,
+    matches: Vec<ExtensionEvent>,
+    client_ip: &str,
+) {
+    for event in matches {
+        if let Err(e) = data.add_extension_event(event).await {
+            log::error!(
+                "❌ Failed to store detection event from IP {}: {}",
+                client_ip,
+                e
+            );
+        }
+    }
 }
 
 #[cfg(feature = "production")]
 pub async fn post_extensions_production(
     req: actix_web::HttpRequest,
     data: web::Data<production::ProductionState>,
-    body: web::BytesMut,
-) -> impl Responder {
+    query: web::Query<IngestQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
-    let body_bytes = body.freeze();
+    let body_bytes = body;
 
-    let body_str = match decompress_body_if_needed(&req, &body_bytes) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    let body_str = decompress_body_if_needed(&req, &body_bytes)?;
 
-    let extension_event: ExtensionEvent = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("Failed to parse extension event JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    let extension_event: ExtensionEvent = serde_json::from_str(&body_str).map_err(|e| {
+        log::error!("Failed to parse extension event JSON: {}", e);
+        ApiError::InvalidJson(e.to_string())
+    })?;
+    let extension_event = EventDataLimit::for_request(&req).apply(extension_event, &client_ip)?;
+
+    if query.dry_run {
+        let category = event_category(&extension_event.event_type);
+        let warnings = event_warnings(&extension_event);
+        let record = insert_packet_and_category(extension_event, DRY_RUN_PACKET_ID, category);
+        let summary = serde_json::json!({ "category": category, "event_type": record.event_type });
+        return Ok(dry_run_response(
+            &req,
+            body_str.len(),
+            &record,
+            summary,
+            warnings,
+        ));
+    }
+
+    let usage = Usage {
+        logs: 0,
+        events: 1,
+        bytes: body_str.len() as u64,
     };
+    admit_client(&req, extension_event.client_id.as_deref(), usage).await?;
+    let details =
+        (query.response == ResponseDetail::Detailed).then(|| event_details(&extension_event));
 
     log::info!(
         "📦 Received extension event from IP {}: session_id={}, event_type={}",
@@ -202,66 +499,87 @@ pub async fn post_extensions_production(
         extension_event.event_type
     );
 
-    let category = if extension_event.event_type == "javascript_execution" {
-        "javascript"
-    } else {
-        "general"
-    };
+    let category = event_category(&extension_event.event_type);
     let packet_id = packet_id::next_packet_id();
-    let extension_event = insert_packet_and_category(extension_event, &packet_id, category);
-
-    match data.add_extension_event(extension_event).await {
-        Ok(_) => {
-            log::info!(
-                "✅ Extension event stored successfully from IP {} (packet_id={})",
-                client_ip,
-                packet_id
-            );
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "message": "Extension event stored",
-                "packet_id": packet_id,
-                "client_ip": client_ip
-            }))
-        }
-        Err(e) => {
-            log::error!("❌ Database error from IP {}: {}", client_ip, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Database error: {}", e),
-                "client_ip": client_ip
-            }))
-        }
+    let mut extension_event = insert_packet_and_category(extension_event, &packet_id, category);
+    let client_id = extension_event.client_id.as_deref();
+    if let Some(held) = hold_unenrolled(&req, client_id, HeldRef::Event(&extension_event)).await? {
+        return Ok(held);
     }
+    record_client_checks(&req, &mut extension_event);
+    observe_outcome_event(&req, &extension_event);
+    lake::append_event(&extension_event, &client_ip);
+    let matches = event_rule_matches(&req, &extension_event, &client_ip);
+
+    data.add_extension_event(extension_event)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    store_rule_matches(&data, matches, &client_ip).await;
+    log::info!(
+        "✅ Extension event stored successfully from IP {} (packet_id={})",
+        client_ip,
+        packet_id
+    );
+    let summary = serde_json::json!({
+        "success": true,
+        "message": "Extension event stored",
+        "packet_id": packet_id,
+        "client_ip": client_ip
+    });
+    Ok(ingest_response(query.response, summary, details))
 }
 
 #[cfg(feature = "production")]
 pub async fn post_security_production(
     req: actix_web::HttpRequest,
     data: web::Data<production::ProductionState>,
-    body: web::BytesMut,
-) -> impl Responder {
+    query: web::Query<IngestQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
-    let body_bytes = body.freeze();
+    let body_bytes = body;
 
-    let body_str = match decompress_body_if_needed(&req, &body_bytes) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    let body_str = decompress_body_if_needed(&req, &body_bytes)?;
 
-    let security_event: ExtensionEvent = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("🔒 SECURITY Failed to parse security event JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    let security_event: ExtensionEvent = serde_json::from_str(&body_str).map_err(|e| {
+        log::error!("🔒 SECURITY Failed to parse security event JSON: {}", e);
+        ApiError::InvalidJson(e.to_string())
+    })?;
+    let security_event = EventDataLimit::for_request(&req).apply(security_event, &client_ip)?;
+
+    if query.dry_run {
+        let category = "security";
+        let warnings = event_warnings(&security_event);
+        let record = insert_packet_and_category(security_event, DRY_RUN_PACKET_ID, category);
+        let summary = serde_json::json!({ "category": category, "event_type": record.event_type });
+        return Ok(dry_run_response(
+            &req,
+            body_str.len(),
+            &record,
+            summary,
+            warnings,
+        ));
+    }
+
+    let usage = Usage {
+        logs: 0,
+        events: 1,
+        bytes: body_str.len() as u64,
     };
+    admit_client(&req, security_event.client_id.as_deref(), usage).await?;
+    let details =
+        (query.response == ResponseDetail::Detailed).then(|| event_details(&security_event));
 
     let packet_id = packet_id::next_packet_id();
-    let security_event = insert_packet_and_category(security_event, &packet_id, "security");
+    let mut security_event = insert_packet_and_category(security_event, &packet_id, "security");
+    let client_id = security_event.client_id.as_deref();
+    if let Some(held) = hold_unenrolled(&req, client_id, HeldRef::Event(&security_event)).await? {
+        return Ok(held);
+    }
+    record_client_checks(&req, &mut security_event);
+    chain::link(&mut security_event);
+    worm::append(&security_event);
+    lake::append_event(&security_event, &client_ip);
 
     log::info!("🔒 SECURITY ─────────── NEW PACKET ───────────");
     log::info!("🔒 SECURITY \tpacket_id:    {}", packet_id);
@@ -289,25 +607,21 @@ pub async fn post_security_production(
         log::info!("🔒 SECURITY \tdata:         {:?}", security_event.data);
     }
 
-    match data.add_extension_event(security_event).await {
-        Ok(_) => {
-            log::info!("🔒 SECURITY \t→ RESULT:     stored");
-            log::info!("🔒 SECURITY ───────────────────────────────────");
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "message": "Security event stored",
-                "packet_id": packet_id,
-                "client_ip": client_ip
-            }))
-        }
-        Err(e) => {
-            log::error!("🔒 SECURITY \t→ RESULT:     error - {}", e);
-            log::info!("🔒 SECURITY ───────────────────────────────────");
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Database error: {}", e),
-                "client_ip": client_ip
-            }))
-        }
+    if let Err(e) = data.add_extension_event(security_event.clone()).await {
+        log::error!("🔒 SECURITY \t→ RESULT:     error - {}", e);
+        log::info!("🔒 SECURITY ───────────────────────────────────");
+        return Err(ApiError::from(e).with("client_ip", client_ip));
     }
+    alerts::raise(&security_event);
+    let matches = event_rule_matches(&req, &security_event, &client_ip);
+    store_rule_matches(&data, matches, &client_ip).await;
+    log::info!("🔒 SECURITY \t→ RESULT:     stored");
+    log::info!("🔒 SECURITY ───────────────────────────────────");
+    let summary = serde_json::json!({
+        "success": true,
+        "message": "Security event stored",
+        "packet_id": packet_id,
+        "client_ip": client_ip
+    });
+    Ok(ingest_response(query.response, summary, details))
 }
@@ -0,0 +1,97 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::features::{Feature, FeatureFlags};
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct FeatureRequest {
+    pub enabled: bool,
+}
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    log::error!("❌ Writing the feature flags failed: {}", e);
+    ApiError::Storage(e.to_string())
+}
+
+fn feature(name: &str) -> Result<Feature, ApiError> {
+    Feature::parse(name).ok_or_else(|| {
+        ApiError::NotFound(format!("no such feature: {}", name))
+            .with("features", Feature::ALL.map(Feature::name))
+    })
+}
+
+fn state(features: &FeatureFlags) -> serde_json::Value {
+    serde_json::json!({
+        "features": features.states(),
+        "persisted": features.path().is_some()
+    })
+}
+
+pub async fn get_features(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    features: web::Data<FeatureFlags>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(state(&features)))
+}
+
+fn change(
+    req: &actix_web::HttpRequest,
+    features: &FeatureFlags,
+    name: &str,
+    enabled: Option<bool>,
+) -> Result<HttpResponse, ApiError> {
+    let feature = feature(name)?;
+    features.set(feature, enabled).map_err(storage_error)?;
+    let client_ip = get_client_ip(req);
+    let now_enabled = features.is_enabled(feature);
+    log::warn!(
+        "🚩 Feature {} {} by {}{}",
+        feature.name(),
+        if now_enabled { "enabled" } else { "disabled" },
+        client_ip,
+        if enabled.is_none() {
+            " (back to the default)"
+        } else {
+            ""
+        }
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "feature",
+            "feature": feature,
+            "enabled": now_enabled,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(state(features)))
+}
+
+/// Turns a feature on or off until changed again, across restarts with
+/// `--feature-flags`.
+pub async fn put_feature(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    features: web::Data<FeatureFlags>,
+    path: web::Path<String>,
+    body: web::Json<FeatureRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    change(&req, &features, &path, Some(body.enabled))
+}
+
+/// Drops the runtime change, back to what the command line set.
+pub async fn delete_feature(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    features: web::Data<FeatureFlags>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    change(&req, &features, &path, None)
+}
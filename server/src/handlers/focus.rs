@@ -0,0 +1,95 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::focus::{FocusSession, FocusSessions, MAX_DURATION};
+use crate::handlers::common::{get_client_ip, parse_duration};
+use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct FocusRequest {
+    pub client_id: String,
+    /// How long to focus for, e.g. `25m` or `2h`.
+    pub duration: String,
+}
+
+fn status(
+    client_id: &str,
+    session: Option<&FocusSession>,
+    focus: &FocusSessions,
+) -> serde_json::Value {
+    serde_json::json!({
+        "client_id": client_id,
+        "active": session.is_some(),
+        "started_at": session.map(|s| s.started_at),
+        "ends_at": session.map(|s| s.ends_at),
+        "remaining_secs": session.map(|s| s.remaining_secs()).unwrap_or(0),
+        "domains": focus.domains()
+    })
+}
+
+/// Starts (or extends) a focus session. No admin token is needed: a client
+/// can always make its own blocklist stricter.
+pub async fn post_focus(
+    req: actix_web::HttpRequest,
+    focus: web::Data<FocusSessions>,
+    body: web::Json<FocusRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let client_id = body.client_id.trim();
+    if client_id.is_empty() {
+        return Err(ApiError::BadRequest("client_id is required".into()).with("field", "client_id"));
+    }
+    let max = chrono::Duration::from_std(MAX_DURATION).expect("MAX_DURATION fits");
+    let duration = parse_duration(&body.duration)
+        .filter(|d| *d > chrono::Duration::zero() && *d <= max)
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "duration must be like 25m or 2h, at most {}h",
+                max.num_hours()
+            ))
+            .with("field", "duration")
+        })?;
+
+    let client_ip = get_client_ip(&req);
+    let session = focus.start(client_id, duration, &client_ip);
+    log::info!(
+        "🎯 Focus for client_id={} until {} (requested by {})",
+        client_id,
+        session.ends_at,
+        client_ip
+    );
+    Ok(HttpResponse::Ok().json(status(client_id, Some(&session), &focus)))
+}
+
+/// What the extension polls for its countdown.
+pub async fn get_focus(path: web::Path<String>, focus: web::Data<FocusSessions>) -> impl Responder {
+    let client_id = path.into_inner();
+    let session = focus.active(&client_id);
+    HttpResponse::Ok().json(status(&client_id, session.as_ref(), &focus))
+}
+
+/// Ending a session early is an admin action.
+pub async fn delete_focus(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    focus: web::Data<FocusSessions>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_id = path.into_inner();
+    let Some(session) = focus.end(&client_id) else {
+        return Err(
+            ApiError::NotFound("no focus session running".into()).with("client_id", client_id)
+        );
+    };
+    log::info!(
+        "🎯 Focus for client_id={} ended early by {} ({}s left)",
+        client_id,
+        get_client_ip(&req),
+        session.remaining_secs()
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "ended": session
+    })))
+}
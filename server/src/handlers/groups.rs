@@ -0,0 +1,108 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::groups::{Groups, GroupsConfig};
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use actix_web::{web, HttpResponse};
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    log::error!("❌ Writing groups failed: {}", e);
+    ApiError::Storage(e.to_string())
+}
+
+pub async fn get_groups(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    groups: web::Data<Groups>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "path": groups.path(),
+        "groups": groups.config().groups
+    })))
+}
+
+/// Replaces every group, memberships included.
+pub async fn put_groups(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    groups: web::Data<Groups>,
+    body: web::Json<GroupsConfig>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let config = body.into_inner();
+    config.validate().map_err(ApiError::BadRequest)?;
+    let count = config.groups.len();
+    let client_ip = get_client_ip(&req);
+    groups.replace(config).map_err(storage_error)?;
+    log::warn!("👥 Groups replaced by {}: {} group(s)", client_ip, count);
+    server_events::record(
+        "config_changed",
+        serde_json::json!({ "setting": "groups", "groups": count, "changed_by": client_ip }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "groups": count,
+        "persisted": groups.path().is_some()
+    })))
+}
+
+async fn set_member(
+    req: &actix_web::HttpRequest,
+    groups: &Groups,
+    group: &str,
+    client_id: &str,
+    member: bool,
+) -> Result<HttpResponse, ApiError> {
+    if !groups
+        .set_member(group, client_id, member)
+        .map_err(storage_error)?
+    {
+        return Err(ApiError::NotFound("unknown group".into()).with("group", group));
+    }
+    let client_ip = get_client_ip(req);
+    log::info!(
+        "👥 Client {} {} group {} by {}",
+        client_id,
+        if member { "added to" } else { "removed from" },
+        group,
+        client_ip
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "group_member",
+            "group": group,
+            "client_id": client_id,
+            "member": member,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "client_id": client_id,
+        "groups": groups.groups_of(client_id)
+    })))
+}
+
+pub async fn put_group_member(
+    req: actix_web::HttpRequest,
+    path: web::Path<(String, String)>,
+    auth: web::Data<AdminAuth>,
+    groups: web::Data<Groups>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let (group, client_id) = path.into_inner();
+    set_member(&req, &groups, &group, &client_id, true).await
+}
+
+pub async fn delete_group_member(
+    req: actix_web::HttpRequest,
+    path: web::Path<(String, String)>,
+    auth: web::Data<AdminAuth>,
+    groups: web::Data<Groups>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let (group, client_id) = path.into_inner();
+    set_member(&req, &groups, &group, &client_id, false).await
+}
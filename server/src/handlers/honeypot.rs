@@ -0,0 +1,114 @@
+use crate::alerts;
+use crate::chain;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::extensions::server_security_event;
+use crate::honeypot::{Honeypot, HoneypotHit};
+use crate::metrics;
+use crate::simple;
+use crate::types::ExtensionEvent;
+use crate::worm;
+use actix_web::{web, HttpResponse, Responder};
+
+#[cfg(feature = "production")]
+use crate::production;
+
+fn honeypot_event(req: &actix_web::HttpRequest, honeypot: &Honeypot) -> ExtensionEvent {
+    let client_ip = get_client_ip(req);
+    let path = req.path().to_string();
+    let hit: HoneypotHit = honeypot.record(&client_ip, &path);
+    metrics::HONEYPOT_HITS.inc();
+
+    let user_agent = req
+        .headers()
+        .get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let risk = match hit.classification {
+        "scanner" => 80,
+        "repeat_probe" => 60,
+        _ => 40,
+    };
+    let (packet_id, mut event) = server_security_event(
+        None,
+        "",
+        "honeypot_hit",
+        serde_json::json!({
+            "path": path,
+            "method": req.method().as_str(),
+            "query": req.query_string(),
+            "source_ip": client_ip,
+            "classification": hit.classification,
+            "network": hit.network,
+            "hits": hit.hits,
+            "distinct_paths": hit.distinct_paths,
+            "first_seen": hit.first_seen.to_rfc3339(),
+            "user_agent": user_agent,
+            "detection": { "riskScore": risk }
+        }),
+    );
+    event.user_agent = user_agent;
+    chain::link(&mut event);
+    worm::append(&event);
+    log::error!(
+        "🍯 HONEYPOT HIT from IP {} ({}, {}): {} {} (hits={}, packet_id={})",
+        client_ip,
+        hit.network,
+        hit.classification,
+        req.method(),
+        path,
+        hit.hits,
+        packet_id
+    );
+    alerts::raise(&event);
+    event
+}
+
+/// Decoys answer like any unknown path so scanners learn nothing.
+fn decoy_response() -> HttpResponse {
+    HttpResponse::NotFound().finish()
+}
+
+pub async fn honeypot_simple(
+    req: actix_web::HttpRequest,
+    data: web::Data<simple::SimpleState>,
+    honeypot: web::Data<Honeypot>,
+) -> impl Responder {
+    data.add_extension_event(honeypot_event(&req, &honeypot));
+    decoy_response()
+}
+
+#[cfg(feature = "production")]
+pub async fn honeypot_production(
+    req: actix_web::HttpRequest,
+    data: web::Data<production::ProductionState>,
+    honeypot: web::Data<Honeypot>,
+) -> impl Responder {
+    if let Err(e) = data
+        .add_extension_event(honeypot_event(&req, &honeypot))
+        .await
+    {
+        log::error!("❌ Failed to store honeypot event: {}", e);
+    }
+    decoy_response()
+}
+
+/// Registers each decoy path (any method, plus anything below it).
+pub fn configure_simple(cfg: &mut web::ServiceConfig, paths: &[String]) {
+    for path in paths {
+        cfg.route(path, web::route().to(honeypot_simple)).route(
+            &format!("{}/{{tail:.*}}", path),
+            web::route().to(honeypot_simple),
+        );
+    }
+}
+
+#[cfg(feature = "production")]
+pub fn configure_production(cfg: &mut web::ServiceConfig, paths: &[String]) {
+    for path in paths {
+        cfg.route(path, web::route().to(honeypot_production)).route(
+            &format!("{}/{{tail:.*}}", path),
+            web::route().to(honeypot_production),
+        );
+    }
+}
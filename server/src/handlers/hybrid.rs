@@ -0,0 +1,193 @@
+//! Read handlers for hybrid mode: the hot in-memory tier answers for the last
+//! few minutes and older records are merged in from the warm database tier.
+//! Ingest goes through the simple handlers; the hot tier forwards writes.
+
+use crate::annotations::AnnotationStore;
+use crate::archive::ClientArchive;
+use crate::auth::AdminAuth;
+use crate::client_names::ClientNames;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::dashboard::{
+    export_events, find_packet, hidden_clients, packet, summarize_events,
+};
+use crate::handlers::query::{DashboardQuery, FieldsQuery, LogsQuery, TailQuery};
+use crate::production;
+use crate::reputation::ReputationService;
+use crate::simple;
+use crate::tail::{self, LogTail};
+use crate::triage::TriageStore;
+use actix_web::{web, HttpResponse};
+
+const WARM_READ_LIMIT: i64 = 1000;
+
+pub async fn get_logs_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    hot: web::Data<simple::SimpleState>,
+    warm: web::Data<production::ProductionState>,
+    query: web::Query<LogsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let client_ip = get_client_ip(&req);
+    let mut logs = match hot.hot_cutoff() {
+        Some(cutoff) => match warm.get_logs_before(cutoff, WARM_READ_LIMIT).await {
+            Ok(older) => older,
+            Err(e) => {
+                log::error!("❌ Warm tier read failed for IP {}: {}", client_ip, e);
+                Vec::new()
+            }
+        },
+        None => Vec::new(),
+    };
+    logs.extend(hot.get_logs());
+    logs.retain(|e| query.matches(e));
+    log::info!(
+        "📊 Logs requested from IP {}: {} entries (hybrid)",
+        client_ip,
+        logs.len()
+    );
+    Ok(match &query.fields {
+        Some(fields) => {
+            HttpResponse::Ok().json(logs.iter().map(|e| fields.project(e)).collect::<Vec<_>>())
+        }
+        None => HttpResponse::Ok().json(logs),
+    })
+}
+
+pub async fn tail_logs_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    hot: web::Data<simple::SimpleState>,
+    warm: web::Data<production::ProductionState>,
+    logs_tail: web::Data<LogTail>,
+    query: web::Query<TailQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let query = query.into_inner();
+    let client_ip = get_client_ip(&req);
+    let follow = query.follow.then(|| logs_tail.subscribe());
+    let mut logs = match hot.hot_cutoff() {
+        Some(cutoff) if query.lines > 0 => {
+            match warm.get_logs_before(cutoff, tail::MAX_LINES as i64).await {
+                Ok(older) => older,
+                Err(e) => {
+                    log::error!("❌ Warm tier read failed for IP {}: {}", client_ip, e);
+                    Vec::new()
+                }
+            }
+        }
+        _ => Vec::new(),
+    };
+    logs.extend(hot.get_logs());
+    logs.retain(|e| query.matches(e));
+    let history = logs.split_off(logs.len().saturating_sub(query.lines));
+    log::info!(
+        "📜 Log tail for IP {}: {} entries{} (hybrid)",
+        client_ip,
+        history.len(),
+        if query.follow { ", following" } else { "" }
+    );
+    Ok(tail::respond(history, follow, move |e| query.matches(e)))
+}
+
+pub async fn get_dashboard_events_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    (hot, warm): (
+        web::Data<simple::SimpleState>,
+        web::Data<production::ProductionState>,
+    ),
+    reputation: web::Data<ReputationService>,
+    (archive, names): (web::Data<ClientArchive>, web::Data<ClientNames>),
+    (annotations, triage): (web::Data<AnnotationStore>, web::Data<TriageStore>),
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut events = match hot.hot_cutoff() {
+        Some(cutoff) => warm
+            .get_extension_events_before(cutoff, WARM_READ_LIMIT)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("❌ Warm tier read failed: {}", e);
+                Vec::new()
+            }),
+        None => Vec::new(),
+    };
+    events.extend(hot.get_extension_events());
+    let hidden = hidden_clients(&query, &archive);
+    let mut out = summarize_events(
+        &events,
+        &query,
+        &reputation,
+        hidden,
+        &annotations,
+        &triage,
+        &names,
+    );
+    if let Some(fields) = &query.fields {
+        out = out
+            .into_iter()
+            .map(|row| fields.project_value(row))
+            .collect();
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "events": out })))
+}
+
+/// `/api/dashboard/events` with the same filters, as CSV.
+pub async fn export_dashboard_events_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    (hot, warm): (
+        web::Data<simple::SimpleState>,
+        web::Data<production::ProductionState>,
+    ),
+    reputation: web::Data<ReputationService>,
+    (archive, names): (web::Data<ClientArchive>, web::Data<ClientNames>),
+    (annotations, triage): (web::Data<AnnotationStore>, web::Data<TriageStore>),
+    query: web::Query<DashboardQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut events = match hot.hot_cutoff() {
+        Some(cutoff) => warm
+            .get_extension_events_before(cutoff, WARM_READ_LIMIT)
+            .await
+            .map_err(ApiError::from)?,
+        None => Vec::new(),
+    };
+    events.extend(hot.get_extension_events());
+    let hidden = hidden_clients(&query, &archive);
+    let rows = summarize_events(
+        &events,
+        &query,
+        &reputation,
+        hidden,
+        &annotations,
+        &triage,
+        &names,
+    );
+    log::info!("📤 {} dashboard events exported (hybrid)", rows.len());
+    Ok(export_events(rows, &query))
+}
+
+pub async fn get_dashboard_packet_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    hot: web::Data<simple::SimpleState>,
+    warm: web::Data<production::ProductionState>,
+    names: web::Data<ClientNames>,
+    query: web::Query<FieldsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let events = hot.get_extension_events();
+    if let Some(e) = find_packet(&events, &packet_id) {
+        return Ok(packet(e, &names, &query));
+    }
+    match warm.get_extension_event_by_packet_id(&packet_id).await {
+        Ok(Some(e)) => Ok(packet(&e, &names, &query)),
+        Ok(None) => Err(ApiError::NotFound("packet not found".into()).with("packet_id", packet_id)),
+        Err(e) => Err(ApiError::from(e).with("packet_id", packet_id)),
+    }
+}
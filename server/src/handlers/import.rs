@@ -0,0 +1,171 @@
+use crate::auth::AdminAuth;
+use crate::categories::Categories;
+use crate::error::ApiError;
+use crate::handlers::common::{decompress_body_within, get_client_ip};
+use crate::handlers::query::ImportQuery;
+use crate::import::{self, Format, ImportReport, MAX_IMPORT_BYTES, PROGRESS_EVERY};
+use crate::simple;
+use crate::types::LogEntry;
+use actix_web::dev::Decompress;
+use actix_web::{web, HttpResponse};
+use futures_util::TryStreamExt;
+
+#[cfg(feature = "production")]
+use crate::production;
+
+/// Reads the upload once the caller is authenticated, so nobody else can
+/// make the server buffer [`MAX_IMPORT_BYTES`]; the limit applies to the
+/// body after `Content-Encoding` is decoded.
+async fn receive(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    payload: web::Payload,
+) -> Result<web::Bytes, ApiError> {
+    auth.check(req).await?;
+    let mut stream = Decompress::from_headers(payload, req.headers());
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|e| ApiError::BadRequest(format!("Failed to read the upload: {}", e)))?
+    {
+        if body.len() + chunk.len() > MAX_IMPORT_BYTES {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "Upload is larger than the {} byte limit",
+                MAX_IMPORT_BYTES
+            ))
+            .with("max_import_bytes", MAX_IMPORT_BYTES));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// Authenticates, decodes and parses an upload. Returns the parsed records
+/// with a report already holding the parse/validation outcome.
+async fn read_upload(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    query: &ImportQuery,
+    payload: web::Payload,
+) -> Result<(Vec<(usize, LogEntry)>, ImportReport), ApiError> {
+    let body = receive(req, auth, payload).await?;
+    let body_str = decompress_body_within(req, &body, MAX_IMPORT_BYTES)?;
+
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let format = Format::detect(query.format.as_deref(), content_type, &body_str)
+        .map_err(ApiError::BadRequest)?;
+
+    let mut report = ImportReport::new(format);
+    let mut records = import::parse(format, &body_str, &mut report);
+    if let Some(categories) = req.app_data::<web::Data<Categories>>() {
+        for (_, entry) in &mut records {
+            categories.tag(entry);
+        }
+    }
+    log::info!(
+        "📦 Import from IP {}: format={}, records={}, requests={}, invalid={}",
+        get_client_ip(req),
+        format.as_str(),
+        report.records,
+        report.requests,
+        report.invalid
+    );
+    Ok((records, report))
+}
+
+fn log_progress(done: usize, report: &ImportReport) {
+    if done.is_multiple_of(PROGRESS_EVERY) {
+        log::info!(
+            "📦 Import progress: {}/{} records, {} requests imported, {} duplicates",
+            done,
+            report.records,
+            report.imported,
+            report.duplicates
+        );
+    }
+}
+
+fn finish(client_ip: &str, report: ImportReport) -> Result<HttpResponse, ApiError> {
+    log::info!(
+        "✅ Import from IP {} finished: imported={}, duplicates={}, invalid={}",
+        client_ip,
+        report.imported,
+        report.duplicates,
+        report.invalid
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Import complete",
+        "report": report,
+        "client_ip": client_ip
+    })))
+}
+
+/// Imported history is stored as-is: detectors and reputation lookups only
+/// run on live traffic, so old batches don't raise alerts stamped "now".
+pub async fn import_logs_simple(
+    req: actix_web::HttpRequest,
+    data: web::Data<simple::SimpleState>,
+    auth: web::Data<AdminAuth>,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let client_ip = get_client_ip(&req);
+    let (records, mut report) = read_upload(&req, &auth, &query, payload).await?;
+
+    let mut seen = data.log_fingerprints();
+    for (done, (_, mut entry)) in records.into_iter().enumerate() {
+        report.duplicates += import::dedupe(&mut entry, &mut seen);
+        if !entry.logs.is_empty() {
+            report.imported += entry.logs.len();
+            data.add_log(entry);
+        }
+        log_progress(done + 1, &report);
+    }
+    finish(&client_ip, report)
+}
+
+#[cfg(feature = "production")]
+pub async fn import_logs_production(
+    req: actix_web::HttpRequest,
+    data: web::Data<production::ProductionState>,
+    auth: web::Data<AdminAuth>,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let client_ip = get_client_ip(&req);
+    let (records, mut report) = read_upload(&req, &auth, &query, payload).await?;
+
+    // In-upload duplicates are caught here; ones already in the database by
+    // the insert itself.
+    let mut seen = std::collections::HashSet::new();
+    for (done, (line, mut entry)) in records.into_iter().enumerate() {
+        report.duplicates += import::dedupe(&mut entry, &mut seen);
+        let candidates = entry.logs.len();
+        match data.import_log(entry).await {
+            Ok(inserted) => {
+                report.imported += inserted;
+                report.duplicates += candidates - inserted;
+            }
+            Err(e) => {
+                log::error!(
+                    "❌ Import from IP {} failed at line {}: {}",
+                    client_ip,
+                    line,
+                    e
+                );
+                return Err(ApiError::from(e)
+                    .with("line", line)
+                    .with("report", report)
+                    .with("client_ip", client_ip));
+            }
+        }
+        log_progress(done + 1, &report);
+    }
+    finish(&client_ip, report)
+}
@@ -0,0 +1,147 @@
+use crate::blocklist_history::BlocklistHistory;
+use crate::blocklist_sync::{self, BlocklistSync, SyncChanges, SyncPush};
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::server_events;
+use crate::simple;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+/// A verified push, or `None` when its delivery was already applied.
+fn verified(
+    req: &actix_web::HttpRequest,
+    sync: &BlocklistSync,
+    body: &[u8],
+) -> Result<Option<(SyncPush, String)>, ApiError> {
+    if !sync.is_enabled() {
+        return Err(ApiError::NotFound(
+            "blocklist sync is off (--blocklist-sync-secret)".to_string(),
+        ));
+    }
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+    let now = chrono::Utc::now().timestamp();
+    if let Err(reason) = sync.verify(
+        header(blocklist_sync::TIMESTAMP_HEADER),
+        header(blocklist_sync::DELIVERY_HEADER),
+        header(blocklist_sync::SIGNATURE_HEADER),
+        body,
+        now,
+    ) {
+        log::warn!(
+            "🚫 Blocklist sync from IP {} rejected: {}",
+            get_client_ip(req),
+            reason
+        );
+        return Err(ApiError::Unauthorized.with("reason", reason));
+    }
+    let push: SyncPush =
+        serde_json::from_slice(body).map_err(|e| ApiError::InvalidJson(e.to_string()))?;
+    if push.source.is_empty() || push.source.chars().count() > 100 {
+        return Err(ApiError::BadRequest(
+            "source must be 1-100 characters".to_string(),
+        ));
+    }
+    // Verified above, so present.
+    let delivery = header(blocklist_sync::DELIVERY_HEADER)
+        .unwrap_or_default()
+        .to_string();
+    if !sync.first_delivery(&delivery, now) {
+        log::info!(
+            "🔁 Blocklist sync delivery {} from {} already applied",
+            delivery,
+            push.source
+        );
+        return Ok(None);
+    }
+    Ok(Some((push, delivery)))
+}
+
+fn duplicate() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "success": true, "duplicate": true }))
+}
+
+fn synced(
+    req: &actix_web::HttpRequest,
+    push: &SyncPush,
+    changes: SyncChanges,
+    version: u64,
+) -> HttpResponse {
+    let client_ip = get_client_ip(req);
+    log::info!(
+        "🔄 Blocklist sync from {} (IP {}): +{} / -{} entries, version {}",
+        push.source,
+        client_ip,
+        changes.added,
+        changes.removed,
+        version
+    );
+    if changes.added + changes.removed > 0 {
+        server_events::record(
+            "config_changed",
+            serde_json::json!({
+                "setting": "blocklist_sync",
+                "source": push.source,
+                "added": changes.added,
+                "removed": changes.removed,
+                "version": version,
+                "changed_by": client_ip
+            }),
+        );
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "source": push.source,
+        "added": changes.added,
+        "removed": changes.removed,
+        "version": version
+    }))
+}
+
+/// Applies a signed push from an external policy system to the blocklist.
+pub async fn post_blocklist_sync_simple(
+    req: actix_web::HttpRequest,
+    data: web::Data<simple::SimpleState>,
+    sync: web::Data<BlocklistSync>,
+    history: web::Data<BlocklistHistory>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let Some((push, _)) = verified(&req, &sync, &body)? else {
+        return Ok(duplicate());
+    };
+    let mut blocklist = data.get_blocklist();
+    let changes = blocklist_sync::apply(&mut blocklist, &push);
+    let version = history.observe(&blocklist);
+    data.update_blocklist(blocklist);
+    Ok(synced(&req, &push, changes, version))
+}
+
+#[cfg(feature = "production")]
+pub async fn post_blocklist_sync_production(
+    req: actix_web::HttpRequest,
+    data: web::Data<production::ProductionState>,
+    sync: web::Data<BlocklistSync>,
+    history: web::Data<BlocklistHistory>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let Some((push, delivery)) = verified(&req, &sync, &body)? else {
+        return Ok(duplicate());
+    };
+    let client_ip = get_client_ip(&req);
+    let stored = async {
+        let mut blocklist = data.get_blocklist().await?;
+        let changes = blocklist_sync::apply(&mut blocklist, &push);
+        data.update_blocklist(blocklist.clone()).await?;
+        Ok::<_, sqlx::Error>((blocklist, changes))
+    }
+    .await;
+    let (blocklist, changes) = stored.map_err(|e| {
+        sync.forget(&delivery);
+        db_error(&client_ip, e)
+    })?;
+    let version = history.observe(&blocklist);
+    Ok(synced(&req, &push, changes, version))
+}
@@ -0,0 +1,111 @@
+use crate::auth::AdminAuth;
+use crate::detection::DetectionRules;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::query::JobHistoryQuery;
+use crate::rescan::{self, RescanRequest, Rescans};
+use crate::scheduler::Scheduler;
+use actix_web::{web, HttpResponse};
+
+/// Every scheduled job with its last and next run.
+pub async fn get_jobs(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    scheduler: web::Data<Scheduler>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let jobs = scheduler.jobs();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": jobs.len(),
+        "jobs": jobs
+    })))
+}
+
+/// Starts a job now; `GET /api/admin/jobs` shows how it went.
+pub async fn post_run_job(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    scheduler: web::Data<Scheduler>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let name = path.into_inner();
+    let job = scheduler.trigger(&name)?;
+    log::info!("▶️ Job {} started by {}", name, get_client_ip(&req));
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "success": true,
+        "job": job
+    })))
+}
+
+/// Asks a running job to stop; `GET /api/admin/jobs` shows when it has.
+pub async fn post_cancel_job(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    scheduler: web::Data<Scheduler>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let name = path.into_inner();
+    let job = scheduler.cancel(&name)?;
+    log::info!("⏹️ Job {} cancelled by {}", name, get_client_ip(&req));
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "success": true,
+        "job": job
+    })))
+}
+
+/// A job's past runs, newest first (20 unless `?limit=`).
+pub async fn get_job_history(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    scheduler: web::Data<Scheduler>,
+    path: web::Path<String>,
+    query: web::Query<JobHistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let name = path.into_inner();
+    let runs = scheduler
+        .history(&name, query.limit.unwrap_or(20))
+        .ok_or_else(|| ApiError::NotFound(format!("no job named {}", name)))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "name": name,
+        "count": runs.len(),
+        "runs": runs
+    })))
+}
+
+/// Starts the `rescan` job on the named rules and patterns.
+pub async fn post_rescan(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    scheduler: web::Data<Scheduler>,
+    rescans: web::Data<Rescans>,
+    rules: web::Data<DetectionRules>,
+    body: web::Json<RescanRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let status = scheduler
+        .status(rescan::JOB)
+        .ok_or_else(|| ApiError::NotFound(format!("no job named {}", rescan::JOB)))?;
+    if status.running {
+        return Err(ApiError::Conflict(format!(
+            "job {} is already running",
+            rescan::JOB
+        )));
+    }
+    let request = body.into_inner();
+    rescans.queue(request.clone(), &rules)?;
+    let job = scheduler.trigger(rescan::JOB)?;
+    log::info!(
+        "🔁 Rescan of {} rules and {} patterns started by {}",
+        request.rules.len(),
+        request.patterns.len(),
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "success": true,
+        "job": job,
+        "rescan": request
+    })))
+}
@@ -1,40 +1,347 @@
-use crate::handlers::common::{decompress_body_if_needed, get_client_ip};
+use crate::alerts;
+use crate::asn::{self, AsnEnricher};
+use crate::auth::AdminAuth;
+use crate::batch::BatchLimit;
+use crate::beaconing::BeaconDetector;
+use crate::categories::Categories;
+use crate::chain;
+use crate::counters::BatchSummary;
+use crate::detection::DetectionRules;
+use crate::enrollment::HeldRef;
+use crate::error::ApiError;
+use crate::exfil::ExfilMonitor;
+use crate::features::{Feature, FeatureFlags};
+use crate::handlers::common::{
+    admit_client, count_batch, domain_from_url, dry_run_response, envelope_warnings, get_client_ip,
+    hold_unenrolled, ingest_response, observe_clock_skew, observe_distinct, observe_ingest,
+    observe_session, observe_user, publish_to_tail,
+};
+use crate::handlers::extensions::{rule_match_events, server_security_event};
+use crate::handlers::query::{IngestQuery, LogsQuery, ResponseDetail, TailQuery};
+use crate::homograph::HomographDetector;
+use crate::lake;
+use crate::metrics;
+use crate::quotas::Usage;
+use crate::rdap::{self, RdapService};
+use crate::reputation::{ReputationService, SUSPICIOUS_THRESHOLD};
+use crate::request_types::{Dropped, RequestTypeFilter};
+use crate::screen_time::ScreenTime;
 use crate::simple;
-use crate::types::LogEntry;
-use actix_web::{web, HttpResponse, Responder};
-use std::collections::HashSet;
+use crate::stream_parse::parse_log_batch;
+use crate::tail::{self, LogTail};
+use crate::types::{LogEntry, NetworkLog};
+use crate::url_normalize::UrlNormalizer;
+use crate::worm;
+use actix_web::{web, HttpResponse};
 
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
 #[cfg(feature = "production")]
 use crate::production;
 
+/// Attaches a reputation score to each log whose domain is known. The
+/// domains of blocked requests go through the full (possibly external)
+/// lookup, concurrently and a bounded number per batch; everything else only
+/// consults the local list and cache. Returns the number of suspicious logs.
+async fn annotate_reputation(
+    entry: &mut LogEntry,
+    reputation: &ReputationService,
+    client_ip: &str,
+) -> usize {
+    let blocked = entry
+        .logs
+        .iter()
+        .filter(|l| l.blocked)
+        .filter_map(|l| domain_from_url(&l.url))
+        .collect();
+    let looked_up = reputation.scores(blocked).await;
+    let mut suspicious = 0;
+    for network_log in entry.logs.iter_mut() {
+        let Some(domain) = domain_from_url(&network_log.url) else {
+            continue;
+        };
+        let score = match looked_up.get(&domain.to_ascii_lowercase()) {
+            Some(score) if network_log.blocked => *score,
+            _ => reputation.cached_score(&domain),
+        };
+        network_log.reputation_score = score;
+        if let Some(score) = score {
+            if score >= SUSPICIOUS_THRESHOLD {
+                suspicious += 1;
+                log::warn!(
+                    "☣️ SUSPICIOUS DOMAIN from IP {}: domain={}, reputation={}",
+                    client_ip,
+                    domain,
+                    score
+                );
+            }
+        }
+    }
+    suspicious
+}
+
+/// Runs the per-batch detectors and the detection rules and turns their
+/// findings into security events, which also go through the alert rules.
+/// Nothing runs while `anomaly_detection` is disabled.
+fn detection_events(
+    req: &actix_web::HttpRequest,
+    entry: &LogEntry,
+    beacons: &BeaconDetector,
+    exfil: &ExfilMonitor,
+    client_ip: &str,
+) -> Vec<crate::types::ExtensionEvent> {
+    let mut events = Vec::new();
+    if !FeatureFlags::enabled_for(req, Feature::AnomalyDetection) {
+        return events;
+    }
+    for finding in beacons.observe(entry) {
+        let (packet_id, event) = server_security_event(
+            Some(finding.client_id.clone()),
+            &entry.session_id,
+            "beaconing_detection",
+            finding.to_event_data(),
+        );
+        log::error!(
+            "📡 BEACONING DETECTED from IP {}: client_id={}, domain={}, period={:.1}s, confidence={:.2} (packet_id={})",
+            client_ip,
+            finding.client_id,
+            finding.domain,
+            finding.period_secs,
+            finding.confidence,
+            packet_id
+        );
+        events.push(event);
+    }
+    for finding in exfil.observe(entry) {
+        let (packet_id, event) = server_security_event(
+            Some(finding.client_id.clone()),
+            &entry.session_id,
+            "data_exfiltration_suspected",
+            finding.to_event_data(exfil.threshold_bytes()),
+        );
+        log::error!(
+            "📤 POSSIBLE EXFILTRATION from IP {}: client_id={}, domain={}, bytes={} in {}s (packet_id={})",
+            client_ip,
+            finding.client_id,
+            finding.domain,
+            finding.bytes,
+            finding.window_secs,
+            packet_id
+        );
+        events.push(event);
+    }
+    if let Some(homographs) = req.app_data::<web::Data<HomographDetector>>() {
+        for finding in homographs.observe(entry) {
+            let (packet_id, event) = server_security_event(
+                finding.client_id.clone(),
+                &entry.session_id,
+                "homograph_domain",
+                finding.to_event_data(),
+            );
+            metrics::HOMOGRAPH_DOMAINS.inc();
+            log::error!(
+                "🎭 HOMOGRAPH DOMAIN from IP {}: client_id={:?}, host={} ({}), imitates={:?} (packet_id={})",
+                client_ip,
+                finding.client_id,
+                finding.host,
+                finding.unicode_host,
+                finding.imitates,
+                packet_id
+            );
+            events.push(event);
+        }
+    }
+    if let Some(rdap) = req.app_data::<web::Data<RdapService>>() {
+        for finding in rdap::observe(rdap, entry) {
+            let (packet_id, event) = server_security_event(
+                finding.client_id.clone(),
+                &entry.session_id,
+                "newly_registered_domain",
+                finding.to_event_data(),
+            );
+            metrics::NEWLY_REGISTERED_DOMAINS.inc();
+            log::error!(
+                "🆕 NEWLY REGISTERED DOMAIN from IP {}: client_id={:?}, domain={}, registered {} (packet_id={})",
+                client_ip,
+                finding.client_id,
+                finding.domain,
+                finding.registered_at.date_naive(),
+                packet_id
+            );
+            events.push(event);
+        }
+    }
+    if let Some(enricher) = req.app_data::<web::Data<AsnEnricher>>() {
+        for finding in asn::observe(enricher, entry) {
+            let (packet_id, event) = server_security_event(
+                finding.client_id.clone(),
+                &entry.session_id,
+                "bad_asn_host",
+                finding.to_event_data(),
+            );
+            metrics::BAD_ASN_HOSTS.inc();
+            log::error!(
+                "🏴 BAD ASN HOST from IP {}: client_id={:?}, host={} ({}, AS{} {}) (packet_id={})",
+                client_ip,
+                finding.client_id,
+                finding.host,
+                finding.ip,
+                finding.asn.asn,
+                finding.asn.name,
+                packet_id
+            );
+            events.push(event);
+        }
+    }
+    if let Some(rules) = req.app_data::<web::Data<DetectionRules>>() {
+        events.extend(rule_match_events(
+            rules.check_logs(entry),
+            entry.client_id.as_deref(),
+            &entry.session_id,
+            client_ip,
+        ));
+    }
+    for event in &mut events {
+        chain::link(event);
+        worm::append(event);
+        alerts::raise(event);
+    }
+    events
+}
+
+/// What a dry run reports about a batch that would still be accepted.
+fn log_warnings(entry: &LogEntry) -> Vec<String> {
+    let mut warnings = envelope_warnings(&entry.session_id, &entry.timestamp, &entry.user_agent);
+    if entry.logs.is_empty() {
+        warnings.push("logs is empty".to_string());
+    }
+    for (idx, l) in entry.logs.iter().enumerate() {
+        for warning in network_log_warnings(l) {
+            warnings.push(format!("logs[{}].{}", idx, warning));
+        }
+    }
+    warnings
+}
+
+fn network_log_warnings(l: &NetworkLog) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if l.url.is_empty() {
+        warnings.push("url is empty".to_string());
+    } else if domain_from_url(&l.url).is_none() {
+        warnings.push(format!("url has no host: {}", l.url));
+    }
+    if l.method.is_empty() {
+        warnings.push("method is empty".to_string());
+    }
+    warnings
+}
+
+/// `?response=detailed`: the batch's warnings, each log's request id and
+/// warnings in batch order, the packet ids of the security events it
+/// raised and the logs dropped per request type.
+fn log_details(entry: &LogEntry, packet_ids: &[String], dropped: &Dropped) -> serde_json::Value {
+    let logs: Vec<serde_json::Value> = entry
+        .logs
+        .iter()
+        .map(|l| {
+            serde_json::json!({
+                "request_id": l.request_id,
+                "category": l.category,
+                "reputation_score": l.reputation_score,
+                "warnings": network_log_warnings(l)
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "warnings": log_warnings(entry),
+        "logs": logs,
+        "packet_ids": packet_ids,
+        "dropped": dropped
+    })
+}
+
 pub async fn post_logs_simple(
     req: actix_web::HttpRequest,
     data: web::Data<simple::SimpleState>,
+    reputation: web::Data<ReputationService>,
+    beacons: web::Data<BeaconDetector>,
+    exfil: web::Data<ExfilMonitor>,
+    query: web::Query<IngestQuery>,
     body: web::Bytes,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
+    let batch_limit = BatchLimit::for_request(&req);
+    let type_filter = RequestTypeFilter::for_request(&req);
 
-    let body_str = match decompress_body_if_needed(&req, &body) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    // A dry run reports an oversized batch instead of refusing it.
+    let max_logs = batch_limit.parse_limit().filter(|_| !query.dry_run);
+    let parsed = parse_log_batch(&req, &body, max_logs)?;
+    let mut log_entry = parsed.entry;
+    UrlNormalizer::for_request(&req).apply(&mut log_entry);
+    if let Some(categories) = req.app_data::<web::Data<Categories>>() {
+        categories.tag(&mut log_entry);
+    }
 
-    let log_entry: LogEntry = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("Failed to parse log entry JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    if query.dry_run {
+        let dropped = type_filter.apply(&mut log_entry);
+        let suspicious_count = annotate_reputation(&mut log_entry, &reputation, &client_ip).await;
+        let batch = BatchSummary::of(&log_entry, suspicious_count);
+        let summary = serde_json::json!({
+            "logs_count": batch.logs,
+            "blocked_count": batch.blocked,
+            "suspicious_count": batch.suspicious,
+            "dropped_count": dropped.total()
+        });
+        let mut warnings = log_warnings(&log_entry);
+        warnings.extend(batch_limit.warning(&log_entry));
+        return Ok(dry_run_response(
+            &req,
+            parsed.decompressed_bytes,
+            &log_entry,
+            summary,
+            warnings,
+        ));
+    }
+
+    batch_limit.admit(&log_entry, &client_ip)?;
+    let dropped = type_filter.apply(&mut log_entry);
+    let usage = Usage {
+        logs: log_entry.logs.len() as u64,
+        events: 0,
+        bytes: parsed.decompressed_bytes as u64,
     };
+    admit_client(&req, log_entry.client_id.as_deref(), usage).await?;
+    if let Some(held) = hold_unenrolled(
+        &req,
+        log_entry.client_id.as_deref(),
+        HeldRef::Log(&log_entry),
+    )
+    .await?
+    {
+        return Ok(held);
+    }
+    dropped.record(&client_ip);
+    log_entry.clock_skew_ms =
+        observe_clock_skew(&req, log_entry.client_id.as_deref(), &log_entry.timestamp);
+    observe_session(&req, &log_entry.session_id, log_entry.client_id.as_deref());
+    observe_distinct(&req, &log_entry);
+    observe_user(&req, &log_entry);
+    observe_ingest(&req, &log_entry);
+    if let Some(screen_time) = req.app_data::<web::Data<ScreenTime>>() {
+        screen_time.record(&log_entry);
+    }
 
     if log_entry.session_id.is_empty() {
-        log::warn!("⚠️ Received log entry with empty session_id from IP: {}", client_ip);
+        log::warn!(
+            "⚠️ Received log entry with empty session_id from IP: {}",
+            client_ip
+        );
     }
     if log_entry.user_agent.is_empty() {
-        log::warn!("⚠️ Received log entry with empty user_agent from IP: {}", client_ip);
+        log::warn!(
+            "⚠️ Received log entry with empty user_agent from IP: {}",
+            client_ip
+        );
     }
 
     log::info!(
@@ -47,30 +354,37 @@ pub async fn post_logs_simple(
     );
 
     if log_entry.logs.is_empty() {
-        log::warn!("⚠️ Received log entry with empty logs array from IP: {}", client_ip);
-        return HttpResponse::Ok().json(serde_json::json!({
+        log::warn!(
+            "⚠️ Received log entry with empty logs array from IP: {}",
+            client_ip
+        );
+        count_batch(
+            &req,
+            log_entry.client_id.as_deref(),
+            &BatchSummary::default(),
+        );
+        let summary = serde_json::json!({
             "success": true,
             "message": "Logs stored (empty batch)",
             "logs_count": 0,
+            "dropped_count": dropped.total(),
             "client_ip": client_ip
-        }));
+        });
+        let details = (query.response == ResponseDetail::Detailed)
+            .then(|| log_details(&log_entry, &[], &dropped));
+        return Ok(ingest_response(query.response, summary, details));
     }
 
-    let mut blocked_count = 0;
-    let mut unique_urls = HashSet::new();
-
     for (idx, network_log) in log_entry.logs.iter().enumerate() {
         if network_log.url.is_empty() {
             log::warn!("⚠️ Log[{}] from IP {}: Empty URL detected", idx, client_ip);
         }
-        unique_urls.insert(network_log.url.clone());
         log::debug!(
             "  Log[{}] from IP {}: request_id={}, url={}, method={}, type={}, blocked={}, block_reason={:?}",
             idx, client_ip, network_log.request_id, network_log.url, network_log.method,
             network_log.request_type, network_log.blocked, network_log.block_reason
         );
         if network_log.blocked {
-            blocked_count += 1;
             log::warn!(
                 "🚫 BLOCKED REQUEST from IP {}: url={}, reason={:?}",
                 client_ip,
@@ -88,52 +402,121 @@ pub async fn post_logs_simple(
         }
     }
 
-    let logs_count = log_entry.logs.len();
+    let suspicious_count = annotate_reputation(&mut log_entry, &reputation, &client_ip).await;
+
+    let batch = BatchSummary::of(&log_entry, suspicious_count);
     log::info!(
-        "📊 Batch summary from IP {}: total={}, blocked={}, unique_urls={}",
+        "📊 Batch summary from IP {}: total={}, blocked={}, unique_urls={}, suspicious={}",
         client_ip,
-        logs_count,
-        blocked_count,
-        unique_urls.len()
+        batch.logs,
+        batch.blocked,
+        batch.unique_urls,
+        batch.suspicious
     );
+    count_batch(&req, log_entry.client_id.as_deref(), &batch);
+    lake::append_logs(&log_entry, &client_ip);
 
-    data.add_log(log_entry);
+    let mut packet_ids = Vec::new();
+    for event in detection_events(&req, &log_entry, &beacons, &exfil, &client_ip) {
+        packet_ids.extend(event.data["packet_id"].as_str().map(str::to_string));
+        data.add_extension_event(event);
+    }
+    let details = (query.response == ResponseDetail::Detailed)
+        .then(|| log_details(&log_entry, &packet_ids, &dropped));
+    publish_to_tail(&req, &log_entry);
+    let parts = batch_limit.split(log_entry, &client_ip);
+    let batches = parts.len();
+    for part in parts {
+        data.add_log(part);
+    }
 
     log::info!("✅ Logs stored successfully from IP: {}", client_ip);
-    HttpResponse::Ok().json(serde_json::json!({
+    let summary = serde_json::json!({
         "success": true,
         "message": "Logs stored",
-        "logs_count": logs_count,
-        "blocked_count": blocked_count,
-        "unique_urls": unique_urls.len(),
+        "logs_count": batch.logs,
+        "blocked_count": batch.blocked,
+        "unique_urls": batch.unique_urls,
+        "suspicious_count": batch.suspicious,
+        "dropped_count": dropped.total(),
+        "batches": batches,
         "client_ip": client_ip
-    }))
+    });
+    Ok(ingest_response(query.response, summary, details))
 }
 
 #[cfg(feature = "production")]
 pub async fn post_logs_production(
     req: actix_web::HttpRequest,
     data: web::Data<production::ProductionState>,
-    body: web::BytesMut,
-) -> impl Responder {
+    reputation: web::Data<ReputationService>,
+    beacons: web::Data<BeaconDetector>,
+    exfil: web::Data<ExfilMonitor>,
+    query: web::Query<IngestQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
     let client_ip = get_client_ip(&req);
-    let body_bytes = body.freeze();
+    let batch_limit = BatchLimit::for_request(&req);
+    let type_filter = RequestTypeFilter::for_request(&req);
+    let body_bytes = body;
 
-    let body_str = match decompress_body_if_needed(&req, &body_bytes) {
-        Ok(s) => s,
-        Err(e) => return e,
-    };
+    // A dry run reports an oversized batch instead of refusing it.
+    let max_logs = batch_limit.parse_limit().filter(|_| !query.dry_run);
+    let parsed = parse_log_batch(&req, &body_bytes, max_logs)?;
+    let mut log_entry = parsed.entry;
+    UrlNormalizer::for_request(&req).apply(&mut log_entry);
+    if let Some(categories) = req.app_data::<web::Data<Categories>>() {
+        categories.tag(&mut log_entry);
+    }
 
-    let log_entry: LogEntry = match serde_json::from_str(&body_str) {
-        Ok(e) => e,
-        Err(e) => {
-            log::error!("Failed to parse log entry JSON: {}", e);
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid JSON: {}", e)
-            }));
-        }
+    if query.dry_run {
+        let dropped = type_filter.apply(&mut log_entry);
+        let suspicious_count = annotate_reputation(&mut log_entry, &reputation, &client_ip).await;
+        let batch = BatchSummary::of(&log_entry, suspicious_count);
+        let summary = serde_json::json!({
+            "logs_count": batch.logs,
+            "blocked_count": batch.blocked,
+            "suspicious_count": batch.suspicious,
+            "dropped_count": dropped.total()
+        });
+        let mut warnings = log_warnings(&log_entry);
+        warnings.extend(batch_limit.warning(&log_entry));
+        return Ok(dry_run_response(
+            &req,
+            parsed.decompressed_bytes,
+            &log_entry,
+            summary,
+            warnings,
+        ));
+    }
+
+    batch_limit.admit(&log_entry, &client_ip)?;
+    let dropped = type_filter.apply(&mut log_entry);
+    let usage = Usage {
+        logs: log_entry.logs.len() as u64,
+        events: 0,
+        bytes: parsed.decompressed_bytes as u64,
     };
+    admit_client(&req, log_entry.client_id.as_deref(), usage).await?;
+    if let Some(held) = hold_unenrolled(
+        &req,
+        log_entry.client_id.as_deref(),
+        HeldRef::Log(&log_entry),
+    )
+    .await?
+    {
+        return Ok(held);
+    }
+    dropped.record(&client_ip);
+    log_entry.clock_skew_ms =
+        observe_clock_skew(&req, log_entry.client_id.as_deref(), &log_entry.timestamp);
+    observe_session(&req, &log_entry.session_id, log_entry.client_id.as_deref());
+    observe_distinct(&req, &log_entry);
+    observe_user(&req, &log_entry);
+    observe_ingest(&req, &log_entry);
+    if let Some(screen_time) = req.app_data::<web::Data<ScreenTime>>() {
+        screen_time.record(&log_entry);
+    }
 
     log::info!(
         "📥 Received log entry from IP {}: session_id={}, logs_count={}",
@@ -142,32 +525,128 @@ pub async fn post_logs_production(
         log_entry.logs.len()
     );
 
-    match data.add_log(log_entry).await {
-        Ok(_) => {
-            log::info!("✅ Logs stored successfully from IP: {}", client_ip);
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "message": "Logs stored",
-                "client_ip": client_ip
-            }))
-        }
-        Err(e) => {
-            log::error!("❌ Database error from IP {}: {}", client_ip, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Database error: {}", e),
-                "client_ip": client_ip
-            }))
+    let suspicious_count = annotate_reputation(&mut log_entry, &reputation, &client_ip).await;
+    let batch = BatchSummary::of(&log_entry, suspicious_count);
+    lake::append_logs(&log_entry, &client_ip);
+
+    let mut packet_ids = Vec::new();
+    for event in detection_events(&req, &log_entry, &beacons, &exfil, &client_ip) {
+        let packet_id = event.data["packet_id"].as_str().map(str::to_string);
+        match data.add_extension_event(event).await {
+            Ok(_) => packet_ids.extend(packet_id),
+            Err(e) => log::error!(
+                "❌ Failed to store detection event from IP {}: {}",
+                client_ip,
+                e
+            ),
         }
     }
+    let details = (query.response == ResponseDetail::Detailed)
+        .then(|| log_details(&log_entry, &packet_ids, &dropped));
+
+    let client_id = log_entry.client_id.clone();
+    publish_to_tail(&req, &log_entry);
+    let parts = batch_limit.split(log_entry, &client_ip);
+    let batches = parts.len();
+    for part in parts {
+        data.add_log(part)
+            .await
+            .map_err(|e| db_error(&client_ip, e))?;
+    }
+    count_batch(&req, client_id.as_deref(), &batch);
+    log::info!("✅ Logs stored successfully from IP: {}", client_ip);
+    let summary = serde_json::json!({
+        "success": true,
+        "message": "Logs stored",
+        "logs_count": batch.logs,
+        "blocked_count": batch.blocked,
+        "unique_urls": batch.unique_urls,
+        "suspicious_count": batch.suspicious,
+        "dropped_count": dropped.total(),
+        "batches": batches,
+        "client_ip": client_ip
+    });
+    Ok(ingest_response(query.response, summary, details))
 }
 
+/// `?client_id=` and `?profile_id=` narrow the list down.
 pub async fn get_logs_simple(
     req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
     data: web::Data<simple::SimpleState>,
-) -> impl Responder {
+    query: web::Query<LogsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
     let client_ip = get_client_ip(&req);
-    let logs = data.get_logs();
-    log::info!("📊 Logs requested from IP {}: {} entries", client_ip, logs.len());
-    HttpResponse::Ok().json(logs)
+    let mut logs = data.get_logs();
+    logs.retain(|e| query.matches(e));
+    log::info!(
+        "📊 Logs requested from IP {}: {} entries",
+        client_ip,
+        logs.len()
+    );
+    Ok(match &query.fields {
+        Some(fields) => {
+            HttpResponse::Ok().json(logs.iter().map(|e| fields.project(e)).collect::<Vec<_>>())
+        }
+        None => HttpResponse::Ok().json(logs),
+    })
+}
+
+/// The last `lines` stored batches, oldest first, as NDJSON; with
+/// `follow=true` the response stays open and new batches follow.
+pub async fn tail_logs_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    logs_tail: web::Data<LogTail>,
+    query: web::Query<TailQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let query = query.into_inner();
+    let follow = query.follow.then(|| logs_tail.subscribe());
+    let mut logs = data.get_logs();
+    logs.retain(|e| query.matches(e));
+    let history = logs.split_off(logs.len().saturating_sub(query.lines));
+    log::info!(
+        "📜 Log tail for IP {}: {} batches{}",
+        get_client_ip(&req),
+        history.len(),
+        if query.follow { ", following" } else { "" }
+    );
+    Ok(tail::respond(history, follow, move |e| query.matches(e)))
+}
+
+/// In production each stored request is its own line.
+#[cfg(feature = "production")]
+pub async fn tail_logs_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<production::ProductionState>,
+    logs_tail: web::Data<LogTail>,
+    query: web::Query<TailQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let query = query.into_inner();
+    let client_ip = get_client_ip(&req);
+    // Subscribed first, so nothing accepted during the read is missed.
+    let follow = query.follow.then(|| logs_tail.subscribe());
+    let mut logs = if query.lines == 0 {
+        Vec::new()
+    } else {
+        // Filtered after the read: a filtered tail looks at the newest
+        // MAX_LINES rows only.
+        data.get_logs_before(chrono::Utc::now(), tail::MAX_LINES as i64)
+            .await
+            .map_err(|e| db_error(&client_ip, e))?
+    };
+    logs.retain(|e| query.matches(e));
+    let history = logs.split_off(logs.len().saturating_sub(query.lines));
+    log::info!(
+        "📜 Log tail for IP {}: {} rows{}",
+        client_ip,
+        history.len(),
+        if query.follow { ", following" } else { "" }
+    );
+    Ok(tail::respond(history, follow, move |e| query.matches(e)))
 }
@@ -0,0 +1,144 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::{get_client_ip, parse_duration};
+use crate::maintenance::{Maintenance, MaintenanceWindow};
+use crate::server_events;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct PauseRequest {
+    #[serde(default = "default_true")]
+    pub ingest: bool,
+    #[serde(default)]
+    pub reads: bool,
+    /// Defaults to now.
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Instead of `ends_at`: how long after `starts_at`, e.g. `30m`.
+    pub duration: Option<String>,
+    pub reason: Option<String>,
+}
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    log::error!("❌ Writing the maintenance state failed: {}", e);
+    ApiError::Storage(e.to_string())
+}
+
+fn state(maintenance: &Maintenance) -> serde_json::Value {
+    let window = maintenance.current();
+    serde_json::json!({
+        "paused": window.as_ref().is_some_and(|w| w.is_active(Utc::now())),
+        "window": window,
+        "persisted": maintenance.path().is_some()
+    })
+}
+
+pub async fn get_maintenance(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    maintenance: web::Data<Maintenance>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(state(&maintenance)))
+}
+
+/// Pauses now, or schedules a window with `starts_at`. Replaces any pause
+/// already set.
+pub async fn post_pause(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    maintenance: web::Data<Maintenance>,
+    body: web::Json<PauseRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    if !body.ingest && !body.reads {
+        return Err(ApiError::BadRequest("nothing to pause".into()).with("field", "ingest"));
+    }
+    let now = Utc::now();
+    let starts_at = body.starts_at.unwrap_or(now);
+    let ends_at = match (body.ends_at, &body.duration) {
+        (Some(_), Some(_)) => {
+            return Err(
+                ApiError::BadRequest("give ends_at or duration, not both".into())
+                    .with("field", "duration"),
+            )
+        }
+        (Some(end), None) => Some(end),
+        (None, Some(d)) => Some(
+            starts_at
+                + parse_duration(d).ok_or_else(|| {
+                    ApiError::BadRequest("duration must look like 30m or 2h".into())
+                        .with("field", "duration")
+                        .with("value", d)
+                })?,
+        ),
+        (None, None) => None,
+    };
+    if ends_at.is_some_and(|end| end <= starts_at.max(now)) {
+        return Err(ApiError::BadRequest(
+            "the window has to end after it starts and in the future".into(),
+        )
+        .with("field", "ends_at"));
+    }
+    let client_ip = get_client_ip(&req);
+    let window = MaintenanceWindow {
+        ingest: body.ingest,
+        reads: body.reads,
+        starts_at,
+        ends_at,
+        reason: body.reason,
+        set_by: client_ip.clone(),
+        set_at: now,
+    };
+    maintenance.pause(window.clone()).map_err(storage_error)?;
+    log::warn!(
+        "🚧 Maintenance pause set by {}: ingest={} reads={} from {} until {}",
+        client_ip,
+        window.ingest,
+        window.reads,
+        window.starts_at.to_rfc3339(),
+        window
+            .ends_at
+            .map_or("resumed".to_string(), |t| t.to_rfc3339())
+    );
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "maintenance",
+            "paused": true,
+            "window": window,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(state(&maintenance)))
+}
+
+pub async fn post_resume(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    maintenance: web::Data<Maintenance>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let Some(window) = maintenance.resume().map_err(storage_error)? else {
+        return Err(ApiError::NotFound("no maintenance pause is set".into()));
+    };
+    let client_ip = get_client_ip(&req);
+    log::warn!("🚧 Maintenance pause lifted by {}", client_ip);
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "maintenance",
+            "paused": false,
+            "window": window,
+            "changed_by": client_ip
+        }),
+    );
+    Ok(HttpResponse::Ok().json(state(&maintenance)))
+}
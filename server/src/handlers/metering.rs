@@ -0,0 +1,38 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::query::{UsageFormat, UsageQuery};
+use crate::metering::{self, Metering};
+use actix_web::{web, HttpResponse};
+
+/// Per-org usage for a month, as JSON or as a CSV to invoice from.
+pub async fn get_usage(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    metering: web::Data<Metering>,
+    query: web::Query<UsageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let month = query.month.clone().unwrap_or_else(metering::current_month);
+    let report = metering
+        .report(query.org.as_deref(), &month)
+        .await
+        .ok_or_else(|| ApiError::BadRequest(format!("no such month: {}", month)))?;
+    log::info!(
+        "🧾 Usage for {} requested from IP {}: {} orgs ({})",
+        month,
+        get_client_ip(&req),
+        report.orgs.len(),
+        report.source
+    );
+    match query.format {
+        UsageFormat::Json => Ok(HttpResponse::Ok().json(report)),
+        UsageFormat::Csv => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"usage-{}.csv\"", month),
+            ))
+            .body(report.to_csv())),
+    }
+}
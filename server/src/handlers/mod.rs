@@ -1,5 +1,38 @@
+pub mod admin;
+pub mod alerts;
+pub mod annotations;
+pub mod backup;
 pub mod blocklist;
+pub mod bundle;
+pub mod categories;
+pub mod chain;
+pub mod clients;
 pub mod common;
 pub mod dashboard;
+pub mod detection;
+pub mod domains;
+pub mod enrollment;
+pub mod experiments;
+pub mod extension_errors;
 pub mod extensions;
+pub mod features;
+pub mod focus;
+pub mod groups;
+pub mod honeypot;
+#[cfg(feature = "production")]
+pub mod hybrid;
+pub mod import;
+pub mod integrations;
+pub mod jobs;
 pub mod logs;
+pub mod maintenance;
+pub mod metering;
+pub mod oidc;
+pub mod query;
+pub mod seed;
+pub mod stats;
+pub mod telemetry;
+pub mod training;
+pub mod triage;
+pub mod uploads;
+pub mod users;
@@ -0,0 +1,121 @@
+use crate::auth::{self, AdminAuth, Identity};
+use crate::error::ApiError;
+use crate::external_url;
+use crate::handlers::common::get_client_ip;
+use crate::oidc::{self, Oidc};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    /// Path on this server to land on once logged in.
+    pub return_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+fn configured(auth: &AdminAuth) -> Result<&Arc<Oidc>, ApiError> {
+    auth.oidc().ok_or_else(|| {
+        ApiError::NotFound("single sign-on isn't configured (--oidc-config)".to_string())
+    })
+}
+
+/// Only paths on this server, so the login can't be used to send users
+/// elsewhere.
+fn local_path(return_to: Option<String>) -> String {
+    return_to
+        .filter(|p| p.starts_with('/') && !p.starts_with("//") && !p.contains('\\'))
+        .unwrap_or_else(|| "/dashboard".to_string())
+}
+
+fn state_cookie(value: String, secure: bool) -> Cookie<'static> {
+    // Lax: the provider sends the browser back with a cross-site redirect.
+    Cookie::build(oidc::STATE_COOKIE, value)
+        .path(oidc::CALLBACK_PATH)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(secure)
+        .max_age(actix_web::cookie::time::Duration::seconds(
+            oidc::LOGIN_TIMEOUT.as_secs() as i64,
+        ))
+        .finish()
+}
+
+/// Sends the browser to the identity provider.
+pub async fn get_login(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    query: web::Query<LoginQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let oidc = configured(&auth)?;
+    let redirect_uri = oidc
+        .config()
+        .redirect_url
+        .clone()
+        .unwrap_or_else(|| external_url::absolute(&req, oidc::CALLBACK_PATH));
+    let return_to = local_path(query.into_inner().return_to);
+    let (url, state) = oidc.begin(redirect_uri, return_to).await?;
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", url))
+        .cookie(state_cookie(state, auth::is_https(&req)))
+        .finish())
+}
+
+/// Where the provider sends the browser back: starts a dashboard session
+/// and goes on to the page the login started from.
+pub async fn get_callback(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    query: web::Query<CallbackQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let oidc = configured(&auth)?;
+    let query = query.into_inner();
+    if let Some(error) = query.error {
+        log::warn!(
+            "🪪 OIDC login from IP {} failed at the provider: {}",
+            get_client_ip(&req),
+            error
+        );
+        return Err(ApiError::Unauthorized.with("reason", query.error_description.unwrap_or(error)));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(ApiError::BadRequest(
+            "code and state are required".to_string(),
+        ));
+    };
+    let cookie_state = req.cookie(oidc::STATE_COOKIE);
+    if cookie_state.as_ref().map(|c| c.value()) != Some(state.as_str()) {
+        crate::metrics::CSRF_REJECTED.inc();
+        return Err(ApiError::CsrfFailed(
+            "login state doesn't match this browser",
+        ));
+    }
+    let (user, return_to) = oidc.finish(&state, &code).await?;
+    log::info!(
+        "🔐 Dashboard login of {} ({}) from IP {} through single sign-on",
+        user.name,
+        user.role.name(),
+        get_client_ip(&req)
+    );
+    let login = auth
+        .start_session(Identity {
+            user: user.name,
+            role: user.role,
+        })
+        .await;
+    let mut clear_state = state_cookie(String::new(), auth::is_https(&req));
+    clear_state.make_removal();
+    Ok(HttpResponse::Found()
+        .insert_header(("Location", return_to))
+        .cookie(auth.session_cookie(&req, &login))
+        .cookie(clear_state)
+        .finish())
+}
@@ -0,0 +1,622 @@
+//! Query-string parameters, one struct per family of routes, extracted with
+//! `web::Query`. Values are checked while deserializing and a bad one is
+//! rejected with a 400 naming the parameter (see [`config`]). Unknown
+//! parameters are ignored.
+
+use crate::error::ApiError;
+use crate::handlers::common::parse_duration;
+use crate::seed::Profile;
+use crate::summary::Period;
+use crate::types::{ExtensionEvent, LogEntry, TriageStatus};
+use actix_web::{web, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_STATS_LIMIT: usize = 50;
+pub const MAX_STATS_LIMIT: usize = 1000;
+/// Paths one `fields=` may name.
+pub const MAX_FIELDS: usize = 64;
+
+/// Rejected query strings get the usual error envelope.
+pub fn config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let message = match &err {
+            actix_web::error::QueryPayloadError::Deserialize(e) => e.to_string(),
+            other => other.to_string(),
+        };
+        let resp = ApiError::BadRequest(format!("Invalid query: {}", message)).error_response();
+        actix_web::error::InternalError::from_response(err, resp).into()
+    })
+}
+
+/// `?filter=` on `/api/dashboard/events`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventFilter {
+    #[default]
+    All,
+    Security,
+    Javascript,
+    Server,
+}
+
+impl EventFilter {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventFilter::All => "all",
+            EventFilter::Security => "security",
+            EventFilter::Javascript => "javascript",
+            EventFilter::Server => "server",
+        }
+    }
+
+    pub fn matches(self, category: &str) -> bool {
+        match self {
+            EventFilter::All => true,
+            EventFilter::Security => category == "security",
+            EventFilter::Javascript => category == "javascript",
+            EventFilter::Server => category == "server",
+        }
+    }
+}
+
+/// `/api/dashboard/events` and `/api/dashboard/clients`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DashboardQuery {
+    #[serde(default, deserialize_with = "filter")]
+    pub filter: EventFilter,
+    /// Archived clients are left out unless this is set.
+    #[serde(default, deserialize_with = "include_archived")]
+    pub include_archived: bool,
+    /// Only events with this triage status (events endpoint only).
+    #[serde(default, deserialize_with = "status")]
+    pub status: Option<TriageStatus>,
+    /// Only events assigned to this analyst (events endpoint only).
+    #[serde(default)]
+    pub assignee: Option<String>,
+    /// Only events from this browser profile.
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    /// Only events timestamped at or after this (events endpoint only).
+    #[serde(default, deserialize_with = "from")]
+    pub from: Option<DateTime<Utc>>,
+    /// Only events timestamped before this (events endpoint only).
+    #[serde(default, deserialize_with = "to")]
+    pub to: Option<DateTime<Utc>>,
+    /// Compare `from` / `to` with timestamps corrected for the client's
+    /// clock skew.
+    #[serde(default, deserialize_with = "corrected_time")]
+    pub corrected_time: bool,
+    /// Only these fields of each row (events endpoint only).
+    #[serde(default, deserialize_with = "fields")]
+    pub fields: Option<Fields>,
+}
+
+impl DashboardQuery {
+    pub fn in_range(&self, event: &ExtensionEvent) -> bool {
+        let skew = event.data.get("clock_skew_ms").and_then(|v| v.as_i64());
+        in_time_range(
+            self.from,
+            self.to,
+            self.corrected_time,
+            &event.timestamp,
+            skew,
+        )
+    }
+}
+
+/// `GET /api/logs`.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogsQuery {
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    #[serde(default, deserialize_with = "from")]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "to")]
+    pub to: Option<DateTime<Utc>>,
+    /// See [`DashboardQuery::corrected_time`].
+    #[serde(default, deserialize_with = "corrected_time")]
+    pub corrected_time: bool,
+    /// Only these fields of each batch.
+    #[serde(default, deserialize_with = "fields")]
+    pub fields: Option<Fields>,
+}
+
+impl LogsQuery {
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.client_id
+            .as_ref()
+            .is_none_or(|c| entry.client_id.as_ref() == Some(c))
+            && self
+                .profile_id
+                .as_ref()
+                .is_none_or(|p| entry.profile_id.as_ref() == Some(p))
+            && in_time_range(
+                self.from,
+                self.to,
+                self.corrected_time,
+                &entry.timestamp,
+                entry.clock_skew_ms,
+            )
+    }
+}
+
+/// Whether a record stamped `timestamp` falls in `[from, to)`; with
+/// `corrected`, the client's clock offset `skew_ms` is taken off first. With
+/// either bound set, a timestamp that isn't RFC 3339 is out of range.
+fn in_time_range(
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    corrected: bool,
+    timestamp: &str,
+    skew_ms: Option<i64>,
+) -> bool {
+    if from.is_none() && to.is_none() {
+        return true;
+    }
+    let Ok(at) = DateTime::parse_from_rfc3339(timestamp) else {
+        return false;
+    };
+    let mut at = at.with_timezone(&Utc);
+    if corrected {
+        at -= chrono::Duration::milliseconds(skew_ms.unwrap_or(0));
+    }
+    from.is_none_or(|from| at >= from) && to.is_none_or(|to| at < to)
+}
+
+/// `?fields=` on read endpoints: the fields of each record to answer with,
+/// comma-separated. A `.` reaches into a nested object, or into each object
+/// of a nested array: `client_id,timestamp,logs.url` keeps a log batch's
+/// client, time and request URLs. Names a record doesn't have are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fields(BTreeMap<String, Selection>);
+
+/// What is kept of one field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selection {
+    Whole,
+    Only(Fields),
+}
+
+impl Fields {
+    fn parse(value: &str) -> Option<Self> {
+        let paths: Vec<&str> = value.split(',').map(str::trim).collect();
+        if paths.len() > MAX_FIELDS {
+            return None;
+        }
+        let mut fields = Fields::default();
+        for path in paths {
+            let names: Vec<&str> = path.split('.').collect();
+            if names.iter().any(|n| n.is_empty()) {
+                return None;
+            }
+            fields.insert(&names);
+        }
+        Some(fields)
+    }
+
+    fn insert(&mut self, names: &[&str]) {
+        let Some((first, rest)) = names.split_first() else {
+            return;
+        };
+        let selection = self
+            .0
+            .entry(first.to_string())
+            .or_insert_with(|| Selection::Only(Fields::default()));
+        match selection {
+            // A whole field already covers anything under it.
+            Selection::Whole => {}
+            Selection::Only(_) if rest.is_empty() => *selection = Selection::Whole,
+            Selection::Only(inner) => inner.insert(rest),
+        }
+    }
+
+    /// `record` with only the selected fields.
+    pub fn project<T: Serialize>(&self, record: &T) -> Value {
+        self.project_value(serde_json::to_value(record).unwrap_or_default())
+    }
+
+    pub fn project_value(&self, value: Value) -> Value {
+        match value {
+            Value::Object(mut map) => Value::Object(
+                self.0
+                    .iter()
+                    .filter_map(|(name, selection)| {
+                        let v = map.remove(name)?;
+                        let v = match selection {
+                            Selection::Whole => v,
+                            Selection::Only(inner) => inner.project_value(v),
+                        };
+                        Some((name.clone(), v))
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => {
+                Value::Array(items.into_iter().map(|v| self.project_value(v)).collect())
+            }
+            other => other,
+        }
+    }
+}
+
+/// `/api/dashboard/events/{packet_id}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct FieldsQuery {
+    #[serde(default, deserialize_with = "fields")]
+    pub fields: Option<Fields>,
+}
+
+/// `GET /api/blocklist`.
+#[derive(Debug, Default, Deserialize)]
+pub struct BlocklistQuery {
+    /// The client asking; its exhausted screen-time categories are added.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// The browser profile asking; its own entries are merged in.
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    /// `bloom` compresses the domain patterns into a Bloom filter.
+    #[serde(default, deserialize_with = "blocklist_format")]
+    pub format: BlocklistFormat,
+    /// The Bloom filter's false-positive rate.
+    #[serde(default, deserialize_with = "fp_rate")]
+    pub fp_rate: Option<f64>,
+}
+
+/// `GET /api/blocklist/delta`.
+#[derive(Debug, Deserialize)]
+pub struct BlocklistDeltaQuery {
+    /// The `X-Blocklist-Version` the client has; 0 for none.
+    pub from_version: u64,
+    /// Lets a blocklist rollout answer canary clients with their list.
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlocklistFormat {
+    #[default]
+    Json,
+    Bloom,
+}
+
+/// `/api/clients/{id}/effective-policy`.
+#[derive(Debug, Deserialize)]
+pub struct PolicyQuery {
+    /// Resolve for this browser profile of the client.
+    #[serde(default)]
+    pub profile_id: Option<String>,
+}
+
+/// `/api/admin/chain/verify`.
+#[derive(Debug, Deserialize)]
+pub struct ChainQuery {
+    /// Verify only this client's chain.
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+/// `/api/export/training-set`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TrainingSetQuery {
+    /// Share of `security` events kept, 0 to 1.
+    pub security_rate: Option<f64>,
+    /// Share of `benign` events kept, 0 to 1.
+    pub benign_rate: Option<f64>,
+    /// The same seed samples the same events.
+    pub seed: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// `/api/clients/clock-skew`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ClockSkewQuery {
+    #[serde(default, deserialize_with = "flagged")]
+    pub flagged: bool,
+}
+
+/// `/api/stats`.
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    #[serde(default = "default_stats_limit", deserialize_with = "limit")]
+    pub limit: usize,
+}
+
+/// `/api/clients/{client_id}/summary`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SummaryQuery {
+    #[serde(default, deserialize_with = "period")]
+    pub period: Period,
+}
+
+/// `/api/domains`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DomainsQuery {
+    /// Only domains first seen after this point (given as an age, e.g. `24h`).
+    #[serde(default, deserialize_with = "new_since")]
+    pub new_since: Option<DateTime<Utc>>,
+}
+
+/// `/api/logs`, `/api/extensions` and `/api/security`.
+#[derive(Debug, Default, Deserialize)]
+pub struct IngestQuery {
+    #[serde(default, deserialize_with = "dry_run")]
+    pub dry_run: bool,
+    #[serde(default, deserialize_with = "response_detail")]
+    pub response: ResponseDetail,
+}
+
+/// How much an accepted ingest request answers with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseDetail {
+    /// `204 No Content`.
+    Minimal,
+    #[default]
+    Summary,
+    /// The summary plus validation warnings and the IDs given to records.
+    Detailed,
+}
+
+/// `/api/logs/import`. The format itself is checked by `import::Format::detect`,
+/// which also falls back to the content type.
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    pub format: Option<String>,
+}
+
+/// `/api/logs/tail`.
+#[derive(Debug, Deserialize)]
+pub struct TailQuery {
+    #[serde(default = "default_tail_lines", deserialize_with = "lines")]
+    pub lines: usize,
+    #[serde(default, deserialize_with = "follow")]
+    pub follow: bool,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub profile_id: Option<String>,
+}
+
+fn default_tail_lines() -> usize {
+    100
+}
+
+impl TailQuery {
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.client_id
+            .as_ref()
+            .is_none_or(|c| entry.client_id.as_ref() == Some(c))
+            && self
+                .profile_id
+                .as_ref()
+                .is_none_or(|p| entry.profile_id.as_ref() == Some(p))
+    }
+}
+
+/// `/api/admin/seed`.
+#[derive(Debug, Deserialize)]
+pub struct SeedQuery {
+    #[serde(default)]
+    pub profile: Profile,
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+}
+
+fn default_seed() -> u64 {
+    1
+}
+
+/// `/api/admin/jobs/{name}/history`.
+#[derive(Debug, Default, Deserialize)]
+pub struct JobHistoryQuery {
+    pub limit: Option<usize>,
+}
+
+/// `/api/admin/usage`.
+#[derive(Debug, Default, Deserialize)]
+pub struct UsageQuery {
+    /// Only this org; every org with usage otherwise.
+    #[serde(default)]
+    pub org: Option<String>,
+    /// `YYYY-MM`; the current UTC month by default.
+    #[serde(default, deserialize_with = "month")]
+    pub month: Option<String>,
+    #[serde(default, deserialize_with = "usage_format")]
+    pub format: UsageFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UsageFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+fn default_stats_limit() -> usize {
+    DEFAULT_STATS_LIMIT
+}
+
+fn invalid<E: serde::de::Error>(field: &str, value: &str, expected: &str) -> E {
+    E::custom(format!("{}: expected {}, got '{}'", field, expected, value))
+}
+
+fn flag<'de, D: Deserializer<'de>>(d: D, field: &str) -> Result<bool, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" | "" => Ok(false),
+        _ => Err(invalid(field, &value, "true, false, 1 or 0")),
+    }
+}
+
+fn dry_run<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    flag(d, "dry_run")
+}
+
+fn response_detail<'de, D: Deserializer<'de>>(d: D) -> Result<ResponseDetail, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "minimal" => Ok(ResponseDetail::Minimal),
+        "summary" | "" => Ok(ResponseDetail::Summary),
+        "detailed" => Ok(ResponseDetail::Detailed),
+        _ => Err(invalid("response", &value, "minimal, summary or detailed")),
+    }
+}
+
+fn corrected_time<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    flag(d, "corrected_time")
+}
+
+fn timestamp<'de, D: Deserializer<'de>>(
+    d: D,
+    field: &str,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = String::deserialize(d)?;
+    match DateTime::parse_from_rfc3339(&value) {
+        Ok(at) => Ok(Some(at.with_timezone(&Utc))),
+        Err(_) => Err(invalid(field, &value, "an RFC 3339 timestamp")),
+    }
+}
+
+fn from<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    timestamp(d, "from")
+}
+
+fn to<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    timestamp(d, "to")
+}
+
+fn flagged<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    flag(d, "flagged")
+}
+
+fn follow<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    flag(d, "follow")
+}
+
+fn lines<'de, D: Deserializer<'de>>(d: D) -> Result<usize, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.parse::<usize>() {
+        Ok(n) if n <= crate::tail::MAX_LINES => Ok(n),
+        _ => Err(invalid(
+            "lines",
+            &value,
+            &format!("a number from 0 to {}", crate::tail::MAX_LINES),
+        )),
+    }
+}
+
+fn include_archived<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    flag(d, "include_archived")
+}
+
+fn filter<'de, D: Deserializer<'de>>(d: D) -> Result<EventFilter, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "all" | "" => Ok(EventFilter::All),
+        "security" => Ok(EventFilter::Security),
+        "javascript" => Ok(EventFilter::Javascript),
+        "server" => Ok(EventFilter::Server),
+        _ => Err(invalid(
+            "filter",
+            &value,
+            "one of all, security, javascript, server",
+        )),
+    }
+}
+
+fn status<'de, D: Deserializer<'de>>(d: D) -> Result<Option<TriageStatus>, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "" => Ok(None),
+        s => TriageStatus::parse(s).map(Some).ok_or_else(|| {
+            invalid(
+                "status",
+                &value,
+                "one of new, acknowledged, false-positive, escalated",
+            )
+        }),
+    }
+}
+
+fn limit<'de, D: Deserializer<'de>>(d: D) -> Result<usize, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.parse::<usize>() {
+        Ok(n) if (1..=MAX_STATS_LIMIT).contains(&n) => Ok(n),
+        _ => Err(invalid(
+            "limit",
+            &value,
+            &format!("a number from 1 to {}", MAX_STATS_LIMIT),
+        )),
+    }
+}
+
+fn blocklist_format<'de, D: Deserializer<'de>>(d: D) -> Result<BlocklistFormat, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "json" | "" => Ok(BlocklistFormat::Json),
+        "bloom" => Ok(BlocklistFormat::Bloom),
+        _ => Err(invalid("format", &value, "json or bloom")),
+    }
+}
+
+fn usage_format<'de, D: Deserializer<'de>>(d: D) -> Result<UsageFormat, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.as_str() {
+        "json" | "" => Ok(UsageFormat::Json),
+        "csv" => Ok(UsageFormat::Csv),
+        _ => Err(invalid("format", &value, "json or csv")),
+    }
+}
+
+fn month<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let value = String::deserialize(d)?;
+    match crate::metering::month_days(&value) {
+        Some(_) if value.len() == 7 => Ok(Some(value)),
+        _ => Err(invalid("month", &value, "a month such as 2024-05")),
+    }
+}
+
+fn fp_rate<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    let value = String::deserialize(d)?;
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate < 0.5 => Ok(Some(rate)),
+        _ => Err(invalid("fp_rate", &value, "a number between 0 and 0.5")),
+    }
+}
+
+fn period<'de, D: Deserializer<'de>>(d: D) -> Result<Period, D::Error> {
+    let value = String::deserialize(d)?;
+    Period::parse(&value).ok_or_else(|| invalid("period", &value, "one of day, week, month"))
+}
+
+fn new_since<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = String::deserialize(d)?;
+    match parse_duration(&value) {
+        Some(age) => Ok(Some(Utc::now() - age)),
+        None => Err(invalid(
+            "new_since",
+            &value,
+            "an age such as 30m, 24h or 7d",
+        )),
+    }
+}
+
+fn fields<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Fields>, D::Error> {
+    let value = String::deserialize(d)?;
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    Fields::parse(&value).map(Some).ok_or_else(|| {
+        invalid(
+            "fields",
+            &value,
+            &format!(
+                "up to {} comma-separated field names, with '.' for nested fields",
+                MAX_FIELDS
+            ),
+        )
+    })
+}
@@ -0,0 +1,103 @@
+use crate::auth::AdminAuth;
+use crate::categories::Categories;
+use crate::error::ApiError;
+use crate::handlers::backup::Target;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::query::SeedQuery;
+use crate::seed;
+use crate::simple::SimpleState;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::production::ProductionState;
+
+/// Generates the profile's data and stores what isn't there yet, the way a
+/// restore does.
+async fn seed_into(
+    req: &actix_web::HttpRequest,
+    auth: &AdminAuth,
+    target: Target,
+    query: &SeedQuery,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(req).await?;
+    let client_ip = get_client_ip(req);
+    let mut seeded = seed::generate(query.profile, query.seed, chrono::Utc::now());
+    if let Some(categories) = req.app_data::<web::Data<Categories>>() {
+        for entry in &mut seeded.logs {
+            categories.tag(entry);
+        }
+    }
+
+    let (mut logs_seen, mut events_seen) = target.stored();
+    let (mut requests, mut events) = (0, 0);
+    for entry in seeded.logs {
+        let count = entry.logs.len();
+        if target
+            .restore_log(entry, &mut logs_seen)
+            .await
+            .map_err(ApiError::Database)?
+        {
+            requests += count;
+        }
+    }
+    for event in seeded.events {
+        if target
+            .restore_event(event, &mut events_seen)
+            .await
+            .map_err(ApiError::Database)?
+        {
+            events += 1;
+        }
+    }
+
+    log::info!(
+        "🌱 Seeded {} profile (seed {}) from IP {}: {} requests, {} events",
+        query.profile.name(),
+        query.seed,
+        client_ip,
+        requests,
+        events
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "profile": query.profile.name(),
+        "seed": query.seed,
+        "mode": target.mode(),
+        "clients": seeded.clients,
+        "sessions": seeded.sessions,
+        "requests": requests,
+        "events": events,
+        "from": seeded.from,
+        "to": seeded.to
+    })))
+}
+
+pub async fn seed_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<SimpleState>,
+    query: web::Query<SeedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    seed_into(&req, &auth, Target::Simple(data), &query).await
+}
+
+#[cfg(feature = "production")]
+pub async fn seed_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<ProductionState>,
+    query: web::Query<SeedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    seed_into(&req, &auth, Target::Production(data), &query).await
+}
+
+#[cfg(feature = "production")]
+pub async fn seed_hybrid(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    hot: web::Data<SimpleState>,
+    warm: web::Data<ProductionState>,
+    query: web::Query<SeedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    seed_into(&req, &auth, Target::Hybrid { hot, warm }, &query).await
+}
@@ -0,0 +1,139 @@
+use crate::asn::AsnEnricher;
+use crate::auth::AdminAuth;
+use crate::counters::IngestCounters;
+use crate::distinct::DistinctCounters;
+use crate::error::ApiError;
+use crate::handlers::common::{cached_json, get_client_ip};
+use crate::handlers::query::StatsQuery;
+use crate::response_cache::ResponseCache;
+use crate::simple;
+use crate::stats;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+fn cache_key(query: &StatsQuery) -> String {
+    format!("stats?limit={}", query.limit)
+}
+
+/// Simple mode aggregates the in-memory logs on every request the cache
+/// doesn't answer.
+pub async fn get_stats_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    distinct: web::Data<DistinctCounters>,
+    counters: web::Data<IngestCounters>,
+    cache: web::Data<ResponseCache>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let key = cache_key(&query);
+    if let Some(stats) = cache.get(&key) {
+        return Ok(cached_json(&stats, true));
+    }
+    let logs = data.get_logs();
+    let (domains, clients) = stats::aggregate(&logs, query.limit);
+    let categories = stats::aggregate_categories(&logs);
+    log::info!(
+        "📈 Stats requested from IP {}: {} domains, {} clients",
+        get_client_ip(&req),
+        domains.len(),
+        clients.len()
+    );
+    let stats = serde_json::json!({
+        "source": "live",
+        "refreshed_at": chrono::Utc::now(),
+        "age_secs": 0,
+        "stale": false,
+        "domains": domains,
+        "clients": clients,
+        "categories": categories,
+        "distinct": distinct.snapshot().await,
+        "ingest": counters.snapshot(query.limit).await
+    });
+    cache.put(key, &stats);
+    Ok(cached_json(&stats, false))
+}
+
+/// Production mode reads the precomputed aggregates; `stale` is set when the
+/// background refresh has fallen behind.
+#[cfg(feature = "production")]
+pub async fn get_stats_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<production::ProductionState>,
+    refresher: web::Data<stats::StatsRefresher>,
+    (distinct, counters): (web::Data<DistinctCounters>, web::Data<IngestCounters>),
+    cache: web::Data<ResponseCache>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let key = cache_key(&query);
+    if let Some(stats) = cache.get(&key) {
+        return Ok(cached_json(&stats, true));
+    }
+    let client_ip = get_client_ip(&req);
+    let (refreshed_at, age_secs, stale) = refresher.staleness();
+    let (domains, clients) = data
+        .get_stats(query.limit as i64)
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let categories = data
+        .get_category_stats()
+        .await
+        .map_err(|e| db_error(&client_ip, e))?;
+    let stats = serde_json::json!({
+        "source": "materialized",
+        "refreshed_at": refreshed_at,
+        "age_secs": age_secs,
+        "stale": stale,
+        "domains": domains,
+        "clients": clients,
+        "categories": categories,
+        "distinct": distinct.snapshot().await,
+        "ingest": counters.snapshot(query.limit).await
+    });
+    cache.put(key, &stats);
+    Ok(cached_json(&stats, false))
+}
+
+/// Requests per hosting provider (autonomous system) since the process
+/// started, busiest first; 404 without `--asn-db`.
+pub async fn get_provider_stats(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    enricher: web::Data<AsnEnricher>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    if !enricher.is_enabled() {
+        return Err(ApiError::NotFound(
+            "ASN enrichment is off; start the server with --asn-db".into(),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(enricher.snapshot(query.limit)))
+}
+
+/// Drops every cached stats and summary response, so the next request
+/// reads fresh numbers.
+pub async fn delete_stats_cache(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    cache: web::Data<ResponseCache>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let cleared = cache.clear();
+    log::info!(
+        "🧹 {} cached responses cleared by IP {}",
+        cleared,
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "cleared": cleared
+    })))
+}
@@ -0,0 +1,55 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::metrics;
+use crate::telemetry::{PerfReport, PerfTelemetry};
+use actix_web::{web, HttpResponse};
+
+/// Adds the extension's performance samples to its version's aggregate. Like
+/// the other ingest endpoints, it needs no token.
+pub async fn post_telemetry(
+    req: actix_web::HttpRequest,
+    telemetry: web::Data<PerfTelemetry>,
+    body: web::Json<PerfReport>,
+) -> Result<HttpResponse, ApiError> {
+    let report = body.into_inner();
+    report.validate().map_err(ApiError::BadRequest)?;
+    let perf = telemetry.record(&report);
+    metrics::PERF_SAMPLES.add(report.sample_count() as u64);
+    log::debug!(
+        "📈 {} performance samples for extension {} from {}",
+        report.sample_count(),
+        perf.extension_version,
+        get_client_ip(&req)
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "extension_version": perf.extension_version,
+        "slow": perf.slow
+    })))
+}
+
+pub async fn get_telemetry(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    telemetry: web::Data<PerfTelemetry>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "versions": telemetry.versions() })))
+}
+
+pub async fn get_version_telemetry(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    telemetry: web::Data<PerfTelemetry>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    match telemetry.get(&path) {
+        Some(perf) => Ok(HttpResponse::Ok().json(perf)),
+        None => Err(
+            ApiError::NotFound("no telemetry from this version".to_string())
+                .with("extension_version", &*path),
+        ),
+    }
+}
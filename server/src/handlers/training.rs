@@ -0,0 +1,107 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::handlers::query::TrainingSetQuery;
+use crate::simple;
+use crate::training::{self, Counts, Sampling, Scrubber};
+use crate::triage::TriageStore;
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "production")]
+use crate::handlers::common::db_error;
+#[cfg(feature = "production")]
+use crate::production;
+
+fn sampling(query: TrainingSetQuery) -> Result<Sampling, ApiError> {
+    Sampling::new(
+        query.security_rate,
+        query.benign_rate,
+        query.seed,
+        query.limit,
+    )
+    .map_err(ApiError::BadRequest)
+}
+
+fn exported(req: &actix_web::HttpRequest, counts: Counts, body: Vec<u8>) -> HttpResponse {
+    log::info!(
+        "🧪 Training set exported to IP {}: {} security, {} benign of {} events",
+        get_client_ip(req),
+        counts.security,
+        counts.benign,
+        counts.scanned
+    );
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"training-set.ndjson\"",
+        ))
+        .insert_header(("X-Training-Security", counts.security.to_string()))
+        .insert_header(("X-Training-Benign", counts.benign.to_string()))
+        .body(body)
+}
+
+/// A labeled, scrubbed sample of the stored events, as NDJSON.
+pub async fn get_training_set_simple(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<simple::SimpleState>,
+    triage: web::Data<TriageStore>,
+    query: web::Query<TrainingSetQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let sampling = sampling(query.into_inner())?;
+    let mut counts = Counts::default();
+    let mut body = Vec::new();
+    training::sample_into(
+        &data.get_extension_events(),
+        &sampling,
+        &Scrubber::new(),
+        &triage,
+        &mut counts,
+        &mut body,
+    );
+    Ok(exported(&req, counts, body))
+}
+
+/// Reads the stored events a page at a time, oldest first, until the
+/// sample is full.
+#[cfg(feature = "production")]
+pub async fn get_training_set_production(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    data: web::Data<production::ProductionState>,
+    triage: web::Data<TriageStore>,
+    query: web::Query<TrainingSetQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let sampling = sampling(query.into_inner())?;
+    let client_ip = get_client_ip(&req);
+    let scrubber = Scrubber::new();
+    let as_of = chrono::Utc::now();
+    let mut counts = Counts::default();
+    let mut body = Vec::new();
+    let mut after = 0;
+    loop {
+        let page = data
+            .export_extension_events(after, as_of, crate::backup::PAGE)
+            .await
+            .map_err(|e| db_error(&client_ip, e))?;
+        let Some(&(last, _)) = page.last() else {
+            break;
+        };
+        after = last;
+        let events: Vec<_> = page.into_iter().map(|(_, e)| e).collect();
+        if training::sample_into(
+            &events,
+            &sampling,
+            &scrubber,
+            &triage,
+            &mut counts,
+            &mut body,
+        ) {
+            break;
+        }
+    }
+    Ok(exported(&req, counts, body))
+}
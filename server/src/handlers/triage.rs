@@ -0,0 +1,251 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::tickets::{self, Case, Ticketing};
+use crate::triage::{TriageStore, MAX_COMMENT_LEN, MAX_NAME_LEN, MAX_NOTE_LEN};
+use crate::types::{EventComment, TriageStatus};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct StatusRequest {
+    pub status: String,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AssignRequest {
+    /// `null` or empty unassigns.
+    pub assignee: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CommentRequest {
+    pub body: String,
+    /// The comment this one replies to.
+    pub parent_id: Option<i64>,
+    /// Defaults to the caller's address.
+    pub author: Option<String>,
+}
+
+/// A comment with its replies, for the threaded listing.
+#[derive(Serialize)]
+struct CommentThread<'a> {
+    #[serde(flatten)]
+    comment: &'a EventComment,
+    replies: Vec<CommentThread<'a>>,
+}
+
+/// Nests `comments` (oldest first) under their parents. A reply whose parent
+/// is missing is shown at the top level.
+fn thread(comments: &[EventComment]) -> Vec<CommentThread<'_>> {
+    fn children<'a>(comments: &'a [EventComment], parent: Option<i64>) -> Vec<CommentThread<'a>> {
+        comments
+            .iter()
+            .filter(|c| match parent {
+                Some(id) => c.parent_id == Some(id),
+                None => c
+                    .parent_id
+                    .is_none_or(|p| !comments.iter().any(|other| other.id == p)),
+            })
+            .map(|c| CommentThread {
+                comment: c,
+                replies: children(comments, Some(c.id)),
+            })
+            .collect()
+    }
+    children(comments, None)
+}
+
+/// Trimmed, `None` when empty; 400 naming `field` when over `max` characters.
+fn optional_text(
+    value: Option<String>,
+    field: &'static str,
+    max: usize,
+) -> Result<Option<String>, ApiError> {
+    let value = value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+        return Err(
+            ApiError::BadRequest(format!("{} is longer than {} characters", field, max))
+                .with("field", field),
+        );
+    }
+    Ok(value)
+}
+
+/// Sets the triage status of one event: `{"status": "acknowledged", "note": "..."}`.
+pub async fn post_event_status(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<TriageStore>,
+    body: web::Json<StatusRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let body = body.into_inner();
+    let status = TriageStatus::parse(body.status.trim()).ok_or_else(|| {
+        ApiError::BadRequest(format!(
+            "status must be one of new, acknowledged, false-positive, escalated; got '{}'",
+            body.status
+        ))
+        .with("field", "status")
+    })?;
+    let note = optional_text(body.note, "note", MAX_NOTE_LEN)?;
+
+    let client_ip = get_client_ip(&req);
+    let previous = store.status(&packet_id);
+    let triage = store
+        .set_status(&packet_id, status, note, Some(client_ip.clone()))
+        .await
+        .map_err(|e| {
+            log::error!("❌ Storing triage status of {} failed: {}", packet_id, e);
+            ApiError::Database(e)
+        })?;
+    log::info!(
+        "🚦 Event {} marked {} by {} (was {})",
+        packet_id,
+        status.as_str(),
+        client_ip,
+        previous.as_str()
+    );
+    let mut ticket_error = None;
+    if status == TriageStatus::Escalated {
+        if let Some(ticketing) = req.app_data::<web::Data<Ticketing>>() {
+            let case = Case {
+                packet_id: packet_id.clone(),
+                note: triage.note.clone(),
+                escalated_by: triage.updated_by.clone(),
+            };
+            if let Err(e) = tickets::open_for(ticketing, &store, case).await {
+                log::error!("❌ Opening a ticket for event {} failed: {}", packet_id, e);
+                ticket_error = Some(e);
+            }
+        }
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "triage": triage,
+        "previous_status": previous,
+        "ticket": store.ticket(&packet_id),
+        "ticket_error": ticket_error
+    })))
+}
+
+pub async fn get_event_status(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    store: web::Data<TriageStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let triage = store.get(&packet_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "packet_id": packet_id,
+        "status": triage.as_ref().map(|t| t.status).unwrap_or_default(),
+        "triage": triage,
+        "ticket": store.ticket(&packet_id)
+    })))
+}
+
+/// Assigns the event to an analyst: `{"assignee": "alice"}`.
+pub async fn post_event_assignee(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<TriageStore>,
+    body: web::Json<AssignRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let assignee = optional_text(body.into_inner().assignee, "assignee", MAX_NAME_LEN)?;
+
+    let client_ip = get_client_ip(&req);
+    let previous = store.assignee(&packet_id);
+    let triage = store
+        .assign(&packet_id, assignee.clone(), Some(client_ip.clone()))
+        .await
+        .map_err(|e| {
+            log::error!("❌ Storing assignee of {} failed: {}", packet_id, e);
+            ApiError::Database(e)
+        })?;
+    log::info!(
+        "🚦 Event {} assigned to {} by {} (was {})",
+        packet_id,
+        assignee.as_deref().unwrap_or("nobody"),
+        client_ip,
+        previous.as_deref().unwrap_or("nobody")
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "triage": triage,
+        "previous_assignee": previous
+    })))
+}
+
+/// Adds a comment: `{"body": "...", "parent_id": 3, "author": "alice"}`.
+pub async fn post_event_comment(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    auth: web::Data<AdminAuth>,
+    store: web::Data<TriageStore>,
+    body: web::Json<CommentRequest>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let body = body.into_inner();
+    let Some(text) = optional_text(Some(body.body), "body", MAX_COMMENT_LEN)? else {
+        return Err(ApiError::BadRequest("body must not be empty".into()).with("field", "body"));
+    };
+    let client_ip = get_client_ip(&req);
+    let author = optional_text(body.author, "author", MAX_NAME_LEN)?.unwrap_or(client_ip.clone());
+    if let Some(parent) = body.parent_id {
+        if !store.comments(&packet_id).iter().any(|c| c.id == parent) {
+            return Err(ApiError::BadRequest(format!(
+                "parent_id {} is not a comment on {}",
+                parent, packet_id
+            ))
+            .with("field", "parent_id"));
+        }
+    }
+
+    let comment = store
+        .add_comment(&packet_id, body.parent_id, Some(author), text)
+        .await
+        .map_err(|e| {
+            log::error!("❌ Storing comment on {} failed: {}", packet_id, e);
+            ApiError::Database(e)
+        })?;
+    log::info!(
+        "💬 Comment {} on event {} from {}",
+        comment.id,
+        packet_id,
+        client_ip
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "comment": comment
+    })))
+}
+
+/// Comments on one event as threads: top-level comments oldest first, each
+/// with its `replies`.
+pub async fn get_event_comments(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    store: web::Data<TriageStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let comments = store.comments(&packet_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "packet_id": packet_id,
+        "assignee": store.assignee(&packet_id),
+        "count": comments.len(),
+        "comments": thread(&comments)
+    })))
+}
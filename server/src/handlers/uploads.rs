@@ -0,0 +1,173 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::external_url;
+use crate::handlers::common::{admit_client, get_client_ip};
+use crate::metrics;
+use crate::quotas::Usage;
+use crate::scanning::ScanQueue;
+use crate::uploads::{PartialUpload, UploadInfo, UploadStore, MAX_FILENAME_LEN};
+use actix_multipart::{Field, Multipart};
+use actix_web::{web, HttpResponse};
+use futures_util::TryStreamExt;
+
+/// Text fields (`packet_id`, `client_id`) are short; anything longer is not
+/// what the extension sends.
+const MAX_TEXT_FIELD: usize = 256;
+
+fn multipart_error(e: actix_multipart::MultipartError) -> ApiError {
+    ApiError::BadRequest(format!("Invalid multipart body: {}", e))
+}
+
+fn storage_error(client_ip: &str, e: std::io::Error) -> ApiError {
+    log::error!("❌ Storing upload from IP {} failed: {}", client_ip, e);
+    ApiError::Storage(e.to_string())
+}
+
+async fn read_text(name: &str, mut field: Field) -> Result<String, ApiError> {
+    let mut value = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+        value.extend_from_slice(&chunk);
+        if value.len() > MAX_TEXT_FIELD {
+            return Err(ApiError::BadRequest(format!(
+                "{} is longer than {} bytes",
+                name, MAX_TEXT_FIELD
+            ))
+            .with("field", name));
+        }
+    }
+    String::from_utf8(value)
+        .map(|s| s.trim().to_string())
+        .map_err(|_| ApiError::BadRequest(format!("{} is not UTF-8", name)).with("field", name))
+}
+
+/// Streams the `file` field to disk, stopping as soon as it is over the limit.
+async fn receive_file(
+    store: &UploadStore,
+    mut field: Field,
+    client_ip: &str,
+) -> Result<PartialUpload, ApiError> {
+    let mut partial = store
+        .begin()
+        .await
+        .map_err(|e| storage_error(client_ip, e))?;
+    while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+        if partial.size() + chunk.len() as u64 > store.max_bytes() {
+            metrics::UPLOADS_REJECTED.inc();
+            log::warn!(
+                "📎 Upload from IP {} refused: over --max-upload-mb ({} bytes)",
+                client_ip,
+                store.max_bytes()
+            );
+            return Err(ApiError::PayloadTooLarge(format!(
+                "File is larger than the {} byte limit",
+                store.max_bytes()
+            ))
+            .with("max_upload_bytes", store.max_bytes()));
+        }
+        partial
+            .write(&chunk)
+            .await
+            .map_err(|e| storage_error(client_ip, e))?;
+    }
+    Ok(partial)
+}
+
+/// `multipart/form-data` with a `packet_id` field (the security event the
+/// file belongs to), an optional `client_id` and one `file` field.
+pub async fn post_security_upload(
+    req: actix_web::HttpRequest,
+    store: web::Data<UploadStore>,
+    scan_queue: web::Data<ScanQueue>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let client_ip = get_client_ip(&req);
+    if !store.is_active() {
+        return Err(ApiError::NotFound(
+            "File uploads are disabled; start the server with --upload-dir".into(),
+        ));
+    }
+
+    let mut packet_id = None;
+    let mut client_id = None;
+    let mut file = None;
+    while let Some(field) = payload.try_next().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("packet_id") => packet_id = Some(read_text("packet_id", field).await?),
+            Some("client_id") => client_id = Some(read_text("client_id", field).await?),
+            Some("file") => {
+                if file.is_some() {
+                    return Err(ApiError::BadRequest("Only one file per upload".into())
+                        .with("field", "file"));
+                }
+                let filename = field
+                    .content_disposition()
+                    .and_then(|cd| cd.get_filename())
+                    .map(|f| f.chars().take(MAX_FILENAME_LEN).collect::<String>());
+                let content_type = field.content_type().map(|m| m.to_string());
+                let partial = receive_file(&store, field, &client_ip).await?;
+                file = Some((partial, filename, content_type));
+            }
+            // Unknown fields are drained and ignored.
+            _ => {}
+        }
+    }
+
+    let Some(packet_id) = packet_id.filter(|p| !p.is_empty()) else {
+        return Err(
+            ApiError::BadRequest("packet_id field is required".into()).with("field", "packet_id")
+        );
+    };
+    let Some((partial, filename, content_type)) = file else {
+        return Err(ApiError::BadRequest("file field is required".into()).with("field", "file"));
+    };
+    let client_id = client_id.filter(|c| !c.is_empty());
+    let usage = Usage {
+        bytes: partial.size(),
+        ..Usage::default()
+    };
+    admit_client(&req, client_id.as_deref(), usage).await?;
+
+    let info = UploadInfo {
+        packet_id,
+        client_id,
+        filename,
+        content_type,
+        uploaded_by: client_ip.clone(),
+    };
+    let upload = store
+        .commit(partial, info)
+        .await
+        .map_err(|e| storage_error(&client_ip, e))?;
+    metrics::UPLOADS_STORED.inc();
+    scan_queue.submit(&upload);
+    log::info!(
+        "📎 Upload {} from IP {} attached to {}: {} bytes, sha256 {}",
+        upload.id,
+        client_ip,
+        upload.packet_id,
+        upload.size,
+        upload.sha256
+    );
+    let url = external_url::absolute(&req, &format!("/api/security/uploads/{}", upload.packet_id));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "upload": upload,
+        "url": url,
+        "scan_queued": scan_queue.is_active()
+    })))
+}
+
+pub async fn get_packet_uploads(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    path: web::Path<String>,
+    store: web::Data<UploadStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let packet_id = path.into_inner();
+    let uploads = store.for_packet(&packet_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "packet_id": packet_id,
+        "uploads": uploads
+    })))
+}
@@ -0,0 +1,229 @@
+use crate::auth::AdminAuth;
+use crate::error::ApiError;
+use crate::handlers::common::get_client_ip;
+use crate::roles::Role;
+use crate::server_events;
+use crate::types::LocalUser;
+use crate::users::{self, UserStore, UserSummary};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct NewUser {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: Role,
+    pub password: Option<String>,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct UserChange {
+    pub display_name: Option<String>,
+    pub role: Option<Role>,
+    pub password: Option<String>,
+    pub active: Option<bool>,
+}
+
+fn storage_error(e: String) -> ApiError {
+    log::error!("❌ Saving local users failed: {}", e);
+    ApiError::Storage(e)
+}
+
+fn not_found(username: &str) -> ApiError {
+    ApiError::NotFound("no such user".to_string()).with("username", username)
+}
+
+fn check_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < users::MIN_PASSWORD_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "passwords need at least {} characters",
+            users::MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn check_display_name(name: &Option<String>) -> Result<(), ApiError> {
+    if name.as_ref().is_some_and(|n| n.chars().count() > 100) {
+        return Err(ApiError::BadRequest(
+            "display_name is limited to 100 characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn record(req: &actix_web::HttpRequest, action: &str, username: &str) {
+    let client_ip = get_client_ip(req);
+    log::warn!("👤 User {}: {} by {}", username, action, client_ip);
+    server_events::record(
+        "config_changed",
+        serde_json::json!({
+            "setting": "user",
+            "username": username,
+            "action": action,
+            "changed_by": client_ip
+        }),
+    );
+}
+
+pub async fn get_users(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let list: Vec<UserSummary> = users.list().iter().map(UserSummary::from).collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "users": list,
+        "persisted": users.is_persisted()
+    })))
+}
+
+pub async fn get_user(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let user = users.get(&path).ok_or_else(|| not_found(&path))?;
+    Ok(HttpResponse::Ok().json(UserSummary::from(&user)))
+}
+
+pub async fn post_user(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    body: web::Json<NewUser>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let body = body.into_inner();
+    // `admin-token` names whoever uses the admin token.
+    if !users::valid_username(&body.username) || body.username == "admin-token" {
+        return Err(ApiError::BadRequest(
+            "usernames are up to 64 lowercase letters, digits, '.', '_' and '-'".to_string(),
+        )
+        .with("username", body.username));
+    }
+    check_display_name(&body.display_name)?;
+    if let Some(password) = &body.password {
+        check_password(password)?;
+    }
+    if users.get(&body.username).is_some() {
+        return Err(
+            ApiError::Conflict("user already exists".to_string()).with("username", body.username)
+        );
+    }
+    let now = Utc::now();
+    let user = LocalUser {
+        username: body.username,
+        display_name: body.display_name,
+        role: body.role,
+        active: body.active,
+        password_hash: body.password.as_deref().map(users::hash_password),
+        token_hash: None,
+        created_at: now,
+        updated_at: now,
+    };
+    users.put(user.clone()).await.map_err(storage_error)?;
+    record(&req, "created", &user.username);
+    Ok(HttpResponse::Created().json(UserSummary::from(&user)))
+}
+
+/// Changes the fields given. A new role, a new password or deactivation
+/// also ends the user's dashboard sessions.
+pub async fn patch_user(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    path: web::Path<String>,
+    body: web::Json<UserChange>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut user = users.get(&path).ok_or_else(|| not_found(&path))?;
+    let body = body.into_inner();
+    check_display_name(&body.display_name)?;
+    if let Some(password) = &body.password {
+        check_password(password)?;
+    }
+    let signs_out = body.role.is_some_and(|r| r != user.role)
+        || body.password.is_some()
+        || body.active == Some(false);
+    if body.display_name.is_some() {
+        user.display_name = body.display_name;
+    }
+    if let Some(role) = body.role {
+        user.role = role;
+    }
+    if let Some(active) = body.active {
+        user.active = active;
+    }
+    if let Some(password) = &body.password {
+        user.password_hash = Some(users::hash_password(password));
+    }
+    user.updated_at = Utc::now();
+    users.put(user.clone()).await.map_err(storage_error)?;
+    if signs_out {
+        auth.end_sessions(&user.username).await;
+    }
+    record(&req, "updated", &user.username);
+    Ok(HttpResponse::Ok().json(UserSummary::from(&user)))
+}
+
+pub async fn delete_user(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    if !users.delete(&path).await.map_err(storage_error)? {
+        return Err(not_found(&path));
+    }
+    auth.end_sessions(&path).await;
+    record(&req, "deleted", &path);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true, "username": *path })))
+}
+
+/// Issues the user a new API token, replacing any earlier one. The token is
+/// in this response only.
+pub async fn post_token(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut user = users.get(&path).ok_or_else(|| not_found(&path))?;
+    let (token, hash) = users::new_api_token();
+    user.token_hash = Some(hash);
+    user.updated_at = Utc::now();
+    users.put(user.clone()).await.map_err(storage_error)?;
+    record(&req, "token issued", &user.username);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "user": UserSummary::from(&user)
+    })))
+}
+
+pub async fn delete_token(
+    req: actix_web::HttpRequest,
+    auth: web::Data<AdminAuth>,
+    users: web::Data<UserStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth.check(&req).await?;
+    let mut user = users.get(&path).ok_or_else(|| not_found(&path))?;
+    user.token_hash = None;
+    user.updated_at = Utc::now();
+    users.put(user.clone()).await.map_err(storage_error)?;
+    record(&req, "token revoked", &user.username);
+    Ok(HttpResponse::Ok().json(UserSummary::from(&user)))
+}
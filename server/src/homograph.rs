@@ -0,0 +1,277 @@
+//! IDN homograph detection. Hosts of ingested logs that carry punycode
+//! (`xn--` labels) are decoded, and a label that mixes Latin with Cyrillic,
+//! Greek or Armenian letters, or whose letters read as a watched brand
+//! once look-alikes are mapped to ASCII (`аpple` with a Cyrillic `а`), is
+//! flagged as a `homograph_domain` security event, once per client and host
+//! per day.
+
+use crate::handlers::common::domain_from_url;
+use crate::types::LogEntry;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+/// `--homograph-brand` when none is given.
+pub const DEFAULT_BRANDS: &[&str] = &[
+    "google",
+    "gmail",
+    "youtube",
+    "apple",
+    "icloud",
+    "microsoft",
+    "outlook",
+    "office",
+    "amazon",
+    "paypal",
+    "facebook",
+    "instagram",
+    "whatsapp",
+    "netflix",
+    "linkedin",
+    "twitter",
+    "github",
+    "dropbox",
+    "adobe",
+    "yahoo",
+    "ebay",
+    "coinbase",
+    "binance",
+];
+/// Client/host pairs remembered to alert once a day; the oldest half is
+/// dropped past this.
+const MAX_REMEMBERED: usize = 50_000;
+
+/// The host with its `xn--` labels decoded, when it has any and all of
+/// them are valid IDNA.
+pub fn unicode_host(host: &str) -> Option<String> {
+    if !host.to_ascii_lowercase().contains("xn--") {
+        return None;
+    }
+    match idna::domain_to_unicode(host) {
+        (unicode, Ok(())) => Some(unicode),
+        (_, Err(_)) => None,
+    }
+}
+
+/// The host in punycode, for hosts sent with unicode letters.
+fn ascii_host(host: &str) -> Option<String> {
+    reqwest::Url::parse(&format!("http://{}/", host))
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Other,
+}
+
+impl Script {
+    fn of(c: char) -> Option<Script> {
+        match c {
+            '0'..='9' | '-' | '_' => None,
+            'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => {
+                Some(Script::Latin)
+            }
+            '\u{0250}'..='\u{02AF}' => Some(Script::Latin),
+            '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Some(Script::Greek),
+            '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+            '\u{0530}'..='\u{058F}' => Some(Script::Armenian),
+            _ => Some(Script::Other),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Script::Latin => "latin",
+            Script::Greek => "greek",
+            Script::Cyrillic => "cyrillic",
+            Script::Armenian => "armenian",
+            Script::Other => "other",
+        }
+    }
+}
+
+/// Letters that pass for an ASCII one, grouped by the letter they imitate.
+const CONFUSABLES: &[(&str, char)] = &[
+    ("аɑαàáâãäåāăą", 'a'),
+    ("ƅЬь", 'b'),
+    ("сϲçćĉċč", 'c'),
+    ("ԁďđ", 'd'),
+    ("еёєèéêëēĕėęě", 'e'),
+    ("ɡցĝğġģ", 'g'),
+    ("һհĥħ", 'h'),
+    ("іїıɩιìíîïĩīĭįΐ", 'i'),
+    ("јϳĵ", 'j'),
+    ("кκķ", 'k'),
+    ("ӏǀĺļľŀł", 'l'),
+    ("ոñńņň", 'n'),
+    ("оοօòóôõöøōŏő", 'o'),
+    ("рρ", 'p'),
+    ("ԛզ", 'q'),
+    ("ŕŗř", 'r'),
+    ("ѕśŝşš", 's'),
+    ("τţťŧ", 't'),
+    ("υսùúûüũūŭůűų", 'u'),
+    ("ν", 'v'),
+    ("ԝŵ", 'w'),
+    ("хχ", 'x'),
+    ("уγýÿŷ", 'y'),
+    ("źżž", 'z'),
+];
+
+/// `label` with look-alike letters replaced by the ASCII letter they imitate.
+fn skeleton(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            let c = c.to_lowercase().next().unwrap_or(c);
+            CONFUSABLES
+                .iter()
+                .find(|(group, _)| group.contains(c))
+                .map(|(_, ascii)| *ascii)
+                .unwrap_or(c)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct HomographFinding {
+    pub client_id: Option<String>,
+    pub url: String,
+    /// Punycode, as requested.
+    pub host: String,
+    pub unicode_host: String,
+    /// The watched brand the host reads as.
+    pub imitates: Option<String>,
+    pub scripts: Vec<&'static str>,
+}
+
+impl HomographFinding {
+    pub fn to_event_data(&self) -> serde_json::Value {
+        serde_json::json!({
+            "url": self.url,
+            "host": self.host,
+            "unicode_host": self.unicode_host,
+            "imitates": self.imitates,
+            "scripts": self.scripts,
+            "reason": if self.imitates.is_some() { "imitates_brand" } else { "mixed_script" },
+            "detection": {
+                "riskScore": if self.imitates.is_some() { 90 } else { 60 }
+            }
+        })
+    }
+}
+
+/// Checks one host; `None` unless it is internationalized and looks like
+/// a homograph.
+pub fn check(host: &str, brands: &[String]) -> Option<(Vec<&'static str>, Option<String>)> {
+    let unicode = if host.is_ascii() {
+        unicode_host(host)?
+    } else {
+        host.to_lowercase()
+    };
+    let mut flagged = None;
+    for label in unicode.split('.').filter(|l| !l.is_ascii()) {
+        let scripts: BTreeSet<Script> = label.chars().filter_map(Script::of).collect();
+        let mixed = scripts.contains(&Script::Latin)
+            && [Script::Greek, Script::Cyrillic, Script::Armenian]
+                .iter()
+                .any(|s| scripts.contains(s));
+        let skeleton = skeleton(label);
+        let imitates = brands.iter().find(|b| **b == skeleton).cloned();
+        if mixed || imitates.is_some() {
+            let names = scripts.iter().map(|s| s.name()).collect();
+            if imitates.is_some() {
+                return Some((names, imitates));
+            }
+            flagged.get_or_insert((names, None));
+        }
+    }
+    flagged
+}
+
+/// Flags homograph hosts in ingested logs (`--homograph-brand`).
+pub struct HomographDetector {
+    /// Lowercase labels, e.g. `paypal`.
+    brands: Vec<String>,
+    alerted: Mutex<HashMap<(String, String), DateTime<Utc>>>,
+}
+
+impl HomographDetector {
+    /// Brands are names or domains; `paypal.com` watches `paypal`.
+    pub fn new(brands: &[String]) -> Self {
+        HomographDetector {
+            brands: brands
+                .iter()
+                .filter_map(|b| {
+                    b.trim()
+                        .to_lowercase()
+                        .split('.')
+                        .next()
+                        .map(str::to_string)
+                })
+                .filter(|b| !b.is_empty())
+                .collect(),
+            alerted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn brands(&self) -> &[String] {
+        &self.brands
+    }
+
+    pub fn observe(&self, entry: &LogEntry) -> Vec<HomographFinding> {
+        let mut findings = Vec::new();
+        let now = Utc::now();
+        for log in &entry.logs {
+            let Some(host) = domain_from_url(&log.url) else {
+                continue;
+            };
+            if host.is_ascii() && !host.contains("xn--") {
+                continue;
+            }
+            let Some((scripts, imitates)) = check(&host, &self.brands) else {
+                continue;
+            };
+            let (host, unicode_host) = if host.is_ascii() {
+                let unicode = unicode_host(&host).unwrap_or_else(|| host.clone());
+                (host.to_ascii_lowercase(), unicode)
+            } else {
+                (
+                    ascii_host(&host).unwrap_or_else(|| host.clone()),
+                    host.to_lowercase(),
+                )
+            };
+            let key = (entry.client_id.clone().unwrap_or_default(), host.clone());
+            {
+                let mut alerted = self.alerted.lock().unwrap();
+                if alerted
+                    .get(&key)
+                    .is_some_and(|at| now - *at < Duration::days(1))
+                {
+                    continue;
+                }
+                if alerted.len() >= MAX_REMEMBERED {
+                    let mut times: Vec<DateTime<Utc>> = alerted.values().copied().collect();
+                    times.sort();
+                    let cutoff = times[times.len() / 2];
+                    alerted.retain(|_, at| *at > cutoff);
+                }
+                alerted.insert(key, now);
+            }
+            findings.push(HomographFinding {
+                client_id: entry.client_id.clone(),
+                url: log.url.clone(),
+                host,
+                unicode_host,
+                imitates,
+                scripts,
+            });
+        }
+        findings
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Paths no legitimate extension or dashboard user ever requests.
+pub const DEFAULT_PATHS: &[&str] = &[
+    "/wp-login.php",
+    "/wp-admin",
+    "/xmlrpc.php",
+    "/.env",
+    "/.git/config",
+    "/.aws/credentials",
+    "/phpmyadmin",
+    "/admin.php",
+    "/actuator/env",
+    "/server-status",
+];
+
+/// Distinct decoys an address must touch before it is classed as a scanner.
+const SCANNER_THRESHOLD: usize = 3;
+
+#[derive(Default)]
+struct Visitor {
+    hits: u64,
+    paths: HashSet<String>,
+    first_seen: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub struct Honeypot {
+    paths: Vec<String>,
+    visitors: Mutex<HashMap<String, Visitor>>,
+}
+
+pub struct HoneypotHit {
+    pub classification: &'static str,
+    pub network: &'static str,
+    pub hits: u64,
+    pub distinct_paths: usize,
+    pub first_seen: chrono::DateTime<chrono::Utc>,
+}
+
+impl Honeypot {
+    pub fn new(use_defaults: bool, extra: &[String]) -> Self {
+        let mut paths: Vec<String> = if use_defaults {
+            DEFAULT_PATHS.iter().map(|p| p.to_string()).collect()
+        } else {
+            Vec::new()
+        };
+        for p in extra {
+            let p = if p.starts_with('/') {
+                p.clone()
+            } else {
+                format!("/{}", p)
+            };
+            if !paths.contains(&p) {
+                paths.push(p);
+            }
+        }
+        Honeypot {
+            paths,
+            visitors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn record(&self, client_ip: &str, path: &str) -> HoneypotHit {
+        let now = chrono::Utc::now();
+        let mut visitors = self.visitors.lock().unwrap();
+        if visitors.len() > 50_000 {
+            visitors.clear();
+        }
+        let v = visitors.entry(client_ip.to_string()).or_default();
+        v.hits += 1;
+        v.paths.insert(path.to_string());
+        let first_seen = *v.first_seen.get_or_insert(now);
+        HoneypotHit {
+            classification: if v.paths.len() >= SCANNER_THRESHOLD {
+                "scanner"
+            } else if v.hits > 1 {
+                "repeat_probe"
+            } else {
+                "probe"
+            },
+            network: classify_network(client_ip),
+            hits: v.hits,
+            distinct_paths: v.paths.len(),
+            first_seen,
+        }
+    }
+}
+
+fn classify_network(client_ip: &str) -> &'static str {
+    match client_ip.parse::<IpAddr>().map(|ip| ip.to_canonical()) {
+        Ok(IpAddr::V4(ip)) if ip.is_loopback() => "loopback",
+        Ok(IpAddr::V4(ip)) if ip.is_private() || ip.is_link_local() => "private",
+        Ok(IpAddr::V6(ip)) if ip.is_loopback() => "loopback",
+        Ok(IpAddr::V6(ip)) if (ip.segments()[0] & 0xfe00) == 0xfc00 => "private",
+        Ok(_) => "public",
+        Err(_) => "unknown",
+    }
+}
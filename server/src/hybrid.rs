@@ -0,0 +1,40 @@
+use crate::production::ProductionState;
+use crate::simple::WarmRecord;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Records waiting for the writer; past this the hot tier drops them rather
+/// than hold an ever longer queue while the database is down or slow.
+pub const WARM_QUEUE_CAPACITY: usize = 100_000;
+
+/// Spawns the writer that drains the hot tier's queue into Postgres. Writes are
+/// retried a few times so a short database hiccup doesn't drop records.
+pub fn spawn_warm_writer(warm: Arc<ProductionState>) -> mpsc::Sender<WarmRecord> {
+    let (tx, mut rx) = mpsc::channel::<WarmRecord>(WARM_QUEUE_CAPACITY);
+    actix_web::rt::spawn(async move {
+        while let Some(record) = rx.recv().await {
+            crate::metrics::WARM_QUEUE_DEPTH.add(-1);
+            let mut attempt = 0;
+            loop {
+                let result = match record {
+                    WarmRecord::Log(ref entry) => warm.add_log(entry.clone()).await,
+                    WarmRecord::Event(ref event) => warm.add_extension_event(event.clone()).await,
+                };
+                match result {
+                    Ok(_) => break,
+                    Err(e) if attempt < 3 => {
+                        attempt += 1;
+                        log::warn!("⚠️ Warm tier write failed (attempt {}): {}", attempt, e);
+                        tokio::time::sleep(std::time::Duration::from_millis(250 * attempt)).await;
+                    }
+                    Err(e) => {
+                        log::error!("❌ Warm tier write dropped after retries: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        log::info!("🧊 Warm tier writer stopped");
+    });
+    tx
+}
@@ -0,0 +1,315 @@
+use crate::types::{check_client_id, LogEntry, NetworkLog};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Only the first errors are echoed back; the rest are just counted.
+const MAX_REPORTED_ERRORS: usize = 50;
+/// A progress line is logged every this many records.
+pub const PROGRESS_EVERY: usize = 1000;
+/// Imports may be whole exports, far larger than a normal extension batch.
+pub const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One `LogEntry` object per line.
+    Ndjson,
+    /// A JSON array of `LogEntry`, as returned by `GET /api/logs`.
+    Json,
+    /// One row per request; see `CsvRow` for the columns.
+    Csv,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Ndjson => "ndjson",
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+
+    /// An explicit `?format=` wins, then the Content-Type, then the first
+    /// non-blank character of the body.
+    pub fn detect(param: Option<&str>, content_type: &str, body: &str) -> Result<Format, String> {
+        if let Some(p) = param {
+            return match p.to_ascii_lowercase().as_str() {
+                "ndjson" | "jsonl" => Ok(Format::Ndjson),
+                "json" => Ok(Format::Json),
+                "csv" => Ok(Format::Csv),
+                other => Err(format!(
+                    "Unknown format '{}': expected ndjson, json or csv",
+                    other
+                )),
+            };
+        }
+        let content_type = content_type.to_ascii_lowercase();
+        if content_type.contains("csv") {
+            return Ok(Format::Csv);
+        }
+        if content_type.contains("ndjson") || content_type.contains("jsonl") {
+            return Ok(Format::Ndjson);
+        }
+        Ok(match body.trim_start().chars().next() {
+            Some('[') => Format::Json,
+            Some('{') => Format::Ndjson,
+            _ => Format::Csv,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    pub line: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub format: &'static str,
+    /// Batches (log entries) that parsed and validated.
+    pub records: usize,
+    /// Individual requests across those batches.
+    pub requests: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub errors: Vec<ImportError>,
+}
+
+impl ImportReport {
+    pub fn new(format: Format) -> Self {
+        ImportReport {
+            format: format.as_str(),
+            records: 0,
+            requests: 0,
+            imported: 0,
+            duplicates: 0,
+            invalid: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    fn reject(&mut self, line: usize, error: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(ImportError { line, error });
+        }
+    }
+}
+
+/// Parses and validates the whole upload. Bad records are reported in
+/// `report` and skipped; the good ones come back with their line number.
+pub fn parse(format: Format, body: &str, report: &mut ImportReport) -> Vec<(usize, LogEntry)> {
+    let parsed = match format {
+        Format::Ndjson => parse_ndjson(body, report),
+        Format::Json => parse_json_array(body, report),
+        Format::Csv => parse_csv(body, report),
+    };
+    let mut out = Vec::with_capacity(parsed.len());
+    for (line, entry) in parsed {
+        match validate(&entry) {
+            Ok(()) => {
+                report.records += 1;
+                report.requests += entry.logs.len();
+                out.push((line, entry));
+            }
+            Err(e) => report.reject(line, e),
+        }
+    }
+    out
+}
+
+fn parse_ndjson(body: &str, report: &mut ImportReport) -> Vec<(usize, LogEntry)> {
+    let mut out = Vec::new();
+    for (idx, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<LogEntry>(line) {
+            Ok(entry) => out.push((idx + 1, entry)),
+            Err(e) => report.reject(idx + 1, format!("Invalid JSON: {}", e)),
+        }
+    }
+    out
+}
+
+/// Line numbers here are array positions (1-based), since the whole array
+/// may sit on one line.
+fn parse_json_array(body: &str, report: &mut ImportReport) -> Vec<(usize, LogEntry)> {
+    let items: Vec<serde_json::Value> = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => {
+            report.reject(1, format!("Invalid JSON array: {}", e));
+            return Vec::new();
+        }
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for (idx, item) in items.into_iter().enumerate() {
+        match serde_json::from_value::<LogEntry>(item) {
+            Ok(entry) => out.push((idx + 1, entry)),
+            Err(e) => report.reject(idx + 1, format!("Invalid log entry: {}", e)),
+        }
+    }
+    out
+}
+
+/// Flat, one-request-per-row layout. Consecutive rows sharing client,
+/// profile, session, timestamp and user agent are folded back into one batch.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    profile_id: Option<String>,
+    session_id: String,
+    timestamp: String,
+    #[serde(default)]
+    user_agent: String,
+    #[serde(default)]
+    request_id: String,
+    url: String,
+    method: String,
+    #[serde(rename = "type", default)]
+    request_type: Option<String>,
+    #[serde(default)]
+    blocked: Option<bool>,
+    #[serde(default)]
+    block_reason: Option<String>,
+    #[serde(default)]
+    reputation_score: Option<u8>,
+    #[serde(default)]
+    request_size: Option<u64>,
+    #[serde(default)]
+    response_size: Option<u64>,
+}
+
+fn parse_csv(body: &str, report: &mut ImportReport) -> Vec<(usize, LogEntry)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let headers = match reader.headers() {
+        Ok(h) => h.clone(),
+        Err(e) => {
+            report.reject(1, format!("Invalid CSV header: {}", e));
+            return Vec::new();
+        }
+    };
+    let mut out: Vec<(usize, LogEntry)> = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let line = e.position().map(|p| p.line() as usize).unwrap_or(0);
+                report.reject(line, format!("Invalid CSV row: {}", e));
+                continue;
+            }
+        }
+        let line = record.position().map(|p| p.line() as usize).unwrap_or(0);
+        let row: CsvRow = match record.deserialize(Some(&headers)) {
+            Ok(r) => r,
+            Err(e) => {
+                report.reject(line, format!("Invalid CSV row: {}", e));
+                continue;
+            }
+        };
+        let client_id = row.client_id.filter(|c| !c.is_empty());
+        let profile_id = row.profile_id.filter(|p| !p.is_empty());
+        let log = NetworkLog {
+            request_id: row.request_id,
+            url: row.url,
+            method: row.method,
+            request_type: row
+                .request_type
+                .filter(|t| !t.is_empty())
+                .unwrap_or_else(crate::types::default_request_type),
+            blocked: row.blocked.unwrap_or(false),
+            block_reason: row.block_reason.filter(|r| !r.is_empty()),
+            reputation_score: row.reputation_score,
+            request_size: row.request_size,
+            response_size: row.response_size,
+            category: None,
+        };
+        if let Some((_, last)) = out.last_mut() {
+            if last.client_id == client_id
+                && last.profile_id == profile_id
+                && last.session_id == row.session_id
+                && last.timestamp == row.timestamp
+                && last.user_agent == row.user_agent
+            {
+                last.logs.push(log);
+                continue;
+            }
+        }
+        out.push((
+            line,
+            LogEntry {
+                client_id,
+                profile_id,
+                session_id: row.session_id,
+                timestamp: row.timestamp,
+                user_agent: row.user_agent,
+                logs: vec![log],
+                username: None,
+                clock_skew_ms: None,
+            },
+        ));
+    }
+    out
+}
+
+fn validate(entry: &LogEntry) -> Result<(), String> {
+    if entry.session_id.is_empty() {
+        return Err("session_id is empty".to_string());
+    }
+    if chrono::DateTime::parse_from_rfc3339(&entry.timestamp).is_err() {
+        return Err(format!("timestamp '{}' is not RFC 3339", entry.timestamp));
+    }
+    if let Some(id) = &entry.client_id {
+        check_client_id(id)?;
+    }
+    if entry.logs.is_empty() {
+        return Err("logs array is empty".to_string());
+    }
+    for (idx, log) in entry.logs.iter().enumerate() {
+        if log.url.is_empty() {
+            return Err(format!("logs[{}]: url is empty", idx));
+        }
+        if log.method.is_empty() {
+            return Err(format!("logs[{}]: method is empty", idx));
+        }
+    }
+    Ok(())
+}
+
+/// Identity of one request. Re-importing the same export, or an export that
+/// overlaps what is already stored, matches on this.
+pub fn fingerprint(entry: &LogEntry, log: &NetworkLog) -> String {
+    format!("{}{}", batch_key(entry), request_key(log))
+}
+
+fn batch_key(entry: &LogEntry) -> String {
+    format!(
+        "{}\u{1f}{}\u{1f}{}\u{1f}",
+        entry.client_id.as_deref().unwrap_or(""),
+        entry.session_id,
+        entry.timestamp
+    )
+}
+
+fn request_key(log: &NetworkLog) -> String {
+    format!("{}\u{1f}{}\u{1f}{}", log.request_id, log.method, log.url)
+}
+
+/// Drops requests already in `seen` (stored data or earlier in this upload)
+/// and remembers the rest. Returns how many were dropped.
+pub fn dedupe(entry: &mut LogEntry, seen: &mut HashSet<String>) -> usize {
+    let before = entry.logs.len();
+    let batch = batch_key(entry);
+    entry
+        .logs
+        .retain(|log| seen.insert(format!("{}{}", batch, request_key(log))));
+    before - entry.logs.len()
+}
@@ -0,0 +1,38 @@
+//! Replica identity for load-balanced deployments: the id goes into log
+//! lines, packet IDs and server-raised events. Only the database and Redis
+//! are shared between replicas; what each still keeps to itself is listed
+//! in the README under "Multiple Replicas".
+
+use std::sync::OnceLock;
+
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+static STARTED: OnceLock<(std::time::Instant, chrono::DateTime<chrono::Utc>)> = OnceLock::new();
+
+/// Sets the identifier of this replica. Only the first call has any effect.
+pub fn init(id: String) {
+    let _ = INSTANCE_ID.set(id);
+}
+
+/// The replica identifier, if this server runs as one of several instances.
+pub fn id() -> Option<&'static str> {
+    INSTANCE_ID.get().map(|s| s.as_str())
+}
+
+/// HOSTNAME is what container orchestrators set per replica; fall back to the
+/// pid so two processes on one host still differ.
+pub fn default_id() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| format!("pid{}", std::process::id()))
+}
+
+/// Records the process start time. Only the first call has any effect.
+pub fn mark_started() {
+    let _ = STARTED.set((std::time::Instant::now(), chrono::Utc::now()));
+}
+
+/// When the server started and how long ago, once `mark_started` has run.
+pub fn uptime() -> Option<(chrono::DateTime<chrono::Utc>, std::time::Duration)> {
+    STARTED.get().map(|(at, wall)| (*wall, at.elapsed()))
+}
@@ -0,0 +1,174 @@
+use crate::error::ApiError;
+use crate::handlers::common::client_addr;
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, ResponseError};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Paths load balancers and probes must always reach.
+pub(crate) const EXEMPT_PATHS: &[&str] = &["/health"];
+
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Bare addresses are accepted as single-host ranges.
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        Ok(IpFilter {
+            allow: parse_cidrs(allow)?,
+            deny: parse_cidrs(deny)?,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} allowed range(s), {} denied range(s)",
+            self.allow.len(),
+            self.deny.len()
+        )
+    }
+
+    /// Deny wins over allow; with an allowlist, anything outside it is refused.
+    /// Peers whose address can't be determined are refused by either list,
+    /// since they can't be checked against it.
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip.map(|ip| ip.to_canonical()) else {
+            return !self.is_active();
+        };
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` is believed. Anyone else could
+/// put any address there, so it is ignored on requests from them.
+#[derive(Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+    /// Peers on a Unix socket have no address; `unix` trusts them.
+    unix: bool,
+}
+
+impl TrustedProxies {
+    /// Takes ranges like `IpFilter`, plus `unix` for the Unix socket peer.
+    pub fn new(items: &[String]) -> Result<Self, String> {
+        let (unix, cidrs): (Vec<String>, Vec<String>) =
+            items.iter().cloned().partition(|s| s.trim() == "unix");
+        Ok(TrustedProxies {
+            nets: parse_cidrs(&cidrs)?,
+            unix: !unix.is_empty(),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.unix || !self.nets.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} range(s){}",
+            self.nets.len(),
+            if self.unix {
+                " and the Unix socket"
+            } else {
+                ""
+            }
+        )
+    }
+
+    fn trusts(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => self.nets.iter().any(|net| net.contains(&ip)),
+            None => self.unix,
+        }
+    }
+
+    /// The peer, unless it is a trusted proxy: then the nearest
+    /// `X-Forwarded-For` hop, walking from the right, that isn't one
+    /// (`X-Real-IP` when there is no `X-Forwarded-For`). `None` when that
+    /// can't be told: an untrusted Unix socket peer, or a hop that isn't an
+    /// address.
+    pub fn client_addr(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr().map(|addr| addr.ip().to_canonical());
+        if !self.trusts(peer) {
+            return peer;
+        }
+        let hops: Vec<&str> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .collect();
+        if hops.is_empty() {
+            let real_ip = req
+                .headers()
+                .get("x-real-ip")
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.trim().parse::<IpAddr>().ok());
+            return match real_ip {
+                Some(ip) => ip.map(|ip| ip.to_canonical()),
+                None => peer,
+            };
+        }
+        let mut nearest = peer;
+        for hop in hops.iter().rev() {
+            let ip = hop.trim().parse::<IpAddr>().ok()?.to_canonical();
+            if !self.trusts(Some(ip)) {
+                return Some(ip);
+            }
+            nearest = Some(ip);
+        }
+        // Every hop is a proxy of ours, e.g. its own health check.
+        nearest
+    }
+}
+
+fn parse_cidrs(items: &[String]) -> Result<Vec<IpNet>, String> {
+    items
+        .iter()
+        .map(|s| {
+            let s = s.trim();
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("invalid CIDR '{}'", s))
+        })
+        .collect()
+}
+
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let filter = req.app_data::<web::Data<IpFilter>>().cloned();
+    if let Some(filter) = filter {
+        if filter.is_active() && !EXEMPT_PATHS.contains(&req.path()) {
+            let addr = client_addr(req.request());
+            if !filter.permits(addr) {
+                let client_ip = addr.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+                metrics::IP_FILTER_REJECTED.inc();
+                log::warn!(
+                    "⛔ Rejected request from IP {} to {} (CIDR filter)",
+                    client_ip,
+                    req.path()
+                );
+                let err = ApiError::Forbidden("Forbidden: source address not allowed".into())
+                    .with("client_ip", client_ip);
+                return Ok(req
+                    .into_response(err.error_response())
+                    .map_into_right_body());
+            }
+        }
+    }
+    next.call(req).await.map(|res| res.map_into_left_body())
+}
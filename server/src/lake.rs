@@ -0,0 +1,204 @@
+//! Raw copies of ingested data in object storage (`--lake-s3`), for data-lake
+//! pipelines that shouldn't depend on this server's database schema.
+//!
+//! Every accepted payload becomes one NDJSON object in an S3-compatible
+//! bucket (AWS, MinIO, ...), written before the payload is stored and
+//! whether or not storing it works:
+//!
+//! - a `/api/logs` batch, one line per request with the batch's client,
+//!   session and user agent on each, under
+//!   `<prefix>/logs/date=<yyyy-mm-dd>/client=<client_id>/`;
+//! - an event from `/api/extensions` or `/api/security`, one line with its
+//!   packet_id and category, under `<prefix>/events/...` the same way.
+//!
+//! Dates are the day the server received the payload (UTC); payloads
+//! without a client_id go under `client=unknown`. Every line carries
+//! `received_at` and the sender's `client_ip`. Objects are signed and
+//! written the way [`crate::worm`] writes its S3 copies (credentials from
+//! `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY`), through their own queue:
+//! a bucket that is down is retried, keeping the order, and payloads are
+//! dropped, counted, once [`MAX_QUEUED`] are waiting.
+
+use crate::metrics;
+use crate::types::{ExtensionEvent, LogEntry};
+use crate::worm::S3;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Objects waiting before new payloads are dropped.
+pub const MAX_QUEUED: i64 = 100_000;
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(300);
+
+/// The bucket path objects go under, and the queue to the writer.
+static LAKE: OnceLock<(String, UnboundedSender<Object>)> = OnceLock::new();
+
+/// One NDJSON object on its way to the bucket.
+struct Object {
+    path: String,
+    body: Vec<u8>,
+    received_at: DateTime<Utc>,
+}
+
+/// A client_id as a path segment: anything but letters, digits, `-`, `_`
+/// and `.` becomes `_`.
+fn partition(client_id: Option<&str>) -> String {
+    match client_id.filter(|c| !c.is_empty()) {
+        Some(id) => id
+            .chars()
+            .take(100)
+            .map(|c| match c {
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// `<prefix>/<kind>/date=<yyyy-mm-dd>/client=<id>/<hhmmss>-<random>.ndjson`.
+fn object_path(prefix: &str, kind: &str, client_id: Option<&str>, now: DateTime<Utc>) -> String {
+    format!(
+        "{}/{}/date={}/client={}/{}-{}.ndjson",
+        prefix,
+        kind,
+        now.format("%Y-%m-%d"),
+        partition(client_id),
+        now.format("%H%M%S%3f"),
+        &crate::auth::random_token()[..12]
+    )
+}
+
+fn ndjson(lines: impl Iterator<Item = Value>) -> Vec<u8> {
+    let mut body = Vec::new();
+    for line in lines {
+        // A `Value` always serializes.
+        serde_json::to_writer(&mut body, &line).expect("JSON value");
+        body.push(b'\n');
+    }
+    body
+}
+
+/// The lines for a log batch: each request with the batch's fields.
+pub fn log_lines(entry: &LogEntry, client_ip: &str, now: DateTime<Utc>) -> Vec<u8> {
+    ndjson(entry.logs.iter().map(|log| {
+        let mut line = json!({
+            "received_at": now,
+            "client_ip": client_ip,
+            "client_id": entry.client_id,
+            "profile_id": entry.profile_id,
+            "session_id": entry.session_id,
+            "timestamp": entry.timestamp,
+            "user_agent": entry.user_agent,
+            "clock_skew_ms": entry.clock_skew_ms,
+        });
+        if let (Some(line), Ok(Value::Object(log))) =
+            (line.as_object_mut(), serde_json::to_value(log))
+        {
+            line.extend(log);
+        }
+        line
+    }))
+}
+
+/// The line for an event.
+pub fn event_line(event: &ExtensionEvent, client_ip: &str, now: DateTime<Utc>) -> Vec<u8> {
+    let mut line = serde_json::to_value(event).unwrap_or_default();
+    if let Some(fields) = line.as_object_mut() {
+        fields.insert("received_at".into(), json!(now));
+        fields.insert("client_ip".into(), json!(client_ip));
+    }
+    ndjson(std::iter::once(line))
+}
+
+async fn deliver(s3: S3, mut rx: UnboundedReceiver<Object>) {
+    while let Some(object) = rx.recv().await {
+        let mut wait = FIRST_RETRY;
+        loop {
+            let result = s3
+                .put_object(
+                    &object.path,
+                    object.body.clone(),
+                    "application/x-ndjson",
+                    object.received_at,
+                )
+                .await;
+            match result {
+                Ok(()) => {
+                    metrics::LAKE_OBJECTS_WRITTEN.inc();
+                    break;
+                }
+                Err(e) => {
+                    metrics::LAKE_FAILURES.inc();
+                    log::error!(
+                        "❌ Writing {} to the lake failed, retrying in {}s: {}",
+                        object.path,
+                        wait.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(wait).await;
+                    wait = (wait * 2).min(MAX_RETRY);
+                }
+            }
+        }
+        metrics::LAKE_QUEUE_DEPTH.add(-1);
+    }
+}
+
+/// Copies every accepted payload to `s3` from now on.
+pub fn install(s3: S3) {
+    let prefix = s3.prefix().to_string();
+    let (tx, rx) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(deliver(s3, rx));
+    let _ = LAKE.set((prefix, tx));
+}
+
+fn queue(kind: &str, client_id: Option<&str>, body: Vec<u8>, now: DateTime<Utc>) {
+    let Some((prefix, tx)) = LAKE.get() else {
+        return;
+    };
+    if metrics::LAKE_QUEUE_DEPTH.get() >= MAX_QUEUED {
+        metrics::LAKE_DROPPED.inc();
+        log::error!("❌ The lake is too far behind; {} payload not copied", kind);
+        return;
+    }
+    let object = Object {
+        path: object_path(prefix, kind, client_id, now),
+        body,
+        received_at: now,
+    };
+    if tx.send(object).is_ok() {
+        metrics::LAKE_QUEUE_DEPTH.add(1);
+    }
+}
+
+/// Queues a copy of an accepted log batch; empty batches aren't copied.
+pub fn append_logs(entry: &LogEntry, client_ip: &str) {
+    if LAKE.get().is_none() || entry.logs.is_empty() {
+        return;
+    }
+    let now = Utc::now();
+    queue(
+        "logs",
+        entry.client_id.as_deref(),
+        log_lines(entry, client_ip, now),
+        now,
+    );
+}
+
+/// Queues a copy of an accepted event.
+pub fn append_event(event: &ExtensionEvent, client_ip: &str) {
+    if LAKE.get().is_none() {
+        return;
+    }
+    let now = Utc::now();
+    queue(
+        "events",
+        event.client_id.as_deref(),
+        event_line(event, client_ip, now),
+        now,
+    );
+}
@@ -0,0 +1,424 @@
+//! People behind the clients, from LDAP or Active Directory: with
+//! `--ldap-config FILE` the `username` a log batch reports is looked up in
+//! the directory and the client is named after the user (display name,
+//! department and OU), so reports can be read per person rather than per
+//! client_id.
+//!
+//! ```json
+//! { "url": "ldaps://dc1.acme.local", "base_dn": "DC=acme,DC=local",
+//!   "bind_dn": "CN=canigoin,OU=Service,DC=acme,DC=local", "bind_password": "...",
+//!   "username_attribute": "sAMAccountName", "object_class": "user",
+//!   "display_name_attribute": "displayName", "cache_ttl": "1h" }
+//! ```
+//!
+//! Lookups happen in the background, at most one per username at a time, so
+//! ingest never waits on the directory. Answers, including "no such user",
+//! are cached for `cache_ttl`; a directory that can't be reached is asked
+//! again after [`RETRY_AFTER`]. A `DOMAIN\` prefix on the username is
+//! dropped. What is found lands in the [client names](crate::client_names)
+//! as source `ldap`: `name` is the display name, `owner` the username,
+//! `department` the user's `department` attribute (else the innermost OU)
+//! and `ou` the OUs outermost first (`Staff/Finance`). Names set through the
+//! API or synced from a CSV take precedence.
+//!
+//! Each lookup is a simple bind and one subtree search over `ldaps://`, or
+//! over `ldap://` upgraded with StartTLS (`"starttls": true`). A bind
+//! password is never sent over plain `ldap://` unless the config says
+//! `"allow_plaintext_bind": true`; an anonymous search may use it.
+
+use crate::client_names::{ClientName, ClientNames, Source};
+use crate::metrics;
+use actix_web::web;
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, SearchOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a lookup may take, connection included.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How soon a username whose lookup failed is tried again.
+pub const RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Usernames kept in the cache; the oldest answer goes first.
+pub const MAX_CACHED: usize = 10_000;
+const MAX_USERNAME_LEN: usize = 256;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    /// `ldaps://host[:port]` or `ldap://host[:port]`; the port defaults to
+    /// 636 and 389.
+    pub url: String,
+    /// Where the search starts, e.g. `DC=acme,DC=local`.
+    pub base_dn: String,
+    /// Account to bind as; the search is anonymous without one.
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    #[serde(default = "default_username_attribute")]
+    pub username_attribute: String,
+    #[serde(default = "default_object_class")]
+    pub object_class: String,
+    #[serde(default = "default_display_name_attribute")]
+    pub display_name_attribute: String,
+    /// How long an answer is trusted, e.g. `1h`.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: String,
+    /// Upgrades an `ldap://` connection with StartTLS before binding.
+    #[serde(default)]
+    pub starttls: bool,
+    /// Lets `bind_password` go over `ldap://` without StartTLS, in the clear.
+    #[serde(default)]
+    pub allow_plaintext_bind: bool,
+}
+
+fn default_username_attribute() -> String {
+    "sAMAccountName".to_string()
+}
+
+fn default_object_class() -> String {
+    "user".to_string()
+}
+
+fn default_display_name_attribute() -> String {
+    "displayName".to_string()
+}
+
+fn default_cache_ttl() -> String {
+    "1h".to_string()
+}
+
+/// A user found in the directory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Person {
+    pub username: String,
+    pub dn: String,
+    pub display_name: Option<String>,
+    pub department: Option<String>,
+    /// The OUs of the DN, outermost first, joined with `/`.
+    pub ou: Option<String>,
+}
+
+/// The user name the directory is searched for: trimmed, without a
+/// `DOMAIN\` prefix.
+pub fn normalize_username(username: &str) -> Option<String> {
+    let username = username.trim();
+    let username = username.rsplit_once('\\').map_or(username, |(_, u)| u);
+    (!username.is_empty() && username.len() <= MAX_USERNAME_LEN).then(|| username.to_string())
+}
+
+/// The OUs of `dn`, outermost first, with their escapes undone.
+pub fn ou_path(dn: &str) -> Vec<String> {
+    let mut rdns = Vec::new();
+    let (mut current, mut escaped) = (String::new(), false);
+    for c in dn.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            ',' => rdns.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    rdns.push(current);
+    let mut ous: Vec<String> = rdns
+        .iter()
+        .filter_map(|rdn| rdn.split_once('='))
+        .filter(|(kind, _)| kind.trim().eq_ignore_ascii_case("ou"))
+        .map(|(_, value)| value.trim().to_string())
+        .collect();
+    ous.reverse();
+    ous
+}
+
+impl LdapConfig {
+    fn check_url(&self) -> Result<(), String> {
+        let (tls, rest) = match self.url.split_once("://") {
+            Some(("ldaps", rest)) => (true, rest),
+            Some(("ldap", rest)) => (self.starttls, rest),
+            _ => {
+                return Err(format!(
+                    "url '{}' should start with ldaps:// or ldap://",
+                    self.url
+                ))
+            }
+        };
+        let host = rest.trim_end_matches('/');
+        if host.is_empty() || host.contains('/') {
+            return Err(format!("url '{}' should be ldaps://host[:port]", self.url));
+        }
+        if !tls && self.bind_dn.is_some() && !self.allow_plaintext_bind {
+            return Err(format!(
+                "url '{}' would send the bind password in the clear; use ldaps://, \
+                 \"starttls\": true or \"allow_plaintext_bind\": true",
+                self.url
+            ));
+        }
+        Ok(())
+    }
+
+    /// Asks the directory about `username`.
+    async fn search(&self, username: &str) -> Result<Option<Person>, String> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(LOOKUP_TIMEOUT)
+            .set_starttls(self.starttls && self.url.starts_with("ldap://"));
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(|e| format!("connecting to {}: {}", self.url, e))?;
+        ldap3::drive!(conn);
+        if let Some(bind_dn) = &self.bind_dn {
+            let password = self.bind_password.as_deref().unwrap_or_default();
+            ldap.simple_bind(bind_dn, password)
+                .await
+                .and_then(|r| r.success())
+                .map_err(|e| format!("bind as {} failed: {}", bind_dn, e))?;
+        }
+        let filter = format!(
+            "(&(objectClass={})({}={}))",
+            ldap_escape(self.object_class.as_str()),
+            self.username_attribute,
+            ldap_escape(username)
+        );
+        let found = ldap
+            .with_search_options(
+                SearchOptions::new()
+                    // Two entries tell an ambiguous username.
+                    .sizelimit(2)
+                    .timelimit(LOOKUP_TIMEOUT.as_secs() as i32),
+            )
+            .search(
+                &self.base_dn,
+                Scope::Subtree,
+                &filter,
+                [self.display_name_attribute.as_str(), "department"],
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let _ = ldap.unbind().await;
+        let (entries, result) = (found.0, found.1);
+        // sizeLimitExceeded: more than one entry, reported below. Referrals
+        // to other servers aren't followed.
+        if result.rc != 0 && result.rc != 4 {
+            return Err(format!("search failed: {}", result));
+        }
+        if entries.len() > 1 {
+            return Err(format!(
+                "more than one entry has {}={}",
+                self.username_attribute, username
+            ));
+        }
+        Ok(entries.into_iter().next().map(|entry| {
+            let entry = SearchEntry::construct(entry);
+            let mut values: HashMap<String, String> = entry
+                .attrs
+                .into_iter()
+                .filter_map(|(name, values)| {
+                    Some((name.to_lowercase(), values.into_iter().next()?))
+                })
+                .collect();
+            let ous = ou_path(&entry.dn);
+            let display_name = values.remove(&self.display_name_attribute.to_lowercase());
+            Person {
+                username: username.to_string(),
+                display_name: display_name.filter(|v| !v.trim().is_empty()),
+                department: values
+                    .remove("department")
+                    .filter(|v| !v.trim().is_empty())
+                    .or_else(|| ous.last().cloned()),
+                ou: (!ous.is_empty()).then(|| ous.join("/")),
+                dn: entry.dn,
+            }
+        }))
+    }
+}
+
+struct Cached {
+    person: Option<Person>,
+    /// How long the answer is trusted from `at`.
+    fresh_for: Duration,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Cache {
+    answers: HashMap<String, Cached>,
+    pending: HashSet<String>,
+}
+
+pub struct LdapDirectory {
+    config: Option<LdapConfig>,
+    ttl: Duration,
+    cache: Mutex<Cache>,
+}
+
+impl Default for LdapDirectory {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl LdapDirectory {
+    pub fn disabled() -> Self {
+        LdapDirectory {
+            config: None,
+            ttl: Duration::ZERO,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    pub fn new(config: LdapConfig) -> Result<Self, String> {
+        config.check_url()?;
+        if config.base_dn.trim().is_empty() {
+            return Err("base_dn is empty".into());
+        }
+        let ttl = crate::handlers::common::parse_duration(&config.cache_ttl)
+            .and_then(|d| d.to_std().ok())
+            .ok_or_else(|| {
+                format!(
+                    "cache_ttl '{}' should look like 30m or 1h",
+                    config.cache_ttl
+                )
+            })?;
+        Ok(LdapDirectory {
+            config: Some(config),
+            ttl,
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    /// Reads `--ldap-config`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: LdapConfig = serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not a valid LDAP config: {}", path.display(), e))?;
+        Self::new(config)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.config.as_ref().map(|c| c.url.as_str())
+    }
+
+    /// Looks `username` up in the directory, bypassing the cache.
+    pub async fn lookup(&self, username: &str) -> Result<Option<Person>, String> {
+        let config = self.config.as_ref().ok_or("no directory configured")?;
+        let username = normalize_username(username).ok_or("the username is empty or too long")?;
+        tokio::time::timeout(LOOKUP_TIMEOUT, config.search(&username))
+            .await
+            .map_err(|_| format!("{} timed out", config.url))?
+    }
+
+    /// The cached answer for `username`, if it is still fresh; `Some(None)`
+    /// when the directory has no such user.
+    fn cached(&self, username: &str) -> Option<Option<Person>> {
+        let cache = self.cache.lock().unwrap();
+        let cached = cache.answers.get(username)?;
+        (cached.at.elapsed() < cached.fresh_for).then(|| cached.person.clone())
+    }
+
+    fn remember(&self, username: &str, person: Option<Person>, fresh_for: Duration) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.pending.remove(username);
+        if !cache.answers.contains_key(username) && cache.answers.len() >= MAX_CACHED {
+            let oldest = cache
+                .answers
+                .iter()
+                .min_by_key(|(_, c)| c.at)
+                .map(|(u, _)| u.clone());
+            if let Some(u) = oldest {
+                cache.answers.remove(&u);
+            }
+        }
+        cache.answers.insert(
+            username.to_string(),
+            Cached {
+                person,
+                fresh_for,
+                at: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Names `client_id` after the person, unless it has a name from the API or
+/// a CSV.
+fn name_client(names: &ClientNames, client_id: &str, person: &Person) {
+    let name = ClientName {
+        client_id: client_id.to_string(),
+        name: person.display_name.clone(),
+        owner: Some(person.username.clone()),
+        hostname: None,
+        department: person.department.clone(),
+        ou: person.ou.clone(),
+        source: Source::Ldap,
+        updated_at: chrono::Utc::now(),
+    };
+    if let Err(e) = names.enrich(name) {
+        log::error!(
+            "❌ Naming client {} after {} failed: {}",
+            client_id,
+            person.username,
+            e
+        );
+    }
+}
+
+/// Called for every batch that reports a username: names the client from
+/// the cache, or looks the user up in the background when the cache has no
+/// fresh answer.
+pub fn observe(
+    directory: &web::Data<LdapDirectory>,
+    names: &web::Data<ClientNames>,
+    client_id: &str,
+    username: &str,
+) {
+    if !directory.is_enabled() || client_id.is_empty() {
+        return;
+    }
+    let Some(username) = normalize_username(username) else {
+        return;
+    };
+    match directory.cached(&username) {
+        Some(Some(person)) => return name_client(names, client_id, &person),
+        Some(None) => return,
+        None => {}
+    }
+    if !directory
+        .cache
+        .lock()
+        .unwrap()
+        .pending
+        .insert(username.clone())
+    {
+        return;
+    }
+    let (directory, names, client_id) = (directory.clone(), names.clone(), client_id.to_string());
+    actix_web::rt::spawn(async move {
+        metrics::LDAP_LOOKUPS.inc();
+        match directory.lookup(&username).await {
+            Ok(person) => {
+                match &person {
+                    Some(p) => {
+                        log::info!("📇 {} is {:?} in {:?}", username, p.display_name, p.ou);
+                        name_client(&names, &client_id, p);
+                    }
+                    None => log::info!("📇 {} is not in the directory", username),
+                }
+                directory.remember(&username, person, directory.ttl);
+            }
+            Err(e) => {
+                metrics::LDAP_FAILURES.inc();
+                log::warn!("⚠️ Looking up {} in the directory failed: {}", username, e);
+                directory.remember(&username, None, RETRY_AFTER.min(directory.ttl));
+            }
+        }
+    });
+}
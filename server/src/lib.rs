@@ -0,0 +1,112 @@
+//! CanIGoIn network logging server as a library: the storage backends, the
+//! handlers and [`build_app`], which assembles them into an actix `App`. The
+//! `network-logger-server` binary is a thin command-line wrapper around it.
+
+// `Args::config_snapshot` is one `json!` literal with every setting.
+#![recursion_limit = "512"]
+
+pub mod alert_sinks;
+pub mod alerts;
+pub mod annotations;
+pub mod app;
+pub mod archive;
+pub mod asn;
+pub mod auth;
+pub mod backup;
+pub mod bans;
+pub mod batch;
+pub mod beaconing;
+pub mod blocklist_history;
+pub mod blocklist_rollout;
+pub mod blocklist_signing;
+pub mod blocklist_sync;
+pub mod bloom;
+pub mod bundle;
+pub mod capture;
+pub mod categories;
+pub mod chain;
+pub mod chaos;
+pub mod cli;
+pub mod client_names;
+pub mod clock_skew;
+pub mod counters;
+pub mod ct;
+pub mod detection;
+pub mod distinct;
+pub mod enrollment;
+pub mod error;
+pub mod event_data;
+pub mod exfil;
+pub mod experiments;
+pub mod extension_errors;
+pub mod external_url;
+pub mod features;
+pub mod focus;
+pub mod groups;
+pub mod handlers;
+pub mod homograph;
+pub mod honeypot;
+pub mod import;
+pub mod instance;
+pub mod ip_filter;
+pub mod lake;
+pub mod ldap;
+pub mod listen;
+pub mod logging;
+pub mod maintenance;
+pub mod metering;
+pub mod metrics;
+pub mod oidc;
+pub mod packet_id;
+pub mod policy;
+pub mod quotas;
+pub mod rdap;
+pub mod request_types;
+pub mod rescan;
+pub mod roles;
+pub mod scanning;
+pub mod scheduler;
+pub mod screen_time;
+pub mod security_headers;
+pub mod seed;
+pub mod server_events;
+pub mod sessions;
+pub mod simple;
+pub mod stats;
+pub mod stream_parse;
+pub mod summary;
+pub mod tail;
+pub mod telegram;
+pub mod telemetry;
+pub mod tickets;
+pub mod training;
+pub mod triage;
+pub mod tui;
+pub mod uploads;
+pub mod url_normalize;
+pub mod users;
+pub mod versioning;
+pub mod worm;
+
+#[cfg(feature = "production")]
+pub mod hybrid;
+#[cfg(feature = "production")]
+pub mod migrate;
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "production")]
+pub mod pool_monitor;
+#[cfg(feature = "production")]
+pub mod production;
+#[cfg(feature = "production")]
+pub mod redis_conn;
+#[cfg(feature = "production")]
+pub mod sharding;
+#[cfg(feature = "production")]
+pub mod storage;
+
+pub mod reputation;
+pub mod response_cache;
+pub mod types;
+
+pub use app::{build_app, AppState, Backend};
@@ -0,0 +1,220 @@
+use crate::app::{build_app, AppState};
+use actix_web::HttpServer;
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+
+/// First file descriptor passed by systemd socket activation (SD_LISTEN_FDS_START).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// HTTP server settings. Simple mode keeps actix's defaults; production and
+/// hybrid modes, which usually sit behind a load balancer and take large
+/// batches from many clients, start from [`Tuning::production`].
+#[derive(Debug, Clone)]
+pub struct Tuning {
+    /// Worker threads; one per CPU core when unset.
+    pub workers: Option<usize>,
+    /// How long an idle connection is kept open; zero closes it after each
+    /// response.
+    pub keep_alive: Duration,
+    /// Time a client has to send the request head before it gets a 408.
+    pub client_request_timeout: Duration,
+    /// Time a connection has to shut down cleanly before it is dropped.
+    pub client_disconnect_timeout: Duration,
+    /// Concurrent connections per worker.
+    pub max_connections: usize,
+    /// Also accept HTTP/2 without TLS (h2c, prior knowledge) on TCP
+    /// listeners.
+    pub http2: bool,
+}
+
+impl Tuning {
+    pub fn simple() -> Self {
+        Tuning {
+            workers: None,
+            keep_alive: Duration::from_secs(5),
+            client_request_timeout: Duration::from_secs(5),
+            client_disconnect_timeout: Duration::from_secs(1),
+            max_connections: 25_000,
+            http2: false,
+        }
+    }
+
+    /// The settings for `/api/admin/state`.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "workers": self.workers,
+            "keep_alive_secs": self.keep_alive.as_secs_f64(),
+            "client_request_timeout_secs": self.client_request_timeout.as_secs_f64(),
+            "client_disconnect_timeout_secs": self.client_disconnect_timeout.as_secs_f64(),
+            "max_connections": self.max_connections,
+            "http2": self.http2
+        })
+    }
+
+    /// Keep-alive outlasts the usual 60s load balancer idle timeout, so the
+    /// balancer never reuses a connection the server just closed; slow
+    /// clients get longer to send the head of a large batch; h2c lets a
+    /// proxy multiplex requests over a few connections.
+    pub fn production() -> Self {
+        Tuning {
+            workers: None,
+            keep_alive: Duration::from_secs(75),
+            client_request_timeout: Duration::from_secs(10),
+            client_disconnect_timeout: Duration::from_secs(5),
+            max_connections: 25_000,
+            http2: true,
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(addr) => write!(f, "http://{}", addr),
+                Err(_) => write!(f, "tcp:(unknown)"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(l) => match l
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+            {
+                Some(path) => write!(f, "unix:{}", path),
+                None => write!(f, "unix:(unnamed)"),
+            },
+        }
+    }
+}
+
+/// Opens the sockets to serve on. Sockets handed over by systemd take priority;
+/// otherwise `bind` is either `unix:/path/to.sock` or a `host:port` address.
+/// Call it before any other thread starts: it clears the systemd variables
+/// from the environment and changes the umask while binding a Unix socket.
+pub fn open(bind: &str, socket_mode: u32) -> io::Result<Vec<Listener>> {
+    #[cfg(unix)]
+    {
+        let activated = systemd_listeners()?;
+        if !activated.is_empty() {
+            log::info!(
+                "🔌 Using {} socket(s) from systemd activation",
+                activated.len()
+            );
+            return Ok(activated);
+        }
+    }
+
+    if let Some(path) = bind.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return bind_unix(path, socket_mode).map(|l| vec![Listener::Unix(l)]);
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unix sockets are not supported on this platform ({})", path),
+        ));
+    }
+
+    #[cfg(not(unix))]
+    let _ = socket_mode;
+    Ok(vec![Listener::Tcp(TcpListener::bind(bind)?)])
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, socket_mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket file left over from an unclean shutdown would make bind fail.
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path),
+            ));
+        }
+    }
+    // The socket is created with `socket_mode` already applied, so there is
+    // no moment when it is reachable with wider permissions.
+    // SAFETY: umask only swaps the process's file mode mask; no other thread
+    // runs yet (see `open`).
+    let previous = unsafe { libc::umask(!(socket_mode as libc::mode_t) & 0o777) };
+    let bound = UnixListener::bind(path);
+    // SAFETY: as above.
+    unsafe { libc::umask(previous) };
+    bound
+}
+
+/// Implements the sd_listen_fds(3) protocol: LISTEN_PID must match this process
+/// and LISTEN_FDS gives the number of descriptors starting at fd 3.
+#[cfg(unix)]
+fn systemd_listeners() -> io::Result<Vec<Listener>> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    let pid_matches = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !pid_matches || count <= 0 {
+        return Ok(Vec::new());
+    }
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let mut out = Vec::with_capacity(count as usize);
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: systemd passes ownership of these descriptors to us and
+        // nothing else in the process uses them.
+        let unix = unsafe { UnixListener::from_raw_fd(fd) };
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            out.push(Listener::Unix(unix));
+        } else {
+            let raw = unix.into_raw_fd();
+            // SAFETY: same descriptor, re-wrapped as the TCP socket it actually is.
+            let tcp = unsafe { TcpListener::from_raw_fd(raw) };
+            tcp.set_nonblocking(true)?;
+            out.push(Listener::Tcp(tcp));
+        }
+    }
+    Ok(out)
+}
+
+/// Runs the server for `app` on every listener until it is stopped.
+pub async fn serve(app: AppState, listeners: Vec<Listener>, tuning: &Tuning) -> io::Result<()> {
+    let mut server = HttpServer::new(move || build_app(app.clone()))
+        .keep_alive(match tuning.keep_alive.is_zero() {
+            true => actix_web::http::KeepAlive::Disabled,
+            false => actix_web::http::KeepAlive::Timeout(tuning.keep_alive),
+        })
+        .client_request_timeout(tuning.client_request_timeout)
+        .client_disconnect_timeout(tuning.client_disconnect_timeout)
+        .max_connections(tuning.max_connections);
+    if let Some(workers) = tuning.workers {
+        server = server.workers(workers);
+    }
+    for listener in listeners {
+        server = match listener {
+            Listener::Tcp(l) if tuning.http2 => server.listen_auto_h2c(l)?,
+            Listener::Tcp(l) => server.listen(l)?,
+            #[cfg(unix)]
+            Listener::Unix(l) => server.listen_uds(l)?,
+        };
+    }
+    server.run().await
+}
@@ -0,0 +1,113 @@
+//! Process logger whose filter can be replaced at runtime
+//! (`POST /api/admin/loglevel`) instead of restarting with a new RUST_LOG.
+
+use crate::instance;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::{OnceLock, RwLock};
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+struct ReloadableLogger {
+    /// The filter string and the env_logger built from it.
+    current: RwLock<(String, env_logger::Logger)>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current.read().unwrap().1.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current.read().unwrap().1.log(record)
+    }
+
+    fn flush(&self) {
+        self.current.read().unwrap().1.flush()
+    }
+}
+
+fn build(filters: &str) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filters);
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        builder.parse_write_style(&style);
+    }
+    if instance::id().is_some() {
+        builder.format(|buf, record| {
+            use std::io::Write;
+            writeln!(
+                buf,
+                "[{} {} {} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                instance::id().unwrap_or("-"),
+                record.target(),
+                record.args()
+            )
+        });
+    }
+    builder.build()
+}
+
+/// Installs the logger with RUST_LOG (default `info`). Call after
+/// `instance::init` so log lines carry the instance id.
+pub fn init() {
+    let filters = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    let logger = build(&filters);
+    let max = logger.filter();
+    let installed = LOGGER.get_or_init(|| ReloadableLogger {
+        current: RwLock::new((filters, logger)),
+    });
+    if log::set_logger(installed).is_ok() {
+        log::set_max_level(max);
+    }
+}
+
+/// The filter currently in effect, in RUST_LOG syntax.
+pub fn filters() -> String {
+    LOGGER
+        .get()
+        .map(|l| l.current.read().unwrap().0.clone())
+        .unwrap_or_default()
+}
+
+/// Replaces the filter (RUST_LOG syntax, e.g. `debug` or `info,sqlx=warn`).
+/// Returns the previous one.
+pub fn set_filters(filters: &str) -> Result<String, String> {
+    validate(filters)?;
+    let logger = LOGGER.get().ok_or("logger not initialised")?;
+    let new = build(filters);
+    let max = new.filter();
+    let previous = std::mem::replace(
+        &mut *logger.current.write().unwrap(),
+        (filters.to_string(), new),
+    )
+    .0;
+    log::set_max_level(max);
+    Ok(previous)
+}
+
+/// env_logger ignores directives it can't parse; reject them up front so an
+/// admin typo doesn't silently turn logging off.
+fn validate(filters: &str) -> Result<(), String> {
+    let spec = filters.split('/').next().unwrap_or_default();
+    let directives: Vec<&str> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .collect();
+    if directives.is_empty() {
+        return Err("filter is empty; expected e.g. \"info\" or \"info,sqlx=warn\"".to_string());
+    }
+    for d in directives {
+        if let Some((module, level)) = d.split_once('=') {
+            if module.trim().is_empty() || level.trim().parse::<LevelFilter>().is_err() {
+                return Err(format!(
+                    "invalid directive '{}': expected module=level with level one of off, error, warn, info, debug, trace",
+                    d
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,182 +1,926 @@
-mod handlers;
-mod packet_id;
-mod simple;
+use actix_web::web;
+use clap::{Parser, ValueEnum};
+use network_logger_server::cli::{Args, Command, ServerMode};
+use network_logger_server::handlers::admin::RuntimeConfig;
+use network_logger_server::handlers::common::parse_duration;
+use network_logger_server::{
+    alerts, asn, auth, backup, bans, batch, blocklist_history, blocklist_signing, blocklist_sync,
+    bundle, capture, categories, chain, chaos, client_names, clock_skew, ct, detection, enrollment,
+    event_data, exfil, external_url, features, focus, groups, homograph, honeypot, instance,
+    ip_filter, lake, ldap, listen, logging, maintenance, metering, oidc, quotas, rdap, reputation,
+    request_types, rescan, scanning, scheduler, screen_time, security_headers, server_events,
+    sessions, simple, telegram, telemetry, tickets, tui, uploads, url_normalize, users, worm,
+    AppState, Backend,
+};
 
 #[cfg(feature = "production")]
-mod production;
+use network_logger_server::{counters, distinct, hybrid, migrate, production, redis_conn};
 
-mod types;
+fn record_startup(runtime: &RuntimeConfig, listening_on: &str) {
+    server_events::record(
+        "server_started",
+        serde_json::json!({
+            "mode": runtime.mode,
+            "version": env!("CARGO_PKG_VERSION"),
+            "listening_on": listening_on
+        }),
+    );
+}
 
-use actix_cors::Cors;
-use actix_web::{web, App, HttpResponse, HttpServer};
-use clap::{Parser, ValueEnum};
+/// The `--redis-url` connection everything shares; connects on first use.
+#[cfg(feature = "production")]
+fn shared_redis(url: Option<&str>) -> Option<std::sync::Arc<redis_conn::SharedRedis>> {
+    let url = url?;
+    match redis_conn::SharedRedis::open(url) {
+        Ok(redis) => Some(std::sync::Arc::new(redis)),
+        Err(e) => {
+            log::error!("❌ --redis-url {} is not usable: {}", url, e);
+            None
+        }
+    }
+}
 
-#[derive(Debug, Clone, ValueEnum)]
-enum ServerMode {
-    Simple,
-    Production,
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
+    let instance_id = match (&args.instance_id, &args.mode) {
+        (Some(id), _) => Some(id.clone()),
+        (None, ServerMode::Simple) => None,
+        (None, _) => Some(instance::default_id()),
+    };
+    if let Some(id) = instance_id {
+        instance::init(id);
+    }
+    logging::init();
+    instance::mark_started();
+
+    // Taking over systemd's sockets clears LISTEN_FDS and binding a Unix
+    // socket sets the umask, neither of which is safe once other threads
+    // run, so the server's sockets are opened before the runtime starts.
+    let listeners = match args.command {
+        Some(_) => Vec::new(),
+        None => {
+            let bind_address = args
+                .bind
+                .clone()
+                .unwrap_or_else(|| format!("{}:{}", args.host, args.port));
+            let socket_mode = u32::from_str_radix(&args.socket_mode, 8)
+                .expect("--socket-mode must be an octal permission like 660");
+            listen::open(&bind_address, socket_mode)?
+        }
+    };
+    actix_web::rt::System::new().block_on(run(args, listeners))
 }
 
-#[derive(Parser, Debug)]
-#[command(name = "network-logger-server")]
-#[command(about = "Network logging server with simple and production modes", long_about = None)]
-struct Args {
-    #[arg(short, long, value_enum, default_value = "simple")]
-    mode: ServerMode,
+async fn run(args: Args, listeners: Vec<listen::Listener>) -> std::io::Result<()> {
+    if let Some(Command::MigrateStorage(migrate_args)) = &args.command {
+        #[cfg(feature = "production")]
+        return migrate::run(migrate_args).await;
+        #[cfg(not(feature = "production"))]
+        {
+            let _ = migrate_args;
+            eprintln!(
+                "❌ migrate-storage needs PostgreSQL support. Rebuild with --features production"
+            );
+            std::process::exit(1);
+        }
+    }
+    if let Some(Command::Replay(replay_args)) = &args.command {
+        return capture::replay(replay_args).await;
+    }
+    if let Some(Command::Tui(tui_args)) = &args.command {
+        return tui::run(tui_args).await;
+    }
 
-    #[arg(long, default_value = "127.0.0.1")]
-    host: String,
+    let tuning = args.http_tuning();
+    let listening_on = listeners
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
 
-    #[arg(short, long, default_value = "8080")]
-    port: u16,
+    let reputation = reputation::ReputationService::new(
+        args.reputation_list.as_deref(),
+        args.reputation_api_url.clone(),
+        args.reputation_cache_ttl,
+    )?;
+    if reputation.local_entries() > 0 || reputation.has_external() {
+        log::info!(
+            "☣️  Domain reputation: {} local entries, external lookup {}",
+            reputation.local_entries(),
+            if reputation.has_external() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+    }
 
-    #[arg(long)]
-    database_url: Option<String>,
+    let ip_filter = ip_filter::IpFilter::new(&args.allow_cidrs, &args.deny_cidrs)
+        .unwrap_or_else(|e| panic!("--allow-cidr/--deny-cidr: {}", e));
+    if ip_filter.is_active() {
+        log::info!("⛔ CIDR filter: {}", ip_filter.describe());
+    }
+    let ip_filter = web::Data::new(ip_filter);
+    let trusted_proxies = ip_filter::TrustedProxies::new(&args.trusted_proxies)
+        .unwrap_or_else(|e| panic!("--trusted-proxy: {}", e));
+    if trusted_proxies.is_active() {
+        log::info!(
+            "🔁 Trusting X-Forwarded-For from {}",
+            trusted_proxies.describe()
+        );
+    }
 
-    #[arg(long)]
-    redis_url: Option<String>,
-}
+    let admin_auth = auth::AdminAuth::new(args.admin_token.clone()).with_session_ttl(
+        parse_duration(&args.dashboard_session_ttl)
+            .filter(|ttl| *ttl > chrono::Duration::zero())
+            .expect("--dashboard-session-ttl must look like 30m or 12h"),
+    );
+    let admin_auth = match &args.oidc_config {
+        Some(path) => {
+            let config =
+                oidc::OidcConfig::load(path).unwrap_or_else(|e| panic!("--oidc-config: {}", e));
+            let provider = std::sync::Arc::new(oidc::Oidc::new(config));
+            // A provider that is down at startup is retried in the background.
+            if let Err(e) = provider.refresh().await {
+                log::warn!("⚠️ OIDC provider unreachable, retrying later: {}", e);
+            }
+            provider.spawn_refresh();
+            log::info!(
+                "🪪 Single sign-on through {}",
+                provider.config().discovery_url
+            );
+            admin_auth.with_oidc(provider)
+        }
+        None => admin_auth,
+    };
+    let bundle_signer = web::Data::new(bundle::BundleSigner::new(args.bundle_key.clone()));
+    let runtime_config = web::Data::new(RuntimeConfig {
+        mode: args
+            .mode
+            .to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default(),
+        settings: args.config_snapshot(),
+    });
 
-async fn health_check(req: actix_web::HttpRequest) -> impl actix_web::Responder {
-    let client_ip = handlers::common::get_client_ip(&req);
-    log::debug!("🏥 Health check requested from IP: {}", client_ip);
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().to_rfc3339(),
-        "client_ip": client_ip
-    }))
-}
+    let ban_policy = args.auto_ban.then(|| bans::BanPolicy {
+        auth_failures: args.ban_auth_failures,
+        malformed: args.ban_malformed,
+        window: parse_duration(&args.ban_window).expect("--ban-window must look like 10m or 1h"),
+        ban_duration: parse_duration(&args.ban_duration)
+            .expect("--ban-duration must look like 10m or 1h"),
+    });
+    let ban_list = bans::BanList::new(ban_policy);
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let groups = match &args.groups {
+        Some(path) => {
+            let groups = groups::Groups::load(path).unwrap_or_else(|e| panic!("--groups: {}", e));
+            log::info!(
+                "👥 {} device group(s) from {}",
+                groups.config().groups.len(),
+                path.display()
+            );
+            groups
+        }
+        None => groups::Groups::new(),
+    };
+    let groups = std::sync::Arc::new(groups);
 
-    let args = Args::parse();
-    let bind_address = format!("{}:{}", args.host, args.port);
+    let orgs = match &args.orgs {
+        Some(path) => {
+            let orgs = metering::OrgsConfig::load(path).unwrap_or_else(|e| panic!("--orgs: {}", e));
+            log::info!(
+                "🧾 Metering usage for {} org(s) from {}",
+                orgs.orgs.len(),
+                path.display()
+            );
+            orgs
+        }
+        None => metering::OrgsConfig::default(),
+    };
+    if !orgs.shards.is_empty() && matches!(args.mode, ServerMode::Simple) {
+        log::warn!("🗺️ The --orgs shards are ignored in simple mode: everything stays in memory");
+    }
+    let metering = metering::Metering::new(orgs);
+    let metering = match &args.metering_file {
+        Some(path) => metering
+            .with_file(path)
+            .unwrap_or_else(|e| panic!("--metering-file: {}", e)),
+        None => metering,
+    };
+
+    let maintenance = match &args.maintenance_file {
+        Some(path) => maintenance::Maintenance::load(path)
+            .unwrap_or_else(|e| panic!("--maintenance-file: {}", e)),
+        None => maintenance::Maintenance::new(),
+    };
+    if let Some(window) = maintenance.current() {
+        log::warn!(
+            "🚧 Maintenance pause carried over: ingest={} reads={} from {} until {}",
+            window.ingest,
+            window.reads,
+            window.starts_at.to_rfc3339(),
+            window
+                .ends_at
+                .map_or("resumed".to_string(), |t| t.to_rfc3339())
+        );
+    }
+
+    let backups = match &args.backup_dir {
+        Some(dir) => {
+            let backups = backup::Backups::new(dir)?;
+            log::info!("💾 Backups kept in {}", dir.display());
+            backups
+        }
+        None => backup::Backups::disabled(),
+    };
+
+    let enrollment = match &args.enrollment_dir {
+        Some(dir) => {
+            let enrollment = enrollment::Enrollment::new(dir)?;
+            log::info!(
+                "🆕 Enrollment required: {} clients approved, {} pending, held in {}",
+                enrollment.approved().await.len(),
+                enrollment.pending().await.len(),
+                dir.display()
+            );
+            enrollment
+        }
+        None => enrollment::Enrollment::disabled(),
+    };
+
+    let event_chain = match &args.event_chain {
+        Some(path) => {
+            let event_chain =
+                chain::EventChain::load(path).unwrap_or_else(|e| panic!("--event-chain: {}", e));
+            log::info!(
+                "🔗 Security events chained, {} chains carried on from {}",
+                event_chain.heads().len(),
+                path.display()
+            );
+            event_chain
+        }
+        None => chain::EventChain::disabled(),
+    };
+    let checkpoint_interval = parse_duration(&args.chain_checkpoint_interval)
+        .and_then(|d| d.to_std().ok())
+        .filter(|d| !d.is_zero())
+        .expect("--chain-checkpoint-interval must look like 15m or 1h");
+    let event_chain = web::Data::new(event_chain);
+    chain::install(event_chain.clone());
+
+    let mut scheduler = scheduler::Scheduler::new()
+        .with_overrides(&args.job_schedules)
+        .unwrap_or_else(|e| panic!("--job-schedule: {}", e))
+        .with_retries(&args.job_retries)
+        .unwrap_or_else(|e| panic!("--job-retries: {}", e))
+        .with_alert_after(args.job_alert_after);
+    if let Some(path) = &args.job_history {
+        scheduler = scheduler
+            .with_history(path)
+            .unwrap_or_else(|e| panic!("--job-history: {}", e));
+        log::info!("⏱️ Job runs recorded in {}", path.display());
+    }
+    if event_chain.is_enabled() {
+        let chain = event_chain.clone();
+        scheduler.register(
+            "chain_checkpoint",
+            scheduler::Schedule::every(checkpoint_interval),
+            scheduler::JobOptions {
+                retries: 3,
+                backoff: std::time::Duration::from_secs(30),
+                critical: true,
+            },
+            move || chain::checkpoint_job(chain.clone()),
+        );
+    }
+    let blocklist_history = match &args.blocklist_history {
+        Some(path) => {
+            let history = blocklist_history::BlocklistHistory::load(path)
+                .unwrap_or_else(|e| panic!("--blocklist-history: {}", e));
+            log::info!("📋 Blocklist versions kept in {}", path.display());
+            history
+        }
+        None => blocklist_history::BlocklistHistory::new(),
+    };
+
+    let blocklist_signer = match &args.blocklist_signing_key {
+        Some(path) => {
+            let signer = blocklist_signing::BlocklistSigner::load(path)
+                .unwrap_or_else(|e| panic!("--blocklist-signing-key: {}", e));
+            let keys = signer.public_keys();
+            log::info!(
+                "🔏 Blocklists signed with key {} ({} key(s) in {})",
+                keys.last().map_or("-", |k| k.key_id.as_str()),
+                keys.len(),
+                path.display()
+            );
+            signer
+        }
+        None => blocklist_signing::BlocklistSigner::disabled(),
+    };
+
+    let mut worm_sinks = Vec::new();
+    if let Some(target) = &args.worm_syslog {
+        let syslog = worm::Syslog::parse(target).unwrap_or_else(|e| panic!("--worm-syslog: {}", e));
+        worm_sinks.push(worm::Sink::Syslog(syslog));
+    }
+    if let Some(url) = &args.worm_s3 {
+        let s3 = worm::S3::new(url, &args.worm_s3_region, args.worm_retention_days)
+            .unwrap_or_else(|e| panic!("--worm-s3: {}", e));
+        worm_sinks.push(worm::Sink::S3(s3));
+    }
+    for sink in &worm_sinks {
+        log::info!("🗄️  Security events appended to {}", sink.name());
+    }
+    worm::install(worm_sinks);
+    if let Some(url) = &args.lake_s3 {
+        // No Object Lock: the lake is a feed, not evidence.
+        let s3 = worm::S3::new(url, &args.lake_s3_region, 0)
+            .unwrap_or_else(|e| panic!("--lake-s3: {}", e));
+        log::info!("🏞️ Ingested payloads copied as NDJSON to {}", url);
+        lake::install(s3);
+    }
+
+    if let Some(url) = &args.external_url {
+        let url = external_url::parse(url).unwrap_or_else(|e| panic!("--external-url: {}", e));
+        log::info!("🔗 Links point at {}", url);
+        external_url::install(url);
+    }
+
+    let clock_skew = clock_skew::ClockSkew::new(
+        parse_duration(&args.max_clock_skew).expect("--max-clock-skew must look like 2m or 30s"),
+        parse_duration(&args.max_clock_drift).expect("--max-clock-drift must look like 1m or 30s"),
+    );
+
+    let quotas = quotas::Quotas::new(quotas::QuotaLimits {
+        logs_per_day: args.quota_logs_per_day,
+        events_per_day: args.quota_events_per_day,
+        bytes_per_day: args.quota_mb_per_day * 1024 * 1024,
+    })
+    .with_groups(groups.clone());
+    if quotas.is_enabled() {
+        log::info!("🪣 Per-client daily quotas: {:?}", quotas.limits());
+    }
+
+    let batch_limit = batch::BatchLimit {
+        max_logs: args.max_batch_logs,
+        oversized: args.oversized_batch,
+    };
+    if batch_limit.max_logs > 0 {
+        log::info!(
+            "📦 Batches over {} logs: {:?}",
+            batch_limit.max_logs,
+            batch_limit.oversized
+        );
+    }
+    let request_types = request_types::RequestTypeFilter::new(&args.drop_request_types);
+    if request_types.is_enabled() {
+        log::info!(
+            "🧹 Dropping logs of request types: {}",
+            request_types.types().join(", ")
+        );
+        for unknown in request_types.unknown_types() {
+            log::warn!(
+                "⚠️ --drop-request-type {} is not a type browsers report",
+                unknown
+            );
+        }
+    }
+    let url_normalizer = if args.normalize_urls {
+        log::info!(
+            "🔗 Normalizing log URLs, stripping query parameters: {}",
+            args.strip_query_params.join(", ")
+        );
+        url_normalize::UrlNormalizer::new(&args.strip_query_params)
+    } else {
+        url_normalize::UrlNormalizer::default()
+    };
+    let event_data_limit = event_data::EventDataLimit {
+        max_depth: args.max_event_data_depth,
+        max_bytes: args.max_event_data_bytes,
+        oversized: args.oversized_event_data,
+    };
+    if event_data_limit.is_limited() {
+        log::info!(
+            "📦 Event data over {} levels or {} bytes: {:?}",
+            event_data_limit.max_depth,
+            event_data_limit.max_bytes,
+            event_data_limit.oversized
+        );
+    }
+
+    let capture = match &args.capture_dir {
+        Some(dir) => {
+            let capture = capture::Capture::new(dir, args.capture_max_files)?;
+            log::warn!(
+                "🎞️  Capturing raw requests to {} (last {} kept)",
+                dir.display(),
+                args.capture_max_files
+            );
+            capture
+        }
+        None => capture::Capture::disabled(),
+    };
+    let capture = web::Data::new(capture);
+
+    let uploads = match &args.upload_dir {
+        Some(dir) => {
+            let uploads = uploads::UploadStore::new(dir, args.max_upload_mb * 1024 * 1024)?;
+            log::info!(
+                "📎 Security event uploads stored in {} (up to {} MB each, {} on record)",
+                dir.display(),
+                args.max_upload_mb,
+                uploads.len()
+            );
+            uploads
+        }
+        None => uploads::UploadStore::disabled(),
+    };
+    let uploads = web::Data::new(uploads);
 
-    match args.mode {
+    let mut scanners = Vec::new();
+    if let Some(command) = &args.scan_command {
+        scanners.push(scanning::Scanner::command(command).unwrap_or_else(|e| panic!("{}", e)));
+    }
+    if let Some(addr) = &args.clamd {
+        scanners.push(scanning::Scanner::Clamd(addr.clone()));
+    }
+    if let Some(dir) = &args.yara_rules {
+        scanners
+            .push(scanning::Scanner::yara(dir).unwrap_or_else(|e| panic!("--yara-rules: {}", e)));
+    }
+    if !scanners.is_empty() {
+        if uploads.is_active() {
+            let names: Vec<&str> = scanners.iter().map(|s| s.name()).collect();
+            log::info!("🧪 Uploads scanned with: {}", names.join(", "));
+        } else {
+            log::warn!("🧪 Upload scanners are configured but --upload-dir is not set; nothing will be scanned");
+        }
+    }
+    let scan_queue = web::Data::new(scanning::ScanQueue::spawn(scanners, uploads.clone()));
+
+    let features = features::FeatureFlags::new(&args.disabled_features);
+    let features = match &args.feature_flags {
+        Some(path) => features
+            .with_file(path)
+            .unwrap_or_else(|e| panic!("--feature-flags: {}", e)),
+        None => features,
+    };
+    let disabled: Vec<&str> = features
+        .states()
+        .iter()
+        .filter(|f| !f.enabled)
+        .map(|f| f.feature.name())
+        .collect();
+    if !disabled.is_empty() {
+        log::info!("🚩 Disabled features: {}", disabled.join(", "));
+    }
+    let features = std::sync::Arc::new(features);
+
+    let alert_router = alerts::AlertRouter::new()
+        .with_telegram_token(args.telegram_bot_token.clone())
+        .with_features(features.clone());
+    let alert_router = match &args.alert_rules {
+        Some(path) => {
+            let router = alert_router
+                .load(path)
+                .unwrap_or_else(|e| panic!("--alert-rules: {}", e));
+            let rules = router.current();
+            log::info!(
+                "🔔 {} alert rule(s), {} channel(s) from {}",
+                rules.rules().len(),
+                rules.config().channels.len(),
+                path.display()
+            );
+            router
+        }
+        None => alert_router,
+    };
+    let alert_router = web::Data::new(alert_router);
+    alerts::install(alert_router.clone());
+
+    if let (Some(token), Some(chat)) = (&args.telegram_bot_token, &args.telegram_chat) {
+        let server_url = match &args.telegram_server_url {
+            Some(url) => url.clone(),
+            None if args.bind.as_deref().is_some_and(|b| b.starts_with("unix:")) => {
+                panic!("--telegram-chat: set --telegram-server-url when listening on a unix socket")
+            }
+            None => format!(
+                "http://127.0.0.1:{}",
+                args.bind
+                    .as_deref()
+                    .and_then(|b| b.rsplit_once(':'))
+                    .map(|(_, port)| port.to_string())
+                    .unwrap_or_else(|| args.port.to_string())
+            ),
+        };
+        log::info!("🤖 Telegram bot answering chat {} via {}", chat, server_url);
+        telegram::TelegramBot::new(
+            token.clone(),
+            chat.clone(),
+            &server_url,
+            args.admin_token.clone(),
+        )
+        .spawn();
+    } else if args.telegram_chat.is_some() {
+        panic!("--telegram-chat: needs --telegram-bot-token");
+    }
+
+    let categories =
+        categories::Categories::new(!args.no_default_categories, args.category_list.as_deref())
+            .unwrap_or_else(|e| panic!("--category-list: {}", e));
+    log::info!("🏷️ {} categorized domains", categories.len());
+    let categories = std::sync::Arc::new(categories);
+
+    let screen_time = match &args.screen_time {
+        Some(path) => {
+            let screen_time = screen_time::ScreenTime::load(path, categories.clone())
+                .unwrap_or_else(|e| panic!("--screen-time: {}", e));
+            log::info!(
+                "⏳ Screen-time budgets for {} client(s) over {} categories from {}",
+                screen_time.config().budgets.len(),
+                screen_time.config().categories.len(),
+                path.display()
+            );
+            screen_time
+        }
+        None => screen_time::ScreenTime::new(),
+    };
+
+    let focus = focus::FocusSessions::new(!args.no_default_focus_domains, &args.focus_domains);
+
+    let honeypot = honeypot::Honeypot::new(!args.no_default_honeypots, &args.honeypot_paths);
+    if !honeypot.paths().is_empty() {
+        log::info!("🍯 {} honeypot path(s) armed", honeypot.paths().len());
+    }
+    let honeypot = web::Data::new(honeypot);
+
+    let exfil_window =
+        parse_duration(&args.exfil_window).expect("--exfil-window must look like 30m, 1h or 1d");
+    let exfil = web::Data::new(exfil::ExfilMonitor::new(
+        args.exfil_threshold_mb.saturating_mul(1024 * 1024),
+        exfil_window,
+        args.exfil_allow_domains.clone(),
+    ));
+    let homographs = web::Data::new(homograph::HomographDetector::new(&args.homograph_brands));
+    let rdap = match &args.rdap_max_age {
+        Some(max_age) => {
+            let max_age = parse_duration(max_age).expect("--rdap-max-age must look like 30d or 4w");
+            let rdap = rdap::RdapService::new(max_age, &args.rdap_url)
+                .unwrap_or_else(|e| panic!("--rdap-max-age/--rdap-url: {}", e));
+            log::info!(
+                "🗓️ Flagging domains registered less than {} days ago (RDAP: {})",
+                max_age.num_days(),
+                args.rdap_url
+            );
+            rdap
+        }
+        None => rdap::RdapService::disabled(),
+    };
+    let security_headers = match &args.security_headers {
+        Some(path) => {
+            let headers = security_headers::SecurityHeaders::load(path)
+                .unwrap_or_else(|e| panic!("--security-headers: {}", e));
+            log::info!("🛡️ Security headers from {}", path.display());
+            headers
+        }
+        None => security_headers::SecurityHeaders::default(),
+    };
+    let asn = match &args.asn_db {
+        Some(path) => {
+            let db = asn::AsnDb::load(path).unwrap_or_else(|e| panic!("--asn-db: {}", e));
+            log::info!(
+                "🌐 {} ASN ranges from {}; host names {}",
+                db.len(),
+                path.display(),
+                if args.resolve_hosts {
+                    format!("resolved, up to {}/s", args.resolve_rate)
+                } else {
+                    "not resolved (--resolve-hosts is off)".to_string()
+                }
+            );
+            asn::AsnEnricher::new(db, args.resolve_hosts, args.resolve_rate, &args.bad_asns)
+        }
+        None => {
+            if args.resolve_hosts || !args.bad_asns.is_empty() {
+                log::warn!("🌐 --resolve-hosts and --bad-asn have no effect without --asn-db");
+            }
+            asn::AsnEnricher::disabled()
+        }
+    };
+
+    let mut app = match args.mode {
         ServerMode::Simple => {
             log::info!("🚀 Starting server in SIMPLE mode");
             log::info!("📦 Using in-memory storage");
-            log::info!("🌐 Listening on http://{}", bind_address);
-            log::info!("📊 Dashboard: http://{}/", bind_address);
+            log::info!("🌐 Listening on {}", listening_on);
+            log::info!("📊 Dashboard: {}/", listening_on);
             log::info!(
                 "📝 Logging level: {}",
                 std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string())
             );
 
             let state = web::Data::new(simple::SimpleState::new());
-
-            HttpServer::new(move || {
-                let cors = Cors::permissive();
-
-                App::new()
-                    .wrap(cors)
-                    .wrap(actix_web::middleware::Logger::default())
-                    .app_data(state.clone())
-                    .app_data(web::JsonConfig::default().error_handler(|err, _req| {
-                        let error_msg = format!("{}", err);
-                        log::error!("❌ JSON parsing error: {}", error_msg);
-                        actix_web::error::InternalError::from_response(
-                            err,
-                            HttpResponse::BadRequest().json(serde_json::json!({
-                                "success": false,
-                                "error": format!("Invalid JSON: {}", error_msg)
-                            })),
-                        )
-                        .into()
-                    }))
-                    .route("/", web::get().to(handlers::dashboard::serve_dashboard))
-                    .route("/dashboard", web::get().to(handlers::dashboard::serve_dashboard))
-                    .route("/logo.png", web::get().to(handlers::dashboard::serve_logo))
-                    .route("/health", web::get().to(health_check))
-                    .route("/api/logs", web::post().to(handlers::logs::post_logs_simple))
-                    .route("/api/logs", web::get().to(handlers::logs::get_logs_simple))
-                    .route("/api/blocklist", web::get().to(handlers::blocklist::get_blocklist_simple))
-                    .route("/api/blocklist", web::post().to(handlers::blocklist::post_blocklist_simple))
-                    .route(
-                        "/api/dashboard/events",
-                        web::get().to(handlers::dashboard::get_dashboard_events_simple),
-                    )
-                    .route(
-                        "/api/dashboard/events/{packet_id}",
-                        web::get().to(handlers::dashboard::get_dashboard_packet_simple),
-                    )
-                    .route(
-                        "/api/dashboard/clients",
-                        web::get().to(handlers::dashboard::get_dashboard_clients_simple),
-                    )
-                    .route(
-                        "/api/extensions",
-                        web::post().to(handlers::extensions::post_extensions_simple),
-                    )
-                    .route(
-                        "/api/security",
-                        web::post().to(handlers::extensions::post_security_simple),
-                    )
-            })
-            .bind(&bind_address)?
-            .run()
-            .await
+            server_events::attach_simple(state.clone());
+            let mut app = AppState::new(Backend::Simple(state));
+            app.reputation = web::Data::new(reputation);
+            app.ban_list = web::Data::new(ban_list);
+            app.quotas = web::Data::new(quotas);
+            app.metering = web::Data::new(metering);
+            app
         }
         #[cfg(feature = "production")]
         ServerMode::Production => {
             log::info!("🚀 Starting server in PRODUCTION mode");
+            log::info!("🏷️  Instance id: {}", instance::id().unwrap_or("-"));
 
             let database_url = args
                 .database_url
+                .as_deref()
                 .expect("--database-url is required for production mode");
             let redis_url = args.redis_url.as_deref();
 
-            log::info!("🗄️  Connecting to PostgreSQL...");
-            let state = production::ProductionState::new(&database_url, redis_url)
-                .await
-                .expect("Failed to initialize production state");
+            log::info!("🗄️  Connecting to database...");
+            let state = production::ProductionState::new(
+                database_url,
+                args.database_read_url.as_deref(),
+                redis_url,
+                &args.pool_config(),
+            )
+            .await
+            .expect("Failed to initialize production state")
+            .with_shards(metering.orgs(), &args.pool_config())
+            .await
+            .expect("Failed to connect to the --orgs shards");
 
             log::info!("✅ Database connected");
+            if state.has_replica() {
+                log::info!("📖 Read replica connected; query endpoints read from it");
+            }
             if redis_url.is_some() {
                 log::info!("✅ Redis connected");
+            } else {
+                log::info!(
+                    "ℹ️  No --redis-url: sessions, bans, quotas, metering and ingest and distinct counts are per replica"
+                );
             }
-            log::info!("🌐 Listening on http://{}", bind_address);
-
-            let state = web::Data::new(state);
-
-            HttpServer::new(move || {
-                let cors = Cors::permissive();
-
-                App::new()
-                    .wrap(cors)
-                    .app_data(state.clone())
-                    .route("/", web::get().to(handlers::dashboard::serve_dashboard))
-                    .route("/dashboard", web::get().to(handlers::dashboard::serve_dashboard))
-                    .route("/logo.png", web::get().to(handlers::dashboard::serve_logo))
-                    .route("/health", web::get().to(health_check))
-                    .route("/api/logs", web::post().to(handlers::logs::post_logs_production))
-                    .route(
-                        "/api/blocklist",
-                        web::get().to(handlers::blocklist::get_blocklist_production),
-                    )
-                    .route(
-                        "/api/blocklist",
-                        web::post().to(handlers::blocklist::post_blocklist_production),
-                    )
-                    .route(
-                        "/api/extensions",
-                        web::post().to(handlers::extensions::post_extensions_production),
-                    )
-                    .route(
-                        "/api/security",
-                        web::post().to(handlers::extensions::post_security_production),
-                    )
-            })
-            .bind(&bind_address)?
-            .run()
+            log::info!("🌐 Listening on {}", listening_on);
+
+            let state = std::sync::Arc::new(state);
+            let backend = Backend::production(state.clone(), args.stats_interval());
+            server_events::attach_production(state);
+            let mut app = AppState::new(backend);
+            app.redis = shared_redis(args.redis_url.as_deref());
+            app.reputation = web::Data::new(reputation.with_redis(app.redis.clone()));
+            app.ban_list = web::Data::new(ban_list.with_redis(app.redis.clone()));
+            app.quotas = web::Data::new(quotas.with_redis(app.redis.clone()));
+            app.metering = web::Data::new(metering.with_redis(app.redis.clone()));
+            app.distinct =
+                web::Data::new(distinct::DistinctCounters::new().with_redis(app.redis.clone()));
+            app.ingest_counters =
+                web::Data::new(counters::IngestCounters::new().with_redis(app.redis.clone()));
+            app
+        }
+        #[cfg(feature = "production")]
+        ServerMode::Hybrid => {
+            log::info!("🚀 Starting server in HYBRID mode");
+            log::info!("🏷️  Instance id: {}", instance::id().unwrap_or("-"));
+
+            let database_url = args
+                .database_url
+                .as_deref()
+                .expect("--database-url is required for hybrid mode");
+            let hot_window =
+                parse_duration(&args.hot_window).expect("--hot-window must look like 15m or 1h");
+
+            log::info!("🗄️  Connecting to database...");
+            let warm = production::ProductionState::new(
+                database_url,
+                args.database_read_url.as_deref(),
+                args.redis_url.as_deref(),
+                &args.pool_config(),
+            )
             .await
+            .expect("Failed to initialize production state")
+            .with_shards(metering.orgs(), &args.pool_config())
+            .await
+            .expect("Failed to connect to the --orgs shards");
+            log::info!("✅ Database connected");
+            if warm.has_replica() {
+                log::info!("📖 Read replica connected; warm-tier reads come from it");
+            }
+            log::info!("🔥 Hot tier keeps the last {} in memory", args.hot_window);
+            log::info!("🌐 Listening on {}", listening_on);
+
+            let warm = std::sync::Arc::new(warm);
+            let writer = hybrid::spawn_warm_writer(warm.clone());
+            let hot = web::Data::new(simple::SimpleState::new().with_warm_tier(hot_window, writer));
+            server_events::attach_simple(hot.clone());
+            let mut app = AppState::new(Backend::hybrid(warm, hot, args.stats_interval()));
+            app.redis = shared_redis(args.redis_url.as_deref());
+            app.reputation = web::Data::new(reputation);
+            app.ban_list = web::Data::new(ban_list.with_redis(app.redis.clone()));
+            app.quotas = web::Data::new(quotas.with_redis(app.redis.clone()));
+            app.metering = web::Data::new(metering.with_redis(app.redis.clone()));
+            app.distinct =
+                web::Data::new(distinct::DistinctCounters::new().with_redis(app.redis.clone()));
+            app.ingest_counters =
+                web::Data::new(counters::IngestCounters::new().with_redis(app.redis.clone()));
+            app
         }
         #[cfg(not(feature = "production"))]
-        ServerMode::Production => {
-            eprintln!("❌ Production mode not available. Rebuild with --features production");
+        ServerMode::Production | ServerMode::Hybrid => {
+            eprintln!(
+                "❌ Production and hybrid modes not available. Rebuild with --features production"
+            );
             std::process::exit(1);
         }
+    };
+    app.ip_filter = ip_filter;
+    app.trusted_proxies = web::Data::new(trusted_proxies);
+    app.capture = capture;
+    app.uploads = uploads;
+    app.scan_queue = scan_queue;
+    app.alerts = alert_router;
+    app.features = web::Data::from(features);
+    app.screen_time = web::Data::new(screen_time);
+    app.focus = web::Data::new(focus);
+    app.categories = web::Data::from(categories);
+    app.groups = web::Data::from(groups);
+    if let Some(path) = &args.client_names {
+        let names = client_names::ClientNames::load(path)
+            .unwrap_or_else(|e| panic!("--client-names: {}", e));
+        log::info!(
+            "🏷️ {} client name(s) from {}",
+            names.list().len(),
+            path.display()
+        );
+        app.client_names = web::Data::new(names);
+    }
+    if let Some(path) = &args.ldap_config {
+        let directory =
+            ldap::LdapDirectory::load(path).unwrap_or_else(|e| panic!("--ldap-config: {}", e));
+        log::info!(
+            "📇 Clients are named after their users in {}",
+            directory.url().unwrap_or_default()
+        );
+        app.ldap = web::Data::new(directory);
+    }
+    if let Some(spec) = &args.client_names_csv {
+        let directory = std::sync::Arc::new(client_names::Directory::parse(spec));
+        log::info!("🏷️ Client names are synced from {}", directory.describe());
+        let names = app.client_names.clone();
+        scheduler.register(
+            "client_names_sync",
+            scheduler::Schedule::every(std::time::Duration::from_secs(900)),
+            scheduler::JobOptions {
+                retries: 2,
+                backoff: std::time::Duration::from_secs(60),
+                critical: false,
+            },
+            move || client_names::sync_job(names.clone(), directory.clone()),
+        );
+    }
+    app.maintenance = web::Data::new(maintenance);
+    if let Some(spec) = &args.chaos {
+        let chaos = chaos::Chaos::parse(spec).unwrap_or_else(|e| panic!("--chaos: {}", e));
+        log::warn!(
+            "🐒 Chaos mode: ingest requests are delayed, failed and dropped ({})",
+            chaos.describe()
+        );
+        app.chaos = web::Data::new(chaos);
+    }
+    app.backups = web::Data::new(backups);
+    app.chain = event_chain;
+    app.clock_skew = web::Data::new(clock_skew);
+    app.telemetry = web::Data::new(telemetry::PerfTelemetry::new(args.slow_blocklist_match));
+    app.sessions = web::Data::new(sessions::SessionTracker::new(args.session_max_ips));
+    #[cfg(feature = "production")]
+    let enrollment = enrollment.with_redis(app.redis.clone());
+    app.enrollment = web::Data::new(enrollment);
+    if let Some(path) = &args.detection_rules {
+        let rules = detection::DetectionRules::load(path)
+            .unwrap_or_else(|e| panic!("--detection-rules: {}", e));
+        log::info!(
+            "🧩 {} detection rule(s), {} enabled, from {}",
+            rules.list().len(),
+            rules.active().len(),
+            path.display()
+        );
+        app.detection_rules = web::Data::new(rules);
+    }
+    if let Some(path) = &args.ticket_config {
+        let ticketing = web::Data::new(
+            tickets::Ticketing::load(path).unwrap_or_else(|e| panic!("--ticket-config: {}", e)),
+        );
+        log::info!(
+            "🎫 Escalated events open {} tickets ({})",
+            ticketing.provider().unwrap_or_default(),
+            path.display()
+        );
+        let triage = app.triage.clone();
+        let sync = ticketing.clone();
+        scheduler.register(
+            "ticket_sync",
+            scheduler::Schedule::every(std::time::Duration::from_secs(600)),
+            scheduler::JobOptions {
+                retries: 2,
+                backoff: std::time::Duration::from_secs(60),
+                critical: false,
+            },
+            move || tickets::sync_job(sync.clone(), triage.clone()),
+        );
+        app.ticketing = ticketing;
+    }
+    if args.ct_lookups {
+        let ct = ct::CtLookups::new(&args.ct_url, &args.ct_event_types, app.triage.clone())
+            .unwrap_or_else(|e| panic!("--ct-url: {}", e));
+        log::info!(
+            "🔏 Domains flagged by {} events are cross-checked in certificate transparency ({})",
+            args.ct_event_types.join(", "),
+            args.ct_url
+        );
+        ct::install(web::Data::new(ct));
+    }
+    let blocklist_history = web::Data::new(blocklist_history);
+    rescan::register(
+        &scheduler,
+        app.backend.clone(),
+        app.rescans.clone(),
+        app.detection_rules.clone(),
+        blocklist_history.clone(),
+    );
+    let unknown = scheduler.unknown_overrides();
+    if !unknown.is_empty() {
+        panic!(
+            "--job-schedule: there is no job named {}",
+            unknown.join(", ")
+        );
+    }
+    app.scheduler = web::Data::new(scheduler);
+    app.blocklist_history = blocklist_history;
+    app.blocklist_signer = web::Data::new(blocklist_signer);
+    if args.blocklist_sync_secret.is_some() {
+        log::info!("🔄 Blocklist pushes accepted at /api/integrations/blocklist-sync");
+    }
+    app.blocklist_sync = web::Data::new(blocklist_sync::BlocklistSync::new(
+        args.blocklist_sync_secret.clone(),
+    ));
+    app.stats_cache = web::Data::new(args.stats_cache());
+    app.honeypot = honeypot;
+    app.exfil = exfil;
+    app.homographs = homographs;
+    app.rdap = web::Data::new(rdap);
+    app.security_headers = web::Data::new(security_headers);
+    app.asn = web::Data::new(asn);
+    app.batch_limit = web::Data::new(batch_limit);
+    app.request_types = web::Data::new(request_types);
+    app.url_normalizer = web::Data::new(url_normalizer);
+    app.event_data_limit = web::Data::new(event_data_limit);
+    #[cfg(feature = "production")]
+    let admin_auth = admin_auth.with_redis(app.redis.clone());
+    app.admin_auth = web::Data::new(admin_auth);
+    if let Some(path) = &args.users_file {
+        if matches!(args.mode, ServerMode::Simple) {
+            let users = users::UserStore::new()
+                .with_file(path)
+                .unwrap_or_else(|e| panic!("--users-file: {}", e));
+            log::info!(
+                "👤 {} local user(s) from {}",
+                users.list().len(),
+                path.display()
+            );
+            app.users = web::Data::new(users);
+        } else {
+            log::warn!("👤 --users-file is ignored: with a database, local users are kept there");
+        }
     }
+    if !app.admin_auth.is_enabled(Some(&app.users)) {
+        log::warn!(
+            "🔓 No --admin-token set: admin and blocklist-update endpoints are unauthenticated until a local user is added"
+        );
+    }
+    app.bundle_signer = bundle_signer;
+    app.runtime_config = runtime_config;
+
+    record_startup(&app.runtime_config, &listening_on);
+    log::info!(
+        "⚙️ HTTP: {} workers, keep-alive {}, request timeout {:?}, {} connections per worker{}",
+        tuning
+            .workers
+            .map_or("per-core".to_string(), |w| w.to_string()),
+        match tuning.keep_alive.is_zero() {
+            true => "off".to_string(),
+            false => format!("{:?}", tuning.keep_alive),
+        },
+        tuning.client_request_timeout,
+        tuning.max_connections,
+        if tuning.http2 { ", h2c" } else { "" }
+    );
+    let metering = app.metering.clone();
+    let served = listen::serve(app, listeners, &tuning).await;
+    metering.flush();
+    served
 }
@@ -0,0 +1,161 @@
+//! Maintenance pauses: an admin stops ingestion (and optionally reads) for a
+//! database migration or restore, now or in a scheduled window. Paused
+//! requests get `503` with `Retry-After` pointing at the end of the window,
+//! so extensions keep their batches and send them later.
+//!
+//! With `--maintenance-file` the pause is written to disk and picked up again
+//! on startup, so a restart in the middle of maintenance doesn't resume
+//! early.
+
+use crate::error::ApiError;
+use crate::metrics;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// `Retry-After` for a pause with no end set.
+pub const DEFAULT_RETRY_AFTER: i64 = 60;
+
+/// Endpoints that receive data from extensions.
+pub const INGEST_PATHS: &[&str] = &[
+    "/api/logs",
+    "/api/logs/import",
+    "/api/extensions",
+    "/api/security",
+    "/api/security/upload",
+    "/api/errors",
+    "/api/telemetry",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    /// Refuse ingest.
+    pub ingest: bool,
+    /// Refuse every other `/api/` request too, bar the admin endpoints.
+    pub reads: bool,
+    pub starts_at: DateTime<Utc>,
+    /// Resume by itself at this time; `None` until resumed through the API.
+    pub ends_at: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+    pub set_by: String,
+    pub set_at: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && self.ends_at.is_none_or(|end| now < end)
+    }
+
+    pub fn is_over(&self, now: DateTime<Utc>) -> bool {
+        self.ends_at.is_some_and(|end| end <= now)
+    }
+
+    fn covers(&self, req: &ServiceRequest) -> bool {
+        let path = req.path();
+        if INGEST_PATHS.contains(&path) && req.method() == actix_web::http::Method::POST {
+            return self.ingest;
+        }
+        self.reads && path.starts_with("/api/") && !path.starts_with("/api/admin/")
+    }
+}
+
+pub struct Maintenance {
+    path: Option<PathBuf>,
+    window: RwLock<Option<MaintenanceWindow>>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance::new()
+    }
+}
+
+impl Maintenance {
+    /// Never paused to begin with; a pause lasts until restart.
+    pub fn new() -> Self {
+        Maintenance {
+            path: None,
+            window: RwLock::new(None),
+        }
+    }
+
+    /// Picks up the pause saved in `path`, if there is one that isn't over.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let window = if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let window: Option<MaintenanceWindow> =
+                serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+            window.filter(|w| !w.is_over(Utc::now()))
+        } else {
+            None
+        };
+        Ok(Maintenance {
+            path: Some(path.to_path_buf()),
+            window: RwLock::new(window),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The pause that is running or scheduled, if any.
+    pub fn current(&self) -> Option<MaintenanceWindow> {
+        let window = self.window.read().unwrap();
+        window.clone().filter(|w| !w.is_over(Utc::now()))
+    }
+
+    fn persist(&self, window: &Option<MaintenanceWindow>) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut text = serde_json::to_vec_pretty(window).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces any earlier pause.
+    pub fn pause(&self, window: MaintenanceWindow) -> std::io::Result<()> {
+        let mut current = self.window.write().unwrap();
+        let window = Some(window);
+        self.persist(&window)?;
+        *current = window;
+        Ok(())
+    }
+
+    /// Lifts the pause, or cancels a scheduled one; returns it if there was one.
+    pub fn resume(&self) -> std::io::Result<Option<MaintenanceWindow>> {
+        let mut current = self.window.write().unwrap();
+        self.persist(&None)?;
+        Ok(current.take().filter(|w| !w.is_over(Utc::now())))
+    }
+}
+
+/// Refuses the requests a running pause covers.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let paused = req
+        .app_data::<web::Data<Maintenance>>()
+        .and_then(|m| m.current())
+        .filter(|w| w.is_active(Utc::now()) && w.covers(&req));
+    let Some(window) = paused else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+    metrics::MAINTENANCE_REJECTED.inc();
+    let err = ApiError::Maintenance {
+        ends_at: window.ends_at,
+        reason: window.reason,
+    };
+    Ok(req
+        .into_response(err.error_response())
+        .map_into_right_body())
+}
@@ -0,0 +1,473 @@
+//! Per-organization usage metering for hosted deployments (`--orgs`): how
+//! many log entries and events each org's clients sent, how many bytes of
+//! it were stored and how many API calls they made, per UTC day, for
+//! `GET /api/admin/usage` and its CSV export to invoice from.
+//!
+//! Clients belong to an org by exact `client_id` or by prefix; the rest,
+//! and batches without a `client_id`, are metered under [`UNASSIGNED`].
+//! What is counted is what got past the archive and quota checks
+//! (ingest and uploads, whether stored or held for enrollment), plus
+//! blocklist downloads that name a client. Days are kept for
+//! [`RETENTION_DAYS`], in `--metering-file` when given (written at most
+//! once a minute and at shutdown). With Redis (production and hybrid
+//! modes) replicas also count into shared hashes `metering:<org>:<day>`,
+//! which the report reads when Redis answers.
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+use chrono::{Datelike, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "production")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The org of clients no org claims.
+pub const UNASSIGNED: &str = "unassigned";
+/// Days of usage kept, enough to re-export last year's invoices.
+pub const RETENTION_DAYS: u64 = 400;
+/// Least time between two writes of `--metering-file`.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The `--orgs` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrgsConfig {
+    #[serde(default)]
+    pub orgs: BTreeMap<String, Org>,
+    /// Databases (name → URL) orgs keep their logs and events in instead
+    /// of `--database-url`, for data residency; see [`crate::sharding`].
+    #[serde(default)]
+    pub shards: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Org {
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// Every client_id starting with one of these; the longest match wins.
+    #[serde(default)]
+    pub client_prefixes: Vec<String>,
+    /// The `shards` entry the org's logs and events are stored in; the
+    /// main database when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
+}
+
+impl OrgsConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: OrgsConfig =
+            serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if config.orgs.contains_key(UNASSIGNED) {
+            return Err(format!(
+                "{}: \"{}\" is reserved",
+                path.display(),
+                UNASSIGNED
+            ));
+        }
+        config
+            .check_shards()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Every org's `shard` must be one of `shards`.
+    pub fn check_shards(&self) -> Result<(), String> {
+        for (name, org) in &self.orgs {
+            if let Some(shard) = &org.shard {
+                if !self.shards.contains_key(shard) {
+                    return Err(format!(
+                        "org \"{}\" names unknown shard \"{}\"",
+                        name, shard
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The shard `client_id`'s data is stored in; `None` for the main
+    /// database.
+    pub fn shard_of(&self, client_id: Option<&str>) -> Option<&str> {
+        self.orgs.get(self.org_of(client_id))?.shard.as_deref()
+    }
+
+    /// The org `client_id` is metered under.
+    pub fn org_of(&self, client_id: Option<&str>) -> &str {
+        let Some(client_id) = client_id.filter(|c| !c.is_empty()) else {
+            return UNASSIGNED;
+        };
+        if let Some((name, _)) = self
+            .orgs
+            .iter()
+            .find(|(_, org)| org.clients.iter().any(|c| c == client_id))
+        {
+            return name;
+        }
+        self.orgs
+            .iter()
+            .flat_map(|(name, org)| org.client_prefixes.iter().map(move |p| (name, p)))
+            .filter(|(_, prefix)| client_id.starts_with(prefix.as_str()))
+            .max_by_key(|(_, prefix)| prefix.len())
+            .map_or(UNASSIGNED, |(name, _)| name)
+    }
+}
+
+/// One org's usage over a day, or what one request adds to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Meter {
+    pub logs: u64,
+    pub events: u64,
+    /// Uncompressed bytes of the batches and uploads accepted.
+    pub stored_bytes: u64,
+    pub api_calls: u64,
+}
+
+impl Meter {
+    fn add(&mut self, other: &Meter) {
+        self.logs += other.logs;
+        self.events += other.events;
+        self.stored_bytes += other.stored_bytes;
+        self.api_calls += other.api_calls;
+    }
+}
+
+/// `--metering-file`: org -> day -> usage.
+type UsageByOrg = BTreeMap<String, BTreeMap<NaiveDate, Meter>>;
+
+#[derive(Debug, Serialize)]
+pub struct DayUsage {
+    pub day: NaiveDate,
+    #[serde(flatten)]
+    pub usage: Meter,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrgUsage {
+    pub org: String,
+    pub total: Meter,
+    /// Days with any usage, oldest first.
+    pub days: Vec<DayUsage>,
+}
+
+/// `GET /api/admin/usage`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `YYYY-MM`.
+    pub month: String,
+    /// `memory` (this replica) or `redis` (every replica).
+    pub source: &'static str,
+    pub orgs: Vec<OrgUsage>,
+}
+
+impl Report {
+    /// One row per org and day, then the org's `total` row.
+    pub fn to_csv(&self) -> String {
+        let mut out = csv::Writer::from_writer(Vec::new());
+        // Records of one length written to memory can't fail.
+        let mut write = |record: [String; 7]| out.write_record(record).expect("CSV into memory");
+        write(
+            [
+                "org",
+                "month",
+                "day",
+                "logs",
+                "events",
+                "stored_bytes",
+                "api_calls",
+            ]
+            .map(String::from),
+        );
+        for org in &self.orgs {
+            let rows = org
+                .days
+                .iter()
+                .map(|d| (d.day.to_string(), d.usage))
+                .chain(std::iter::once(("total".to_string(), org.total)));
+            for (day, usage) in rows {
+                write([
+                    org.org.clone(),
+                    self.month.clone(),
+                    day,
+                    usage.logs.to_string(),
+                    usage.events.to_string(),
+                    usage.stored_bytes.to_string(),
+                    usage.api_calls.to_string(),
+                ]);
+            }
+        }
+        let bytes = out.into_inner().expect("CSV into memory");
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+/// The first and last day of `month` (`YYYY-MM`).
+pub fn month_days(month: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let first = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()?;
+    let last = first.checked_add_months(chrono::Months::new(1))? - Days::new(1);
+    Some((first, last))
+}
+
+pub fn current_month() -> String {
+    let today = Utc::now().date_naive();
+    format!("{:04}-{:02}", today.year(), today.month())
+}
+
+struct Saved {
+    days: UsageByOrg,
+    last_save: Instant,
+    dirty: bool,
+}
+
+pub struct Metering {
+    orgs: OrgsConfig,
+    path: Option<PathBuf>,
+    saved: Mutex<Saved>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl Default for Metering {
+    fn default() -> Self {
+        Self::new(OrgsConfig::default())
+    }
+}
+
+impl Metering {
+    pub fn new(orgs: OrgsConfig) -> Self {
+        Metering {
+            orgs,
+            path: None,
+            saved: Mutex::new(Saved {
+                days: UsageByOrg::new(),
+                last_save: Instant::now(),
+                dirty: false,
+            }),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Keeps the daily usage in `path`, starting from what is already there.
+    pub fn with_file(mut self, path: &Path) -> std::io::Result<Self> {
+        let days = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => UsageByOrg::new(),
+            Err(e) => return Err(e),
+        };
+        self.saved.get_mut().unwrap().days = days;
+        self.path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Also counts into Redis hashes `metering:<org>:<day>`, shared by
+    /// every replica.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    pub fn orgs(&self) -> &OrgsConfig {
+        &self.orgs
+    }
+
+    /// Adds `usage` to the org of `client_id` for today.
+    pub async fn record(&self, client_id: Option<&str>, usage: Meter) {
+        let org = self.orgs.org_of(client_id).to_string();
+        let day = Utc::now().date_naive();
+        {
+            let mut saved = self.saved.lock().unwrap();
+            saved
+                .days
+                .entry(org.clone())
+                .or_default()
+                .entry(day)
+                .or_default()
+                .add(&usage);
+            saved.dirty = true;
+            if saved.last_save.elapsed() >= SAVE_INTERVAL {
+                self.save(&mut saved);
+            }
+        }
+        #[cfg(feature = "production")]
+        if let Some(redis) = &self.redis {
+            redis_add(redis, &org, day, usage).await;
+        }
+    }
+
+    fn save(&self, saved: &mut Saved) {
+        let cutoff = Utc::now().date_naive() - Days::new(RETENTION_DAYS);
+        for days in saved.days.values_mut() {
+            days.retain(|day, _| *day >= cutoff);
+        }
+        saved.days.retain(|_, days| !days.is_empty());
+        saved.last_save = Instant::now();
+        saved.dirty = false;
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(&saved.days)
+            .map_err(std::io::Error::other)
+            .and_then(|text| {
+                let tmp = path.with_extension("tmp");
+                std::fs::write(&tmp, text)?;
+                std::fs::rename(&tmp, path)
+            });
+        if let Err(e) = result {
+            log::error!("❌ Writing {} failed: {}", path.display(), e);
+        }
+    }
+
+    /// Writes what was counted since the last write, at shutdown.
+    pub fn flush(&self) {
+        let mut saved = self.saved.lock().unwrap();
+        if saved.dirty {
+            self.save(&mut saved);
+        }
+    }
+
+    /// Usage in `month` (`YYYY-MM`), for one org or all that used anything.
+    pub async fn report(&self, org: Option<&str>, month: &str) -> Option<Report> {
+        let (first, last) = month_days(month)?;
+        #[cfg(feature = "production")]
+        let shared = match &self.redis {
+            Some(redis) => self.redis_report(redis, org, first, last).await,
+            None => None,
+        };
+        #[cfg(not(feature = "production"))]
+        let shared: Option<UsageByOrg> = None;
+        let (source, days) = match shared {
+            Some(days) => ("redis", days),
+            None => {
+                let saved = self.saved.lock().unwrap();
+                let days = saved
+                    .days
+                    .iter()
+                    .filter(|(name, _)| org.is_none_or(|o| o == name.as_str()))
+                    .map(|(name, days)| {
+                        (
+                            name.clone(),
+                            days.range(first..=last).map(|(d, u)| (*d, *u)).collect(),
+                        )
+                    })
+                    .collect();
+                ("memory", days)
+            }
+        };
+        let mut orgs: Vec<OrgUsage> = days
+            .into_iter()
+            .filter(|(_, days)| !days.is_empty())
+            .map(|(org, days)| {
+                let mut total = Meter::default();
+                let days = days
+                    .into_iter()
+                    .map(|(day, usage)| {
+                        total.add(&usage);
+                        DayUsage { day, usage }
+                    })
+                    .collect();
+                OrgUsage { org, total, days }
+            })
+            .collect();
+        // An org asked for by name is reported even with no usage.
+        if let (Some(org), true) = (org, orgs.is_empty()) {
+            orgs.push(OrgUsage {
+                org: org.to_string(),
+                total: Meter::default(),
+                days: Vec::new(),
+            });
+        }
+        Some(Report {
+            month: month.to_string(),
+            source,
+            orgs,
+        })
+    }
+
+    /// Every configured org plus [`UNASSIGNED`], or just `org`, read from
+    /// Redis for each day of the month.
+    #[cfg(feature = "production")]
+    async fn redis_report(
+        &self,
+        redis: &SharedRedis,
+        org: Option<&str>,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> Option<UsageByOrg> {
+        let names: Vec<String> = match org {
+            Some(org) => vec![org.to_string()],
+            None => self
+                .orgs
+                .orgs
+                .keys()
+                .cloned()
+                .chain(std::iter::once(UNASSIGNED.to_string()))
+                .collect(),
+        };
+        let days: Vec<NaiveDate> = first.iter_days().take_while(|d| *d <= last).collect();
+        let mut conn = redis.conn().await?;
+        let mut pipe = redis::pipe();
+        for name in &names {
+            for day in &days {
+                pipe.hgetall(redis_key(name, *day));
+            }
+        }
+        let rows: Vec<std::collections::HashMap<String, u64>> =
+            timed(pipe.query_async(&mut conn)).await?;
+        let mut out = UsageByOrg::new();
+        for (i, name) in names.iter().enumerate() {
+            let org_days = out.entry(name.clone()).or_default();
+            for (j, day) in days.iter().enumerate() {
+                let fields = &rows[i * days.len() + j];
+                if fields.is_empty() {
+                    continue;
+                }
+                let get = |k: &str| fields.get(k).copied().unwrap_or(0);
+                org_days.insert(
+                    *day,
+                    Meter {
+                        logs: get("logs"),
+                        events: get("events"),
+                        stored_bytes: get("stored_bytes"),
+                        api_calls: get("api_calls"),
+                    },
+                );
+            }
+        }
+        Some(out)
+    }
+}
+
+#[cfg(feature = "production")]
+fn redis_key(org: &str, day: NaiveDate) -> String {
+    format!("metering:{}:{}", org, day)
+}
+
+#[cfg(feature = "production")]
+async fn redis_add(redis: &SharedRedis, org: &str, day: NaiveDate, usage: Meter) {
+    let Some(mut conn) = redis.conn().await else {
+        return;
+    };
+    let key = redis_key(org, day);
+    let mut pipe = redis::pipe();
+    pipe.hincr(&key, "logs", usage.logs)
+        .ignore()
+        .hincr(&key, "events", usage.events)
+        .ignore()
+        .hincr(&key, "stored_bytes", usage.stored_bytes)
+        .ignore()
+        .hincr(&key, "api_calls", usage.api_calls)
+        .ignore()
+        .expire(&key, (RETENTION_DAYS * 86_400) as i64)
+        .ignore();
+    timed(pipe.query_async::<_, ()>(&mut conn)).await;
+}
@@ -0,0 +1,585 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+pub struct Counter(AtomicU64);
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down, set by whoever samples it.
+pub struct Gauge(AtomicI64);
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Observations counted into fixed cumulative buckets (Prometheus histogram).
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// One per bound, plus `+Inf`.
+    buckets: [AtomicU64; MAX_BUCKETS],
+    sum: AtomicU64,
+}
+
+const MAX_BUCKETS: usize = 16;
+
+impl Histogram {
+    /// `bounds` are the bucket upper limits in increasing order.
+    pub const fn new(bounds: &'static [u64]) -> Self {
+        assert!(bounds.len() < MAX_BUCKETS);
+        Histogram {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; MAX_BUCKETS],
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let idx = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn render(&self, name: &str, help: &str, out: &mut String) {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} histogram\n"));
+        let mut cumulative = 0;
+        for (i, bound) in self.bounds.iter().enumerate() {
+            cumulative += self.buckets[i].load(Ordering::Relaxed);
+            out.push_str(&format!("{name}_bucket{{le=\"{bound}\"}} {cumulative}\n"));
+        }
+        cumulative += self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+        out.push_str(&format!("{name}_bucket{{le=\"+Inf\"}} {cumulative}\n"));
+        out.push_str(&format!(
+            "{name}_sum {}\n",
+            self.sum.load(Ordering::Relaxed)
+        ));
+        out.push_str(&format!("{name}_count {cumulative}\n"));
+    }
+}
+
+pub static INGESTED_BATCHES: Counter = Counter::new();
+pub static INGESTED_LOGS: Counter = Counter::new();
+pub static INGESTED_BLOCKED: Counter = Counter::new();
+pub static INGESTED_SUSPICIOUS: Counter = Counter::new();
+pub static IP_FILTER_REJECTED: Counter = Counter::new();
+pub static HONEYPOT_HITS: Counter = Counter::new();
+pub static IPS_BANNED: Counter = Counter::new();
+pub static BANNED_REQUESTS_REJECTED: Counter = Counter::new();
+pub static QUOTA_REJECTED: Counter = Counter::new();
+pub static ARCHIVED_CLIENT_REJECTED: Counter = Counter::new();
+pub static MAINTENANCE_REJECTED: Counter = Counter::new();
+pub static CHAOS_INJECTED: Counter = Counter::new();
+pub static FEATURE_DISABLED_REJECTED: Counter = Counter::new();
+pub static LEGACY_API_REQUESTS: Counter = Counter::new();
+pub static EXTENSION_ERRORS: Counter = Counter::new();
+pub static PERF_SAMPLES: Counter = Counter::new();
+pub static LDAP_LOOKUPS: Counter = Counter::new();
+pub static LDAP_FAILURES: Counter = Counter::new();
+pub static CSRF_REJECTED: Counter = Counter::new();
+pub static OVERSIZED_BATCHES_REJECTED: Counter = Counter::new();
+pub static OVERSIZED_BATCHES_SPLIT: Counter = Counter::new();
+pub static REQUEST_TYPE_DROPPED: Counter = Counter::new();
+pub static URLS_NORMALIZED: Counter = Counter::new();
+pub static HOMOGRAPH_DOMAINS: Counter = Counter::new();
+pub static RDAP_LOOKUPS: Counter = Counter::new();
+pub static RDAP_FAILURES: Counter = Counter::new();
+pub static NEWLY_REGISTERED_DOMAINS: Counter = Counter::new();
+pub static CT_LOOKUPS: Counter = Counter::new();
+pub static CT_FAILURES: Counter = Counter::new();
+pub static HOSTS_RESOLVED: Counter = Counter::new();
+pub static HOST_RESOLUTION_FAILURES: Counter = Counter::new();
+pub static BAD_ASN_HOSTS: Counter = Counter::new();
+pub static EVENT_DATA_REJECTED: Counter = Counter::new();
+pub static EVENT_DATA_TRUNCATED: Counter = Counter::new();
+pub static GZIP_WITHOUT_HEADER: Counter = Counter::new();
+pub static DECOMPRESSED_TOO_LARGE: Counter = Counter::new();
+pub static UPLOADS_STORED: Counter = Counter::new();
+pub static UPLOADS_REJECTED: Counter = Counter::new();
+pub static UPLOADS_INFECTED: Counter = Counter::new();
+pub static UPLOAD_SCAN_ERRORS: Counter = Counter::new();
+pub static ALERTS_SENT: Counter = Counter::new();
+pub static ALERT_DELIVERY_FAILURES: Counter = Counter::new();
+pub static WORM_APPENDED: Counter = Counter::new();
+pub static WORM_FAILURES: Counter = Counter::new();
+pub static WORM_DROPPED: Counter = Counter::new();
+pub static LAKE_OBJECTS_WRITTEN: Counter = Counter::new();
+pub static LAKE_FAILURES: Counter = Counter::new();
+pub static LAKE_DROPPED: Counter = Counter::new();
+pub static DETECTION_RULE_MATCHES: Counter = Counter::new();
+pub static RESCAN_FINDINGS: Counter = Counter::new();
+pub static JOB_RUNS: Counter = Counter::new();
+pub static JOB_FAILURES: Counter = Counter::new();
+pub static JOB_SKIPPED: Counter = Counter::new();
+pub static JOB_RETRIES: Counter = Counter::new();
+pub static STATS_CACHE_HITS: Counter = Counter::new();
+pub static STATS_CACHE_MISSES: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static DB_POOL_EXHAUSTED: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static DB_PING_FAILURES: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static DB_REPLICA_FALLBACKS: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static DB_QUERY_TIMEOUTS: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static DB_QUERIES_CANCELLED: Counter = Counter::new();
+#[cfg(feature = "production")]
+pub static WARM_QUEUE_DROPPED: Counter = Counter::new();
+
+pub static BATCH_LOGS: Histogram =
+    Histogram::new(&[1, 10, 50, 100, 250, 500, 1000, 2500, 5000, 10000]);
+
+pub static WORM_QUEUE_DEPTH: Gauge = Gauge::new();
+pub static LAKE_QUEUE_DEPTH: Gauge = Gauge::new();
+#[cfg(feature = "production")]
+pub static DB_UP: Gauge = Gauge::new();
+#[cfg(feature = "production")]
+pub static DB_POOL_CONNECTIONS: Gauge = Gauge::new();
+#[cfg(feature = "production")]
+pub static DB_POOL_IN_USE: Gauge = Gauge::new();
+#[cfg(feature = "production")]
+pub static DB_POOL_MAX: Gauge = Gauge::new();
+#[cfg(feature = "production")]
+pub static WARM_QUEUE_DEPTH: Gauge = Gauge::new();
+
+/// Every exported counter: (name, help, counter).
+static COUNTERS: &[(&str, &str, &Counter)] = &[
+    (
+        "canigoin_ingested_batches_total",
+        "Log batches accepted on /api/logs",
+        &INGESTED_BATCHES,
+    ),
+    (
+        "canigoin_ingested_logs_total",
+        "Log entries accepted on /api/logs",
+        &INGESTED_LOGS,
+    ),
+    (
+        "canigoin_ingested_blocked_total",
+        "Accepted log entries the extension had blocked",
+        &INGESTED_BLOCKED,
+    ),
+    (
+        "canigoin_ingested_suspicious_total",
+        "Accepted log entries for domains with a suspicious reputation score",
+        &INGESTED_SUSPICIOUS,
+    ),
+    (
+        "canigoin_ip_filter_rejected_total",
+        "Requests rejected by the CIDR allow/deny lists",
+        &IP_FILTER_REJECTED,
+    ),
+    (
+        "canigoin_honeypot_hits_total",
+        "Requests to decoy endpoints",
+        &HONEYPOT_HITS,
+    ),
+    (
+        "canigoin_ips_banned_total",
+        "Automatic IP bans issued",
+        &IPS_BANNED,
+    ),
+    (
+        "canigoin_banned_requests_rejected_total",
+        "Requests refused because the source IP is banned",
+        &BANNED_REQUESTS_REJECTED,
+    ),
+    (
+        "canigoin_quota_rejected_total",
+        "Ingest requests refused because the client_id is over its daily quota",
+        &QUOTA_REJECTED,
+    ),
+    (
+        "canigoin_archived_client_rejected_total",
+        "Ingest requests refused because the client_id is archived",
+        &ARCHIVED_CLIENT_REJECTED,
+    ),
+    (
+        "canigoin_maintenance_rejected_total",
+        "Requests refused with 503 during a maintenance pause",
+        &MAINTENANCE_REJECTED,
+    ),
+    (
+        "canigoin_chaos_injected_total",
+        "Ingest requests --chaos failed or dropped on purpose",
+        &CHAOS_INJECTED,
+    ),
+    (
+        "canigoin_feature_disabled_total",
+        "Requests refused because their feature is disabled",
+        &FEATURE_DISABLED_REJECTED,
+    ),
+    (
+        "canigoin_legacy_api_requests_total",
+        "Requests to unversioned /api/ routes, which answer with a Deprecation header",
+        &LEGACY_API_REQUESTS,
+    ),
+    (
+        "canigoin_extension_errors_total",
+        "Error reports the extension sent about itself",
+        &EXTENSION_ERRORS,
+    ),
+    (
+        "canigoin_extension_perf_samples_total",
+        "Performance samples the extension reported about itself",
+        &PERF_SAMPLES,
+    ),
+    (
+        "canigoin_ldap_lookups_total",
+        "Usernames looked up in the --ldap-config directory",
+        &LDAP_LOOKUPS,
+    ),
+    (
+        "canigoin_ldap_lookup_failures_total",
+        "Directory lookups that failed or timed out",
+        &LDAP_FAILURES,
+    ),
+    (
+        "canigoin_csrf_rejected_total",
+        "Dashboard session requests refused for a missing or wrong CSRF token or a foreign origin",
+        &CSRF_REJECTED,
+    ),
+    (
+        "canigoin_oversized_batches_rejected_total",
+        "Log batches refused for having more than --max-batch-logs entries",
+        &OVERSIZED_BATCHES_REJECTED,
+    ),
+    (
+        "canigoin_oversized_batches_split_total",
+        "Log batches over --max-batch-logs stored as several smaller batches",
+        &OVERSIZED_BATCHES_SPLIT,
+    ),
+    (
+        "canigoin_request_type_dropped_total",
+        "Log entries dropped at ingest for a --drop-request-type type",
+        &REQUEST_TYPE_DROPPED,
+    ),
+    (
+        "canigoin_urls_normalized_total",
+        "Log URLs rewritten to their canonical form by --normalize-urls",
+        &URLS_NORMALIZED,
+    ),
+    (
+        "canigoin_homograph_domains_total",
+        "homograph_domain security events raised for look-alike IDN hosts",
+        &HOMOGRAPH_DOMAINS,
+    ),
+    (
+        "canigoin_rdap_lookups_total",
+        "Domain registration dates looked up over RDAP for --rdap-max-age",
+        &RDAP_LOOKUPS,
+    ),
+    (
+        "canigoin_rdap_failures_total",
+        "RDAP lookups that failed or timed out",
+        &RDAP_FAILURES,
+    ),
+    (
+        "canigoin_newly_registered_domains_total",
+        "newly_registered_domain security events raised for domains younger than --rdap-max-age",
+        &NEWLY_REGISTERED_DOMAINS,
+    ),
+    (
+        "canigoin_ct_lookups_total",
+        "Certificate transparency lookups made for domains flagged by detections (--ct-lookups)",
+        &CT_LOOKUPS,
+    ),
+    (
+        "canigoin_ct_failures_total",
+        "Certificate transparency lookups that failed or timed out",
+        &CT_FAILURES,
+    ),
+    (
+        "canigoin_hosts_resolved_total",
+        "Requested host names resolved for ASN enrichment (--resolve-hosts)",
+        &HOSTS_RESOLVED,
+    ),
+    (
+        "canigoin_host_resolution_failures_total",
+        "Host name lookups for ASN enrichment that failed or timed out",
+        &HOST_RESOLUTION_FAILURES,
+    ),
+    (
+        "canigoin_bad_asn_hosts_total",
+        "bad_asn_host security events raised for hosts in a --bad-asn",
+        &BAD_ASN_HOSTS,
+    ),
+    (
+        "canigoin_event_data_rejected_total",
+        "Events refused for data over --max-event-data-depth or --max-event-data-bytes",
+        &EVENT_DATA_REJECTED,
+    ),
+    (
+        "canigoin_event_data_truncated_total",
+        "Events stored with data cut down to --max-event-data-depth and --max-event-data-bytes",
+        &EVENT_DATA_TRUNCATED,
+    ),
+    (
+        "canigoin_gzip_without_header_total",
+        "Gzip-compressed request bodies sent without Content-Encoding: gzip",
+        &GZIP_WITHOUT_HEADER,
+    ),
+    (
+        "canigoin_decompressed_too_large_total",
+        "Gzip request bodies refused for expanding past the decompressed size limit",
+        &DECOMPRESSED_TOO_LARGE,
+    ),
+    (
+        "canigoin_uploads_stored_total",
+        "Files stored through /api/security/upload",
+        &UPLOADS_STORED,
+    ),
+    (
+        "canigoin_uploads_rejected_total",
+        "Uploads refused for being larger than --max-upload-mb",
+        &UPLOADS_REJECTED,
+    ),
+    (
+        "canigoin_uploads_infected_total",
+        "Uploaded files at least one scanner flagged",
+        &UPLOADS_INFECTED,
+    ),
+    (
+        "canigoin_upload_scan_errors_total",
+        "Upload scans that failed or timed out",
+        &UPLOAD_SCAN_ERRORS,
+    ),
+    (
+        "canigoin_alerts_sent_total",
+        "Notifications delivered by the alert rules",
+        &ALERTS_SENT,
+    ),
+    (
+        "canigoin_alert_delivery_failures_total",
+        "Notifications that could not be delivered",
+        &ALERT_DELIVERY_FAILURES,
+    ),
+    (
+        "canigoin_worm_appended_total",
+        "Security events accepted by an append-only destination",
+        &WORM_APPENDED,
+    ),
+    (
+        "canigoin_worm_failures_total",
+        "Failed attempts to append a security event (each is retried)",
+        &WORM_FAILURES,
+    ),
+    (
+        "canigoin_worm_dropped_total",
+        "Security events not appended because a destination was too far behind",
+        &WORM_DROPPED,
+    ),
+    (
+        "canigoin_lake_objects_written_total",
+        "Ingested payloads copied to the --lake-s3 bucket",
+        &LAKE_OBJECTS_WRITTEN,
+    ),
+    (
+        "canigoin_lake_failures_total",
+        "Failed attempts to write a payload to the --lake-s3 bucket (each is retried)",
+        &LAKE_FAILURES,
+    ),
+    (
+        "canigoin_lake_dropped_total",
+        "Ingested payloads not copied because the --lake-s3 queue was full",
+        &LAKE_DROPPED,
+    ),
+    (
+        "canigoin_detection_rule_matches_total",
+        "Security events raised by detection rules",
+        &DETECTION_RULE_MATCHES,
+    ),
+    (
+        "canigoin_rescan_findings_total",
+        "Security events raised by re-scans of stored logs and events",
+        &RESCAN_FINDINGS,
+    ),
+    (
+        "canigoin_job_runs_total",
+        "Scheduled or manually triggered job runs",
+        &JOB_RUNS,
+    ),
+    (
+        "canigoin_job_failures_total",
+        "Job runs that ended in an error",
+        &JOB_FAILURES,
+    ),
+    (
+        "canigoin_job_skipped_total",
+        "Job runs skipped because the previous run was still going",
+        &JOB_SKIPPED,
+    ),
+    (
+        "canigoin_job_retries_total",
+        "Failed job attempts that were retried after a backoff",
+        &JOB_RETRIES,
+    ),
+    (
+        "canigoin_stats_cache_hits_total",
+        "Stats and summary requests answered from the response cache",
+        &STATS_CACHE_HITS,
+    ),
+    (
+        "canigoin_stats_cache_misses_total",
+        "Stats and summary requests computed because nothing fresh was cached",
+        &STATS_CACHE_MISSES,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_pool_exhausted_total",
+        "Pool samples with every connection checked out",
+        &DB_POOL_EXHAUSTED,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_ping_failures_total",
+        "Failed database health pings",
+        &DB_PING_FAILURES,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_replica_fallbacks_total",
+        "Reads retried on the primary after the read replica failed",
+        &DB_REPLICA_FALLBACKS,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_query_timeouts_total",
+        "Reads answered with 504 after hitting the statement timeout or waiting too long for a connection",
+        &DB_QUERY_TIMEOUTS,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_queries_cancelled_total",
+        "Reads cancelled on the server because the client disconnected",
+        &DB_QUERIES_CANCELLED,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_warm_queue_dropped_total",
+        "Records the hybrid-mode warm tier never got because its queue was full",
+        &WARM_QUEUE_DROPPED,
+    ),
+];
+
+/// Every exported gauge: (name, help, gauge).
+static GAUGES: &[(&str, &str, &Gauge)] = &[
+    (
+        "canigoin_worm_queue_depth",
+        "Security events waiting for an append-only destination",
+        &WORM_QUEUE_DEPTH,
+    ),
+    (
+        "canigoin_lake_queue_depth",
+        "Ingested payloads waiting to be copied to the --lake-s3 bucket",
+        &LAKE_QUEUE_DEPTH,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_up",
+        "1 if the last database health ping succeeded",
+        &DB_UP,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_pool_connections",
+        "Open database connections",
+        &DB_POOL_CONNECTIONS,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_pool_in_use",
+        "Database connections currently checked out",
+        &DB_POOL_IN_USE,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_db_pool_max_connections",
+        "Configured database pool size",
+        &DB_POOL_MAX,
+    ),
+    #[cfg(feature = "production")]
+    (
+        "canigoin_warm_queue_depth",
+        "Records waiting for the hybrid-mode warm tier writer",
+        &WARM_QUEUE_DEPTH,
+    ),
+];
+
+/// Every exported histogram: (name, help, histogram).
+static HISTOGRAMS: &[(&str, &str, &Histogram)] = &[(
+    "canigoin_batch_logs",
+    "Log entries per /api/logs batch",
+    &BATCH_LOGS,
+)];
+
+/// Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for (name, help, counter) in COUNTERS {
+        out.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+            counter.get()
+        ));
+    }
+    for (name, help, gauge) in GAUGES {
+        out.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {}\n",
+            gauge.get()
+        ));
+    }
+    for (name, help, histogram) in HISTOGRAMS {
+        histogram.render(name, help, &mut out);
+    }
+    out
+}
+
+pub async fn get_metrics() -> impl actix_web::Responder {
+    actix_web::HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render())
+}
@@ -0,0 +1,190 @@
+use crate::cli::MigrateArgs;
+use crate::import::{self, Format, ImportReport, PROGRESS_EVERY};
+use crate::production::ProductionState;
+use crate::storage::PoolConfig;
+use crate::types::{Blocklist, ExtensionEvent, LogEntry};
+use std::collections::HashSet;
+use std::io;
+
+/// Everything readable from a simple-mode source.
+struct Snapshot {
+    logs: Vec<LogEntry>,
+    events: Vec<ExtensionEvent>,
+    blocklist: Option<Blocklist>,
+}
+
+pub async fn run(args: &MigrateArgs) -> io::Result<()> {
+    let snapshot = if args.from.starts_with("http://") || args.from.starts_with("https://") {
+        fetch_from_server(args.from.trim_end_matches('/'), args.admin_token.as_deref()).await?
+    } else {
+        read_export(&args.from)?
+    };
+    log::info!(
+        "📦 Source {}: {} log entries, {} events, blocklist={}",
+        args.from,
+        snapshot.logs.len(),
+        snapshot.events.len(),
+        if snapshot.blocklist.is_some() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+
+    let state = ProductionState::new(&args.database_url, None, None, &PoolConfig::default())
+        .await
+        .map_err(|e| io::Error::other(format!("Database connection failed: {}", e)))?;
+    log::info!("✅ Database connected");
+
+    let mut seen = HashSet::new();
+    let (mut imported, mut duplicates) = (0, 0);
+    let total = snapshot.logs.len();
+    for (done, mut entry) in snapshot.logs.into_iter().enumerate() {
+        duplicates += import::dedupe(&mut entry, &mut seen);
+        let candidates = entry.logs.len();
+        let inserted = state.import_log(entry).await.map_err(db_error)?;
+        imported += inserted;
+        duplicates += candidates - inserted;
+        if (done + 1).is_multiple_of(PROGRESS_EVERY) {
+            log::info!("📦 Migrated {}/{} log entries", done + 1, total);
+        }
+    }
+    log::info!(
+        "✅ Logs: {} requests written, {} already present",
+        imported,
+        duplicates
+    );
+
+    if !args.no_events {
+        let (mut written, mut skipped) = (0, 0);
+        for event in snapshot.events {
+            let packet_id = event.data.get("packet_id").and_then(|v| v.as_str());
+            if let Some(id) = packet_id {
+                if state
+                    .get_extension_event_by_packet_id(id)
+                    .await
+                    .map_err(db_error)?
+                    .is_some()
+                {
+                    skipped += 1;
+                    continue;
+                }
+            }
+            state.add_extension_event(event).await.map_err(db_error)?;
+            written += 1;
+        }
+        log::info!(
+            "✅ Events: {} written, {} already present",
+            written,
+            skipped
+        );
+    }
+
+    if !args.no_blocklist {
+        match snapshot.blocklist {
+            Some(b) if !b.url_patterns.is_empty() || !b.youtube_channels.is_empty() => {
+                let (urls, channels) = (b.url_patterns.len(), b.youtube_channels.len());
+                state.update_blocklist(b).await.map_err(db_error)?;
+                log::info!(
+                    "✅ Blocklist: {} URL patterns, {} YouTube channels",
+                    urls,
+                    channels
+                );
+            }
+            _ => log::info!("ℹ️ Blocklist: nothing to migrate"),
+        }
+    }
+
+    log::info!("🎉 Migration from {} complete", args.from);
+    Ok(())
+}
+
+fn db_error(e: sqlx::Error) -> io::Error {
+    io::Error::other(format!("Database error: {}", e))
+}
+
+fn http_error(url: &str, e: reqwest::Error) -> io::Error {
+    io::Error::other(format!("GET {} failed: {}", url, e))
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    url: &str,
+    admin_token: Option<&str>,
+) -> io::Result<T> {
+    let mut request = http.get(url);
+    if let Some(token) = admin_token {
+        request = request.header("x-admin-token", token);
+    }
+    request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| http_error(url, e))?
+        .json()
+        .await
+        .map_err(|e| http_error(url, e))
+}
+
+/// Pulls logs, events and the blocklist out of a running simple-mode server
+/// through its API, with `admin_token` on every request.
+async fn fetch_from_server(base: &str, admin_token: Option<&str>) -> io::Result<Snapshot> {
+    let http = reqwest::Client::new();
+    let logs: Vec<LogEntry> = get_json(&http, &format!("{}/api/logs", base), admin_token).await?;
+    let blocklist: Blocklist =
+        get_json(&http, &format!("{}/api/blocklist", base), admin_token).await?;
+
+    // The dashboard list is summaries only; full events are fetched one by
+    // one. Archived clients are hidden from it unless asked for.
+    let list: serde_json::Value = get_json(
+        &http,
+        &format!("{}/api/dashboard/events?include_archived=true", base),
+        admin_token,
+    )
+    .await?;
+    let ids: Vec<String> = list
+        .get("events")
+        .and_then(|v| v.as_array())
+        .map(|rows| {
+            rows.iter()
+                .filter_map(|r| r.get("packet_id").and_then(|v| v.as_str()))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut events = Vec::with_capacity(ids.len());
+    // Listed newest first; store oldest first like live ingestion would.
+    for id in ids.iter().rev() {
+        let url = format!("{}/api/dashboard/events/{}", base, id);
+        events.push(get_json::<ExtensionEvent>(&http, &url, admin_token).await?);
+    }
+
+    Ok(Snapshot {
+        logs,
+        events,
+        blocklist: Some(blocklist),
+    })
+}
+
+/// Reads a log export (NDJSON, JSON array or CSV, as accepted by
+/// `/api/logs/import`). Exports carry no events or blocklist.
+fn read_export(path: &str) -> io::Result<Snapshot> {
+    let body = std::fs::read_to_string(path)?;
+    let format = Format::detect(None, "", &body).map_err(io::Error::other)?;
+    let mut report = ImportReport::new(format);
+    let logs = import::parse(format, &body, &mut report)
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect();
+    for e in &report.errors {
+        log::warn!("⚠️ {}:{}: {}", path, e.line, e.error);
+    }
+    if report.invalid > 0 {
+        log::warn!("⚠️ Skipped {} invalid records in {}", report.invalid, path);
+    }
+    Ok(Snapshot {
+        logs,
+        events: Vec::new(),
+        blocklist: None,
+    })
+}
@@ -0,0 +1,1019 @@
+use crate::roles::Role;
+use crate::storage::{self, CancelOnDrop, PoolConfig, PoolStatus, Storage};
+use crate::types::{
+    Annotation, ArchivedClient, Blocklist, BlocklistEntries, CaseTicket, CategoryStats,
+    ClientStats, DomainStats, EventComment, EventTriage, ExtensionError, ExtensionEvent, LocalUser,
+    LogEntry, NetworkLog, ObservedDomain, TriageStatus,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::mysql::{MySql, MySqlPool, MySqlRow};
+use sqlx::types::Json;
+use sqlx::{Executor, Row};
+
+/// MySQL / MariaDB backend (schema.mysql.sql). Uses runtime-checked queries:
+/// the compile-time `query!` macros can only be checked against one database.
+pub struct MySqlStorage {
+    pool: MySqlPool,
+}
+
+/// Host part of `network_logs.url`, the MySQL counterpart of `domain_from_url`.
+const URL_DOMAIN: &str = "LOWER(SUBSTRING_INDEX(SUBSTRING_INDEX(SUBSTRING_INDEX(SUBSTRING_INDEX(\
+     SUBSTRING_INDEX(url, '://', -1), '/', 1), '?', 1), '#', 1), ':', 1))";
+
+/// MySQL has no timestamptz; timestamps are stored as UTC DATETIME(6).
+fn parse_timestamp(ts: &str) -> Result<DateTime<Utc>, sqlx::Error> {
+    DateTime::parse_from_rfc3339(ts)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| sqlx::Error::Protocol(format!("invalid timestamp '{}': {}", ts, e)))
+}
+
+fn log_from_row(r: &MySqlRow) -> Result<LogEntry, sqlx::Error> {
+    let timestamp: DateTime<Utc> = r.try_get("timestamp")?;
+    Ok(LogEntry {
+        client_id: r.try_get("client_id")?,
+        profile_id: r.try_get("profile_id")?,
+        session_id: r.try_get("session_id")?,
+        timestamp: timestamp.to_rfc3339(),
+        user_agent: r
+            .try_get::<Option<String>, _>("user_agent")?
+            .unwrap_or_default(),
+        logs: vec![NetworkLog {
+            request_id: r.try_get("request_id")?,
+            url: r.try_get("url")?,
+            method: r.try_get("method")?,
+            request_type: r
+                .try_get::<Option<String>, _>("request_type")?
+                .unwrap_or_else(crate::types::default_request_type),
+            blocked: r.try_get::<Option<bool>, _>("blocked")?.unwrap_or(false),
+            block_reason: r.try_get("block_reason")?,
+            reputation_score: r
+                .try_get::<Option<i16>, _>("reputation_score")?
+                .map(|s| s as u8),
+            request_size: r
+                .try_get::<Option<i64>, _>("request_size")?
+                .map(|s| s as u64),
+            response_size: r
+                .try_get::<Option<i64>, _>("response_size")?
+                .map(|s| s as u64),
+            category: r.try_get("category")?,
+        }],
+        username: None,
+        clock_skew_ms: r.try_get("clock_skew_ms")?,
+    })
+}
+
+fn event_from_row(r: &MySqlRow) -> Result<ExtensionEvent, sqlx::Error> {
+    let timestamp: DateTime<Utc> = r.try_get("timestamp")?;
+    let data: Json<serde_json::Value> = r.try_get("data")?;
+    Ok(ExtensionEvent {
+        client_id: r.try_get("client_id")?,
+        profile_id: r.try_get("profile_id")?,
+        session_id: r.try_get("session_id")?,
+        timestamp: timestamp.to_rfc3339(),
+        user_agent: r
+            .try_get::<Option<String>, _>("user_agent")?
+            .unwrap_or_default(),
+        event_type: r.try_get("event_type")?,
+        data: data.0,
+    })
+}
+
+impl MySqlStorage {
+    pub async fn connect(database_url: &str, pool: &PoolConfig) -> Result<Self, sqlx::Error> {
+        let statement_timeout = pool.statement_timeout;
+        let pool = pool
+            .options::<MySql>()
+            .after_connect(move |conn, _| {
+                Box::pin(async move {
+                    if let Some(t) = statement_timeout {
+                        // MySQL limits SELECTs only (so the stats refresh is
+                        // unaffected); MariaDB names it differently, in
+                        // seconds, and applies it to every statement.
+                        let mysql = format!("SET SESSION max_execution_time = {}", t.as_millis());
+                        if conn.execute(mysql.as_str()).await.is_err() {
+                            let mariadb =
+                                format!("SET SESSION max_statement_time = {}", t.as_secs_f64());
+                            conn.execute(mariadb.as_str()).await?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .connect(database_url)
+            .await?;
+        Ok(MySqlStorage { pool })
+    }
+
+    /// Checks out a connection for a read that is stopped with `KILL QUERY`
+    /// if the request goes away mid-query.
+    async fn cancellable(&self) -> Result<CancelOnDrop<MySql>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        let id: u64 = sqlx::query_scalar("SELECT CONNECTION_ID()")
+            .fetch_one(&mut *conn)
+            .await?;
+        let pool = self.pool.clone();
+        Ok(CancelOnDrop::new(conn, move |conn| {
+            actix_web::rt::spawn(async move {
+                // KILL takes no placeholders; the id is a server-issued integer.
+                if let Err(e) = pool.execute(format!("KILL QUERY {}", id).as_str()).await {
+                    log::warn!("⚠️ Failed to cancel query on connection {}: {}", id, e);
+                }
+                drop(conn);
+            });
+        }))
+    }
+
+    async fn insert_log(
+        &self,
+        entry: &LogEntry,
+        log: &NetworkLog,
+        skip_existing: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let timestamp = parse_timestamp(&entry.timestamp)?;
+        let sql = if skip_existing {
+            r#"
+            INSERT INTO network_logs
+            (client_id, session_id, timestamp, user_agent, request_id, url, method, request_type, blocked, block_reason, reputation_score, request_size, response_size, category, profile_id, clock_skew_ms)
+            SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? FROM DUAL
+            WHERE NOT EXISTS (
+                SELECT 1 FROM network_logs
+                WHERE client_id <=> ? AND session_id = ? AND timestamp = ?
+                  AND request_id = ? AND method = ? AND url = ?
+            )
+            "#
+        } else {
+            r#"
+            INSERT INTO network_logs
+            (client_id, session_id, timestamp, user_agent, request_id, url, method, request_type, blocked, block_reason, reputation_score, request_size, response_size, category, profile_id, clock_skew_ms)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        };
+        let mut query = sqlx::query(sql)
+            .bind(&entry.client_id)
+            .bind(&entry.session_id)
+            .bind(timestamp)
+            .bind(&entry.user_agent)
+            .bind(&log.request_id)
+            .bind(&log.url)
+            .bind(&log.method)
+            .bind(&log.request_type)
+            .bind(log.blocked)
+            .bind(&log.block_reason)
+            .bind(log.reputation_score.map(|s| s as i16))
+            .bind(log.request_size.map(|s| s as i64))
+            .bind(log.response_size.map(|s| s as i64))
+            .bind(&log.category)
+            .bind(&entry.profile_id)
+            .bind(entry.clock_skew_ms);
+        if skip_existing {
+            // Positional parameters can't be reused, so the key is bound again.
+            query = query
+                .bind(&entry.client_id)
+                .bind(&entry.session_id)
+                .bind(timestamp)
+                .bind(&log.request_id)
+                .bind(&log.method)
+                .bind(&log.url);
+        }
+        let result = query.execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_domains(&self, entry: &LogEntry) -> Result<(), sqlx::Error> {
+        for (domain, count) in storage::domain_counts(entry) {
+            sqlx::query(
+                r#"
+                INSERT INTO observed_domains (domain, request_count)
+                VALUES (?, ?)
+                ON DUPLICATE KEY UPDATE
+                    last_seen = CURRENT_TIMESTAMP(6),
+                    request_count = request_count + VALUES(request_count)
+                "#,
+            )
+            .bind(&domain)
+            .bind(count)
+            .execute(&self.pool)
+            .await?;
+
+            if let Some(ref client_id) = entry.client_id {
+                sqlx::query(
+                    "INSERT IGNORE INTO observed_domain_clients (domain, client_id) VALUES (?, ?)",
+                )
+                .bind(&domain)
+                .bind(client_id)
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn patterns(&self, kind: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT pattern FROM blocklist_patterns WHERE type = ? AND active = true",
+        )
+        .bind(kind)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[async_trait]
+impl Storage for MySqlStorage {
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus::from_pool(&self.pool)
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error> {
+        for log in &entry.logs {
+            self.insert_log(&entry, log, false).await?;
+        }
+        self.record_domains(&entry).await
+    }
+
+    async fn import_log(&self, mut entry: LogEntry) -> Result<usize, sqlx::Error> {
+        let mut inserted: Vec<NetworkLog> = Vec::with_capacity(entry.logs.len());
+        for log in std::mem::take(&mut entry.logs) {
+            if self.insert_log(&entry, &log, true).await? {
+                inserted.push(log);
+            }
+        }
+        entry.logs = inserted;
+        self.record_domains(&entry).await?;
+        Ok(entry.logs.len())
+    }
+
+    async fn get_domains(
+        &self,
+        first_seen_after: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT d.domain, d.first_seen, d.last_seen, d.request_count,
+                   (SELECT COUNT(*) FROM observed_domain_clients c WHERE c.domain = d.domain) AS client_count
+            FROM observed_domains d
+            WHERE ? IS NULL OR d.first_seen >= ?
+            ORDER BY d.first_seen DESC
+            "#,
+        )
+        .bind(first_seen_after)
+        .bind(first_seen_after)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter()
+            .map(|r| {
+                Ok(ObservedDomain {
+                    domain: r.try_get("domain")?,
+                    first_seen: r.try_get("first_seen")?,
+                    last_seen: r.try_get("last_seen")?,
+                    request_count: r.try_get::<i64, _>("request_count")? as u64,
+                    client_count: r.try_get::<i64, _>("client_count")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT profile_id, pattern, type FROM profile_blocklist_patterns ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut profiles: std::collections::BTreeMap<String, BlocklistEntries> = Default::default();
+        for r in &rows {
+            let profile = profiles.entry(r.try_get("profile_id")?).or_default();
+            let pattern: String = r.try_get("pattern")?;
+            match r.try_get::<String, _>("type")?.as_str() {
+                "youtube" => profile.youtube_channels.push(pattern),
+                _ => profile.url_patterns.push(pattern),
+            }
+        }
+        Ok(Blocklist {
+            url_patterns: self.patterns("url").await?,
+            youtube_channels: self.patterns("youtube").await?,
+            profiles,
+        })
+    }
+
+    async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE blocklist_patterns SET active = false")
+            .execute(&self.pool)
+            .await?;
+
+        let patterns = blocklist
+            .url_patterns
+            .into_iter()
+            .map(|p| (p, "url"))
+            .chain(
+                blocklist
+                    .youtube_channels
+                    .into_iter()
+                    .map(|c| (c, "youtube")),
+            );
+        for (pattern, kind) in patterns {
+            sqlx::query(
+                "INSERT INTO blocklist_patterns (pattern, type) VALUES (?, ?) ON DUPLICATE KEY UPDATE active = true",
+            )
+            .bind(pattern)
+            .bind(kind)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM profile_blocklist_patterns")
+            .execute(&self.pool)
+            .await?;
+        for (profile_id, profile) in &blocklist.profiles {
+            let entries = profile
+                .url_patterns
+                .iter()
+                .map(|p| (p, "url"))
+                .chain(profile.youtube_channels.iter().map(|c| (c, "youtube")));
+            for (pattern, kind) in entries {
+                sqlx::query(
+                    "INSERT IGNORE INTO profile_blocklist_patterns (profile_id, pattern, type) VALUES (?, ?, ?)",
+                )
+                .bind(profile_id)
+                .bind(pattern)
+                .bind(kind)
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO extension_events
+            (client_id, session_id, timestamp, user_agent, event_type, data, profile_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&event.client_id)
+        .bind(&event.session_id)
+        .bind(parse_timestamp(&event.timestamp)?)
+        .bind(&event.user_agent)
+        .bind(&event.event_type)
+        .bind(Json(&event.data))
+        .bind(&event.profile_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_logs_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT client_id, session_id, timestamp, user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE created_at < ?
+            ORDER BY created_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter().rev().map(log_from_row).collect()
+    }
+
+    async fn get_client_logs(
+        &self,
+        client_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT client_id, session_id, timestamp, user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE client_id = ? AND timestamp >= ? AND timestamp < ?
+            ORDER BY timestamp DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(client_id)
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter().rev().map(log_from_row).collect()
+    }
+
+    async fn export_logs(
+        &self,
+        after_id: i64,
+        as_of: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT id, client_id, session_id, timestamp, user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE id > ? AND created_at < ?
+            ORDER BY id
+            LIMIT ?
+            "#,
+        )
+        .bind(after_id)
+        .bind(as_of)
+        .bind(limit)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter()
+            .map(|r| Ok((r.try_get("id")?, log_from_row(r)?)))
+            .collect()
+    }
+
+    async fn export_extension_events(
+        &self,
+        after_id: i64,
+        as_of: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT id, client_id, profile_id, session_id, timestamp, user_agent, event_type, data
+            FROM extension_events
+            WHERE id > ? AND created_at < ?
+            ORDER BY id
+            LIMIT ?
+            "#,
+        )
+        .bind(after_id)
+        .bind(as_of)
+        .bind(limit)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter()
+            .map(|r| Ok((r.try_get("id")?, event_from_row(r)?)))
+            .collect()
+    }
+
+    async fn get_extension_events_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT client_id, profile_id, session_id, timestamp, user_agent, event_type, data
+            FROM extension_events
+            WHERE created_at < ?
+            ORDER BY created_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        rows.iter().rev().map(event_from_row).collect()
+    }
+
+    async fn get_extension_event_by_packet_id(
+        &self,
+        packet_id: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT client_id, profile_id, session_id, timestamp, user_agent, event_type, data
+            FROM extension_events
+            WHERE packet_id = ?
+            LIMIT 1
+            "#,
+        )
+        .bind(packet_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(event_from_row).transpose()
+    }
+
+    /// MySQL has no materialized views; the summary tables are rebuilt in one
+    /// transaction so readers never see them half-filled.
+    async fn refresh_stats(&self) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM domain_stats")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO domain_stats (domain, requests, blocked, clients, last_seen)
+            SELECT {d} AS domain, COUNT(*), COUNT(CASE WHEN blocked THEN 1 END),
+                   COUNT(DISTINCT client_id), MAX(timestamp)
+            FROM network_logs
+            WHERE url LIKE '%://%'
+            GROUP BY {d}
+            "#,
+            d = URL_DOMAIN
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM client_stats")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO client_stats (client_id, requests, blocked, domains, sessions, last_seen)
+            SELECT client_id, COUNT(*), COUNT(CASE WHEN blocked THEN 1 END), COUNT(DISTINCT {d}),
+                   COUNT(DISTINCT session_id), MAX(timestamp)
+            FROM network_logs
+            WHERE client_id IS NOT NULL
+            GROUP BY client_id
+            "#,
+            d = URL_DOMAIN
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM category_stats")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO category_stats (category, requests, blocked, domains, clients)
+            SELECT category, COUNT(*), COUNT(CASE WHEN blocked THEN 1 END), COUNT(DISTINCT {d}),
+                   COUNT(DISTINCT client_id)
+            FROM network_logs
+            WHERE category IS NOT NULL
+            GROUP BY category
+            "#,
+            d = URL_DOMAIN
+        ))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    async fn get_stats(
+        &self,
+        limit: i64,
+    ) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let conn = q.conn();
+        let rows = async {
+            let domains = sqlx::query(
+                "SELECT domain, requests, blocked, clients, last_seen FROM domain_stats ORDER BY requests DESC, domain LIMIT ?",
+            )
+            .bind(limit)
+            .fetch_all(&mut *conn)
+            .await?;
+            let clients = sqlx::query(
+                "SELECT client_id, requests, blocked, domains, sessions, last_seen FROM client_stats ORDER BY requests DESC, client_id LIMIT ?",
+            )
+            .bind(limit)
+            .fetch_all(&mut *conn)
+            .await?;
+            Ok::<_, sqlx::Error>((domains, clients))
+        }
+        .await;
+        q.finish();
+        let (domains, clients) = rows?;
+
+        let domains = domains
+            .iter()
+            .map(|r| {
+                Ok(DomainStats {
+                    domain: r.try_get("domain")?,
+                    requests: r.try_get::<i64, _>("requests")? as u64,
+                    blocked: r.try_get::<i64, _>("blocked")? as u64,
+                    clients: r.try_get::<i64, _>("clients")? as u64,
+                    last_seen: r.try_get("last_seen")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+        let clients = clients
+            .iter()
+            .map(|r| {
+                Ok(ClientStats {
+                    client_id: r.try_get("client_id")?,
+                    requests: r.try_get::<i64, _>("requests")? as u64,
+                    blocked: r.try_get::<i64, _>("blocked")? as u64,
+                    domains: r.try_get::<i64, _>("domains")? as u64,
+                    sessions: r.try_get::<i64, _>("sessions")? as u64,
+                    last_seen: r.try_get("last_seen")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+        Ok((domains, clients))
+    }
+
+    async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query(
+            "SELECT category, requests, blocked, domains, clients FROM category_stats ORDER BY requests DESC, category",
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        rows?
+            .iter()
+            .map(|r| {
+                Ok(CategoryStats {
+                    category: r.try_get("category")?,
+                    requests: r.try_get::<i64, _>("requests")? as u64,
+                    blocked: r.try_get::<i64, _>("blocked")? as u64,
+                    domains: r.try_get::<i64, _>("domains")? as u64,
+                    clients: r.try_get::<i64, _>("clients")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error> {
+        sqlx::query("SELECT client_id, archived_at, archived_by, reason FROM archived_clients")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|r| {
+                Ok(ArchivedClient {
+                    client_id: r.try_get("client_id")?,
+                    archived_at: r.try_get("archived_at")?,
+                    archived_by: r.try_get("archived_by")?,
+                    reason: r.try_get("reason")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn archive_client(&self, client: &ArchivedClient) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO archived_clients (client_id, archived_at, archived_by, reason)
+            VALUES (?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                archived_at = VALUES(archived_at),
+                archived_by = VALUES(archived_by),
+                reason = VALUES(reason)
+            "#,
+        )
+        .bind(&client.client_id)
+        .bind(client.archived_at)
+        .bind(&client.archived_by)
+        .bind(&client.reason)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unarchive_client(&self, client_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM archived_clients WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error> {
+        sqlx::query(
+            "SELECT id, scope, target, label, note, created_by, created_at FROM annotations ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            Ok(Annotation {
+                id: r.try_get("id")?,
+                scope: r.try_get("scope")?,
+                target: r.try_get("target")?,
+                label: r.try_get("label")?,
+                note: r.try_get("note")?,
+                created_by: r.try_get("created_by")?,
+                created_at: r.try_get("created_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn add_annotation(&self, annotation: &Annotation) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO annotations (scope, target, label, note, created_by, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&annotation.scope)
+        .bind(&annotation.target)
+        .bind(&annotation.label)
+        .bind(&annotation.note)
+        .bind(&annotation.created_by)
+        .bind(annotation.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_id() as i64)
+    }
+
+    async fn delete_annotation(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM annotations WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error> {
+        sqlx::query(
+            "SELECT packet_id, status, note, assignee, updated_by, updated_at FROM event_triage",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            let status: String = r.try_get("status")?;
+            Ok(EventTriage {
+                packet_id: r.try_get("packet_id")?,
+                status: TriageStatus::parse(&status).unwrap_or_default(),
+                note: r.try_get("note")?,
+                assignee: r.try_get("assignee")?,
+                updated_by: r.try_get("updated_by")?,
+                updated_at: r.try_get("updated_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn set_event_triage(&self, triage: &EventTriage) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO event_triage (packet_id, status, note, assignee, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                status = VALUES(status),
+                note = VALUES(note),
+                assignee = VALUES(assignee),
+                updated_by = VALUES(updated_by),
+                updated_at = VALUES(updated_at)
+            "#,
+        )
+        .bind(&triage.packet_id)
+        .bind(triage.status.as_str())
+        .bind(&triage.note)
+        .bind(&triage.assignee)
+        .bind(&triage.updated_by)
+        .bind(triage.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error> {
+        sqlx::query(
+            "SELECT id, packet_id, parent_id, author, body, created_at FROM event_comments ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            Ok(EventComment {
+                id: r.try_get("id")?,
+                packet_id: r.try_get("packet_id")?,
+                parent_id: r.try_get("parent_id")?,
+                author: r.try_get("author")?,
+                body: r.try_get("body")?,
+                created_at: r.try_get("created_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn add_event_comment(&self, comment: &EventComment) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO event_comments (packet_id, parent_id, author, body, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&comment.packet_id)
+        .bind(comment.parent_id)
+        .bind(&comment.author)
+        .bind(&comment.body)
+        .bind(comment.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_id() as i64)
+    }
+
+    async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error> {
+        sqlx::query(
+            r#"
+            SELECT packet_id, provider, ticket_key, url, closed, created_at, closed_at, synced_at
+            FROM case_tickets
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            Ok(CaseTicket {
+                packet_id: r.try_get("packet_id")?,
+                provider: r.try_get("provider")?,
+                key: r.try_get("ticket_key")?,
+                url: r.try_get("url")?,
+                closed: r.try_get("closed")?,
+                created_at: r.try_get("created_at")?,
+                closed_at: r.try_get("closed_at")?,
+                synced_at: r.try_get("synced_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn set_case_ticket(&self, ticket: &CaseTicket) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO case_tickets
+                (packet_id, provider, ticket_key, url, closed, created_at, closed_at, synced_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                provider = VALUES(provider),
+                ticket_key = VALUES(ticket_key),
+                url = VALUES(url),
+                closed = VALUES(closed),
+                created_at = VALUES(created_at),
+                closed_at = VALUES(closed_at),
+                synced_at = VALUES(synced_at)
+            "#,
+        )
+        .bind(&ticket.packet_id)
+        .bind(&ticket.provider)
+        .bind(&ticket.key)
+        .bind(&ticket.url)
+        .bind(ticket.closed)
+        .bind(ticket.created_at)
+        .bind(ticket.closed_at)
+        .bind(ticket.synced_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error> {
+        sqlx::query(
+            r#"
+            SELECT hash, message, stack, source, extension_version, last_client_id,
+                   count, first_seen, last_seen
+            FROM extension_errors
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            Ok(ExtensionError {
+                hash: r.try_get("hash")?,
+                message: r.try_get("message")?,
+                stack: r.try_get("stack")?,
+                source: r.try_get("source")?,
+                extension_version: r.try_get("extension_version")?,
+                last_client_id: r.try_get("last_client_id")?,
+                count: r.try_get("count")?,
+                first_seen: r.try_get("first_seen")?,
+                last_seen: r.try_get("last_seen")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn record_extension_error(&self, error: &ExtensionError) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO extension_errors
+                (hash, message, stack, source, extension_version, last_client_id,
+                 count, first_seen, last_seen)
+            VALUES (?, ?, ?, ?, ?, ?, 1, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                message = VALUES(message),
+                extension_version = VALUES(extension_version),
+                last_client_id = VALUES(last_client_id),
+                count = count + 1,
+                last_seen = GREATEST(last_seen, VALUES(last_seen))
+            "#,
+        )
+        .bind(&error.hash)
+        .bind(&error.message)
+        .bind(&error.stack)
+        .bind(&error.source)
+        .bind(&error.extension_version)
+        .bind(&error.last_client_id)
+        .bind(error.first_seen)
+        .bind(error.last_seen)
+        .execute(&mut *tx)
+        .await?;
+        let count: i64 = sqlx::query_scalar("SELECT count FROM extension_errors WHERE hash = ?")
+            .bind(&error.hash)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(count)
+    }
+
+    async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error> {
+        sqlx::query(
+            r#"
+            SELECT username, display_name, role, active, password_hash, token_hash,
+                   created_at, updated_at
+            FROM local_users
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|r| {
+            let role: String = r.try_get("role")?;
+            Ok(LocalUser {
+                username: r.try_get("username")?,
+                display_name: r.try_get("display_name")?,
+                role: Role::parse(&role).unwrap_or(Role::Viewer),
+                active: r.try_get("active")?,
+                password_hash: r.try_get("password_hash")?,
+                token_hash: r.try_get("token_hash")?,
+                created_at: r.try_get("created_at")?,
+                updated_at: r.try_get("updated_at")?,
+            })
+        })
+        .collect()
+    }
+
+    async fn put_local_user(&self, user: &LocalUser) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO local_users (username, display_name, role, active, password_hash,
+                                     token_hash, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                display_name = VALUES(display_name),
+                role = VALUES(role),
+                active = VALUES(active),
+                password_hash = VALUES(password_hash),
+                token_hash = VALUES(token_hash),
+                updated_at = VALUES(updated_at)
+            "#,
+        )
+        .bind(&user.username)
+        .bind(&user.display_name)
+        .bind(user.role.name())
+        .bind(user.active)
+        .bind(&user.password_hash)
+        .bind(&user.token_hash)
+        .bind(user.created_at)
+        .bind(user.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_local_user(&self, username: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM local_users WHERE username = ?")
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
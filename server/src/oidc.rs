@@ -0,0 +1,510 @@
+//! OpenID Connect single sign-on (`--oidc-config`), so an enterprise's
+//! identity provider decides who may use the dashboard and the admin API,
+//! and with which [`Role`].
+//!
+//! The dashboard logs in with the authorization code flow (with PKCE):
+//! `/api/auth/oidc/login` sends the browser to the provider, which sends it
+//! back to `/api/auth/oidc/callback`; the ID token got for the code starts
+//! a dashboard session like an admin token login does. Scripts can instead
+//! send an ID token (or a JWT access token issued to the same client) as
+//! `Authorization: Bearer`. Either way the token's signature is checked
+//! against the provider's published keys (RS256 only), as are its issuer,
+//! audience and expiry, and the groups in `groups_claim` pick the role:
+//! the highest one any group maps to in `roles`, else `default_role`, else
+//! none and the user is refused.
+//!
+//! Discovery and the keys are fetched at startup and every 15 minutes, and
+//! again when a token names a key not seen yet.
+
+use crate::error::ApiError;
+use crate::roles::Role;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Where the provider sends the browser back to.
+pub const CALLBACK_PATH: &str = "/api/auth/oidc/callback";
+/// Ties a callback to the browser that started the login.
+pub const STATE_COOKIE: &str = "canigoin_oidc_state";
+/// How long a user has to finish logging in at the provider.
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Logins started but not yet finished; starting one needs no credentials,
+/// so past this the oldest is forgotten to make room.
+pub const MAX_PENDING_LOGINS: usize = 10_000;
+const KEYS_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Unknown keys trigger a refresh at most this often.
+const MIN_REFRESH_GAP: Duration = Duration::from_secs(60);
+/// Clock difference tolerated on `exp` / `nbf`.
+const LEEWAY_SECS: i64 = 60;
+
+fn default_scopes() -> Vec<String> {
+    ["openid", "profile", "email"].map(String::from).to_vec()
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+/// The `--oidc-config` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// The provider's `.well-known/openid-configuration` URL.
+    pub discovery_url: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// Defaults to [`CALLBACK_PATH`] on `--external-url`, or on the address
+    /// the browser reached the server on.
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// The ID token claim listing the user's groups.
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// Role of each IdP group.
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    /// Role of users in none of `roles`' groups; without it they are refused.
+    #[serde(default)]
+    pub default_role: Option<Role>,
+}
+
+impl OidcConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: OidcConfig =
+            serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.discovery_url.starts_with("https://") && !self.discovery_url.starts_with("http://")
+        {
+            return Err(format!(
+                "discovery_url '{}' isn't an http(s) URL",
+                self.discovery_url
+            ));
+        }
+        if self.client_id.is_empty() {
+            return Err("client_id is empty".to_string());
+        }
+        if !self.scopes.iter().any(|s| s == "openid") {
+            return Err("scopes must include \"openid\"".to_string());
+        }
+        if self.roles.is_empty() && self.default_role.is_none() {
+            return Err("no roles and no default_role: nobody could log in".to_string());
+        }
+        Ok(())
+    }
+
+    /// The role of a user in `groups`.
+    pub fn role_for<'a>(&self, groups: impl IntoIterator<Item = &'a str>) -> Option<Role> {
+        groups
+            .into_iter()
+            .filter_map(|g| self.roles.get(g).copied())
+            .max()
+            .or(self.default_role)
+    }
+}
+
+/// A signed-in single sign-on user.
+#[derive(Debug, Clone)]
+pub struct OidcUser {
+    /// `preferred_username`, `email` or `sub`, whichever the token has first.
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+struct RsaKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+struct Provider {
+    discovery: Discovery,
+    /// By `kid`; a key published without one is under `""`.
+    keys: HashMap<String, RsaKey>,
+}
+
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    redirect_uri: String,
+    return_to: String,
+    started: Instant,
+}
+
+pub struct Oidc {
+    config: OidcConfig,
+    http: reqwest::Client,
+    provider: RwLock<Option<Arc<Provider>>>,
+    last_refresh: Mutex<Option<Instant>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Oidc {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            provider: RwLock::new(None),
+            last_refresh: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let resp = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("{}: {}", url, e))?;
+        resp.json().await.map_err(|e| format!("{}: {}", url, e))
+    }
+
+    /// Reads the discovery document and the signing keys again.
+    pub async fn refresh(&self) -> Result<(), String> {
+        *self.last_refresh.lock().unwrap() = Some(Instant::now());
+        let discovery: Discovery = self.get_json(&self.config.discovery_url).await?;
+        let jwks: Value = self.get_json(&discovery.jwks_uri).await?;
+        let keys: HashMap<String, RsaKey> = jwks["keys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|k| k["kty"] == "RSA" && k["use"] != "enc")
+            .filter_map(|k| {
+                let n = URL_SAFE_NO_PAD.decode(k["n"].as_str()?).ok()?;
+                let e = URL_SAFE_NO_PAD.decode(k["e"].as_str()?).ok()?;
+                let kid = k["kid"].as_str().unwrap_or_default().to_string();
+                Some((kid, RsaKey { n, e }))
+            })
+            .collect();
+        if keys.is_empty() {
+            return Err(format!("{}: no RSA signing keys", discovery.jwks_uri));
+        }
+        log::info!(
+            "🪪 OIDC provider {}: {} signing key(s)",
+            discovery.issuer,
+            keys.len()
+        );
+        *self.provider.write().unwrap() = Some(Arc::new(Provider { discovery, keys }));
+        Ok(())
+    }
+
+    /// Refreshes in the background, unless that was just tried.
+    fn request_refresh(self: &Arc<Self>) {
+        {
+            let mut last = self.last_refresh.lock().unwrap();
+            if last.is_some_and(|t| t.elapsed() < MIN_REFRESH_GAP) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        let oidc = self.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = oidc.refresh().await {
+                log::warn!("⚠️ OIDC refresh failed: {}", e);
+            }
+        });
+    }
+
+    /// Keeps discovery and the keys current.
+    pub fn spawn_refresh(self: &Arc<Self>) {
+        let oidc = self.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::sleep(KEYS_REFRESH_INTERVAL).await;
+                if let Err(e) = oidc.refresh().await {
+                    log::warn!("⚠️ OIDC refresh failed: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn provider(&self) -> Result<Arc<Provider>, String> {
+        if let Some(provider) = self.provider.read().unwrap().clone() {
+            return Ok(provider);
+        }
+        self.refresh().await?;
+        Ok(self
+            .provider
+            .read()
+            .unwrap()
+            .clone()
+            .expect("just refreshed"))
+    }
+
+    /// Starts a login: the provider's authorization URL to send the browser
+    /// to, and the `state` to keep in its [`STATE_COOKIE`].
+    pub async fn begin(
+        &self,
+        redirect_uri: String,
+        return_to: String,
+    ) -> Result<(String, String), ApiError> {
+        let provider = self.provider().await.map_err(provider_error)?;
+        let state = crate::auth::random_token();
+        let nonce = crate::auth::random_token();
+        let verifier = crate::auth::random_token();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let scope = self.config.scopes.join(" ");
+        let url = reqwest::Url::parse_with_params(
+            &provider.discovery.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", redirect_uri.as_str()),
+                ("scope", scope.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| provider_error(format!("authorization_endpoint: {}", e)))?;
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.started.elapsed() < LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            if let Some(oldest) = pending
+                .iter()
+                .min_by_key(|(_, p)| p.started)
+                .map(|(state, _)| state.clone())
+            {
+                pending.remove(&oldest);
+            }
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                verifier,
+                redirect_uri,
+                return_to,
+                started: Instant::now(),
+            },
+        );
+        Ok((url.to_string(), state))
+    }
+
+    /// Redeems the code the provider sent back with `state`: the user and
+    /// where to send them.
+    pub async fn finish(&self, state: &str, code: &str) -> Result<(OidcUser, String), ApiError> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|p| p.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or_else(|| login_error("unknown or expired login, start again"))?;
+        let provider = self.provider().await.map_err(provider_error)?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", pending.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", pending.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let resp = self
+            .http
+            .post(&provider.discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| provider_error(format!("token endpoint: {}", e)))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body: Value = resp.json().await.unwrap_or_default();
+            let reason = body["error_description"]
+                .as_str()
+                .or(body["error"].as_str());
+            return Err(login_error(&format!(
+                "the provider refused the code ({}{})",
+                status,
+                reason.map(|r| format!(": {}", r)).unwrap_or_default()
+            )));
+        }
+        let tokens: Value = resp
+            .json()
+            .await
+            .map_err(|e| provider_error(format!("token endpoint: {}", e)))?;
+        let id_token = tokens["id_token"]
+            .as_str()
+            .ok_or_else(|| login_error("the provider returned no id_token"))?;
+        let claims = verify(
+            &provider,
+            &self.config.client_id,
+            id_token,
+            Some(&pending.nonce),
+        )
+        .map_err(|e| login_error(&e))?;
+        Ok((self.user(&claims)?, pending.return_to))
+    }
+
+    /// Checks a bearer token against the keys at hand; a token signed with
+    /// a key not seen yet fails, and has the keys fetched again for next time.
+    pub fn verify_bearer(self: &Arc<Self>, token: &str) -> Result<OidcUser, ApiError> {
+        let Some(provider) = self.provider.read().unwrap().clone() else {
+            self.request_refresh();
+            return Err(login_error("the identity provider hasn't been reached yet"));
+        };
+        match verify(&provider, &self.config.client_id, token, None) {
+            Ok(claims) => self.user(&claims),
+            Err(e) => {
+                if e == UNKNOWN_KEY {
+                    self.request_refresh();
+                }
+                Err(login_error(&e))
+            }
+        }
+    }
+
+    fn user(&self, claims: &Value) -> Result<OidcUser, ApiError> {
+        let name = ["preferred_username", "email", "sub"]
+            .iter()
+            .find_map(|c| claims[*c].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let groups: Vec<&str> = match &claims[&self.config.groups_claim] {
+            Value::Array(groups) => groups.iter().filter_map(Value::as_str).collect(),
+            Value::String(group) => vec![group.as_str()],
+            _ => Vec::new(),
+        };
+        match self.config.role_for(groups) {
+            Some(role) => Ok(OidcUser { name, role }),
+            None => {
+                log::warn!("🪪 OIDC user {} has no group with a role", name);
+                Err(ApiError::Forbidden(format!(
+                    "{} is in no group with a role on this server",
+                    name
+                )))
+            }
+        }
+    }
+}
+
+const UNKNOWN_KEY: &str = "signed with an unknown key";
+
+fn provider_error(e: String) -> ApiError {
+    log::error!("❌ OIDC provider: {}", e);
+    ApiError::Unauthorized.with("reason", format!("identity provider unavailable: {}", e))
+}
+
+fn login_error(reason: &str) -> ApiError {
+    ApiError::Unauthorized.with("reason", reason)
+}
+
+fn decode_json(part: &str) -> Result<Value, String> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .map_err(|_| "malformed token".to_string())?;
+    serde_json::from_slice(&bytes).map_err(|_| "malformed token".to_string())
+}
+
+/// The claims of `token` once its signature, issuer, audience, lifetime and
+/// (for logins) nonce check out.
+fn verify(
+    provider: &Provider,
+    client_id: &str,
+    token: &str,
+    nonce: Option<&str>,
+) -> Result<Value, String> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [header, payload, signature] = parts.as_slice() else {
+        return Err("malformed token".to_string());
+    };
+    let header_claims = decode_json(header)?;
+    if header_claims["alg"] != "RS256" {
+        return Err(format!(
+            "unsupported signature algorithm {}",
+            header_claims["alg"]
+        ));
+    }
+    let kid = header_claims["kid"].as_str().unwrap_or_default();
+    let key = provider
+        .keys
+        .get(kid)
+        .or_else(
+            || match provider.keys.values().collect::<Vec<_>>().as_slice() {
+                [only] if kid.is_empty() => Some(*only),
+                _ => None,
+            },
+        )
+        .ok_or_else(|| UNKNOWN_KEY.to_string())?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| "malformed token".to_string())?;
+    // `<header>.<payload>`, as sent.
+    let signed = &token[..header.len() + payload.len() + 1];
+    ring::signature::RsaPublicKeyComponents {
+        n: &key.n,
+        e: &key.e,
+    }
+    .verify(
+        &ring::signature::RSA_PKCS1_2048_8192_SHA256,
+        signed.as_bytes(),
+        &signature,
+    )
+    .map_err(|_| "bad signature".to_string())?;
+
+    let claims = decode_json(payload)?;
+    if claims["iss"] != provider.discovery.issuer.as_str() {
+        return Err(format!("issued by {}", claims["iss"]));
+    }
+    let audience_ok = match &claims["aud"] {
+        Value::String(aud) => aud == client_id,
+        Value::Array(auds) => auds.iter().any(|a| a == client_id),
+        _ => false,
+    };
+    if !audience_ok {
+        return Err("issued for another client".to_string());
+    }
+    let now = Utc::now().timestamp();
+    match claims["exp"].as_i64() {
+        Some(exp) if exp + LEEWAY_SECS > now => {}
+        Some(_) => return Err("expired".to_string()),
+        None => return Err("no expiry".to_string()),
+    }
+    if claims["nbf"]
+        .as_i64()
+        .is_some_and(|nbf| nbf - LEEWAY_SECS > now)
+    {
+        return Err("not valid yet".to_string());
+    }
+    if let Some(nonce) = nonce {
+        if claims["nonce"] != nonce {
+            return Err("nonce doesn't match the login".to_string());
+        }
+    }
+    Ok(claims)
+}
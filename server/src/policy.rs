@@ -0,0 +1,294 @@
+//! Policy resolution: what one client (and browser profile) is served by
+//! `GET /api/blocklist` and held to by the quotas, worked out from every
+//! layer that can contribute, and why.
+//!
+//! Layers are applied in this order, most general first:
+//!
+//! 1. the shared (global) blocklist;
+//! 2. the browser profile's own entries (`?profile_id=`);
+//! 3. the client's variant in each running experiment;
+//! 4. each of the client's device groups: its blocklist, then its schedules
+//!    whose window covers the current time;
+//! 5. the screen-time categories the client has used up today;
+//! 6. the focus list while a focus session runs.
+//!
+//! Blocking only ever adds up: an entry from any active layer is blocked,
+//! and no layer takes out what another one blocks. An entry listed by more
+//! than one layer is credited to the first. Quotas are the one setting
+//! layers override: a limit set by one of the client's groups replaces the
+//! server-wide one, and among several groups the strictest wins (0, for
+//! unlimited, only when no group sets a limit).
+
+use crate::experiments::Experiments;
+use crate::focus::FocusSessions;
+use crate::groups::{GroupQuotas, Groups, Schedule};
+use crate::quotas::QuotaLimits;
+use crate::screen_time::ScreenTime;
+use crate::types::{Blocklist, BlocklistEntries};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerKind {
+    Global,
+    Profile,
+    Experiment,
+    Group,
+    Schedule,
+    ScreenTime,
+    Focus,
+}
+
+/// One contribution to the policy, active or not.
+#[derive(Debug, Clone, Serialize)]
+pub struct Layer {
+    pub layer: LayerKind,
+    /// The profile, `experiment/variant`, group, `group#index` schedule or
+    /// screen-time category.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub active: bool,
+    /// Why the layer does or doesn't apply right now.
+    pub reason: String,
+    #[serde(rename = "urlPatterns")]
+    pub url_patterns: Vec<String>,
+    #[serde(rename = "youtubeChannels")]
+    pub youtube_channels: Vec<String>,
+    /// Entries this layer contributed that no earlier layer had.
+    pub added: usize,
+}
+
+/// Where one quota limit comes from: `global` or `group:<name>`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaSources {
+    pub logs_per_day: String,
+    pub events_per_day: String,
+    pub bytes_per_day: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedQuotas {
+    pub limits: QuotaLimits,
+    pub sources: QuotaSources,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Policy {
+    pub client_id: Option<String>,
+    pub profile_id: Option<String>,
+    pub resolved_at: DateTime<Utc>,
+    pub groups: Vec<String>,
+    /// Exactly what `GET /api/blocklist` returns for this client and profile.
+    pub blocklist: Blocklist,
+    pub layers: Vec<Layer>,
+}
+
+/// `GET /api/clients/{id}/effective-policy`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectivePolicy {
+    #[serde(flatten)]
+    pub policy: Policy,
+    pub quotas: ResolvedQuotas,
+}
+
+/// The per-client policy sources, besides the stored blocklist.
+pub struct Sources<'a> {
+    pub groups: &'a Groups,
+    pub screen_time: &'a ScreenTime,
+    pub focus: &'a FocusSessions,
+    pub experiments: Option<&'a Experiments>,
+}
+
+fn layer(
+    kind: LayerKind,
+    name: Option<&str>,
+    active: bool,
+    reason: String,
+    entries: BlocklistEntries,
+) -> Layer {
+    Layer {
+        layer: kind,
+        name: name.map(str::to_string),
+        active,
+        reason,
+        url_patterns: entries.url_patterns,
+        youtube_channels: entries.youtube_channels,
+        added: 0,
+    }
+}
+
+fn patterns(url_patterns: Vec<String>) -> BlocklistEntries {
+    BlocklistEntries {
+        url_patterns,
+        youtube_channels: Vec::new(),
+    }
+}
+
+fn window(schedule: &Schedule) -> String {
+    let days = if schedule.days.is_empty() {
+        "every day".to_string()
+    } else {
+        schedule.days.join(",")
+    };
+    format!("{}-{} UTC, {}", schedule.from, schedule.to, days)
+}
+
+/// Resolves the policy for `client_id` / `profile_id` at `now`. Without a
+/// profile the stored `profiles` map is served as is; without a client only
+/// the global and profile layers apply.
+pub fn resolve(
+    mut blocklist: Blocklist,
+    client_id: Option<&str>,
+    profile_id: Option<&str>,
+    sources: &Sources,
+    now: DateTime<Utc>,
+) -> Policy {
+    let mut layers = vec![layer(
+        LayerKind::Global,
+        None,
+        true,
+        "shared blocklist".into(),
+        BlocklistEntries {
+            url_patterns: std::mem::take(&mut blocklist.url_patterns),
+            youtube_channels: std::mem::take(&mut blocklist.youtube_channels),
+        },
+    )];
+
+    if let Some(profile_id) = profile_id {
+        let entries = blocklist.profiles.remove(profile_id);
+        blocklist.profiles.clear();
+        let reason = match &entries {
+            Some(_) => format!("profile_id={}", profile_id),
+            None => format!("no entries for profile {}", profile_id),
+        };
+        layers.push(layer(
+            LayerKind::Profile,
+            Some(profile_id),
+            entries.is_some(),
+            reason,
+            entries.unwrap_or_default(),
+        ));
+    }
+
+    let mut groups = Vec::new();
+    if let Some(client_id) = client_id {
+        for a in sources
+            .experiments
+            .map(|e| e.assignments(client_id))
+            .unwrap_or_default()
+        {
+            layers.push(layer(
+                LayerKind::Experiment,
+                Some(&format!("{}/{}", a.experiment, a.variant)),
+                true,
+                format!("{} is in variant {}", client_id, a.variant),
+                a.blocklist,
+            ));
+        }
+
+        let config = sources.groups.config();
+        let members: Vec<_> = config
+            .groups
+            .iter()
+            .filter(|(_, g)| g.clients.iter().any(|c| c == client_id))
+            .collect();
+        for (name, group) in &members {
+            groups.push(name.to_string());
+            layers.push(layer(
+                LayerKind::Group,
+                Some(name),
+                true,
+                format!("{} is a member", client_id),
+                group.blocklist.clone(),
+            ));
+            for (i, schedule) in group.schedules.iter().enumerate() {
+                let active = schedule.is_active(now);
+                let reason = format!(
+                    "{} window {}",
+                    if active { "inside" } else { "outside" },
+                    window(schedule)
+                );
+                layers.push(layer(
+                    LayerKind::Schedule,
+                    Some(&format!("{}#{}", name, i)),
+                    active,
+                    reason,
+                    schedule.blocklist.clone(),
+                ));
+            }
+        }
+
+        for usage in sources.screen_time.usage(client_id) {
+            let reason = format!("{}s of {}s used today", usage.used_secs, usage.budget_secs);
+            layers.push(layer(
+                LayerKind::ScreenTime,
+                Some(&usage.category),
+                usage.exhausted,
+                reason,
+                patterns(sources.screen_time.patterns_for(&usage.category)),
+            ));
+        }
+
+        let session = sources.focus.active(client_id);
+        let reason = match &session {
+            Some(s) => format!("focus session until {}", s.ends_at.to_rfc3339()),
+            None => "no focus session".into(),
+        };
+        let domains = sources.focus.domains().iter();
+        layers.push(layer(
+            LayerKind::Focus,
+            None,
+            session.is_some(),
+            reason,
+            patterns(domains.map(|d| Blocklist::domain_pattern(d)).collect()),
+        ));
+    }
+
+    // What's left of `blocklist` is the profiles map, served when no
+    // profile was asked for.
+    let len = |b: &Blocklist| b.url_patterns.len() + b.youtube_channels.len();
+    for l in layers.iter_mut().filter(|l| l.active) {
+        let before = len(&blocklist);
+        blocklist.add(BlocklistEntries {
+            url_patterns: l.url_patterns.clone(),
+            youtube_channels: l.youtube_channels.clone(),
+        });
+        l.added = len(&blocklist) - before;
+    }
+
+    Policy {
+        client_id: client_id.map(str::to_string),
+        profile_id: profile_id.map(str::to_string),
+        resolved_at: now,
+        groups,
+        blocklist,
+        layers,
+    }
+}
+
+/// The limits `client_id` is held to, given the server-wide `global` ones,
+/// and which layer set each.
+pub fn resolve_quotas(groups: &Groups, client_id: &str, global: QuotaLimits) -> ResolvedQuotas {
+    let limits = groups.quota_limits(client_id, global);
+    let config = groups.config();
+    let members: Vec<_> = config
+        .groups
+        .iter()
+        .filter(|(_, g)| g.clients.iter().any(|c| c == client_id))
+        .collect();
+    let source = |limit: u64, field: fn(&GroupQuotas) -> Option<u64>| {
+        members
+            .iter()
+            .find(|(_, g)| field(&g.quotas) == Some(limit))
+            .map_or("global".to_string(), |(name, _)| format!("group:{}", name))
+    };
+    ResolvedQuotas {
+        sources: QuotaSources {
+            logs_per_day: source(limits.logs_per_day, |q| q.logs_per_day),
+            events_per_day: source(limits.events_per_day, |q| q.events_per_day),
+            bytes_per_day: source(limits.bytes_per_day, |q| q.bytes_per_day),
+        },
+        limits,
+    }
+}
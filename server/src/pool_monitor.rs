@@ -0,0 +1,134 @@
+use crate::metrics;
+use crate::production::ProductionState;
+use crate::server_events;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the pool is sampled and pinged while the database is healthy.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Pings back off up to this interval while the database is unreachable.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Tracks connection pool occupancy and database reachability for `/health`
+/// and `/metrics`. The pool reconnects on its own; this only observes it.
+pub struct PoolMonitor {
+    state: Arc<ProductionState>,
+    reachable: AtomicBool,
+    exhausted: AtomicBool,
+    last_error: Mutex<Option<String>>,
+    down_since: Mutex<Option<std::time::Instant>>,
+}
+
+impl PoolMonitor {
+    pub fn spawn(state: Arc<ProductionState>) -> Arc<PoolMonitor> {
+        metrics::DB_UP.set(1);
+        let monitor = Arc::new(PoolMonitor {
+            state,
+            reachable: AtomicBool::new(true),
+            exhausted: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+            down_since: Mutex::new(None),
+        });
+        let m = monitor.clone();
+        actix_web::rt::spawn(async move {
+            let mut delay = SAMPLE_INTERVAL;
+            loop {
+                tokio::time::sleep(delay).await;
+                delay = if m.check().await {
+                    SAMPLE_INTERVAL
+                } else {
+                    (delay * 2).min(MAX_BACKOFF)
+                };
+            }
+        });
+        monitor
+    }
+
+    /// One sample + ping. Returns false while the database is unreachable.
+    async fn check(&self) -> bool {
+        let status = self.state.pool_status();
+        metrics::DB_POOL_CONNECTIONS.set(status.size as i64);
+        metrics::DB_POOL_IN_USE.set(status.in_use() as i64);
+        metrics::DB_POOL_MAX.set(status.max as i64);
+
+        let exhausted = status.is_exhausted();
+        if exhausted {
+            metrics::DB_POOL_EXHAUSTED.inc();
+        }
+        if exhausted != self.exhausted.swap(exhausted, Ordering::Relaxed) {
+            if exhausted {
+                log::warn!(
+                    "🚰 Database pool exhausted: {}/{} connections in use; queries are queueing",
+                    status.in_use(),
+                    status.max
+                );
+            } else {
+                log::info!("✅ Database pool has free connections again");
+            }
+        }
+
+        match self.state.ping().await {
+            Ok(()) => {
+                if !self.reachable.swap(true, Ordering::Relaxed) {
+                    log::info!("✅ Database reachable again");
+                    let down = self.down_since.lock().unwrap().take();
+                    server_events::record(
+                        "database_reconnected",
+                        serde_json::json!({ "outage_secs": down.map(|d| d.elapsed().as_secs()) }),
+                    );
+                }
+                metrics::DB_UP.set(1);
+                *self.last_error.lock().unwrap() = None;
+                true
+            }
+            // A saturated pool times out the ping without the database being down.
+            Err(sqlx::Error::PoolTimedOut) => true,
+            Err(e) => {
+                metrics::DB_PING_FAILURES.inc();
+                metrics::DB_UP.set(0);
+                if self.reachable.swap(false, Ordering::Relaxed) {
+                    log::error!("❌ Database unreachable: {}", e);
+                    *self.down_since.lock().unwrap() = Some(std::time::Instant::now());
+                    server_events::record(
+                        "database_unreachable",
+                        serde_json::json!({ "error": e.to_string() }),
+                    );
+                }
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+                false
+            }
+        }
+    }
+
+    /// `(healthy, details)` for the health endpoint. An exhausted pool is
+    /// reported as degraded but still healthy; an unreachable database is not.
+    pub fn report(&self) -> (bool, serde_json::Value) {
+        let status = self.state.pool_status();
+        let reachable = self.reachable.load(Ordering::Relaxed);
+        let state = if !reachable {
+            "unreachable"
+        } else if status.is_exhausted() {
+            "exhausted"
+        } else {
+            "ok"
+        };
+        let mut details = serde_json::json!({
+            "status": state,
+            "connections": status.size,
+            "in_use": status.in_use(),
+            "idle": status.idle,
+            "max_connections": status.max,
+            "last_error": *self.last_error.lock().unwrap(),
+        });
+        if let Some(replica) = self.state.replica_pool_status() {
+            details["replica"] = serde_json::json!({
+                "connections": replica.size,
+                "in_use": replica.in_use(),
+                "idle": replica.idle,
+                "max_connections": replica.max,
+            });
+        }
+        (reachable, details)
+    }
+}
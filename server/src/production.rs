@@ -1,26 +1,66 @@
 #[cfg(feature = "production")]
-use crate::types::{Blocklist, ExtensionEvent, LogEntry};
+use crate::roles::Role;
+#[cfg(feature = "production")]
+use crate::storage::{self, CancelOnDrop, PoolConfig, PoolStatus, Storage};
+#[cfg(feature = "production")]
+use crate::types::{
+    Annotation, ArchivedClient, Blocklist, BlocklistEntries, CaseTicket, CategoryStats,
+    ClientStats, DomainStats, EventComment, EventTriage, ExtensionError, ExtensionEvent, LocalUser,
+    LogEntry, NetworkLog, ObservedDomain, TriageStatus,
+};
+#[cfg(feature = "production")]
+use async_trait::async_trait;
 #[cfg(feature = "production")]
 use redis::Client as RedisClient;
 #[cfg(feature = "production")]
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{Executor, PgPool, Postgres};
 
 #[cfg(feature = "production")]
 pub struct ProductionState {
-    db_pool: PgPool,
+    storage: Box<dyn Storage>,
+    /// Read-only replica for query endpoints; writes always go to `storage`.
+    replica: Option<Box<dyn Storage>>,
     redis_client: Option<RedisClient>,
 }
 
+/// Runs a read on the replica when there is one, falling back to the primary
+/// if the replica fails so a lagging or restarting replica doesn't break reads.
+/// Timeouts are passed through: the primary would be just as slow.
+#[cfg(feature = "production")]
+macro_rules! read {
+    ($self:ident . $method:ident ( $($arg:expr),* )) => {
+        match &$self.replica {
+            Some(replica) => match replica.$method($($arg),*).await {
+                Ok(v) => Ok(v),
+                Err(e) if storage::is_statement_timeout(&e) => Err(e),
+                Err(e) => {
+                    crate::metrics::DB_REPLICA_FALLBACKS.inc();
+                    log::warn!(
+                        "⚠️ Read replica failed for {}, using primary: {}",
+                        stringify!($method),
+                        e
+                    );
+                    $self.storage.$method($($arg),*).await
+                }
+            },
+            None => $self.storage.$method($($arg),*).await,
+        }
+    };
+}
+
 #[cfg(feature = "production")]
 impl ProductionState {
     pub async fn new(
         database_url: &str,
+        read_url: Option<&str>,
         redis_url: Option<&str>,
+        pool: &PoolConfig,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let db_pool = PgPoolOptions::new()
-            .max_connections(20)
-            .connect(database_url)
-            .await?;
+        let storage = storage::connect(database_url, pool).await?;
+        let replica = match read_url {
+            Some(url) => Some(storage::connect(url, pool).await?),
+            None => None,
+        };
 
         let redis_client = if let Some(url) = redis_url {
             Some(RedisClient::open(url)?)
@@ -29,18 +69,325 @@ impl ProductionState {
         };
 
         Ok(ProductionState {
-            db_pool,
+            storage,
+            replica,
             redis_client,
         })
     }
 
+    /// Routes the logs and events of orgs with a `shard` to that database
+    /// (see [`crate::sharding`]); nothing changes when `orgs` names none.
+    /// The read replica stands in for the home database on reads.
+    pub async fn with_shards(
+        self,
+        orgs: &crate::metering::OrgsConfig,
+        pool: &PoolConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if orgs.shards.is_empty() {
+            return Ok(self);
+        }
+        let shards = crate::sharding::connect_shards(orgs, pool).await?;
+        let ProductionState {
+            storage,
+            replica,
+            redis_client,
+        } = self;
+        let sharded = |home: Box<dyn Storage>| {
+            Box::new(crate::sharding::ShardedStorage::new(
+                home,
+                shards.clone(),
+                orgs.clone(),
+            )) as Box<dyn Storage>
+        };
+        Ok(ProductionState {
+            replica: replica.map(sharded),
+            storage: sharded(storage),
+            redis_client,
+        })
+    }
+
+    pub fn has_replica(&self) -> bool {
+        self.replica.is_some()
+    }
+
+    pub fn pool_status(&self) -> PoolStatus {
+        self.storage.pool_status()
+    }
+
+    pub fn replica_pool_status(&self) -> Option<PoolStatus> {
+        self.replica.as_ref().map(|r| r.pool_status())
+    }
+
+    pub async fn ping(&self) -> Result<(), sqlx::Error> {
+        self.storage.ping().await
+    }
+
+    // Writes: primary only.
+
     pub async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error> {
+        self.storage.add_log(entry).await
+    }
+
+    pub async fn import_log(&self, entry: LogEntry) -> Result<usize, sqlx::Error> {
+        self.storage.import_log(entry).await
+    }
+
+    pub async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error> {
+        self.storage.update_blocklist(blocklist).await
+    }
+
+    pub async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
+        self.storage.add_extension_event(event).await
+    }
+
+    pub async fn refresh_stats(&self) -> Result<(), sqlx::Error> {
+        self.storage.refresh_stats().await
+    }
+
+    pub async fn archive_client(&self, client: &ArchivedClient) -> Result<(), sqlx::Error> {
+        self.storage.archive_client(client).await
+    }
+
+    pub async fn unarchive_client(&self, client_id: &str) -> Result<bool, sqlx::Error> {
+        self.storage.unarchive_client(client_id).await
+    }
+
+    /// Read from the primary: replicas may lag behind a just-made change.
+    pub async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error> {
+        self.storage.get_archived_clients().await
+    }
+
+    pub async fn add_annotation(&self, annotation: &Annotation) -> Result<i64, sqlx::Error> {
+        self.storage.add_annotation(annotation).await
+    }
+
+    pub async fn delete_annotation(&self, id: i64) -> Result<bool, sqlx::Error> {
+        self.storage.delete_annotation(id).await
+    }
+
+    /// Read from the primary, like `get_archived_clients`.
+    pub async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error> {
+        self.storage.get_annotations().await
+    }
+
+    pub async fn set_event_triage(&self, triage: &EventTriage) -> Result<(), sqlx::Error> {
+        self.storage.set_event_triage(triage).await
+    }
+
+    /// Read from the primary, like `get_archived_clients`.
+    pub async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error> {
+        self.storage.get_event_triage().await
+    }
+
+    pub async fn add_event_comment(&self, comment: &EventComment) -> Result<i64, sqlx::Error> {
+        self.storage.add_event_comment(comment).await
+    }
+
+    pub async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error> {
+        self.storage.get_event_comments().await
+    }
+
+    pub async fn set_case_ticket(&self, ticket: &CaseTicket) -> Result<(), sqlx::Error> {
+        self.storage.set_case_ticket(ticket).await
+    }
+
+    pub async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error> {
+        self.storage.get_case_tickets().await
+    }
+
+    pub async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error> {
+        self.storage.get_extension_errors().await
+    }
+
+    pub async fn record_extension_error(&self, error: &ExtensionError) -> Result<i64, sqlx::Error> {
+        self.storage.record_extension_error(error).await
+    }
+
+    pub async fn put_local_user(&self, user: &LocalUser) -> Result<(), sqlx::Error> {
+        self.storage.put_local_user(user).await
+    }
+
+    pub async fn delete_local_user(&self, username: &str) -> Result<bool, sqlx::Error> {
+        self.storage.delete_local_user(username).await
+    }
+
+    /// Read from the primary, like `get_archived_clients`.
+    pub async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error> {
+        self.storage.get_local_users().await
+    }
+
+    // Reads: replica when configured.
+
+    pub async fn get_domains(
+        &self,
+        first_seen_after: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error> {
+        read!(self.get_domains(first_seen_after))
+    }
+
+    pub async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
+        read!(self.get_blocklist())
+    }
+
+    pub async fn get_logs_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        read!(self.get_logs_before(before, limit))
+    }
+
+    pub async fn get_client_logs(
+        &self,
+        client_id: &str,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        read!(self.get_client_logs(client_id, from, to, limit))
+    }
+
+    pub async fn get_extension_events_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error> {
+        read!(self.get_extension_events_before(before, limit))
+    }
+
+    pub async fn export_logs(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error> {
+        read!(self.export_logs(after_id, as_of, limit))
+    }
+
+    pub async fn export_extension_events(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error> {
+        read!(self.export_extension_events(after_id, as_of, limit))
+    }
+
+    pub async fn get_extension_event_by_packet_id(
+        &self,
+        packet_id: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error> {
+        read!(self.get_extension_event_by_packet_id(packet_id))
+    }
+
+    pub async fn get_stats(
+        &self,
+        limit: i64,
+    ) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error> {
+        read!(self.get_stats(limit))
+    }
+
+    pub async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error> {
+        read!(self.get_category_stats())
+    }
+}
+
+#[cfg(feature = "production")]
+pub struct PostgresStorage {
+    db_pool: PgPool,
+}
+
+#[cfg(feature = "production")]
+impl PostgresStorage {
+    pub async fn connect(database_url: &str, pool: &PoolConfig) -> Result<Self, sqlx::Error> {
+        let statement_timeout = pool.statement_timeout.map(|t| t.as_millis());
+        let db_pool = pool
+            .options::<Postgres>()
+            .after_connect(move |conn, _| {
+                Box::pin(async move {
+                    if let Some(ms) = statement_timeout {
+                        conn.execute(format!("SET statement_timeout = {}", ms).as_str())
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .connect(database_url)
+            .await?;
+        Ok(PostgresStorage { db_pool })
+    }
+
+    /// Checks out a connection for a read that is cancelled with
+    /// `pg_cancel_backend` if the request goes away mid-query.
+    async fn cancellable(&self) -> Result<CancelOnDrop<Postgres>, sqlx::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&mut *conn)
+            .await?;
+        let pool = self.db_pool.clone();
+        Ok(CancelOnDrop::new(conn, move |conn| {
+            actix_web::rt::spawn(async move {
+                if let Err(e) = sqlx::query("SELECT pg_cancel_backend($1)")
+                    .bind(pid)
+                    .execute(&pool)
+                    .await
+                {
+                    log::warn!("⚠️ Failed to cancel query on backend {}: {}", pid, e);
+                }
+                drop(conn);
+            });
+        }))
+    }
+
+    async fn record_domains(&self, entry: &LogEntry) -> Result<(), sqlx::Error> {
+        for (domain, count) in storage::domain_counts(entry) {
+            sqlx::query!(
+                r#"
+                INSERT INTO observed_domains (domain, request_count)
+                VALUES ($1, $2)
+                ON CONFLICT (domain) DO UPDATE
+                SET last_seen = CURRENT_TIMESTAMP,
+                    request_count = observed_domains.request_count + EXCLUDED.request_count
+                "#,
+                domain,
+                count
+            )
+            .execute(&self.db_pool)
+            .await?;
+
+            if let Some(ref client_id) = entry.client_id {
+                sqlx::query!(
+                    "INSERT INTO observed_domain_clients (domain, client_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    domain,
+                    client_id
+                )
+                .execute(&self.db_pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "production")]
+#[async_trait]
+impl Storage for PostgresStorage {
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus::from_pool(&self.db_pool)
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1").execute(&self.db_pool).await?;
+        Ok(())
+    }
+
+    async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error> {
         for log in &entry.logs {
             sqlx::query!(
                 r#"
                 INSERT INTO network_logs 
-                (client_id, session_id, timestamp, user_agent, request_id, url, method, request_type, blocked, block_reason)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                (client_id, session_id, timestamp, user_agent, request_id, url, method, request_type, blocked, block_reason, reputation_score, request_size, response_size, category, profile_id, clock_skew_ms)
+                VALUES ($1, $2, $3::TEXT::TIMESTAMPTZ, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
                 "#,
                 entry.client_id,
                 entry.session_id,
@@ -51,15 +398,102 @@ impl ProductionState {
                 log.method,
                 log.request_type,
                 log.blocked,
-                log.block_reason
+                log.block_reason,
+                log.reputation_score.map(|s| s as i16),
+                log.request_size.map(|s| s as i64),
+                log.response_size.map(|s| s as i64),
+                log.category,
+                entry.profile_id,
+                entry.clock_skew_ms
             )
             .execute(&self.db_pool)
             .await?;
         }
+        self.record_domains(&entry).await?;
         Ok(())
     }
 
-    pub async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
+    async fn import_log(&self, mut entry: LogEntry) -> Result<usize, sqlx::Error> {
+        let mut inserted: Vec<NetworkLog> = Vec::with_capacity(entry.logs.len());
+        for log in std::mem::take(&mut entry.logs) {
+            let result = sqlx::query!(
+                r#"
+                INSERT INTO network_logs
+                (client_id, session_id, timestamp, user_agent, request_id, url, method, request_type, blocked, block_reason, reputation_score, request_size, response_size, category, profile_id, clock_skew_ms)
+                SELECT $1::VARCHAR, $2::VARCHAR, $3::TEXT::TIMESTAMPTZ, $4::TEXT, $5::VARCHAR, $6::TEXT,
+                       $7::VARCHAR, $8::VARCHAR, $9::BOOLEAN, $10::TEXT, $11::SMALLINT, $12::BIGINT,
+                       $13::BIGINT, $14::VARCHAR, $15::VARCHAR, $16::BIGINT
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM network_logs
+                    WHERE client_id IS NOT DISTINCT FROM $1::VARCHAR
+                      AND session_id = $2::VARCHAR
+                      AND timestamp = $3::TEXT::TIMESTAMPTZ
+                      AND request_id = $5::VARCHAR
+                      AND method = $7::VARCHAR
+                      AND url = $6::TEXT
+                )
+                "#,
+                entry.client_id,
+                entry.session_id,
+                entry.timestamp,
+                entry.user_agent,
+                log.request_id,
+                log.url,
+                log.method,
+                log.request_type,
+                log.blocked,
+                log.block_reason,
+                log.reputation_score.map(|s| s as i16),
+                log.request_size.map(|s| s as i64),
+                log.response_size.map(|s| s as i64),
+                log.category,
+                entry.profile_id,
+                entry.clock_skew_ms
+            )
+            .execute(&self.db_pool)
+            .await?;
+            if result.rows_affected() > 0 {
+                inserted.push(log);
+            }
+        }
+        entry.logs = inserted;
+        self.record_domains(&entry).await?;
+        Ok(entry.logs.len())
+    }
+
+    async fn get_domains(
+        &self,
+        first_seen_after: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT d.domain, d.first_seen, d.last_seen, d.request_count,
+                   (SELECT COUNT(*) FROM observed_domain_clients c WHERE c.domain = d.domain) AS "client_count!"
+            FROM observed_domains d
+            WHERE $1::timestamptz IS NULL OR d.first_seen >= $1
+            ORDER BY d.first_seen DESC
+            "#,
+            first_seen_after
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ObservedDomain {
+                domain: r.domain,
+                first_seen: r.first_seen,
+                last_seen: r.last_seen,
+                request_count: r.request_count as u64,
+                client_count: r.client_count as u64,
+            })
+            .collect())
+    }
+
+    async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
         let url_patterns: Vec<String> = sqlx::query_scalar!(
             "SELECT pattern FROM blocklist_patterns WHERE type = 'url' AND active = true"
         )
@@ -72,13 +506,28 @@ impl ProductionState {
         .fetch_all(&self.db_pool)
         .await?;
 
+        let rows = sqlx::query!(
+            "SELECT profile_id, pattern, type AS kind FROM profile_blocklist_patterns ORDER BY id"
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        let mut profiles: std::collections::BTreeMap<String, BlocklistEntries> = Default::default();
+        for r in rows {
+            let profile = profiles.entry(r.profile_id).or_default();
+            match r.kind.as_str() {
+                "youtube" => profile.youtube_channels.push(r.pattern),
+                _ => profile.url_patterns.push(r.pattern),
+            }
+        }
+
         Ok(Blocklist {
             url_patterns,
             youtube_channels,
+            profiles,
         })
     }
 
-    pub async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error> {
+    async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error> {
         sqlx::query!("UPDATE blocklist_patterns SET active = false")
             .execute(&self.db_pool)
             .await?;
@@ -101,25 +550,766 @@ impl ProductionState {
             .await?;
         }
 
+        sqlx::query!("DELETE FROM profile_blocklist_patterns")
+            .execute(&self.db_pool)
+            .await?;
+        for (profile_id, profile) in &blocklist.profiles {
+            let entries = profile
+                .url_patterns
+                .iter()
+                .map(|p| (p, "url"))
+                .chain(profile.youtube_channels.iter().map(|c| (c, "youtube")));
+            for (pattern, kind) in entries {
+                sqlx::query!(
+                    "INSERT INTO profile_blocklist_patterns (profile_id, pattern, type) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                    profile_id,
+                    pattern,
+                    kind
+                )
+                .execute(&self.db_pool)
+                .await?;
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
+    async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
             INSERT INTO extension_events 
-            (client_id, session_id, timestamp, user_agent, event_type, data)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            (client_id, session_id, timestamp, user_agent, event_type, data, profile_id)
+            VALUES ($1, $2, $3::TEXT::TIMESTAMPTZ, $4, $5, $6, $7)
             "#,
             event.client_id,
             event.session_id,
             event.timestamp,
             event.user_agent,
             event.event_type,
-            event.data
+            event.data,
+            event.profile_id
         )
         .execute(&self.db_pool)
         .await?;
         Ok(())
     }
+
+    async fn get_logs_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT client_id, session_id, timestamp::text AS "timestamp!", user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE created_at < $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            before,
+            limit
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .rev()
+            .map(|r| LogEntry {
+                client_id: r.client_id,
+                profile_id: r.profile_id,
+                session_id: r.session_id,
+                timestamp: r.timestamp,
+                user_agent: r.user_agent.unwrap_or_default(),
+                logs: vec![NetworkLog {
+                    request_id: r.request_id,
+                    url: r.url,
+                    method: r.method,
+                    request_type: r
+                        .request_type
+                        .unwrap_or_else(crate::types::default_request_type),
+                    blocked: r.blocked.unwrap_or(false),
+                    block_reason: r.block_reason,
+                    reputation_score: r.reputation_score.map(|s| s as u8),
+                    request_size: r.request_size.map(|s| s as u64),
+                    response_size: r.response_size.map(|s| s as u64),
+                    category: r.category,
+                }],
+                username: None,
+                clock_skew_ms: r.clock_skew_ms,
+            })
+            .collect())
+    }
+
+    async fn get_client_logs(
+        &self,
+        client_id: &str,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT client_id, session_id, timestamp, user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE client_id = $1 AND timestamp >= $2 AND timestamp < $3
+            ORDER BY timestamp DESC
+            LIMIT $4
+            "#,
+            client_id,
+            from,
+            to,
+            limit
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .rev()
+            .map(|r| LogEntry {
+                client_id: r.client_id,
+                profile_id: r.profile_id,
+                session_id: r.session_id,
+                // Rendered as RFC 3339, which the summary parses.
+                timestamp: r.timestamp.to_rfc3339(),
+                user_agent: r.user_agent.unwrap_or_default(),
+                logs: vec![NetworkLog {
+                    request_id: r.request_id,
+                    url: r.url,
+                    method: r.method,
+                    request_type: r
+                        .request_type
+                        .unwrap_or_else(crate::types::default_request_type),
+                    blocked: r.blocked.unwrap_or(false),
+                    block_reason: r.block_reason,
+                    reputation_score: r.reputation_score.map(|s| s as u8),
+                    request_size: r.request_size.map(|s| s as u64),
+                    response_size: r.response_size.map(|s| s as u64),
+                    category: r.category,
+                }],
+                username: None,
+                clock_skew_ms: r.clock_skew_ms,
+            })
+            .collect())
+    }
+
+    async fn export_logs(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, client_id, session_id, timestamp, user_agent,
+                   request_id, url, method, request_type, blocked, block_reason,
+                   reputation_score, request_size, response_size, category, profile_id, clock_skew_ms
+            FROM network_logs
+            WHERE id > $1 AND created_at < $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+            after_id,
+            as_of,
+            limit
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| {
+                let entry = LogEntry {
+                    client_id: r.client_id,
+                    profile_id: r.profile_id,
+                    session_id: r.session_id,
+                    timestamp: r.timestamp.to_rfc3339(),
+                    user_agent: r.user_agent.unwrap_or_default(),
+                    logs: vec![NetworkLog {
+                        request_id: r.request_id,
+                        url: r.url,
+                        method: r.method,
+                        request_type: r
+                            .request_type
+                            .unwrap_or_else(crate::types::default_request_type),
+                        blocked: r.blocked.unwrap_or(false),
+                        block_reason: r.block_reason,
+                        reputation_score: r.reputation_score.map(|s| s as u8),
+                        request_size: r.request_size.map(|s| s as u64),
+                        response_size: r.response_size.map(|s| s as u64),
+                        category: r.category,
+                    }],
+                    username: None,
+                    clock_skew_ms: r.clock_skew_ms,
+                };
+                (r.id, entry)
+            })
+            .collect())
+    }
+
+    async fn export_extension_events(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, client_id, profile_id, session_id, timestamp, user_agent, event_type, data
+            FROM extension_events
+            WHERE id > $1 AND created_at < $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+            after_id,
+            as_of,
+            limit
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| {
+                let event = ExtensionEvent {
+                    client_id: r.client_id,
+                    profile_id: r.profile_id,
+                    session_id: r.session_id,
+                    timestamp: r.timestamp.to_rfc3339(),
+                    user_agent: r.user_agent.unwrap_or_default(),
+                    event_type: r.event_type,
+                    data: r.data,
+                };
+                (r.id, event)
+            })
+            .collect())
+    }
+
+    async fn get_extension_events_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT client_id, profile_id, session_id, timestamp::text AS "timestamp!", user_agent, event_type, data
+            FROM extension_events
+            WHERE created_at < $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            before,
+            limit
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        let rows = rows?;
+
+        Ok(rows
+            .into_iter()
+            .rev()
+            .map(|r| ExtensionEvent {
+                client_id: r.client_id,
+                profile_id: r.profile_id,
+                session_id: r.session_id,
+                timestamp: r.timestamp,
+                user_agent: r.user_agent.unwrap_or_default(),
+                event_type: r.event_type,
+                data: r.data,
+            })
+            .collect())
+    }
+
+    async fn get_extension_event_by_packet_id(
+        &self,
+        packet_id: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT client_id, profile_id, session_id, timestamp::text AS "timestamp!", user_agent, event_type, data
+            FROM extension_events
+            WHERE data->>'packet_id' = $1
+            LIMIT 1
+            "#,
+            packet_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(row.map(|r| ExtensionEvent {
+            client_id: r.client_id,
+            profile_id: r.profile_id,
+            session_id: r.session_id,
+            timestamp: r.timestamp,
+            user_agent: r.user_agent.unwrap_or_default(),
+            event_type: r.event_type,
+            data: r.data,
+        }))
+    }
+
+    async fn refresh_stats(&self) -> Result<(), sqlx::Error> {
+        // CONCURRENTLY keeps the views readable during the refresh; it needs
+        // the unique indexes created in schema.sql. A full refresh can take
+        // longer than the per-statement limit meant for dashboard reads.
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query!("SET LOCAL statement_timeout = 0")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY domain_stats")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY client_stats")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY category_stats")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    async fn get_stats(
+        &self,
+        limit: i64,
+    ) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let conn = q.conn();
+        let rows = async {
+            let domains = sqlx::query!(
+                r#"
+                SELECT domain AS "domain!", requests AS "requests!", blocked AS "blocked!",
+                       clients AS "clients!", last_seen
+                FROM domain_stats
+                ORDER BY requests DESC, domain
+                LIMIT $1
+                "#,
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            let clients = sqlx::query!(
+                r#"
+                SELECT client_id AS "client_id!", requests AS "requests!", blocked AS "blocked!",
+                       domains AS "domains!", sessions AS "sessions!", last_seen
+                FROM client_stats
+                ORDER BY requests DESC, client_id
+                LIMIT $1
+                "#,
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+            Ok::<_, sqlx::Error>((domains, clients))
+        }
+        .await;
+        q.finish();
+        let (domains, clients) = rows?;
+
+        Ok((
+            domains
+                .into_iter()
+                .map(|r| DomainStats {
+                    domain: r.domain,
+                    requests: r.requests as u64,
+                    blocked: r.blocked as u64,
+                    clients: r.clients as u64,
+                    last_seen: r.last_seen,
+                })
+                .collect(),
+            clients
+                .into_iter()
+                .map(|r| ClientStats {
+                    client_id: r.client_id,
+                    requests: r.requests as u64,
+                    blocked: r.blocked as u64,
+                    domains: r.domains as u64,
+                    sessions: r.sessions as u64,
+                    last_seen: r.last_seen,
+                })
+                .collect(),
+        ))
+    }
+
+    async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error> {
+        let mut q = self.cancellable().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT category AS "category!", requests AS "requests!", blocked AS "blocked!",
+                   domains AS "domains!", clients AS "clients!"
+            FROM category_stats
+            ORDER BY requests DESC, category
+            "#
+        )
+        .fetch_all(q.conn())
+        .await;
+        q.finish();
+        Ok(rows?
+            .into_iter()
+            .map(|r| CategoryStats {
+                category: r.category,
+                requests: r.requests as u64,
+                blocked: r.blocked as u64,
+                domains: r.domains as u64,
+                clients: r.clients as u64,
+            })
+            .collect())
+    }
+
+    async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT client_id, archived_at, archived_by, reason FROM archived_clients"
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| ArchivedClient {
+                client_id: r.client_id,
+                archived_at: r.archived_at,
+                archived_by: r.archived_by,
+                reason: r.reason,
+            })
+            .collect())
+    }
+
+    async fn archive_client(&self, client: &ArchivedClient) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO archived_clients (client_id, archived_at, archived_by, reason)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (client_id) DO UPDATE
+            SET archived_at = EXCLUDED.archived_at,
+                archived_by = EXCLUDED.archived_by,
+                reason = EXCLUDED.reason
+            "#,
+            client.client_id,
+            client.archived_at,
+            client.archived_by,
+            client.reason
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn unarchive_client(&self, client_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM archived_clients WHERE client_id = $1",
+            client_id
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT id, scope, target, label, note, created_by, created_at FROM annotations ORDER BY id"
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| Annotation {
+                id: r.id,
+                scope: r.scope,
+                target: r.target,
+                label: r.label,
+                note: r.note,
+                created_by: r.created_by,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn add_annotation(&self, annotation: &Annotation) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO annotations (scope, target, label, note, created_by, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+            annotation.scope,
+            annotation.target,
+            annotation.label,
+            annotation.note,
+            annotation.created_by,
+            annotation.created_at
+        )
+        .fetch_one(&self.db_pool)
+        .await
+    }
+
+    async fn delete_annotation(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM annotations WHERE id = $1", id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT packet_id, status, note, assignee, updated_by, updated_at FROM event_triage"
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| EventTriage {
+                packet_id: r.packet_id,
+                status: TriageStatus::parse(&r.status).unwrap_or_default(),
+                note: r.note,
+                assignee: r.assignee,
+                updated_by: r.updated_by,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    async fn set_event_triage(&self, triage: &EventTriage) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO event_triage (packet_id, status, note, assignee, updated_by, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (packet_id) DO UPDATE
+            SET status = EXCLUDED.status,
+                note = EXCLUDED.note,
+                assignee = EXCLUDED.assignee,
+                updated_by = EXCLUDED.updated_by,
+                updated_at = EXCLUDED.updated_at
+            "#,
+            triage.packet_id,
+            triage.status.as_str(),
+            triage.note,
+            triage.assignee,
+            triage.updated_by,
+            triage.updated_at
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT id, packet_id, parent_id, author, body, created_at FROM event_comments ORDER BY id"
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| EventComment {
+                id: r.id,
+                packet_id: r.packet_id,
+                parent_id: r.parent_id,
+                author: r.author,
+                body: r.body,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn add_event_comment(&self, comment: &EventComment) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO event_comments (packet_id, parent_id, author, body, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+            "#,
+            comment.packet_id,
+            comment.parent_id,
+            comment.author,
+            comment.body,
+            comment.created_at
+        )
+        .fetch_one(&self.db_pool)
+        .await
+    }
+
+    async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT packet_id, provider, ticket_key, url, closed, created_at, closed_at, synced_at
+            FROM case_tickets
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| CaseTicket {
+                packet_id: r.packet_id,
+                provider: r.provider,
+                key: r.ticket_key,
+                url: r.url,
+                closed: r.closed,
+                created_at: r.created_at,
+                closed_at: r.closed_at,
+                synced_at: r.synced_at,
+            })
+            .collect())
+    }
+
+    async fn set_case_ticket(&self, ticket: &CaseTicket) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO case_tickets
+                (packet_id, provider, ticket_key, url, closed, created_at, closed_at, synced_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (packet_id) DO UPDATE
+            SET provider = EXCLUDED.provider,
+                ticket_key = EXCLUDED.ticket_key,
+                url = EXCLUDED.url,
+                closed = EXCLUDED.closed,
+                created_at = EXCLUDED.created_at,
+                closed_at = EXCLUDED.closed_at,
+                synced_at = EXCLUDED.synced_at
+            "#,
+            ticket.packet_id,
+            ticket.provider,
+            ticket.key,
+            ticket.url,
+            ticket.closed,
+            ticket.created_at,
+            ticket.closed_at,
+            ticket.synced_at
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT hash, message, stack, source, extension_version, last_client_id,
+                   count, first_seen, last_seen
+            FROM extension_errors
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| ExtensionError {
+                hash: r.hash,
+                message: r.message,
+                stack: r.stack,
+                source: r.source,
+                extension_version: r.extension_version,
+                last_client_id: r.last_client_id,
+                count: r.count,
+                first_seen: r.first_seen,
+                last_seen: r.last_seen,
+            })
+            .collect())
+    }
+
+    async fn record_extension_error(&self, error: &ExtensionError) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO extension_errors
+                (hash, message, stack, source, extension_version, last_client_id,
+                 count, first_seen, last_seen)
+            VALUES ($1, $2, $3, $4, $5, $6, 1, $7, $7)
+            ON CONFLICT (hash) DO UPDATE
+            SET message = EXCLUDED.message,
+                extension_version = EXCLUDED.extension_version,
+                last_client_id = EXCLUDED.last_client_id,
+                count = extension_errors.count + 1,
+                last_seen = GREATEST(extension_errors.last_seen, EXCLUDED.last_seen)
+            RETURNING count
+            "#,
+            error.hash,
+            error.message,
+            error.stack,
+            error.source,
+            error.extension_version,
+            error.last_client_id,
+            error.last_seen
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+        Ok(row.count)
+    }
+
+    async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT username, display_name, role, active, password_hash, token_hash,
+                   created_at, updated_at
+            FROM local_users
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| LocalUser {
+                username: r.username,
+                display_name: r.display_name,
+                role: Role::parse(&r.role).unwrap_or(Role::Viewer),
+                active: r.active,
+                password_hash: r.password_hash,
+                token_hash: r.token_hash,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    async fn put_local_user(&self, user: &LocalUser) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO local_users (username, display_name, role, active, password_hash,
+                                     token_hash, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (username) DO UPDATE
+            SET display_name = EXCLUDED.display_name,
+                role = EXCLUDED.role,
+                active = EXCLUDED.active,
+                password_hash = EXCLUDED.password_hash,
+                token_hash = EXCLUDED.token_hash,
+                updated_at = EXCLUDED.updated_at
+            "#,
+            user.username,
+            user.display_name,
+            user.role.name(),
+            user.active,
+            user.password_hash,
+            user.token_hash,
+            user.created_at,
+            user.updated_at
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_local_user(&self, username: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM local_users WHERE username = $1", username)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
 }
@@ -0,0 +1,237 @@
+use crate::error::ApiError;
+use crate::groups::Groups;
+use crate::metrics;
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use redis::AsyncCommands;
+
+/// Daily per-client limits; 0 means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QuotaLimits {
+    pub logs_per_day: u64,
+    pub events_per_day: u64,
+    pub bytes_per_day: u64,
+}
+
+/// What a client has sent today (UTC), or what one request adds to it.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Usage {
+    pub logs: u64,
+    pub events: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.logs += other.logs;
+        self.events += other.events;
+        self.bytes += other.bytes;
+    }
+
+    /// First limit this usage is over, as `(quota name, limit, used)`.
+    fn exceeded(&self, limits: &QuotaLimits) -> Option<(&'static str, u64, u64)> {
+        [
+            ("logs_per_day", limits.logs_per_day, self.logs),
+            ("events_per_day", limits.events_per_day, self.events),
+            ("bytes_per_day", limits.bytes_per_day, self.bytes),
+        ]
+        .into_iter()
+        .find(|(_, limit, used)| *limit > 0 && used > limit)
+    }
+}
+
+/// Counts ingest per client_id per UTC day and refuses batches that would push
+/// a client over its quota, so one noisy machine can't fill the storage. Usage
+/// is tracked even with no limits set, for `/api/clients/{id}/usage`.
+pub struct Quotas {
+    limits: QuotaLimits,
+    groups: Arc<Groups>,
+    usage: Mutex<HashMap<String, (NaiveDate, Usage)>>,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Start of the next UTC day, when every quota resets.
+fn resets_at() -> chrono::DateTime<Utc> {
+    (today() + Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc()
+}
+
+impl Quotas {
+    pub fn new(limits: QuotaLimits) -> Self {
+        Quotas {
+            limits,
+            groups: Arc::new(Groups::new()),
+            usage: Mutex::new(HashMap::new()),
+            #[cfg(feature = "production")]
+            redis: None,
+        }
+    }
+
+    /// Shares counters across replicas through Redis hashes
+    /// `quota:<client_id>:<date>` that expire after two days.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    /// Group quotas override the server-wide limits for group members.
+    pub fn with_groups(mut self, groups: Arc<Groups>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn limits(&self) -> QuotaLimits {
+        self.limits
+    }
+
+    /// The limits one client is held to, its groups' quotas included.
+    pub fn limits_for(&self, client_id: &str) -> QuotaLimits {
+        self.groups.quota_limits(client_id, self.limits)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limits.logs_per_day > 0
+            || self.limits.events_per_day > 0
+            || self.limits.bytes_per_day > 0
+    }
+
+    /// Adds `delta` to the client's usage, or refuses it with a 429 when that
+    /// would exceed a quota (nothing is counted then). Batches without a
+    /// client_id aren't attributable and are never limited.
+    pub async fn charge(&self, client_id: Option<&str>, delta: Usage) -> Result<(), ApiError> {
+        let Some(client_id) = client_id.filter(|c| !c.is_empty()) else {
+            return Ok(());
+        };
+        let limits = self.limits_for(client_id);
+        #[cfg(feature = "production")]
+        if let Some(result) = self.redis_charge(client_id, delta, &limits).await {
+            return result;
+        }
+
+        let day = today();
+        let mut usage = self.usage.lock().unwrap();
+        if usage.len() > 50_000 {
+            usage.retain(|_, (d, _)| *d == day);
+        }
+        let entry = usage
+            .entry(client_id.to_string())
+            .or_insert((day, Usage::default()));
+        if entry.0 != day {
+            *entry = (day, Usage::default());
+        }
+        let mut after = entry.1;
+        after.add(delta);
+        if let Some(over) = after.exceeded(&limits) {
+            return Err(self.reject(client_id, over));
+        }
+        entry.1 = after;
+        Ok(())
+    }
+
+    /// Today's usage for one client.
+    pub async fn usage(&self, client_id: &str) -> Usage {
+        #[cfg(feature = "production")]
+        if let Some(usage) = self.redis_usage(client_id).await {
+            return usage;
+        }
+        match self.usage.lock().unwrap().get(client_id) {
+            Some((day, usage)) if *day == today() => *usage,
+            _ => Usage::default(),
+        }
+    }
+
+    pub fn resets_at(&self) -> chrono::DateTime<Utc> {
+        resets_at()
+    }
+
+    fn reject(&self, client_id: &str, (quota, limit, used): (&'static str, u64, u64)) -> ApiError {
+        metrics::QUOTA_REJECTED.inc();
+        log::warn!(
+            "🪣 Quota {} exceeded for client_id={}: {} > {}",
+            quota,
+            client_id,
+            used,
+            limit
+        );
+        ApiError::QuotaExceeded {
+            quota,
+            limit,
+            client_id: client_id.to_string(),
+            resets_at: resets_at(),
+        }
+    }
+
+    #[cfg(feature = "production")]
+    fn redis_key(client_id: &str) -> String {
+        format!("quota:{}:{}", client_id, today())
+    }
+
+    /// `None` when Redis isn't configured or unreachable; the local counters
+    /// take over then.
+    #[cfg(feature = "production")]
+    async fn redis_charge(
+        &self,
+        client_id: &str,
+        delta: Usage,
+        limits: &QuotaLimits,
+    ) -> Option<Result<(), ApiError>> {
+        let mut conn = self.redis.as_ref()?.conn().await?;
+        let key = Self::redis_key(client_id);
+        let (logs, events, bytes): (u64, u64, u64) = timed(
+            redis::pipe()
+                .hincr(&key, "logs", delta.logs)
+                .hincr(&key, "events", delta.events)
+                .hincr(&key, "bytes", delta.bytes)
+                .expire(&key, 2 * 86_400)
+                .ignore()
+                .query_async(&mut conn),
+        )
+        .await?;
+        let after = Usage {
+            logs,
+            events,
+            bytes,
+        };
+        match after.exceeded(limits) {
+            None => Some(Ok(())),
+            Some(over) => {
+                // Undo the increment so a refused batch doesn't count.
+                let _: Option<()> = timed(
+                    redis::pipe()
+                        .hincr(&key, "logs", -(delta.logs as i64))
+                        .hincr(&key, "events", -(delta.events as i64))
+                        .hincr(&key, "bytes", -(delta.bytes as i64))
+                        .query_async(&mut conn),
+                )
+                .await;
+                Some(Err(self.reject(client_id, over)))
+            }
+        }
+    }
+
+    #[cfg(feature = "production")]
+    async fn redis_usage(&self, client_id: &str) -> Option<Usage> {
+        let mut conn = self.redis.as_ref()?.conn().await?;
+        let fields: HashMap<String, u64> = timed(conn.hgetall(Self::redis_key(client_id))).await?;
+        let get = |k: &str| fields.get(k).copied().unwrap_or(0);
+        Some(Usage {
+            logs: get("logs"),
+            events: get("events"),
+            bytes: get("bytes"),
+        })
+    }
+}
@@ -0,0 +1,364 @@
+//! Newly registered domains: with `--rdap-max-age`, the registration date of
+//! each domain the clients request is looked up over RDAP (the successor of
+//! WHOIS), and requests to a domain younger than that raise a
+//! `newly_registered_domain` security event, once per client and domain a
+//! day. Fresh domains are disproportionately phishing and malware
+//! infrastructure.
+//!
+//! Lookups run in the background, at most one per domain at a time, so
+//! ingest never waits on a registry; the clients that requested a domain
+//! while it was being looked up are flagged when the answer comes in.
+//! Answers are cached for [`CACHE_TTL`] (a registration date doesn't
+//! change), failures are retried after [`RETRY_AFTER`]. The registered
+//! domain is taken as the last two labels of the host, or three under a
+//! ccTLD's `co.` / `com.` / `org.`-style second level; IP addresses and
+//! internal names aren't looked up.
+
+use crate::handlers::common::domain_from_url;
+use crate::metrics;
+use crate::server_events;
+use crate::types::LogEntry;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `--rdap-url` default; rdap.org redirects to the registry of each TLD.
+pub const DEFAULT_URL: &str = "https://rdap.org/domain/";
+/// How long a registration date (or "unknown") is trusted.
+pub const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+/// How soon a domain whose lookup failed is tried again.
+pub const RETRY_AFTER: Duration = Duration::from_secs(3600);
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Domains kept in the cache; the oldest answer goes first.
+pub const MAX_CACHED: usize = 100_000;
+/// Lookups in flight at once; further new domains wait for a later batch.
+pub const MAX_PENDING: usize = 256;
+/// Clients remembered per domain while it is looked up.
+const MAX_WAITING: usize = 20;
+
+const SECOND_LEVELS: &[&str] = &[
+    "co", "com", "net", "org", "gov", "edu", "ac", "or", "ne", "go",
+];
+const INTERNAL_TLDS: &[&str] = &[
+    "local",
+    "localhost",
+    "internal",
+    "lan",
+    "home",
+    "corp",
+    "test",
+    "example",
+    "invalid",
+    "arpa",
+];
+
+/// The domain a registry knows `host` by, e.g. `example.co.uk` for
+/// `www.example.co.uk`; `None` for addresses and internal names.
+pub fn registered_domain(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[') {
+        return None;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let n = labels.len();
+    if n < 2 || labels.iter().any(|l| l.is_empty()) || INTERNAL_TLDS.contains(&labels[n - 1]) {
+        return None;
+    }
+    let take = if n >= 3 && labels[n - 1].len() == 2 && SECOND_LEVELS.contains(&labels[n - 2]) {
+        3
+    } else {
+        2
+    };
+    Some(labels[n - take..].join("."))
+}
+
+/// The `registration` event of an RDAP domain answer.
+pub fn registration_date(answer: &serde_json::Value) -> Option<DateTime<Utc>> {
+    answer["events"]
+        .as_array()?
+        .iter()
+        .find(|e| e["eventAction"] == "registration")
+        .and_then(|e| e["eventDate"].as_str())
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+        .map(|d| d.with_timezone(&Utc))
+}
+
+#[derive(Debug, Clone)]
+pub struct NewDomainFinding {
+    pub client_id: Option<String>,
+    pub url: String,
+    pub domain: String,
+    pub registered_at: DateTime<Utc>,
+    pub max_age_days: i64,
+}
+
+impl NewDomainFinding {
+    pub fn age_days(&self) -> i64 {
+        (Utc::now() - self.registered_at).num_days().max(0)
+    }
+
+    pub fn to_event_data(&self) -> serde_json::Value {
+        let age_days = self.age_days();
+        serde_json::json!({
+            "url": self.url,
+            "host": domain_from_url(&self.url),
+            "domain": self.domain,
+            "registered_at": self.registered_at.to_rfc3339(),
+            "age_days": age_days,
+            "max_age_days": self.max_age_days,
+            "detection": {
+                // A day-old domain is 90, one at the limit 60.
+                "riskScore": 90 - 30 * age_days.min(self.max_age_days) / self.max_age_days.max(1)
+            }
+        })
+    }
+}
+
+struct Cached {
+    registered_at: Option<DateTime<Utc>>,
+    fresh_for: Duration,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Cache {
+    answers: HashMap<String, Cached>,
+    /// Domains being looked up, with the (client_id, url) that asked.
+    pending: HashMap<String, Vec<(Option<String>, String)>>,
+    /// When each (client_id, domain) was last flagged.
+    alerted: HashMap<(String, String), DateTime<Utc>>,
+}
+
+pub struct RdapService {
+    /// None when `--rdap-max-age` isn't set.
+    max_age: Option<chrono::Duration>,
+    base_url: String,
+    client: reqwest::Client,
+    cache: Mutex<Cache>,
+}
+
+impl Default for RdapService {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl RdapService {
+    pub fn disabled() -> Self {
+        RdapService {
+            max_age: None,
+            base_url: DEFAULT_URL.to_string(),
+            client: reqwest::Client::new(),
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// `base_url` is the RDAP `domain/` endpoint the domain is appended to.
+    pub fn new(max_age: chrono::Duration, base_url: &str) -> Result<Self, String> {
+        if max_age <= chrono::Duration::zero() {
+            return Err("the age must be positive".into());
+        }
+        reqwest::Url::parse(base_url).map_err(|e| format!("{}: {}", base_url, e))?;
+        let mut base_url = base_url.to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        Ok(RdapService {
+            max_age: Some(max_age),
+            base_url,
+            client: reqwest::Client::builder()
+                .timeout(LOOKUP_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?,
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some()
+    }
+
+    /// The registration date of `domain` from RDAP, bypassing the cache;
+    /// `None` when the registry doesn't know the domain or has no date.
+    pub async fn lookup(&self, domain: &str) -> Result<Option<DateTime<Utc>>, String> {
+        let resp = self
+            .client
+            .get(format!("{}{}", self.base_url, domain))
+            .header("accept", "application/rdap+json, application/json")
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            return Err(format!("RDAP answered {}", resp.status()));
+        }
+        let answer: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+        Ok(registration_date(&answer))
+    }
+
+    /// A finding for `client_id` unless it was flagged for the domain today.
+    fn flag(
+        &self,
+        cache: &mut Cache,
+        client_id: Option<String>,
+        url: String,
+        domain: &str,
+        registered_at: DateTime<Utc>,
+    ) -> Option<NewDomainFinding> {
+        let max_age = self.max_age?;
+        let now = Utc::now();
+        if now - registered_at > max_age {
+            return None;
+        }
+        let key = (client_id.clone().unwrap_or_default(), domain.to_string());
+        if cache
+            .alerted
+            .get(&key)
+            .is_some_and(|at| now - *at < chrono::Duration::days(1))
+        {
+            return None;
+        }
+        if cache.alerted.len() >= MAX_CACHED {
+            cache
+                .alerted
+                .retain(|_, at| now - *at < chrono::Duration::days(1));
+        }
+        cache.alerted.insert(key, now);
+        Some(NewDomainFinding {
+            client_id,
+            url,
+            domain: domain.to_string(),
+            registered_at,
+            max_age_days: max_age.num_days(),
+        })
+    }
+
+    fn remember(&self, domain: &str, registered_at: Option<DateTime<Utc>>, fresh_for: Duration) {
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.answers.contains_key(domain) && cache.answers.len() >= MAX_CACHED {
+            let oldest = cache
+                .answers
+                .iter()
+                .min_by_key(|(_, c)| c.at)
+                .map(|(d, _)| d.clone());
+            if let Some(d) = oldest {
+                cache.answers.remove(&d);
+            }
+        }
+        cache.answers.insert(
+            domain.to_string(),
+            Cached {
+                registered_at,
+                fresh_for,
+                at: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Called for every stored batch: returns findings for domains whose age is
+/// cached, and looks the others up in the background, raising their
+/// findings as security events when the answers come in.
+pub fn observe(rdap: &web::Data<RdapService>, entry: &LogEntry) -> Vec<NewDomainFinding> {
+    if !rdap.is_enabled() {
+        return Vec::new();
+    }
+    let mut findings = Vec::new();
+    let mut lookups = Vec::new();
+    {
+        let mut cache = rdap.cache.lock().unwrap();
+        for log in &entry.logs {
+            let Some(domain) = domain_from_url(&log.url).and_then(|h| registered_domain(&h)) else {
+                continue;
+            };
+            let cached = cache
+                .answers
+                .get(&domain)
+                .filter(|c| c.at.elapsed() < c.fresh_for)
+                .map(|c| c.registered_at);
+            match cached {
+                Some(Some(registered_at)) => findings.extend(rdap.flag(
+                    &mut cache,
+                    entry.client_id.clone(),
+                    log.url.clone(),
+                    &domain,
+                    registered_at,
+                )),
+                Some(None) => {}
+                None => {
+                    let pending = cache.pending.len();
+                    let waiting = match cache.pending.get_mut(&domain) {
+                        Some(waiting) => waiting,
+                        None if pending < MAX_PENDING => {
+                            lookups.push(domain.clone());
+                            cache.pending.entry(domain).or_default()
+                        }
+                        None => continue,
+                    };
+                    let asked = (entry.client_id.clone(), log.url.clone());
+                    if waiting.len() < MAX_WAITING && !waiting.iter().any(|(c, _)| *c == asked.0) {
+                        waiting.push(asked);
+                    }
+                }
+            }
+        }
+    }
+    for domain in lookups {
+        let rdap = rdap.clone();
+        actix_web::rt::spawn(async move {
+            metrics::RDAP_LOOKUPS.inc();
+            let answer = rdap.lookup(&domain).await;
+            let registered_at = match answer {
+                Ok(registered_at) => {
+                    log::debug!("🗓️ {} registered {:?}", domain, registered_at);
+                    rdap.remember(&domain, registered_at, CACHE_TTL);
+                    registered_at
+                }
+                Err(e) => {
+                    metrics::RDAP_FAILURES.inc();
+                    log::warn!("⚠️ RDAP lookup of {} failed: {}", domain, e);
+                    rdap.remember(&domain, None, RETRY_AFTER);
+                    None
+                }
+            };
+            let found: Vec<NewDomainFinding> = {
+                let mut cache = rdap.cache.lock().unwrap();
+                let waiting = cache.pending.remove(&domain).unwrap_or_default();
+                match registered_at {
+                    Some(registered_at) => waiting
+                        .into_iter()
+                        .filter_map(|(client_id, url)| {
+                            rdap.flag(&mut cache, client_id, url, &domain, registered_at)
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                }
+            };
+            for finding in found {
+                raise(&finding);
+            }
+        });
+    }
+    findings
+}
+
+/// Raises a finding made outside the request that reported it.
+fn raise(finding: &NewDomainFinding) {
+    metrics::NEWLY_REGISTERED_DOMAINS.inc();
+    let packet_id = server_events::record_security(
+        finding.client_id.clone(),
+        "newly_registered_domain",
+        finding.to_event_data(),
+    );
+    log::error!(
+        "🆕 NEWLY REGISTERED DOMAIN requested: client_id={:?}, domain={}, registered {} (packet_id={})",
+        finding.client_id,
+        finding.domain,
+        finding.registered_at.date_naive(),
+        packet_id
+    );
+}
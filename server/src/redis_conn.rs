@@ -0,0 +1,66 @@
+//! The one Redis connection (`--redis-url`) shared by everything that keeps
+//! cross-replica state there: dashboard sessions, ingest totals, quotas,
+//! metering, distinct counts, the reputation cache and bans. It is a `ConnectionManager`, opened on first
+//! use and reconnected when it drops, so no request pays for a new
+//! connection. Connecting and every command are bounded by [`TIMEOUT`]: a
+//! Redis that is slow or gone makes its callers fall back to their local
+//! state instead of holding up ingest.
+
+use redis::aio::ConnectionManager;
+use redis::{Client, ErrorKind, RedisResult};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Longest a connection attempt or a command may take.
+pub const TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct SharedRedis {
+    client: Client,
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl SharedRedis {
+    /// Checks the URL; nothing is connected until the first use.
+    pub fn open(url: &str) -> RedisResult<Self> {
+        Ok(SharedRedis {
+            client: Client::open(url)?,
+            conn: OnceCell::new(),
+        })
+    }
+
+    /// The shared connection, or `None` while Redis can't be reached. A
+    /// failed attempt is retried by the next caller.
+    pub async fn conn(&self) -> Option<ConnectionManager> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| async {
+                tokio::time::timeout(TIMEOUT, ConnectionManager::new(self.client.clone()))
+                    .await
+                    .unwrap_or_else(|_| Err((ErrorKind::IoError, "connect timed out").into()))
+            })
+            .await;
+        match conn {
+            Ok(conn) => Some(conn.clone()),
+            Err(e) => {
+                log::debug!("Redis unavailable: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// What `cmd` returned, or `None` when it failed or took over [`TIMEOUT`].
+pub async fn timed<T>(cmd: impl Future<Output = RedisResult<T>>) -> Option<T> {
+    match tokio::time::timeout(TIMEOUT, cmd).await {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            log::debug!("Redis command failed: {}", e);
+            None
+        }
+        Err(_) => {
+            log::debug!("Redis command timed out after {:?}", TIMEOUT);
+            None
+        }
+    }
+}
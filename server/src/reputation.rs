@@ -0,0 +1,273 @@
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "production")]
+use crate::redis_conn::{timed, SharedRedis};
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+/// Scores run from 0 (clean) to 100 (known bad), the same scale as the
+/// extension's `riskScore`.
+pub const SUSPICIOUS_THRESHOLD: u8 = 50;
+/// How long a domain the API had no score for (or failed on) is left alone.
+pub const MISS_TTL: Duration = Duration::from_secs(300);
+/// Domains looked up per batch, all at once, so ingest waits on at most one
+/// lookup timeout; the rest are looked up by later batches.
+pub const MAX_LOOKUPS_PER_BATCH: usize = 16;
+
+pub struct ReputationService {
+    local: HashMap<String, u8>,
+    api_url: Option<String>,
+    ttl: Duration,
+    /// `None` for a miss, kept for [`MISS_TTL`] instead of the TTL.
+    cache: Mutex<HashMap<String, (Option<u8>, Instant)>>,
+    http: reqwest::Client,
+    #[cfg(feature = "production")]
+    redis: Option<Arc<SharedRedis>>,
+}
+
+impl ReputationService {
+    pub fn new(
+        list_path: Option<&str>,
+        api_url: Option<String>,
+        ttl_secs: u64,
+    ) -> std::io::Result<Self> {
+        let local = match list_path {
+            Some(path) => parse_local_list(&std::fs::read_to_string(path)?),
+            None => HashMap::new(),
+        };
+        Ok(ReputationService {
+            local,
+            api_url,
+            ttl: Duration::from_secs(ttl_secs),
+            cache: Mutex::new(HashMap::new()),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(3))
+                .build()
+                .expect("Failed to build HTTP client"),
+            #[cfg(feature = "production")]
+            redis: None,
+        })
+    }
+
+    /// Shares scores between replicas under `reputation:<domain>`.
+    #[cfg(feature = "production")]
+    pub fn with_redis(mut self, redis: Option<Arc<SharedRedis>>) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    pub fn local_entries(&self) -> usize {
+        self.local.len()
+    }
+
+    pub fn has_external(&self) -> bool {
+        self.api_url.is_some()
+    }
+
+    /// Local list and cache only; never goes to the network. Used on read paths.
+    pub fn cached_score(&self, domain: &str) -> Option<u8> {
+        self.cached(&domain.to_ascii_lowercase()).flatten()
+    }
+
+    /// `Some` when `domain` (lowercase) needs no lookup: its score, or
+    /// `None` for a recent miss.
+    fn cached(&self, domain: &str) -> Option<Option<u8>> {
+        if let Some(score) = self.local_score(domain) {
+            return Some(Some(score));
+        }
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(domain)
+            .filter(|(score, at)| at.elapsed() < if score.is_some() { self.ttl } else { MISS_TTL })
+            .map(|(score, _)| *score)
+    }
+
+    /// Full lookup: local list, in-process cache, Redis (production), then the
+    /// external API. Results from the API are cached for the configured TTL,
+    /// misses and failures for [`MISS_TTL`].
+    pub async fn score(&self, domain: &str) -> Option<u8> {
+        let domain = domain.to_ascii_lowercase();
+        self.scores(HashSet::from([domain.clone()]))
+            .await
+            .remove(&domain)
+            .flatten()
+    }
+
+    /// Full lookups of `domains`. Only the first [`MAX_LOOKUPS_PER_BATCH`]
+    /// that aren't known yet go out: to Redis in one round trip, then the
+    /// ones Redis lacks to the API concurrently, whose answers are written
+    /// back in one more. The others get what the local list and cache have.
+    pub async fn scores(&self, domains: HashSet<String>) -> HashMap<String, Option<u8>> {
+        let (known, unknown): (Vec<String>, Vec<String>) = domains
+            .into_iter()
+            .map(|d| d.to_ascii_lowercase())
+            .partition(|d| self.cached(d).is_some());
+        let mut out: HashMap<String, Option<u8>> = known
+            .into_iter()
+            .map(|d| {
+                let score = self.cached_score(&d);
+                (d, score)
+            })
+            .collect();
+        let mut unknown = unknown.into_iter();
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut lookups: Vec<String> = unknown.by_ref().take(MAX_LOOKUPS_PER_BATCH).collect();
+
+        #[cfg(feature = "production")]
+        if let Some(redis) = &self.redis {
+            let shared = redis_get(redis, &lookups).await;
+            lookups.retain(|d| !shared.contains_key(d));
+            for (domain, score) in shared {
+                self.remember(&domain, Some(score));
+                out.insert(domain, Some(score));
+            }
+        }
+
+        let fetched: Vec<(String, Option<u8>)> = futures_util::stream::iter(lookups)
+            .map(|d| async move {
+                let score = self.fetch_external(&d).await;
+                (d, score)
+            })
+            .buffer_unordered(MAX_LOOKUPS_PER_BATCH)
+            .collect()
+            .await;
+        if self.has_external() {
+            for (domain, score) in &fetched {
+                self.remember(domain, *score);
+            }
+        }
+
+        #[cfg(feature = "production")]
+        if let Some(redis) = &self.redis {
+            let found: Vec<(&str, u8)> = fetched
+                .iter()
+                .filter_map(|(d, score)| Some((d.as_str(), (*score)?)))
+                .collect();
+            redis_set(redis, &found, self.ttl).await;
+        }
+
+        out.extend(fetched);
+        out.extend(unknown.map(|d| (d, None)));
+        out
+    }
+
+    fn local_score(&self, domain: &str) -> Option<u8> {
+        // Walk up the labels so an entry for "evil.com" also covers "cdn.evil.com".
+        let mut candidate = domain;
+        loop {
+            if let Some(score) = self.local.get(candidate) {
+                return Some(*score);
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return None,
+            }
+        }
+    }
+
+    fn remember(&self, domain: &str, score: Option<u8>) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(domain.to_string(), (score, Instant::now()));
+        if cache.len() > 10_000 {
+            let ttl = self.ttl;
+            cache.retain(|_, (score, at)| {
+                at.elapsed() < if score.is_some() { ttl } else { MISS_TTL }
+            });
+        }
+    }
+
+    async fn fetch_external(&self, domain: &str) -> Option<u8> {
+        let template = self.api_url.as_ref()?;
+        let url = if template.contains("{domain}") {
+            template.replace("{domain}", domain)
+        } else {
+            format!("{}{}", template, domain)
+        };
+        let resp = match self.http.get(&url).send().await {
+            Ok(r) => r,
+            Err(e) => {
+                log::warn!("⚠️ Reputation lookup failed for {}: {}", domain, e);
+                return None;
+            }
+        };
+        let body: serde_json::Value = match resp.json().await {
+            Ok(b) => b,
+            Err(e) => {
+                log::warn!("⚠️ Reputation response for {} is not JSON: {}", domain, e);
+                return None;
+            }
+        };
+        let score = body.get("score").and_then(|v| v.as_f64())?;
+        Some(score.clamp(0.0, 100.0) as u8)
+    }
+}
+
+#[cfg(feature = "production")]
+fn redis_key(domain: &str) -> String {
+    format!("reputation:{}", domain)
+}
+
+/// The scores Redis has for any of `domains`, in one `MGET`.
+#[cfg(feature = "production")]
+async fn redis_get(redis: &SharedRedis, domains: &[String]) -> HashMap<String, u8> {
+    if domains.is_empty() {
+        return HashMap::new();
+    }
+    let Some(mut conn) = redis.conn().await else {
+        return HashMap::new();
+    };
+    let keys: Vec<String> = domains.iter().map(|d| redis_key(d)).collect();
+    let scores: Vec<Option<u8>> = timed(redis::cmd("MGET").arg(&keys).query_async(&mut conn))
+        .await
+        .unwrap_or_default();
+    domains
+        .iter()
+        .zip(scores)
+        .filter_map(|(d, score)| Some((d.clone(), score?)))
+        .collect()
+}
+
+/// Writes every score in one pipeline, each expiring after `ttl`.
+#[cfg(feature = "production")]
+async fn redis_set(redis: &SharedRedis, scores: &[(&str, u8)], ttl: Duration) {
+    if scores.is_empty() {
+        return;
+    }
+    let Some(mut conn) = redis.conn().await else {
+        return;
+    };
+    let mut pipe = redis::pipe();
+    for (domain, score) in scores {
+        pipe.set_ex(redis_key(domain), *score, ttl.as_secs())
+            .ignore();
+    }
+    timed(pipe.query_async::<_, ()>(&mut conn)).await;
+}
+
+/// One entry per line: `domain score` or `domain,score`. Blank lines and `#`
+/// comments are skipped; a bare domain counts as 100.
+fn parse_local_list(contents: &str) -> HashMap<String, u8> {
+    let mut out = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty());
+        let Some(domain) = parts.next() else {
+            continue;
+        };
+        let score = parts
+            .next()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(100)
+            .min(100);
+        out.insert(domain.to_ascii_lowercase(), score);
+    }
+    out
+}
@@ -0,0 +1,110 @@
+//! Request types dropped at ingest (`--drop-request-type`). Logs of those
+//! types (`image`, `font`, ...) are taken out of each `/api/logs` batch
+//! right after the batch size check, so they are never counted, charged,
+//! scanned or stored.
+
+use crate::metrics;
+use crate::types::LogEntry;
+use actix_web::{web, HttpRequest};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The `type` values the browser's webRequest API reports.
+pub const KNOWN_TYPES: &[&str] = &[
+    "main_frame",
+    "sub_frame",
+    "stylesheet",
+    "script",
+    "image",
+    "font",
+    "object",
+    "xmlhttprequest",
+    "ping",
+    "csp_report",
+    "media",
+    "websocket",
+    "webtransport",
+    "webbundle",
+    "other",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestTypeFilter {
+    dropped: BTreeSet<String>,
+}
+
+/// Logs taken out of one batch, per request type.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(transparent)]
+pub struct Dropped(BTreeMap<String, usize>);
+
+impl Dropped {
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Counts a stored batch's dropped logs in the metrics.
+    pub fn record(&self, client_ip: &str) {
+        if self.is_empty() {
+            return;
+        }
+        metrics::REQUEST_TYPE_DROPPED.add(self.total() as u64);
+        log::debug!("🧹 Dropped logs from IP {}: {:?}", client_ip, self.0);
+    }
+}
+
+impl RequestTypeFilter {
+    /// Type names are matched case-insensitively.
+    pub fn new(types: &[String]) -> Self {
+        RequestTypeFilter {
+            dropped: types
+                .iter()
+                .map(|t| t.trim().to_ascii_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The filter registered as app data; drops nothing when there is none.
+    pub fn for_request(req: &HttpRequest) -> web::Data<Self> {
+        req.app_data::<web::Data<Self>>()
+            .cloned()
+            .unwrap_or_else(|| web::Data::new(Self::default()))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.dropped.is_empty()
+    }
+
+    pub fn types(&self) -> Vec<&str> {
+        self.dropped.iter().map(String::as_str).collect()
+    }
+
+    /// Configured types the browser never reports, most likely typos.
+    pub fn unknown_types(&self) -> Vec<&str> {
+        self.types()
+            .into_iter()
+            .filter(|t| !KNOWN_TYPES.contains(t))
+            .collect()
+    }
+
+    /// Takes the logs of dropped types out of `entry`.
+    pub fn apply(&self, entry: &mut LogEntry) -> Dropped {
+        let mut dropped = Dropped::default();
+        if !self.is_enabled() {
+            return dropped;
+        }
+        entry.logs.retain(|log| {
+            let request_type = log.request_type.to_ascii_lowercase();
+            if !self.dropped.contains(&request_type) {
+                return true;
+            }
+            *dropped.0.entry(request_type).or_default() += 1;
+            false
+        });
+        dropped
+    }
+}
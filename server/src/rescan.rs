@@ -0,0 +1,505 @@
+//! Re-scans of stored logs and events (the `rescan` job), so a detection
+//! rule or blocklist pattern added today also finds what was sent before it
+//! existed.
+//!
+//! Run on its own (`POST /api/admin/jobs/rescan/run`, or on a
+//! `--job-schedule rescan=...`), the job checks what was sent in the last
+//! [`DEFAULT_WINDOW_DAYS`] days against the enabled detection rules created
+//! or changed since the previous complete rescan (the server start before
+//! the first) and the blocklist URL patterns added since then.
+//! `POST /api/admin/rescan` names the rules, patterns and time range
+//! instead and starts the job.
+//!
+//! Findings are stored as security events: `detection_rule_match` for a
+//! rule, as if it had matched live, and [`PATTERN_EVENT`] for a pattern,
+//! one per batch or event that matched. Both carry `backfilled: true` and
+//! the `source_timestamp` of what matched, and go through the event chain,
+//! the WORM export and the alert rules. The job reports how many stored
+//! logs and events it has read in `GET /api/admin/jobs`, and
+//! `POST /api/admin/jobs/rescan/cancel` stops it after the current page.
+
+use crate::blocklist_history::BlocklistHistory;
+use crate::detection::{self, ActiveRule, DetectionRules, RuleMatch, Target};
+use crate::error::ApiError;
+use crate::handlers::common::domain_from_url;
+use crate::handlers::extensions::server_security_event;
+use crate::scheduler::{JobControl, JobOptions, Schedule, Scheduler};
+use crate::types::{Blocklist, ExtensionEvent, LogEntry};
+use crate::{metrics, Backend};
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+pub const JOB: &str = "rescan";
+/// The event a blocklist pattern raises for a batch it matches.
+pub const PATTERN_EVENT: &str = "blocklist_pattern_match";
+/// How far back the job reads when nothing says otherwise.
+pub const DEFAULT_WINDOW_DAYS: i64 = 7;
+/// Stored logs or events read between progress reports and cancellation
+/// checks.
+const PAGE: usize = 1000;
+/// URLs kept in a pattern finding, as for a rule's.
+const SAMPLE_URLS: usize = 5;
+
+/// What to rescan, as `POST /api/admin/rescan` names it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RescanRequest {
+    /// Enabled detection rules, by id; checked at their current version.
+    #[serde(default)]
+    pub rules: Vec<String>,
+    /// URL patterns (regular expressions), on the blocklist or not.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// [`DEFAULT_WINDOW_DAYS`] before `to` by default.
+    pub from: Option<DateTime<Utc>>,
+    /// Now by default.
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// A checked request.
+struct Scope {
+    rules: Vec<Arc<ActiveRule>>,
+    patterns: Vec<(String, Regex)>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    /// Patterns left out because they aren't valid here.
+    skipped: Vec<String>,
+}
+
+impl Scope {
+    fn new(request: &RescanRequest, rules: &DetectionRules) -> Result<Scope, ApiError> {
+        if request.rules.is_empty() && request.patterns.is_empty() {
+            return Err(ApiError::BadRequest(
+                "name the 'rules' or 'patterns' to rescan with".to_string(),
+            ));
+        }
+        let to = request.to.unwrap_or_else(Utc::now);
+        let from = request
+            .from
+            .unwrap_or(to - Duration::days(DEFAULT_WINDOW_DAYS));
+        if from >= to {
+            return Err(ApiError::BadRequest(
+                "'from' must be before 'to'".to_string(),
+            ));
+        }
+        let active = rules.active();
+        let mut selected = Vec::new();
+        for id in &request.rules {
+            if rules.get(id).is_none() {
+                return Err(
+                    ApiError::NotFound("no such detection rule".to_string()).with("rule", id)
+                );
+            }
+            let rule = active.iter().find(|r| &r.id == id).ok_or_else(|| {
+                ApiError::BadRequest(format!("rule {} is disabled", id)).with("rule", id)
+            })?;
+            selected.push(rule.clone());
+        }
+        let mut patterns = Vec::new();
+        for pattern in &request.patterns {
+            let regex = Regex::new(pattern).map_err(|e| {
+                ApiError::BadRequest(format!("'{}' is not a valid pattern: {}", pattern, e))
+            })?;
+            patterns.push((pattern.clone(), regex));
+        }
+        Ok(Scope {
+            rules: selected,
+            patterns,
+            from,
+            to,
+            skipped: Vec::new(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.patterns.is_empty()
+    }
+
+    fn has(&self, target: Target) -> bool {
+        self.rules.iter().any(|r| r.rule.target == target)
+    }
+}
+
+/// What the next run of the job scans.
+pub struct Rescans {
+    queued: Mutex<Option<RescanRequest>>,
+    /// When the last complete automatic rescan started.
+    since: Mutex<DateTime<Utc>>,
+}
+
+impl Default for Rescans {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rescans {
+    pub fn new() -> Self {
+        Rescans {
+            queued: Mutex::new(None),
+            since: Mutex::new(Utc::now()),
+        }
+    }
+
+    /// Checks `request` and keeps it for the next run.
+    pub fn queue(&self, request: RescanRequest, rules: &DetectionRules) -> Result<(), ApiError> {
+        Scope::new(&request, rules)?;
+        *self.queued.lock().unwrap() = Some(request);
+        Ok(())
+    }
+
+    /// The enabled rules and blocklist patterns changed since the last
+    /// complete rescan, over the default window.
+    fn changes(
+        &self,
+        rules: &DetectionRules,
+        history: &BlocklistHistory,
+        blocklist: &Blocklist,
+        now: DateTime<Utc>,
+    ) -> Scope {
+        let since = *self.since.lock().unwrap();
+        let changed: Vec<String> = rules
+            .list()
+            .into_iter()
+            .filter(|r| r.current().changed_at > since)
+            .map(|r| r.id)
+            .collect();
+        let rules = rules
+            .active()
+            .iter()
+            .filter(|r| changed.contains(&r.id))
+            .cloned()
+            .collect();
+        let (mut patterns, mut skipped) = (Vec::new(), Vec::new());
+        for pattern in history.patterns_added_since(blocklist, since) {
+            match Regex::new(&pattern) {
+                Ok(regex) => patterns.push((pattern, regex)),
+                Err(_) => skipped.push(pattern),
+            }
+        }
+        Scope {
+            rules,
+            patterns,
+            from: now - Duration::days(DEFAULT_WINDOW_DAYS),
+            to: now,
+            skipped,
+        }
+    }
+}
+
+/// A finding, marked as backfilled.
+fn finding(
+    client_id: Option<&str>,
+    session_id: &str,
+    source_timestamp: &str,
+    event_type: &str,
+    mut data: serde_json::Value,
+) -> ExtensionEvent {
+    data["backfilled"] = serde_json::json!(true);
+    data["source_timestamp"] = serde_json::json!(source_timestamp);
+    let (packet_id, mut event) =
+        server_security_event(client_id.map(str::to_string), session_id, event_type, data);
+    metrics::RESCAN_FINDINGS.inc();
+    log::warn!(
+        "🔁 Backfilled {} for client_id={} from {} (packet_id={})",
+        event_type,
+        client_id.unwrap_or("-"),
+        source_timestamp,
+        packet_id
+    );
+    crate::chain::link(&mut event);
+    crate::worm::append(&event);
+    crate::alerts::raise(&event);
+    event
+}
+
+fn rule_finding(
+    found: RuleMatch,
+    client_id: Option<&str>,
+    session_id: &str,
+    source_timestamp: &str,
+) -> ExtensionEvent {
+    let data = found.to_event_data();
+    finding(
+        client_id,
+        session_id,
+        source_timestamp,
+        detection::MATCH_EVENT,
+        data,
+    )
+}
+
+/// One run's scan, page by page.
+struct Scan {
+    scope: Scope,
+    control: Arc<JobControl>,
+    read: u64,
+    total: Option<u64>,
+    checked: u64,
+    found: u64,
+}
+
+impl Scan {
+    fn in_range(&self, timestamp: &str) -> bool {
+        detection::sent_at(timestamp).is_some_and(|t| t >= self.scope.from && t < self.scope.to)
+    }
+
+    fn logs(&mut self, entries: &[LogEntry]) -> Vec<ExtensionEvent> {
+        let mut findings = Vec::new();
+        for entry in entries {
+            if !self.in_range(&entry.timestamp) {
+                continue;
+            }
+            self.checked += 1;
+            let client_id = entry.client_id.as_deref();
+            for found in detection::match_logs(&self.scope.rules, entry) {
+                findings.push(rule_finding(
+                    found,
+                    client_id,
+                    &entry.session_id,
+                    &entry.timestamp,
+                ));
+            }
+            for (pattern, regex) in &self.scope.patterns {
+                let urls: Vec<&str> = entry
+                    .logs
+                    .iter()
+                    .map(|l| l.url.as_str())
+                    .filter(|u| regex.is_match(u))
+                    .collect();
+                let Some(url) = urls.first() else {
+                    continue;
+                };
+                let data = serde_json::json!({
+                    "url": url,
+                    "host": domain_from_url(url),
+                    "pattern": pattern,
+                    "matches": urls.len(),
+                    "urls": urls.iter().take(SAMPLE_URLS).collect::<Vec<_>>()
+                });
+                findings.push(finding(
+                    client_id,
+                    &entry.session_id,
+                    &entry.timestamp,
+                    PATTERN_EVENT,
+                    data,
+                ));
+            }
+        }
+        self.found += findings.len() as u64;
+        findings
+    }
+
+    fn events(&mut self, events: &[ExtensionEvent]) -> Vec<ExtensionEvent> {
+        let mut findings = Vec::new();
+        let own = [detection::MATCH_EVENT, PATTERN_EVENT];
+        for event in events {
+            if own.contains(&event.event_type.as_str()) || !self.in_range(&event.timestamp) {
+                continue;
+            }
+            self.checked += 1;
+            let client_id = event.client_id.as_deref();
+            for found in detection::match_event(&self.scope.rules, event) {
+                findings.push(rule_finding(
+                    found,
+                    client_id,
+                    &event.session_id,
+                    &event.timestamp,
+                ));
+            }
+        }
+        self.found += findings.len() as u64;
+        findings
+    }
+
+    /// Counts a page as read; an error once the run was cancelled.
+    fn page_read(&mut self, rows: usize) -> Result<(), String> {
+        self.read += rows as u64;
+        self.control.progress(self.read, self.total);
+        if self.control.is_cancelled() {
+            return Err(format!(
+                "cancelled after reading {} stored logs and events ({} findings)",
+                self.read, self.found
+            ));
+        }
+        Ok(())
+    }
+}
+
+async fn read_blocklist(backend: &Backend) -> Result<Blocklist, String> {
+    match backend {
+        Backend::Simple(state) => Ok(state.get_blocklist()),
+        #[cfg(feature = "production")]
+        Backend::Production { state, .. } | Backend::Hybrid { warm: state, .. } => {
+            state.get_blocklist().await.map_err(|e| e.to_string())
+        }
+    }
+}
+
+async fn store(backend: &Backend, findings: Vec<ExtensionEvent>) -> Result<(), String> {
+    for event in findings {
+        match backend {
+            Backend::Simple(state) => state.add_extension_event(event),
+            #[cfg(feature = "production")]
+            Backend::Hybrid { hot, .. } => hot.add_extension_event(event),
+            #[cfg(feature = "production")]
+            Backend::Production { state, .. } => state
+                .add_extension_event(event)
+                .await
+                .map_err(|e| format!("storing a finding failed: {}", e))?,
+        }
+    }
+    Ok(())
+}
+
+async fn scan(backend: &Backend, scan: &mut Scan) -> Result<(), String> {
+    let (logs, events) = (
+        scan.scope.has(Target::Logs) || !scan.scope.patterns.is_empty(),
+        scan.scope.has(Target::Events),
+    );
+    match backend {
+        Backend::Simple(state) => {
+            let logs = if logs { state.get_logs() } else { Vec::new() };
+            let events = if events {
+                state.get_extension_events()
+            } else {
+                Vec::new()
+            };
+            scan.total = Some((logs.len() + events.len()) as u64);
+            for page in logs.chunks(PAGE) {
+                let findings = scan.logs(page);
+                store(backend, findings).await?;
+                scan.page_read(page.len())?;
+            }
+            for page in events.chunks(PAGE) {
+                let findings = scan.events(page);
+                store(backend, findings).await?;
+                scan.page_read(page.len())?;
+            }
+        }
+        #[cfg(feature = "production")]
+        Backend::Production { state, .. } | Backend::Hybrid { warm: state, .. } => {
+            // Findings stored while scanning are newer than `as_of`.
+            let as_of = Utc::now();
+            if logs {
+                let mut after = 0;
+                loop {
+                    let page = state
+                        .export_logs(after, as_of, PAGE as i64)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    let Some((last, _)) = page.last() else {
+                        break;
+                    };
+                    after = *last;
+                    let entries: Vec<LogEntry> = page.into_iter().map(|(_, e)| e).collect();
+                    let findings = scan.logs(&entries);
+                    store(backend, findings).await?;
+                    scan.page_read(entries.len())?;
+                }
+            }
+            if events {
+                let mut after = 0;
+                loop {
+                    let page = state
+                        .export_extension_events(after, as_of, PAGE as i64)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    let Some((last, _)) = page.last() else {
+                        break;
+                    };
+                    after = *last;
+                    let stored: Vec<ExtensionEvent> = page.into_iter().map(|(_, e)| e).collect();
+                    let findings = scan.events(&stored);
+                    store(backend, findings).await?;
+                    scan.page_read(stored.len())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One run of the job: the queued request, or what changed since the last
+/// complete rescan.
+pub async fn job(
+    backend: Backend,
+    rescans: web::Data<Rescans>,
+    rules: web::Data<DetectionRules>,
+    history: web::Data<BlocklistHistory>,
+    control: Arc<JobControl>,
+) -> Result<String, String> {
+    let started = Utc::now();
+    let queued = rescans.queued.lock().unwrap().take();
+    let automatic = queued.is_none();
+    let scope = match queued {
+        Some(request) => Scope::new(&request, &rules).map_err(|e| e.to_string())?,
+        None => {
+            let blocklist = read_blocklist(&backend).await?;
+            rescans.changes(&rules, &history, &blocklist, started)
+        }
+    };
+    let skipped = match scope.skipped.len() {
+        0 => String::new(),
+        n => format!(" ({} invalid patterns skipped)", n),
+    };
+    if scope.is_empty() {
+        if automatic {
+            *rescans.since.lock().unwrap() = started;
+        }
+        return Ok(format!(
+            "no new rules or patterns to rescan with{}",
+            skipped
+        ));
+    }
+    let mut run = Scan {
+        scope,
+        control,
+        read: 0,
+        total: None,
+        checked: 0,
+        found: 0,
+    };
+    scan(&backend, &mut run).await?;
+    if automatic {
+        *rescans.since.lock().unwrap() = started;
+    }
+    Ok(format!(
+        "{} rules and {} patterns, {} to {}: {} of {} stored records in range, {} findings{}",
+        run.scope.rules.len(),
+        run.scope.patterns.len(),
+        run.scope.from.to_rfc3339(),
+        run.scope.to.to_rfc3339(),
+        run.checked,
+        run.read,
+        run.found,
+        skipped
+    ))
+}
+
+/// Registers the job on `scheduler`; it runs only when started unless
+/// `--job-schedule rescan=...` says otherwise.
+pub fn register(
+    scheduler: &Scheduler,
+    backend: Backend,
+    rescans: web::Data<Rescans>,
+    rules: web::Data<DetectionRules>,
+    history: web::Data<BlocklistHistory>,
+) {
+    scheduler.register_cancellable(
+        JOB,
+        Schedule::manual(),
+        JobOptions::default(),
+        move |control| {
+            job(
+                backend.clone(),
+                rescans.clone(),
+                rules.clone(),
+                history.clone(),
+                control,
+            )
+        },
+    );
+}
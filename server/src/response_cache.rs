@@ -0,0 +1,120 @@
+//! A short-lived cache in front of `/api/stats` and
+//! `/api/clients/{client_id}/summary`, so a dashboard refreshing every few
+//! seconds (or several open at once) doesn't run the same aggregate queries
+//! each time.
+//!
+//! A response is reused for `--stats-cache-ttl`, or until
+//! `--stats-cache-invalidate-after` log entries have been ingested since it
+//! was computed, whichever comes first. The cache is per replica and kept in
+//! memory.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Responses kept at most; the oldest goes first.
+const MAX_ENTRIES: usize = 1000;
+
+struct Entry {
+    stored: Instant,
+    /// `ingested` when the response was computed.
+    ingested: u64,
+    value: serde_json::Value,
+}
+
+pub struct ResponseCache {
+    ttl: Duration,
+    invalidate_after: u64,
+    ingested: AtomicU64,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    /// `ttl` of zero turns the cache off; `invalidate_after` of zero never
+    /// drops a response for new data.
+    pub fn new(ttl: Duration, invalidate_after: u64) -> Self {
+        ResponseCache {
+            ttl,
+            invalidate_after,
+            ingested: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(Duration::ZERO, 0)
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn fresh(&self, entry: &Entry, ingested: u64) -> bool {
+        entry.stored.elapsed() < self.ttl
+            && (self.invalidate_after == 0 || ingested - entry.ingested < self.invalidate_after)
+    }
+
+    /// Counts `logs` new log entries towards the invalidation threshold.
+    pub fn record_ingest(&self, logs: usize) {
+        self.ingested.fetch_add(logs as u64, Ordering::Relaxed);
+    }
+
+    /// The response stored under `key`, if it is still fresh.
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        if !self.enabled() {
+            return None;
+        }
+        let ingested = self.ingested.load(Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap();
+        let hit = match entries.get(key) {
+            Some(entry) if self.fresh(entry, ingested) => Some(entry.value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        match hit {
+            Some(_) => crate::metrics::STATS_CACHE_HITS.inc(),
+            None => crate::metrics::STATS_CACHE_MISSES.inc(),
+        }
+        hit
+    }
+
+    pub fn put(&self, key: String, value: &serde_json::Value) {
+        if !self.enabled() {
+            return;
+        }
+        let ingested = self.ingested.load(Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, e| self.fresh(e, ingested));
+        }
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.stored)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                stored: Instant::now(),
+                ingested,
+                value: value.clone(),
+            },
+        );
+    }
+
+    /// Drops every stored response.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+}
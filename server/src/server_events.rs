@@ -0,0 +1,77 @@
+//! Operational events recorded by the server itself (startup, configuration
+//! changes, database outages) with category "server", so the dashboard
+//! timeline shows them next to client events.
+
+use crate::handlers::extensions::{server_event, server_security_event};
+use crate::simple::SimpleState;
+use crate::types::ExtensionEvent;
+use std::sync::OnceLock;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+static SINK: OnceLock<UnboundedSender<ExtensionEvent>> = OnceLock::new();
+
+/// Queues a server event for the event store. Events raised before a store
+/// is attached are only logged.
+pub fn record(event_type: &str, data: serde_json::Value) {
+    let (packet_id, event) = server_event("server", None, "server", event_type, data);
+    log::debug!("🖥️ Server event {} (packet_id={})", event_type, packet_id);
+    if let Some(tx) = SINK.get() {
+        let _ = tx.send(event);
+    }
+}
+
+/// Queues a security event raised outside a request (e.g. by the upload
+/// scanner), hands it to the alert rules and returns its packet id.
+pub fn record_security(
+    client_id: Option<String>,
+    event_type: &str,
+    data: serde_json::Value,
+) -> String {
+    let (packet_id, mut event) = server_security_event(client_id, "server", event_type, data);
+    crate::chain::link(&mut event);
+    crate::worm::append(&event);
+    crate::alerts::raise(&event);
+    if let Some(tx) = SINK.get() {
+        let _ = tx.send(event);
+    }
+    packet_id
+}
+
+/// Stores server events in memory (simple mode, and hybrid mode's hot tier,
+/// which forwards them to the database like any other event).
+pub fn attach_simple(state: actix_web::web::Data<SimpleState>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ExtensionEvent>();
+    if SINK.set(tx).is_err() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            state.add_extension_event(event);
+        }
+    });
+}
+
+/// Stores server events in the database. A write that fails (typically
+/// because the database is the thing that went down) is retried until it
+/// succeeds, so the outage itself ends up on the timeline.
+#[cfg(feature = "production")]
+pub fn attach_production(state: std::sync::Arc<crate::production::ProductionState>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ExtensionEvent>();
+    if SINK.set(tx).is_err() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let mut delay = std::time::Duration::from_secs(1);
+            while let Err(e) = state.add_extension_event(event.clone()).await {
+                log::debug!(
+                    "⚠️ Server event write failed, retrying in {}s: {}",
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(std::time::Duration::from_secs(60));
+            }
+        }
+    });
+}
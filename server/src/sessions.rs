@@ -0,0 +1,175 @@
+//! Duplicate session detection: a browser session_id is random per extension
+//! install, so one reported by several client_ids, or from more source IPs
+//! than `--session-max-ips`, points at a cloned extension or forged
+//! payloads. Each new client_id or IP that puts a session over the limit
+//! raises a `session_conflict` security event; the sessions in conflict are
+//! listed at `/api/admin/session-conflicts`.
+//!
+//! Sessions unseen for [`SESSION_TTL`] are forgotten.
+
+use crate::server_events;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+pub const SESSION_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+/// Distinct client_ids / IPs remembered per session.
+pub const MAX_VALUES: usize = 50;
+/// Forgotten sessions are swept every this many observations.
+const SWEEP_EVERY: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictReason {
+    MultipleClients,
+    MultipleIps,
+}
+
+impl ConflictReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConflictReason::MultipleClients => "multiple_clients",
+            ConflictReason::MultipleIps => "multiple_ips",
+        }
+    }
+}
+
+/// One session reported by more than one client or too many IPs.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionConflict {
+    pub session_id: String,
+    pub reasons: BTreeSet<ConflictReason>,
+    pub client_ids: BTreeSet<String>,
+    pub ips: BTreeSet<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub detected_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Seen {
+    client_ids: BTreeSet<String>,
+    ips: BTreeSet<String>,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+    detected_at: Option<DateTime<Utc>>,
+}
+
+pub struct SessionTracker {
+    max_ips: usize,
+    sessions: Mutex<(u64, HashMap<String, Seen>)>,
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl SessionTracker {
+    pub fn new(max_ips: usize) -> Self {
+        SessionTracker {
+            max_ips: max_ips.max(1),
+            sessions: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    fn reasons(&self, seen: &Seen) -> BTreeSet<ConflictReason> {
+        let mut reasons = BTreeSet::new();
+        if seen.client_ids.len() > 1 {
+            reasons.insert(ConflictReason::MultipleClients);
+        }
+        if seen.ips.len() > self.max_ips {
+            reasons.insert(ConflictReason::MultipleIps);
+        }
+        reasons
+    }
+
+    fn conflict(&self, session_id: &str, seen: &Seen) -> SessionConflict {
+        SessionConflict {
+            session_id: session_id.to_string(),
+            reasons: self.reasons(seen),
+            client_ids: seen.client_ids.clone(),
+            ips: seen.ips.clone(),
+            first_seen: seen.first_seen.unwrap_or_default(),
+            last_seen: seen.last_seen.unwrap_or_default(),
+            detected_at: seen.detected_at,
+        }
+    }
+
+    /// Records that `session_id` was reported by `client_id` from `ip`, and
+    /// raises a security event when that is new and puts the session over a
+    /// limit.
+    pub fn observe(&self, session_id: &str, client_id: Option<&str>, ip: &str) {
+        if session_id.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let conflict = {
+            let mut guard = self.sessions.lock().unwrap();
+            let (observations, sessions) = &mut *guard;
+            *observations += 1;
+            if *observations % SWEEP_EVERY == 0 {
+                let cutoff = now - chrono::Duration::from_std(SESSION_TTL).unwrap_or_default();
+                sessions.retain(|_, s| s.last_seen.is_some_and(|at| at >= cutoff));
+            }
+            let seen = sessions.entry(session_id.to_string()).or_default();
+            seen.first_seen.get_or_insert(now);
+            seen.last_seen = Some(now);
+            let mut new_value = false;
+            if let Some(client_id) = client_id.filter(|c| !c.is_empty()) {
+                if seen.client_ids.len() < MAX_VALUES && !seen.client_ids.contains(client_id) {
+                    new_value |= seen.client_ids.insert(client_id.to_string());
+                }
+            }
+            if seen.ips.len() < MAX_VALUES && !seen.ips.contains(ip) {
+                new_value |= seen.ips.insert(ip.to_string());
+            }
+            if new_value && !self.reasons(seen).is_empty() {
+                seen.detected_at.get_or_insert(now);
+                Some(self.conflict(session_id, seen))
+            } else {
+                None
+            }
+        };
+        let Some(conflict) = conflict else {
+            return;
+        };
+        log::warn!(
+            "👥 Session {} reported by clients {:?} from IPs {:?}",
+            session_id,
+            conflict.client_ids,
+            conflict.ips
+        );
+        let reasons: Vec<&str> = conflict.reasons.iter().map(|r| r.as_str()).collect();
+        server_events::record_security(
+            client_id.map(str::to_string),
+            "session_conflict",
+            serde_json::json!({
+                "session_id": session_id,
+                "reasons": reasons,
+                "client_ids": conflict.client_ids,
+                "ips": conflict.ips,
+                "first_seen": conflict.first_seen,
+            }),
+        );
+    }
+
+    /// Sessions currently in conflict, most recently seen first.
+    pub fn conflicts(&self) -> Vec<SessionConflict> {
+        let guard = self.sessions.lock().unwrap();
+        let mut out: Vec<SessionConflict> = guard
+            .1
+            .iter()
+            .filter(|(_, seen)| !self.reasons(seen).is_empty())
+            .map(|(id, seen)| self.conflict(id, seen))
+            .collect();
+        out.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then(a.session_id.cmp(&b.session_id))
+        });
+        out
+    }
+}
@@ -0,0 +1,416 @@
+//! Storage sharding by org for data residency. The `--orgs` file can name
+//! extra databases under `shards` and give an org a `shard`:
+//!
+//! ```json
+//! { "shards": { "eu": "postgres://db.eu.internal/canigoin" },
+//!   "orgs": { "acme-gmbh": { "client_prefixes": ["acme-de-"], "shard": "eu" } } }
+//! ```
+//!
+//! [`ShardedStorage`] sits in front of the [`Storage`] backends: the logs
+//! and extension events of an org's clients are written to and read from
+//! its shard only, everything else (and clients of orgs without a shard)
+//! stays in `--database-url`, the home database. Server-wide reads ask every
+//! database and merge the answers; the control-plane tables (blocklist,
+//! archive, annotations, triage, tickets, extension errors, users) live in
+//! the home database alone. Each shard needs the same schema as the home
+//! database.
+//!
+//! Only the databases are sharded: the lake copy, request captures, backups
+//! and WORM sinks still hold every org's data in one place.
+
+use crate::metering::OrgsConfig;
+use crate::storage::{self, PoolConfig, PoolStatus, Storage};
+use crate::types::{
+    Annotation, ArchivedClient, Blocklist, CaseTicket, CategoryStats, ClientStats, DomainStats,
+    EventComment, EventTriage, ExtensionError, ExtensionEvent, LocalUser, LogEntry, ObservedDomain,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Shard {
+    pub name: String,
+    pub storage: Arc<dyn Storage>,
+}
+
+pub struct ShardedStorage {
+    home: Box<dyn Storage>,
+    shards: Vec<Shard>,
+    orgs: OrgsConfig,
+}
+
+/// Opens every database `orgs` names under `shards`.
+pub async fn connect_shards(
+    orgs: &OrgsConfig,
+    pool: &PoolConfig,
+) -> Result<Vec<Shard>, Box<dyn std::error::Error>> {
+    let mut shards = Vec::new();
+    for (name, url) in &orgs.shards {
+        log::info!("🗺️ Connecting to shard {}", name);
+        let storage = storage::connect(url, pool)
+            .await
+            .map_err(|e| format!("shard {}: {}", name, e))?;
+        shards.push(Shard {
+            name: name.clone(),
+            storage: Arc::from(storage),
+        });
+    }
+    Ok(shards)
+}
+
+/// Sort key of a stored timestamp; backends return RFC 3339 or Postgres'
+/// `2025-01-28 12:00:00+00` text.
+fn timestamp_key(ts: &str) -> (Option<DateTime<Utc>>, String) {
+    let parsed = DateTime::parse_from_rfc3339(ts)
+        .or_else(|_| DateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S%.f%#z"))
+        .ok()
+        .map(|t| t.with_timezone(&Utc));
+    (parsed, ts.to_string())
+}
+
+impl ShardedStorage {
+    /// `shards` are the databases `orgs` names; the rest stays in `home`.
+    pub fn new(home: Box<dyn Storage>, shards: Vec<Shard>, orgs: OrgsConfig) -> Self {
+        ShardedStorage { home, shards, orgs }
+    }
+
+    /// The database `client_id`'s logs and events belong in.
+    fn for_client(&self, client_id: Option<&str>) -> &dyn Storage {
+        let Some(name) = self.orgs.shard_of(client_id) else {
+            return self.home.as_ref();
+        };
+        self.shards
+            .iter()
+            .find(|s| s.name == name)
+            .map_or(self.home.as_ref(), |s| s.storage.as_ref())
+    }
+
+    /// Every database, the home one first.
+    fn all(&self) -> Vec<&dyn Storage> {
+        std::iter::once(self.home.as_ref())
+            .chain(self.shards.iter().map(|s| s.storage.as_ref()))
+            .collect()
+    }
+
+    /// Row ids are per database: a merged id is `local * n + index`, so
+    /// paging with the last merged id seen works across all of them.
+    fn merged_id(&self, local: i64, index: usize) -> i64 {
+        local * (self.shards.len() as i64 + 1) + index as i64
+    }
+
+    fn local_after(&self, after_id: i64, index: usize) -> i64 {
+        (after_id - index as i64).div_euclid(self.shards.len() as i64 + 1)
+    }
+}
+
+#[async_trait]
+impl Storage for ShardedStorage {
+    fn pool_status(&self) -> PoolStatus {
+        self.home.pool_status()
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        for db in self.all() {
+            db.ping().await?;
+        }
+        Ok(())
+    }
+
+    async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error> {
+        self.for_client(entry.client_id.as_deref())
+            .add_log(entry)
+            .await
+    }
+
+    async fn import_log(&self, entry: LogEntry) -> Result<usize, sqlx::Error> {
+        self.for_client(entry.client_id.as_deref())
+            .import_log(entry)
+            .await
+    }
+
+    async fn get_domains(
+        &self,
+        first_seen_after: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error> {
+        let mut merged: HashMap<String, ObservedDomain> = HashMap::new();
+        for db in self.all() {
+            for d in db.get_domains(first_seen_after).await? {
+                match merged.get_mut(&d.domain) {
+                    Some(m) => {
+                        m.first_seen = m.first_seen.min(d.first_seen);
+                        m.last_seen = m.last_seen.max(d.last_seen);
+                        m.request_count += d.request_count;
+                        // Clients are in one database each.
+                        m.client_count += d.client_count;
+                    }
+                    None => {
+                        merged.insert(d.domain.clone(), d);
+                    }
+                }
+            }
+        }
+        let mut domains: Vec<ObservedDomain> = merged.into_values().collect();
+        domains.sort_by(|a, b| {
+            b.first_seen
+                .cmp(&a.first_seen)
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        Ok(domains)
+    }
+
+    async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
+        self.home.get_blocklist().await
+    }
+
+    async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error> {
+        self.home.update_blocklist(blocklist).await
+    }
+
+    async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
+        self.for_client(event.client_id.as_deref())
+            .add_extension_event(event)
+            .await
+    }
+
+    async fn get_logs_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        let mut logs = Vec::new();
+        for db in self.all() {
+            logs.extend(db.get_logs_before(before, limit).await?);
+        }
+        logs.sort_by_cached_key(|e| timestamp_key(&e.timestamp));
+        let excess = logs.len().saturating_sub(limit.max(0) as usize);
+        logs.drain(..excess);
+        Ok(logs)
+    }
+
+    async fn get_client_logs(
+        &self,
+        client_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        self.for_client(Some(client_id))
+            .get_client_logs(client_id, from, to, limit)
+            .await
+    }
+
+    async fn export_logs(
+        &self,
+        after_id: i64,
+        as_of: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error> {
+        let mut rows = Vec::new();
+        for (index, db) in self.all().into_iter().enumerate() {
+            let page = db
+                .export_logs(self.local_after(after_id, index), as_of, limit)
+                .await?;
+            rows.extend(
+                page.into_iter()
+                    .map(|(id, entry)| (self.merged_id(id, index), entry)),
+            );
+        }
+        rows.sort_by_key(|(id, _)| *id);
+        rows.truncate(limit.max(0) as usize);
+        Ok(rows)
+    }
+
+    async fn export_extension_events(
+        &self,
+        after_id: i64,
+        as_of: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error> {
+        let mut rows = Vec::new();
+        for (index, db) in self.all().into_iter().enumerate() {
+            let page = db
+                .export_extension_events(self.local_after(after_id, index), as_of, limit)
+                .await?;
+            rows.extend(
+                page.into_iter()
+                    .map(|(id, event)| (self.merged_id(id, index), event)),
+            );
+        }
+        rows.sort_by_key(|(id, _)| *id);
+        rows.truncate(limit.max(0) as usize);
+        Ok(rows)
+    }
+
+    async fn get_extension_events_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error> {
+        let mut events = Vec::new();
+        for db in self.all() {
+            events.extend(db.get_extension_events_before(before, limit).await?);
+        }
+        events.sort_by_cached_key(|e| timestamp_key(&e.timestamp));
+        let excess = events.len().saturating_sub(limit.max(0) as usize);
+        events.drain(..excess);
+        Ok(events)
+    }
+
+    async fn get_extension_event_by_packet_id(
+        &self,
+        packet_id: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error> {
+        for db in self.all() {
+            if let Some(event) = db.get_extension_event_by_packet_id(packet_id).await? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn refresh_stats(&self) -> Result<(), sqlx::Error> {
+        for db in self.all() {
+            db.refresh_stats().await?;
+        }
+        Ok(())
+    }
+
+    /// Each database's top `limit`, merged: a domain just outside the top
+    /// of several databases can be missing.
+    async fn get_stats(
+        &self,
+        limit: i64,
+    ) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error> {
+        let mut domains: HashMap<String, DomainStats> = HashMap::new();
+        let mut clients = Vec::new();
+        for db in self.all() {
+            let (d, c) = db.get_stats(limit).await?;
+            for d in d {
+                match domains.get_mut(&d.domain) {
+                    Some(m) => {
+                        m.requests += d.requests;
+                        m.blocked += d.blocked;
+                        m.clients += d.clients;
+                        m.last_seen = m.last_seen.max(d.last_seen);
+                    }
+                    None => {
+                        domains.insert(d.domain.clone(), d);
+                    }
+                }
+            }
+            clients.extend(c);
+        }
+        let mut domains: Vec<DomainStats> = domains.into_values().collect();
+        domains.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        domains.truncate(limit.max(0) as usize);
+        clients.sort_by(|a: &ClientStats, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.client_id.cmp(&b.client_id))
+        });
+        clients.truncate(limit.max(0) as usize);
+        Ok((domains, clients))
+    }
+
+    /// `domains` is summed across databases, so a domain requested in two
+    /// of them counts twice.
+    async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error> {
+        let mut merged: HashMap<String, CategoryStats> = HashMap::new();
+        for db in self.all() {
+            for c in db.get_category_stats().await? {
+                match merged.get_mut(&c.category) {
+                    Some(m) => {
+                        m.requests += c.requests;
+                        m.blocked += c.blocked;
+                        m.domains += c.domains;
+                        m.clients += c.clients;
+                    }
+                    None => {
+                        merged.insert(c.category.clone(), c);
+                    }
+                }
+            }
+        }
+        let mut categories: Vec<CategoryStats> = merged.into_values().collect();
+        categories.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.category.cmp(&b.category))
+        });
+        Ok(categories)
+    }
+
+    async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error> {
+        self.home.get_archived_clients().await
+    }
+
+    async fn archive_client(&self, client: &ArchivedClient) -> Result<(), sqlx::Error> {
+        self.home.archive_client(client).await
+    }
+
+    async fn unarchive_client(&self, client_id: &str) -> Result<bool, sqlx::Error> {
+        self.home.unarchive_client(client_id).await
+    }
+
+    async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error> {
+        self.home.get_annotations().await
+    }
+
+    async fn add_annotation(&self, annotation: &Annotation) -> Result<i64, sqlx::Error> {
+        self.home.add_annotation(annotation).await
+    }
+
+    async fn delete_annotation(&self, id: i64) -> Result<bool, sqlx::Error> {
+        self.home.delete_annotation(id).await
+    }
+
+    async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error> {
+        self.home.get_event_triage().await
+    }
+
+    async fn set_event_triage(&self, triage: &EventTriage) -> Result<(), sqlx::Error> {
+        self.home.set_event_triage(triage).await
+    }
+
+    async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error> {
+        self.home.get_event_comments().await
+    }
+
+    async fn add_event_comment(&self, comment: &EventComment) -> Result<i64, sqlx::Error> {
+        self.home.add_event_comment(comment).await
+    }
+
+    async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error> {
+        self.home.get_case_tickets().await
+    }
+
+    async fn set_case_ticket(&self, ticket: &CaseTicket) -> Result<(), sqlx::Error> {
+        self.home.set_case_ticket(ticket).await
+    }
+
+    async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error> {
+        self.home.get_extension_errors().await
+    }
+
+    async fn record_extension_error(&self, error: &ExtensionError) -> Result<i64, sqlx::Error> {
+        self.home.record_extension_error(error).await
+    }
+
+    async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error> {
+        self.home.get_local_users().await
+    }
+
+    async fn put_local_user(&self, user: &LocalUser) -> Result<(), sqlx::Error> {
+        self.home.put_local_user(user).await
+    }
+
+    async fn delete_local_user(&self, username: &str) -> Result<bool, sqlx::Error> {
+        self.home.delete_local_user(username).await
+    }
+}
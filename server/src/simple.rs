@@ -1,10 +1,49 @@
-use crate::types::{Blocklist, ExtensionEvent, LogEntry};
+use crate::handlers::common::domain_from_url;
+use crate::types::{Blocklist, ExtensionEvent, LogEntry, ObservedDomain};
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+
+/// A record handed to the warm (database) tier in hybrid mode.
+#[cfg_attr(not(feature = "production"), allow(dead_code))]
+pub enum WarmRecord {
+    Log(LogEntry),
+    Event(ExtensionEvent),
+}
+
+struct DomainSighting {
+    first_seen: chrono::DateTime<chrono::Utc>,
+    last_seen: chrono::DateTime<chrono::Utc>,
+    request_count: u64,
+    clients: HashSet<String>,
+}
+
+type Received<T> = (chrono::DateTime<chrono::Utc>, T);
+
+/// In-memory buffer sizes; older records are dropped first. The hot tier of
+/// hybrid mode is bounded by its window instead: the warm tier only answers
+/// for what is older than that, so nothing newer may be dropped.
+const MAX_LOGS: usize = 1000;
+const MAX_EVENTS: usize = 500;
+/// Domain sightings kept; past it the least recently seen tenth is dropped.
+pub const MAX_DOMAINS: usize = 100_000;
+/// Distinct clients remembered per domain; `client_count` stops there.
+pub const MAX_CLIENTS_PER_DOMAIN: usize = 10_000;
 
 pub struct SimpleState {
-    logs: Mutex<Vec<LogEntry>>,
+    logs: Mutex<Vec<Received<LogEntry>>>,
     blocklist: Mutex<Blocklist>,
-    extension_events: Mutex<Vec<ExtensionEvent>>,
+    extension_events: Mutex<Vec<Received<ExtensionEvent>>>,
+    domains: Mutex<HashMap<String, DomainSighting>>,
+    hot_window: Option<chrono::Duration>,
+    warm_tier: Option<Sender<WarmRecord>>,
+}
+
+impl Default for SimpleState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SimpleState {
@@ -18,22 +57,153 @@ impl SimpleState {
                     ".*doubleclick\\..*".to_string(),
                 ],
                 youtube_channels: vec!["@spam".to_string()],
+                profiles: Default::default(),
             }),
             extension_events: Mutex::new(Vec::new()),
+            domains: Mutex::new(HashMap::new()),
+            hot_window: None,
+            warm_tier: None,
+        }
+    }
+
+    /// Turns this state into the hot tier of hybrid mode: every record is
+    /// forwarded to `warm_tier` and at most the last `hot_window` is kept in memory.
+    #[cfg(feature = "production")]
+    pub fn with_warm_tier(
+        mut self,
+        hot_window: chrono::Duration,
+        warm_tier: Sender<WarmRecord>,
+    ) -> Self {
+        self.hot_window = Some(hot_window);
+        self.warm_tier = Some(warm_tier);
+        self
+    }
+
+    /// Start of the in-memory window; anything older must come from the warm tier.
+    pub fn hot_cutoff(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.hot_window.map(|w| chrono::Utc::now() - w)
+    }
+
+    fn forward(&self, record: WarmRecord) {
+        if let Some(ref tx) = self.warm_tier {
+            match tx.try_send(record) {
+                Ok(()) => {
+                    #[cfg(feature = "production")]
+                    crate::metrics::WARM_QUEUE_DEPTH.add(1);
+                }
+                Err(TrySendError::Full(_)) => {
+                    #[cfg(feature = "production")]
+                    {
+                        crate::metrics::WARM_QUEUE_DROPPED.inc();
+                        let dropped = crate::metrics::WARM_QUEUE_DROPPED.get();
+                        if dropped % 1000 == 1 {
+                            log::warn!(
+                                "⚠️ Warm tier queue is full; {} record(s) kept in memory only so far",
+                                dropped
+                            );
+                        }
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    log::error!("❌ Warm tier writer has stopped; record kept in memory only");
+                }
+            }
+        }
+    }
+
+    fn trim<T>(&self, items: &mut Vec<Received<T>>, max: usize) {
+        if let Some(cutoff) = self.hot_cutoff() {
+            let expired = items.iter().take_while(|(at, _)| *at < cutoff).count();
+            items.drain(0..expired);
+            return;
+        }
+        if items.len() > max {
+            let len = items.len();
+            items.drain(0..len - max);
         }
     }
 
     pub fn add_log(&self, entry: LogEntry) {
-        let mut logs = self.logs.lock().unwrap();
-        logs.push(entry);
-        if logs.len() > 1000 {
-            let len = logs.len();
-            logs.drain(0..len - 1000);
+        self.record_domains(&entry);
+        if self.warm_tier.is_some() {
+            self.forward(WarmRecord::Log(entry.clone()));
         }
+        let mut logs = self.logs.lock().unwrap();
+        logs.push((chrono::Utc::now(), entry));
+        self.trim(&mut logs, MAX_LOGS);
     }
 
     pub fn get_logs(&self) -> Vec<LogEntry> {
-        self.logs.lock().unwrap().clone()
+        let mut logs = self.logs.lock().unwrap();
+        self.trim(&mut logs, MAX_LOGS);
+        logs.iter().map(|(_, e)| e.clone()).collect()
+    }
+
+    /// Fingerprints of every request still held in memory, for import
+    /// duplicate detection.
+    pub fn log_fingerprints(&self) -> HashSet<String> {
+        let logs = self.logs.lock().unwrap();
+        logs.iter()
+            .flat_map(|(_, e)| e.logs.iter().map(move |l| crate::import::fingerprint(e, l)))
+            .collect()
+    }
+
+    /// Unlike the log buffer, domain sightings outlive the records they came
+    /// from, so first_seen stays accurate for any domain still in use. Only
+    /// past [`MAX_DOMAINS`] are the ones seen least recently dropped, in bulk
+    /// so a flood of new names doesn't rescan the map for each.
+    fn record_domains(&self, entry: &LogEntry) {
+        let now = chrono::Utc::now();
+        let mut domains = self.domains.lock().unwrap();
+        for network_log in &entry.logs {
+            let Some(domain) = domain_from_url(&network_log.url) else {
+                continue;
+            };
+            let domain = domain.to_ascii_lowercase();
+            if domains.len() >= MAX_DOMAINS && !domains.contains_key(&domain) {
+                let mut seen: Vec<_> = domains
+                    .iter()
+                    .map(|(d, s)| (s.last_seen, d.clone()))
+                    .collect();
+                let (oldest, _, _) = seen.select_nth_unstable(MAX_DOMAINS / 10);
+                for (_, d) in oldest.iter() {
+                    domains.remove(d);
+                }
+            }
+            let sighting = domains.entry(domain).or_insert_with(|| DomainSighting {
+                first_seen: now,
+                last_seen: now,
+                request_count: 0,
+                clients: HashSet::new(),
+            });
+            sighting.last_seen = now;
+            sighting.request_count += 1;
+            if let Some(ref cid) = entry.client_id {
+                if sighting.clients.len() < MAX_CLIENTS_PER_DOMAIN {
+                    sighting.clients.insert(cid.clone());
+                }
+            }
+        }
+    }
+
+    pub fn get_domains(
+        &self,
+        first_seen_after: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Vec<ObservedDomain> {
+        let domains = self.domains.lock().unwrap();
+        let mut out: Vec<ObservedDomain> = domains
+            .iter()
+            .filter(|(_, s)| first_seen_after.is_none_or(|t| s.first_seen >= t))
+            .map(|(domain, s)| ObservedDomain {
+                domain: domain.clone(),
+                first_seen: s.first_seen,
+                last_seen: s.last_seen,
+                request_count: s.request_count,
+                client_count: s.clients.len() as u64,
+            })
+            .collect();
+        out.sort_by_key(|d| std::cmp::Reverse(d.first_seen));
+        out
     }
 
     pub fn get_blocklist(&self) -> Blocklist {
@@ -45,15 +215,33 @@ impl SimpleState {
     }
 
     pub fn add_extension_event(&self, event: ExtensionEvent) {
-        let mut events = self.extension_events.lock().unwrap();
-        events.push(event);
-        if events.len() > 500 {
-            let len = events.len();
-            events.drain(0..len - 500);
+        if self.warm_tier.is_some() {
+            self.forward(WarmRecord::Event(event.clone()));
         }
+        let mut events = self.extension_events.lock().unwrap();
+        events.push((chrono::Utc::now(), event));
+        self.trim(&mut events, MAX_EVENTS);
     }
 
     pub fn get_extension_events(&self) -> Vec<ExtensionEvent> {
-        self.extension_events.lock().unwrap().clone()
+        let mut events = self.extension_events.lock().unwrap();
+        self.trim(&mut events, MAX_EVENTS);
+        events.iter().map(|(_, e)| e.clone()).collect()
+    }
+
+    /// Buffer occupancy for `/api/admin/state`.
+    pub fn buffer_stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "logs": {
+                "len": self.logs.lock().unwrap().len(),
+                "capacity": self.hot_window.is_none().then_some(MAX_LOGS)
+            },
+            "events": {
+                "len": self.extension_events.lock().unwrap().len(),
+                "capacity": self.hot_window.is_none().then_some(MAX_EVENTS)
+            },
+            "domains": self.domains.lock().unwrap().len(),
+            "hot_window_secs": self.hot_window.map(|w| w.num_seconds()),
+        })
     }
 }
@@ -0,0 +1,182 @@
+use crate::handlers::common::domain_from_url;
+use crate::types::{CategoryStats, ClientStats, DomainStats, LogEntry};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+struct DomainAcc {
+    requests: u64,
+    blocked: u64,
+    clients: HashSet<String>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct ClientAcc {
+    requests: u64,
+    blocked: u64,
+    domains: HashSet<String>,
+    sessions: HashSet<String>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+/// Computes the `/api/stats` aggregates directly from log entries (simple
+/// mode, where there is no database to precompute them). Both lists are
+/// ordered by request count and cut to `limit`.
+pub fn aggregate(entries: &[LogEntry], limit: usize) -> (Vec<DomainStats>, Vec<ClientStats>) {
+    let mut domains: HashMap<String, DomainAcc> = HashMap::new();
+    let mut clients: HashMap<String, ClientAcc> = HashMap::new();
+
+    for entry in entries {
+        let at = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .ok()
+            .map(|t| t.with_timezone(&Utc));
+        let client_id = entry.client_id.as_deref().filter(|c| !c.is_empty());
+        for log in &entry.logs {
+            let domain = domain_from_url(&log.url).map(|d| d.to_ascii_lowercase());
+            if let Some(ref domain) = domain {
+                let acc = domains.entry(domain.clone()).or_default();
+                acc.requests += 1;
+                acc.blocked += log.blocked as u64;
+                if let Some(c) = client_id {
+                    acc.clients.insert(c.to_string());
+                }
+                acc.last_seen = acc.last_seen.max(at);
+            }
+            if let Some(c) = client_id {
+                let acc = clients.entry(c.to_string()).or_default();
+                acc.requests += 1;
+                acc.blocked += log.blocked as u64;
+                if let Some(ref domain) = domain {
+                    acc.domains.insert(domain.clone());
+                }
+                acc.sessions.insert(entry.session_id.clone());
+                acc.last_seen = acc.last_seen.max(at);
+            }
+        }
+    }
+
+    let mut domain_stats: Vec<DomainStats> = domains
+        .into_iter()
+        .map(|(domain, a)| DomainStats {
+            domain,
+            requests: a.requests,
+            blocked: a.blocked,
+            clients: a.clients.len() as u64,
+            last_seen: a.last_seen,
+        })
+        .collect();
+    domain_stats.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.domain.cmp(&b.domain)));
+    domain_stats.truncate(limit);
+
+    let mut client_stats: Vec<ClientStats> = clients
+        .into_iter()
+        .map(|(client_id, a)| ClientStats {
+            client_id,
+            requests: a.requests,
+            blocked: a.blocked,
+            domains: a.domains.len() as u64,
+            sessions: a.sessions.len() as u64,
+            last_seen: a.last_seen,
+        })
+        .collect();
+    client_stats.sort_by(|a, b| {
+        b.requests
+            .cmp(&a.requests)
+            .then(a.client_id.cmp(&b.client_id))
+    });
+    client_stats.truncate(limit);
+
+    (domain_stats, client_stats)
+}
+
+#[derive(Default)]
+struct CategoryAcc {
+    requests: u64,
+    blocked: u64,
+    domains: HashSet<String>,
+    clients: HashSet<String>,
+}
+
+/// Per-category totals over logs tagged on ingest, by request count.
+pub fn aggregate_categories(entries: &[LogEntry]) -> Vec<CategoryStats> {
+    let mut categories: HashMap<&str, CategoryAcc> = HashMap::new();
+    for entry in entries {
+        let client_id = entry.client_id.as_deref().filter(|c| !c.is_empty());
+        for log in &entry.logs {
+            let Some(category) = log.category.as_deref() else {
+                continue;
+            };
+            let acc = categories.entry(category).or_default();
+            acc.requests += 1;
+            acc.blocked += log.blocked as u64;
+            if let Some(domain) = domain_from_url(&log.url) {
+                acc.domains.insert(domain.to_ascii_lowercase());
+            }
+            if let Some(c) = client_id {
+                acc.clients.insert(c.to_string());
+            }
+        }
+    }
+    let mut out: Vec<CategoryStats> = categories
+        .into_iter()
+        .map(|(category, a)| CategoryStats {
+            category: category.to_string(),
+            requests: a.requests,
+            blocked: a.blocked,
+            domains: a.domains.len() as u64,
+            clients: a.clients.len() as u64,
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.requests
+            .cmp(&a.requests)
+            .then(a.category.cmp(&b.category))
+    });
+    out
+}
+
+/// Refreshes the database's precomputed aggregates on a schedule and
+/// remembers when that last succeeded, so responses can say how stale they are.
+#[cfg(feature = "production")]
+pub struct StatsRefresher {
+    interval: std::time::Duration,
+    refreshed_at: std::sync::Mutex<Option<DateTime<Utc>>>,
+}
+
+#[cfg(feature = "production")]
+impl StatsRefresher {
+    pub fn spawn(
+        state: std::sync::Arc<crate::production::ProductionState>,
+        interval: std::time::Duration,
+    ) -> std::sync::Arc<StatsRefresher> {
+        let refresher = std::sync::Arc::new(StatsRefresher {
+            interval,
+            refreshed_at: std::sync::Mutex::new(None),
+        });
+        let r = refresher.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                let started = std::time::Instant::now();
+                match state.refresh_stats().await {
+                    Ok(()) => {
+                        *r.refreshed_at.lock().unwrap() = Some(Utc::now());
+                        log::debug!("📈 Stats refreshed in {:?}", started.elapsed());
+                    }
+                    Err(e) => log::error!("❌ Stats refresh failed: {}", e),
+                }
+                tokio::time::sleep(r.interval).await;
+            }
+        });
+        refresher
+    }
+
+    /// `(refreshed_at, age_secs, stale)`. Stale means the last successful
+    /// refresh is more than two intervals old (or never happened).
+    pub fn staleness(&self) -> (Option<DateTime<Utc>>, Option<i64>, bool) {
+        let refreshed_at = *self.refreshed_at.lock().unwrap();
+        let age = refreshed_at.map(|t| (Utc::now() - t).num_seconds());
+        let stale = age.is_none_or(|a| a > 2 * self.interval.as_secs() as i64);
+        (refreshed_at, age, stale)
+    }
+}
@@ -0,0 +1,344 @@
+use crate::handlers::common::domain_from_url;
+use crate::types::{
+    Annotation, ArchivedClient, Blocklist, CaseTicket, CategoryStats, ClientStats, DomainStats,
+    EventComment, EventTriage, ExtensionError, ExtensionEvent, LocalUser, LogEntry, ObservedDomain,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Connection pool settings, shared by every backend.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    /// `None` keeps idle connections open indefinitely.
+    pub idle_timeout: Option<Duration>,
+    /// Extra attempts at startup before giving up on the database.
+    pub connect_retries: u32,
+    /// Server-side limit per statement; `None` means no limit.
+    pub statement_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 20,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            connect_retries: 5,
+            statement_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn options<DB: sqlx::Database>(&self) -> sqlx::pool::PoolOptions<DB> {
+        sqlx::pool::PoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+    }
+}
+
+/// Point-in-time pool occupancy.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStatus {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+impl PoolStatus {
+    pub fn from_pool<DB: sqlx::Database>(pool: &sqlx::Pool<DB>) -> Self {
+        PoolStatus {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max: pool.options().get_max_connections(),
+        }
+    }
+
+    pub fn in_use(&self) -> u32 {
+        self.size.saturating_sub(self.idle as u32)
+    }
+
+    /// Every connection is open and checked out; new queries must wait.
+    pub fn is_exhausted(&self) -> bool {
+        self.size >= self.max && self.idle == 0
+    }
+}
+
+/// True when the database aborted a statement for exceeding
+/// `statement_timeout`. Such queries are not retried elsewhere: they would be
+/// just as slow on the primary.
+pub fn is_statement_timeout(e: &sqlx::Error) -> bool {
+    let Some(db) = e.as_database_error() else {
+        return false;
+    };
+    // 57014 query_canceled: statement_timeout or a cancel request.
+    if db.code().as_deref() == Some("57014") {
+        return true;
+    }
+    #[cfg(feature = "mysql")]
+    if let Some(my) = db.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
+        // ER_QUERY_TIMEOUT (MySQL), ER_STATEMENT_TIMEOUT (MariaDB).
+        return matches!(my.number(), 3024 | 1969);
+    }
+    false
+}
+
+/// A pooled connection running a read that may outlive its caller. actix drops
+/// the handler future when the client disconnects; if that happens before
+/// `finish`, the statement is cancelled on the server and the connection is
+/// discarded instead of going back to the pool.
+pub struct CancelOnDrop<DB: sqlx::Database> {
+    conn: Option<sqlx::pool::PoolConnection<DB>>,
+    cancel: Option<Box<dyn FnOnce(DB::Connection) + Send>>,
+}
+
+impl<DB: sqlx::Database> CancelOnDrop<DB> {
+    /// `cancel` receives the detached connection and must stop whatever
+    /// statement is still running on it.
+    pub fn new(
+        conn: sqlx::pool::PoolConnection<DB>,
+        cancel: impl FnOnce(DB::Connection) + Send + 'static,
+    ) -> Self {
+        CancelOnDrop {
+            conn: Some(conn),
+            cancel: Some(Box::new(cancel)),
+        }
+    }
+
+    pub fn conn(&mut self) -> &mut DB::Connection {
+        self.conn.as_mut().expect("connection taken")
+    }
+
+    /// The read completed (successfully or not): return the connection to the pool.
+    pub fn finish(mut self) {
+        self.cancel = None;
+    }
+}
+
+impl<DB: sqlx::Database> Drop for CancelOnDrop<DB> {
+    fn drop(&mut self) {
+        if let (Some(cancel), Some(conn)) = (self.cancel.take(), self.conn.take()) {
+            crate::metrics::DB_QUERIES_CANCELLED.inc();
+            log::debug!("🛑 Client went away mid-query; cancelling it on the server");
+            cancel(conn.detach());
+        }
+    }
+}
+
+/// Durable storage behind production mode. Each database backend implements
+/// this; `ProductionState` picks one from the `--database-url` scheme.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    fn pool_status(&self) -> PoolStatus;
+
+    /// Round-trips a trivial query to check the database is reachable.
+    async fn ping(&self) -> Result<(), sqlx::Error>;
+
+    async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error>;
+
+    /// Like `add_log`, but skips requests that are already stored (same
+    /// client, session, timestamp, request id, method and URL). Returns the
+    /// number of requests actually inserted.
+    async fn import_log(&self, entry: LogEntry) -> Result<usize, sqlx::Error>;
+
+    async fn get_domains(
+        &self,
+        first_seen_after: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error>;
+
+    async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error>;
+
+    async fn update_blocklist(&self, blocklist: Blocklist) -> Result<(), sqlx::Error>;
+
+    async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error>;
+
+    /// Logs stored before `before`, oldest first. Each row comes back as its
+    /// own single-log entry.
+    async fn get_logs_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error>;
+
+    /// One client's logs timestamped in `[from, to)`, oldest first; the
+    /// newest `limit` when there are more. One entry per log, as above.
+    async fn get_client_logs(
+        &self,
+        client_id: &str,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error>;
+
+    /// Logs stored before `as_of` with an id above `after_id`, with their
+    /// ids, in id order: a backup pages through them with the last id seen.
+    async fn export_logs(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error>;
+
+    /// Extension events, paged the same way as [`Storage::export_logs`].
+    async fn export_extension_events(
+        &self,
+        after_id: i64,
+        as_of: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error>;
+
+    /// Extension events stored before `before`, oldest first.
+    async fn get_extension_events_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error>;
+
+    async fn get_extension_event_by_packet_id(
+        &self,
+        packet_id: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error>;
+
+    /// Recomputes the precomputed `/api/stats` aggregates.
+    async fn refresh_stats(&self) -> Result<(), sqlx::Error>;
+
+    /// Top domains and clients by request count from the last refresh.
+    async fn get_stats(
+        &self,
+        limit: i64,
+    ) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error>;
+
+    /// Every category by request count from the last refresh.
+    async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error>;
+
+    async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error>;
+
+    /// Inserts or replaces the archive record for `client.client_id`.
+    async fn archive_client(&self, client: &ArchivedClient) -> Result<(), sqlx::Error>;
+
+    /// Returns whether the client was archived.
+    async fn unarchive_client(&self, client_id: &str) -> Result<bool, sqlx::Error>;
+
+    async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error>;
+
+    /// Stores `annotation` (its `id` is ignored) and returns the new id.
+    async fn add_annotation(&self, annotation: &Annotation) -> Result<i64, sqlx::Error>;
+
+    /// Returns whether the annotation existed.
+    async fn delete_annotation(&self, id: i64) -> Result<bool, sqlx::Error>;
+
+    async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error>;
+
+    /// Inserts or replaces the triage record for `triage.packet_id`.
+    async fn set_event_triage(&self, triage: &EventTriage) -> Result<(), sqlx::Error>;
+
+    /// Every event comment, oldest first.
+    async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error>;
+
+    /// Stores `comment` (its `id` is ignored) and returns the new id.
+    async fn add_event_comment(&self, comment: &EventComment) -> Result<i64, sqlx::Error>;
+
+    async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error>;
+
+    /// Inserts or replaces the ticket for `ticket.packet_id`.
+    async fn set_case_ticket(&self, ticket: &CaseTicket) -> Result<(), sqlx::Error>;
+
+    async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error>;
+
+    /// Adds one report of `error.hash`: inserts it, or adds to its count and
+    /// takes its message, version, client and `last_seen`. Returns the
+    /// stored count.
+    async fn record_extension_error(&self, error: &ExtensionError) -> Result<i64, sqlx::Error>;
+
+    async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error>;
+
+    /// Inserts or replaces the account `user.username`.
+    async fn put_local_user(&self, user: &LocalUser) -> Result<(), sqlx::Error>;
+
+    /// Returns whether the account existed.
+    async fn delete_local_user(&self, username: &str) -> Result<bool, sqlx::Error>;
+}
+
+/// Opens the backend named by the URL scheme: `mysql://` / `mariadb://` for
+/// MySQL (needs the `mysql` feature), anything else for PostgreSQL. A database
+/// that isn't up yet is retried with exponential backoff.
+pub async fn connect(
+    database_url: &str,
+    pool: &PoolConfig,
+) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    let mut attempt = 0;
+    let mut delay = Duration::from_secs(1);
+    loop {
+        match open(database_url, pool).await {
+            Ok(storage) => return Ok(storage),
+            Err(OpenError::Database(e)) if attempt < pool.connect_retries => {
+                attempt += 1;
+                log::warn!(
+                    "⚠️ Database connection failed (attempt {}/{}), retrying in {}s: {}",
+                    attempt,
+                    pool.connect_retries + 1,
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+            Err(OpenError::Database(e)) => return Err(Box::new(e)),
+            Err(OpenError::Unsupported(msg)) => return Err(msg.into()),
+        }
+    }
+}
+
+enum OpenError {
+    Database(sqlx::Error),
+    #[cfg_attr(feature = "mysql", allow(dead_code))]
+    Unsupported(&'static str),
+}
+
+async fn open(database_url: &str, pool: &PoolConfig) -> Result<Box<dyn Storage>, OpenError> {
+    if let Some(rest) = database_url
+        .strip_prefix("mysql://")
+        .or_else(|| database_url.strip_prefix("mariadb://"))
+    {
+        #[cfg(feature = "mysql")]
+        {
+            log::info!("🗄️ Using MySQL storage");
+            let url = format!("mysql://{}", rest);
+            return crate::mysql::MySqlStorage::connect(&url, pool)
+                .await
+                .map(|s| Box::new(s) as Box<dyn Storage>)
+                .map_err(OpenError::Database);
+        }
+        #[cfg(not(feature = "mysql"))]
+        {
+            let _ = rest;
+            return Err(OpenError::Unsupported(
+                "MySQL support not built in. Rebuild with --features mysql",
+            ));
+        }
+    }
+    log::info!("🗄️ Using PostgreSQL storage");
+    crate::production::PostgresStorage::connect(database_url, pool)
+        .await
+        .map(|s| Box::new(s) as Box<dyn Storage>)
+        .map_err(OpenError::Database)
+}
+
+/// Requests per (lowercased) domain in one batch, for the observed_domains upsert.
+pub fn domain_counts(entry: &LogEntry) -> HashMap<String, i64> {
+    let mut counts = HashMap::new();
+    for log in &entry.logs {
+        if let Some(domain) = domain_from_url(&log.url) {
+            *counts.entry(domain.to_ascii_lowercase()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
@@ -0,0 +1,262 @@
+//! Incremental parsing of `/api/logs` batches. A gzip body is gunzipped
+//! into the JSON parser through a small buffer instead of into a `String`
+//! first, so a batch never sits in memory both decompressed and parsed.
+//! Logs are taken one at a time as they are parsed, and with
+//! `--max-batch-logs` in `reject` mode parsing stops at the first log over
+//! the limit rather than reading the rest of an oversized batch.
+//!
+//! With `Content-Encoding: gzip` actix decodes the body before the handler,
+//! within the 256 KB body limit. The gzip bodies seen here are the ones
+//! sent without the header (see
+//! [`decompress_body_if_needed`](crate::handlers::common::decompress_body_if_needed)),
+//! which would otherwise be held whole however far they expand. Past
+//! [`MAX_DECOMPRESSED_BYTES`] of decoded text the batch is refused, and a
+//! body that starts like gzip but fails to gunzip (a truncated upload, say)
+//! is refused too rather than parsed as the raw bytes.
+
+use crate::error::ApiError;
+use crate::handlers::common::{decompressed_too_large, get_client_ip, MAX_DECOMPRESSED_BYTES};
+use crate::metrics;
+use crate::types::{LogEntry, NetworkLog};
+use actix_web::HttpRequest;
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::fmt;
+use std::io::Read;
+
+/// Decoded text read from the decompressor at a time.
+const BUFFER_BYTES: usize = 16 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A parsed batch and the size of the JSON it came from.
+pub struct Parsed {
+    pub entry: LogEntry,
+    /// Uncompressed body size, for quotas and dry runs.
+    pub decompressed_bytes: usize,
+}
+
+/// Counts the bytes read through it, and fails the read that takes the
+/// count past `limit`.
+struct Counted<R> {
+    inner: R,
+    bytes: usize,
+    limit: usize,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n;
+        if self.bytes > self.limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "decompressed body over the limit",
+            ));
+        }
+        Ok(n)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum Field {
+    ClientId,
+    #[serde(alias = "profileId")]
+    ProfileId,
+    SessionId,
+    Timestamp,
+    UserAgent,
+    Logs,
+    Username,
+    ClockSkewMs,
+    #[serde(other)]
+    Other,
+}
+
+/// Deserializes a [`LogEntry`] as its derived `Deserialize` does, with the
+/// logs read one at a time against `max_logs`.
+struct EntrySeed<'a> {
+    max_logs: Option<usize>,
+    /// Set when parsing stopped at the limit.
+    over_limit: &'a Cell<bool>,
+}
+
+struct LogsSeed<'a>(&'a EntrySeed<'a>);
+
+impl<'de> DeserializeSeed<'de> for LogsSeed<'_> {
+    type Value = Vec<NetworkLog>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LogsSeed<'_> {
+    type Value = Vec<NetworkLog>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of network logs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut logs = Vec::new();
+        while let Some(log) = seq.next_element::<NetworkLog>()? {
+            if self.0.max_logs.is_some_and(|max| logs.len() >= max) {
+                self.0.over_limit.set(true);
+                return Err(de::Error::custom("batch over --max-batch-logs"));
+            }
+            logs.push(log);
+        }
+        Ok(logs)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for &EntrySeed<'_> {
+    type Value = LogEntry;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for &EntrySeed<'_> {
+    type Value = LogEntry;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a log batch object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut client_id = None;
+        let mut profile_id = None;
+        let mut session_id = None;
+        let mut timestamp = None;
+        let mut user_agent = None;
+        let mut logs = None;
+        let mut username = None;
+        let mut clock_skew_ms = None;
+        while let Some(field) = map.next_key::<Field>()? {
+            match field {
+                Field::ClientId => client_id = map.next_value()?,
+                Field::ProfileId => profile_id = map.next_value()?,
+                Field::SessionId => session_id = Some(map.next_value()?),
+                Field::Timestamp => timestamp = Some(map.next_value()?),
+                Field::UserAgent => user_agent = Some(map.next_value()?),
+                Field::Logs => logs = Some(map.next_value_seed(LogsSeed(self))?),
+                Field::Username => username = map.next_value()?,
+                Field::ClockSkewMs => clock_skew_ms = map.next_value()?,
+                Field::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(LogEntry {
+            client_id,
+            profile_id,
+            session_id: session_id.ok_or_else(|| de::Error::missing_field("session_id"))?,
+            timestamp: timestamp.ok_or_else(|| de::Error::missing_field("timestamp"))?,
+            user_agent: user_agent.ok_or_else(|| de::Error::missing_field("user_agent"))?,
+            logs: logs.ok_or_else(|| de::Error::missing_field("logs"))?,
+            username,
+            clock_skew_ms,
+        })
+    }
+}
+
+fn read_entry<R: Read>(reader: R, seed: &EntrySeed) -> serde_json::Result<LogEntry> {
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let entry = seed.deserialize(&mut de)?;
+    de.end()?;
+    Ok(entry)
+}
+
+fn plain_entry(body: &[u8], seed: &EntrySeed) -> serde_json::Result<Parsed> {
+    // Borrowed unless the body isn't valid UTF-8.
+    let text = String::from_utf8_lossy(body);
+    let mut de = serde_json::Deserializer::from_str(&text);
+    let entry = seed.deserialize(&mut de)?;
+    de.end()?;
+    Ok(Parsed {
+        entry,
+        decompressed_bytes: text.len(),
+    })
+}
+
+/// Parses a `/api/logs` body. `max_logs` refuses a batch with more logs
+/// as soon as the first one past it is read.
+pub fn parse_log_batch(
+    req: &HttpRequest,
+    body: &[u8],
+    max_logs: Option<usize>,
+) -> Result<Parsed, ApiError> {
+    let over_limit = Cell::new(false);
+    let seed = EntrySeed {
+        max_logs,
+        over_limit: &over_limit,
+    };
+    let gzip_header = req
+        .headers()
+        .get("content-encoding")
+        .and_then(|h| h.to_str().ok())
+        == Some("gzip");
+    let parsed = match body.starts_with(&GZIP_MAGIC) {
+        true => {
+            let mut counted = Counted {
+                inner: flate2::read::GzDecoder::new(body),
+                bytes: 0,
+                limit: MAX_DECOMPRESSED_BYTES,
+            };
+            let result = read_entry(
+                std::io::BufReader::with_capacity(BUFFER_BYTES, &mut counted),
+                &seed,
+            );
+            match result {
+                Ok(entry) => {
+                    if !gzip_header {
+                        metrics::GZIP_WITHOUT_HEADER.inc();
+                    }
+                    Ok(Parsed {
+                        entry,
+                        decompressed_bytes: counted.bytes,
+                    })
+                }
+                Err(_) if counted.bytes > counted.limit => {
+                    return Err(decompressed_too_large(req, counted.limit));
+                }
+                Err(e) if e.is_io() => {
+                    log::warn!(
+                        "Gzip body from IP {} failed to decompress: {}",
+                        get_client_ip(req),
+                        e
+                    );
+                    return Err(ApiError::BadRequest(format!(
+                        "Body looks gzip-compressed but failed to decompress: {}",
+                        e
+                    )));
+                }
+                Err(e) => Err(e),
+            }
+        }
+        false => plain_entry(body, &seed),
+    };
+    parsed.map_err(|e| {
+        if over_limit.get() {
+            let max_logs = max_logs.unwrap_or_default();
+            metrics::OVERSIZED_BATCHES_REJECTED.inc();
+            log::warn!(
+                "📦 Oversized batch from IP {} refused while parsing: over {} logs",
+                get_client_ip(req),
+                max_logs
+            );
+            return ApiError::PayloadTooLarge(format!(
+                "Batch is over the limit of {} logs per request",
+                max_logs
+            ))
+            .with("max_batch_logs", max_logs);
+        }
+        log::error!("Failed to parse log entry JSON: {}", e);
+        ApiError::InvalidJson(e.to_string())
+    })
+}
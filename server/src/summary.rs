@@ -0,0 +1,293 @@
+//! Per-client browsing summaries (`GET /api/clients/{id}/summary`): requests
+//! and time by category, the sites most time went to, blocked attempts, and
+//! how the period compares with the one before it.
+//!
+//! Time is estimated the way screen time is: the gap between a logged request
+//! and the client's next one, capped at [`IDLE_CAP`], goes to the site of the
+//! last page (`main_frame`) navigated to.
+
+use crate::handlers::common::domain_from_url;
+use crate::screen_time::IDLE_CAP;
+use crate::types::LogEntry;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Sites listed under `top_sites` and `blocked_sites`.
+pub const TOP_SITES: usize = 10;
+/// Logs without a category are counted under this name.
+pub const UNCATEGORIZED: &str = "uncategorized";
+
+/// A trailing window ending now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn parse(s: &str) -> Option<Period> {
+        match s {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            _ => None,
+        }
+    }
+
+    pub fn length(self) -> chrono::Duration {
+        match self {
+            Period::Day => chrono::Duration::days(1),
+            Period::Week => chrono::Duration::days(7),
+            Period::Month => chrono::Duration::days(30),
+        }
+    }
+
+    /// Start of the previous period and of this one, for a period ending at `now`.
+    pub fn window(self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = now - self.length();
+        (start - self.length(), start)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub requests: u64,
+    pub blocked: u64,
+    pub sites: u64,
+    pub time_secs: u64,
+}
+
+/// Change against the previous period in percent; null when it had nothing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trends {
+    pub requests_pct: Option<f64>,
+    pub blocked_pct: Option<f64>,
+    pub time_pct: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategorySummary {
+    pub category: String,
+    pub requests: u64,
+    pub blocked: u64,
+    pub time_secs: u64,
+    pub previous_time_secs: u64,
+    pub time_change_pct: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteSummary {
+    pub domain: String,
+    pub category: Option<String>,
+    pub requests: u64,
+    pub time_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockedSite {
+    pub domain: String,
+    pub attempts: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub client_id: String,
+    pub period: Period,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub totals: Totals,
+    pub previous: Totals,
+    pub trends: Trends,
+    pub categories: Vec<CategorySummary>,
+    pub top_sites: Vec<SiteSummary>,
+    pub blocked_sites: Vec<BlockedSite>,
+    /// Set when the store held more logs than could be read; the oldest
+    /// (previous-period) ones are then missing.
+    pub truncated: bool,
+}
+
+#[derive(Default)]
+struct SiteAcc {
+    category: Option<String>,
+    requests: u64,
+    blocked: u64,
+    time_secs: u64,
+}
+
+/// Per-site totals over one period.
+#[derive(Default)]
+struct PeriodAcc {
+    sites: HashMap<String, SiteAcc>,
+    /// Requests without a parsable domain count here but under no site.
+    requests: u64,
+    blocked: u64,
+}
+
+impl PeriodAcc {
+    fn totals(&self) -> Totals {
+        Totals {
+            requests: self.requests,
+            blocked: self.blocked,
+            sites: self.sites.len() as u64,
+            time_secs: self.sites.values().map(|s| s.time_secs).sum(),
+        }
+    }
+
+    fn by_category(&self) -> HashMap<&str, (u64, u64, u64)> {
+        let mut out: HashMap<&str, (u64, u64, u64)> = HashMap::new();
+        for site in self.sites.values() {
+            let c = out
+                .entry(site.category.as_deref().unwrap_or(UNCATEGORIZED))
+                .or_default();
+            c.0 += site.requests;
+            c.1 += site.blocked;
+            c.2 += site.time_secs;
+        }
+        out
+    }
+}
+
+fn change_pct(current: u64, previous: u64) -> Option<f64> {
+    (previous > 0).then(|| {
+        let pct = (current as f64 - previous as f64) / previous as f64 * 100.0;
+        (pct * 10.0).round() / 10.0
+    })
+}
+
+/// Summarizes `client_id`'s logs for the `period` ending at `now`. `logs`
+/// should cover this period and the previous one; anything outside both, or
+/// with a timestamp that doesn't parse, is ignored.
+pub fn summarize(
+    client_id: &str,
+    period: Period,
+    now: DateTime<Utc>,
+    logs: &[LogEntry],
+) -> Summary {
+    let (previous_start, start) = period.window(now);
+    let mut requests: Vec<(DateTime<Utc>, &crate::types::NetworkLog)> = logs
+        .iter()
+        .filter(|e| e.client_id.as_deref() == Some(client_id))
+        .filter_map(|e| {
+            let ts = DateTime::parse_from_rfc3339(&e.timestamp)
+                .ok()?
+                .with_timezone(&Utc);
+            (ts >= previous_start && ts < now).then_some((ts, &e.logs))
+        })
+        .flat_map(|(ts, logs)| logs.iter().map(move |l| (ts, l)))
+        .collect();
+    requests.sort_by_key(|(ts, _)| *ts);
+
+    let idle_cap = chrono::Duration::from_std(IDLE_CAP).expect("IDLE_CAP fits");
+    let mut previous = PeriodAcc::default();
+    let mut current = PeriodAcc::default();
+    // The page being looked at and when the client was last active on it.
+    let mut page: Option<(String, Option<String>, DateTime<Utc>)> = None;
+    for (ts, log) in requests {
+        if let Some((domain, category, last)) = &mut page {
+            // The time follows `last`, so it lands in the period it was spent in.
+            let acc = if *last < start {
+                &mut previous
+            } else {
+                &mut current
+            };
+            let secs = (ts.min(*last + idle_cap) - *last).num_seconds().max(0) as u64;
+            let site = acc.sites.entry(domain.clone()).or_default();
+            site.time_secs += secs;
+            if site.category.is_none() {
+                site.category = category.clone();
+            }
+            *last = ts;
+        }
+        let acc = if ts < start {
+            &mut previous
+        } else {
+            &mut current
+        };
+        acc.requests += 1;
+        acc.blocked += log.blocked as u64;
+        let Some(domain) = domain_from_url(&log.url).map(|d| d.to_ascii_lowercase()) else {
+            continue;
+        };
+        let site = acc.sites.entry(domain.clone()).or_default();
+        site.requests += 1;
+        site.blocked += log.blocked as u64;
+        if site.category.is_none() {
+            site.category = log.category.clone();
+        }
+        if log.request_type == "main_frame" && !log.blocked {
+            page = Some((domain, log.category.clone(), ts));
+        }
+    }
+
+    let totals = current.totals();
+    let before = previous.totals();
+    let previous_categories = previous.by_category();
+    let mut categories: Vec<CategorySummary> = current
+        .by_category()
+        .into_iter()
+        .map(|(category, (requests, blocked, time_secs))| {
+            let previous_time_secs = previous_categories.get(category).map_or(0, |c| c.2);
+            CategorySummary {
+                category: category.to_string(),
+                requests,
+                blocked,
+                time_secs,
+                previous_time_secs,
+                time_change_pct: change_pct(time_secs, previous_time_secs),
+            }
+        })
+        .collect();
+    categories.sort_by(|a, b| {
+        (b.time_secs, b.requests)
+            .cmp(&(a.time_secs, a.requests))
+            .then(a.category.cmp(&b.category))
+    });
+
+    let mut top_sites: Vec<SiteSummary> = current
+        .sites
+        .iter()
+        .filter(|(_, s)| s.time_secs > 0)
+        .map(|(domain, s)| SiteSummary {
+            domain: domain.clone(),
+            category: s.category.clone(),
+            requests: s.requests,
+            time_secs: s.time_secs,
+        })
+        .collect();
+    top_sites.sort_by(|a, b| b.time_secs.cmp(&a.time_secs).then(a.domain.cmp(&b.domain)));
+    top_sites.truncate(TOP_SITES);
+
+    let mut blocked_sites: Vec<BlockedSite> = current
+        .sites
+        .iter()
+        .filter(|(_, s)| s.blocked > 0)
+        .map(|(domain, s)| BlockedSite {
+            domain: domain.clone(),
+            attempts: s.blocked,
+        })
+        .collect();
+    blocked_sites.sort_by(|a, b| b.attempts.cmp(&a.attempts).then(a.domain.cmp(&b.domain)));
+    blocked_sites.truncate(TOP_SITES);
+
+    Summary {
+        client_id: client_id.to_string(),
+        period,
+        from: start,
+        to: now,
+        trends: Trends {
+            requests_pct: change_pct(totals.requests, before.requests),
+            blocked_pct: change_pct(totals.blocked, before.blocked),
+            time_pct: change_pct(totals.time_secs, before.time_secs),
+        },
+        totals,
+        previous: before,
+        categories,
+        top_sites,
+        blocked_sites,
+        truncated: false,
+    }
+}
@@ -0,0 +1,87 @@
+//! `GET /api/logs/tail`: the last stored log batches and then, with
+//! `follow=true`, every batch accepted from then on, one JSON object per
+//! line (NDJSON) on a response that stays open — `tail -f` for operators
+//! debugging from curl.
+//!
+//! New batches are handed to followers as they are accepted, before they
+//! are stored, the way the lake gets them. Each replica only sees the
+//! batches it accepts itself. A follower that falls more than [`BUFFERED`]
+//! batches behind gets a `{"skipped": n}` line in place of what it missed.
+
+use crate::types::LogEntry;
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Most batches `lines=` returns.
+pub const MAX_LINES: usize = 1000;
+/// Batches a slow follower may fall behind before some are skipped.
+pub const BUFFERED: usize = 1024;
+
+pub struct LogTail {
+    sender: broadcast::Sender<Arc<LogEntry>>,
+}
+
+impl Default for LogTail {
+    fn default() -> Self {
+        LogTail::new()
+    }
+}
+
+impl LogTail {
+    pub fn new() -> Self {
+        LogTail {
+            sender: broadcast::channel(BUFFERED).0,
+        }
+    }
+
+    /// Hands an accepted batch to the followers; a no-op without any.
+    pub fn publish(&self, entry: &LogEntry) {
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(Arc::new(entry.clone()));
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<LogEntry>> {
+        self.sender.subscribe()
+    }
+}
+
+fn line(value: &impl serde::Serialize) -> web::Bytes {
+    let mut bytes = serde_json::to_vec(value).unwrap_or_default();
+    bytes.push(b'\n');
+    web::Bytes::from(bytes)
+}
+
+/// The response: `history` (oldest first), then what `follow` receives that
+/// `matches`, until the client goes away.
+pub fn respond(
+    history: Vec<LogEntry>,
+    follow: Option<broadcast::Receiver<Arc<LogEntry>>>,
+    matches: impl Fn(&LogEntry) -> bool + 'static,
+) -> HttpResponse {
+    use futures_util::StreamExt;
+    let history = futures_util::stream::iter(history.iter().map(line).collect::<Vec<_>>());
+    let live = futures_util::stream::unfold((follow, matches), |(receiver, matches)| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(entry) if matches(&entry) => {
+                    return Some((line(&*entry), (Some(receiver), matches)))
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    let skipped = line(&serde_json::json!({ "skipped": n }));
+                    return Some((skipped, (Some(receiver), matches)));
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keeps reverse proxies from holding lines back.
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(history.chain(live).map(Ok::<_, std::convert::Infallible>))
+}
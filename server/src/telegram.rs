@@ -0,0 +1,352 @@
+//! Telegram bot (`--telegram-bot-token` + `--telegram-chat`), for admins who
+//! run the server at home and would rather get alerts and block a domain from
+//! their phone than from the dashboard. Alert delivery goes through the
+//! `telegram` channel type in `alerts`. The bot answers commands in the
+//! configured chat only, by calling this server's own HTTP API, so it goes
+//! through the same auth, validation and logging as any other admin client.
+
+use crate::types::{Blocklist, DomainStats};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+/// Seconds a `getUpdates` call waits for a message before returning empty.
+const POLL_TIMEOUT_SECS: u64 = 30;
+/// Pause after a failed poll, so a bad token or an outage doesn't spin.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(10);
+/// Telegram rejects longer messages.
+const MAX_MESSAGE_CHARS: usize = 4096;
+const LISTED_EVENTS: usize = 5;
+
+const HELP: &str = "/stats [today|week|all] - event counts and top domains\n\
+/alerts - latest security events\n\
+/block <domain> - add a domain to the blocklist\n\
+/unblock <domain> - remove a domain from the blocklist\n\
+/help - this list";
+
+/// A `sendMessage` call; plain text, so event data can't break the markup.
+pub fn send_request(
+    http: &reqwest::Client,
+    token: &str,
+    chat_id: &str,
+    text: &str,
+) -> reqwest::RequestBuilder {
+    let text: String = text.chars().take(MAX_MESSAGE_CHARS).collect();
+    http.post(format!("{}/bot{}/sendMessage", TELEGRAM_API, token))
+        .json(&serde_json::json!({ "chat_id": chat_id, "text": text }))
+}
+
+#[derive(Deserialize)]
+struct Updates {
+    ok: bool,
+    #[serde(default)]
+    result: Vec<Update>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    #[serde(default)]
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+    #[serde(default)]
+    username: Option<String>,
+}
+
+impl Chat {
+    /// `--telegram-chat` may be the numeric id or `@username`.
+    fn is(&self, configured: &str) -> bool {
+        self.id.to_string() == configured
+            || self
+                .username
+                .as_ref()
+                .is_some_and(|u| configured.strip_prefix('@') == Some(u.as_str()))
+    }
+}
+
+pub struct TelegramBot {
+    token: String,
+    chat_id: String,
+    /// Base URL of this server, e.g. `http://127.0.0.1:8080`.
+    server_url: String,
+    admin_token: Option<String>,
+    http: reqwest::Client,
+}
+
+impl TelegramBot {
+    pub fn new(
+        token: String,
+        chat_id: String,
+        server_url: &str,
+        admin_token: Option<String>,
+    ) -> Self {
+        TelegramBot {
+            token,
+            chat_id,
+            server_url: server_url.trim_end_matches('/').to_string(),
+            admin_token,
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn spawn(self) {
+        actix_web::rt::spawn(async move { self.run().await });
+    }
+
+    async fn run(&self) {
+        let mut offset = 0;
+        loop {
+            let updates = match self.updates(offset).await {
+                Ok(updates) => updates,
+                Err(e) => {
+                    log::warn!("🤖 Telegram poll failed: {}", e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            for update in updates {
+                offset = update.update_id + 1;
+                let Some(message) = update.message else {
+                    continue;
+                };
+                let Some(text) = message.text.as_deref() else {
+                    continue;
+                };
+                if !message.chat.is(&self.chat_id) {
+                    log::warn!(
+                        "🤖 Ignoring Telegram message from chat {}, not --telegram-chat",
+                        message.chat.id
+                    );
+                    continue;
+                }
+                let Some(reply) = self.answer(text).await else {
+                    continue;
+                };
+                let sent = send_request(&self.http, &self.token, &self.chat_id, &reply)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status());
+                if let Err(e) = sent {
+                    log::warn!("🤖 Telegram reply failed: {}", e.without_url());
+                }
+            }
+        }
+    }
+
+    async fn updates(&self, offset: i64) -> Result<Vec<Update>, String> {
+        let url = format!(
+            "{}/bot{}/getUpdates?timeout={}&offset={}",
+            TELEGRAM_API, self.token, POLL_TIMEOUT_SECS, offset
+        );
+        // The URL holds the bot token, so keep it out of the error.
+        let updates: Updates = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.without_url().to_string())?
+            .json()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+        if !updates.ok {
+            return Err(updates.description.unwrap_or_else(|| "not ok".into()));
+        }
+        Ok(updates.result)
+    }
+
+    /// The reply to one message; `None` for anything that isn't a command.
+    async fn answer(&self, text: &str) -> Option<String> {
+        let mut words = text.split_whitespace();
+        let command = words.next()?.strip_prefix('/')?;
+        // In groups commands arrive as `/stats@SomeBot`.
+        let command = command.split('@').next().unwrap_or_default();
+        let argument = words.next();
+        log::info!("🤖 Telegram command /{}", command);
+
+        let reply = match command {
+            "stats" => self.stats(argument.unwrap_or("today")).await,
+            "alerts" => self.alerts().await,
+            "block" => self.set_blocked(argument, true).await,
+            "unblock" => self.set_blocked(argument, false).await,
+            "start" | "help" => Ok(HELP.to_string()),
+            _ => Ok(format!("Unknown command /{}\n\n{}", command, HELP)),
+        };
+        Some(reply.unwrap_or_else(|e| format!("⚠️ {}", e)))
+    }
+
+    async fn api_get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        let mut request = self.http.get(format!("{}{}", self.server_url, path));
+        if let Some(token) = &self.admin_token {
+            request = request.header("x-admin-token", token);
+        }
+        let resp = request.send().await.map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("{} returned {}", path, resp.status()));
+        }
+        resp.json().await.map_err(|e| e.to_string())
+    }
+
+    async fn dashboard_events(&self) -> Result<Vec<serde_json::Value>, String> {
+        let body: serde_json::Value = self.api_get("/api/dashboard/events").await?;
+        Ok(body["events"].as_array().cloned().unwrap_or_default())
+    }
+
+    async fn stats(&self, period: &str) -> Result<String, String> {
+        let since = match period {
+            "today" => Some(Utc::now() - Duration::hours(24)),
+            "week" => Some(Utc::now() - Duration::days(7)),
+            "all" => None,
+            other => {
+                return Err(format!(
+                    "Unknown period '{}': use today, week or all",
+                    other
+                ))
+            }
+        };
+        let events = self.dashboard_events().await?;
+        let events: Vec<_> = events
+            .iter()
+            .filter(|e| since.is_none_or(|since| event_time(e).is_some_and(|t| t >= since)))
+            .collect();
+        let security = events
+            .iter()
+            .filter(|e| e["category"] == "security")
+            .count();
+        let untriaged = events.iter().filter(|e| e["status"] == "new").count();
+
+        #[derive(Deserialize)]
+        struct Stats {
+            domains: Vec<DomainStats>,
+        }
+        let stats: Stats = self.api_get("/api/stats?limit=5").await?;
+
+        let mut text = format!(
+            "📊 {} events ({})\n{} security, {} not triaged",
+            events.len(),
+            period,
+            security,
+            untriaged
+        );
+        if !stats.domains.is_empty() {
+            text.push_str("\n\nTop domains (all time):");
+            for d in &stats.domains {
+                text.push_str(&format!(
+                    "\n{} - {} requests, {} blocked",
+                    d.domain, d.requests, d.blocked
+                ));
+            }
+        }
+        Ok(text)
+    }
+
+    async fn alerts(&self) -> Result<String, String> {
+        let events = self.dashboard_events().await?;
+        let latest: Vec<_> = events
+            .iter()
+            .filter(|e| e["category"] == "security")
+            .take(LISTED_EVENTS)
+            .collect();
+        if latest.is_empty() {
+            return Ok("No security events".into());
+        }
+        let mut text = String::from("🚨 Latest security events:");
+        for e in latest {
+            let domain = [&e["script_domain"], &e["page_domain"]]
+                .into_iter()
+                .filter_map(|d| d.as_str())
+                .find(|d| !d.is_empty())
+                .unwrap_or("-");
+            text.push_str(&format!(
+                "\n{} {} on {} (client {}, risk {}, {})",
+                e["timestamp"].as_str().unwrap_or_default(),
+                e["event_type"].as_str().unwrap_or_default(),
+                domain,
+                e["client_id"].as_str().unwrap_or("-"),
+                e["risk_score"],
+                e["status"].as_str().unwrap_or_default()
+            ));
+        }
+        Ok(text)
+    }
+
+    async fn set_blocked(&self, domain: Option<&str>, blocked: bool) -> Result<String, String> {
+        let Some(domain) = domain.map(|d| d.trim_end_matches('.').to_ascii_lowercase()) else {
+            return Err(format!(
+                "Usage: /{} <domain>",
+                if blocked { "block" } else { "unblock" }
+            ));
+        };
+        if domain.is_empty()
+            || !domain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        {
+            return Err(format!("'{}' is not a domain name", domain));
+        }
+        let pattern = Blocklist::domain_pattern(&domain);
+        let mut blocklist: Blocklist = self.api_get("/api/blocklist").await?;
+        let listed = blocklist.url_patterns.contains(&pattern);
+        if listed == blocked {
+            return Ok(format!(
+                "{} is {} blocked",
+                domain,
+                if blocked { "already" } else { "not" }
+            ));
+        }
+        if blocked {
+            blocklist.url_patterns.push(pattern);
+        } else {
+            blocklist.url_patterns.retain(|p| *p != pattern);
+        }
+
+        let mut request = self
+            .http
+            .post(format!("{}/api/blocklist", self.server_url))
+            .json(&blocklist);
+        if let Some(token) = &self.admin_token {
+            request = request.header("x-admin-token", token);
+        }
+        let resp = request.send().await.map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("Blocklist update returned {}", resp.status()));
+        }
+        log::info!(
+            "🤖 {} {} from Telegram",
+            if blocked { "Blocked" } else { "Unblocked" },
+            domain
+        );
+        Ok(format!(
+            "{} {} ({} URL patterns)",
+            if blocked {
+                "⛔ Blocked"
+            } else {
+                "✅ Unblocked"
+            },
+            domain,
+            blocklist.url_patterns.len()
+        ))
+    }
+}
+
+fn event_time(event: &serde_json::Value) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(event["timestamp"].as_str()?)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
@@ -0,0 +1,293 @@
+//! Performance samples the extension reports about itself
+//! (`POST /api/telemetry`): how long matching a URL against the blocklist
+//! took, how long building a batch took, and how much memory the extension
+//! uses. They are aggregated per extension version, so it shows when a
+//! growing blocklist starts slowing browsers down.
+//!
+//! Each version keeps a count, mean and maximum of every sample since
+//! startup, and the last [`MAX_RECENT`] samples per metric for percentiles.
+//! A version is flagged `slow` when the p95 of its recent blocklist-match
+//! samples is over `--slow-blocklist-match`; a version becoming flagged is
+//! recorded as a `slow_extension` server event. Like clock skew this is kept
+//! per replica and starts empty after a restart.
+
+use crate::server_events;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Samples kept per version and metric for the percentiles.
+pub const MAX_RECENT: usize = 1000;
+/// Samples of one metric accepted in one report.
+pub const MAX_SAMPLES_PER_REPORT: usize = 500;
+/// Recent blocklist-match samples needed before a version is judged.
+pub const MIN_SAMPLES: usize = 20;
+/// Versions tracked; the least recently seen goes first.
+pub const MAX_VERSIONS: usize = 200;
+const MAX_VERSION_LEN: usize = 50;
+
+/// The samples of one report, by metric.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Samples {
+    #[serde(default)]
+    pub blocklist_match_ms: Vec<f64>,
+    #[serde(default)]
+    pub batch_build_ms: Vec<f64>,
+    #[serde(default)]
+    pub memory_mb: Vec<f64>,
+}
+
+impl Samples {
+    fn metrics(&self) -> [(&'static str, &[f64]); 3] {
+        [
+            ("blocklist_match_ms", &self.blocklist_match_ms),
+            ("batch_build_ms", &self.batch_build_ms),
+            ("memory_mb", &self.memory_mb),
+        ]
+    }
+}
+
+/// The body of `POST /api/telemetry`.
+#[derive(Debug, Deserialize)]
+pub struct PerfReport {
+    #[serde(default)]
+    pub client_id: Option<String>,
+    pub extension_version: String,
+    /// Rules in the blocklist the samples were taken with.
+    #[serde(default)]
+    pub blocklist_rules: Option<u64>,
+    pub samples: Samples,
+}
+
+impl PerfReport {
+    /// Checks the report: a version, and at most
+    /// [`MAX_SAMPLES_PER_REPORT`] finite, non-negative samples per metric.
+    pub fn validate(&self) -> Result<(), String> {
+        let version = self.extension_version.trim();
+        if version.is_empty() || version.len() > MAX_VERSION_LEN {
+            return Err(format!(
+                "extension_version must be 1 to {} characters",
+                MAX_VERSION_LEN
+            ));
+        }
+        for (name, values) in self.samples.metrics() {
+            if values.len() > MAX_SAMPLES_PER_REPORT {
+                return Err(format!(
+                    "at most {} {} samples per report",
+                    MAX_SAMPLES_PER_REPORT, name
+                ));
+            }
+            if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+                return Err(format!("{} samples must be non-negative numbers", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.metrics().iter().map(|(_, v)| v.len()).sum()
+    }
+}
+
+/// One metric of one version.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSummary {
+    pub count: u64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+/// What is known about one extension version's performance.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionPerf {
+    pub extension_version: String,
+    pub reports: u64,
+    /// Rules in the blocklist of the last report that said.
+    pub blocklist_rules: Option<u64>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub slow: bool,
+    pub metrics: BTreeMap<&'static str, MetricSummary>,
+}
+
+#[derive(Default)]
+struct Series {
+    count: u64,
+    sum: f64,
+    max: f64,
+    recent: VecDeque<f64>,
+}
+
+impl Series {
+    fn add(&mut self, values: &[f64]) {
+        for &v in values {
+            self.count += 1;
+            self.sum += v;
+            self.max = self.max.max(v);
+            self.recent.push_back(v);
+        }
+        while self.recent.len() > MAX_RECENT {
+            self.recent.pop_front();
+        }
+    }
+
+    /// The `q` quantile of the recent samples, nearest rank.
+    fn quantile(&self, q: f64) -> f64 {
+        let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let rank = ((sorted.len() as f64 * q).ceil() as usize).max(1);
+        sorted.get(rank - 1).copied().unwrap_or_default()
+    }
+
+    fn summary(&self) -> MetricSummary {
+        MetricSummary {
+            count: self.count,
+            mean: if self.count == 0 {
+                0.0
+            } else {
+                self.sum / self.count as f64
+            },
+            p50: self.quantile(0.5),
+            p95: self.quantile(0.95),
+            max: self.max,
+        }
+    }
+}
+
+struct Track {
+    series: HashMap<&'static str, Series>,
+    reports: u64,
+    blocklist_rules: Option<u64>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    slow: bool,
+}
+
+pub struct PerfTelemetry {
+    /// Blocklist-match p95 in milliseconds over which a version is slow.
+    slow_match_ms: f64,
+    versions: Mutex<HashMap<String, Track>>,
+}
+
+impl Default for PerfTelemetry {
+    fn default() -> Self {
+        Self::new(5.0)
+    }
+}
+
+impl PerfTelemetry {
+    pub fn new(slow_match_ms: f64) -> Self {
+        PerfTelemetry {
+            slow_match_ms,
+            versions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_slow(&self, track: &Track) -> bool {
+        track
+            .series
+            .get("blocklist_match_ms")
+            .filter(|s| s.recent.len() >= MIN_SAMPLES)
+            .is_some_and(|s| s.quantile(0.95) > self.slow_match_ms)
+    }
+
+    fn summary(version: &str, track: &Track) -> VersionPerf {
+        VersionPerf {
+            extension_version: version.to_string(),
+            reports: track.reports,
+            blocklist_rules: track.blocklist_rules,
+            first_seen: track.first_seen,
+            last_seen: track.last_seen,
+            slow: track.slow,
+            metrics: track
+                .series
+                .iter()
+                .filter(|(_, s)| s.count > 0)
+                .map(|(name, s)| (*name, s.summary()))
+                .collect(),
+        }
+    }
+
+    /// Adds a validated report and returns its version's aggregate.
+    pub fn record(&self, report: &PerfReport) -> VersionPerf {
+        let now = Utc::now();
+        let version = report.extension_version.trim();
+        let (summary, newly_slow) = {
+            let mut versions = self.versions.lock().unwrap();
+            if !versions.contains_key(version) && versions.len() >= MAX_VERSIONS {
+                let oldest = versions
+                    .iter()
+                    .min_by_key(|(_, t)| t.last_seen)
+                    .map(|(v, _)| v.clone());
+                if let Some(v) = oldest {
+                    versions.remove(&v);
+                }
+            }
+            let track = versions
+                .entry(version.to_string())
+                .or_insert_with(|| Track {
+                    series: HashMap::new(),
+                    reports: 0,
+                    blocklist_rules: None,
+                    first_seen: now,
+                    last_seen: now,
+                    slow: false,
+                });
+            track.reports += 1;
+            track.last_seen = now;
+            if report.blocklist_rules.is_some() {
+                track.blocklist_rules = report.blocklist_rules;
+            }
+            for (name, values) in report.samples.metrics() {
+                if !values.is_empty() {
+                    track.series.entry(name).or_default().add(values);
+                }
+            }
+            let slow = self.is_slow(track);
+            let newly_slow = slow && !track.slow;
+            track.slow = slow;
+            (Self::summary(version, track), newly_slow)
+        };
+        if newly_slow {
+            let p95 = summary.metrics.get("blocklist_match_ms").map(|m| m.p95);
+            log::warn!(
+                "🐢 Extension {} matches the blocklist slowly: p95 {:.1}ms with {:?} rules",
+                version,
+                p95.unwrap_or_default(),
+                summary.blocklist_rules
+            );
+            server_events::record(
+                "slow_extension",
+                serde_json::json!({
+                    "extension_version": version,
+                    "blocklist_match_p95_ms": p95,
+                    "threshold_ms": self.slow_match_ms,
+                    "blocklist_rules": summary.blocklist_rules,
+                    "client_id": report.client_id,
+                }),
+            );
+        }
+        summary
+    }
+
+    pub fn get(&self, version: &str) -> Option<VersionPerf> {
+        let versions = self.versions.lock().unwrap();
+        versions.get(version).map(|t| Self::summary(version, t))
+    }
+
+    /// Every version seen, most recently reporting first.
+    pub fn versions(&self) -> Vec<VersionPerf> {
+        let versions = self.versions.lock().unwrap();
+        let mut out: Vec<VersionPerf> = versions.iter().map(|(v, t)| Self::summary(v, t)).collect();
+        out.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then(a.extension_version.cmp(&b.extension_version))
+        });
+        out
+    }
+}
@@ -0,0 +1,395 @@
+//! Issue-tracker tickets for escalated security cases, so an escalation
+//! lands where the team that acts on it already works.
+//!
+//! `--ticket-config FILE` names the tracker, as JSON:
+//!
+//! ```json
+//! { "provider": "jira", "url": "https://acme.atlassian.net", "project": "SEC",
+//!   "issue_type": "Task", "labels": ["canigoin"], "user": "bot@acme.com", "token": "..." }
+//! { "provider": "github", "repo": "acme/security", "labels": ["canigoin"], "token": "..." }
+//! ```
+//!
+//! (GitHub Enterprise adds `"api_url": "https://github.acme.com/api/v3"`.)
+//! Marking an event `escalated` opens a ticket for it, unless it already has
+//! one, and keeps the ticket's URL with the event's triage. A tracker that
+//! can't be reached doesn't stop the escalation; escalating again retries.
+//! The `ticket_sync` job (every 10 minutes by default) asks the tracker
+//! about every open ticket and records the ones that were closed there,
+//! with a comment on the event.
+
+use crate::alert_sinks::DELIVERY_TIMEOUT;
+use crate::external_url;
+use crate::triage::TriageStore;
+use crate::types::CaseTicket;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Author of the comments the sync job leaves.
+pub const SYNC_AUTHOR: &str = "ticket-sync";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum TrackerConfig {
+    Jira {
+        /// The site, e.g. `https://acme.atlassian.net`.
+        url: String,
+        project: String,
+        #[serde(default = "default_issue_type")]
+        issue_type: String,
+        #[serde(default)]
+        labels: Vec<String>,
+        /// Account email; Jira API tokens use basic auth.
+        user: String,
+        token: String,
+    },
+    Github {
+        #[serde(default = "default_github_api")]
+        api_url: String,
+        /// `owner/name`.
+        repo: String,
+        #[serde(default)]
+        labels: Vec<String>,
+        token: String,
+    },
+}
+
+fn default_issue_type() -> String {
+    "Task".to_string()
+}
+
+fn default_github_api() -> String {
+    "https://api.github.com".to_string()
+}
+
+impl TrackerConfig {
+    fn provider(&self) -> &'static str {
+        match self {
+            TrackerConfig::Jira { .. } => "jira",
+            TrackerConfig::Github { .. } => "github",
+        }
+    }
+}
+
+/// What a new ticket says about the case.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub packet_id: String,
+    pub note: Option<String>,
+    pub escalated_by: Option<String>,
+}
+
+impl Case {
+    fn title(&self) -> String {
+        format!("Security case {} escalated", self.packet_id)
+    }
+
+    fn description(&self) -> String {
+        let mut text = format!(
+            "Event {} was escalated by {}.",
+            self.packet_id,
+            self.escalated_by.as_deref().unwrap_or("an analyst")
+        );
+        if let Some(note) = &self.note {
+            text.push_str(&format!("\n\n{}", note));
+        }
+        if let Some(link) = external_url::packet_link(&self.packet_id) {
+            text.push_str(&format!("\n\n{}", link));
+        }
+        text
+    }
+}
+
+#[derive(Deserialize)]
+struct JiraCreated {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct JiraIssue {
+    fields: JiraFields,
+}
+
+#[derive(Deserialize)]
+struct JiraFields {
+    status: JiraStatus,
+    resolutiondate: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JiraStatus {
+    status_category: JiraStatusCategory,
+}
+
+#[derive(Deserialize)]
+struct JiraStatusCategory {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct GithubIssue {
+    number: u64,
+    html_url: String,
+    state: String,
+    closed_at: Option<DateTime<Utc>>,
+}
+
+pub struct Ticketing {
+    tracker: Option<TrackerConfig>,
+    http: reqwest::Client,
+}
+
+impl Ticketing {
+    pub fn disabled() -> Self {
+        Ticketing {
+            tracker: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn new(tracker: TrackerConfig) -> Result<Self, String> {
+        let base = match &tracker {
+            TrackerConfig::Jira { url, .. } => url,
+            TrackerConfig::Github { api_url, .. } => api_url,
+        };
+        external_url::parse(base)?;
+        if let TrackerConfig::Github { repo, .. } = &tracker {
+            if repo.split('/').filter(|p| !p.is_empty()).count() != 2 {
+                return Err(format!("repo '{}' should be owner/name", repo));
+            }
+        }
+        Ok(Ticketing {
+            tracker: Some(tracker),
+            http: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .user_agent("CanIGoIn")
+                .build()
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    /// Reads `--ticket-config`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let tracker: TrackerConfig = serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not a valid tracker: {}", path.display(), e))?;
+        Self::new(tracker)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tracker.is_some()
+    }
+
+    /// `jira` or `github`.
+    pub fn provider(&self) -> Option<&'static str> {
+        self.tracker.as_ref().map(TrackerConfig::provider)
+    }
+
+    /// Opens a ticket for `case`.
+    pub async fn open(&self, case: &Case) -> Result<CaseTicket, String> {
+        let tracker = self.tracker.as_ref().ok_or("no tracker configured")?;
+        let (key, url) = match tracker {
+            TrackerConfig::Jira {
+                url,
+                project,
+                issue_type,
+                labels,
+                user,
+                token,
+            } => {
+                let base = url.trim_end_matches('/');
+                let body = serde_json::json!({
+                    "fields": {
+                        "project": { "key": project },
+                        "summary": case.title(),
+                        "description": case.description(),
+                        "issuetype": { "name": issue_type },
+                        "labels": labels
+                    }
+                });
+                let request = self
+                    .http
+                    .post(format!("{}/rest/api/2/issue", base))
+                    .basic_auth(user, Some(token))
+                    .json(&body);
+                let created: JiraCreated = send(request).await?;
+                let link = format!("{}/browse/{}", base, created.key);
+                (created.key, link)
+            }
+            TrackerConfig::Github {
+                api_url,
+                repo,
+                labels,
+                token,
+            } => {
+                let body = serde_json::json!({
+                    "title": case.title(),
+                    "body": case.description(),
+                    "labels": labels
+                });
+                let request = self
+                    .http
+                    .post(format!(
+                        "{}/repos/{}/issues",
+                        api_url.trim_end_matches('/'),
+                        repo
+                    ))
+                    .bearer_auth(token)
+                    .header("Accept", "application/vnd.github+json")
+                    .json(&body);
+                let issue: GithubIssue = send(request).await?;
+                (issue.number.to_string(), issue.html_url)
+            }
+        };
+        Ok(CaseTicket {
+            packet_id: case.packet_id.clone(),
+            provider: tracker.provider().to_string(),
+            key,
+            url,
+            closed: false,
+            created_at: Utc::now(),
+            closed_at: None,
+            synced_at: None,
+        })
+    }
+
+    /// When the ticket was closed in the tracker; `None` while it is open.
+    pub async fn closed_at(&self, ticket: &CaseTicket) -> Result<Option<DateTime<Utc>>, String> {
+        let tracker = self.tracker.as_ref().ok_or("no tracker configured")?;
+        match tracker {
+            TrackerConfig::Jira {
+                url, user, token, ..
+            } => {
+                let request = self
+                    .http
+                    .get(format!(
+                        "{}/rest/api/2/issue/{}?fields=status,resolutiondate",
+                        url.trim_end_matches('/'),
+                        ticket.key
+                    ))
+                    .basic_auth(user, Some(token));
+                let issue: JiraIssue = send(request).await?;
+                if issue.fields.status.status_category.key != "done" {
+                    return Ok(None);
+                }
+                // Jira's "2024-05-01T10:00:00.000+0000" isn't quite RFC 3339.
+                let resolved = issue
+                    .fields
+                    .resolutiondate
+                    .and_then(|d| DateTime::parse_from_str(&d, "%Y-%m-%dT%H:%M:%S%.f%z").ok())
+                    .map(|d| d.with_timezone(&Utc));
+                Ok(Some(resolved.unwrap_or_else(Utc::now)))
+            }
+            TrackerConfig::Github {
+                api_url,
+                repo,
+                token,
+                ..
+            } => {
+                let request = self
+                    .http
+                    .get(format!(
+                        "{}/repos/{}/issues/{}",
+                        api_url.trim_end_matches('/'),
+                        repo,
+                        ticket.key
+                    ))
+                    .bearer_auth(token)
+                    .header("Accept", "application/vnd.github+json");
+                let issue: GithubIssue = send(request).await?;
+                if issue.state != "closed" {
+                    return Ok(None);
+                }
+                Ok(Some(issue.closed_at.unwrap_or_else(Utc::now)))
+            }
+        }
+    }
+}
+
+async fn send<T: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+) -> Result<T, String> {
+    request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.without_url().to_string())?
+        .json()
+        .await
+        .map_err(|e| format!("unexpected tracker response: {}", e.without_url()))
+}
+
+/// Opens a ticket for an escalated case that has none yet and stores it
+/// with the case's triage. `Ok(None)` without a tracker or when the case
+/// already has a ticket.
+pub async fn open_for(
+    ticketing: &Ticketing,
+    triage: &TriageStore,
+    case: Case,
+) -> Result<Option<CaseTicket>, String> {
+    if !ticketing.is_enabled() || triage.ticket(&case.packet_id).is_some() {
+        return Ok(None);
+    }
+    let ticket = ticketing.open(&case).await?;
+    log::info!(
+        "🎫 Ticket {} opened for escalated event {}: {}",
+        ticket.key,
+        ticket.packet_id,
+        ticket.url
+    );
+    triage.save_ticket(ticket).await.map(Some)
+}
+
+/// The `ticket_sync` job: records tickets closed in the tracker.
+pub async fn sync_job(
+    ticketing: web::Data<Ticketing>,
+    triage: web::Data<TriageStore>,
+) -> Result<String, String> {
+    let open = triage.open_tickets();
+    let (mut closed, mut failed) = (0, Vec::new());
+    for mut ticket in open.iter().cloned() {
+        let closed_at = match ticketing.closed_at(&ticket).await {
+            Ok(closed_at) => closed_at,
+            Err(e) => {
+                failed.push(format!("{}: {}", ticket.key, e));
+                continue;
+            }
+        };
+        ticket.synced_at = Some(Utc::now());
+        ticket.closed = closed_at.is_some();
+        ticket.closed_at = closed_at;
+        let packet_id = ticket.packet_id.clone();
+        let key = ticket.key.clone();
+        triage.save_ticket(ticket).await?;
+        if closed_at.is_some() {
+            closed += 1;
+            log::info!("🎫 Ticket {} for event {} was closed", key, packet_id);
+            triage
+                .add_comment(
+                    &packet_id,
+                    None,
+                    Some(SYNC_AUTHOR.to_string()),
+                    format!("Ticket {} was closed in the tracker.", key),
+                )
+                .await?;
+        }
+    }
+    if !failed.is_empty() && failed.len() == open.len() {
+        return Err(format!(
+            "no ticket could be checked ({})",
+            failed.join("; ")
+        ));
+    }
+    let mut report = format!("{} open tickets checked, {} closed", open.len(), closed);
+    if !failed.is_empty() {
+        report.push_str(&format!(
+            ", {} failed ({})",
+            failed.len(),
+            failed.join("; ")
+        ));
+    }
+    Ok(report)
+}
@@ -0,0 +1,275 @@
+//! Labeled samples of stored events for training detection models
+//! (`GET /api/export/training-set`).
+//!
+//! Every event is labeled `security` or `benign`. Security events (posted to
+//! `/api/security` or raised by the server's detectors) are `security`
+//! unless an analyst triaged them `false-positive`; everything else is
+//! `benign`. `label_source` says whether triage or the event's category
+//! decided.
+//!
+//! Each label has its own sampling rate, so the rare class can be kept
+//! whole and the common one thinned out. An event is kept when a hash of
+//! its packet_id under `seed` falls below the rate, so exporting again with
+//! the same seed picks the same events.
+//!
+//! Nothing that identifies a person leaves unscrubbed: client, session and
+//! profile ids become pseudonyms (stable within one export, different in
+//! the next); in the event data, fields whose names suggest credentials or
+//! contact details are replaced with `[redacted]`, email addresses with
+//! `[email]`, IPv4 addresses with `[ip]`, and URLs lose their query string
+//! and fragment.
+
+use crate::triage::TriageStore;
+use crate::types::{ExtensionEvent, TriageStatus};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_SECURITY_RATE: f64 = 1.0;
+pub const DEFAULT_BENIGN_RATE: f64 = 0.1;
+pub const DEFAULT_LIMIT: usize = 10_000;
+pub const MAX_LIMIT: usize = 100_000;
+
+/// Data fields whose values are dropped, matched as parts of the name.
+const SENSITIVE_KEYS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "cookie",
+    "authorization",
+    "email",
+    "username",
+    "phone",
+    "address",
+    "ssn",
+    "card",
+];
+/// Ids in the event data replaced by pseudonyms, like the event's own.
+const ID_KEYS: &[&str] = &["client_id", "session_id", "profile_id"];
+/// Bookkeeping the server adds, not part of what the client saw.
+const DROPPED_KEYS: &[&str] = &["chain", "instance_id"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Label {
+    Security,
+    Benign,
+}
+
+/// The label of `event` and what decided it (`triage` or `category`).
+pub fn label(event: &ExtensionEvent, triage: &TriageStore) -> (Label, &'static str) {
+    let security = event.data.get("category").and_then(Value::as_str) == Some("security");
+    let status = event
+        .data
+        .get("packet_id")
+        .and_then(Value::as_str)
+        .map(|id| triage.status(id))
+        .unwrap_or_default();
+    match (security, status) {
+        (true, TriageStatus::FalsePositive) => (Label::Benign, "triage"),
+        (true, TriageStatus::Acknowledged | TriageStatus::Escalated) => (Label::Security, "triage"),
+        (true, _) => (Label::Security, "category"),
+        (false, _) => (Label::Benign, "category"),
+    }
+}
+
+/// A hash of `seed` and `key` in `[0, 1)`.
+fn unit_hash(seed: &str, key: &str) -> f64 {
+    let digest = Sha256::digest(format!("{}\n{}", seed, key).as_bytes());
+    let n = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (n >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// How one export samples.
+#[derive(Debug, Clone)]
+pub struct Sampling {
+    pub security_rate: f64,
+    pub benign_rate: f64,
+    pub seed: String,
+    pub limit: usize,
+}
+
+impl Sampling {
+    /// Checks the query's rates (0 to 1) and limit (1 to [`MAX_LIMIT`]).
+    pub fn new(
+        security_rate: Option<f64>,
+        benign_rate: Option<f64>,
+        seed: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Self, String> {
+        let rate = |r: Option<f64>, default: f64, name: &str| {
+            let r = r.unwrap_or(default);
+            if (0.0..=1.0).contains(&r) {
+                Ok(r)
+            } else {
+                Err(format!("{} must be between 0 and 1", name))
+            }
+        };
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        Ok(Sampling {
+            security_rate: rate(security_rate, DEFAULT_SECURITY_RATE, "security_rate")?,
+            benign_rate: rate(benign_rate, DEFAULT_BENIGN_RATE, "benign_rate")?,
+            seed: seed.unwrap_or_else(|| "training".to_string()),
+            limit,
+        })
+    }
+
+    pub fn keeps(&self, event: &ExtensionEvent, label: Label) -> bool {
+        let rate = match label {
+            Label::Security => self.security_rate,
+            Label::Benign => self.benign_rate,
+        };
+        let Some(packet_id) = event.data.get("packet_id").and_then(Value::as_str) else {
+            return false;
+        };
+        unit_hash(&self.seed, packet_id) < rate
+    }
+}
+
+/// Replaces identifying values; see the module docs.
+pub struct Scrubber {
+    salt: String,
+}
+
+impl Default for Scrubber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scrubber {
+    /// Pseudonyms under a fresh random salt.
+    pub fn new() -> Self {
+        Scrubber {
+            salt: crate::auth::random_token(),
+        }
+    }
+
+    pub fn pseudonym(&self, id: &str) -> String {
+        let digest = Sha256::digest(format!("{}\n{}", self.salt, id).as_bytes());
+        format!("anon-{}", &hex::encode(digest)[..16])
+    }
+
+    fn value(&self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                fields.retain(|k, _| !DROPPED_KEYS.contains(&k.as_str()));
+                for (key, v) in fields.iter_mut() {
+                    let lower = key.to_ascii_lowercase();
+                    if ID_KEYS.contains(&lower.as_str()) {
+                        if let Some(id) = v.as_str() {
+                            *v = Value::String(self.pseudonym(id));
+                            continue;
+                        }
+                    }
+                    if SENSITIVE_KEYS.iter().any(|s| lower.contains(s)) {
+                        *v = Value::String("[redacted]".to_string());
+                    } else {
+                        self.value(v);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.value(v)),
+            Value::String(s) => *s = scrub_text(s),
+            _ => {}
+        }
+    }
+
+    /// One line of the export.
+    pub fn record(&self, event: &ExtensionEvent, label: Label, label_source: &str) -> Value {
+        let mut data = event.data.clone();
+        self.value(&mut data);
+        serde_json::json!({
+            "label": label,
+            "label_source": label_source,
+            "packet_id": event.data.get("packet_id"),
+            "event_type": event.event_type,
+            "category": event.data.get("category"),
+            "timestamp": event.timestamp,
+            "client": event.client_id.as_deref().map(|id| self.pseudonym(id)),
+            "session": self.pseudonym(&event.session_id),
+            "profile": event.profile_id.as_deref().map(|id| self.pseudonym(id)),
+            "user_agent": event.user_agent,
+            "data": data
+        })
+    }
+}
+
+/// Masks email and IPv4 addresses and strips URL queries and fragments in
+/// free text, word by word.
+pub fn scrub_text(text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|piece| {
+            let word = piece.trim_end_matches(char::is_whitespace);
+            let space = &piece[word.len()..];
+            format!("{}{}", scrub_word(word), space)
+        })
+        .collect()
+}
+
+fn scrub_word(word: &str) -> String {
+    let trimmed = word.trim_matches(|c: char| "\"'()<>[],;".contains(c));
+    if trimmed.is_empty() {
+        return word.to_string();
+    }
+    let replacement = if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+        let end = trimmed.find(['?', '#']).unwrap_or(trimmed.len());
+        trimmed[..end].to_string()
+    } else if trimmed
+        .split_once('@')
+        .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'))
+    {
+        "[email]".to_string()
+    } else if trimmed
+        .trim_end_matches('.')
+        .parse::<std::net::Ipv4Addr>()
+        .is_ok()
+    {
+        "[ip]".to_string()
+    } else {
+        return word.to_string();
+    };
+    word.replacen(trimmed, &replacement, 1)
+}
+
+/// The export so far, for the response headers and the log.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub scanned: usize,
+    pub security: usize,
+    pub benign: usize,
+}
+
+/// Samples `events` into NDJSON lines on `out` until `sampling.limit` lines
+/// are written. Returns whether the limit was reached.
+pub fn sample_into(
+    events: &[ExtensionEvent],
+    sampling: &Sampling,
+    scrubber: &Scrubber,
+    triage: &TriageStore,
+    counts: &mut Counts,
+    out: &mut Vec<u8>,
+) -> bool {
+    for event in events {
+        if counts.security + counts.benign >= sampling.limit {
+            return true;
+        }
+        counts.scanned += 1;
+        let (label, source) = label(event, triage);
+        if !sampling.keeps(event, label) {
+            continue;
+        }
+        match label {
+            Label::Security => counts.security += 1,
+            Label::Benign => counts.benign += 1,
+        }
+        serde_json::to_writer(&mut *out, &scrubber.record(event, label, source))
+            .expect("JSON value");
+        out.push(b'\n');
+    }
+    counts.security + counts.benign >= sampling.limit
+}
@@ -0,0 +1,249 @@
+//! Triage of dashboard events: a status (new, acknowledged, false-positive,
+//! escalated), the analyst it is assigned to, and a thread of comments, so
+//! analysts can work through security events together instead of re-reading
+//! the same list. Only the latest status and assignee per packet_id are kept;
+//! comments accumulate. Escalated events can also carry the issue-tracker
+//! ticket opened for them (see [`crate::tickets`]).
+
+use crate::types::{CaseTicket, EventComment, EventTriage, TriageStatus};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::RwLock;
+
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+pub const MAX_NOTE_LEN: usize = 4000;
+pub const MAX_COMMENT_LEN: usize = 4000;
+/// Assignee and comment author names.
+pub const MAX_NAME_LEN: usize = 100;
+
+/// How often production replicas re-read the triage tables.
+#[cfg(feature = "production")]
+const RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub struct TriageStore {
+    triage: RwLock<HashMap<String, EventTriage>>,
+    comments: RwLock<Vec<EventComment>>,
+    tickets: RwLock<HashMap<String, CaseTicket>>,
+    /// Comment id source for simple mode; the database assigns ids otherwise.
+    next_comment_id: AtomicI64,
+    #[cfg(feature = "production")]
+    storage: Option<Arc<crate::production::ProductionState>>,
+}
+
+impl Default for TriageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TriageStore {
+    /// In-memory only (simple mode): triage doesn't survive a restart.
+    pub fn new() -> Self {
+        TriageStore {
+            triage: RwLock::new(HashMap::new()),
+            comments: RwLock::new(Vec::new()),
+            tickets: RwLock::new(HashMap::new()),
+            next_comment_id: AtomicI64::new(1),
+            #[cfg(feature = "production")]
+            storage: None,
+        }
+    }
+
+    /// Persists triage in `event_triage`, `event_comments` and `case_tickets`
+    /// and keeps the
+    /// in-memory copy in sync with them.
+    #[cfg(feature = "production")]
+    pub fn spawn(state: Arc<crate::production::ProductionState>) -> Arc<TriageStore> {
+        let store = Arc::new(TriageStore {
+            triage: RwLock::new(HashMap::new()),
+            comments: RwLock::new(Vec::new()),
+            tickets: RwLock::new(HashMap::new()),
+            next_comment_id: AtomicI64::new(1),
+            storage: Some(state.clone()),
+        });
+        let s = store.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match state.get_event_triage().await {
+                    Ok(list) => {
+                        *s.triage.write().unwrap() =
+                            list.into_iter().map(|t| (t.packet_id.clone(), t)).collect();
+                    }
+                    Err(e) => log::error!("❌ Loading event triage failed: {}", e),
+                }
+                match state.get_event_comments().await {
+                    Ok(list) => *s.comments.write().unwrap() = list,
+                    Err(e) => log::error!("❌ Loading event comments failed: {}", e),
+                }
+                match state.get_case_tickets().await {
+                    Ok(list) => {
+                        *s.tickets.write().unwrap() =
+                            list.into_iter().map(|t| (t.packet_id.clone(), t)).collect();
+                    }
+                    Err(e) => log::error!("❌ Loading case tickets failed: {}", e),
+                }
+                tokio::time::sleep(RESYNC_INTERVAL).await;
+            }
+        });
+        store
+    }
+
+    pub fn get(&self, packet_id: &str) -> Option<EventTriage> {
+        self.triage.read().unwrap().get(packet_id).cloned()
+    }
+
+    /// `new` for events that have never been triaged.
+    pub fn status(&self, packet_id: &str) -> TriageStatus {
+        self.triage
+            .read()
+            .unwrap()
+            .get(packet_id)
+            .map(|t| t.status)
+            .unwrap_or_default()
+    }
+
+    pub fn assignee(&self, packet_id: &str) -> Option<String> {
+        self.triage
+            .read()
+            .unwrap()
+            .get(packet_id)
+            .and_then(|t| t.assignee.clone())
+    }
+
+    /// Changes the status and note; the assignee stays.
+    pub async fn set_status(
+        &self,
+        packet_id: &str,
+        status: TriageStatus,
+        note: Option<String>,
+        updated_by: Option<String>,
+    ) -> Result<EventTriage, String> {
+        let assignee = self.assignee(packet_id);
+        self.save(EventTriage {
+            packet_id: packet_id.to_string(),
+            status,
+            note,
+            assignee,
+            updated_by,
+            updated_at: Utc::now(),
+        })
+        .await
+    }
+
+    /// Changes the assignee (`None` unassigns); the status and note stay.
+    pub async fn assign(
+        &self,
+        packet_id: &str,
+        assignee: Option<String>,
+        updated_by: Option<String>,
+    ) -> Result<EventTriage, String> {
+        let current = self.get(packet_id);
+        self.save(EventTriage {
+            packet_id: packet_id.to_string(),
+            status: current.as_ref().map(|t| t.status).unwrap_or_default(),
+            note: current.and_then(|t| t.note),
+            assignee,
+            updated_by,
+            updated_at: Utc::now(),
+        })
+        .await
+    }
+
+    async fn save(&self, entry: EventTriage) -> Result<EventTriage, String> {
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            state
+                .set_event_triage(&entry)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        self.triage
+            .write()
+            .unwrap()
+            .insert(entry.packet_id.clone(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Comments on one event, oldest first.
+    pub fn comments(&self, packet_id: &str) -> Vec<EventComment> {
+        self.comments
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|c| c.packet_id == packet_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn comment_count(&self, packet_id: &str) -> usize {
+        self.comments
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|c| c.packet_id == packet_id)
+            .count()
+    }
+
+    pub async fn add_comment(
+        &self,
+        packet_id: &str,
+        parent_id: Option<i64>,
+        author: Option<String>,
+        body: String,
+    ) -> Result<EventComment, String> {
+        let mut comment = EventComment {
+            id: 0,
+            packet_id: packet_id.to_string(),
+            parent_id,
+            author,
+            body,
+            created_at: Utc::now(),
+        };
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            comment.id = state
+                .add_event_comment(&comment)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        if comment.id == 0 {
+            comment.id = self.next_comment_id.fetch_add(1, Ordering::Relaxed);
+        }
+        self.comments.write().unwrap().push(comment.clone());
+        Ok(comment)
+    }
+
+    pub fn ticket(&self, packet_id: &str) -> Option<CaseTicket> {
+        self.tickets.read().unwrap().get(packet_id).cloned()
+    }
+
+    /// Tickets not yet closed in the tracker.
+    pub fn open_tickets(&self) -> Vec<CaseTicket> {
+        self.tickets
+            .read()
+            .unwrap()
+            .values()
+            .filter(|t| !t.closed)
+            .cloned()
+            .collect()
+    }
+
+    /// Inserts or replaces the ticket of `ticket.packet_id`.
+    pub async fn save_ticket(&self, ticket: CaseTicket) -> Result<CaseTicket, String> {
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            state
+                .set_case_ticket(&ticket)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        self.tickets
+            .write()
+            .unwrap()
+            .insert(ticket.packet_id.clone(), ticket.clone());
+        Ok(ticket)
+    }
+}
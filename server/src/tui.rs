@@ -0,0 +1,618 @@
+//! The `tui` command: a terminal viewer for a running server, for operators
+//! in an SSH session who don't want to tunnel the web dashboard. It follows
+//! `/api/logs/tail`, polls `/api/dashboard/events?filter=security` and
+//! `/api/stats` for the counters, and draws them with ratatui.
+//!
+//! crossterm puts the terminal in raw mode on the alternate screen for the
+//! key bindings and restores it on exit, panics included.
+
+use crate::cli::TuiArgs;
+use crate::types::LogEntry;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures_util::StreamExt;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line as TextLine, Span};
+use ratatui::widgets::{Paragraph, Widget};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Lines kept per pane for scrolling back.
+pub const MAX_KEPT: usize = 2000;
+/// Event ids remembered so a poll doesn't list an event twice: more than a
+/// poll returns, so the oldest can be forgotten.
+const SEEN_KEPT: usize = 2 * MAX_KEPT;
+/// Wait before following the tail again after the connection drops.
+const RECONNECT_AFTER: Duration = Duration::from_secs(2);
+/// Most screen redraws per second while data streams in.
+const REDRAW_EVERY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Plain,
+    Dim,
+    Yellow,
+    Red,
+}
+
+impl Color {
+    fn style(self) -> Style {
+        match self {
+            Color::Plain => Style::new(),
+            Color::Dim => Style::new().dim(),
+            Color::Yellow => Style::new().yellow(),
+            Color::Red => Style::new().red(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    pub color: Color,
+}
+
+/// The `/api/stats` totals shown in the header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub batches: u64,
+    pub logs: u64,
+    pub blocked: u64,
+    pub suspicious: u64,
+    pub clients_today: u64,
+    pub domains_today: u64,
+}
+
+#[derive(Debug)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Tab,
+}
+
+/// What the feeds and the keyboard hand to the [`Viewer`].
+#[derive(Debug)]
+pub enum Update {
+    Log(Box<LogEntry>),
+    /// The server skipped batches this reader was too slow for.
+    Skipped(u64),
+    Event(serde_json::Value),
+    Counters(Counters),
+    Following(bool),
+    Status(String),
+    Key(Key),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Logs,
+    Events,
+}
+
+/// The screen's state; [`Viewer::render`] draws it.
+pub struct Viewer {
+    server: String,
+    logs: VecDeque<Line>,
+    events: VecDeque<Line>,
+    seen_events: HashSet<String>,
+    /// `seen_events` oldest first, to forget beyond [`SEEN_KEPT`].
+    seen_order: VecDeque<String>,
+    counters: Option<Counters>,
+    following: bool,
+    status: String,
+    focus: Pane,
+    /// Lines scrolled back from the newest, per pane.
+    scroll: [usize; 2],
+    pub paused: bool,
+    pub quit: bool,
+}
+
+fn time_of(timestamp: &str) -> &str {
+    timestamp.get(11..19).unwrap_or(timestamp)
+}
+
+/// `text` without control characters, which the server passes on from
+/// clients and which would otherwise reach the terminal as escapes.
+fn printable(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn push(lines: &mut VecDeque<Line>, line: Line) {
+    if lines.len() == MAX_KEPT {
+        lines.pop_front();
+    }
+    lines.push_back(Line {
+        text: printable(&line.text),
+        color: line.color,
+    });
+}
+
+impl Viewer {
+    pub fn new(server: &str) -> Self {
+        Viewer {
+            server: server.to_string(),
+            logs: VecDeque::new(),
+            events: VecDeque::new(),
+            seen_events: HashSet::new(),
+            seen_order: VecDeque::new(),
+            counters: None,
+            following: false,
+            status: "connecting…".to_string(),
+            focus: Pane::Logs,
+            scroll: [0, 0],
+            paused: false,
+            quit: false,
+        }
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Log(entry) => {
+                let client = entry.client_id.as_deref().unwrap_or("-");
+                for log in &entry.logs {
+                    let color = if log.blocked {
+                        Color::Red
+                    } else if log.reputation_score.is_some_and(|s| s >= 50) {
+                        Color::Yellow
+                    } else if log.request_type != "main_frame" {
+                        Color::Dim
+                    } else {
+                        Color::Plain
+                    };
+                    let text = format!(
+                        "{}  {:<16} {:<6} {:<10} {}{}",
+                        time_of(&entry.timestamp),
+                        client,
+                        log.method,
+                        log.request_type,
+                        log.url,
+                        if log.blocked { "  [blocked]" } else { "" }
+                    );
+                    push(&mut self.logs, Line { text, color });
+                }
+            }
+            Update::Skipped(n) => push(
+                &mut self.logs,
+                Line {
+                    text: format!("… {} batches skipped", n),
+                    color: Color::Yellow,
+                },
+            ),
+            Update::Event(event) => {
+                let field = |name: &str| event.get(name).and_then(|v| v.as_str()).unwrap_or("");
+                let id = field("packet_id").to_string();
+                if !self.seen_events.insert(id.clone()) {
+                    return;
+                }
+                self.seen_order.push_back(id);
+                if self.seen_order.len() > SEEN_KEPT {
+                    if let Some(oldest) = self.seen_order.pop_front() {
+                        self.seen_events.remove(&oldest);
+                    }
+                }
+                let risk = event.get("risk_score").and_then(|v| v.as_i64());
+                let color = match risk {
+                    Some(r) if r >= 70 => Color::Red,
+                    Some(r) if r >= 40 => Color::Yellow,
+                    _ => Color::Plain,
+                };
+                let client = match field("client_name") {
+                    "" => field("client_id"),
+                    name => name,
+                };
+                let domain = match field("script_domain") {
+                    "" => field("page_domain"),
+                    script => script,
+                };
+                let text = format!(
+                    "{}  {:>3}  {:<28} {:<16} {}",
+                    time_of(field("timestamp")),
+                    risk.map(|r| r.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    field("event_type"),
+                    client,
+                    domain
+                );
+                push(&mut self.events, Line { text, color });
+            }
+            Update::Counters(counters) => self.counters = Some(counters),
+            Update::Following(following) => {
+                self.following = following;
+                if following {
+                    self.status.clear();
+                }
+            }
+            Update::Status(status) => self.status = printable(&status),
+            Update::Key(key) => self.key(key),
+        }
+    }
+
+    fn key(&mut self, key: Key) {
+        let pane = self.focus as usize;
+        let len = match self.focus {
+            Pane::Logs => self.logs.len(),
+            Pane::Events => self.events.len(),
+        };
+        match key {
+            Key::Char('q') => self.quit = true,
+            Key::Char('p') | Key::Char(' ') => self.paused = !self.paused,
+            Key::Char('c') => {
+                self.logs.clear();
+                self.events.clear();
+                self.scroll = [0, 0];
+            }
+            Key::Tab => {
+                self.focus = match self.focus {
+                    Pane::Logs => Pane::Events,
+                    Pane::Events => Pane::Logs,
+                }
+            }
+            Key::Up | Key::Char('k') => self.scroll[pane] = (self.scroll[pane] + 1).min(len),
+            Key::Down | Key::Char('j') => self.scroll[pane] = self.scroll[pane].saturating_sub(1),
+            Key::Char('G') => self.scroll[pane] = 0,
+            Key::Char(_) => {}
+        }
+    }
+
+    /// The screen as plain text, `height` lines of at most `width`
+    /// characters.
+    pub fn render(&self, width: u16, height: u16) -> String {
+        let area = Rect::new(0, 0, width, height);
+        let mut buffer = Buffer::empty(area);
+        self.render_ref(area, &mut buffer);
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_ref(&self, area: Rect, buf: &mut Buffer) {
+        let body = area.height.saturating_sub(5);
+        let logs_height = body * 2 / 3;
+        let [header, counters, logs_title, logs, events_title, events, footer] =
+            Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Length(logs_height),
+                Constraint::Length(1),
+                Constraint::Length(body - logs_height),
+                Constraint::Length(1),
+            ])
+            .areas(area);
+
+        TextLine::from(format!(" CanIGoIn  {}", self.server))
+            .bold()
+            .render(header, buf);
+        let text = match &self.counters {
+            Some(c) => format!(
+                " batches {}  logs {}  blocked {}  suspicious {}  clients today {}  domains today {}",
+                c.batches, c.logs, c.blocked, c.suspicious, c.clients_today, c.domains_today
+            ),
+            None => " waiting for /api/stats…".to_string(),
+        };
+        TextLine::from(text).render(counters, buf);
+
+        for (pane, title, lines, title_area, rows) in [
+            (Pane::Logs, "Logs", &self.logs, logs_title, logs),
+            (
+                Pane::Events,
+                "Security events",
+                &self.events,
+                events_title,
+                events,
+            ),
+        ] {
+            let scroll = self.scroll[pane as usize];
+            let title = format!(
+                "── {} ({}){} ",
+                title,
+                lines.len(),
+                if scroll > 0 {
+                    format!(", {} back", scroll)
+                } else {
+                    String::new()
+                }
+            );
+            let rule = "─".repeat((area.width as usize).saturating_sub(title.chars().count()));
+            let style = if self.focus == pane {
+                Style::new().cyan().bold()
+            } else {
+                Style::new().dim()
+            };
+            TextLine::styled(format!("{}{}", title, rule), style).render(title_area, buf);
+            let end = lines.len() - scroll.min(lines.len());
+            let start = end.saturating_sub(rows.height as usize);
+            let shown: Vec<TextLine> = lines
+                .range(start..end)
+                .map(|line| TextLine::styled(line.text.as_str(), line.color.style()))
+                .collect();
+            Paragraph::new(shown).render(rows, buf);
+        }
+
+        let state = if self.paused {
+            Span::from("❚❚ paused").yellow()
+        } else if self.following {
+            Span::from("● live").green()
+        } else {
+            Span::from("○ offline").red()
+        };
+        TextLine::from(vec![
+            Span::from(" "),
+            state,
+            Span::from(format!(
+                "  q quit · p pause · c clear · tab switch pane · ↑↓ scroll · G newest  {}",
+                self.status
+            )),
+        ])
+        .style(Style::new().add_modifier(Modifier::REVERSED))
+        .render(footer, buf);
+    }
+}
+
+/// Where the viewer reads from, with the credentials and filter to send.
+#[derive(Clone)]
+pub struct Source {
+    base: String,
+    admin_token: Option<String>,
+    client_id: Option<String>,
+    client: reqwest::Client,
+}
+
+fn counters(stats: &serde_json::Value) -> Counters {
+    let n = |path: &str| stats.pointer(path).and_then(|v| v.as_u64()).unwrap_or(0);
+    Counters {
+        batches: n("/ingest/batches"),
+        logs: n("/ingest/logs"),
+        blocked: n("/ingest/blocked"),
+        suspicious: n("/ingest/suspicious"),
+        clients_today: n("/distinct/today/clients"),
+        domains_today: n("/distinct/today/domains"),
+    }
+}
+
+fn tail_line(line: &[u8]) -> Option<Update> {
+    if let Ok(entry) = serde_json::from_slice::<LogEntry>(line) {
+        return Some(Update::Log(Box::new(entry)));
+    }
+    let value: serde_json::Value = serde_json::from_slice(line).ok()?;
+    value
+        .get("skipped")
+        .and_then(|n| n.as_u64())
+        .map(Update::Skipped)
+}
+
+impl Source {
+    pub fn new(base: &str, admin_token: Option<String>, client_id: Option<String>) -> Self {
+        Source {
+            base: base.trim_end_matches('/').to_string(),
+            admin_token,
+            client_id,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.get(format!("{}{}", self.base, path));
+        match &self.admin_token {
+            Some(token) => request.header("x-admin-token", token),
+            None => request,
+        }
+    }
+
+    fn tail(&self, lines: usize, follow: bool) -> reqwest::RequestBuilder {
+        let mut query = vec![("lines", lines.to_string()), ("follow", follow.to_string())];
+        if let Some(client_id) = &self.client_id {
+            query.push(("client_id", client_id.clone()));
+        }
+        self.get("/api/logs/tail").query(&query)
+    }
+
+    /// The counters and the security events, in the order they happened.
+    pub async fn poll(&self) -> Vec<Update> {
+        let mut updates = Vec::new();
+        match self.fetch("/api/stats?limit=1").await {
+            Ok(stats) => updates.push(Update::Counters(counters(&stats))),
+            Err(e) => updates.push(Update::Status(format!("stats: {}", e))),
+        }
+        match self.fetch("/api/dashboard/events?filter=security").await {
+            Ok(body) => {
+                let mut events: Vec<_> = body["events"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|e| {
+                        let client = e.get("client_id").and_then(|v| v.as_str());
+                        self.client_id.is_none() || client == self.client_id.as_deref()
+                    })
+                    .collect();
+                events.sort_by(|a, b| {
+                    let time =
+                        |e: &serde_json::Value| e["timestamp"].as_str().unwrap_or("").to_string();
+                    time(a).cmp(&time(b))
+                });
+                let skip = events.len().saturating_sub(MAX_KEPT);
+                updates.extend(events.into_iter().skip(skip).map(Update::Event));
+            }
+            Err(e) => updates.push(Update::Status(format!("events: {}", e))),
+        }
+        updates
+    }
+
+    /// The last `lines` batches, without following.
+    pub async fn recent_logs(&self, lines: usize) -> Result<Vec<Update>, String> {
+        let resp = self
+            .tail(lines, false)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(resp.status().to_string());
+        }
+        let body = resp.bytes().await.map_err(|e| e.to_string())?;
+        Ok(body.split(|b| *b == b'\n').filter_map(tail_line).collect())
+    }
+
+    async fn fetch(&self, path: &str) -> Result<serde_json::Value, String> {
+        let resp = self.get(path).send().await.map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(resp.status().to_string());
+        }
+        resp.json().await.map_err(|e| e.to_string())
+    }
+
+    /// Follows the tail until the viewer goes away, reconnecting (without
+    /// history) when the connection drops.
+    async fn follow(self, mut lines: usize, updates: mpsc::UnboundedSender<Update>) {
+        loop {
+            match self.tail(lines, true).send().await {
+                Ok(mut resp) if resp.status().is_success() => {
+                    let _ = updates.send(Update::Following(true));
+                    let mut pending = Vec::new();
+                    while let Ok(Some(chunk)) = resp.chunk().await {
+                        pending.extend_from_slice(&chunk);
+                        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                            let line: Vec<u8> = pending.drain(..=end).collect();
+                            if let Some(update) = tail_line(&line) {
+                                if updates.send(update).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    let _ = updates.send(Update::Status("tail connection closed".to_string()));
+                }
+                Ok(resp) => {
+                    let _ = updates.send(Update::Status(format!("tail: {}", resp.status())));
+                }
+                Err(e) => {
+                    let _ = updates.send(Update::Status(format!("tail: {}", e)));
+                }
+            }
+            if updates.send(Update::Following(false)).is_err() {
+                return;
+            }
+            lines = 0;
+            tokio::time::sleep(RECONNECT_AFTER).await;
+        }
+    }
+}
+
+/// The terminal in raw mode on the alternate screen; restored when dropped.
+struct Terminal(ratatui::DefaultTerminal);
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        // Also installs a panic hook that restores the terminal.
+        ratatui::try_init()
+            .map(Terminal)
+            .map_err(|_| io::Error::other("tui needs a terminal"))
+    }
+
+    fn draw(&mut self, viewer: &Viewer) -> io::Result<()> {
+        self.0
+            .draw(|frame| viewer.render_ref(frame.area(), frame.buffer_mut()))?;
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+fn key(event: Event) -> Option<Key> {
+    let Event::Key(key) = event else { return None };
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    match key.code {
+        // Ctrl-C and Ctrl-D quit, as raw input doesn't raise signals.
+        KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            Some(Key::Char('q'))
+        }
+        KeyCode::Char(c) => Some(Key::Char(c)),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Tab => Some(Key::Tab),
+        _ => None,
+    }
+}
+
+pub async fn run(args: &TuiArgs) -> io::Result<()> {
+    let source = Source::new(
+        &args.server,
+        args.admin_token.clone(),
+        args.client_id.clone(),
+    );
+    let refresh = crate::handlers::common::parse_duration(&args.refresh)
+        .and_then(|d| d.to_std().ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| io::Error::other("--refresh must look like 2s or 1m"))?;
+    let mut terminal = Terminal::enter()?;
+    let (sender, mut updates) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(source.clone().follow(args.lines, sender.clone()));
+    let poller = sender.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            for update in source.poll().await {
+                if poller.send(update).is_err() {
+                    return;
+                }
+            }
+            tokio::time::sleep(refresh).await;
+        }
+    });
+    drop(sender);
+
+    let mut viewer = Viewer::new(&args.server);
+    let mut keys = EventStream::new();
+    let mut ticks = tokio::time::interval(REDRAW_EVERY);
+    let mut dirty = true;
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let Some(update) = update else { break };
+                viewer.apply(update);
+                dirty = true;
+            }
+            event = keys.next() => {
+                match event {
+                    Some(Ok(Event::Resize(..))) => terminal.draw(&viewer)?,
+                    Some(Ok(event)) => {
+                        if let Some(key) = key(event) {
+                            viewer.apply(Update::Key(key));
+                            if viewer.quit {
+                                break;
+                            }
+                            terminal.draw(&viewer)?;
+                            dirty = false;
+                        }
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => break,
+                }
+            }
+            _ = ticks.tick() => {
+                if dirty && !viewer.paused {
+                    terminal.draw(&viewer)?;
+                    dirty = false;
+                }
+            }
+        }
+    }
+    drop(terminal);
+    Ok(())
+}
@@ -1,13 +1,25 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LogEntry {
     #[serde(default)]
     pub client_id: Option<String>,
+    /// The browser profile on that machine, when the extension sends one.
+    #[serde(default, alias = "profileId")]
+    pub profile_id: Option<String>,
     pub session_id: String,
     pub timestamp: String,
     pub user_agent: String,
     pub logs: Vec<NetworkLog>,
+    /// The user logged in on the client, when the extension reports one; used
+    /// to look the user up with `--ldap-config`, not stored.
+    #[serde(default, skip_serializing)]
+    pub username: Option<String>,
+    /// The client's clock offset when the batch arrived (client minus server
+    /// time), set by the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_ms: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,6 +34,46 @@ pub struct NetworkLog {
     pub blocked: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reputation_score: Option<u8>,
+    /// Upload size in bytes (request body), when the extension can observe it.
+    #[serde(
+        default,
+        alias = "requestSize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub request_size: Option<u64>,
+    #[serde(
+        default,
+        alias = "responseSize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub response_size: Option<u64>,
+    /// The domain's category (`social`, `video`, ...), set by the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+/// Longest `client_id` ingest accepts.
+pub const MAX_CLIENT_ID_LEN: usize = 128;
+
+/// Client ids end up in logs, file names and dashboard markup, so ingest
+/// only accepts ASCII letters, digits and `-_.:@`, up to
+/// [`MAX_CLIENT_ID_LEN`] characters.
+pub fn check_client_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.len() > MAX_CLIENT_ID_LEN {
+        return Err(format!(
+            "client_id must be 1 to {} characters",
+            MAX_CLIENT_ID_LEN
+        ));
+    }
+    if !id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-_.:@".contains(&b))
+    {
+        return Err("client_id may only contain ASCII letters, digits and -_.:@".to_string());
+    }
+    Ok(())
 }
 
 pub fn default_string() -> String {
@@ -38,15 +90,250 @@ pub struct Blocklist {
     pub url_patterns: Vec<String>,
     #[serde(rename = "youtubeChannels")]
     pub youtube_channels: Vec<String>,
+    /// Extra entries for one browser profile, by profile_id, on top of the
+    /// lists above.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, BlocklistEntries>,
+}
+
+/// URL patterns and channels added on top of the shared blocklist.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BlocklistEntries {
+    #[serde(rename = "urlPatterns", default)]
+    pub url_patterns: Vec<String>,
+    #[serde(rename = "youtubeChannels", default)]
+    pub youtube_channels: Vec<String>,
+}
+
+impl Blocklist {
+    /// The lists one profile gets: the shared ones with its own entries
+    /// added, and no `profiles` map.
+    pub fn for_profile(mut self, profile_id: &str) -> Blocklist {
+        let extra = self.profiles.remove(profile_id);
+        self.profiles.clear();
+        if let Some(extra) = extra {
+            self.add(extra);
+        }
+        self
+    }
+
+    /// Appends the entries not already listed.
+    pub fn add(&mut self, extra: BlocklistEntries) {
+        for p in extra.url_patterns {
+            if !self.url_patterns.contains(&p) {
+                self.url_patterns.push(p);
+            }
+        }
+        for c in extra.youtube_channels {
+            if !self.youtube_channels.contains(&c) {
+                self.youtube_channels.push(c);
+            }
+        }
+    }
+
+    /// The URL pattern that blocks a domain and its subdomains, in the
+    /// `.*tracker\..*` style of the default list.
+    pub fn domain_pattern(domain: &str) -> String {
+        format!(".*{}.*", domain.replace('.', "\\."))
+    }
+
+    /// The domain a [`Blocklist::domain_pattern`] blocks; `None` for any
+    /// other regex.
+    pub fn pattern_domain(pattern: &str) -> Option<String> {
+        let escaped = pattern.strip_prefix(".*")?.strip_suffix(".*")?;
+        let domain = escaped.replace("\\.", ".");
+        let labels: Vec<&str> = domain.split('.').collect();
+        let valid = labels.len() > 1
+            && labels.iter().all(|l| {
+                !l.is_empty() && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            });
+        // Every dot must have been escaped, or it is a wildcard.
+        (valid && escaped.matches("\\.").count() == labels.len() - 1)
+            .then(|| domain.to_ascii_lowercase())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtensionEvent {
     #[serde(default)]
     pub client_id: Option<String>,
+    #[serde(default, alias = "profileId")]
+    pub profile_id: Option<String>,
     pub session_id: String,
     pub timestamp: String,
     pub user_agent: String,
     pub event_type: String,
     pub data: serde_json::Value,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservedDomain {
+    pub domain: String,
+    pub first_seen: chrono::DateTime<chrono::Utc>,
+    pub last_seen: chrono::DateTime<chrono::Utc>,
+    pub request_count: u64,
+    pub client_count: u64,
+}
+
+/// Per-domain aggregate over stored network logs (`/api/stats`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainStats {
+    pub domain: String,
+    pub requests: u64,
+    pub blocked: u64,
+    pub clients: u64,
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Per-category aggregate over stored network logs (`/api/stats`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryStats {
+    pub category: String,
+    pub requests: u64,
+    pub blocked: u64,
+    pub domains: u64,
+    pub clients: u64,
+}
+
+/// A client whose new ingest is refused; its stored data is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedClient {
+    pub client_id: String,
+    pub archived_at: chrono::DateTime<chrono::Utc>,
+    pub archived_by: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// A dashboard and API account kept by the server itself, for deployments
+/// without an identity provider. Secrets are only stored hashed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalUser {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: crate::roles::Role,
+    /// Inactive users can't log in and their API token is refused.
+    pub active: bool,
+    /// PBKDF2-HMAC-SHA256 as `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+    pub password_hash: Option<String>,
+    /// SHA-256 of the user's API token, hex.
+    pub token_hash: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Analyst label and/or note attached to a session or a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: i64,
+    /// `session` or `client`.
+    pub scope: String,
+    /// The session_id or client_id annotated.
+    pub target: String,
+    pub label: Option<String>,
+    pub note: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Where an event stands in triage. Events nobody has looked at are `new`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TriageStatus {
+    #[default]
+    New,
+    Acknowledged,
+    FalsePositive,
+    Escalated,
+}
+
+impl TriageStatus {
+    pub const ALL: [TriageStatus; 4] = [
+        TriageStatus::New,
+        TriageStatus::Acknowledged,
+        TriageStatus::FalsePositive,
+        TriageStatus::Escalated,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriageStatus::New => "new",
+            TriageStatus::Acknowledged => "acknowledged",
+            TriageStatus::FalsePositive => "false-positive",
+            TriageStatus::Escalated => "escalated",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<TriageStatus> {
+        TriageStatus::ALL.into_iter().find(|t| t.as_str() == s)
+    }
+}
+
+/// The latest triage decision on one event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventTriage {
+    pub packet_id: String,
+    pub status: TriageStatus,
+    pub note: Option<String>,
+    /// Analyst investigating the event, if anyone has taken it.
+    pub assignee: Option<String>,
+    pub updated_by: Option<String>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The issue-tracker ticket opened for an escalated event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaseTicket {
+    pub packet_id: String,
+    /// `jira` or `github`.
+    pub provider: String,
+    /// `SEC-123` in Jira, the issue number on GitHub.
+    pub key: String,
+    pub url: String,
+    pub closed: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub closed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the tracker was last asked whether the ticket is closed.
+    pub synced_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// An exception the extension reported about itself, one per distinct
+/// stack trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtensionError {
+    /// Hash of the stack (or of the message when there is no stack).
+    pub hash: String,
+    /// The latest report's message.
+    pub message: String,
+    pub stack: Option<String>,
+    /// Which part of the extension threw, e.g. `background` or `content`.
+    pub source: Option<String>,
+    /// The extension version of the latest report.
+    pub extension_version: Option<String>,
+    pub last_client_id: Option<String>,
+    /// Reports with this hash so far.
+    pub count: i64,
+    pub first_seen: chrono::DateTime<chrono::Utc>,
+    pub last_seen: chrono::DateTime<chrono::Utc>,
+}
+
+/// A comment on an event. Replies name the comment they answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventComment {
+    pub id: i64,
+    pub packet_id: String,
+    pub parent_id: Option<i64>,
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Per-client aggregate over stored network logs (`/api/stats`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientStats {
+    pub client_id: String,
+    pub requests: u64,
+    pub blocked: u64,
+    pub domains: u64,
+    pub sessions: u64,
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+}
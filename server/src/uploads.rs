@@ -0,0 +1,282 @@
+//! Files attached to security events (`POST /api/security/upload`), e.g. the
+//! document behind a `chatgpt_file_upload` event.
+//!
+//! Uploads are streamed to `--upload-dir` and stored once per content, as
+//! `<dir>/<sha256>`. `uploads.ndjson` in the same directory records every
+//! upload and the packet_id it belongs to, `verdicts.ndjson` the results of
+//! scanning them (see [`crate::scanning`]); both are re-read at startup.
+//! Replicas that should see each other's uploads need a shared directory.
+
+use crate::scanning::Verdict;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use tokio::io::AsyncWriteExt;
+
+const INDEX_FILE: &str = "uploads.ndjson";
+const VERDICTS_FILE: &str = "verdicts.ndjson";
+/// Files still being received; left-overs from a crash are removed at startup.
+const PARTIAL_PREFIX: &str = ".partial-";
+
+pub const MAX_FILENAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: String,
+    pub packet_id: String,
+    pub client_id: Option<String>,
+    /// As sent by the client; never used to build a path.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub uploaded_by: String,
+    /// Empty until the file has been scanned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verdicts: Vec<Verdict>,
+}
+
+/// One line of `verdicts.ndjson`; a later line for the same upload wins.
+#[derive(Serialize, Deserialize)]
+struct VerdictRecord {
+    upload_id: String,
+    verdicts: Vec<Verdict>,
+}
+
+/// What the client said about a file, recorded next to its hash.
+pub struct UploadInfo {
+    pub packet_id: String,
+    pub client_id: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub uploaded_by: String,
+}
+
+pub struct UploadStore {
+    dir: Option<PathBuf>,
+    max_bytes: u64,
+    uploads: RwLock<Vec<Upload>>,
+    /// Serializes appends to the index files.
+    index: Mutex<()>,
+    next_id: AtomicU64,
+    next_partial: AtomicU64,
+}
+
+impl UploadStore {
+    pub fn disabled() -> Self {
+        UploadStore {
+            dir: None,
+            max_bytes: 0,
+            uploads: RwLock::new(Vec::new()),
+            index: Mutex::new(()),
+            next_id: AtomicU64::new(0),
+            next_partial: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates `dir` if needed, loads its index and removes partial files.
+    pub fn new(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        for entry in std::fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(PARTIAL_PREFIX)
+            {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        let mut uploads: Vec<Upload> = read_ndjson(&dir.join(INDEX_FILE))?;
+        for record in read_ndjson::<VerdictRecord>(&dir.join(VERDICTS_FILE))? {
+            if let Some(u) = uploads.iter_mut().find(|u| u.id == record.upload_id) {
+                u.verdicts = record.verdicts;
+            }
+        }
+        let next_id = uploads.len() as u64;
+        Ok(UploadStore {
+            dir: Some(dir.to_path_buf()),
+            max_bytes,
+            uploads: RwLock::new(uploads),
+            index: Mutex::new(()),
+            next_id: AtomicU64::new(next_id),
+            next_partial: AtomicU64::new(0),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.uploads.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Uploads attached to one security event, oldest first.
+    pub fn for_packet(&self, packet_id: &str) -> Vec<Upload> {
+        self.uploads
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|u| u.packet_id == packet_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Path of a stored file, by content hash.
+    pub fn path_of(&self, sha256: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(dir.join(sha256))
+    }
+
+    /// Starts receiving a file into a partial file in the upload directory.
+    pub async fn begin(&self) -> io::Result<PartialUpload> {
+        let dir = self
+            .dir
+            .as_ref()
+            .ok_or_else(|| io::Error::other("uploads are disabled"))?;
+        let path = dir.join(format!(
+            "{}{}-{}",
+            PARTIAL_PREFIX,
+            std::process::id(),
+            self.next_partial.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(PartialUpload {
+            path,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+            committed: false,
+        })
+    }
+
+    /// Moves a complete file to its content address and records the upload.
+    /// A file that is already stored is not written twice.
+    pub async fn commit(&self, mut partial: PartialUpload, info: UploadInfo) -> io::Result<Upload> {
+        partial.file.flush().await?;
+        partial.file.sync_all().await?;
+        let sha256 = hex::encode(partial.hasher.finalize_reset());
+        let target = self
+            .path_of(&sha256)
+            .ok_or_else(|| io::Error::other("uploads are disabled"))?;
+        tokio::fs::rename(&partial.path, &target).await?;
+        partial.committed = true;
+
+        let seq = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let upload = Upload {
+            id: format!("upl-{}-{}", Utc::now().format("%Y%m%d-%H%M%S"), seq),
+            packet_id: info.packet_id,
+            client_id: info.client_id,
+            filename: info.filename,
+            content_type: info.content_type,
+            size: partial.size,
+            sha256,
+            uploaded_at: Utc::now(),
+            uploaded_by: info.uploaded_by,
+            verdicts: Vec::new(),
+        };
+        self.append(INDEX_FILE, &upload)?;
+        self.uploads.write().unwrap().push(upload.clone());
+        Ok(upload)
+    }
+
+    /// Replaces the scan results of an upload.
+    pub fn set_verdicts(&self, upload_id: &str, verdicts: Vec<Verdict>) -> io::Result<()> {
+        let record = VerdictRecord {
+            upload_id: upload_id.to_string(),
+            verdicts,
+        };
+        self.append(VERDICTS_FILE, &record)?;
+        if let Some(u) = self
+            .uploads
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|u| u.id == upload_id)
+        {
+            u.verdicts = record.verdicts;
+        }
+        Ok(())
+    }
+
+    fn append(&self, file: &str, record: &impl Serialize) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let _guard = self.index.lock().unwrap();
+        let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(file))?
+            .write_all(&line)
+    }
+}
+
+/// Records in an NDJSON file, skipping lines that don't parse. A missing file
+/// has none.
+fn read_ndjson<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| match serde_json::from_str(l) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                log::warn!("⚠️ Skipping unreadable line in {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// A file being received. Dropped without [`UploadStore::commit`], its
+/// partial file is deleted.
+pub struct PartialUpload {
+    path: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    size: u64,
+    committed: bool,
+}
+
+impl PartialUpload {
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        self.file.write_all(chunk).await
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! URL normalization at ingest (`--normalize-urls`), so the same page isn't
+//! counted, deduplicated or searched as several URLs. A normalized URL has
+//! a lowercase scheme and host (IDN hosts in punycode), no default port,
+//! `.` / `..` path segments resolved, no fragment, and its query parameters
+//! sorted by name without the tracking ones (`--strip-query-param`).
+//! URLs that don't parse or aren't http(s)/ws(s) are stored as sent.
+
+use crate::metrics;
+use crate::types::LogEntry;
+use actix_web::{web, HttpRequest};
+
+/// `--strip-query-param` when none is given.
+pub const DEFAULT_STRIPPED: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "mc_cid", "mc_eid",
+    "yclid", "igshid", "_hsenc", "_hsmi",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct UrlNormalizer {
+    enabled: bool,
+    /// Lowercase; a trailing `*` matches any suffix.
+    stripped: Vec<String>,
+}
+
+impl UrlNormalizer {
+    pub fn new(stripped: &[String]) -> Self {
+        UrlNormalizer {
+            enabled: true,
+            stripped: stripped
+                .iter()
+                .map(|p| p.trim().to_ascii_lowercase())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The normalizer registered as app data; leaves URLs alone when there
+    /// is none.
+    pub fn for_request(req: &HttpRequest) -> web::Data<Self> {
+        req.app_data::<web::Data<Self>>()
+            .cloned()
+            .unwrap_or_else(|| web::Data::new(Self::default()))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn stripped(&self) -> &[String] {
+        &self.stripped
+    }
+
+    fn strips(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.stripped
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == *pattern,
+            })
+    }
+
+    /// The canonical form of `url`; `url` itself when it can't be parsed
+    /// or normalization is off.
+    pub fn normalize(&self, url: &str) -> String {
+        if !self.enabled {
+            return url.to_string();
+        }
+        let mut parsed = match reqwest::Url::parse(url.trim()) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https" | "ws" | "wss") => parsed,
+            _ => return url.to_string(),
+        };
+        parsed.set_fragment(None);
+        if let Some(query) = parsed.query() {
+            let mut params: Vec<&str> = query
+                .split('&')
+                .filter(|p| !p.is_empty())
+                .filter(|p| !self.strips(p.split('=').next().unwrap_or(p)))
+                .collect();
+            // Stable: repeated names keep their order.
+            params.sort_by_key(|p| p.split('=').next().unwrap_or(p));
+            let query = params.join("&");
+            parsed.set_query((!query.is_empty()).then_some(query.as_str()));
+        }
+        parsed.into()
+    }
+
+    /// Normalizes every URL of the batch; returns how many changed.
+    pub fn apply(&self, entry: &mut LogEntry) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        let mut changed = 0;
+        for log in &mut entry.logs {
+            let normalized = self.normalize(&log.url);
+            if normalized != log.url {
+                log.url = normalized;
+                changed += 1;
+            }
+        }
+        metrics::URLS_NORMALIZED.add(changed as u64);
+        changed
+    }
+}
@@ -0,0 +1,299 @@
+//! Local user accounts, for deployments without an identity provider: each
+//! has a role and logs in to the dashboard with a password, and scripts can
+//! use a personal API token instead of the shared `--admin-token`. They are
+//! managed through `/api/admin/users` and kept in the database in
+//! production, in `--users-file` otherwise (in memory without it).
+//!
+//! Accounts only matter while admin authentication is on (`--admin-token`
+//! or `--oidc-config`): without it the admin endpoints are open anyway.
+//! Passwords are stored as PBKDF2-HMAC-SHA256 and tokens as SHA-256; a
+//! token is shown once, when it is issued.
+
+use crate::auth::random_token;
+use crate::roles::Role;
+use crate::types::LocalUser;
+use chrono::{DateTime, Utc};
+use ring::pbkdf2;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+#[cfg(feature = "production")]
+use std::sync::Arc;
+
+/// How often production replicas re-read the accounts, so a change made
+/// through another replica takes effect here too.
+#[cfg(feature = "production")]
+const RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+pub const MIN_PASSWORD_LENGTH: usize = 10;
+/// Prefix of personal API tokens, so they can be told from the admin token
+/// and provider tokens (and found by secret scanners).
+const TOKEN_PREFIX: &str = "cgu_";
+
+/// An account as the API shows it: without its secrets.
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: Role,
+    pub active: bool,
+    pub has_password: bool,
+    pub has_token: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<&LocalUser> for UserSummary {
+    fn from(user: &LocalUser) -> Self {
+        UserSummary {
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            role: user.role,
+            active: user.active,
+            has_password: user.password_hash.is_some(),
+            has_token: user.token_hash.is_some(),
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+pub struct UserStore {
+    users: RwLock<BTreeMap<String, LocalUser>>,
+    path: Option<PathBuf>,
+    #[cfg(feature = "production")]
+    storage: Option<Arc<crate::production::ProductionState>>,
+}
+
+impl Default for UserStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserStore {
+    /// In-memory only: accounts don't survive a restart.
+    pub fn new() -> Self {
+        UserStore {
+            users: RwLock::new(BTreeMap::new()),
+            path: None,
+            #[cfg(feature = "production")]
+            storage: None,
+        }
+    }
+
+    /// Keeps the accounts in `path`, starting from the ones saved there.
+    pub fn with_file(mut self, path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let users: Vec<LocalUser> = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+            *self.users.get_mut().unwrap() =
+                users.into_iter().map(|u| (u.username.clone(), u)).collect();
+        }
+        self.path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Keeps the accounts in `local_users` and the in-memory copy in sync
+    /// with it.
+    #[cfg(feature = "production")]
+    pub fn spawn(state: Arc<crate::production::ProductionState>) -> Arc<UserStore> {
+        let store = Arc::new(UserStore {
+            storage: Some(state.clone()),
+            ..UserStore::new()
+        });
+        let s = store.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match state.get_local_users().await {
+                    Ok(list) => {
+                        *s.users.write().unwrap() =
+                            list.into_iter().map(|u| (u.username.clone(), u)).collect();
+                    }
+                    Err(e) => log::error!("❌ Loading local users failed: {}", e),
+                }
+                tokio::time::sleep(RESYNC_INTERVAL).await;
+            }
+        });
+        store
+    }
+
+    /// Whether changes outlive a restart.
+    pub fn is_persisted(&self) -> bool {
+        #[cfg(feature = "production")]
+        if self.storage.is_some() {
+            return true;
+        }
+        self.path.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.read().unwrap().is_empty()
+    }
+
+    /// Every account, by username.
+    pub fn list(&self) -> Vec<LocalUser> {
+        self.users.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, username: &str) -> Option<LocalUser> {
+        self.users.read().unwrap().get(username).cloned()
+    }
+
+    fn persist(&self, users: &BTreeMap<String, LocalUser>) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let list: Vec<&LocalUser> = users.values().collect();
+            let mut text = serde_json::to_vec_pretty(&list).map_err(std::io::Error::other)?;
+            text.push(b'\n');
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// Creates or replaces the account `user.username`.
+    pub async fn put(&self, user: LocalUser) -> Result<(), String> {
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            state
+                .put_local_user(&user)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let mut users = self.users.write().unwrap();
+        let mut changed = users.clone();
+        changed.insert(user.username.clone(), user);
+        self.persist(&changed).map_err(|e| e.to_string())?;
+        *users = changed;
+        Ok(())
+    }
+
+    /// Returns whether the account existed.
+    pub async fn delete(&self, username: &str) -> Result<bool, String> {
+        #[cfg_attr(not(feature = "production"), allow(unused_mut))]
+        let mut found = false;
+        #[cfg(feature = "production")]
+        if let Some(state) = &self.storage {
+            found = state
+                .delete_local_user(username)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let mut users = self.users.write().unwrap();
+        let mut changed = users.clone();
+        found |= changed.remove(username).is_some();
+        self.persist(&changed).map_err(|e| e.to_string())?;
+        *users = changed;
+        Ok(found)
+    }
+
+    /// The active account `username` if `password` is its password. This
+    /// runs PBKDF2, so call it off the async workers (`web::block`). An
+    /// account that doesn't exist, is inactive or has no password is checked
+    /// against a dummy hash, so it is refused as slowly as a wrong password
+    /// and the time taken doesn't tell which accounts exist.
+    pub fn by_password(&self, username: &str, password: &str) -> Option<LocalUser> {
+        let user = self
+            .get(username)
+            .filter(|u| u.active && u.password_hash.is_some());
+        let hash = user
+            .as_ref()
+            .and_then(|u| u.password_hash.as_deref())
+            .unwrap_or_else(|| dummy_hash());
+        let matches = verify_password(hash, password);
+        user.filter(|_| matches)
+    }
+
+    /// The active account whose API token is `token`.
+    pub fn by_token(&self, token: &str) -> Option<LocalUser> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let hash = token_hash(token);
+        self.users
+            .read()
+            .unwrap()
+            .values()
+            .find(|u| u.active && u.token_hash.as_deref() == Some(hash.as_str()))
+            .cloned()
+    }
+}
+
+/// Lowercase letters, digits, `.`, `_` and `-`, up to 64 characters.
+pub fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 64
+        && username
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"._-".contains(&b))
+}
+
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("no system random number generator");
+    let mut hash = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    format!(
+        "pbkdf2-sha256${}${}${}",
+        PBKDF2_ITERATIONS,
+        hex::encode(salt),
+        hex::encode(hash)
+    )
+}
+
+/// A hash no password is checked against for real, at the same cost.
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password(&random_token()))
+}
+
+fn verify_password(stored: &str, password: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        hex::decode(salt),
+        hex::decode(hash),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+/// A new personal API token and the hash to store for it.
+pub fn new_api_token() -> (String, String) {
+    let token = format!("{}{}", TOKEN_PREFIX, random_token());
+    let hash = token_hash(&token);
+    (token, hash)
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,143 @@
+//! Versioned API routes. Every `/api/...` endpoint is also served under
+//! `/api/v1/...`, where JSON answers come in one envelope:
+//!
+//! - success: `{ "data": <the answer>, "meta": { "api_version": "v1" } }`;
+//! - failure: `{ "error": { "code", "message", ...details }, "meta": {...} }`,
+//!   with the details an [`ApiError`](crate::error::ApiError) carries (e.g.
+//!   `packet_id`) next to the code.
+//!
+//! Answers that aren't JSON (CSV and NDJSON downloads, event streams, `204`)
+//! are left as they are. Versioned requests are routed to the same handlers
+//! as the unversioned ones, so every middleware and handler sees the
+//! unversioned path.
+//!
+//! The unversioned routes keep answering as before, with `Deprecation: true`
+//! and a `Link` to their `/api/v1/` successor, and are counted in
+//! `canigoin_legacy_api_requests_total` so it shows when clients have moved.
+
+use crate::metrics;
+use actix_web::body::{self, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{StatusCode, Uri};
+use actix_web::middleware::Next;
+use serde_json::{json, Map, Value};
+
+pub const VERSION: &str = "v1";
+const LEGACY_PREFIX: &str = "/api/";
+const V1_PREFIX: &str = "/api/v1/";
+
+/// `/api/v1/logs?x=1` as `/api/logs?x=1`.
+fn unversioned(uri: &Uri) -> Option<Uri> {
+    let rest = uri.path().strip_prefix(V1_PREFIX)?;
+    let path = match uri.query() {
+        Some(query) => format!("{}{}?{}", LEGACY_PREFIX, rest, query),
+        None => format!("{}{}", LEGACY_PREFIX, rest),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+fn is_json(headers: &header::HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+fn meta() -> Value {
+    json!({ "api_version": VERSION })
+}
+
+/// A `snake_case` code for an error answer that has none, e.g. `not_found`.
+fn status_code_name(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("error")
+        .to_ascii_lowercase()
+        .replace([' ', '-'], "_")
+}
+
+/// The envelope for an answer with `status` and `body` (JSON or not).
+pub fn envelope(status: StatusCode, body: &[u8], json: bool) -> Value {
+    let value: Option<Value> = json.then(|| serde_json::from_slice(body).ok()).flatten();
+    if !status.is_client_error() && !status.is_server_error() {
+        return json!({ "data": value.unwrap_or(Value::Null), "meta": meta() });
+    }
+    let mut error = Map::new();
+    match value {
+        Some(Value::Object(mut fields)) => {
+            fields.remove("success");
+            let message = fields.remove("error").unwrap_or_default();
+            error.insert(
+                "code".into(),
+                fields
+                    .remove("code")
+                    .unwrap_or_else(|| status_code_name(status).into()),
+            );
+            error.insert("message".into(), message);
+            error.extend(fields);
+        }
+        _ => {
+            let text = String::from_utf8_lossy(body).trim().to_string();
+            let message = if text.is_empty() {
+                status.canonical_reason().unwrap_or_default().to_string()
+            } else {
+                text
+            };
+            error.insert("code".into(), status_code_name(status).into());
+            error.insert("message".into(), message.into());
+        }
+    }
+    json!({ "error": error, "meta": meta() })
+}
+
+/// Routes `/api/v1/` requests to the unversioned handlers and wraps their
+/// answers; marks unversioned `/api/` answers as deprecated.
+pub async fn route(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, String>>, actix_web::Error> {
+    if let Some(uri) = unversioned(req.uri()) {
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+        let res = next.call(req).await?;
+        let status = res.status();
+        let json = is_json(res.headers());
+        let failed = status.is_client_error() || status.is_server_error();
+        let answered = json && status.is_success() && status != StatusCode::NO_CONTENT;
+        if !failed && !answered {
+            return Ok(res.map_into_left_body());
+        }
+        let (req, res) = res.into_parts();
+        let (mut head, body) = res.into_parts();
+        let bytes = body::to_bytes(body)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("response body"))?;
+        let wrapped = envelope(status, &bytes, json).to_string();
+        head.headers_mut().remove(header::CONTENT_LENGTH);
+        head.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        return Ok(ServiceResponse::new(req, head.set_body(wrapped)).map_into_right_body());
+    }
+
+    let successor = req
+        .path()
+        .strip_prefix(LEGACY_PREFIX)
+        .filter(|rest| *rest != "v1")
+        .map(|rest| format!("<{}{}>; rel=\"successor-version\"", V1_PREFIX, rest));
+    let mut res = next.call(req).await?;
+    if let Some(link) = successor.and_then(|l| HeaderValue::from_str(&l).ok()) {
+        metrics::LEGACY_API_REQUESTS.inc();
+        let headers = res.headers_mut();
+        headers.insert(
+            header::HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+        headers.append(header::LINK, link);
+    }
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,382 @@
+//! Append-only copies of security events (`--worm-syslog`, `--worm-s3`):
+//! every security event is sent on to external write-once storage the
+//! moment it is received, so history can't be rewritten from this server
+//! without the copy showing it.
+//!
+//! - syslog (RFC 5424 over UDP, or TCP with octet counting), facility
+//!   `log audit`, the event as JSON in the message;
+//! - an S3 bucket with Object Lock: one object per event, written with
+//!   `If-None-Match: *` and a `COMPLIANCE` retention of
+//!   `--worm-retention-days`, so it can neither be overwritten nor deleted
+//!   before then. Credentials come from `AWS_ACCESS_KEY_ID`,
+//!   `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`.
+//!
+//! Each destination has its own queue and is retried until it accepts the
+//! event, keeping the order; one that is down doesn't hold up the other.
+
+use crate::metrics;
+use crate::types::ExtensionEvent;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Events waiting per destination before new ones are dropped.
+pub const MAX_QUEUED: i64 = 100_000;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(300);
+/// `log audit` (13) at severity `notice` (5).
+const SYSLOG_PRI: u8 = 13 * 8 + 5;
+
+static SINKS: OnceLock<Vec<(String, UnboundedSender<ExtensionEvent>)>> = OnceLock::new();
+
+pub enum Sink {
+    Syslog(Syslog),
+    S3(S3),
+}
+
+impl Sink {
+    pub fn name(&self) -> String {
+        match self {
+            Sink::Syslog(s) => format!("syslog {}", s.addr),
+            Sink::S3(s) => format!("s3 {}{}", s.origin, s.path),
+        }
+    }
+
+    async fn send(&mut self, event: &ExtensionEvent) -> Result<(), String> {
+        match self {
+            Sink::Syslog(s) => s.send(event).await.map_err(|e| e.to_string()),
+            Sink::S3(s) => s.put(event).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+pub struct Syslog {
+    transport: Transport,
+    addr: String,
+    hostname: String,
+    tcp: Option<tokio::net::TcpStream>,
+}
+
+impl Syslog {
+    /// `udp://host:port` or `tcp://host:port`; a bare `host:port` is UDP.
+    pub fn parse(target: &str) -> Result<Syslog, String> {
+        let (transport, addr) = match target.split_once("://") {
+            Some(("udp", addr)) => (Transport::Udp, addr),
+            Some(("tcp", addr)) => (Transport::Tcp, addr),
+            Some((scheme, _)) => return Err(format!("unknown syslog transport {}", scheme)),
+            None => (Transport::Udp, target),
+        };
+        if addr
+            .rsplit_once(':')
+            .is_none_or(|(_, port)| port.parse::<u16>().is_err())
+        {
+            return Err(format!("{} should be host:port", addr));
+        }
+        Ok(Syslog {
+            transport,
+            addr: addr.to_string(),
+            hostname: crate::instance::id()
+                .map(str::to_string)
+                .unwrap_or_else(crate::instance::default_id),
+            tcp: None,
+        })
+    }
+
+    /// An RFC 5424 message carrying `event`.
+    fn message(&self, event: &ExtensionEvent) -> String {
+        let msg_id: String = event
+            .event_type
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .take(32)
+            .collect();
+        format!(
+            "<{}>1 {} {} canigoin - {} - {}",
+            SYSLOG_PRI,
+            Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            self.hostname,
+            if msg_id.is_empty() { "-" } else { &msg_id },
+            serde_json::to_string(event).unwrap_or_default()
+        )
+    }
+
+    pub(crate) async fn send(&mut self, event: &ExtensionEvent) -> std::io::Result<()> {
+        let message = self.message(event);
+        match self.transport {
+            Transport::Udp => {
+                let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+                socket.send_to(message.as_bytes(), &self.addr).await?;
+            }
+            Transport::Tcp => {
+                if self.tcp.is_none() {
+                    self.tcp = Some(tokio::net::TcpStream::connect(&self.addr).await?);
+                }
+                let framed = format!("{} {}", message.len(), message);
+                let stream = self.tcp.as_mut().expect("connected above");
+                if let Err(e) = stream.write_all(framed.as_bytes()).await {
+                    self.tcp = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct S3 {
+    http: reqwest::Client,
+    /// `scheme://host[:port]`.
+    origin: String,
+    /// `host[:port]`, as signed.
+    host: String,
+    /// `/bucket[/prefix]`, without a trailing slash.
+    path: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    retention_days: u32,
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, as S3
+/// signing expects of a path.
+fn uri_encode(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl S3 {
+    /// `url` is the bucket, path-style, with an optional key prefix:
+    /// `https://s3.eu-west-1.amazonaws.com/evidence/canigoin`.
+    pub fn new(url: &str, region: &str, retention_days: u32) -> Result<S3, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+        let host = parsed.host_str().ok_or("the URL has no host")?;
+        let host = match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let path = parsed.path().trim_end_matches('/').to_string();
+        if path.is_empty() {
+            return Err("the URL should include the bucket: https://host/bucket[/prefix]".into());
+        }
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let (Some(access_key), Some(secret_key)) =
+            (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY"))
+        else {
+            return Err("set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY".into());
+        };
+        Ok(S3 {
+            http: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?,
+            origin: format!("{}://{}", parsed.scheme(), host),
+            host,
+            path,
+            region: region.to_string(),
+            access_key,
+            secret_key,
+            session_token: env("AWS_SESSION_TOKEN"),
+            retention_days,
+        })
+    }
+
+    /// `<prefix>/<yyyy>/<mm>/<dd>/<packet_id>.json`, by receive date.
+    fn key(&self, event: &ExtensionEvent, now: DateTime<Utc>) -> String {
+        let packet_id = event
+            .data
+            .get("packet_id")
+            .and_then(|p| p.as_str())
+            .unwrap_or("unknown");
+        format!(
+            "{}/{}/{}.json",
+            self.path,
+            now.format("%Y/%m/%d"),
+            packet_id
+        )
+    }
+
+    /// Signed (AWS Signature Version 4) headers for a PUT of `body` to `path`.
+    fn signed_headers(&self, path: &str, body: &[u8], now: DateTime<Utc>) -> Vec<(String, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let digest = Sha256::digest(body);
+        let mut headers = vec![
+            ("host".to_string(), self.host.clone()),
+            ("if-none-match".to_string(), "*".to_string()),
+            ("x-amz-checksum-sha256".to_string(), STANDARD.encode(digest)),
+            ("x-amz-content-sha256".to_string(), hex::encode(digest)),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if self.retention_days > 0 {
+            let until = now + chrono::Duration::days(self.retention_days.into());
+            headers.push(("x-amz-object-lock-mode".into(), "COMPLIANCE".into()));
+            headers.push((
+                "x-amz-object-lock-retain-until-date".into(),
+                until.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            ));
+        }
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".into(), token.clone()));
+        }
+        headers.sort();
+
+        let signed: Vec<&str> = headers.iter().map(|(k, _)| k.as_str()).collect();
+        let signed = signed.join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect();
+        let canonical_request = format!(
+            "PUT\n{}\n\n{}\n{}\n{}",
+            uri_encode(path),
+            canonical_headers,
+            signed,
+            hex::encode(digest)
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+        headers.push((
+            "authorization".into(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed, signature
+            ),
+        ));
+        headers
+    }
+
+    async fn put(&self, event: &ExtensionEvent) -> Result<(), String> {
+        let now = Utc::now();
+        let path = self.key(event, now);
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        self.put_object(&path, body, "application/json", now).await
+    }
+
+    /// `/bucket[/prefix]`, to build object paths under.
+    pub(crate) fn prefix(&self) -> &str {
+        &self.path
+    }
+
+    /// Writes `body` to `path` (under [`prefix`](Self::prefix)) unless an
+    /// object is already there.
+    pub(crate) async fn put_object(
+        &self,
+        path: &str,
+        body: Vec<u8>,
+        content_type: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let url = format!("{}{}", self.origin, uri_encode(path));
+        let mut request = self.http.put(url).header("content-type", content_type);
+        for (name, value) in self.signed_headers(path, &body, now) {
+            if name != "host" {
+                request = request.header(name, value);
+            }
+        }
+        let resp = request.body(body).send().await.map_err(|e| e.to_string())?;
+        match resp.status() {
+            // Already there (e.g. sent again after a timeout): nothing to do.
+            s if s.is_success() || s == reqwest::StatusCode::PRECONDITION_FAILED => Ok(()),
+            s => Err(format!("{}: {}", s, resp.text().await.unwrap_or_default())),
+        }
+    }
+}
+
+async fn deliver(name: String, mut sink: Sink, mut rx: UnboundedReceiver<ExtensionEvent>) {
+    while let Some(event) = rx.recv().await {
+        let mut wait = FIRST_RETRY;
+        loop {
+            match sink.send(&event).await {
+                Ok(()) => {
+                    metrics::WORM_APPENDED.inc();
+                    break;
+                }
+                Err(e) => {
+                    metrics::WORM_FAILURES.inc();
+                    log::error!(
+                        "❌ Append to {} failed, retrying in {}s: {}",
+                        name,
+                        wait.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(wait).await;
+                    wait = (wait * 2).min(MAX_RETRY);
+                }
+            }
+        }
+        metrics::WORM_QUEUE_DEPTH.add(-1);
+    }
+}
+
+/// Sends every security event to `sinks` from now on.
+pub fn install(sinks: Vec<Sink>) {
+    let mut senders = Vec::new();
+    for sink in sinks {
+        let name = sink.name();
+        let (tx, rx) = mpsc::unbounded_channel();
+        actix_web::rt::spawn(deliver(name.clone(), sink, rx));
+        senders.push((name, tx));
+    }
+    if !senders.is_empty() {
+        let _ = SINKS.set(senders);
+    }
+}
+
+/// Queues `event` for every installed destination.
+pub fn append(event: &ExtensionEvent) {
+    let Some(sinks) = SINKS.get() else {
+        return;
+    };
+    for (name, tx) in sinks {
+        if metrics::WORM_QUEUE_DEPTH.get() >= MAX_QUEUED * sinks.len() as i64 {
+            metrics::WORM_DROPPED.inc();
+            log::error!(
+                "❌ {} is too far behind; event {} not appended",
+                name,
+                event.event_type
+            );
+            continue;
+        }
+        if tx.send(event.clone()).is_ok() {
+            metrics::WORM_QUEUE_DEPTH.add(1);
+        }
+    }
+}
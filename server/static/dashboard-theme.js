@@ -0,0 +1 @@
+(function(){var t=localStorage.getItem('cigi-dashboard-theme');var p=window.matchMedia&&window.matchMedia('(prefers-color-scheme: light)').matches;var theme=t||(p?'light':'dark');if(theme==='light')document.documentElement.setAttribute('data-theme','light');})();
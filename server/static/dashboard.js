@@ -0,0 +1,691 @@
+const API = '';
+const PAGE_SIZE = 50;
+const DEBOUNCE_MS = 300;
+let eventsData = [], logsData = [], clientsData = [];
+let eventsSort = { key: 'timestamp', dir: -1 };
+let logsSort = { key: 'timestamp', dir: -1 };
+let eventsPage = 1, logsPage = 1;
+let lastUpdated = 0;
+let refreshInterval = null;
+let inspectPacketId = null;
+let inspectRawJson = null;
+let allEventsCache = [];
+let tabCounts = { events: 0, security: 0, javascript: 0, logs: 0, clients: 0 };
+const EVENT_TABS = ['events', 'security', 'javascript'];
+let currentEventFilter = 'all';
+
+// Safe in element content and in quoted attribute values alike.
+const HTML_ESCAPES = { '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;' };
+function escapeHtml(s) {
+  if (s == null) return '';
+  return String(s).replace(/[&<>"']/g, c => HTML_ESCAPES[c]);
+}
+
+// Punycode first, as requested; the unicode form an IDN host displays as next to it.
+function domainHtml(ascii, unicode) {
+  if (!unicode) return escapeHtml(ascii || '');
+  return escapeHtml(ascii) + ' <span class="idn" title="Displayed by browsers as">(' + escapeHtml(unicode) + ')</span>';
+}
+
+function relativeTime(iso) {
+  if (!iso) return '';
+  const d = new Date(iso);
+  const now = Date.now();
+  const s = Math.round((now - d) / 1000);
+  if (s < 60) return s + 's ago';
+  if (s < 3600) return Math.floor(s / 60) + 'm ago';
+  if (s < 86400) return Math.floor(s / 3600) + 'h ago';
+  if (s < 604800) return Math.floor(s / 86400) + 'd ago';
+  return d.toLocaleString();
+}
+
+function debounce(fn, ms) {
+  let t;
+  return function(...a) { clearTimeout(t); t = setTimeout(() => fn.apply(this, a), ms); };
+}
+
+function setConnectionStatus(ok) {
+  const dot = document.getElementById('live-dot');
+  const err = document.getElementById('connection-error');
+  dot.classList.toggle('offline', !ok);
+  dot.title = ok ? 'Connected' : 'Disconnected';
+  err.classList.toggle('visible', !ok);
+}
+
+async function checkHealth() {
+  try {
+    const r = await fetch(API + '/health', { signal: AbortSignal.timeout(3000) });
+    setConnectionStatus(r.ok);
+    return r.ok;
+  } catch (e) {
+    setConnectionStatus(false);
+    return false;
+  }
+}
+
+function retryConnection() {
+  checkHealth().then(ok => { if (ok) { loadEvents(); loadLogs(); loadClients(); } });
+}
+
+function updateStatusBar() {
+  const el = document.getElementById('status-bar');
+  if (lastUpdated) {
+    const ago = Math.round((Date.now() - lastUpdated) / 1000);
+    el.textContent = 'Updated ' + (ago < 60 ? ago + 's ago' : Math.floor(ago / 60) + 'm ago');
+  } else {
+    el.textContent = '—';
+  }
+}
+
+(function initTheme() {
+  const key = 'cigi-dashboard-theme';
+  const toggle = document.getElementById('theme-toggle');
+  function getSavedTheme() { return localStorage.getItem(key); }
+  function prefersLight() { return window.matchMedia && window.matchMedia('(prefers-color-scheme: light)').matches; }
+  function applyTheme(theme) {
+    const isLight = theme === 'light';
+    document.documentElement.setAttribute('data-theme', isLight ? 'light' : '');
+    toggle.textContent = isLight ? 'Light' : 'Dark';
+    localStorage.setItem(key, theme);
+    const hljsLink = document.getElementById('hljs-theme');
+    if (hljsLink) hljsLink.href = isLight
+      ? 'https://cdn.jsdelivr.net/gh/highlightjs/cdn-release@11/build/styles/atom-one-light.min.css'
+      : 'https://cdn.jsdelivr.net/gh/highlightjs/cdn-release@11/build/styles/atom-one-dark.min.css';
+  }
+  const saved = getSavedTheme();
+  applyTheme(saved || (prefersLight() ? 'light' : 'dark'));
+  toggle.addEventListener('click', () => applyTheme(document.documentElement.getAttribute('data-theme') === 'light' ? 'dark' : 'light'));
+})();
+
+document.getElementById('auto-refresh').addEventListener('change', function() {
+  if (this.checked) {
+    refreshInterval = setInterval(refreshCurrent, 5000);
+  } else {
+    clearInterval(refreshInterval);
+    refreshInterval = null;
+  }
+});
+
+function refreshCurrent() {
+  const active = document.querySelector('.panel.active');
+  if (!active) return;
+  if (active.id === 'panel-events') loadEvents();
+  else if (active.id === 'panel-logs') loadLogs();
+  else if (active.id === 'panel-clients') loadClients();
+}
+
+document.addEventListener('keydown', function(e) {
+  if (e.key === 'Escape') closeInspect();
+});
+
+function closeInspect() {
+  document.getElementById('inspect').style.display = 'none';
+}
+
+function tab(t) {
+  document.getElementById('inspect').style.display = 'none';
+  document.querySelectorAll('.panel').forEach(p => p.classList.remove('active'));
+  document.querySelectorAll('.tabs button').forEach(b => b.classList.remove('active'));
+  const panelId = EVENT_TABS.includes(t) ? 'panel-events' : 'panel-' + t;
+  document.getElementById(panelId).classList.add('active');
+  document.querySelector('.tabs button[data-tab="' + t + '"]').classList.add('active');
+  if (EVENT_TABS.includes(t)) {
+    currentEventFilter = t === 'events' ? 'all' : t;
+    const titles = { all: 'All events', security: 'Security events', javascript: 'JavaScript events' };
+    document.getElementById('events-title').textContent = titles[currentEventFilter];
+    loadEvents();
+  } else if (t === 'logs') loadLogs();
+  else if (t === 'clients') loadClients();
+  else if (t === 'errors') loadErrors();
+  else if (t === 'blocklist') loadBlocklist();
+}
+document.querySelectorAll('.tabs button').forEach(b => b.addEventListener('click', () => tab(b.dataset.tab)));
+
+function sortData(data, key, dir) {
+  const arr = [...data];
+  arr.sort((a, b) => {
+    let va = a[key], vb = b[key];
+    if (va == null) va = '';
+    if (vb == null) vb = '';
+    const cmp = String(va).localeCompare(String(vb), undefined, { numeric: true });
+    return dir > 0 ? cmp : -cmp;
+  });
+  return arr;
+}
+
+function filterEvents(data) {
+  let out = data;
+  const q = (document.getElementById('events-search') || {}).value || '';
+  if (q) {
+    const s = q.toLowerCase().trim();
+    out = out.filter(e => {
+      const parts = (e.timestamp || '') + ' ' + (e.client_id || '') + ' ' + (e.client_name || '') + ' ' + (e.page_domain || '') + ' ' + (e.page_domain_unicode || '') + ' ' + (e.script_domain || '') + ' ' + (e.script_domain_unicode || '') + ' ' + (e.event_type || '') + ' ' + (e.packet_id || '');
+      return parts.toLowerCase().includes(s);
+    });
+  }
+  const from = document.getElementById('events-date-from')?.value;
+  const to = document.getElementById('events-date-to')?.value;
+  if (from || to) {
+    out = out.filter(e => {
+      const t = e.timestamp ? new Date(e.timestamp).getTime() : 0;
+      if (from && t < new Date(from).getTime()) return false;
+      if (to && t > new Date(to + 'T23:59:59').getTime()) return false;
+      return true;
+    });
+  }
+  const typeFilter = document.getElementById('events-type-filter')?.value;
+  if (typeFilter) {
+    out = out.filter(e => (e.event_type || '') === typeFilter);
+  }
+  return out;
+}
+
+function filterLogs(data) {
+  let out = data;
+  const q = (document.getElementById('logs-search') || {}).value || '';
+  if (q) {
+    const s = q.toLowerCase().trim();
+    out = out.filter(r => {
+      const parts = (r.timestamp || '') + ' ' + (r.session_id || '') + ' ' + (r.client_id || '') + ' ' + (r.url || '') + ' ' + (r.type || '');
+      return parts.toLowerCase().includes(s);
+    });
+  }
+  const from = document.getElementById('logs-date-from')?.value;
+  const to = document.getElementById('logs-date-to')?.value;
+  if (from || to) {
+    out = out.filter(r => {
+      const t = r.timestamp ? new Date(r.timestamp).getTime() : 0;
+      if (from && t < new Date(from).getTime()) return false;
+      if (to && t > new Date(to + 'T23:59:59').getTime()) return false;
+      return true;
+    });
+  }
+  const typeFilter = document.getElementById('logs-type-filter')?.value;
+  if (typeFilter) out = out.filter(r => (r.type || '') === typeFilter);
+  return out;
+}
+
+function badgeClass(cat) {
+  if (cat === 'security') return 'badge-security';
+  if (cat === 'javascript') return 'badge-javascript';
+  if (cat === 'server') return 'badge-server';
+  return 'badge-general';
+}
+
+function renderEvents(data) {
+  const filtered = filterEvents(data);
+  const sorted = sortData(filtered, eventsSort.key, eventsSort.dir);
+  const total = sorted.length;
+  const start = (eventsPage - 1) * PAGE_SIZE;
+  const pageData = sorted.slice(start, start + PAGE_SIZE);
+
+  const thead = document.getElementById('events-thead');
+  const cols = ['packet_id','event_type','category','page','script','client_id','risk','timestamp'];
+  thead.innerHTML = cols.map(c => {
+    const label = c === 'packet_id' ? 'packet_id' : c === 'risk' ? 'risk' : c;
+    const active = eventsSort.key === c;
+    return '<th class="' + (active ? 'sort-' + (eventsSort.dir > 0 ? 'asc' : 'desc') : '') + '" data-sort="' + c + '">' + escapeHtml(label) + '</th>';
+  }).join('');
+  thead.querySelectorAll('th').forEach(th => {
+    th.addEventListener('click', () => {
+      eventsSort = { key: th.dataset.sort, dir: eventsSort.key === th.dataset.sort ? -eventsSort.dir : -1 };
+      eventsPage = 1;
+      renderEvents(eventsData);
+    });
+  });
+
+  const tbody = document.getElementById('events-tbody');
+  document.getElementById('events-search-hint').textContent = total + ' row' + (total !== 1 ? 's' : '');
+  if (!pageData.length) {
+    tbody.innerHTML = '<tr><td colspan="8">No events</td></tr>';
+  } else {
+    tbody.innerHTML = pageData.map(e => {
+      const cat = e.category || 'general';
+      const risk = e.risk_score != null ? e.risk_score : '';
+      const notesHtml = (e.annotations || []).map(a =>
+        '<span class="badge badge-note" title="' + escapeHtml(a.scope + ' ' + a.target + (a.note ? ': ' + a.note : '')) + '">' +
+        escapeHtml(a.label || 'note') + '</span>').join('');
+      const riskHtml = risk !== '' ? '<span class="risk-bar" title="' + risk + '"><span style="width:' + Math.min(risk, 100) + '%"></span></span>' : '';
+      return '<tr class="packet-row" data-id="' + escapeHtml(e.packet_id) + '" title="' + escapeHtml(e.timestamp || '') + '">' +
+        '<td class="mono">' + escapeHtml(e.packet_id) + '</td>' +
+        '<td>' + escapeHtml(e.event_type) + notesHtml + '</td>' +
+        '<td><span class="badge ' + badgeClass(cat) + '">' + escapeHtml(cat) + '</span></td>' +
+        '<td class="mono">' + domainHtml(e.page_domain, e.page_domain_unicode) + '</td>' +
+        '<td class="mono">' + domainHtml(e.script_domain, e.script_domain_unicode) + '</td>' +
+        '<td class="mono" title="' + escapeHtml(e.client_id || '') + '">' + escapeHtml(e.client_name || e.client_id || '') + '</td>' +
+        '<td>' + (risk !== '' ? escapeHtml(risk) : '') + riskHtml + '</td>' +
+        '<td>' + escapeHtml(relativeTime(e.timestamp)) + '</td></tr>';
+    }).join('');
+    tbody.querySelectorAll('.packet-row').forEach(row => {
+      row.addEventListener('click', () => inspectPacket(row.dataset.id));
+    });
+  }
+
+  const pag = document.getElementById('events-pagination');
+  const pages = Math.max(1, Math.ceil(total / PAGE_SIZE));
+  pag.innerHTML = '<button type="button" class="btn" ' + (eventsPage <= 1 ? 'disabled' : '') + ' data-events-page="1">First</button>' +
+    '<button type="button" class="btn" ' + (eventsPage <= 1 ? 'disabled' : '') + ' data-events-page="' + (eventsPage - 1) + '">Prev</button>' +
+    '<span>' + eventsPage + ' / ' + pages + '</span>' +
+    '<button type="button" class="btn" ' + (eventsPage >= pages ? 'disabled' : '') + ' data-events-page="' + (eventsPage + 1) + '">Next</button>' +
+    '<button type="button" class="btn" ' + (eventsPage >= pages ? 'disabled' : '') + ' data-events-page="' + pages + '">Last</button>';
+}
+
+function renderLogs(data) {
+  const filtered = filterLogs(data);
+  const sorted = sortData(filtered, logsSort.key, logsSort.dir);
+  const total = sorted.length;
+  const start = (logsPage - 1) * PAGE_SIZE;
+  const pageData = sorted.slice(start, start + PAGE_SIZE);
+
+  const thead = document.getElementById('logs-thead');
+  const cols = ['timestamp','type','url','method','blocked','client_id','session_id'];
+  thead.innerHTML = cols.map(c => '<th class="' + (logsSort.key === c ? 'sort-' + (logsSort.dir > 0 ? 'asc' : 'desc') : '') + '" data-sort="' + c + '">' + escapeHtml(c) + '</th>').join('');
+  thead.querySelectorAll('th').forEach(th => {
+    th.addEventListener('click', () => {
+      logsSort = { key: th.dataset.sort, dir: logsSort.key === th.dataset.sort ? -logsSort.dir : -1 };
+      logsPage = 1;
+      renderLogs(logsData);
+    });
+  });
+
+  const tbody = document.getElementById('logs-tbody');
+  document.getElementById('logs-search-hint').textContent = total + ' row' + (total !== 1 ? 's' : '');
+  if (!pageData.length) {
+    tbody.innerHTML = '<tr><td colspan="7">No logs</td></tr>';
+  } else {
+    tbody.innerHTML = pageData.map(r => {
+      const cls = r.blocked ? ' class="blocked"' : '';
+      return '<tr' + cls + ' title="' + escapeHtml(r.timestamp || '') + '">' +
+        '<td>' + escapeHtml(relativeTime(r.timestamp)) + '</td>' +
+        '<td>' + escapeHtml(r.type) + '</td>' +
+        '<td class="mono" title="' + escapeHtml(r.url) + '">' + escapeHtml(r.url.length > 60 ? r.url.slice(0, 57) + '...' : r.url) + '</td>' +
+        '<td>' + escapeHtml(r.method) + '</td>' +
+        '<td>' + (r.blocked ? '<span class="error">✓</span>' : '') + '</td>' +
+        '<td class="mono">' + escapeHtml(r.client_id || '') + '</td>' +
+        '<td class="mono">' + escapeHtml(r.session_id || '') + '</td></tr>';
+    }).join('');
+  }
+
+  const pag = document.getElementById('logs-pagination');
+  const pages = Math.max(1, Math.ceil(total / PAGE_SIZE));
+  pag.innerHTML = '<button type="button" class="btn" ' + (logsPage <= 1 ? 'disabled' : '') + ' data-logs-page="1">First</button>' +
+    '<button type="button" class="btn" ' + (logsPage <= 1 ? 'disabled' : '') + ' data-logs-page="' + (logsPage - 1) + '">Prev</button>' +
+    '<span>' + logsPage + ' / ' + pages + '</span>' +
+    '<button type="button" class="btn" ' + (logsPage >= pages ? 'disabled' : '') + ' data-logs-page="' + (logsPage + 1) + '">Next</button>' +
+    '<button type="button" class="btn" ' + (logsPage >= pages ? 'disabled' : '') + ' data-logs-page="' + pages + '">Last</button>';
+}
+
+const applyEventsSearch = debounce(function() { eventsPage = 1; renderEvents(eventsData); }, DEBOUNCE_MS);
+const applyLogsSearch = debounce(function() { logsPage = 1; renderLogs(logsData); }, DEBOUNCE_MS);
+
+document.getElementById('events-search')?.addEventListener('input', applyEventsSearch);
+document.getElementById('events-date-from')?.addEventListener('change', applyEventsSearch);
+document.getElementById('events-date-to')?.addEventListener('change', applyEventsSearch);
+document.getElementById('events-type-filter')?.addEventListener('change', applyEventsSearch);
+document.getElementById('logs-search')?.addEventListener('input', applyLogsSearch);
+document.getElementById('logs-date-from')?.addEventListener('change', applyLogsSearch);
+document.getElementById('logs-date-to')?.addEventListener('change', applyLogsSearch);
+document.getElementById('logs-type-filter')?.addEventListener('change', applyLogsSearch);
+
+function showSkeleton(rows, cols, id) {
+  const tbody = document.getElementById(id);
+  tbody.innerHTML = Array(rows).fill('<tr>' + Array(cols).fill('<td><div class="skeleton"></div></td>').join('') + '</tr>').join('');
+}
+
+async function loadEvents() {
+  const tbody = document.getElementById('events-tbody');
+  showSkeleton(5, 8, 'events-tbody');
+  try {
+    const r = await adminFetch(API + '/api/dashboard/events?filter=all');
+    if (!r.ok) throw new Error(r.statusText);
+    const j = await r.json();
+    allEventsCache = j.events || [];
+    eventsData = currentEventFilter === 'all' ? allEventsCache : allEventsCache.filter(e => (e.category || '') === currentEventFilter);
+    lastUpdated = Date.now();
+    updateStatusBar();
+    setConnectionStatus(true);
+    updateTabCounts();
+    populateEventTypeFilter(allEventsCache);
+    renderEvents(eventsData);
+  } catch (e) {
+    tbody.innerHTML = '<tr><td colspan="8" class="error">Failed: ' + escapeHtml(e.message) + ' <button class="btn" data-action="loadEvents">Retry</button></td></tr>';
+    setConnectionStatus(false);
+  }
+}
+
+async function loadLogs() {
+  const tbody = document.getElementById('logs-tbody');
+  showSkeleton(5, 7, 'logs-tbody');
+  try {
+    const r = await adminFetch(API + '/api/logs');
+    if (!r.ok) throw new Error(r.statusText);
+    const entries = await r.json();
+    const flat = [];
+    for (const entry of (entries || [])) {
+      for (const log of (entry.logs || [])) {
+        flat.push({
+          timestamp: entry.timestamp,
+          session_id: entry.session_id,
+          client_id: entry.client_id || '',
+          type: log.type || log.request_type || 'other',
+          url: log.url || '',
+          method: log.method || 'GET',
+          blocked: log.blocked || false
+        });
+      }
+    }
+    flat.reverse();
+    logsData = flat;
+    lastUpdated = Date.now();
+    updateStatusBar();
+    setConnectionStatus(true);
+    tabCounts.logs = flat.length;
+    document.getElementById('badge-logs').textContent = flat.length;
+    const types = [...new Set(flat.map(r => r.type))].sort();
+    const sel = document.getElementById('logs-type-filter');
+    sel.innerHTML = '<option value="">All types</option>' + types.map(t => '<option value="' + escapeHtml(t) + '">' + escapeHtml(t) + '</option>').join('');
+    logsPage = 1;
+    renderLogs(logsData);
+  } catch (e) {
+    tbody.innerHTML = '<tr><td colspan="7" class="error">Failed: ' + escapeHtml(e.message) + ' <button class="btn" data-action="loadLogs">Retry</button></td></tr>';
+    setConnectionStatus(false);
+  }
+}
+
+function populateEventTypeFilter(data) {
+  const types = [...new Set(data.map(e => e.event_type).filter(Boolean))].sort();
+  const sel = document.getElementById('events-type-filter');
+  sel.innerHTML = '<option value="">All types</option>' + types.map(t => '<option value="' + escapeHtml(t) + '">' + escapeHtml(t) + '</option>').join('');
+}
+
+function updateTabCounts() {
+  const data = allEventsCache || eventsData;
+  tabCounts.events = data.length;
+  tabCounts.security = data.filter(e => (e.category || '') === 'security').length;
+  tabCounts.javascript = data.filter(e => (e.category || '') === 'javascript').length;
+  document.getElementById('badge-events').textContent = tabCounts.events;
+  document.getElementById('badge-security').textContent = tabCounts.security;
+  document.getElementById('badge-javascript').textContent = tabCounts.javascript;
+}
+
+async function loadClients() {
+  const el = document.getElementById('clients-list');
+  el.innerHTML = '<li><div class="skeleton" style="width:150px"></div></li>';
+  try {
+    const r = await adminFetch(API + '/api/dashboard/clients');
+    if (!r.ok) throw new Error(r.statusText);
+    const j = await r.json();
+    clientsData = j.clients || [];
+    tabCounts.clients = clientsData.length;
+    document.getElementById('badge-clients').textContent = clientsData.length;
+    setConnectionStatus(true);
+    const q = ((document.getElementById('clients-search') || {}).value || '').toLowerCase();
+    const filtered = q ? clientsData.filter(c => c.toLowerCase().includes(q)) : clientsData;
+    el.innerHTML = filtered.length ? filtered.map(c => '<li data-copy="' + escapeHtml(c) + '" title="Click to copy">' + escapeHtml(c) + '</li>').join('') : '<li>No clients</li>';
+    el.querySelectorAll('li[data-copy]').forEach(li => li.addEventListener('click', () => navigator.clipboard.writeText(li.dataset.copy)));
+  } catch (e) {
+    el.innerHTML = '<li class="error">Failed: ' + escapeHtml(e.message) + ' <button class="btn" data-action="loadClients">Retry</button></li>';
+    setConnectionStatus(false);
+  }
+}
+
+let errorsData = [];
+
+async function loadErrors() {
+  const tbody = document.getElementById('errors-tbody');
+  showSkeleton(3, 6, 'errors-tbody');
+  try {
+    const r = await adminFetch(API + '/api/errors');
+    if (!r.ok) throw new Error(r.statusText);
+    const j = await r.json();
+    errorsData = j.errors || [];
+    document.getElementById('badge-errors').textContent = errorsData.length;
+    setConnectionStatus(true);
+    tbody.innerHTML = errorsData.length ? errorsData.map((e, i) =>
+      '<tr data-error="' + i + '" title="Click for the stack trace">' +
+      '<td>' + escapeHtml((e.message || '').split('\n')[0]) + '</td>' +
+      '<td>' + escapeHtml(e.source || '') + '</td>' +
+      '<td>' + escapeHtml(e.extension_version || '') + '</td>' +
+      '<td>' + e.count + '</td>' +
+      '<td title="' + escapeHtml(e.first_seen) + '">' + relativeTime(e.first_seen) + '</td>' +
+      '<td title="' + escapeHtml(e.last_seen) + '">' + relativeTime(e.last_seen) + '</td></tr>'
+    ).join('') : '<tr><td colspan="6">No errors reported</td></tr>';
+    tbody.querySelectorAll('tr[data-error]').forEach(tr => tr.addEventListener('click', () => {
+      const e = errorsData[Number(tr.dataset.error)];
+      const pre = document.getElementById('errors-stack');
+      pre.textContent = e.stack || e.message;
+      pre.style.display = 'block';
+    }));
+  } catch (e) {
+    tbody.innerHTML = '<tr><td colspan="6" class="error">Failed: ' + escapeHtml(e.message) + ' <button class="btn" data-action="loadErrors">Retry</button></td></tr>';
+    setConnectionStatus(false);
+  }
+}
+
+document.getElementById('clients-search')?.addEventListener('input', debounce(() => {
+  const q = (document.getElementById('clients-search')?.value || '').toLowerCase();
+  const filtered = q ? clientsData.filter(c => c.toLowerCase().includes(q)) : clientsData;
+  const el = document.getElementById('clients-list');
+  el.innerHTML = filtered.length ? filtered.map(c => '<li data-copy="' + escapeHtml(c) + '" title="Click to copy">' + escapeHtml(c) + '</li>').join('') : '<li>No matches</li>';
+  el.querySelectorAll('li[data-copy]').forEach(li => li.addEventListener('click', () => navigator.clipboard.writeText(li.dataset.copy)));
+}, DEBOUNCE_MS));
+
+async function inspectPacket(packetId) {
+  inspectPacketId = packetId;
+  try {
+    const r = await adminFetch(API + '/api/dashboard/events/' + encodeURIComponent(packetId));
+    if (!r.ok) throw new Error(r.statusText);
+    const event = await r.json();
+    inspectRawJson = event;
+    document.getElementById('inspect').style.display = 'flex';
+    renderInspect(event);
+    document.getElementById('inspect-search').value = '';
+  } catch (e) {
+    document.getElementById('inspect-body').textContent = 'Error: ' + e.message;
+    document.getElementById('inspect').style.display = 'flex';
+  }
+}
+
+function linkifyUrls(str) {
+  return str.replace(/(https?:\/\/[^\s"'<>]+)/g, '<a href="$1" target="_blank" rel="noopener">$1</a>');
+}
+
+function renderInspect(obj, searchTerm) {
+  let jsonStr = JSON.stringify(obj, null, 2);
+  if (typeof hljs !== 'undefined') {
+    let html = hljs.highlight(jsonStr, { language: 'json' }).value;
+    html = linkifyUrls(html);
+    if (searchTerm) {
+      const re = new RegExp('(' + searchTerm.replace(/[.*+?^${}()|[\]\\]/g, '\\$&') + ')', 'gi');
+      html = html.replace(re, '<mark>$1</mark>');
+    }
+    const pre = document.getElementById('inspect-body');
+    pre.innerHTML = html;
+    pre.classList.add('hljs');
+  } else {
+    document.getElementById('inspect-body').textContent = jsonStr;
+  }
+}
+
+document.getElementById('inspect-search')?.addEventListener('input', function() {
+  const q = this.value.trim();
+  if (inspectRawJson) renderInspect(inspectRawJson, q || null);
+});
+
+function copyPacketId() {
+  if (inspectPacketId) {
+    navigator.clipboard.writeText(inspectPacketId);
+  }
+}
+
+function copyInspectJson() {
+  if (inspectRawJson) {
+    navigator.clipboard.writeText(JSON.stringify(inspectRawJson, null, 2));
+  }
+}
+
+function exportEvents() {
+  const params = new URLSearchParams({ filter: currentEventFilter });
+  const from = document.getElementById('events-date-from')?.value;
+  const to = document.getElementById('events-date-to')?.value;
+  if (from) params.set('from', new Date(from + 'T00:00:00').toISOString());
+  if (to) params.set('to', new Date(new Date(to + 'T00:00:00').getTime() + 86400000).toISOString());
+  const a = document.createElement('a');
+  a.href = API + '/api/dashboard/events/export?' + params.toString();
+  a.click();
+}
+
+function exportLogs() {
+  const filtered = filterLogs(logsData);
+  const sorted = sortData(filtered, logsSort.key, logsSort.dir);
+  const cols = ['timestamp','type','url','method','blocked','client_id','session_id'];
+  const csv = [cols.join(',')].concat(sorted.map(r => cols.map(c => '"' + String(r[c] || '').replace(/"/g, '""') + '"').join(','))).join('\n');
+  const a = document.createElement('a');
+  a.href = 'data:text/csv;charset=utf-8,' + encodeURIComponent(csv);
+  a.download = 'logs-' + new Date().toISOString().slice(0, 10) + '.csv';
+  a.click();
+}
+
+(function initSplitter() {
+  const splitter = document.getElementById('splitter');
+  const tableWrap = document.querySelector('.events-table-wrap');
+  const inspect = document.getElementById('inspect');
+  if (!splitter || !tableWrap || !inspect) return;
+  let startX, startW;
+  splitter.addEventListener('mousedown', function(e) {
+    e.preventDefault();
+    startX = e.clientX;
+    startW = inspect.offsetWidth;
+    document.addEventListener('mousemove', drag);
+    document.addEventListener('mouseup', stopDrag);
+  });
+  function drag(e) {
+    const dx = e.clientX - startX;
+    const w = Math.max(300, Math.min(800, startW - dx));
+    inspect.style.width = w + 'px';
+  }
+  function stopDrag() {
+    document.removeEventListener('mousemove', drag);
+    document.removeEventListener('mouseup', stopDrag);
+  }
+})();
+
+// API calls from the page use a login session: the cookie is sent by
+// the browser, the CSRF token has to be added to every change. A 401
+// asks to sign in once, however many panes hit it at the same time.
+let csrfToken = null;
+let ssoLogin = null;
+let pendingLogin = null;
+let loginDeclined = false;
+
+async function loadSession() {
+  try {
+    const r = await fetch(API + '/api/dashboard/session', { credentials: 'same-origin' });
+    const j = await r.json();
+    if (r.ok) csrfToken = j.csrf_token;
+    else ssoLogin = j.sso_login || null;
+  } catch (e) {}
+}
+
+async function login() {
+  if (ssoLogin) {
+    location.href = API + ssoLogin + '?return_to=' + encodeURIComponent(location.pathname + location.search);
+    return false;
+  }
+  const who = prompt('Username (leave empty to use the admin token)');
+  if (who === null) return false;
+  const secret = prompt(who ? 'Password' : 'Admin token');
+  if (!secret) return false;
+  const r = await fetch(API + '/api/dashboard/login', {
+    method: 'POST',
+    credentials: 'same-origin',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(who ? { username: who, password: secret } : { token: secret })
+  });
+  if (!r.ok) return false;
+  csrfToken = (await r.json()).csrf_token;
+  return true;
+}
+
+function signIn() {
+  if (loginDeclined) return Promise.resolve(false);
+  if (!pendingLogin) {
+    pendingLogin = login().then(ok => {
+      pendingLogin = null;
+      loginDeclined = !ok;
+      return ok;
+    });
+  }
+  return pendingLogin;
+}
+
+async function adminFetch(url, opts) {
+  const send = () => {
+    const headers = Object.assign({}, opts && opts.headers);
+    if (csrfToken) headers['X-CSRF-Token'] = csrfToken;
+    return fetch(url, Object.assign({ credentials: 'same-origin' }, opts, { headers: headers }));
+  };
+  const r = await send();
+  if (r.status !== 401 || !(await signIn())) return r;
+  return send();
+}
+
+async function loadBlocklist() {
+  try {
+    const r = await adminFetch(API + '/api/blocklist');
+    if (!r.ok) throw new Error(r.statusText);
+    const j = await r.json();
+    document.getElementById('blocklist-urls').value = (j.url_patterns || j.urlPatterns || []).join('\n');
+    document.getElementById('blocklist-youtube').value = (j.youtube_channels || j.youtubeChannels || []).join('\n');
+  } catch (e) {
+    document.getElementById('blocklist-msg').textContent = 'Failed to load: ' + e.message;
+    document.getElementById('blocklist-msg').className = 'error';
+  }
+}
+
+async function saveBlocklist() {
+  if (!confirm('Save blocklist? This will update URL patterns and YouTube channels.')) return;
+  const msg = document.getElementById('blocklist-msg');
+  msg.textContent = '';
+  msg.className = '';
+  const urlPatterns = document.getElementById('blocklist-urls').value.split('\n').map(s => s.trim()).filter(Boolean);
+  const youtubeChannels = document.getElementById('blocklist-youtube').value.split('\n').map(s => s.trim()).filter(Boolean);
+  try {
+    const r = await adminFetch(API + '/api/blocklist', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ url_patterns: urlPatterns, urlPatterns: urlPatterns, youtube_channels: youtubeChannels, youtubeChannels: youtubeChannels })
+    });
+    const j = await r.json();
+    if (r.ok) msg.textContent = 'Saved.';
+    else msg.textContent = j.error || 'Failed';
+    if (!r.ok) msg.className = 'error';
+  } catch (e) {
+    msg.textContent = 'Failed: ' + e.message;
+    msg.className = 'error';
+  }
+}
+
+checkHealth();
+// Buttons name what they do in data attributes instead of inline handlers,
+// which the Content-Security-Policy doesn't allow.
+const ACTIONS = {
+  retryConnection, loadEvents, loadLogs, loadClients, loadErrors,
+  exportEvents, exportLogs, closeInspect, copyPacketId, copyInspectJson, saveBlocklist
+};
+document.addEventListener('click', e => {
+  const el = e.target.closest('[data-action], [data-events-page], [data-logs-page]');
+  if (!el || el.disabled) return;
+  if (el.dataset.action) ACTIONS[el.dataset.action]();
+  else if (el.dataset.eventsPage) { eventsPage = Number(el.dataset.eventsPage); renderEvents(eventsData); }
+  else { logsPage = Number(el.dataset.logsPage); renderLogs(logsData); }
+});
+
+// The session decides whether a 401 prompts or redirects to SSO.
+loadSession().then(() => {
+  loadEvents();
+  const linkedPacket = new URLSearchParams(location.search).get('packet');
+  if (linkedPacket) inspectPacket(linkedPacket);
+});
+refreshInterval = setInterval(refreshCurrent, 5000);
+setInterval(updateStatusBar, 1000);
@@ -0,0 +1,723 @@
+mod common;
+
+use actix_web::{web, App, HttpServer};
+use common::{expect_json, extension_event, log_batch, TestServer};
+use network_logger_server::alert_sinks::{Alert, AlertSink, SinkContext, SinkRegistry};
+use network_logger_server::alerts::{self, AlertRouter};
+use network_logger_server::simple::SimpleState;
+use network_logger_server::{homograph, rdap, rescan, server_events, worm, AppState, Backend};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[actix_web::test]
+async fn alert_rules_are_validated_and_can_be_tried_out() {
+    let server = TestServer::simple();
+    let config = serde_json::json!({
+        "channels": { "slack#sec-alerts": { "type": "slack", "url": "https://hooks.example.com/x" } },
+        "rules": ["when event_type == clickfix_detection and severity > 70 then notify slack#sec-alerts"]
+    });
+    let resp = server
+        .put("/api/admin/alert-rules")
+        .json(&config)
+        .send()
+        .await
+        .unwrap();
+    let resp = expect_json(resp, 200).await;
+    assert_eq!(resp["rules"], 1);
+    assert_eq!(resp["persisted"], false);
+
+    let broken = serde_json::json!({
+        "channels": config["channels"],
+        "rules": [config["rules"][0], "when severity > 50 then notify pager"]
+    });
+    let resp = server
+        .put("/api/admin/alert-rules")
+        .json(&broken)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["rule"], 1);
+    let current = server.get_json("/api/admin/alert-rules", 200).await;
+    assert_eq!(current["rules"], config["rules"]);
+
+    // No --telegram-bot-token, so a Telegram channel needs its own.
+    let telegram = serde_json::json!({
+        "channels": { "phone": { "type": "telegram", "chat_id": "12345" } },
+        "rules": ["when severity > 50 then notify phone"]
+    });
+    let resp = server
+        .put("/api/admin/alert-rules")
+        .json(&telegram)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 400).await;
+
+    let try_rules = |risk: u64| {
+        serde_json::json!({
+            "event_type": "clickfix_detection",
+            "data": { "detection": { "riskScore": risk } }
+        })
+    };
+    let high = server
+        .post_json("/api/admin/alert-rules/test", &try_rules(85), 200)
+        .await;
+    assert_eq!(high["channels"], serde_json::json!(["slack#sec-alerts"]));
+    let low = server
+        .post_json("/api/admin/alert-rules/test", &try_rules(40), 200)
+        .await;
+    assert_eq!(low["matched"], serde_json::json!([]));
+}
+
+/// The events detection rules raised, in full.
+async fn raised(server: &TestServer) -> Vec<serde_json::Value> {
+    let events = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let mut found = Vec::new();
+    for e in events["events"].as_array().unwrap() {
+        assert_eq!(e["event_type"], "detection_rule_match");
+        let path = format!("/api/dashboard/events/{}", e["packet_id"].as_str().unwrap());
+        found.push(server.get_json(&path, 200).await);
+    }
+    found
+}
+
+#[actix_web::test]
+async fn detection_rules_raise_events_and_keep_their_versions() {
+    let server = TestServer::simple();
+    let rule = serde_json::json!({
+        "id": "paste-upload",
+        "source": "alert when url matches /pastebin\\.com/ and method == \"POST\" severity 80"
+    });
+    let created = server.post_json("/api/rules", &rule, 201).await;
+    assert_eq!(created["version"], 1);
+    assert_eq!(created["target"], "logs");
+    server.post_json("/api/rules", &rule, 409).await;
+    let broken = serde_json::json!({ "id": "broken", "source": "alert when url > pastebin" });
+    let resp = server
+        .post("/api/rules")
+        .json(&broken)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["rule"], "broken");
+
+    let tried = serde_json::json!({
+        "source": rule["source"],
+        "logs": [
+            { "url": "https://pastebin.com/raw/1", "method": "GET" },
+            { "url": "https://pastebin.com/api", "method": "POST" }
+        ]
+    });
+    let tried = server.post_json("/api/rules/test", &tried, 200).await;
+    assert_eq!(tried["matches"], serde_json::json!([1]));
+
+    let mut batch = log_batch(
+        "client-1",
+        &["https://pastebin.com/api", "https://example.com/"],
+    );
+    batch["logs"][0]["method"] = "POST".into();
+    server.post_json("/api/logs", &batch, 200).await;
+    let found = &raised(&server).await[0];
+    assert_eq!(found["data"]["rule_id"], "paste-upload");
+    assert_eq!(found["data"]["matches"], 1);
+    assert_eq!(found["data"]["url"], "https://pastebin.com/api");
+    assert_eq!(found["data"]["detection"]["riskScore"], 80);
+
+    // Event rules look at incoming events; a disabled rule raises nothing.
+    let events_rule =
+        serde_json::json!({ "source": "alert on events when event_type == clipboard_read" });
+    let updated = server
+        .put("/api/rules/paste-upload")
+        .json(&events_rule)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(updated, 200).await["version"], 2);
+    let disabled = serde_json::json!({ "enabled": false });
+    let updated = server
+        .put("/api/rules/paste-upload")
+        .json(&disabled)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(updated, 200).await["version"], 3);
+    let read = extension_event("client-1", "clipboard_read", serde_json::json!({}));
+    server.post_json("/api/extensions", &read, 200).await;
+    let restored = serde_json::json!({ "version": 2 });
+    let restored = server
+        .post_json("/api/rules/paste-upload/restore", &restored, 200)
+        .await;
+    assert_eq!(restored["version"], 4);
+    assert_eq!(restored["enabled"], true);
+    assert_eq!(restored["versions"].as_array().unwrap().len(), 4);
+    let stored = server.post_json("/api/extensions", &read, 200).await;
+
+    let found = raised(&server).await;
+    let matches: Vec<&serde_json::Value> = found
+        .iter()
+        .filter(|e| e["data"]["target"] == "events")
+        .collect();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0]["data"]["rule_version"], 4);
+    assert_eq!(matches[0]["data"]["source_packet_id"], stored["packet_id"]);
+
+    let resp = server
+        .delete("/api/rules/paste-upload")
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    server.get_json("/api/rules/paste-upload", 404).await;
+}
+
+#[actix_web::test]
+async fn saved_rules_are_tested_on_samples_and_history() {
+    let server = TestServer::simple();
+    let rule = serde_json::json!({
+        "id": "paste",
+        "source": "alert when domain == pastebin.com",
+        "enabled": false
+    });
+    server.post_json("/api/rules", &rule, 201).await;
+    let batch = log_batch(
+        "client-1",
+        &["https://pastebin.com/raw/1", "https://example.com/"],
+    );
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let sample = serde_json::json!({
+        "logs": [
+            { "url": "https://example.com/", "method": "GET" },
+            { "url": "https://pastebin.com/", "method": "GET" }
+        ]
+    });
+    let tested = server
+        .post_json("/api/rules/paste/test", &sample, 200)
+        .await;
+    assert_eq!(tested["version"], 1);
+    assert_eq!(tested["matches"], serde_json::json!([1]));
+
+    // Disabled, the rule raised nothing, but it matches what was stored.
+    let range = serde_json::json!({
+        "from": "2026-01-01T00:00:00Z",
+        "to": "2026-01-02T00:00:00Z"
+    });
+    let tested = server.post_json("/api/rules/paste/test", &range, 200).await;
+    assert_eq!(tested["checked"], 2);
+    assert_eq!(tested["matched"], 1);
+    assert_eq!(tested["matches"][0]["url"], "https://pastebin.com/raw/1");
+    assert_eq!(tested["matches"][0]["client_id"], "client-1");
+    assert_eq!(tested["truncated"], false);
+    assert!(raised(&server).await.is_empty());
+
+    let before = serde_json::json!({
+        "from": "2025-01-01T00:00:00Z",
+        "to": "2026-01-01T00:00:00Z"
+    });
+    let tested = server
+        .post_json("/api/rules/paste/test", &before, 200)
+        .await;
+    assert_eq!(tested["checked"], 0);
+    let backwards = serde_json::json!({
+        "from": "2026-01-02T00:00:00Z",
+        "to": "2026-01-01T00:00:00Z"
+    });
+    server
+        .post_json("/api/rules/paste/test", &backwards, 400)
+        .await;
+    let unknown = serde_json::json!({ "version": 7 });
+    server
+        .post_json("/api/rules/paste/test", &unknown, 404)
+        .await;
+    server.post_json("/api/rules/nope/test", &sample, 404).await;
+}
+
+#[actix_web::test]
+async fn rescans_backfill_findings_for_rules_and_patterns_added_later() {
+    let app = AppState::simple();
+    rescan::register(
+        &app.scheduler,
+        app.backend.clone(),
+        app.rescans.clone(),
+        app.detection_rules.clone(),
+        app.blocklist_history.clone(),
+    );
+    let server = TestServer::start(app);
+    let batch = log_batch(
+        "client-1",
+        &["https://pastebin.com/raw/1", "https://example.com/"],
+    );
+    server.post_json("/api/logs", &batch, 200).await;
+    let rule = serde_json::json!({ "id": "paste", "source": "alert when domain == pastebin.com" });
+    server.post_json("/api/rules", &rule, 201).await;
+    assert!(raised(&server).await.is_empty());
+
+    let none = serde_json::json!({});
+    server.post_json("/api/admin/rescan", &none, 400).await;
+    let unknown = serde_json::json!({ "rules": ["nope"] });
+    server.post_json("/api/admin/rescan", &unknown, 404).await;
+    let request = serde_json::json!({
+        "rules": ["paste"],
+        "from": "2026-01-01T00:00:00Z",
+        "to": "2026-01-02T00:00:00Z"
+    });
+    let started = server.post_json("/api/admin/rescan", &request, 202).await;
+    assert_eq!(started["job"]["name"], "rescan");
+    assert_eq!(started["job"]["cancellable"], true);
+
+    let mut job = serde_json::Value::Null;
+    for _ in 0..100 {
+        job = server.get_json("/api/admin/jobs", 200).await["jobs"][0].clone();
+        if job["runs"] == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["last_outcome"], "ok", "{}", job);
+    assert_eq!(job["progress"]["done"], 1);
+    let found = raised(&server).await;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0]["data"]["rule_id"], "paste");
+    assert_eq!(found[0]["data"]["backfilled"], true);
+    assert_eq!(found[0]["data"]["source_timestamp"], "2026-01-01T12:00:00Z");
+    assert_eq!(found[0]["client_id"], "client-1");
+
+    let request = serde_json::json!({
+        "patterns": ["example\\.com"],
+        "from": "2026-01-01T00:00:00Z",
+        "to": "2026-01-02T00:00:00Z"
+    });
+    server.post_json("/api/admin/rescan", &request, 202).await;
+    let mut events = serde_json::Value::Null;
+    for _ in 0..100 {
+        events = server
+            .get_json("/api/dashboard/events?filter=security", 200)
+            .await;
+        if events["events"].as_array().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        events["events"][0]["event_type"], "blocklist_pattern_match",
+        "{}",
+        events
+    );
+    let path = format!(
+        "/api/dashboard/events/{}",
+        events["events"][0]["packet_id"].as_str().unwrap()
+    );
+    let finding = server.get_json(&path, 200).await;
+    assert_eq!(finding["data"]["pattern"], "example\\.com");
+    assert_eq!(finding["data"]["url"], "https://example.com/");
+    assert_eq!(finding["data"]["backfilled"], true);
+}
+
+/// A channel type registered by the test, keeping what it is sent.
+struct Recorder(Arc<Mutex<Vec<(String, String)>>>);
+
+impl AlertSink for Recorder {
+    fn send<'a>(
+        &'a self,
+        alert: &'a Alert,
+    ) -> futures_util::future::BoxFuture<'a, Result<(), String>> {
+        self.0
+            .lock()
+            .unwrap()
+            .push((alert.channel.clone(), alert.event.event_type.clone()));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[actix_web::test]
+async fn matching_security_events_are_delivered() {
+    let received: Arc<Mutex<Vec<serde_json::Value>>> = Arc::default();
+    let sink = received.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let hook = format!("http://{}/hook", listener.local_addr().unwrap());
+    let receiver = HttpServer::new(move || {
+        let sink = sink.clone();
+        App::new().route(
+            "/hook",
+            web::post().to(move |body: web::Json<serde_json::Value>| {
+                let sink = sink.clone();
+                async move {
+                    sink.lock().unwrap().push(body.into_inner());
+                    "ok"
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(receiver);
+
+    let recorded: Arc<Mutex<Vec<(String, String)>>> = Arc::default();
+    let record = recorded.clone();
+    let router = AlertRouter::new().register_sink("recorder", move |_, _| {
+        Ok(Arc::new(Recorder(record.clone())) as Arc<dyn AlertSink>)
+    });
+    assert!(router.sink_kinds().contains(&"email"));
+    let router = web::Data::new(router);
+    alerts::install(router.clone());
+    let mut app = AppState::simple();
+    app.alerts = router;
+    let server = TestServer::start(app);
+    let config = serde_json::json!({
+        "channels": {
+            "soc": { "type": "webhook", "url": hook },
+            "audit": { "type": "recorder" }
+        },
+        "rules": [
+            "when event_type == clickfix_detection then notify soc",
+            "when any then notify audit"
+        ]
+    });
+    let unknown = serde_json::json!({ "channels": { "pager": { "type": "pigeon" } }, "rules": [] });
+    let resp = server
+        .put("/api/admin/alert-rules")
+        .json(&unknown)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 400).await;
+    let resp = server
+        .put("/api/admin/alert-rules")
+        .json(&config)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+
+    for event_type in ["clipboard_read", "clickfix_detection"] {
+        let event = extension_event("alice", event_type, serde_json::json!({}));
+        server.post_json("/api/security", &event, 200).await;
+    }
+    for _ in 0..50 {
+        if !received.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let mut recorded = recorded.lock().unwrap().clone();
+    recorded.sort();
+    assert_eq!(
+        recorded,
+        [
+            ("audit".into(), "clickfix_detection".into()),
+            ("audit".into(), "clipboard_read".into())
+        ]
+    );
+    assert_eq!(received[0]["channel"], "soc");
+    assert_eq!(received[0]["event"]["event_type"], "clickfix_detection");
+}
+
+#[actix_web::test]
+async fn security_events_are_appended_to_syslog_as_received() {
+    let collector = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("udp://{}", collector.local_addr().unwrap());
+    worm::install(vec![worm::Sink::Syslog(
+        worm::Syslog::parse(&target).unwrap(),
+    )]);
+    let server = TestServer::simple();
+
+    let event = extension_event("alice", "clickfix_detection", serde_json::json!({}));
+    let stored = server.post_json("/api/security", &event, 200).await;
+    let mut buf = vec![0; 65536];
+    let len = tokio::time::timeout(Duration::from_secs(5), collector.recv(&mut buf))
+        .await
+        .expect("no syslog message")
+        .unwrap();
+    let line = String::from_utf8_lossy(&buf[..len]).into_owned();
+    assert!(line.starts_with("<109>1 "), "{}", line);
+    let (header, json) = line.split_once(" - clickfix_detection - ").unwrap();
+    assert!(header.ends_with(" canigoin"), "{}", header);
+    let appended: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(appended["client_id"], "alice");
+    assert_eq!(appended["data"]["packet_id"], stored["packet_id"]);
+}
+
+#[actix_web::test]
+async fn look_alike_idn_hosts_raise_homograph_events_with_both_forms() {
+    assert_eq!(
+        homograph::unicode_host("www.xn--mnchen-3ya.de").as_deref(),
+        Some("www.münchen.de")
+    );
+    assert_eq!(homograph::unicode_host("xn--.example"), None);
+    let host = |url: &str| {
+        reqwest::Url::parse(url)
+            .unwrap()
+            .host_str()
+            .unwrap()
+            .to_string()
+    };
+    // A Cyrillic "а" in each.
+    let fake_apple = host("https://\u{430}pple.com/");
+    let mixed = host("https://b\u{430}nk.example/");
+
+    let server = TestServer::simple();
+    let urls = [
+        format!("https://{}/login", fake_apple),
+        format!("https://{}/", mixed),
+        format!("https://{}/", host("https://münchen.de/")),
+        "https://apple.com/".to_string(),
+    ];
+    let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+    // Once per client and host a day.
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+
+    let events = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let events = events["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    let apple = events
+        .iter()
+        .find(|e| e["page_domain"] == fake_apple.as_str())
+        .unwrap();
+    assert_eq!(apple["event_type"], "homograph_domain");
+    assert_eq!(apple["page_domain_unicode"], "\u{430}pple.com");
+    assert_eq!(apple["risk_score"], 90);
+    let path = format!(
+        "/api/dashboard/events/{}",
+        apple["packet_id"].as_str().unwrap()
+    );
+    let full = server.get_json(&path, 200).await;
+    assert_eq!(full["data"]["imitates"], "apple");
+    assert_eq!(full["data"]["unicode_host"], "\u{430}pple.com");
+    assert_eq!(
+        full["data"]["scripts"],
+        serde_json::json!(["latin", "cyrillic"])
+    );
+
+    let bank = events
+        .iter()
+        .find(|e| e["page_domain"] == mixed.as_str())
+        .unwrap();
+    assert_eq!(bank["risk_score"], 60);
+    assert_eq!(bank["page_domain_unicode"], "b\u{430}nk.example");
+}
+
+#[actix_web::test]
+async fn requests_to_newly_registered_domains_raise_events_once_looked_up() {
+    assert_eq!(
+        rdap::registered_domain("www.shop.example.co.uk").as_deref(),
+        Some("example.co.uk")
+    );
+    assert_eq!(
+        rdap::registered_domain("a.b.fresh-shop.com").as_deref(),
+        Some("fresh-shop.com")
+    );
+    assert_eq!(rdap::registered_domain("10.0.0.1"), None);
+    assert_eq!(rdap::registered_domain("printer.local"), None);
+
+    let asked: Arc<Mutex<Vec<String>>> = Arc::default();
+    let record = asked.clone();
+    let registered = (chrono::Utc::now() - chrono::Duration::days(3)).to_rfc3339();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let registry = format!("http://{}/domain/", listener.local_addr().unwrap());
+    let fake = HttpServer::new(move || {
+        let record = record.clone();
+        let registered = registered.clone();
+        App::new().route(
+            "/domain/{name}",
+            web::get().to(move |name: web::Path<String>| {
+                let name = name.into_inner();
+                record.lock().unwrap().push(name.clone());
+                let date = match name.as_str() {
+                    "fresh-shop.com" => Some(registered.clone()),
+                    "old-bank.com" => Some("2001-05-04T00:00:00Z".to_string()),
+                    _ => None,
+                };
+                async move {
+                    let Some(date) = date else {
+                        return actix_web::HttpResponse::NotFound().finish();
+                    };
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({
+                        "objectClassName": "domain",
+                        "ldhName": name,
+                        "events": [
+                            { "eventAction": "last changed", "eventDate": "2026-01-01T00:00:00Z" },
+                            { "eventAction": "registration", "eventDate": date }
+                        ]
+                    }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(fake);
+
+    let state = web::Data::new(SimpleState::new());
+    server_events::attach_simple(state.clone());
+    let mut app = AppState::new(Backend::Simple(state));
+    app.rdap =
+        web::Data::new(rdap::RdapService::new(chrono::Duration::days(30), &registry).unwrap());
+    let server = TestServer::start(app);
+    let urls = [
+        "https://www.fresh-shop.com/login",
+        "https://fresh-shop.com/cart",
+        "https://old-bank.com/",
+        "https://unknown.net/",
+        "http://10.0.0.1/",
+        "http://printer.local/",
+    ];
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+
+    let flagged = || async {
+        let events = server
+            .get_json("/api/dashboard/events?filter=security", 200)
+            .await;
+        events["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["event_type"] == "newly_registered_domain")
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let mut events = Vec::new();
+    for _ in 0..100 {
+        events = flagged().await;
+        if !events.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["client_id"], "laptop-1");
+    let path = format!(
+        "/api/dashboard/events/{}",
+        events[0]["packet_id"].as_str().unwrap()
+    );
+    let full = server.get_json(&path, 200).await;
+    assert_eq!(full["data"]["domain"], "fresh-shop.com");
+    assert_eq!(full["data"]["url"], "https://www.fresh-shop.com/login");
+    assert_eq!(full["data"]["age_days"], 3);
+    assert_eq!(full["data"]["max_age_days"], 30);
+    assert_eq!(full["data"]["detection"]["riskScore"], 87);
+
+    // Answered from the cache: flagged right away for a new client, not again for the first.
+    server
+        .post_json("/api/logs", &log_batch("laptop-2", &urls), 200)
+        .await;
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+    let events = flagged().await;
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().any(|e| e["client_id"] == "laptop-2"));
+    let mut asked = asked.lock().unwrap().clone();
+    asked.sort();
+    assert_eq!(asked, ["fresh-shop.com", "old-bank.com", "unknown.net"]);
+}
+
+/// An SMTP relay that takes one message and returns everything it was sent.
+fn fake_smtp() -> (String, std::thread::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let relay = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(b"220 relay.acme.local ESMTP\r\n").unwrap();
+        let (mut transcript, mut in_data) = (String::new(), false);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            transcript.push_str(&line);
+            let reply: &[u8] = if in_data {
+                in_data = line != ".\r\n";
+                if in_data {
+                    b""
+                } else {
+                    b"250 queued\r\n"
+                }
+            } else if line.starts_with("EHLO") {
+                b"250 relay.acme.local\r\n"
+            } else if line.starts_with("DATA") {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).unwrap();
+            line.clear();
+        }
+        transcript
+    });
+    (addr, relay)
+}
+
+#[actix_web::test]
+async fn email_channels_mail_through_the_relay() {
+    let registry = SinkRegistry::builtin();
+    let ctx = SinkContext {
+        http: reqwest::Client::new(),
+        telegram_token: None,
+    };
+    let (addr, relay) = fake_smtp();
+    let settings = serde_json::json!({
+        "smtp": addr,
+        "tls": "none",
+        "from": "canigoin@acme.local",
+        "to": ["soc@acme.local"]
+    });
+    let sink = registry
+        .build("email", settings.as_object().unwrap(), &ctx)
+        .unwrap();
+    let event = extension_event("alice", "clickfix_detection", serde_json::json!({}));
+    let alert = Alert {
+        channel: "oncall".into(),
+        rules: vec!["when any then notify oncall".into()],
+        event: serde_json::from_value(event).unwrap(),
+    };
+    sink.send(&alert).await.unwrap();
+    let transcript = relay.join().unwrap();
+    assert!(
+        transcript.contains("MAIL FROM:<canigoin@acme.local>"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.contains("RCPT TO:<soc@acme.local>"),
+        "{}",
+        transcript
+    );
+    assert!(
+        transcript.contains("Subject: [CanIGoIn] clickfix_detection from client alice"),
+        "{}",
+        transcript
+    );
+
+    // Credentials only go over TLS.
+    let mut with_login = settings.as_object().unwrap().clone();
+    with_login.insert("username".into(), "canigoin".into());
+    with_login.insert("password".into(), "hunter2".into());
+    let err = registry.build("email", &with_login, &ctx).err().unwrap();
+    assert!(err.contains("without tls"), "{}", err);
+    with_login.remove("tls");
+    assert!(registry.build("email", &with_login, &ctx).is_ok());
+}
@@ -0,0 +1,626 @@
+mod common;
+
+use actix_web::{web, HttpResponse};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use common::{expect_json, TestServer};
+use network_logger_server::auth::AdminAuth;
+use network_logger_server::ip_filter::{IpFilter, TrustedProxies};
+use network_logger_server::oidc::{Oidc, OidcConfig};
+use network_logger_server::roles::Role;
+use network_logger_server::users::UserStore;
+use network_logger_server::AppState;
+use ring::signature::{RsaKeyPair, RsaPublicKeyComponents};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// A stand-in identity provider: discovery, its key, and a token endpoint
+/// that hands out whatever ID token the test queued for `good-code`.
+struct FakeIdp {
+    base: String,
+    key: Arc<RsaKeyPair>,
+    next_id_token: Arc<Mutex<String>>,
+    handle: actix_web::dev::ServerHandle,
+}
+
+impl FakeIdp {
+    fn start() -> FakeIdp {
+        let pem = include_str!("common/oidc-test-key.pem");
+        let der: String = pem.lines().filter(|l| !l.starts_with("-----")).collect();
+        let key = Arc::new(RsaKeyPair::from_pkcs8(&STANDARD.decode(der).unwrap()).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let next_id_token = Arc::new(Mutex::new(String::new()));
+
+        let public = RsaPublicKeyComponents::<Vec<u8>>::from(key.public());
+        let jwks = serde_json::json!({ "keys": [{
+            "kty": "RSA", "kid": "test-key", "use": "sig", "alg": "RS256",
+            "n": URL_SAFE_NO_PAD.encode(&public.n),
+            "e": URL_SAFE_NO_PAD.encode(&public.e)
+        }]});
+        let discovery = serde_json::json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{}/authorize", base),
+            "token_endpoint": format!("{}/token", base),
+            "jwks_uri": format!("{}/jwks", base)
+        });
+        let tokens = next_id_token.clone();
+        let server = actix_web::HttpServer::new(move || {
+            let discovery = discovery.clone();
+            let jwks = jwks.clone();
+            let tokens = tokens.clone();
+            actix_web::App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(move || {
+                        let discovery = discovery.clone();
+                        async move { HttpResponse::Ok().json(discovery) }
+                    }),
+                )
+                .route(
+                    "/jwks",
+                    web::get().to(move || {
+                        let jwks = jwks.clone();
+                        async move { HttpResponse::Ok().json(jwks) }
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(move |form: web::Form<Vec<(String, String)>>| {
+                        let tokens = tokens.clone();
+                        async move {
+                            let get =
+                                |k: &str| form.iter().find(|(n, _)| n == k).map(|(_, v)| v.clone());
+                            if get("code").as_deref() != Some("good-code")
+                                || get("client_secret").as_deref() != Some("shh")
+                                || get("code_verifier").is_none()
+                            {
+                                return HttpResponse::BadRequest()
+                                    .json(serde_json::json!({ "error": "invalid_grant" }));
+                            }
+                            let id_token = tokens.lock().unwrap().clone();
+                            HttpResponse::Ok().json(serde_json::json!({ "id_token": id_token }))
+                        }
+                    }),
+                )
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        FakeIdp {
+            base,
+            key,
+            next_id_token,
+            handle,
+        }
+    }
+
+    fn token(&self, claims: serde_json::Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","kid":"test-key"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{}.{}", header, payload);
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(
+                &ring::signature::RSA_PKCS1_SHA256,
+                &ring::rand::SystemRandom::new(),
+                signed.as_bytes(),
+                &mut signature,
+            )
+            .unwrap();
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn claims(&self, user: &str, groups: &[&str], nonce: Option<&str>) -> serde_json::Value {
+        let mut claims = serde_json::json!({
+            "iss": self.base,
+            "aud": "canigoin",
+            "sub": format!("sub-{}", user),
+            "preferred_username": user,
+            "groups": groups,
+            "exp": chrono::Utc::now().timestamp() + 300
+        });
+        if let Some(nonce) = nonce {
+            claims["nonce"] = nonce.into();
+        }
+        claims
+    }
+}
+
+impl Drop for FakeIdp {
+    fn drop(&mut self) {
+        drop(self.handle.stop(false));
+    }
+}
+
+fn query_param(url: &str, name: &str) -> String {
+    reqwest::Url::parse(url)
+        .unwrap()
+        .query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_else(|| panic!("no {} in {}", name, url))
+}
+
+fn cookie(resp: &reqwest::Response, name: &str) -> String {
+    resp.headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .find(|c| c.starts_with(&format!("{}=", name)))
+        .and_then(|c| c.split(';').next())
+        .unwrap_or_else(|| panic!("no {} cookie", name))
+        .to_string()
+}
+
+#[actix_web::test]
+async fn single_sign_on_maps_idp_groups_to_roles() {
+    let idp = FakeIdp::start();
+    let config: OidcConfig = serde_json::from_value(serde_json::json!({
+        "discovery_url": format!("{}/.well-known/openid-configuration", idp.base),
+        "client_id": "canigoin",
+        "client_secret": "shh",
+        "roles": { "secops": "analyst", "platform": "admin" }
+    }))
+    .unwrap();
+    let oidc = Arc::new(Oidc::new(config));
+    oidc.refresh().await.unwrap();
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(None).with_oidc(oidc));
+    let server = TestServer::start(app);
+    let browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Not logged in: the page learns where single sign-on starts.
+    let resp = server.get_json("/api/dashboard/session", 401).await;
+    assert_eq!(resp["sso_login"], "/api/auth/oidc/login");
+
+    let start = browser
+        .get(server.url("/api/auth/oidc/login?return_to=/dashboard%3Ffilter%3Dsecurity"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(start.status(), 302);
+    let authorize = start.headers()["location"].to_str().unwrap().to_string();
+    assert!(authorize.starts_with(&format!("{}/authorize?", idp.base)));
+    assert_eq!(query_param(&authorize, "client_id"), "canigoin");
+    assert_eq!(query_param(&authorize, "code_challenge_method"), "S256");
+    assert_eq!(
+        query_param(&authorize, "redirect_uri"),
+        server.url("/api/auth/oidc/callback")
+    );
+    let state = query_param(&authorize, "state");
+    let nonce = query_param(&authorize, "nonce");
+    let state_cookie = cookie(&start, "canigoin_oidc_state");
+
+    // A callback from a browser that didn't start the login is refused.
+    let forged = browser
+        .get(server.url(&format!(
+            "/api/auth/oidc/callback?code=good-code&state={}",
+            state
+        )))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(forged, 403).await["code"], "csrf_failed");
+
+    *idp.next_id_token.lock().unwrap() =
+        idp.token(idp.claims("alice", &["secops", "staff"], Some(&nonce)));
+    let back = browser
+        .get(server.url(&format!(
+            "/api/auth/oidc/callback?code=good-code&state={}",
+            state
+        )))
+        .header("cookie", &state_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(back.status(), 302);
+    assert_eq!(back.headers()["location"], "/dashboard?filter=security");
+    let session = cookie(&back, "canigoin_session");
+    // The state is single-use.
+    let replay = browser
+        .get(server.url(&format!(
+            "/api/auth/oidc/callback?code=good-code&state={}",
+            state
+        )))
+        .header("cookie", &state_cookie)
+        .send()
+        .await
+        .unwrap();
+    expect_json(replay, 401).await;
+
+    let resp = server
+        .get("/api/dashboard/session")
+        .header("cookie", &session)
+        .send()
+        .await
+        .unwrap();
+    let me = expect_json(resp, 200).await;
+    assert_eq!(me["user"], "alice");
+    assert_eq!(me["role"], "analyst");
+    let csrf = me["csrf_token"].as_str().unwrap().to_string();
+
+    // An analyst triages but can't change the blocklist.
+    let resp = server
+        .post("/api/events/pkt-1/status")
+        .header("cookie", &session)
+        .header("x-csrf-token", &csrf)
+        .json(&serde_json::json!({ "status": "acknowledged" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let resp = server
+        .post("/api/blocklist")
+        .header("cookie", &session)
+        .header("x-csrf-token", &csrf)
+        .json(&serde_json::json!({ "urlPatterns": [], "youtubeChannels": [] }))
+        .send()
+        .await
+        .unwrap();
+    let refused = expect_json(resp, 403).await;
+    assert_eq!(refused["code"], "forbidden");
+    assert_eq!(refused["role"], "analyst");
+
+    // Scripts send the provider's token directly.
+    let admin = idp.token(idp.claims("bob", &["platform"], None));
+    let resp = server
+        .put("/api/admin/features/export")
+        .bearer_auth(&admin)
+        .json(&serde_json::json!({ "enabled": false }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+
+    let mut expired = idp.claims("bob", &["platform"], None);
+    expired["exp"] = (chrono::Utc::now().timestamp() - 3600).into();
+    let mut other_client = idp.claims("bob", &["platform"], None);
+    other_client["aud"] = "someone-else".into();
+    for claims in [expired, other_client] {
+        let resp = server
+            .get("/api/admin/features")
+            .bearer_auth(idp.token(claims))
+            .send()
+            .await
+            .unwrap();
+        expect_json(resp, 401).await;
+    }
+    let mut tampered = idp.token(idp.claims("eve", &["staff"], None));
+    let payload = URL_SAFE_NO_PAD.encode(idp.claims("eve", &["platform"], None).to_string());
+    let parts: Vec<&str> = tampered.split('.').collect();
+    tampered = format!("{}.{}.{}", parts[0], payload, parts[2]);
+    let resp = server
+        .get("/api/admin/features")
+        .bearer_auth(tampered)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 401).await;
+
+    // Signed in, but in no group with a role.
+    let nobody = idp.token(idp.claims("carol", &["staff"], None));
+    let resp = server
+        .get("/api/admin/features")
+        .bearer_auth(nobody)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 403).await;
+}
+
+#[actix_web::test]
+async fn local_users_log_in_and_use_their_own_tokens() {
+    let file = std::env::temp_dir().join(format!("canigoin-users-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("root-secret".to_string())));
+    app.users = web::Data::new(UserStore::new().with_file(&file).unwrap());
+    let server = TestServer::start(app);
+    let admin = |r: reqwest::RequestBuilder| r.bearer_auth("root-secret");
+
+    let resp = admin(server.post("/api/admin/users"))
+        .json(&serde_json::json!({
+            "username": "alice", "display_name": "Alice", "role": "analyst",
+            "password": "correct horse"
+        }))
+        .send()
+        .await
+        .unwrap();
+    let created = expect_json(resp, 201).await;
+    assert_eq!(created["role"], "analyst");
+    assert_eq!(created["has_password"], true);
+    assert!(created.get("password_hash").is_none());
+    let again = admin(server.post("/api/admin/users"))
+        .json(&serde_json::json!({ "username": "alice", "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(again, 409).await["code"], "conflict");
+    let short = admin(server.post("/api/admin/users"))
+        .json(&serde_json::json!({ "username": "bob", "role": "viewer", "password": "short" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(short, 400).await;
+
+    // The password opens a dashboard session with the user's role.
+    server
+        .post_json(
+            "/api/dashboard/login",
+            &serde_json::json!({ "username": "alice", "password": "nope" }),
+            401,
+        )
+        .await;
+    let resp = server
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "username": "alice", "password": "correct horse" }))
+        .send()
+        .await
+        .unwrap();
+    let session = cookie(&resp, "canigoin_session");
+    let login = expect_json(resp, 200).await;
+    assert_eq!(login["user"], "alice");
+    assert_eq!(login["role"], "analyst");
+    let csrf = login["csrf_token"].as_str().unwrap().to_string();
+    let resp = server
+        .post("/api/events/pkt-1/status")
+        .header("cookie", &session)
+        .header("x-csrf-token", &csrf)
+        .json(&serde_json::json!({ "status": "acknowledged" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+
+    // Her API token is hers: analyst, not admin.
+    let resp = admin(server.post("/api/admin/users/alice/token"))
+        .send()
+        .await
+        .unwrap();
+    let token = expect_json(resp, 200).await["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let resp = server
+        .post("/api/events/pkt-2/status")
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "status": "escalated" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let resp = server
+        .post("/api/admin/users")
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "username": "mallory", "role": "admin" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 403).await["role"], "analyst");
+
+    // Kept across restarts, without the secrets in the clear.
+    let saved = std::fs::read_to_string(&file).unwrap();
+    assert!(saved.contains("pbkdf2-sha256$"));
+    assert!(!saved.contains("correct horse") && !saved.contains(&token));
+    let reloaded = UserStore::new().with_file(&file).unwrap();
+    assert_eq!(reloaded.get("alice").unwrap().role, Role::Analyst);
+
+    // Deactivating her ends the session and refuses the token.
+    let resp = admin(server.patch("/api/admin/users/alice"))
+        .json(&serde_json::json!({ "active": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 200).await["active"], false);
+    let resp = server
+        .get("/api/dashboard/session")
+        .header("cookie", &session)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 401).await;
+    let resp = server
+        .get("/api/admin/features")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 401).await;
+
+    let resp = admin(server.delete("/api/admin/users/alice"))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let resp = admin(server.get("/api/admin/users")).send().await.unwrap();
+    assert_eq!(expect_json(resp, 200).await["users"], serde_json::json!([]));
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[actix_web::test]
+async fn local_users_alone_turn_authentication_on() {
+    let server = TestServer::start(AppState::simple());
+
+    // With no admin token and no users yet, admin endpoints are open.
+    let resp = server
+        .post("/api/admin/users")
+        .json(&serde_json::json!({
+            "username": "olga", "role": "admin", "password": "correct horse"
+        }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 201).await;
+
+    // The first account closes them: no credentials, no admin.
+    let resp = server.get("/api/admin/users").send().await.unwrap();
+    expect_json(resp, 401).await;
+    let resp = server
+        .post("/api/admin/users")
+        .json(&serde_json::json!({ "username": "mallory", "role": "admin" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 401).await;
+
+    let resp = server
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "username": "olga", "password": "correct horse" }))
+        .send()
+        .await
+        .unwrap();
+    let session = cookie(&resp, "canigoin_session");
+    assert_eq!(expect_json(resp, 200).await["role"], "admin");
+    let resp = server
+        .get("/api/admin/users")
+        .header("cookie", &session)
+        .send()
+        .await
+        .unwrap();
+    let users = expect_json(resp, 200).await;
+    assert_eq!(users["users"].as_array().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn dashboard_and_data_reads_need_the_viewer_role() {
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("s3cret".to_string())));
+    let server = TestServer::start(app);
+    let resp = server
+        .post("/api/admin/users")
+        .bearer_auth("s3cret")
+        .json(&serde_json::json!({ "username": "vera", "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 201).await;
+    let resp = server
+        .post("/api/admin/users/vera/token")
+        .bearer_auth("s3cret")
+        .send()
+        .await
+        .unwrap();
+    let viewer = expect_json(resp, 200).await["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    for path in [
+        "/api/logs",
+        "/api/logs/tail?lines=1",
+        "/api/stats",
+        "/api/domains",
+        "/api/dashboard/events",
+        "/api/dashboard/events/export",
+        "/api/dashboard/clients",
+        "/api/clients/c1/summary",
+        "/api/clients/c1/effective-policy",
+        "/api/categories",
+    ] {
+        let resp = server.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), 401, "{} without a token", path);
+        let resp = server.get(path).bearer_auth(&viewer).send().await.unwrap();
+        assert_eq!(resp.status(), 200, "{} as a viewer", path);
+    }
+
+    // What the extension fetches stays open.
+    let resp = server.get("/api/blocklist").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn forwarded_addresses_only_count_from_trusted_proxies() {
+    let deny = ["203.0.113.0/24".to_string()];
+    let status = |server: &TestServer, forwarded: Option<&'static str>| {
+        let mut req = server.get("/api/blocklist");
+        if let Some(hops) = forwarded {
+            req = req.header("x-forwarded-for", hops);
+        }
+        async move { req.send().await.unwrap().status().as_u16() }
+    };
+
+    // Straight from the client, the header is whatever it says.
+    let mut app = AppState::simple();
+    app.ip_filter = web::Data::new(IpFilter::new(&[], &deny).unwrap());
+    let direct = TestServer::start(app);
+    assert_eq!(status(&direct, Some("203.0.113.5")).await, 200);
+
+    let mut app = AppState::simple();
+    app.ip_filter = web::Data::new(IpFilter::new(&[], &deny).unwrap());
+    app.trusted_proxies = web::Data::new(TrustedProxies::new(&["127.0.0.1".into()]).unwrap());
+    let proxied = TestServer::start(app);
+    assert_eq!(status(&proxied, None).await, 200);
+    assert_eq!(status(&proxied, Some("203.0.113.5")).await, 403);
+    // The proxy appends the address it saw; what the client sent is left of it.
+    assert_eq!(
+        status(&proxied, Some("203.0.113.5, 198.51.100.7")).await,
+        200
+    );
+    assert_eq!(
+        status(&proxied, Some("198.51.100.7, 203.0.113.5")).await,
+        403
+    );
+    assert_eq!(status(&proxied, Some("203.0.113.5, 127.0.0.1")).await, 403);
+    // An address that can't be read can't be checked against the denylist.
+    assert_eq!(status(&proxied, Some("not-an-address")).await, 403);
+}
+
+#[actix_web::test]
+async fn failed_logins_are_throttled_per_user_and_per_address() {
+    use network_logger_server::auth::{MAX_FAILED_LOGINS_PER_IP, MAX_FAILED_LOGINS_PER_USER};
+
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("root-secret".to_string())));
+    let server = TestServer::start(app);
+    let resp = server
+        .post("/api/admin/users")
+        .bearer_auth("root-secret")
+        .json(&serde_json::json!({
+            "username": "alice", "role": "viewer", "password": "correct horse"
+        }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 201).await;
+    let server = &server;
+    let login = |body: serde_json::Value| async move {
+        server
+            .post("/api/dashboard/login")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    };
+
+    // An account that doesn't exist is refused like a wrong password.
+    let resp = login(serde_json::json!({ "username": "nobody", "password": "guess" })).await;
+    expect_json(resp, 401).await;
+
+    for _ in 0..MAX_FAILED_LOGINS_PER_USER {
+        let resp = login(serde_json::json!({ "username": "alice", "password": "guess" })).await;
+        expect_json(resp, 401).await;
+    }
+    // Now even the right password waits for the window to pass.
+    let resp = login(serde_json::json!({ "username": "alice", "password": "correct horse" })).await;
+    assert!(resp.headers().contains_key("retry-after"));
+    let error = expect_json(resp, 429).await;
+    assert_eq!(error["code"], "login_throttled");
+    assert!(error["retry_at"].is_string());
+
+    // The address has failures left for other accounts, then runs out too.
+    let spent = MAX_FAILED_LOGINS_PER_USER + 1;
+    for _ in spent..MAX_FAILED_LOGINS_PER_IP {
+        let resp = login(serde_json::json!({ "token": "wrong" })).await;
+        expect_json(resp, 401).await;
+    }
+    let resp = login(serde_json::json!({ "token": "root-secret" })).await;
+    assert_eq!(expect_json(resp, 429).await["code"], "login_throttled");
+}
@@ -0,0 +1,1106 @@
+mod common;
+
+use actix_web::web;
+use common::TestServer;
+use network_logger_server::auth::AdminAuth;
+use network_logger_server::bloom::{self, BloomFilter};
+use network_logger_server::screen_time::ScreenTime;
+use network_logger_server::types::Blocklist;
+use network_logger_server::AppState;
+use std::sync::Arc;
+
+fn version(resp: &reqwest::Response) -> u64 {
+    resp.headers()["x-blocklist-version"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn blocklist() -> serde_json::Value {
+    serde_json::json!({
+        "urlPatterns": ["*://*.ads.example.com/*", "*://tracker.example.net/*"],
+        "youtubeChannels": ["UC_blocked_channel"]
+    })
+}
+
+#[actix_web::test]
+async fn blocklist_round_trips() {
+    let server = TestServer::simple();
+
+    let initial = server.get_json("/api/blocklist", 200).await;
+    assert!(initial["urlPatterns"].is_array());
+
+    let resp = server.post_json("/api/blocklist", &blocklist(), 200).await;
+    assert_eq!(resp["success"], true);
+
+    let stored = server.get_json("/api/blocklist", 200).await;
+    assert_eq!(stored, blocklist());
+}
+
+#[actix_web::test]
+async fn blocklist_update_requires_the_admin_token_when_set() {
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("s3cret".into())));
+    let server = TestServer::start(app);
+    let before = server.get_json("/api/blocklist", 200).await;
+
+    let resp = server.post_json("/api/blocklist", &blocklist(), 401).await;
+    assert_eq!(resp["code"], "unauthorized");
+    assert_eq!(server.get_json("/api/blocklist", 200).await, before);
+
+    let resp = server
+        .post("/api/blocklist")
+        .bearer_auth("s3cret")
+        .json(&blocklist())
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+    assert_eq!(server.get_json("/api/blocklist", 200).await, blocklist());
+}
+
+#[actix_web::test]
+async fn dashboard_login_sessions_need_the_csrf_token_to_change_things() {
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("s3cret".into())));
+    let server = TestServer::start(app);
+
+    let wrong = serde_json::json!({ "token": "guess" });
+    server.post_json("/api/dashboard/login", &wrong, 401).await;
+    let resp = server
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "token": "s3cret" }))
+        .send()
+        .await
+        .unwrap();
+    let set_cookie = resp.headers()["set-cookie"].to_str().unwrap().to_string();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("SameSite=Strict"));
+    assert!(!set_cookie.contains("Secure"), "plain HTTP: {}", set_cookie);
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with("canigoin_session="));
+    let login = common::expect_json(resp, 200).await;
+    let csrf = login["csrf_token"].as_str().unwrap().to_string();
+
+    let resp = server
+        .get("/api/dashboard/session")
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        common::expect_json(resp, 200).await["csrf_token"],
+        csrf.as_str()
+    );
+    // Reads only need the cookie.
+    let resp = server
+        .get("/api/admin/features")
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+
+    let update = |csrf: Option<&str>| {
+        let req = server
+            .post("/api/blocklist")
+            .header("cookie", &cookie)
+            .json(&blocklist());
+        match csrf {
+            Some(token) => req.header("x-csrf-token", token),
+            None => req,
+        }
+    };
+    let refused = common::expect_json(update(None).send().await.unwrap(), 403).await;
+    assert_eq!(refused["code"], "csrf_failed");
+    common::expect_json(update(Some("0000")).send().await.unwrap(), 403).await;
+    let foreign = update(Some(&csrf))
+        .header("origin", "https://evil.example")
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(foreign, 403).await;
+    let own_origin = server.url("");
+    let resp = update(Some(&csrf))
+        .header("origin", own_origin)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+    assert_eq!(server.get_json("/api/blocklist", 200).await, blocklist());
+
+    let resp = server
+        .post("/api/dashboard/logout")
+        .header("cookie", &cookie)
+        .header("x-csrf-token", &csrf)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+    let resp = server
+        .get("/api/dashboard/session")
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 401).await;
+    common::expect_json(update(Some(&csrf)).send().await.unwrap(), 401).await;
+}
+
+#[actix_web::test]
+async fn blocklist_with_the_wrong_shape_is_rejected() {
+    let server = TestServer::simple();
+    let resp = server
+        .post_json(
+            "/api/blocklist",
+            &serde_json::json!({ "urlPatterns": "x" }),
+            400,
+        )
+        .await;
+    assert_eq!(resp["code"], "invalid_json");
+}
+
+#[actix_web::test]
+async fn used_up_screen_time_blocks_the_category_for_that_client() {
+    let config = serde_json::json!({
+        "categories": { "video": ["youtube.com"] },
+        "budgets": { "kid": { "video": "1s" } }
+    });
+    let mut app = AppState::simple();
+    app.screen_time = web::Data::new(
+        ScreenTime::from_config(serde_json::from_value(config).unwrap(), Arc::default()).unwrap(),
+    );
+    let server = TestServer::start(app);
+    let pattern = ".*youtube\\.com.*";
+
+    let batch = common::log_batch("kid", &["https://www.youtube.com/watch?v=x"]);
+    server.post_json("/api/logs", &batch, 200).await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let status = server.get_json("/api/clients/kid/screen-time", 200).await;
+    assert_eq!(status["categories"][0]["category"], "video");
+    assert_eq!(status["categories"][0]["exhausted"], true);
+    let served = server.get_json("/api/blocklist?client_id=kid", 200).await;
+    assert!(served["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == pattern));
+    let others = server
+        .get_json("/api/blocklist?client_id=parent", 200)
+        .await;
+    assert!(!others["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == pattern));
+}
+
+#[actix_web::test]
+async fn focus_sessions_extend_the_blocklist_until_they_end() {
+    let server = TestServer::simple();
+    let pattern = ".*tiktok\\.com.*";
+    let served = |client: &'static str| {
+        let server = &server;
+        async move {
+            let list = server
+                .get_json(&format!("/api/blocklist?client_id={}", client), 200)
+                .await;
+            list["urlPatterns"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|p| p == pattern)
+        }
+    };
+
+    let bad = serde_json::json!({ "client_id": "kid", "duration": "3d" });
+    assert_eq!(
+        server.post_json("/api/focus", &bad, 400).await["field"],
+        "duration"
+    );
+    let start = serde_json::json!({ "client_id": "kid", "duration": "25m" });
+    let started = server.post_json("/api/focus", &start, 200).await;
+    assert_eq!(started["active"], true);
+    assert!(started["remaining_secs"].as_i64().unwrap() > 24 * 60);
+    assert!(served("kid").await);
+    assert!(!served("parent").await);
+
+    let resp = server.delete("/api/focus/kid").send().await.unwrap();
+    common::expect_json(resp, 200).await;
+    assert_eq!(
+        server.get_json("/api/focus/kid", 200).await["active"],
+        false
+    );
+    assert!(!served("kid").await);
+}
+
+#[actix_web::test]
+async fn logs_are_categorized_and_a_category_can_be_blocked() {
+    let server = TestServer::simple();
+    let batch = common::log_batch(
+        "kid",
+        &[
+            "https://www.youtube.com/watch?v=x",
+            "https://github.com/ping2A",
+        ],
+    );
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let logs = server.get_json("/api/logs", 200).await;
+    assert_eq!(logs[0]["logs"][0]["category"], "video");
+    assert_eq!(logs[0]["logs"][1]["category"], "dev-tools");
+    let stats = server.get_json("/api/stats", 200).await;
+    assert!(stats["categories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|c| c["category"] == "video"));
+
+    let resp = server
+        .put("/api/admin/categories/video")
+        .json(&serde_json::json!({ "blocked": true }))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        common::expect_json(resp, 200).await["patterns_changed"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+    let served = server.get_json("/api/blocklist", 200).await;
+    assert!(served["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == ".*youtube\\.com.*"));
+    let listed = server.get_json("/api/categories", 200).await;
+    let video = listed["categories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["category"] == "video");
+    assert_eq!(video.unwrap()["blocked"], true);
+
+    let resp = server
+        .put("/api/admin/categories/knitting")
+        .json(&serde_json::json!({ "blocked": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(common::expect_json(resp, 404).await["category"], "knitting");
+}
+
+#[actix_web::test]
+async fn profiles_get_their_own_entries_and_filter_logs() {
+    let server = TestServer::simple();
+    let mut list = blocklist();
+    list["profiles"] = serde_json::json!({
+        "work": { "urlPatterns": [".*reddit\\.com.*"], "youtubeChannels": ["@gaming"] }
+    });
+    server.post_json("/api/blocklist", &list, 200).await;
+    assert_eq!(server.get_json("/api/blocklist", 200).await, list);
+
+    let work = server.get_json("/api/blocklist?profile_id=work", 200).await;
+    assert!(work["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == ".*reddit\\.com.*"));
+    assert!(work["youtubeChannels"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|c| c == "@gaming"));
+    assert!(work.get("profiles").is_none());
+    let personal = server
+        .get_json("/api/blocklist?profile_id=personal", 200)
+        .await;
+    assert_eq!(personal["urlPatterns"], list["urlPatterns"]);
+
+    for profile in ["work", "personal"] {
+        let mut batch = common::log_batch("laptop", &["https://example.com/"]);
+        batch["profile_id"] = profile.into();
+        server.post_json("/api/logs", &batch, 200).await;
+    }
+    let logs = server
+        .get_json("/api/logs?client_id=laptop&profile_id=work", 200)
+        .await;
+    assert_eq!(logs.as_array().unwrap().len(), 1);
+    assert_eq!(logs[0]["profile_id"], "work");
+}
+
+#[actix_web::test]
+async fn group_blocklists_and_quotas_apply_to_members() {
+    let server = TestServer::simple();
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+    let now = chrono::Utc::now();
+    let at = |hours| {
+        (now + chrono::Duration::hours(hours))
+            .format("%H:%M")
+            .to_string()
+    };
+    let groups = serde_json::json!({
+        "groups": {
+            "kids-laptops": {
+                "clients": ["kid"],
+                "blocklist": { "urlPatterns": [".*roblox\\.com.*"], "youtubeChannels": [] },
+                "schedules": [{ "from": at(-1), "to": at(1), "urlPatterns": [".*tiktok\\.com.*"] }],
+                "quotas": { "logs_per_day": 1 }
+            }
+        }
+    });
+    let resp = server
+        .put("/api/admin/groups")
+        .json(&groups)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(common::expect_json(resp, 200).await["groups"], 1);
+
+    let served = server.get_json("/api/blocklist?client_id=kid", 200).await;
+    let patterns = served["urlPatterns"].as_array().unwrap();
+    assert!(patterns.iter().any(|p| p == ".*roblox\\.com.*"));
+    assert!(patterns.iter().any(|p| p == ".*tiktok\\.com.*"));
+    let other = server.get_json("/api/blocklist?client_id=adult", 200).await;
+    assert!(!other["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == ".*roblox\\.com.*"));
+
+    server
+        .post_json(
+            "/api/logs",
+            &common::log_batch("kid", &["https://example.com/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &common::log_batch("kid", &["https://example.com/"]),
+            429,
+        )
+        .await;
+    let usage = server.get_json("/api/clients/kid/usage", 200).await;
+    assert_eq!(usage["limits"]["logs_per_day"], 1);
+
+    let resp = server
+        .put("/api/admin/groups/kids-laptops/clients/tablet")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        common::expect_json(resp, 200).await["groups"][0],
+        "kids-laptops"
+    );
+    let resp = server
+        .delete("/api/admin/groups/kids-laptops/clients/kid")
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+    let served = server.get_json("/api/blocklist?client_id=kid", 200).await;
+    assert!(!served["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == ".*roblox\\.com.*"));
+    let resp = server
+        .put("/api/admin/groups/unknown/clients/kid")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(common::expect_json(resp, 404).await["group"], "unknown");
+
+    let bad = serde_json::json!({ "groups": { "g": { "schedules": [{ "from": "9pm", "to": "07:00" }] } } });
+    let resp = server
+        .put("/api/admin/groups")
+        .json(&bad)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 400).await;
+}
+
+#[actix_web::test]
+async fn effective_policy_matches_the_served_blocklist_and_explains_it() {
+    let server = TestServer::simple();
+    let mut list = blocklist();
+    list["profiles"] = serde_json::json!({ "work": { "urlPatterns": [".*reddit\\.com.*"] } });
+    server.post_json("/api/blocklist", &list, 200).await;
+    let now = chrono::Utc::now();
+    let at = |hours| {
+        (now + chrono::Duration::hours(hours))
+            .format("%H:%M")
+            .to_string()
+    };
+    let groups = serde_json::json!({
+        "groups": {
+            "kids-laptops": {
+                "clients": ["kid"],
+                "blocklist": { "urlPatterns": ["*://tracker.example.net/*", ".*roblox\\.com.*"] },
+                "schedules": [{ "from": at(2), "to": at(3), "urlPatterns": [".*tiktok\\.com.*"] }],
+                "quotas": { "events_per_day": 10 }
+            }
+        }
+    });
+    let resp = server
+        .put("/api/admin/groups")
+        .json(&groups)
+        .send()
+        .await
+        .unwrap();
+    common::expect_json(resp, 200).await;
+
+    let policy = server
+        .get_json("/api/clients/kid/effective-policy?profile_id=work", 200)
+        .await;
+    let served = server
+        .get_json("/api/blocklist?client_id=kid&profile_id=work", 200)
+        .await;
+    assert_eq!(policy["blocklist"], served);
+    assert_eq!(policy["groups"][0], "kids-laptops");
+
+    let layers = policy["layers"].as_array().unwrap();
+    let find = |kind: &str| layers.iter().find(|l| l["layer"] == kind).unwrap();
+    assert_eq!(find("global")["active"], true);
+    assert_eq!(find("profile")["added"], 1);
+    // The tracker pattern is already in the global list.
+    assert_eq!(find("group")["added"], 1);
+    assert_eq!(find("schedule")["active"], false);
+    assert_eq!(find("focus")["active"], false);
+    assert!(!served["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p == ".*tiktok\\.com.*"));
+
+    assert_eq!(policy["quotas"]["limits"]["events_per_day"], 10);
+    assert_eq!(
+        policy["quotas"]["sources"]["events_per_day"],
+        "group:kids-laptops"
+    );
+    assert_eq!(policy["quotas"]["sources"]["logs_per_day"], "global");
+}
+
+#[actix_web::test]
+async fn large_blocklists_are_served_as_a_bloom_filter() {
+    let server = TestServer::simple();
+    let domains: Vec<String> = (0..2000)
+        .map(|i| format!("ads-{}.example.com", i))
+        .collect();
+    let mut patterns: Vec<String> = domains
+        .iter()
+        .map(|d| Blocklist::domain_pattern(d))
+        .collect();
+    patterns.push(".*tracker\\..*".to_string());
+    let list = serde_json::json!({ "urlPatterns": patterns, "youtubeChannels": ["@spam"] });
+    server.post_json("/api/blocklist", &list, 200).await;
+
+    let full = server
+        .get("/api/blocklist")
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let resp = server
+        .get("/api/blocklist?format=bloom")
+        .send()
+        .await
+        .unwrap();
+    let compressed = resp.bytes().await.unwrap();
+    assert!(
+        compressed.len() * 5 < full.len(),
+        "{} vs {} bytes",
+        compressed.len(),
+        full.len()
+    );
+    let served: serde_json::Value = serde_json::from_slice(&compressed).unwrap();
+    assert_eq!(served["urlPatterns"], serde_json::json!([".*tracker\\..*"]));
+    assert_eq!(served["youtubeChannels"], serde_json::json!(["@spam"]));
+    assert_eq!(served["bloom"]["domains"], 2000);
+
+    let mut filter = BloomFilter::new(2000, bloom::DEFAULT_FP_RATE);
+    for domain in &domains {
+        filter.insert(domain);
+    }
+    assert_eq!(served["bloom"]["filter"], filter.to_base64());
+    assert!(filter.blocks_host("cdn.ads-7.example.com"));
+    let false_positives = (0..10_000)
+        .filter(|i| filter.blocks_host(&format!("site-{}.example.org", i)))
+        .count();
+    assert!(false_positives < 30, "{} false positives", false_positives);
+
+    server
+        .get_json("/api/blocklist?format=bloom&fp_rate=2", 400)
+        .await;
+    server.get_json("/api/blocklist?format=xml", 400).await;
+}
+
+#[actix_web::test]
+async fn a_client_at_an_old_version_downloads_only_the_changes() {
+    let server = TestServer::simple();
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+    let resp = server.get("/api/blocklist").send().await.unwrap();
+    let from = version(&resp);
+
+    let changed = serde_json::json!({
+        "urlPatterns": ["*://*.ads.example.com/*", "*://new.example.org/*"],
+        "youtubeChannels": ["UC_blocked_channel", "UC_other"]
+    });
+    let resp = server.post_json("/api/blocklist", &changed, 200).await;
+    assert_eq!(resp["version"], from + 1);
+
+    let delta = server
+        .get_json(&format!("/api/blocklist/delta?from_version={}", from), 200)
+        .await;
+    assert_eq!(delta["full"], false);
+    assert_eq!(delta["version"], from + 1);
+    assert_eq!(
+        delta["added"]["urlPatterns"],
+        serde_json::json!(["*://new.example.org/*"])
+    );
+    assert_eq!(
+        delta["added"]["youtubeChannels"],
+        serde_json::json!(["UC_other"])
+    );
+    assert_eq!(
+        delta["removed"]["urlPatterns"],
+        serde_json::json!(["*://tracker.example.net/*"])
+    );
+    assert_eq!(delta["removed"]["youtubeChannels"], serde_json::json!([]));
+
+    let current = server
+        .get_json(
+            &format!("/api/blocklist/delta?from_version={}", from + 1),
+            200,
+        )
+        .await;
+    assert_eq!(current["added"]["urlPatterns"], serde_json::json!([]));
+
+    let unknown = server
+        .get_json("/api/blocklist/delta?from_version=999", 200)
+        .await;
+    assert_eq!(unknown["full"], true);
+    assert_eq!(unknown["urlPatterns"], changed["urlPatterns"]);
+    server.get_json("/api/blocklist/delta", 400).await;
+}
+
+#[actix_web::test]
+async fn served_blocklists_are_signed_and_the_key_can_be_rotated() {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use network_logger_server::blocklist_signing::{signed_message, BlocklistSigner};
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    let file = std::env::temp_dir().join(format!("canigoin-signing-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let mut app = AppState::simple();
+    app.blocklist_signer = web::Data::new(BlocklistSigner::load(&file).unwrap());
+    let server = TestServer::start(app);
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+
+    let keys = server.get_json("/api/blocklist/keys", 200).await;
+    assert_eq!(keys["enabled"], true);
+    let first = keys["keys"][0].clone();
+    assert_eq!(first["algorithm"], "ed25519");
+    assert_eq!(first["active"], true);
+
+    // What an extension does: check the body against the pinned key.
+    let verify = |key: &serde_json::Value, version: u64, body: &[u8], signature: &str| {
+        let public = STANDARD
+            .decode(key["public_key"].as_str().unwrap())
+            .unwrap();
+        UnparsedPublicKey::new(&ED25519, public)
+            .verify(
+                &signed_message(version, body),
+                &STANDARD.decode(signature).unwrap(),
+            )
+            .is_ok()
+    };
+    let resp = server.get("/api/blocklist").send().await.unwrap();
+    let v = version(&resp);
+    assert_eq!(
+        resp.headers()["x-blocklist-key-id"],
+        first["key_id"].as_str().unwrap()
+    );
+    let signature = resp.headers()["x-blocklist-signature"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = resp.bytes().await.unwrap();
+    assert!(verify(&first, v, &body, &signature));
+    // A captive portal's edit, or the same list under another version, fails.
+    let tampered = String::from_utf8(body.to_vec())
+        .unwrap()
+        .replace("ads.example.com", "ads.example.org");
+    assert!(!verify(&first, v, tampered.as_bytes(), &signature));
+    assert!(!verify(&first, v + 1, &body, &signature));
+
+    let resp = server
+        .get("/api/blocklist/delta?from_version=0")
+        .send()
+        .await
+        .unwrap();
+    let v = version(&resp);
+    let signature = resp.headers()["x-blocklist-signature"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(verify(&first, v, &resp.bytes().await.unwrap(), &signature));
+
+    let second = server
+        .post_json(
+            "/api/admin/blocklist-keys/rotate",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+    assert_ne!(second["key_id"], first["key_id"]);
+    let resp = server.get("/api/blocklist").send().await.unwrap();
+    let v = version(&resp);
+    assert_eq!(
+        resp.headers()["x-blocklist-key-id"],
+        second["key_id"].as_str().unwrap()
+    );
+    let signature = resp.headers()["x-blocklist-signature"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(verify(&second, v, &resp.bytes().await.unwrap(), &signature));
+
+    // The old key stays published, retired, until removed; the active one can't be.
+    let keys = server.get_json("/api/blocklist/keys", 200).await;
+    assert_eq!(keys["keys"][0]["active"], false);
+    assert!(keys["keys"][0]["retired_at"].is_string());
+    let resp = server
+        .delete(&format!(
+            "/api/admin/blocklist-keys/{}",
+            second["key_id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 409);
+    let resp = server
+        .delete(&format!(
+            "/api/admin/blocklist-keys/{}",
+            first["key_id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // Restarting keeps signing with the same key.
+    let reloaded = BlocklistSigner::load(&file).unwrap();
+    assert_eq!(reloaded.public_keys().len(), 1);
+    assert_eq!(
+        reloaded.public_keys()[0].key_id,
+        second["key_id"].as_str().unwrap()
+    );
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[actix_web::test]
+async fn a_blocklist_rollout_reaches_its_canary_and_can_be_promoted_or_rolled_back() {
+    use network_logger_server::blocklist_rollout::bucket;
+
+    let server = TestServer::simple();
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+    let candidate = serde_json::json!({
+        "urlPatterns": ["*://*.ads.example.com/*", "*://*.new-threat.example/*"],
+        "youtubeChannels": ["UC_blocked_channel"]
+    });
+    let started = server
+        .post_json(
+            "/api/admin/blocklist-rollout",
+            &serde_json::json!({ "blocklist": candidate, "percent": 50 }),
+            201,
+        )
+        .await;
+    let id = started["id"].as_str().unwrap();
+    let clients: Vec<String> = (0..100).map(|i| format!("client-{}", i)).collect();
+    let canary = clients.iter().find(|c| bucket(id, c) < 50).unwrap();
+    let stable = clients.iter().find(|c| bucket(id, c) >= 50).unwrap();
+
+    let resp = server
+        .get(&format!("/api/blocklist?client_id={}", canary))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-blocklist-rollout"], "canary");
+    let served: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(served["urlPatterns"], candidate["urlPatterns"]);
+    let resp = server
+        .get(&format!("/api/blocklist?client_id={}", stable))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-blocklist-rollout"], "stable");
+    let served: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(served["urlPatterns"], blocklist()["urlPatterns"]);
+    // Without a client_id there's no arm to pick: the current list.
+    let served = server.get_json("/api/blocklist", 200).await;
+    assert_eq!(served["urlPatterns"], blocklist()["urlPatterns"]);
+
+    // A canary client can't patch its copy with the shared deltas.
+    let delta = server
+        .get_json(
+            &format!("/api/blocklist/delta?from_version=1&client_id={}", canary),
+            200,
+        )
+        .await;
+    assert_eq!(delta["full"], true);
+    assert_eq!(delta["urlPatterns"], candidate["urlPatterns"]);
+
+    let logs = common::log_batch(canary, &["https://a.example/", "https://b.example/"]);
+    server.post_json("/api/logs", &logs, 200).await;
+    let event = common::extension_event(canary, "blocklist_error", serde_json::json!({}));
+    server.post_json("/api/extensions", &event, 200).await;
+    let status = server.get_json("/api/admin/blocklist-rollout", 200).await;
+    assert_eq!(status["rollout"]["canary"]["clients"], 1);
+    assert_eq!(status["rollout"]["canary"]["logs"], 2);
+    assert_eq!(status["rollout"]["canary"]["errors"], 1);
+    assert_eq!(status["rollout"]["canary"]["error_rate"], 0.5);
+    assert_eq!(status["rollout"]["stable"]["errors"], 0);
+
+    // Narrowing to 0% sends the canary client back, with a full list once.
+    let resp = server
+        .put("/api/admin/blocklist-rollout")
+        .json(&serde_json::json!({ "percent": 0 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let path = format!("/api/blocklist/delta?from_version=1&client_id={}", canary);
+    let delta = server.get_json(&path, 200).await;
+    assert_eq!(delta["full"], true);
+    assert_eq!(delta["urlPatterns"], blocklist()["urlPatterns"]);
+    assert_eq!(server.get_json(&path, 200).await["full"], false);
+
+    // Promoting over a blocklist changed since the start needs ?force=true.
+    let mut edited = blocklist();
+    edited["youtubeChannels"] = serde_json::json!(["UC_other"]);
+    server.post_json("/api/blocklist", &edited, 200).await;
+    server
+        .post_json(
+            "/api/admin/blocklist-rollout/promote",
+            &serde_json::json!({}),
+            409,
+        )
+        .await;
+    let promoted = server
+        .post_json(
+            "/api/admin/blocklist-rollout/promote?force=true",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+    assert_eq!(promoted["rollout"]["id"], id);
+    let served = server
+        .get_json(&format!("/api/blocklist?client_id={}", stable), 200)
+        .await;
+    assert_eq!(served["urlPatterns"], candidate["urlPatterns"]);
+    assert!(server.get_json("/api/admin/blocklist-rollout", 200).await["rollout"].is_null());
+
+    server
+        .post_json(
+            "/api/admin/blocklist-rollout",
+            &serde_json::json!({ "blocklist": blocklist(), "percent": 100 }),
+            201,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/admin/blocklist-rollout",
+            &serde_json::json!({ "blocklist": blocklist(), "percent": 10 }),
+            409,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/admin/blocklist-rollout/rollback",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+    let served = server
+        .get_json(&format!("/api/blocklist?client_id={}", canary), 200)
+        .await;
+    assert_eq!(served["urlPatterns"], candidate["urlPatterns"]);
+    server
+        .post_json(
+            "/api/admin/blocklist-rollout/rollback",
+            &serde_json::json!({}),
+            404,
+        )
+        .await;
+}
+
+#[actix_web::test]
+async fn experiments_split_clients_between_variants_and_report_outcomes() {
+    use network_logger_server::blocklist_rollout::bucket;
+
+    let server = TestServer::simple();
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+    let experiment = serde_json::json!({
+        "id": "block-games",
+        "description": "Does blocking game sites cut browsing time?",
+        "variants": [
+            { "name": "control", "weight": 50 },
+            { "name": "strict", "weight": 50, "urlPatterns": ["*://*.games.example/*"] }
+        ]
+    });
+    server.post_json("/api/experiments", &experiment, 201).await;
+    server.post_json("/api/experiments", &experiment, 409).await;
+    let mut lopsided = experiment.clone();
+    lopsided["id"] = "lopsided".into();
+    lopsided["variants"][1]["weight"] = 60.into();
+    server.post_json("/api/experiments", &lopsided, 400).await;
+
+    let clients: Vec<String> = (0..100).map(|i| format!("client-{}", i)).collect();
+    let control = clients
+        .iter()
+        .find(|c| bucket("block-games", c) < 50)
+        .unwrap();
+    let strict = clients
+        .iter()
+        .find(|c| bucket("block-games", c) >= 50)
+        .unwrap();
+
+    let served = server
+        .get_json(&format!("/api/blocklist?client_id={}", strict), 200)
+        .await;
+    assert!(served["urlPatterns"]
+        .as_array()
+        .unwrap()
+        .contains(&"*://*.games.example/*".into()));
+    let served = server
+        .get_json(&format!("/api/blocklist?client_id={}", control), 200)
+        .await;
+    assert_eq!(served["urlPatterns"], blocklist()["urlPatterns"]);
+    let policy = server
+        .get_json(&format!("/api/clients/{}/effective-policy", strict), 200)
+        .await;
+    let layer = policy["layers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|l| l["layer"] == "experiment")
+        .unwrap();
+    assert_eq!(layer["name"], "block-games/strict");
+
+    for _ in 0..2 {
+        let logs = common::log_batch(strict, &["https://games.example/", "https://news.example/"]);
+        server.post_json("/api/logs", &logs, 200).await;
+    }
+    let event = common::extension_event(strict, "override_requested", serde_json::json!({}));
+    server.post_json("/api/extensions", &event, 200).await;
+    let results = server
+        .get_json("/api/experiments/block-games/results", 200)
+        .await;
+    assert_eq!(results["running"], true);
+    let variants = results["variants"].as_array().unwrap();
+    assert_eq!(variants[0]["name"], "control");
+    assert_eq!(variants[0]["clients"], 0);
+    assert_eq!(variants[1]["clients"], 1);
+    assert_eq!(variants[1]["logs"], 4);
+    assert_eq!(variants[1]["page_views"], 4);
+    assert_eq!(variants[1]["override_requests"], 1);
+    assert_eq!(variants[1]["overrides_per_client"], 1.0);
+
+    // Stopped, the variants are no longer served but the results stay.
+    server
+        .post_json(
+            "/api/experiments/block-games/stop",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+    let served = server
+        .get_json(&format!("/api/blocklist?client_id={}", strict), 200)
+        .await;
+    assert_eq!(served["urlPatterns"], blocklist()["urlPatterns"]);
+    let results = server
+        .get_json("/api/experiments/block-games/results", 200)
+        .await;
+    assert_eq!(results["running"], false);
+    assert_eq!(results["variants"][1]["logs"], 4);
+
+    let resp = server
+        .delete("/api/experiments/block-games")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    server
+        .get_json("/api/experiments/block-games/results", 404)
+        .await;
+}
+
+#[actix_web::test]
+async fn experiment_variants_forget_their_least_recently_active_clients_past_the_cap() {
+    use network_logger_server::counters::BatchSummary;
+    use network_logger_server::experiments::{Experiments, NewExperiment, MAX_CLIENTS_PER_VARIANT};
+
+    let experiments = Experiments::new();
+    let new: NewExperiment = serde_json::from_value(serde_json::json!({
+        "id": "everyone",
+        "variants": [{ "name": "all", "weight": 100 }, { "name": "none", "weight": 0 }]
+    }))
+    .unwrap();
+    experiments.start(new, "test").unwrap();
+    let batch = BatchSummary {
+        logs: 1,
+        ..BatchSummary::default()
+    };
+    for i in 0..MAX_CLIENTS_PER_VARIANT {
+        experiments.record_logs(Some(&format!("client-{}", i)), &batch);
+    }
+    let clients = |e: &Experiments| e.results("everyone").unwrap().variants[0].outcomes.clients;
+    assert_eq!(clients(&experiments), MAX_CLIENTS_PER_VARIANT);
+
+    experiments.record_logs(Some("one-more"), &batch);
+    assert_eq!(
+        clients(&experiments),
+        MAX_CLIENTS_PER_VARIANT - MAX_CLIENTS_PER_VARIANT / 10 + 1
+    );
+    let results = experiments.results("everyone").unwrap();
+    assert_eq!(
+        results.variants[0].outcomes.logs,
+        MAX_CLIENTS_PER_VARIANT as u64 + 1
+    );
+}
+
+#[actix_web::test]
+async fn signed_pushes_from_a_policy_system_update_the_blocklist() {
+    use network_logger_server::blocklist_sync::{signature, BlocklistSync};
+
+    let mut app = AppState::simple();
+    app.blocklist_sync = web::Data::new(BlocklistSync::new(Some("hook-secret".into())));
+    let server = TestServer::start(app);
+    server.post_json("/api/blocklist", &blocklist(), 200).await;
+
+    let push = |body: &serde_json::Value, secret: &str, delivery: &str| {
+        let body = serde_json::to_vec(body).unwrap();
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        server
+            .post("/api/integrations/blocklist-sync")
+            .header("Content-Type", "application/json")
+            .header("X-CanIGoIn-Timestamp", &timestamp)
+            .header(
+                "X-CanIGoIn-Signature",
+                signature(secret, &timestamp, delivery, &body),
+            )
+            .header("X-CanIGoIn-Delivery", delivery)
+            .body(body)
+    };
+    let body = serde_json::json!({
+        "source": "policy-hub",
+        "add": { "urlPatterns": ["*://*.gambling.example/*"] },
+        "remove": { "urlPatterns": ["*://tracker.example.net/*"], "youtubeChannels": ["UC_unknown"] }
+    });
+
+    let resp = push(&body, "wrong-secret", "d-1").send().await.unwrap();
+    let error = common::expect_json(resp, 401).await;
+    assert_eq!(error["reason"], "signature mismatch");
+
+    let resp = push(&body, "hook-secret", "d-1").send().await.unwrap();
+    let result = common::expect_json(resp, 200).await;
+    assert_eq!(result["added"], 1);
+    assert_eq!(result["removed"], 1);
+    let stored = server.get_json("/api/blocklist", 200).await;
+    assert_eq!(
+        stored["urlPatterns"],
+        serde_json::json!(["*://*.ads.example.com/*", "*://*.gambling.example/*"])
+    );
+
+    // A retried delivery is applied once.
+    let resp = push(&body, "hook-secret", "d-1").send().await.unwrap();
+    assert_eq!(common::expect_json(resp, 200).await["duplicate"], true);
+
+    // The delivery id is signed: replaying the push under a fresh id
+    // doesn't verify, and neither does leaving it out.
+    let bytes = serde_json::to_vec(&body).unwrap();
+    let now = chrono::Utc::now().timestamp().to_string();
+    let resp = server
+        .post("/api/integrations/blocklist-sync")
+        .header("X-CanIGoIn-Timestamp", &now)
+        .header(
+            "X-CanIGoIn-Signature",
+            signature("hook-secret", &now, "d-1", &bytes),
+        )
+        .header("X-CanIGoIn-Delivery", "d-2")
+        .body(bytes.clone())
+        .send()
+        .await
+        .unwrap();
+    let error = common::expect_json(resp, 401).await;
+    assert_eq!(error["reason"], "signature mismatch");
+    let resp = server
+        .post("/api/integrations/blocklist-sync")
+        .header("X-CanIGoIn-Timestamp", &now)
+        .header(
+            "X-CanIGoIn-Signature",
+            signature("hook-secret", &now, "d-1", &bytes),
+        )
+        .body(bytes.clone())
+        .send()
+        .await
+        .unwrap();
+    let error = common::expect_json(resp, 401).await;
+    assert_eq!(error["reason"], "missing or malformed delivery id");
+
+    // An old timestamp doesn't verify, even correctly signed.
+    let stale = (chrono::Utc::now().timestamp() - 3600).to_string();
+    let resp = server
+        .post("/api/integrations/blocklist-sync")
+        .header("X-CanIGoIn-Timestamp", &stale)
+        .header(
+            "X-CanIGoIn-Signature",
+            signature("hook-secret", &stale, "d-3", &bytes),
+        )
+        .header("X-CanIGoIn-Delivery", "d-3")
+        .body(bytes.clone())
+        .send()
+        .await
+        .unwrap();
+    let error = common::expect_json(resp, 401).await;
+    assert_eq!(error["reason"], "timestamp too far from the server's clock");
+
+    // Nor does one at the edge of i64, signed or not.
+    let extreme = i64::MIN.to_string();
+    for signed in [
+        signature("hook-secret", &extreme, "d-4", &bytes),
+        "sha256=00".to_string(),
+    ] {
+        let resp = server
+            .post("/api/integrations/blocklist-sync")
+            .header("X-CanIGoIn-Timestamp", &extreme)
+            .header("X-CanIGoIn-Signature", signed)
+            .header("X-CanIGoIn-Delivery", "d-4")
+            .body(bytes.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 401);
+    }
+
+    // Without --blocklist-sync-secret there is nothing to push to.
+    let server = TestServer::simple();
+    let resp = server
+        .post("/api/integrations/blocklist-sync")
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
@@ -0,0 +1,372 @@
+//! A Redis for tests: an in-memory server speaking enough of RESP for the
+//! commands the server sends, so several replicas in one test can share
+//! state the way they would through a real Redis. Unknown commands answer
+//! `OK`, which covers the client's connection setup; MULTI/EXEC queue
+//! commands and run them together.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+enum Value {
+    Str(String),
+    Hash(HashMap<String, String>),
+    Set(BTreeSet<String>),
+    ZSet(HashMap<String, f64>),
+    List(Vec<String>),
+}
+
+enum Reply {
+    Ok,
+    Queued,
+    Nil,
+    Int(i64),
+    Bulk(String),
+    Array(Vec<Reply>),
+    Error(&'static str),
+}
+
+impl Reply {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Ok => out.extend_from_slice(b"+OK\r\n"),
+            Reply::Queued => out.extend_from_slice(b"+QUEUED\r\n"),
+            Reply::Nil => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Int(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(s) => {
+                out.extend_from_slice(format!("${}\r\n{}\r\n", s.len(), s).as_bytes())
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write(out);
+                }
+            }
+            Reply::Error(e) => out.extend_from_slice(format!("-ERR {}\r\n", e).as_bytes()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    keys: HashMap<String, (Value, Option<Instant>)>,
+}
+
+fn bulks<'a>(items: impl IntoIterator<Item = &'a String>) -> Reply {
+    Reply::Array(items.into_iter().cloned().map(Reply::Bulk).collect())
+}
+
+/// `start..=stop` of `len` items, with negative indexes from the end.
+fn range(len: usize, start: &str, stop: &str) -> std::ops::Range<usize> {
+    let index = |i: &str| {
+        let i: i64 = i.parse().unwrap_or(0);
+        if i < 0 {
+            (len as i64 + i).max(0) as usize
+        } else {
+            i as usize
+        }
+    };
+    let (start, stop) = (index(start), (index(stop) + 1).min(len));
+    start.min(stop)..stop
+}
+
+impl Store {
+    fn live(&mut self, key: &str) -> Option<&mut Value> {
+        if matches!(self.keys.get(key), Some((_, Some(at))) if *at <= Instant::now()) {
+            self.keys.remove(key);
+        }
+        self.keys.get_mut(key).map(|(value, _)| value)
+    }
+
+    fn entry(&mut self, key: &str, new: fn() -> Value) -> &mut Value {
+        if self.live(key).is_none() {
+            self.keys.insert(key.to_string(), (new(), None));
+        }
+        &mut self.keys.get_mut(key).unwrap().0
+    }
+
+    fn hash(&mut self, key: &str) -> &mut HashMap<String, String> {
+        match self.entry(key, || Value::Hash(HashMap::new())) {
+            Value::Hash(hash) => hash,
+            _ => panic!("{} is not a hash", key),
+        }
+    }
+
+    fn zset(&mut self, key: &str) -> &mut HashMap<String, f64> {
+        match self.entry(key, || Value::ZSet(HashMap::new())) {
+            Value::ZSet(zset) => zset,
+            _ => panic!("{} is not a sorted set", key),
+        }
+    }
+
+    /// Members from the lowest score up, or the highest down.
+    fn ranked(&mut self, key: &str, reverse: bool) -> Vec<(String, f64)> {
+        let mut members: Vec<(String, f64)> = match self.live(key) {
+            Some(Value::ZSet(zset)) => zset.iter().map(|(m, s)| (m.clone(), *s)).collect(),
+            _ => Vec::new(),
+        };
+        members.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        if reverse {
+            members.reverse();
+        }
+        members
+    }
+
+    fn run(&mut self, args: &[String]) -> Reply {
+        let name = args[0].to_ascii_uppercase();
+        let key = args.get(1).map(String::as_str).unwrap_or_default();
+        let rest = args.get(2..).unwrap_or_default();
+        match name.as_str() {
+            "PING" => Reply::Bulk("PONG".into()),
+            "GET" => match self.live(key) {
+                Some(Value::Str(s)) => Reply::Bulk(s.clone()),
+                _ => Reply::Nil,
+            },
+            "MGET" => Reply::Array(
+                args[1..]
+                    .iter()
+                    .map(|key| match self.live(key) {
+                        Some(Value::Str(s)) => Reply::Bulk(s.clone()),
+                        _ => Reply::Nil,
+                    })
+                    .collect(),
+            ),
+            "SET" | "SETEX" => {
+                let (value, options) = match name.as_str() {
+                    "SETEX" => (&rest[1], vec!["EX".to_string(), rest[0].clone()]),
+                    _ => (&rest[0], rest[1..].to_vec()),
+                };
+                let upper: Vec<String> = options.iter().map(|o| o.to_ascii_uppercase()).collect();
+                if upper.contains(&"NX".to_string()) && self.live(key).is_some() {
+                    return Reply::Nil;
+                }
+                let expires = upper
+                    .iter()
+                    .position(|o| o == "EX")
+                    .and_then(|i| options.get(i + 1)?.parse().ok())
+                    .map(|secs| Instant::now() + Duration::from_secs(secs));
+                self.keys
+                    .insert(key.to_string(), (Value::Str(value.clone()), expires));
+                Reply::Ok
+            }
+            "DEL" => Reply::Int(
+                args[1..]
+                    .iter()
+                    .filter(|key| {
+                        let live = self.live(key).is_some();
+                        self.keys.remove(key.as_str());
+                        live
+                    })
+                    .count() as i64,
+            ),
+            "EXPIRE" => {
+                let secs: u64 = rest[0].parse().unwrap_or(0);
+                match self.live(key) {
+                    Some(_) => {
+                        self.keys.get_mut(key).unwrap().1 =
+                            Some(Instant::now() + Duration::from_secs(secs));
+                        Reply::Int(1)
+                    }
+                    None => Reply::Int(0),
+                }
+            }
+            "TTL" => match self.live(key) {
+                None => Reply::Int(-2),
+                Some(_) => match self.keys[key].1 {
+                    Some(at) => {
+                        Reply::Int(at.saturating_duration_since(Instant::now()).as_secs() as i64)
+                    }
+                    None => Reply::Int(-1),
+                },
+            },
+            "INCR" | "INCRBY" => {
+                let by: i64 = rest.first().map_or(1, |n| n.parse().unwrap_or(0));
+                let n = match self.live(key) {
+                    Some(Value::Str(s)) => s.parse::<i64>().unwrap_or(0),
+                    _ => 0,
+                } + by;
+                let expires = self.keys.get(key).and_then(|(_, at)| *at);
+                self.keys
+                    .insert(key.to_string(), (Value::Str(n.to_string()), expires));
+                Reply::Int(n)
+            }
+            "SADD" => {
+                let Value::Set(set) = self.entry(key, || Value::Set(BTreeSet::new())) else {
+                    return Reply::Error("WRONGTYPE");
+                };
+                Reply::Int(rest.iter().filter(|m| set.insert(m.to_string())).count() as i64)
+            }
+            "SMEMBERS" => match self.live(key) {
+                Some(Value::Set(set)) => bulks(set.iter()),
+                _ => Reply::Array(Vec::new()),
+            },
+            "HSET" => {
+                let hash = self.hash(key);
+                let added = rest
+                    .chunks(2)
+                    .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                    .count();
+                Reply::Int(added as i64)
+            }
+            "HSETNX" => {
+                let hash = self.hash(key);
+                match hash.contains_key(&rest[0]) {
+                    true => Reply::Int(0),
+                    false => {
+                        hash.insert(rest[0].clone(), rest[1].clone());
+                        Reply::Int(1)
+                    }
+                }
+            }
+            "HGET" => match self.live(key) {
+                Some(Value::Hash(hash)) => {
+                    hash.get(&rest[0]).cloned().map_or(Reply::Nil, Reply::Bulk)
+                }
+                _ => Reply::Nil,
+            },
+            "HEXISTS" => match self.live(key) {
+                Some(Value::Hash(hash)) => Reply::Int(hash.contains_key(&rest[0]) as i64),
+                _ => Reply::Int(0),
+            },
+            "HDEL" => match self.live(key) {
+                Some(Value::Hash(hash)) => Reply::Int(
+                    rest.iter()
+                        .filter(|f| hash.remove(f.as_str()).is_some())
+                        .count() as i64,
+                ),
+                _ => Reply::Int(0),
+            },
+            "HLEN" => match self.live(key) {
+                Some(Value::Hash(hash)) => Reply::Int(hash.len() as i64),
+                _ => Reply::Int(0),
+            },
+            "HGETALL" => match self.live(key) {
+                Some(Value::Hash(hash)) => bulks(hash.iter().flat_map(|(f, v)| [f, v])),
+                _ => Reply::Array(Vec::new()),
+            },
+            "HVALS" => match self.live(key) {
+                Some(Value::Hash(hash)) => bulks(hash.values()),
+                _ => Reply::Array(Vec::new()),
+            },
+            "HINCRBY" => {
+                let hash = self.hash(key);
+                let by: i64 = rest[1].parse().unwrap_or(0);
+                let n = hash
+                    .get(&rest[0])
+                    .and_then(|n| n.parse::<i64>().ok())
+                    .unwrap_or(0)
+                    + by;
+                hash.insert(rest[0].clone(), n.to_string());
+                Reply::Int(n)
+            }
+            "ZINCRBY" => {
+                let by: f64 = rest[0].parse().unwrap_or(0.0);
+                let score = self.zset(key).entry(rest[1].clone()).or_insert(0.0);
+                *score += by;
+                Reply::Bulk(score.to_string())
+            }
+            "ZCARD" => match self.live(key) {
+                Some(Value::ZSet(zset)) => Reply::Int(zset.len() as i64),
+                _ => Reply::Int(0),
+            },
+            "ZREM" => match self.live(key) {
+                Some(Value::ZSet(zset)) => Reply::Int(
+                    rest.iter()
+                        .filter(|m| zset.remove(m.as_str()).is_some())
+                        .count() as i64,
+                ),
+                _ => Reply::Int(0),
+            },
+            "ZRANGE" | "ZREVRANGE" => {
+                let members = self.ranked(key, name == "ZREVRANGE");
+                let with_scores = rest
+                    .get(2)
+                    .is_some_and(|o| o.eq_ignore_ascii_case("WITHSCORES"));
+                let mut out = Vec::new();
+                for (member, score) in &members[range(members.len(), &rest[0], &rest[1])] {
+                    out.push(Reply::Bulk(member.clone()));
+                    if with_scores {
+                        out.push(Reply::Bulk(score.to_string()));
+                    }
+                }
+                Reply::Array(out)
+            }
+            "RPUSH" => {
+                let Value::List(list) = self.entry(key, || Value::List(Vec::new())) else {
+                    return Reply::Error("WRONGTYPE");
+                };
+                list.extend(rest.iter().cloned());
+                Reply::Int(list.len() as i64)
+            }
+            "LRANGE" => match self.live(key) {
+                Some(Value::List(list)) => bulks(&list[range(list.len(), &rest[0], &rest[1])]),
+                _ => Reply::Array(Vec::new()),
+            },
+            _ => Reply::Ok,
+        }
+    }
+}
+
+/// One command as sent: an array of bulk strings.
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).ok()?;
+        arg.truncate(len);
+        args.push(String::from_utf8(arg).ok()?);
+    }
+    Some(args)
+}
+
+fn serve(stream: TcpStream, store: Arc<Mutex<Store>>) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    // Commands queued between MULTI and EXEC, run together under one lock.
+    let mut queued: Option<Vec<Vec<String>>> = None;
+    while let Some(args) = read_command(&mut reader) {
+        let mut out = Vec::new();
+        let reply = match (args[0].to_ascii_uppercase().as_str(), &mut queued) {
+            ("MULTI", _) => {
+                queued = Some(Vec::new());
+                Reply::Ok
+            }
+            ("EXEC", Some(_)) => {
+                let commands = queued.take().unwrap();
+                let mut store = store.lock().unwrap();
+                Reply::Array(commands.iter().map(|args| store.run(args)).collect())
+            }
+            (_, Some(commands)) => {
+                commands.push(args);
+                Reply::Queued
+            }
+            _ => store.lock().unwrap().run(&args),
+        };
+        reply.write(&mut out);
+        if writer.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+/// Starts the server; its `redis://` URL. It runs until the test ends.
+pub fn start() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    let store = Arc::new(Mutex::new(Store::default()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let store = store.clone();
+            std::thread::spawn(move || serve(stream, store));
+        }
+    });
+    url
+}
@@ -0,0 +1,144 @@
+//! Shared harness: serves [`build_app`] on a free local port inside the test
+//! process and talks to it over HTTP, so the tests exercise the real routing,
+//! middleware and extractors.
+
+#![allow(dead_code)]
+
+use actix_web::dev::ServerHandle;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use network_logger_server::{build_app, AppState};
+use std::io::Write;
+use std::net::TcpListener;
+
+pub mod fake_redis;
+
+pub struct TestServer {
+    handle: ServerHandle,
+    base: String,
+    client: reqwest::Client,
+}
+
+impl TestServer {
+    /// Simple mode with in-memory storage and default settings.
+    pub fn simple() -> TestServer {
+        TestServer::start(AppState::simple())
+    }
+
+    /// Must be called from an actix runtime (`#[actix_web::test]`).
+    pub fn start(app: AppState) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("no free port");
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let server = actix_web::HttpServer::new(move || build_app(app.clone()))
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .expect("failed to start the server")
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        TestServer {
+            handle,
+            base,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    pub fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.get(self.url(path))
+    }
+
+    pub fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.post(self.url(path))
+    }
+
+    pub fn put(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.put(self.url(path))
+    }
+
+    pub fn patch(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.patch(self.url(path))
+    }
+
+    pub fn delete(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.delete(self.url(path))
+    }
+
+    /// GET that expects `status` and returns the JSON body.
+    pub async fn get_json(&self, path: &str, status: u16) -> serde_json::Value {
+        expect_json(self.get(path).send().await.unwrap(), status).await
+    }
+
+    /// POST of a JSON body that expects `status` and returns the JSON response.
+    pub async fn post_json(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+        status: u16,
+    ) -> serde_json::Value {
+        expect_json(self.post(path).json(body).send().await.unwrap(), status).await
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Sends the stop command right away; the returned future only waits
+        // for shutdown to finish.
+        drop(self.handle.stop(false));
+    }
+}
+
+pub async fn expect_json(resp: reqwest::Response, status: u16) -> serde_json::Value {
+    let actual = resp.status().as_u16();
+    let text = resp.text().await.unwrap();
+    assert_eq!(actual, status, "unexpected status, body: {}", text);
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("not JSON ({}): {}", e, text))
+}
+
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// A log batch as the extension sends it.
+pub fn log_batch(client_id: &str, urls: &[&str]) -> serde_json::Value {
+    let logs: Vec<serde_json::Value> = urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            serde_json::json!({
+                "requestId": format!("req-{}", i),
+                "url": url,
+                "method": "GET",
+                "type": "main_frame"
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "client_id": client_id,
+        "session_id": format!("session-{}", client_id),
+        "timestamp": "2026-01-01T12:00:00Z",
+        "user_agent": "integration-test",
+        "logs": logs
+    })
+}
+
+pub fn extension_event(
+    client_id: &str,
+    event_type: &str,
+    data: serde_json::Value,
+) -> serde_json::Value {
+    serde_json::json!({
+        "client_id": client_id,
+        "session_id": format!("session-{}", client_id),
+        "timestamp": "2026-01-01T12:00:00Z",
+        "user_agent": "integration-test",
+        "event_type": event_type,
+        "data": data
+    })
+}
@@ -0,0 +1,1643 @@
+mod common;
+
+use actix_web::web;
+use chrono::TimeZone;
+use common::{expect_json, extension_event, log_batch, TestServer};
+use network_logger_server::chain::{self, EventChain};
+use network_logger_server::client_names::{self, ClientNames, Source};
+use network_logger_server::distinct::{self, HyperLogLog};
+use network_logger_server::features::{Feature, FeatureFlags};
+use network_logger_server::response_cache::ResponseCache;
+use network_logger_server::scheduler::{JobOptions, Schedule, Scheduler};
+use network_logger_server::security_headers::{self, SecurityHeaders, SecurityHeadersConfig};
+use network_logger_server::simple::SimpleState;
+use network_logger_server::tickets::{self, Ticketing, TrackerConfig};
+use network_logger_server::{ct, server_events, tui, AppState, Backend};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// One event of each category the dashboard filters on.
+async fn seed(server: &TestServer) {
+    let events = [
+        (
+            "/api/extensions",
+            extension_event(
+                "client-js",
+                "javascript_execution",
+                serde_json::json!({
+                    "url": "https://shop.example.com/",
+                    "scriptUrl": "https://cdn.example.net/lib.js"
+                }),
+            ),
+        ),
+        (
+            "/api/extensions",
+            extension_event("client-gen", "extension_installed", serde_json::json!({})),
+        ),
+        (
+            "/api/security",
+            extension_event(
+                "client-sec",
+                "clickfix_detection",
+                serde_json::json!({
+                    "url": "https://evil.example.org/"
+                }),
+            ),
+        ),
+    ];
+    for (path, event) in &events {
+        server.post_json(path, event, 200).await;
+    }
+}
+
+fn categories(resp: &serde_json::Value) -> Vec<String> {
+    resp["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["category"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[actix_web::test]
+async fn events_are_filtered_by_category() {
+    let server = TestServer::simple();
+    seed(&server).await;
+
+    let all = server.get_json("/api/dashboard/events", 200).await;
+    assert_eq!(categories(&all), ["security", "general", "javascript"]);
+
+    let security = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    assert_eq!(categories(&security), ["security"]);
+    assert_eq!(security["events"][0]["page_domain"], "evil.example.org");
+
+    let js = server
+        .get_json("/api/dashboard/events?filter=javascript", 200)
+        .await;
+    assert_eq!(categories(&js), ["javascript"]);
+    assert_eq!(js["events"][0]["script_domain"], "cdn.example.net");
+
+    let resp = server
+        .get_json("/api/dashboard/events?filter=bogus", 400)
+        .await;
+    assert_eq!(resp["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn the_event_list_downloads_as_csv_with_the_same_filters() {
+    let server = TestServer::simple();
+    seed(&server).await;
+    let mut tricky = extension_event("client-csv", "form_submit", serde_json::json!({}));
+    tricky["user_agent"] = "=HYPERLINK(\"https://evil.example\"), \"quoted\"".into();
+    server.post_json("/api/extensions", &tricky, 200).await;
+
+    let resp = server
+        .get("/api/dashboard/events/export?filter=security")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+    let disposition = resp.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        disposition.starts_with("attachment; filename=\"events-security-"),
+        "{}",
+        disposition
+    );
+    assert_eq!(resp.headers()["x-export-rows"], "1");
+    let text = resp.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    assert_eq!(&reader.headers().unwrap()[0], "packet_id");
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(&rows[0][3], "security");
+    assert_eq!(&rows[0][4], "client-sec");
+    assert_eq!(&rows[0][7], "evil.example.org");
+
+    let resp = server
+        .get("/api/dashboard/events/export")
+        .send()
+        .await
+        .unwrap();
+    let text = resp.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 4);
+    // A cell a spreadsheet would run as a formula is defused; quotes and
+    // commas survive the round trip.
+    assert_eq!(&rows[0][4], "client-csv");
+    assert_eq!(
+        &rows[0][13],
+        "'=HYPERLINK(\"https://evil.example\"), \"quoted\""
+    );
+
+    let resp = server
+        .get_json("/api/dashboard/events/export?filter=bogus", 400)
+        .await;
+    assert_eq!(resp["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn read_endpoints_answer_with_only_the_requested_fields() {
+    let server = TestServer::simple();
+    seed(&server).await;
+    let batch = log_batch("client-logs", &["https://a.example/", "https://b.example/"]);
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let logs = server
+        .get_json("/api/logs?fields=client_id,logs.url", 200)
+        .await;
+    assert_eq!(
+        logs,
+        serde_json::json!([{
+            "client_id": "client-logs",
+            "logs": [{ "url": "https://a.example/" }, { "url": "https://b.example/" }]
+        }])
+    );
+
+    let events = server
+        .get_json(
+            "/api/dashboard/events?filter=security&fields=packet_id,category",
+            200,
+        )
+        .await;
+    let row = events["events"][0].as_object().unwrap();
+    assert_eq!(row.len(), 2);
+    assert_eq!(row["category"], "security");
+    let packet_id = row["packet_id"].as_str().unwrap().to_string();
+
+    // A whole field wins over a path into it; unknown names are left out.
+    let packet = server
+        .get_json(
+            &format!(
+                "/api/dashboard/events/{}?fields=event_type,data.url,data,nope",
+                packet_id
+            ),
+            200,
+        )
+        .await;
+    assert_eq!(packet["event_type"], "clickfix_detection");
+    assert_eq!(packet["data"]["url"], "https://evil.example.org/");
+    assert_eq!(packet["data"]["packet_id"], packet_id.as_str());
+    assert!(packet.get("client_id").is_none());
+    assert!(packet.get("nope").is_none());
+
+    let packet = server
+        .get_json(
+            &format!("/api/dashboard/events/{}?fields=data.url", packet_id),
+            200,
+        )
+        .await;
+    assert_eq!(
+        packet,
+        serde_json::json!({ "data": { "url": "https://evil.example.org/" } })
+    );
+
+    for bad in ["logs..url", "client_id,", ".url"] {
+        let resp = server
+            .get_json(&format!("/api/logs?fields={}", bad), 400)
+            .await;
+        assert_eq!(resp["code"], "bad_request");
+    }
+}
+
+#[actix_web::test]
+async fn archived_clients_are_hidden_unless_requested() {
+    let server = TestServer::simple();
+    seed(&server).await;
+
+    server
+        .post_json(
+            "/api/admin/clients/client-gen/archive",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+
+    let clients = server.get_json("/api/dashboard/clients", 200).await;
+    let clients = clients["clients"].as_array().unwrap();
+    assert!(!clients.contains(&"client-gen".into()));
+
+    let clients = server
+        .get_json("/api/dashboard/clients?include_archived=true", 200)
+        .await;
+    assert!(clients["clients"]
+        .as_array()
+        .unwrap()
+        .contains(&"client-gen".into()));
+}
+
+#[actix_web::test]
+async fn packet_id_links_ingest_to_the_dashboard() {
+    let server = TestServer::simple();
+    let event = extension_event(
+        "client-sec",
+        "chatgpt_file_upload",
+        serde_json::json!({
+            "file_name": "secrets.txt"
+        }),
+    );
+
+    let resp = server.post_json("/api/security", &event, 200).await;
+    let packet_id = resp["packet_id"].as_str().unwrap().to_string();
+    assert!(packet_id.starts_with("sec-"), "{}", packet_id);
+
+    let listed = server.get_json("/api/dashboard/events", 200).await;
+    assert_eq!(listed["events"][0]["packet_id"], packet_id.as_str());
+
+    let packet = server
+        .get_json(&format!("/api/dashboard/events/{}", packet_id), 200)
+        .await;
+    assert_eq!(packet["event_type"], "chatgpt_file_upload");
+    assert_eq!(packet["data"]["file_name"], "secrets.txt");
+    assert_eq!(packet["data"]["packet_id"], packet_id.as_str());
+
+    let missing = server
+        .get_json("/api/dashboard/events/sec-unknown", 404)
+        .await;
+    assert_eq!(missing["code"], "not_found");
+    assert_eq!(missing["packet_id"], "sec-unknown");
+}
+
+#[actix_web::test]
+async fn triage_status_filters_the_event_list() {
+    let server = TestServer::simple();
+    seed(&server).await;
+    let security = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let packet_id = security["events"][0]["packet_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(security["events"][0]["status"], "new");
+
+    let path = format!("/api/events/{}/status", packet_id);
+    let resp = server
+        .post_json(&path, &serde_json::json!({ "status": "escalated" }), 200)
+        .await;
+    assert_eq!(resp["previous_status"], "new");
+    let resp = server
+        .post_json(&path, &serde_json::json!({ "status": "closed" }), 400)
+        .await;
+    assert_eq!(resp["field"], "status");
+
+    let escalated = server
+        .get_json("/api/dashboard/events?status=escalated", 200)
+        .await;
+    assert_eq!(categories(&escalated), ["security"]);
+    let untriaged = server
+        .get_json("/api/dashboard/events?status=new", 200)
+        .await;
+    assert_eq!(categories(&untriaged), ["general", "javascript"]);
+    assert_eq!(server.get_json(&path, 200).await["status"], "escalated");
+}
+
+#[actix_web::test]
+async fn comments_thread_and_the_assignee_survives_status_changes() {
+    let server = TestServer::simple();
+    seed(&server).await;
+    let security = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let packet_id = security["events"][0]["packet_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let assign = serde_json::json!({ "assignee": "alice" });
+    server
+        .post_json(&format!("/api/events/{}/assignee", packet_id), &assign, 200)
+        .await;
+    let status = serde_json::json!({ "status": "acknowledged" });
+    server
+        .post_json(&format!("/api/events/{}/status", packet_id), &status, 200)
+        .await;
+
+    let path = format!("/api/events/{}/comments", packet_id);
+    let comment = serde_json::json!({ "body": "Looking into it", "author": "alice" });
+    let first = server.post_json(&path, &comment, 200).await;
+    let reply =
+        serde_json::json!({ "body": "Bob hit it too", "parent_id": first["comment"]["id"] });
+    server.post_json(&path, &reply, 200).await;
+    let orphan = serde_json::json!({ "body": "?", "parent_id": 999 });
+    assert_eq!(
+        server.post_json(&path, &orphan, 400).await["field"],
+        "parent_id"
+    );
+
+    let listing = server.get_json(&path, 200).await;
+    assert_eq!(listing["count"], 2);
+    assert_eq!(listing["assignee"], "alice");
+    assert_eq!(listing["comments"][0]["author"], "alice");
+    assert_eq!(
+        listing["comments"][0]["replies"][0]["body"],
+        "Bob hit it too"
+    );
+
+    let mine = server
+        .get_json("/api/dashboard/events?assignee=alice", 200)
+        .await;
+    assert_eq!(categories(&mine), ["security"]);
+    assert_eq!(mine["events"][0]["status"], "acknowledged");
+    assert_eq!(mine["events"][0]["comments"], 2);
+}
+
+#[actix_web::test]
+async fn escalating_opens_a_ticket_and_its_closure_is_synced_back() {
+    // A GitHub API that opens issue 7 and reports it closed once asked twice.
+    let checks = Arc::new(AtomicUsize::new(0));
+    let counter = checks.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let api = format!("http://{}", listener.local_addr().unwrap());
+    let html = format!("{}/acme/security/issues/7", api);
+    let github = actix_web::HttpServer::new(move || {
+        let counter = counter.clone();
+        let html = html.clone();
+        actix_web::App::new()
+            .route(
+                "/repos/acme/security/issues",
+                web::post().to(move |body: web::Json<serde_json::Value>| {
+                    let html = html.clone();
+                    async move {
+                        assert_eq!(body["labels"], serde_json::json!(["canigoin"]));
+                        web::Json(serde_json::json!({
+                            "number": 7, "html_url": html, "state": "open", "closed_at": null
+                        }))
+                    }
+                }),
+            )
+            .route(
+                "/repos/acme/security/issues/7",
+                web::get().to(move || {
+                    let closed = counter.fetch_add(1, Ordering::SeqCst) > 0;
+                    async move {
+                        web::Json(serde_json::json!({
+                            "number": 7, "html_url": "", "state": if closed { "closed" } else { "open" },
+                            "closed_at": if closed { Some("2026-10-01T12:00:00Z") } else { None }
+                        }))
+                    }
+                }),
+            )
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(github);
+
+    let tracker: TrackerConfig = serde_json::from_value(serde_json::json!({
+        "provider": "github", "api_url": api, "repo": "acme/security",
+        "labels": ["canigoin"], "token": "t"
+    }))
+    .unwrap();
+    let mut app = AppState::simple();
+    app.ticketing = web::Data::new(Ticketing::new(tracker).unwrap());
+    let (ticketing, triage) = (app.ticketing.clone(), app.triage.clone());
+    let server = TestServer::start(app);
+    seed(&server).await;
+    let security = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let packet_id = security["events"][0]["packet_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let path = format!("/api/events/{}/status", packet_id);
+    let escalate = serde_json::json!({ "status": "escalated", "note": "C2 beacon" });
+    let resp = server.post_json(&path, &escalate, 200).await;
+    assert_eq!(resp["ticket"]["provider"], "github");
+    assert_eq!(resp["ticket"]["key"], "7");
+    assert!(resp["ticket"]["url"]
+        .as_str()
+        .unwrap()
+        .ends_with("/issues/7"));
+    // Escalating again keeps the ticket rather than opening another.
+    let again = server.post_json(&path, &escalate, 200).await;
+    assert_eq!(again["ticket"]["created_at"], resp["ticket"]["created_at"]);
+
+    let report = tickets::sync_job(ticketing.clone(), triage.clone())
+        .await
+        .unwrap();
+    assert_eq!(report, "1 open tickets checked, 0 closed");
+    tickets::sync_job(ticketing, triage).await.unwrap();
+    let status = server.get_json(&path, 200).await;
+    assert_eq!(status["ticket"]["closed"], true);
+    assert_eq!(status["ticket"]["closed_at"], "2026-10-01T12:00:00Z");
+    let comments = server
+        .get_json(&format!("/api/events/{}/comments", packet_id), 200)
+        .await;
+    assert_eq!(comments["comments"][0]["author"], tickets::SYNC_AUTHOR);
+}
+
+#[actix_web::test]
+async fn the_training_set_is_labeled_sampled_and_scrubbed() {
+    let server = TestServer::simple();
+    seed(&server).await;
+    let phish = extension_event(
+        "client-sec",
+        "credential_phish",
+        serde_json::json!({
+            "url": "https://login.example.org/?user=alice&sid=42",
+            "message": "sent to alice@example.com from 10.1.2.3",
+            "password": "hunter2",
+            "nested": { "session_id": "session-client-sec" }
+        }),
+    );
+    let stored = server.post_json("/api/security", &phish, 200).await;
+    let dismissed = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["packet_id"].as_str().unwrap().to_string())
+        .find(|id| *id != stored["packet_id"])
+        .unwrap();
+    let fp = serde_json::json!({ "status": "false-positive" });
+    server
+        .post_json(&format!("/api/events/{}/status", dismissed), &fp, 200)
+        .await;
+
+    let lines = |body: String| -> Vec<serde_json::Value> {
+        body.lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    };
+    let resp = server
+        .get("/api/export/training-set?benign_rate=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-training-security"], "1");
+    assert_eq!(resp.headers()["x-training-benign"], "3");
+    let all = lines(resp.text().await.unwrap());
+    assert_eq!(all.len(), 4);
+    let find = |id: &serde_json::Value| all.iter().find(|l| &l["packet_id"] == id).unwrap();
+    let dismissed = find(&serde_json::json!(dismissed));
+    assert_eq!(dismissed["label"], "benign");
+    assert_eq!(dismissed["label_source"], "triage");
+
+    let phish = find(&stored["packet_id"]);
+    assert_eq!(phish["label"], "security");
+    assert_eq!(phish["label_source"], "category");
+    assert!(phish["client"].as_str().unwrap().starts_with("anon-"));
+    assert_eq!(phish["data"]["url"], "https://login.example.org/");
+    assert_eq!(phish["data"]["message"], "sent to [email] from [ip]");
+    assert_eq!(phish["data"]["password"], "[redacted]");
+    assert_eq!(phish["data"]["nested"]["session_id"], phish["session"]);
+    assert!(!phish.to_string().contains("client-sec"));
+
+    // Without benign events the sample is the security ones, and a seed
+    // picks the same ones every time.
+    let resp = server
+        .get("/api/export/training-set?benign_rate=0&security_rate=0.5&seed=s1")
+        .send()
+        .await
+        .unwrap();
+    let first: Vec<_> = lines(resp.text().await.unwrap())
+        .iter()
+        .map(|l| l["packet_id"].clone())
+        .collect();
+    let resp = server
+        .get("/api/export/training-set?benign_rate=0&security_rate=0.5&seed=s1")
+        .send()
+        .await
+        .unwrap();
+    let second: Vec<_> = lines(resp.text().await.unwrap())
+        .iter()
+        .map(|l| l["packet_id"].clone())
+        .collect();
+    assert_eq!(first, second);
+    let resp = server
+        .get("/api/export/training-set?benign_rate=2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn weekly_summary_credits_time_and_compares_with_last_week() {
+    let server = TestServer::simple();
+    let now = chrono::Utc::now();
+    let batch = |ago: chrono::Duration, urls: &[&str]| {
+        let mut b = common::log_batch("kid", urls);
+        b["timestamp"] = (now - ago).to_rfc3339().into();
+        b
+    };
+    let mins = chrono::Duration::minutes;
+    let this_week = [
+        batch(mins(30), &["https://www.youtube.com/watch?v=a"]),
+        batch(mins(28), &["https://github.com/ping2A"]),
+        batch(mins(27), &["https://www.tiktok.com/"]),
+    ];
+    let last_week = batch(
+        chrono::Duration::days(8),
+        &["https://www.youtube.com/watch?v=b"],
+    );
+    for b in this_week.iter().chain([&last_week]) {
+        server.post_json("/api/logs", b, 200).await;
+    }
+    let mut blocked = batch(mins(26), &["https://www.tiktok.com/"]);
+    blocked["logs"][0]["blocked"] = true.into();
+    server.post_json("/api/logs", &blocked, 200).await;
+
+    let summary = server
+        .get_json("/api/clients/kid/summary?period=week", 200)
+        .await;
+    assert_eq!(summary["period"], "week");
+    assert_eq!(summary["totals"]["requests"], 4);
+    assert_eq!(summary["totals"]["blocked"], 1);
+    assert_eq!(summary["previous"]["requests"], 1);
+    assert_eq!(summary["trends"]["requests_pct"], 300.0);
+    assert_eq!(summary["top_sites"][0]["domain"], "www.youtube.com");
+    assert_eq!(summary["top_sites"][0]["time_secs"], 120);
+    assert_eq!(summary["categories"][0]["category"], "video");
+    assert_eq!(summary["blocked_sites"][0]["domain"], "www.tiktok.com");
+    assert_eq!(summary["blocked_sites"][0]["attempts"], 1);
+
+    let bad = server
+        .get("/api/clients/kid/summary?period=year")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(common::expect_json(bad, 400).await["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn the_event_chain_detects_edited_and_deleted_security_events() {
+    let data = web::Data::new(SimpleState::new());
+    let mut app = AppState::new(Backend::Simple(data.clone()));
+    app.chain = web::Data::new(EventChain::new());
+    chain::install(app.chain.clone());
+    let event_chain = app.chain.clone();
+    let server = TestServer::start(app);
+
+    for i in 0..4 {
+        let event = extension_event(
+            "chain-client",
+            "clickfix_detected",
+            serde_json::json!({ "n": i }),
+        );
+        server.post_json("/api/security", &event, 200).await;
+    }
+    let report = server
+        .get_json("/api/admin/chain/verify?client_id=chain-client", 200)
+        .await;
+    assert_eq!(report["ok"], true, "{}", report);
+    assert_eq!(report["chains"][0]["events"], 4);
+    assert_eq!(report["chains"][0]["last_seq"], 4);
+
+    let mut events: Vec<_> = data
+        .get_extension_events()
+        .into_iter()
+        .filter(|e| e.client_id.as_deref() == Some("chain-client"))
+        .collect();
+    events[0].data["n"] = serde_json::json!(99);
+    events.remove(1);
+    events.pop();
+    let heads = event_chain.heads();
+    let report = chain::verify(&events, &heads, &[], Some("chain-client"));
+    let problems: Vec<_> = report.issues.iter().map(|i| (i.problem, i.seq)).collect();
+    assert_eq!(
+        problems,
+        [("modified", 1), ("missing", 2), ("missing_tail", 4)],
+        "{:?}",
+        report.issues
+    );
+}
+
+#[actix_web::test]
+async fn a_session_reported_by_two_clients_is_a_conflict() {
+    let state = web::Data::new(SimpleState::new());
+    server_events::attach_simple(state.clone());
+    let server = TestServer::start(AppState::new(Backend::Simple(state)));
+    for client in ["laptop", "clone"] {
+        let mut batch = log_batch(client, &["https://example.com/"]);
+        batch["session_id"] = "shared-session".into();
+        server.post_json("/api/logs", &batch, 200).await;
+    }
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("other", &["https://example.com/"]),
+            200,
+        )
+        .await;
+
+    let listed = server.get_json("/api/admin/session-conflicts", 200).await;
+    assert_eq!(listed["count"], 1, "{}", listed);
+    let conflict = &listed["conflicts"][0];
+    assert_eq!(conflict["session_id"], "shared-session");
+    assert_eq!(conflict["reasons"], serde_json::json!(["multiple_clients"]));
+    assert_eq!(
+        conflict["client_ids"],
+        serde_json::json!(["clone", "laptop"])
+    );
+
+    let mut events = serde_json::Value::Null;
+    for _ in 0..50 {
+        events = server
+            .get_json("/api/dashboard/events?filter=security", 200)
+            .await;
+        if events["events"].as_array().is_some_and(|e| !e.is_empty()) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let events: Vec<&serde_json::Value> = events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["event_type"] == "session_conflict")
+        .collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["client_id"], "clone");
+}
+
+#[actix_web::test]
+async fn jobs_report_their_runs_and_never_overlap() {
+    let weekdays = Schedule::parse("*/15 9-17 * * 1-5").unwrap();
+    let friday_evening = chrono::Utc.with_ymd_and_hms(2024, 3, 1, 17, 50, 0).unwrap();
+    let next = weekdays.next_after(friday_evening).unwrap();
+    assert_eq!(
+        next,
+        chrono::Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap()
+    );
+    assert!(Schedule::parse("0 0 30 2 *")
+        .unwrap()
+        .next_after(friday_evening)
+        .is_none());
+    assert!(Schedule::parse("61 * * * *").is_err());
+
+    let app = AppState::simple();
+    let release = Arc::new(tokio::sync::Notify::new());
+    let (gate, runs) = (release.clone(), Arc::new(AtomicU64::new(0)));
+    app.scheduler.register(
+        "slow_report",
+        Schedule::manual(),
+        JobOptions::default(),
+        move || {
+            let (gate, runs) = (gate.clone(), runs.clone());
+            async move {
+                gate.notified().await;
+                Ok(format!("run {}", runs.fetch_add(1, Ordering::SeqCst) + 1))
+            }
+        },
+    );
+    let server = TestServer::start(app);
+
+    let started = server
+        .post_json(
+            "/api/admin/jobs/slow_report/run",
+            &serde_json::json!({}),
+            202,
+        )
+        .await;
+    assert_eq!(started["job"]["schedule"], "manual");
+    server
+        .post_json(
+            "/api/admin/jobs/slow_report/run",
+            &serde_json::json!({}),
+            409,
+        )
+        .await;
+    server
+        .post_json("/api/admin/jobs/missing/run", &serde_json::json!({}), 404)
+        .await;
+    release.notify_one();
+
+    let mut job = serde_json::Value::Null;
+    for _ in 0..50 {
+        job = server.get_json("/api/admin/jobs", 200).await["jobs"][0].clone();
+        if job["runs"] == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["name"], "slow_report");
+    assert_eq!(job["running"], false);
+    assert_eq!(job["last_outcome"], "ok");
+    assert_eq!(job["last_trigger"], "manual");
+    assert_eq!(job["last_message"], "run 1");
+}
+
+#[actix_web::test]
+async fn long_jobs_report_progress_and_stop_when_cancelled() {
+    let app = AppState::simple();
+    app.scheduler.register_cancellable(
+        "reindex",
+        Schedule::manual(),
+        JobOptions::default(),
+        |control| async move {
+            for done in 0.. {
+                if control.is_cancelled() {
+                    return Err(format!("stopped after {}", done));
+                }
+                control.progress(done, Some(1_000_000));
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            Ok(String::new())
+        },
+    );
+    app.scheduler.register(
+        "report",
+        Schedule::manual(),
+        JobOptions::default(),
+        || async { Ok(String::new()) },
+    );
+    let server = TestServer::start(app);
+
+    server
+        .post_json(
+            "/api/admin/jobs/reindex/cancel",
+            &serde_json::json!({}),
+            409,
+        )
+        .await;
+    server
+        .post_json("/api/admin/jobs/report/cancel", &serde_json::json!({}), 409)
+        .await;
+    server
+        .post_json(
+            "/api/admin/jobs/missing/cancel",
+            &serde_json::json!({}),
+            404,
+        )
+        .await;
+    server
+        .post_json("/api/admin/jobs/reindex/run", &serde_json::json!({}), 202)
+        .await;
+    let mut job = serde_json::Value::Null;
+    for _ in 0..50 {
+        job = server.get_json("/api/admin/jobs", 200).await["jobs"][0].clone();
+        if job["progress"]["done"].as_u64() > Some(2) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["name"], "reindex");
+    assert_eq!(job["running"], true);
+    assert_eq!(job["progress"]["total"], 1_000_000);
+
+    let cancelled = server
+        .post_json(
+            "/api/admin/jobs/reindex/cancel",
+            &serde_json::json!({}),
+            202,
+        )
+        .await;
+    assert_eq!(cancelled["job"]["cancelling"], true);
+    for _ in 0..50 {
+        job = server.get_json("/api/admin/jobs", 200).await["jobs"][0].clone();
+        if job["running"] == false {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["last_outcome"], "cancelled", "{}", job);
+    assert_eq!(job["cancelled"], 1);
+    assert_eq!(job["failures"], 0);
+    assert_eq!(job["consecutive_failures"], 0);
+    assert!(job["last_message"]
+        .as_str()
+        .unwrap()
+        .starts_with("stopped after"));
+    let history = server
+        .get_json("/api/admin/jobs/reindex/history", 200)
+        .await;
+    assert_eq!(history["runs"][0]["outcome"], "cancelled");
+    assert!(history["runs"][0]["progress"]["done"].as_u64() > Some(2));
+}
+
+#[actix_web::test]
+async fn failed_jobs_are_retried_and_their_history_survives_a_restart() {
+    let path = std::env::temp_dir().join(format!("canigoin-jobs-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let options = JobOptions {
+        retries: 2,
+        backoff: std::time::Duration::from_millis(10),
+        critical: true,
+    };
+    let attempts = Arc::new(AtomicU64::new(0));
+    let counted = attempts.clone();
+    let mut app = AppState::simple();
+    app.scheduler = web::Data::new(Scheduler::new().with_history(&path).unwrap());
+    app.scheduler
+        .register("purge", Schedule::manual(), options, move || {
+            let attempt = counted.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                match attempt {
+                    1 => Err("database unavailable".to_string()),
+                    2 => Ok("purged 10 rows".to_string()),
+                    _ => Err("disk full".to_string()),
+                }
+            }
+        });
+    let server = TestServer::start(app);
+
+    let mut history = serde_json::Value::Null;
+    for expected in [1, 2] {
+        server
+            .post_json("/api/admin/jobs/purge/run", &serde_json::json!({}), 202)
+            .await;
+        for _ in 0..100 {
+            history = server.get_json("/api/admin/jobs/purge/history", 200).await;
+            if history["count"] == expected {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+    let runs = &history["runs"];
+    assert_eq!(runs[0]["outcome"], "failed", "{}", history);
+    assert_eq!(runs[0]["attempts"], 3);
+    assert_eq!(runs[0]["error"], "disk full");
+    assert_eq!(runs[1]["outcome"], "ok");
+    assert_eq!(runs[1]["attempts"], 2);
+    assert_eq!(runs[1]["message"], "purged 10 rows");
+    server
+        .get_json("/api/admin/jobs/missing/history", 404)
+        .await;
+
+    let restarted = Scheduler::new().with_history(&path).unwrap();
+    restarted.register("purge", Schedule::manual(), options, || async {
+        Ok(String::new())
+    });
+    let status = restarted.status("purge").unwrap();
+    assert_eq!(status.consecutive_failures, 1);
+    assert_eq!(status.last_message.as_deref(), Some("disk full"));
+    assert_eq!(restarted.history("purge", 10).unwrap().len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[actix_web::test]
+async fn stats_estimate_distinct_urls_domains_sessions_and_clients() {
+    let mut sketch = HyperLogLog::new();
+    for i in 0..200_000 {
+        sketch.insert(&format!("https://site-{}.example.com/", i % 100_000));
+    }
+    let error = (sketch.count() as f64 - 100_000.0).abs() / 100_000.0;
+    assert!(error < 3.0 * distinct::STANDARD_ERROR, "{}", sketch.count());
+
+    let server = TestServer::simple();
+    for client in ["laptop", "phone"] {
+        let batch = log_batch(
+            client,
+            &[
+                "https://example.com/",
+                "https://example.com/a",
+                "https://news.example.org/",
+            ],
+        );
+        server.post_json("/api/logs", &batch, 200).await;
+    }
+    let stats = server.get_json("/api/stats", 200).await;
+    let distinct = &stats["distinct"];
+    assert_eq!(distinct["approximate"], true);
+    assert_eq!(distinct["source"], "memory");
+    assert_eq!(distinct["total"]["urls"], 3, "{}", distinct);
+    assert_eq!(distinct["total"]["domains"], 2);
+    assert_eq!(distinct["total"]["sessions"], 2);
+    assert_eq!(distinct["total"]["clients"], 2);
+    assert_eq!(distinct["today"], distinct["total"]);
+    assert!(distinct["note"].as_str().unwrap().contains("0.8%"));
+}
+
+#[actix_web::test]
+async fn stats_are_cached_until_enough_new_logs_arrive() {
+    let mut app = AppState::simple();
+    app.stats_cache = web::Data::new(ResponseCache::new(std::time::Duration::from_secs(60), 3));
+    let server = TestServer::start(app);
+    let cache = |resp: &reqwest::Response| resp.headers()["x-cache"].to_str().unwrap().to_string();
+    let batch = log_batch("laptop", &["https://example.com/", "https://example.org/"]);
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let first = server.get("/api/stats").send().await.unwrap();
+    assert_eq!(cache(&first), "MISS");
+    let first: serde_json::Value = first.json().await.unwrap();
+    let before = server.get("/api/stats").send().await.unwrap();
+    assert_eq!(cache(&before), "HIT");
+    assert_eq!(before.json::<serde_json::Value>().await.unwrap(), first);
+
+    // Two new logs stay under the threshold of three.
+    server.post_json("/api/logs", &batch, 200).await;
+    let stale = server.get("/api/stats").send().await.unwrap();
+    assert_eq!(cache(&stale), "HIT");
+    server.post_json("/api/logs", &batch, 200).await;
+    let fresh = server.get("/api/stats").send().await.unwrap();
+    assert_eq!(cache(&fresh), "MISS");
+    let fresh: serde_json::Value = fresh.json().await.unwrap();
+    assert_ne!(fresh["domains"], first["domains"]);
+    let other_limit = server.get("/api/stats?limit=1").send().await.unwrap();
+    assert_eq!(cache(&other_limit), "MISS");
+
+    let summary = server
+        .get("/api/clients/laptop/summary")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(cache(&summary), "MISS");
+    let cleared = expect_json(
+        server
+            .delete("/api/admin/stats-cache")
+            .send()
+            .await
+            .unwrap(),
+        200,
+    )
+    .await;
+    assert_eq!(cleared["cleared"], 3);
+    let after = server.get("/api/stats").send().await.unwrap();
+    assert_eq!(cache(&after), "MISS");
+}
+
+#[actix_web::test]
+async fn disabled_features_are_refused_and_can_be_switched_back_at_runtime() {
+    let file = std::env::temp_dir().join(format!("canigoin-features-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let mut app = AppState::simple();
+    let flags = FeatureFlags::new(&[Feature::Dashboard, Feature::Export])
+        .with_file(&file)
+        .unwrap();
+    app.features = web::Data::new(flags);
+    let server = TestServer::start(app);
+
+    let page = server.get("/").send().await.unwrap();
+    assert_eq!(page.status(), 404);
+    assert!(page.text().await.unwrap().is_empty());
+    let events = server.get("/api/dashboard/events").send().await.unwrap();
+    assert_eq!(events.status(), 404);
+    let refused = server.get_json("/api/logs", 403).await;
+    assert_eq!(refused["code"], "feature_disabled");
+    assert_eq!(refused["feature"], "export");
+    // Ingest on the same path isn't export.
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("flags", &["https://example.com/"]),
+            200,
+        )
+        .await;
+
+    let resp = server
+        .put("/api/admin/features/export")
+        .json(&serde_json::json!({"enabled": true}))
+        .send()
+        .await
+        .unwrap();
+    let state = expect_json(resp, 200).await;
+    assert_eq!(state["persisted"], true);
+    let export = &state["features"].as_array().unwrap()[1];
+    assert_eq!(export["feature"], "export");
+    assert_eq!(export["enabled"], true);
+    assert_eq!(export["default"], false);
+    assert_eq!(export["overridden"], true);
+    assert_eq!(
+        server
+            .get_json("/api/logs", 200)
+            .await
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let resp = server
+        .put("/api/admin/features/uploads")
+        .json(&serde_json::json!({"enabled": false}))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let refused = server.get_json("/api/security/uploads/sec-1", 403).await;
+    assert_eq!(refused["feature"], "uploads");
+    let resp = server
+        .put("/api/admin/features/nope")
+        .json(&serde_json::json!({"enabled": true}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        expect_json(resp, 404).await["features"][5],
+        "anomaly_detection"
+    );
+
+    // What was changed at runtime survives a restart; a reset doesn't.
+    let reloaded = FeatureFlags::new(&[Feature::Dashboard, Feature::Export])
+        .with_file(&file)
+        .unwrap();
+    assert!(reloaded.is_enabled(Feature::Export));
+    assert!(!reloaded.is_enabled(Feature::Uploads));
+    let resp = server
+        .delete("/api/admin/features/export")
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    server.get_json("/api/logs", 403).await;
+    let reloaded = FeatureFlags::new(&[Feature::Export])
+        .with_file(&file)
+        .unwrap();
+    assert!(!reloaded.is_enabled(Feature::Export));
+    let _ = std::fs::remove_file(&file);
+}
+
+#[actix_web::test]
+async fn seeding_fills_an_empty_server_with_repeatable_demo_data() {
+    let server = TestServer::simple();
+    let resp = server
+        .post("/api/admin/seed?profile=small&seed=7")
+        .send()
+        .await
+        .unwrap();
+    let first = expect_json(resp, 200).await;
+    assert_eq!(first["profile"], "small");
+    assert_eq!(first["clients"], 3);
+    assert!(first["requests"].as_u64().unwrap() > 0);
+    assert!(first["events"].as_u64().unwrap() > 0);
+
+    let logs = server.get_json("/api/logs", 200).await;
+    let logs = logs.as_array().unwrap();
+    let requests: usize = logs
+        .iter()
+        .map(|l| l["logs"].as_array().unwrap().len())
+        .sum();
+    assert_eq!(requests as u64, first["requests"].as_u64().unwrap());
+    assert!(logs
+        .iter()
+        .all(|l| l["client_id"].as_str().unwrap().starts_with("demo-")));
+    let events = server.get_json("/api/dashboard/events", 200).await;
+    assert_eq!(
+        events["events"].as_array().unwrap().len() as u64,
+        first["events"].as_u64().unwrap()
+    );
+
+    // The same seed within the hour is the same data, which is already there.
+    let resp = server
+        .post("/api/admin/seed?profile=small&seed=7")
+        .send()
+        .await
+        .unwrap();
+    let again = expect_json(resp, 200).await;
+    if again["to"] == first["to"] {
+        assert_eq!(again["requests"], 0);
+        assert_eq!(again["events"], 0);
+    }
+    let resp = server
+        .post("/api/admin/seed?profile=small&seed=8")
+        .send()
+        .await
+        .unwrap();
+    let other = expect_json(resp, 200).await;
+    assert!(other["requests"].as_u64().unwrap() > 0);
+
+    let resp = server
+        .post("/api/admin/seed?profile=huge")
+        .send()
+        .await
+        .unwrap();
+    let bad = expect_json(resp, 400).await;
+    assert_eq!(bad["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn client_names_from_the_api_and_a_csv_sync_show_up_in_dashboard_payloads() {
+    let app = AppState::simple();
+    let names = app.client_names.clone();
+    let server = TestServer::start(app);
+    for client in ["laptop-1", "laptop-2", "laptop-3"] {
+        let event = extension_event(
+            client,
+            "javascript_execution",
+            serde_json::json!({"url": "https://a.example/"}),
+        );
+        server.post_json("/api/extensions", &event, 200).await;
+    }
+
+    let resp = server
+        .put("/api/admin/clients/laptop-1/name")
+        .json(
+            &serde_json::json!({"name": "Dana's laptop", "owner": "dana", "department": "Finance"}),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 200).await["client"]["source"], "manual");
+    let resp = server
+        .put("/api/admin/clients/laptop-2/name")
+        .json(&serde_json::json!({"name": " "}))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 400).await;
+
+    // The sync replaces what it synced before but leaves API names alone.
+    let csv = "client_id,hostname,owner,department\nlaptop-1,fin-01,dana,Finance\nlaptop-2,eng-07,sam,Engineering\n,orphan,,\n";
+    let (synced, skipped) = client_names::parse_csv(csv, chrono::Utc::now()).unwrap();
+    assert_eq!(skipped, 1);
+    let counts = names.sync(Source::Csv, synced).unwrap();
+    assert_eq!((counts.synced, counts.overridden), (1, 1));
+    assert!(client_names::parse_csv("id,name\nx,y\n", chrono::Utc::now()).is_err());
+
+    let events = server.get_json("/api/dashboard/events", 200).await;
+    let name_of = |client: &str| {
+        events["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["client_id"] == client)
+            .unwrap()["client_name"]
+            .clone()
+    };
+    assert_eq!(name_of("laptop-1"), "Dana's laptop");
+    assert_eq!(name_of("laptop-2"), "eng-07");
+    assert!(name_of("laptop-3").is_null());
+    let clients = server.get_json("/api/dashboard/clients", 200).await;
+    assert_eq!(clients["names"]["laptop-2"], "eng-07");
+    assert!(clients["names"].get("laptop-3").is_none());
+    let row = events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["client_id"] == "laptop-1")
+        .unwrap();
+    let packet_id = row["packet_id"].as_str().unwrap().to_string();
+    let packet = server
+        .get_json(
+            &format!(
+                "/api/dashboard/events/{}?fields=client_id,client_name",
+                packet_id
+            ),
+            200,
+        )
+        .await;
+    assert_eq!(packet["client_name"], "Dana's laptop");
+    let export = server
+        .get("/api/dashboard/events/export")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(export
+        .lines()
+        .next()
+        .unwrap()
+        .ends_with(",user_agent,client_name"));
+    assert!(export.contains("Dana's laptop"));
+
+    let listed = server.get_json("/api/clients/names", 200).await;
+    assert_eq!(listed["clients"].as_array().unwrap().len(), 2);
+    let resp = server
+        .delete("/api/admin/clients/laptop-1/name")
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let resp = server
+        .delete("/api/admin/clients/laptop-1/name")
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 404).await;
+    let counts = names
+        .sync(
+            Source::Csv,
+            client_names::parse_csv(csv, chrono::Utc::now()).unwrap().0,
+        )
+        .unwrap();
+    assert_eq!(counts.synced, 2);
+    assert_eq!(names.resolve("laptop-1").as_deref(), Some("fin-01"));
+
+    // With a file, names survive a restart.
+    let file = std::env::temp_dir().join(format!("client-names-{}.json", std::process::id()));
+    let stored = ClientNames::load(&file).unwrap();
+    stored.set(names.get("laptop-2").unwrap()).unwrap();
+    assert_eq!(
+        ClientNames::load(&file)
+            .unwrap()
+            .resolve("laptop-2")
+            .as_deref(),
+        Some("eng-07")
+    );
+    let _ = std::fs::remove_file(&file);
+}
+
+#[actix_web::test]
+async fn the_tui_viewer_shows_logs_security_events_and_counters_from_a_server() {
+    let server = TestServer::simple();
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("laptop-1", &["https://news.example/today"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("laptop-2", &["https://other.example/"]),
+            200,
+        )
+        .await;
+    let event = extension_event(
+        "laptop-1",
+        "clickfix_detection",
+        serde_json::json!({ "url": "https://evil.example.org/" }),
+    );
+    server.post_json("/api/security", &event, 200).await;
+
+    let source = tui::Source::new(&server.url("/"), None, Some("laptop-1".to_string()));
+    let mut viewer = tui::Viewer::new("test");
+    for update in source.recent_logs(10).await.unwrap() {
+        viewer.apply(update);
+    }
+    // Polled twice: an event already shown isn't listed again.
+    for update in source.poll().await.into_iter().chain(source.poll().await) {
+        viewer.apply(update);
+    }
+
+    let screen = viewer.render(120, 20);
+    assert!(screen.contains("https://news.example/today"));
+    assert!(!screen.contains("https://other.example/"));
+    assert_eq!(screen.matches("clickfix_detection").count(), 1);
+    assert!(screen.contains("evil.example.org"));
+    assert!(screen.contains("batches 2  logs 2  blocked 0"));
+    assert!(screen.contains("── Security events (1)"));
+
+    // Clients choose their names; escapes in them don't reach the terminal.
+    viewer.apply(tui::Update::Event(serde_json::json!({
+        "packet_id": "pkt-escape",
+        "event_type": "clipboard_read",
+        "client_name": "\u{1b}]0;pwned\u{7}mallory\u{1b}[2J",
+    })));
+    let screen = viewer.render(120, 20);
+    assert!(screen.contains("]0;pwnedmallory[2J"), "{}", screen);
+    assert!(!screen.chars().any(|c| c.is_control() && c != '\n'));
+
+    viewer.apply(tui::Update::Key(tui::Key::Char('c')));
+    assert!(viewer.render(120, 20).contains("── Logs (0)"));
+    viewer.apply(tui::Update::Key(tui::Key::Char('q')));
+    assert!(viewer.quit);
+}
+
+#[actix_web::test]
+async fn flagged_domains_get_a_certificate_transparency_summary_on_their_case() {
+    // crt.sh with one certificate (logged twice, as a precertificate and a
+    // certificate) issued three days ago.
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let counter = lookups.clone();
+    let issued = (chrono::Utc::now() - chrono::Duration::days(3))
+        .format("%Y-%m-%dT%H:%M:%S")
+        .to_string();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let crtsh = format!("http://{}/", listener.local_addr().unwrap());
+    let fake = actix_web::HttpServer::new(move || {
+        let counter = counter.clone();
+        let issued = issued.clone();
+        actix_web::App::new().route(
+            "/",
+            web::get().to(
+                move |query: web::Query<std::collections::HashMap<String, String>>| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let row = serde_json::json!({
+                        "issuer_name": "C=US, O=Let's Encrypt, CN=R11",
+                        "name_value": "xn--pple-43d.com\n*.xn--pple-43d.com",
+                        "not_before": issued,
+                        "serial_number": "04a1",
+                    });
+                    async move {
+                        assert_eq!(query["q"], "xn--pple-43d.com");
+                        assert_eq!(query["output"], "json");
+                        web::Json(serde_json::json!([row, row]))
+                    }
+                },
+            ),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(fake);
+
+    let app = AppState::simple();
+    let ct = ct::CtLookups::new(
+        &crtsh,
+        &["homograph_domain".to_string()],
+        app.triage.clone(),
+    );
+    ct::install(web::Data::new(ct.unwrap()));
+    let server = TestServer::start(app);
+    for client in ["laptop-1", "laptop-2"] {
+        let batch = log_batch(
+            client,
+            &["https://xn--pple-43d.com/login", "https://apple.com/"],
+        );
+        server.post_json("/api/logs", &batch, 200).await;
+    }
+    let events = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let events = events["events"].as_array().unwrap().clone();
+    assert_eq!(events.len(), 2);
+
+    for event in &events {
+        let path = format!(
+            "/api/events/{}/comments",
+            event["packet_id"].as_str().unwrap()
+        );
+        let mut comments = serde_json::Value::Null;
+        for _ in 0..100 {
+            comments = server.get_json(&path, 200).await;
+            if comments["count"] == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(comments["count"], 1, "{}", comments);
+        assert_eq!(comments["comments"][0]["author"], ct::AUTHOR);
+        let body = comments["comments"][0]["body"].as_str().unwrap();
+        assert!(
+            body.contains("1 certificate logged for xn--pple-43d.com"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("Issuers: Let's Encrypt. 2 names including a wildcard."),
+            "{}",
+            body
+        );
+        assert!(body.contains("only 3 days old"), "{}", body);
+    }
+    // The second event was answered from the cache or joined the first lookup.
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn every_answer_carries_the_security_headers() {
+    let server = TestServer::start(AppState::simple());
+    let page = server.get("/").send().await.unwrap();
+    assert_eq!(page.status(), 200);
+    let headers = page.headers();
+    assert_eq!(
+        headers["content-security-policy"],
+        security_headers::DASHBOARD_CSP
+    );
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert_eq!(headers["cross-origin-opener-policy"], "same-origin");
+    assert!(headers.contains_key("permissions-policy"));
+    assert!(!headers.contains_key("strict-transport-security"));
+    let csp = headers["content-security-policy"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let script_src = csp
+        .split(';')
+        .find(|d| d.trim().starts_with("script-src"))
+        .unwrap();
+    assert!(!script_src.contains("'unsafe-inline'"), "{}", csp);
+    // So the page itself carries no inline code: only served scripts and no
+    // event handler attributes.
+    let html = page.text().await.unwrap();
+    assert!(!html.contains("<script>"));
+    assert!(!html.contains("onclick="));
+    for script in ["/dashboard.js", "/dashboard-theme.js"] {
+        assert!(html.contains(&format!("<script src=\"{}\"></script>", script)));
+        let resp = server.get(script).send().await.unwrap();
+        assert_eq!(resp.status(), 200, "{}", script);
+        assert!(resp.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/javascript"));
+        assert!(!resp.text().await.unwrap().contains("onclick="));
+    }
+
+    for path in ["/api/stats", "/api/no-such-endpoint"] {
+        let resp = server.get(path).send().await.unwrap();
+        assert_eq!(
+            resp.headers()["content-security-policy"],
+            security_headers::API_CSP,
+            "{}",
+            path
+        );
+        assert_eq!(
+            resp.headers()["x-content-type-options"],
+            "nosniff",
+            "{}",
+            path
+        );
+    }
+
+    let config: SecurityHeadersConfig = serde_json::from_value(serde_json::json!({
+        "dashboard_csp": "default-src 'self'",
+        "csp_report_only": true,
+        "referrer_policy": "",
+        "hsts_max_age": 31536000,
+        "extra": {"X-Robots-Tag": "noindex"}
+    }))
+    .unwrap();
+    let mut app = AppState::simple();
+    app.security_headers = web::Data::new(SecurityHeaders::new(config).unwrap());
+    let server = TestServer::start(app);
+    let page = server.get("/").send().await.unwrap();
+    let headers = page.headers();
+    assert!(!headers.contains_key("content-security-policy"));
+    assert_eq!(
+        headers["content-security-policy-report-only"],
+        "default-src 'self'"
+    );
+    assert!(!headers.contains_key("referrer-policy"));
+    assert_eq!(headers["strict-transport-security"], "max-age=31536000");
+    assert_eq!(headers["x-robots-tag"], "noindex");
+    assert_eq!(headers["x-frame-options"], "DENY");
+
+    let off: SecurityHeadersConfig =
+        serde_json::from_value(serde_json::json!({"enabled": false})).unwrap();
+    let mut app = AppState::simple();
+    app.security_headers = web::Data::new(SecurityHeaders::new(off).unwrap());
+    let server = TestServer::start(app);
+    let resp = server.get("/api/stats").send().await.unwrap();
+    assert!(!resp.headers().contains_key("content-security-policy"));
+    assert!(!resp.headers().contains_key("x-content-type-options"));
+
+    let bad: SecurityHeadersConfig =
+        serde_json::from_value(serde_json::json!({"extra": {"bad header": "x"}})).unwrap();
+    assert!(SecurityHeaders::new(bad).is_err());
+    assert!(
+        serde_json::from_value::<SecurityHeadersConfig>(serde_json::json!({"csp": "x"})).is_err()
+    );
+}
+
+#[actix_web::test]
+async fn observed_domains_are_capped_by_dropping_the_least_recently_seen() {
+    use network_logger_server::simple::MAX_DOMAINS;
+    use network_logger_server::types::LogEntry;
+
+    let state = SimpleState::new();
+    let add = |urls: Vec<String>| {
+        let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+        let entry: LogEntry = serde_json::from_value(log_batch("domains-client", &urls)).unwrap();
+        state.add_log(entry);
+    };
+    add(vec![
+        "https://old.example/".into(),
+        "https://busy.example/".into(),
+    ]);
+    add((0..MAX_DOMAINS - 2)
+        .map(|i| format!("https://d{}.example/", i))
+        .collect());
+    add(vec!["https://busy.example/again".into()]);
+    assert_eq!(state.get_domains(None).len(), MAX_DOMAINS);
+
+    add(vec!["https://new.example/".into()]);
+    let domains = state.get_domains(None);
+    assert_eq!(domains.len(), MAX_DOMAINS - MAX_DOMAINS / 10 + 1);
+    let seen = |name: &str| domains.iter().find(|d| d.domain == name);
+    assert!(seen("old.example").is_none());
+    assert_eq!(seen("busy.example").unwrap().request_count, 2);
+    assert!(seen("new.example").is_some());
+}
+
+#[actix_web::test]
+async fn every_dashboard_pane_loads_over_a_login_session_when_auth_is_on() {
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(network_logger_server::auth::AdminAuth::new(Some(
+        "s3cret".into(),
+    )));
+    let server = TestServer::start(app);
+    seed(&server).await;
+
+    let panes = [
+        "/api/dashboard/events?filter=all",
+        "/api/logs",
+        "/api/dashboard/clients",
+        "/api/errors",
+    ];
+    for pane in panes {
+        let resp = server.get(pane).send().await.unwrap();
+        expect_json(resp, 401).await;
+    }
+
+    let resp = server
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "token": "s3cret" }))
+        .send()
+        .await
+        .unwrap();
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().to_string();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    expect_json(resp, 200).await;
+    for pane in panes {
+        let resp = server
+            .get(pane)
+            .header("cookie", &cookie)
+            .header("referer", server.url("/"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200, "{}", pane);
+    }
+
+    // The page's script sends the cookie with every read and signs in on a
+    // 401, rather than calling the API bare.
+    let page = server
+        .get("/dashboard.js")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    for pane in panes.iter().chain(&["/api/blocklist"]) {
+        let path = pane.split('?').next().unwrap();
+        assert!(
+            page.contains(&format!("adminFetch(API + '{}", path)),
+            "{} is fetched without the session",
+            pane
+        );
+    }
+    assert!(page.contains("credentials: 'same-origin'"));
+}
+
+#[actix_web::test]
+async fn annotation_notes_with_quotes_render_inert_on_the_dashboard() {
+    let server = TestServer::start(AppState::simple());
+    seed(&server).await;
+    let note = "see \"ticket\" 'x' onmouseover=alert(1) <img src=x>";
+    server
+        .post_json(
+            "/api/clients/client-js/annotations",
+            &serde_json::json!({ "note": note }),
+            200,
+        )
+        .await;
+
+    let events = server
+        .get_json("/api/dashboard/events?filter=all", 200)
+        .await;
+    let annotated = events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["client_id"] == "client-js")
+        .unwrap();
+    assert_eq!(annotated["annotations"][0]["note"], note);
+
+    // The badge puts the note in a quoted title attribute through
+    // escapeHtml; applying the page's own escape table must leave nothing
+    // that can close the attribute or open a tag.
+    let page = server
+        .get("/dashboard.js")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("'<span class=\"badge badge-note\" title=\"' + escapeHtml("));
+    let table = page
+        .lines()
+        .find(|l| l.contains("const HTML_ESCAPES"))
+        .expect("escape table");
+    let mut escapes = std::collections::HashMap::new();
+    for (from, to) in [
+        ("&", "&amp;"),
+        ("<", "&lt;"),
+        (">", "&gt;"),
+        ("\"", "&quot;"),
+        ("'", "&#39;"),
+    ] {
+        let quoted = if from == "'" {
+            format!("\"{}\"", from)
+        } else {
+            format!("'{}'", from)
+        };
+        assert!(
+            table.contains(&format!("{}: '{}'", quoted, to)),
+            "escapeHtml must map {} to {}",
+            from,
+            to
+        );
+        escapes.insert(from.chars().next().unwrap(), to);
+    }
+    let rendered: String = format!("client client-js: {}", note)
+        .chars()
+        .map(|c| escapes.get(&c).map_or(c.to_string(), |to| to.to_string()))
+        .collect();
+    assert!(!rendered.contains(['"', '\'', '<', '>']), "{}", rendered);
+}
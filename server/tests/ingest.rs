@@ -0,0 +1,1901 @@
+mod common;
+
+use actix_web::web;
+use clap::Parser;
+use common::{expect_json, extension_event, gzip, log_batch, TestServer};
+use network_logger_server::auth::AdminAuth;
+use network_logger_server::backup::Backups;
+use network_logger_server::batch::{BatchLimit, OversizedBatch};
+use network_logger_server::beaconing::BeaconDetector;
+use network_logger_server::capture::Capture;
+use network_logger_server::chaos::Chaos;
+use network_logger_server::cli::Args;
+use network_logger_server::enrollment::Enrollment;
+use network_logger_server::event_data::{EventDataLimit, OversizedEventData};
+use network_logger_server::exfil::ExfilMonitor;
+use network_logger_server::handlers::common::MAX_DECOMPRESSED_BYTES;
+use network_logger_server::ldap::LdapDirectory;
+use network_logger_server::maintenance::Maintenance;
+use network_logger_server::metering::{Metering, OrgsConfig};
+use network_logger_server::request_types::RequestTypeFilter;
+use network_logger_server::url_normalize::UrlNormalizer;
+use network_logger_server::{asn, lake, worm, AppState};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[actix_web::test]
+async fn plain_and_gzip_batches_are_stored() {
+    let server = TestServer::simple();
+
+    let plain = log_batch(
+        "client-a",
+        &["https://example.com/", "https://example.com/app.js"],
+    );
+    let resp = server.post_json("/api/logs", &plain, 200).await;
+    assert_eq!(resp["success"], true);
+    assert_eq!(resp["logs_count"], 2);
+
+    let compressed = log_batch("client-b", &["https://example.org/"]);
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .body(gzip(compressed.to_string().as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    let resp = expect_json(resp, 200).await;
+    assert_eq!(resp["logs_count"], 1);
+
+    let stored = server.get_json("/api/logs", 200).await;
+    let clients: Vec<&str> = stored
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|e| e["client_id"].as_str())
+        .collect();
+    assert_eq!(clients, ["client-a", "client-b"]);
+}
+
+#[actix_web::test]
+async fn gzip_without_the_header_is_detected() {
+    let server = TestServer::simple();
+    let batch = log_batch("client-sniffed", &["https://example.net/"]);
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .body(gzip(batch.to_string().as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 200).await["logs_count"], 1);
+
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored[0]["client_id"], "client-sniffed");
+}
+
+#[actix_web::test]
+async fn gzip_bodies_that_expand_too_far_are_refused() {
+    let server = TestServer::simple();
+    let padding = "a".repeat(MAX_DECOMPRESSED_BYTES + 1);
+    let event = extension_event(
+        "client-bomb",
+        "extension_installed",
+        serde_json::json!({ "id": padding }),
+    );
+    let body = gzip(event.to_string().as_bytes());
+    assert!(body.len() < 256 * 1024);
+    for path in ["/api/extensions", "/api/security"] {
+        let resp = server
+            .post(path)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .unwrap();
+        let err = expect_json(resp, 413).await;
+        assert_eq!(err["code"], "payload_too_large", "{}", path);
+        assert_eq!(err["max_decompressed_bytes"], MAX_DECOMPRESSED_BYTES);
+    }
+}
+
+#[actix_web::test]
+async fn gzip_log_batches_are_capped_and_must_gunzip() {
+    let server = TestServer::simple();
+    let url = format!("https://a.example/{}", "a".repeat(MAX_DECOMPRESSED_BYTES));
+    let bomb = gzip(log_batch("client-bomb", &[&url]).to_string().as_bytes());
+    assert!(bomb.len() < 256 * 1024);
+    let resp = server.post("/api/logs").body(bomb).send().await.unwrap();
+    let err = expect_json(resp, 413).await;
+    assert_eq!(err["max_decompressed_bytes"], MAX_DECOMPRESSED_BYTES);
+
+    // Cut off mid-stream: refused, not read as the raw gzip bytes.
+    let body = gzip(
+        log_batch("client-cut", &["https://a.example/"])
+            .to_string()
+            .as_bytes(),
+    );
+    let resp = server
+        .post("/api/logs")
+        .body(body[..body.len() / 2].to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["code"], "bad_request");
+    let logs = server.get_json("/api/logs", 200).await;
+    assert_eq!(logs, serde_json::json!([]));
+}
+
+#[actix_web::test]
+async fn imports_are_authenticated_before_the_upload_is_read() {
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("s3cret".into())));
+    let server = TestServer::start(app);
+    let upload = format!(
+        "{}\n{}\n",
+        log_batch("imported-1", &["https://a.example/"]),
+        log_batch("imported-2", &["https://b.example/", "https://c.example/"])
+    );
+
+    let resp = server
+        .post("/api/logs/import")
+        .body(upload.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 401).await["code"], "unauthorized");
+
+    let resp = server
+        .post("/api/logs/import")
+        .bearer_auth("s3cret")
+        .header("Content-Encoding", "gzip")
+        .body(gzip(upload.as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    let report = expect_json(resp, 200).await["report"].clone();
+    assert_eq!(report["format"], "ndjson");
+    assert_eq!(report["imported"], 3);
+}
+
+#[actix_web::test]
+async fn corrupt_gzip_is_rejected() {
+    let server = TestServer::simple();
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .body(b"definitely not gzip".to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 0);
+}
+
+#[actix_web::test]
+async fn malformed_json_gets_the_error_envelope() {
+    let server = TestServer::simple();
+
+    for path in ["/api/logs", "/api/extensions", "/api/security"] {
+        let resp = server
+            .post(path)
+            .header("Content-Type", "application/json")
+            .body("{\"session_id\": ")
+            .send()
+            .await
+            .unwrap();
+        let resp = expect_json(resp, 400).await;
+        assert_eq!(resp["success"], false, "{}", path);
+        assert_eq!(resp["code"], "invalid_json", "{}", path);
+    }
+
+    // Valid JSON, wrong shape.
+    let resp = server
+        .post_json("/api/logs", &serde_json::json!({ "logs": "nope" }), 400)
+        .await;
+    assert_eq!(resp["code"], "invalid_json");
+
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 0);
+}
+
+#[actix_web::test]
+async fn client_ids_outside_the_allowed_charset_or_length_are_refused() {
+    let server = TestServer::simple();
+    let too_long = "c".repeat(129);
+    for id in [
+        "x\"><img src=x onerror=alert(1)>",
+        "has space",
+        "",
+        too_long.as_str(),
+    ] {
+        let resp = server
+            .post_json("/api/logs", &log_batch(id, &["https://a.example/"]), 400)
+            .await;
+        assert_eq!(resp["code"], "bad_request", "{:?}", id);
+        let event = extension_event(id, "extension_installed", serde_json::json!({}));
+        server.post_json("/api/extensions", &event, 400).await;
+        server.post_json("/api/security", &event, 400).await;
+    }
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 0);
+
+    let fine = "laptop-7.org_a:user@example.com";
+    server
+        .post_json("/api/logs", &log_batch(fine, &["https://a.example/"]), 200)
+        .await;
+}
+
+#[actix_web::test]
+async fn versioned_routes_answer_in_the_envelope_and_legacy_ones_are_deprecated() {
+    let server = TestServer::simple();
+
+    let resp = server
+        .post("/api/v1/logs")
+        .json(&log_batch("client-v1", &["https://a.example/"]))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.headers().get("deprecation").is_none());
+    let resp = expect_json(resp, 200).await;
+    assert_eq!(resp["data"]["success"], true);
+    assert_eq!(resp["meta"]["api_version"], "v1");
+
+    let resp = server
+        .get_json("/api/v1/logs?client_id=client-v1&fields=client_id", 200)
+        .await;
+    assert_eq!(
+        resp["data"],
+        serde_json::json!([{ "client_id": "client-v1" }])
+    );
+
+    // Errors keep their code and details under `error`.
+    let resp = server
+        .get_json("/api/v1/dashboard/events/sec-unknown", 404)
+        .await;
+    assert_eq!(resp["error"]["code"], "not_found");
+    assert_eq!(resp["error"]["message"], "packet not found");
+    assert_eq!(resp["error"]["packet_id"], "sec-unknown");
+    assert!(resp.get("data").is_none());
+    let resp = server
+        .post("/api/v1/logs")
+        .header("Content-Type", "application/json")
+        .body("{\"session_id\": ")
+        .send()
+        .await
+        .unwrap();
+    let resp = expect_json(resp, 400).await;
+    assert_eq!(resp["error"]["code"], "invalid_json");
+    let resp = server.get_json("/api/v1/no-such-thing", 404).await;
+    assert_eq!(resp["error"]["code"], "not_found");
+
+    // Downloads stay as they are.
+    let resp = server
+        .get("/api/v1/dashboard/events/export")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+
+    let resp = server
+        .get("/api/logs?client_id=client-v1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["deprecation"], "true");
+    assert_eq!(
+        resp.headers()["link"],
+        "</api/v1/logs>; rel=\"successor-version\""
+    );
+    let resp = expect_json(resp, 200).await;
+    assert_eq!(resp[0]["client_id"], "client-v1");
+
+    let resp = server.get("/health").send().await.unwrap();
+    assert!(resp.headers().get("deprecation").is_none());
+}
+
+#[actix_web::test]
+async fn extension_errors_are_grouped_by_stack_and_counted() {
+    let server = TestServer::simple();
+    let report = |install: &str, client: &str, version: &str| {
+        serde_json::json!({
+            "client_id": client,
+            "message": "TypeError: Cannot read properties of undefined (reading 'tabId')",
+            "stack": format!(
+                "TypeError: Cannot read properties of undefined (reading 'tabId')\n    at flush (chrome-extension://{}/background.js:120:17)\n    at async tick (chrome-extension://{}/background.js:88:5)",
+                install, install
+            ),
+            "source": "background",
+            "extension_version": version
+        })
+    };
+
+    let first = server
+        .post_json("/api/errors", &report("abcdef", "client-1", "3.0.0"), 200)
+        .await;
+    assert_eq!(first["new"], true);
+    assert_eq!(first["count"], 1);
+    // Another install of the same build: same stack once the id is taken out.
+    let again = server
+        .post_json("/api/errors", &report("ghijkl", "client-2", "3.0.1"), 200)
+        .await;
+    assert_eq!(again["hash"], first["hash"]);
+    assert_eq!(again["count"], 2);
+    assert_eq!(again["new"], false);
+
+    let other = serde_json::json!({ "message": "Quota exceeded", "source": "content" });
+    let other = server.post_json("/api/errors", &other, 200).await;
+    assert_ne!(other["hash"], first["hash"]);
+
+    let resp = server
+        .post_json("/api/errors", &serde_json::json!({ "message": "  " }), 400)
+        .await;
+    assert_eq!(resp["code"], "bad_request");
+
+    let list = server.get_json("/api/errors", 200).await;
+    let errors = list["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    let grouped = errors.iter().find(|e| e["hash"] == first["hash"]).unwrap();
+    assert_eq!(grouped["count"], 2);
+    assert_eq!(grouped["extension_version"], "3.0.1");
+    assert_eq!(grouped["last_client_id"], "client-2");
+    assert!(grouped["first_seen"].as_str().unwrap() <= grouped["last_seen"].as_str().unwrap());
+
+    let hash = first["hash"].as_str().unwrap();
+    let one = server.get_json(&format!("/api/errors/{}", hash), 200).await;
+    assert!(one["stack"]
+        .as_str()
+        .unwrap()
+        .contains("chrome-extension://abcdef/"));
+    let missing = server.get_json("/api/errors/nope", 404).await;
+    assert_eq!(missing["hash"], "nope");
+}
+
+#[actix_web::test]
+async fn performance_samples_are_aggregated_per_extension_version() {
+    let server = TestServer::simple();
+    let report = |version: &str, match_ms: Vec<f64>| {
+        serde_json::json!({
+            "client_id": "client-1",
+            "extension_version": version,
+            "blocklist_rules": 1200,
+            "samples": {
+                "blocklist_match_ms": match_ms,
+                "batch_build_ms": [3.0, 5.0],
+                "memory_mb": [40.0]
+            }
+        })
+    };
+
+    let fast = server
+        .post_json("/api/telemetry", &report("3.0.0", vec![0.5; 30]), 200)
+        .await;
+    assert_eq!(fast["slow"], false);
+    // Over the default 5ms threshold once enough samples are in.
+    let slow = server
+        .post_json("/api/telemetry", &report("3.1.0", vec![12.0; 30]), 200)
+        .await;
+    assert_eq!(slow["slow"], true);
+
+    let bad = serde_json::json!({
+        "extension_version": "3.0.0",
+        "samples": { "blocklist_match_ms": [-1.0] }
+    });
+    server.post_json("/api/telemetry", &bad, 400).await;
+    let unknown = serde_json::json!({
+        "extension_version": "3.0.0",
+        "samples": { "cpu_ms": [1.0] }
+    });
+    server.post_json("/api/telemetry", &unknown, 400).await;
+
+    let list = server.get_json("/api/telemetry", 200).await;
+    let versions = list["versions"].as_array().unwrap();
+    assert_eq!(versions.len(), 2);
+    let one = server.get_json("/api/telemetry/3.0.0", 200).await;
+    assert_eq!(one["reports"], 1);
+    assert_eq!(one["blocklist_rules"], 1200);
+    assert_eq!(one["metrics"]["blocklist_match_ms"]["count"], 30);
+    assert_eq!(one["metrics"]["batch_build_ms"]["mean"], 4.0);
+    assert_eq!(one["metrics"]["memory_mb"]["max"], 40.0);
+    server.get_json("/api/telemetry/9.9.9", 404).await;
+}
+
+#[actix_web::test]
+async fn chaos_mode_fails_delays_and_drops_ingest_answers() {
+    let chaos = |spec: &str| {
+        let mut app = AppState::simple();
+        app.chaos = web::Data::new(Chaos::parse(spec).unwrap());
+        TestServer::start(app)
+    };
+
+    // Failed before anything is stored; reads are left alone.
+    let server = chaos("error=1");
+    let resp = server
+        .post_json(
+            "/api/logs",
+            &log_batch("client-chaos", &["https://a.example/"]),
+            500,
+        )
+        .await;
+    assert_eq!(resp["code"], "chaos");
+    assert_eq!(resp["dropped"], false);
+    let stored = server.get_json("/api/logs", 200).await;
+    assert!(stored.as_array().unwrap().is_empty());
+
+    // Stored, but the answer comes late and says it timed out.
+    let server = chaos("latency=200ms,drop=1,hold=100ms");
+    let started = std::time::Instant::now();
+    let resp = server
+        .post_json(
+            "/api/logs",
+            &log_batch("client-chaos", &["https://b.example/"]),
+            504,
+        )
+        .await;
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(resp["dropped"], true);
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 1);
+
+    let server = chaos("latency=10ms..20ms");
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("client-chaos", &["https://c.example/"]),
+            200,
+        )
+        .await;
+
+    assert!(Chaos::parse("error=2").is_err());
+    assert!(Chaos::parse("latency=2s..1s").is_err());
+    assert!(Chaos::parse("error=0.6,drop=0.6").is_err());
+    assert!(Chaos::parse("jitter=1s").is_err());
+}
+
+#[actix_web::test]
+async fn dry_run_returns_the_record_without_storing_it() {
+    let server = TestServer::simple();
+    let batch = log_batch("client-dry", &["https://example.com/"]);
+
+    let resp = server
+        .post_json("/api/logs?dry_run=true", &batch, 200)
+        .await;
+    assert_eq!(resp["dry_run"], true);
+    assert_eq!(resp["record"]["client_id"], "client-dry");
+
+    let resp = server
+        .post_json("/api/logs?dry_run=maybe", &batch, 400)
+        .await;
+    assert_eq!(resp["code"], "bad_request");
+
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 0);
+}
+
+#[actix_web::test]
+async fn oversized_batches_are_refused_or_split() {
+    let urls = [
+        "https://a.example/",
+        "https://b.example/",
+        "https://c.example/",
+    ];
+
+    let mut app = AppState::simple();
+    app.batch_limit = web::Data::new(BatchLimit {
+        max_logs: 2,
+        oversized: OversizedBatch::Reject,
+    });
+    let server = TestServer::start(app);
+    let resp = server
+        .post_json("/api/logs", &log_batch("client-big", &urls), 413)
+        .await;
+    assert_eq!(resp["code"], "payload_too_large");
+    assert_eq!(resp["max_batch_logs"], 2);
+    assert_eq!(
+        server.get_json("/api/logs", 200).await,
+        serde_json::json!([])
+    );
+
+    let mut app = AppState::simple();
+    app.batch_limit = web::Data::new(BatchLimit {
+        max_logs: 2,
+        oversized: OversizedBatch::Split,
+    });
+    let server = TestServer::start(app);
+    let resp = server
+        .post_json("/api/logs", &log_batch("client-big", &urls), 200)
+        .await;
+    assert_eq!(resp["batches"], 2);
+    let stored = server.get_json("/api/logs", 200).await;
+    let sizes: Vec<usize> = stored
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["logs"].as_array().unwrap().len())
+        .collect();
+    assert_eq!(sizes, [2, 1]);
+}
+
+#[actix_web::test]
+async fn logs_of_dropped_request_types_are_never_stored() {
+    let mut app = AppState::simple();
+    app.request_types = web::Data::new(RequestTypeFilter::new(&["image".into(), "Font".into()]));
+    let server = TestServer::start(app);
+    let urls = [
+        "https://a.example/",
+        "https://a.example/logo.png",
+        "https://a.example/x.woff2",
+        "https://a.example/y.png",
+    ];
+    let mut batch = log_batch("laptop-1", &urls);
+    let types = ["main_frame", "image", "font", "IMAGE"];
+    for (log, request_type) in batch["logs"].as_array_mut().unwrap().iter_mut().zip(types) {
+        log["type"] = request_type.into();
+    }
+
+    let dry_run = server
+        .post_json("/api/logs?dry_run=true", &batch, 200)
+        .await;
+    assert_eq!(dry_run["summary"]["dropped_count"], 3);
+    assert_eq!(dry_run["summary"]["logs_count"], 1);
+
+    let resp = server
+        .post_json("/api/logs?response=detailed", &batch, 200)
+        .await;
+    assert_eq!(resp["logs_count"], 1);
+    assert_eq!(resp["dropped_count"], 3);
+    assert_eq!(
+        resp["dropped"],
+        serde_json::json!({ "font": 1, "image": 2 })
+    );
+    let stored = server.get_json("/api/logs", 200).await;
+    let urls: Vec<&str> = stored[0]["logs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(urls, ["https://a.example/"]);
+
+    let metrics = server
+        .get("/metrics")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let dropped: u64 = metrics
+        .lines()
+        .find_map(|l| l.strip_prefix("canigoin_request_type_dropped_total "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(dropped >= 3);
+}
+
+#[actix_web::test]
+async fn urls_are_stored_in_canonical_form_with_normalize_urls() {
+    let urls = [
+        "HTTPS://Example.COM:443/a/./b/../c?utm_source=mail&b=2&a=1&UTM_Medium=x#top",
+        "http://example.com:80/?fbclid=abc",
+        "https://example.com:8443/?q=1&q=0",
+        "chrome-extension://abcdef/popup.html",
+        "not a url",
+    ];
+    let server = TestServer::simple();
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+    let stored = server.get_json("/api/logs", 200).await;
+    assert_eq!(stored[0]["logs"][0]["url"], urls[0], "off by default");
+
+    let args = Args::try_parse_from(["server", "--normalize-urls"]).unwrap();
+    let mut app = AppState::simple();
+    app.url_normalizer = web::Data::new(UrlNormalizer::new(&args.strip_query_params));
+    let server = TestServer::start(app);
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+    let stored = server.get_json("/api/logs", 200).await;
+    let stored: Vec<&str> = stored[0]["logs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        stored,
+        [
+            "https://example.com/a/c?a=1&b=2",
+            "http://example.com/",
+            "https://example.com:8443/?q=1&q=0",
+            "chrome-extension://abcdef/popup.html",
+            "not a url",
+        ]
+    );
+
+    let only_ref = UrlNormalizer::new(&["ref".to_string()]);
+    assert_eq!(
+        only_ref.normalize("https://shop.example/?utm_source=x&ref=home"),
+        "https://shop.example/?utm_source=x"
+    );
+}
+
+#[actix_web::test]
+async fn large_gzip_batches_are_parsed_as_they_decompress() {
+    // Over the 256 KB body limit once decompressed, which only gzip without
+    // Content-Encoding gets past.
+    let urls: Vec<String> = (0..5000)
+        .map(|i| format!("https://site{}.example/", i))
+        .collect();
+    let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+    let json = log_batch("client-stream", &urls).to_string();
+
+    let server = TestServer::simple();
+    let resp = server
+        .post("/api/logs?dry_run=true")
+        .header("Content-Type", "application/json")
+        .body(gzip(json.as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    let resp = expect_json(resp, 200).await;
+    assert_eq!(resp["decompressed_bytes"], json.len());
+    assert_eq!(resp["summary"]["logs_count"], 5000);
+
+    // Parsing stops at the first log over the limit, so what follows it
+    // is never read.
+    let mut app = AppState::simple();
+    app.batch_limit = web::Data::new(BatchLimit {
+        max_logs: 2,
+        oversized: OversizedBatch::Reject,
+    });
+    let server = TestServer::start(app);
+    let cut = json.find("site3.example").unwrap();
+    let truncated = format!("{}not json at all", &json[..cut]);
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .body(gzip(truncated.as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    let resp = expect_json(resp, 413).await;
+    assert_eq!(resp["max_batch_logs"], 2);
+
+    let resp = server
+        .post("/api/logs?dry_run=true")
+        .header("Content-Type", "application/json")
+        .body(gzip(truncated.as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["code"], "invalid_json");
+}
+
+#[actix_web::test]
+async fn oversized_event_data_is_truncated_or_refused() {
+    let mut nested = serde_json::json!("bottom");
+    for _ in 0..10 {
+        nested = serde_json::json!([nested]);
+    }
+    let data = serde_json::json!({
+        "url": "https://page.example/",
+        "script": "x".repeat(5000),
+        "nested": nested,
+    });
+    let limit = |oversized| EventDataLimit {
+        max_depth: 4,
+        max_bytes: 1000,
+        oversized,
+    };
+
+    let mut app = AppState::simple();
+    app.event_data_limit = web::Data::new(limit(OversizedEventData::Reject));
+    let server = TestServer::start(app);
+    let event = extension_event("client-big", "javascript_execution", data.clone());
+    let resp = server.post_json("/api/extensions", &event, 413).await;
+    assert_eq!(resp["code"], "payload_too_large");
+    assert_eq!(resp["data_depth"], 11);
+    assert_eq!(resp["max_event_data_depth"], 4);
+    let events = server.get_json("/api/dashboard/events", 200).await;
+    assert_eq!(events["events"].as_array().unwrap().len(), 0);
+
+    let mut app = AppState::simple();
+    app.event_data_limit = web::Data::new(limit(OversizedEventData::Truncate));
+    let server = TestServer::start(app);
+    let resp = server
+        .post_json("/api/security?dry_run=true", &event, 200)
+        .await;
+    let stored = &resp["record"]["data"];
+    assert!(resp["warnings"][0].as_str().unwrap().contains("truncated"));
+    assert_eq!(stored["url"], "https://page.example/");
+    assert!(stored.get("script").is_none());
+    assert_eq!(stored["nested"][0][0][0], "[truncated]");
+    assert_eq!(
+        stored["_truncated"]["dropped_fields"],
+        serde_json::json!(["script"])
+    );
+}
+
+#[actix_web::test]
+async fn a_maintenance_pause_refuses_ingest_and_survives_a_restart() {
+    let path =
+        std::env::temp_dir().join(format!("canigoin-maintenance-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let batch = log_batch("client-1", &["https://example.com/"]);
+
+    let mut app = AppState::simple();
+    app.maintenance = web::Data::new(Maintenance::load(&path).unwrap());
+    let server = TestServer::start(app);
+    let pause = serde_json::json!({ "duration": "2h", "reason": "database upgrade" });
+    let state = server
+        .post_json("/api/admin/maintenance/pause", &pause, 200)
+        .await;
+    assert_eq!(state["paused"], true);
+
+    let resp = server.post("/api/logs").json(&batch).send().await.unwrap();
+    let retry_after: i64 = resp.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 7000);
+    let body = expect_json(resp, 503).await;
+    assert_eq!(body["code"], "maintenance");
+    assert_eq!(body["reason"], "database upgrade");
+    // Reads weren't paused.
+    server.get_json("/api/logs", 200).await;
+
+    let mut app = AppState::simple();
+    app.maintenance = web::Data::new(Maintenance::load(&path).unwrap());
+    let server = TestServer::start(app);
+    server.post_json("/api/logs", &batch, 503).await;
+    let resumed = server
+        .post_json("/api/admin/maintenance/resume", &serde_json::json!({}), 200)
+        .await;
+    assert_eq!(resumed["paused"], false);
+    server.post_json("/api/logs", &batch, 200).await;
+    assert!(Maintenance::load(&path).unwrap().current().is_none());
+
+    let bad = serde_json::json!({ "ends_at": "2020-01-01T00:00:00Z" });
+    server
+        .post_json("/api/admin/maintenance/pause", &bad, 400)
+        .await;
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Polls the backup status until the job started as `id` has finished.
+async fn finished_job(server: &TestServer, id: &serde_json::Value) -> serde_json::Value {
+    for _ in 0..100 {
+        let status = server.get_json("/api/admin/backup/status", 200).await;
+        if status["job"]["id"] == *id && status["job"]["status"] != "running" {
+            return status["job"].clone();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("the backup job didn't finish");
+}
+
+#[actix_web::test]
+async fn a_backup_restores_into_a_fresh_server_without_duplicates() {
+    let dir = std::env::temp_dir().join(format!("canigoin-backups-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let backups = || web::Data::new(Backups::new(&dir).unwrap());
+
+    let mut app = AppState::simple();
+    app.backups = backups();
+    let server = TestServer::start(app);
+    let batch = log_batch(
+        "client-1",
+        &["https://example.com/", "https://example.com/a.js"],
+    );
+    server.post_json("/api/logs", &batch, 200).await;
+    let blocklist =
+        serde_json::json!({ "urlPatterns": ["*://ads.example.org/*"], "youtubeChannels": [] });
+    server.post_json("/api/blocklist", &blocklist, 200).await;
+    server
+        .post_json(
+            "/api/admin/clients/client-2/archive",
+            &serde_json::json!({}),
+            200,
+        )
+        .await;
+
+    let started = server
+        .post_json("/api/admin/backup", &serde_json::json!({}), 202)
+        .await;
+    let job = finished_job(&server, &started["job"]["id"]).await;
+    assert_eq!(job["status"], "done", "{}", job);
+    assert_eq!(job["progress"]["logs"], 1);
+    assert_eq!(job["progress"]["archived_clients"], 1);
+    let list = server.get_json("/api/admin/backups", 200).await;
+    let file = list["backups"][0]["file"].as_str().unwrap().to_string();
+    assert_eq!(file, job["file"]);
+
+    let mut app = AppState::simple();
+    app.backups = backups();
+    let server = TestServer::start(app);
+    let restore = serde_json::json!({ "file": file });
+    for skipped in [0, 1] {
+        let started = server.post_json("/api/admin/restore", &restore, 202).await;
+        let job = finished_job(&server, &started["job"]["id"]).await;
+        assert_eq!(job["status"], "done", "{}", job);
+        assert_eq!(job["total"]["logs"], 1);
+        assert_eq!(job["skipped"]["logs"], skipped);
+    }
+    let logs = server.get_json("/api/logs", 200).await;
+    assert_eq!(logs.as_array().unwrap().len(), 1);
+    let restored = server.get_json("/api/blocklist", 200).await;
+    assert_eq!(restored["urlPatterns"], blocklist["urlPatterns"]);
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("client-2", &["https://example.com/"]),
+            410,
+        )
+        .await;
+
+    let escape = serde_json::json!({ "file": "../canigoin-backup-x.ndjson.gz" });
+    server.post_json("/api/admin/restore", &escape, 404).await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn clock_skew_is_flagged_and_corrects_time_range_filters() {
+    let server = TestServer::simple();
+    let now = chrono::Utc::now();
+    let stamp =
+        |at: chrono::DateTime<chrono::Utc>| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    for (client, sent) in [
+        ("behind", now - chrono::Duration::hours(1)),
+        ("on-time", now),
+    ] {
+        let mut batch = log_batch(client, &["https://example.com/"]);
+        batch["timestamp"] = stamp(sent).into();
+        server.post_json("/api/logs", &batch, 200).await;
+    }
+
+    let flagged = server
+        .get_json("/api/clients/clock-skew?flagged=true", 200)
+        .await;
+    let clients = flagged["clients"].as_array().unwrap();
+    assert_eq!(clients.len(), 1, "{}", flagged);
+    assert_eq!(clients[0]["client_id"], "behind");
+    assert_eq!(clients[0]["flags"], serde_json::json!(["large"]));
+    let offset = clients[0]["offset_ms"].as_i64().unwrap();
+    assert!((-3_610_000..=-3_590_000).contains(&offset), "{}", offset);
+    let on_time = server
+        .get_json("/api/clients/on-time/clock-skew", 200)
+        .await;
+    assert_eq!(on_time["flags"], serde_json::json!([]));
+
+    let from = stamp(now - chrono::Duration::minutes(10));
+    let clients_of = |logs: serde_json::Value| -> Vec<String> {
+        let logs = logs.as_array().unwrap();
+        logs.iter()
+            .map(|e| e["client_id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let raw = server
+        .get_json(&format!("/api/logs?from={}", from), 200)
+        .await;
+    assert_eq!(clients_of(raw), ["on-time"]);
+    let corrected = server
+        .get_json(&format!("/api/logs?from={}&corrected_time=true", from), 200)
+        .await;
+    assert_eq!(clients_of(corrected), ["behind", "on-time"]);
+
+    let resp = server.get("/api/logs?from=yesterday").send().await.unwrap();
+    expect_json(resp, 400).await;
+}
+
+#[actix_web::test]
+async fn clock_skew_tracks_are_capped_by_dropping_the_least_recently_seen() {
+    use network_logger_server::clock_skew::{ClockSkew, MAX_CLIENTS};
+
+    let skew = ClockSkew::default();
+    let now = chrono::Utc::now().to_rfc3339();
+    skew.observe("old-client", &now);
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    for i in 0..MAX_CLIENTS - 1 {
+        skew.observe(&format!("client-{}", i), &now);
+    }
+    assert_eq!(skew.clients().len(), MAX_CLIENTS);
+
+    skew.observe("new-client", &now);
+    assert_eq!(skew.clients().len(), MAX_CLIENTS - MAX_CLIENTS / 10 + 1);
+    assert!(skew.get("old-client").is_none());
+    assert!(skew.get("new-client").is_some());
+}
+
+#[actix_web::test]
+async fn unknown_clients_are_held_until_approved() {
+    let dir = std::env::temp_dir().join(format!("canigoin-enrollment-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let start = || {
+        let mut app = AppState::simple();
+        app.enrollment = web::Data::new(Enrollment::new(&dir).unwrap());
+        TestServer::start(app)
+    };
+
+    let server = start();
+    let held = server
+        .post_json(
+            "/api/logs",
+            &log_batch("newcomer", &["https://example.com/"]),
+            202,
+        )
+        .await;
+    assert_eq!(held["pending_approval"], true);
+    let event = extension_event("newcomer", "extension_installed", serde_json::json!({}));
+    server.post_json("/api/extensions", &event, 202).await;
+    let mut anonymous = log_batch("newcomer", &["https://example.com/"]);
+    anonymous.as_object_mut().unwrap().remove("client_id");
+    server.post_json("/api/logs", &anonymous, 403).await;
+    assert_eq!(
+        server.get_json("/api/logs", 200).await,
+        serde_json::json!([])
+    );
+    let listed = server.get_json("/api/admin/enrollment", 200).await;
+    assert_eq!(listed["pending"][0]["client_id"], "newcomer");
+    assert_eq!(listed["pending"][0]["logs"], 1);
+    assert_eq!(listed["pending"][0]["events"], 1);
+
+    let approved = server
+        .post_json("/api/clients/newcomer/approve", &serde_json::json!({}), 200)
+        .await;
+    assert_eq!(approved["logs_stored"], 1);
+    assert_eq!(approved["events_stored"], 1);
+    assert_eq!(
+        server
+            .get_json("/api/logs", 200)
+            .await
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    let events = server.get_json("/api/dashboard/events", 200).await;
+    assert_eq!(events["events"].as_array().unwrap().len(), 1, "{}", events);
+    server
+        .post_json("/api/clients/newcomer/reject", &serde_json::json!({}), 404)
+        .await;
+
+    let server = start();
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("newcomer", &["https://example.com/b"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("stranger", &["https://example.com/"]),
+            202,
+        )
+        .await;
+    server
+        .post_json("/api/clients/stranger/reject", &serde_json::json!({}), 200)
+        .await;
+    let listed = server.get_json("/api/admin/enrollment", 200).await;
+    assert_eq!(listed["pending_count"], 0);
+    assert_eq!(listed["approved_count"], 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn job_links_follow_the_forwarded_scheme_and_host() {
+    let dir = std::env::temp_dir().join(format!("canigoin-links-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut app = AppState::simple();
+    app.backups = web::Data::new(Backups::new(&dir).unwrap());
+    let server = TestServer::start(app);
+
+    let resp = server
+        .post("/api/admin/backup")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "logs.example.com")
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    let location = resp.headers()["location"].to_str().unwrap().to_string();
+    let body = expect_json(resp, 202).await;
+    assert_eq!(location, "https://logs.example.com/api/admin/backup/status");
+    assert_eq!(body["status_url"], location);
+    finished_job(&server, &body["job"]["id"]).await;
+
+    let direct = server
+        .post_json("/api/admin/backup", &serde_json::json!({}), 202)
+        .await;
+    let status_url = direct["status_url"].as_str().unwrap();
+    assert_eq!(status_url, server.url("/api/admin/backup/status"));
+    finished_job(&server, &direct["job"]["id"]).await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn accepted_batches_add_to_the_running_totals() {
+    let server = TestServer::simple();
+    let mut batch = log_batch(
+        "counted",
+        &[
+            "https://a.example/",
+            "https://a.example/",
+            "https://b.example/",
+        ],
+    );
+    batch["logs"][2]["blocked"] = serde_json::json!(true);
+    batch["logs"][2]["type"] = serde_json::json!("script");
+    let resp = server.post_json("/api/logs", &batch, 200).await;
+    assert_eq!(resp["blocked_count"], 1);
+    assert_eq!(resp["unique_urls"], 2);
+    server.post_json("/api/logs", &batch, 200).await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("other", &["https://c.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json("/api/logs?dry_run=true", &batch, 200)
+        .await;
+
+    let ingest = server.get_json("/api/stats", 200).await["ingest"].clone();
+    assert_eq!(ingest["batches"], 3);
+    assert_eq!(ingest["logs"], 7);
+    assert_eq!(ingest["blocked"], 2);
+    assert_eq!(ingest["unique_urls"], 5);
+    assert_eq!(ingest["page_views"], 5);
+    assert_eq!(ingest["clients"][0]["client_id"], "counted");
+    assert_eq!(ingest["clients"][0]["logs"], 6);
+    assert_eq!(ingest["clients"][1]["client_id"], "other");
+    let limited = server.get_json("/api/stats?limit=1", 200).await;
+    assert_eq!(limited["ingest"]["clients"].as_array().unwrap().len(), 1);
+
+    let metrics = server
+        .get("/metrics")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let logs: u64 = metrics
+        .lines()
+        .find_map(|l| l.strip_prefix("canigoin_ingested_logs_total "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(logs >= 7);
+}
+
+#[actix_web::test]
+async fn per_client_totals_are_capped_by_dropping_the_quietest() {
+    use network_logger_server::counters::{BatchSummary, IngestCounters, MAX_CLIENTS};
+
+    let counters = IngestCounters::new();
+    let batch = |logs| BatchSummary {
+        logs,
+        ..BatchSummary::default()
+    };
+    counters.record(Some("busy"), &batch(1000));
+    for i in 0..MAX_CLIENTS - 1 {
+        counters.record(Some(&format!("client-{}", i)), &batch(1));
+    }
+    assert!(counters.client("client-0").is_some());
+
+    counters.record(Some("new"), &batch(5));
+    assert_eq!(counters.client("busy").unwrap().logs, 1000);
+    assert_eq!(counters.client("new").unwrap().logs, 5);
+    let dropped = (0..MAX_CLIENTS - 1)
+        .filter(|i| counters.client(&format!("client-{}", i)).is_none())
+        .count();
+    assert_eq!(dropped, MAX_CLIENTS / 10);
+
+    let top = counters.snapshot(2).await;
+    let ids: Vec<&str> = top.clients.iter().map(|c| c.client_id.as_str()).collect();
+    assert_eq!(ids, ["busy", "new"]);
+    assert_eq!(top.total.logs, 1000 + MAX_CLIENTS as u64 - 1 + 5);
+}
+
+#[actix_web::test]
+async fn usage_is_metered_per_org_and_exported_as_csv() {
+    let dir = std::env::temp_dir().join(format!("canigoin-metering-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("usage.json");
+    let orgs: OrgsConfig = serde_json::from_value(serde_json::json!({
+        "orgs": {
+            "acme": {"clients": ["laptop-7"], "client_prefixes": ["acme-"]},
+            "acme-labs": {"client_prefixes": ["acme-labs-"]}
+        }
+    }))
+    .unwrap();
+    let mut app = AppState::simple();
+    app.metering = web::Data::new(Metering::new(orgs.clone()).with_file(&file).unwrap());
+    let metering = app.metering.clone();
+    let server = TestServer::start(app);
+
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("acme-1", &["https://a.example/", "https://b.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("laptop-7", &["https://c.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("acme-labs-1", &["https://d.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("stranger", &["https://e.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs?dry_run=true",
+            &log_batch("acme-1", &["https://f.example/"]),
+            200,
+        )
+        .await;
+    let event = extension_event(
+        "acme-2",
+        "extension_installed",
+        serde_json::json!({"id": "x"}),
+    );
+    server.post_json("/api/extensions", &event, 200).await;
+    server
+        .get_json("/api/blocklist?client_id=acme-1", 200)
+        .await;
+
+    let month = chrono::Utc::now().format("%Y-%m").to_string();
+    let report = server.get_json("/api/admin/usage", 200).await;
+    assert_eq!(report["month"], month);
+    assert_eq!(report["source"], "memory");
+    let orgs_seen: Vec<&str> = report["orgs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["org"].as_str().unwrap())
+        .collect();
+    assert_eq!(orgs_seen, ["acme", "acme-labs", "unassigned"]);
+    let acme = server.get_json("/api/admin/usage?org=acme", 200).await["orgs"][0].clone();
+    assert_eq!(acme["total"]["logs"], 3);
+    assert_eq!(acme["total"]["events"], 1);
+    assert_eq!(acme["total"]["api_calls"], 4);
+    assert!(acme["total"]["stored_bytes"].as_u64().unwrap() > 0);
+    assert_eq!(acme["days"].as_array().unwrap().len(), 1);
+
+    let resp = server
+        .get("/api/admin/usage?format=csv&org=acme-labs")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()["content-disposition"],
+        format!("attachment; filename=\"usage-{}.csv\"", month).as_str()
+    );
+    let csv = resp.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "org,month,day,logs,events,stored_bytes,api_calls");
+    assert_eq!(lines.len(), 3);
+    assert!(lines[2].starts_with(&format!("acme-labs,{},total,1,0,", month)));
+    assert!(lines[2].ends_with(",1"));
+
+    let past = server.get_json("/api/admin/usage?month=2020-01", 200).await;
+    assert_eq!(past["orgs"].as_array().unwrap().len(), 0);
+    server.get_json("/api/admin/usage?month=2020-13", 400).await;
+
+    metering.flush();
+    let reloaded = Metering::new(orgs).with_file(&file).unwrap();
+    let report = reloaded.report(Some("acme"), &month).await.unwrap();
+    assert_eq!(report.orgs[0].total.logs, 3);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn orgs_name_the_shard_their_clients_data_is_stored_in() {
+    let orgs: OrgsConfig = serde_json::from_value(serde_json::json!({
+        "shards": {"eu": "postgres://logger@db.eu.example/logs"},
+        "orgs": {
+            "acme-de": {"client_prefixes": ["acme-de-"], "shard": "eu"},
+            "acme": {"client_prefixes": ["acme-"]}
+        }
+    }))
+    .unwrap();
+    orgs.check_shards().unwrap();
+    assert_eq!(orgs.shard_of(Some("acme-de-1")), Some("eu"));
+    assert_eq!(orgs.shard_of(Some("acme-us-1")), None);
+    assert_eq!(orgs.shard_of(Some("stranger")), None);
+    assert_eq!(orgs.shard_of(None), None);
+
+    let dir = std::env::temp_dir().join(format!("canigoin-shards-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("orgs.json");
+    std::fs::write(
+        &file,
+        r#"{"orgs": {"acme-fr": {"client_prefixes": ["acme-fr-"], "shard": "fr"}}}"#,
+    )
+    .unwrap();
+    let err = OrgsConfig::load(&file).unwrap_err();
+    assert!(err.contains("unknown shard \"fr\""), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn ingest_responses_come_minimal_summary_or_detailed() {
+    let server = TestServer::simple();
+    let mut batch = log_batch("detail", &["https://example.com/", ""]);
+    batch["logs"][1]["method"] = serde_json::json!("");
+
+    let resp = server
+        .post("/api/logs?response=minimal")
+        .json(&batch)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+    assert!(resp.bytes().await.unwrap().is_empty());
+
+    let summary = server.post_json("/api/logs", &batch, 200).await;
+    assert_eq!(summary["logs_count"], 2);
+    assert!(summary.get("logs").is_none());
+    assert!(summary["client_ip"].is_string());
+
+    let detailed = server
+        .post_json("/api/logs?response=detailed", &batch, 200)
+        .await;
+    assert_eq!(detailed["logs_count"], 2);
+    assert_eq!(detailed["logs"][0]["request_id"], "req-0");
+    assert_eq!(detailed["logs"][0]["warnings"], serde_json::json!([]));
+    assert_eq!(
+        detailed["logs"][1]["warnings"],
+        serde_json::json!(["url is empty", "method is empty"])
+    );
+    assert_eq!(detailed["warnings"][0], "logs[1].url is empty");
+    assert!(detailed["packet_ids"].is_array());
+    let stored = server.get_json("/api/logs?client_id=detail", 200).await;
+    assert_eq!(stored.as_array().unwrap().len(), 3);
+
+    let event = extension_event(
+        "detail",
+        "extension_installed",
+        serde_json::json!("not an object"),
+    );
+    let stored = server
+        .post_json("/api/extensions?response=detailed", &event, 200)
+        .await;
+    assert!(stored["packet_id"].is_string());
+    assert!(stored["warnings"][0]
+        .as_str()
+        .unwrap()
+        .starts_with("data is not an object"));
+    let resp = server
+        .post("/api/security?response=minimal")
+        .json(&event)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+
+    let resp = server
+        .post("/api/logs?response=verbose")
+        .json(&batch)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[test]
+fn http_tuning_starts_from_the_mode_and_flags_override_it() {
+    let parse = |flags: &[&str]| {
+        let argv = std::iter::once("network-logger-server").chain(flags.iter().copied());
+        Args::try_parse_from(argv).unwrap().http_tuning()
+    };
+    let simple = parse(&[]);
+    assert_eq!(simple.keep_alive, Duration::from_secs(5));
+    assert!(!simple.http2);
+    let production = parse(&["--mode", "production"]);
+    assert_eq!(production.keep_alive, Duration::from_secs(75));
+    assert_eq!(production.client_request_timeout, Duration::from_secs(10));
+    assert!(production.http2);
+
+    let tuned = parse(&[
+        "--mode",
+        "hybrid",
+        "--keep-alive",
+        "0",
+        "--http2",
+        "false",
+        "--workers",
+        "2",
+        "--client-request-timeout",
+        "30s",
+        "--max-connections",
+        "5000",
+    ]);
+    assert!(tuned.keep_alive.is_zero());
+    assert!(!tuned.http2);
+    assert_eq!(tuned.workers, Some(2));
+    assert_eq!(tuned.client_request_timeout, Duration::from_secs(30));
+    assert_eq!(tuned.client_disconnect_timeout, Duration::from_secs(5));
+    assert_eq!(tuned.max_connections, 5000);
+    assert_eq!(tuned.to_json()["keep_alive_secs"], 0.0);
+}
+
+#[actix_web::test]
+async fn accepted_payloads_are_copied_to_the_lake_as_ndjson() {
+    let objects: Arc<Mutex<Vec<(String, String)>>> = Arc::default();
+    let sink = objects.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let bucket = format!("http://{}/lake/raw", listener.local_addr().unwrap());
+    let minio = actix_web::HttpServer::new(move || {
+        let sink = sink.clone();
+        actix_web::App::new().default_service(web::to(
+            move |req: actix_web::HttpRequest, body: web::Bytes| {
+                let sink = sink.clone();
+                async move {
+                    assert!(req.headers().contains_key("authorization"));
+                    let body = String::from_utf8(body.to_vec()).unwrap();
+                    // Keys are percent-encoded: `date%3D...`.
+                    let path = req.path().replace("%3D", "=");
+                    sink.lock().unwrap().push((path, body));
+                    actix_web::HttpResponse::Ok().finish()
+                }
+            },
+        ))
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(minio);
+    std::env::set_var("AWS_ACCESS_KEY_ID", "minio");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "minio-secret");
+    lake::install(worm::S3::new(&bucket, "us-east-1", 0).unwrap());
+    let server = TestServer::simple();
+
+    let batch = log_batch(
+        "lake:client",
+        &["https://a.example/", "https://b.example/x"],
+    );
+    server.post_json("/api/logs", &batch, 200).await;
+    let event = extension_event("lake:client", "page_visit", serde_json::json!({}));
+    let stored = server.post_json("/api/extensions", &event, 200).await;
+
+    let mut mine = Vec::new();
+    for _ in 0..50 {
+        mine = objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| path.contains("/client=lake_client/"))
+            .cloned()
+            .collect();
+        if mine.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    mine.sort();
+    assert_eq!(mine.len(), 2, "{:?}", mine);
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let (events_path, event_body) = &mine[0];
+    assert!(events_path.starts_with(&format!("/lake/raw/events/date={}/", today)));
+    assert!(events_path.ends_with(".ndjson"));
+    let line: serde_json::Value = serde_json::from_str(event_body.trim_end()).unwrap();
+    assert_eq!(line["data"]["packet_id"], stored["packet_id"]);
+    assert_eq!(line["client_ip"], "127.0.0.1");
+
+    let (logs_path, logs_body) = &mine[1];
+    assert!(logs_path.starts_with(&format!("/lake/raw/logs/date={}/", today)));
+    let lines: Vec<serde_json::Value> = logs_body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["url"], "https://b.example/x");
+    assert_eq!(lines[1]["client_id"], "lake:client");
+    assert!(lines[1]["received_at"].is_string());
+}
+
+fn ber(tag: u8, content: &[u8]) -> Vec<u8> {
+    assert!(content.len() < 0x80);
+    [&[tag, content.len() as u8][..], content].concat()
+}
+
+/// A directory that knows one user, `dana`, and counts the searches.
+fn fake_directory(searches: Arc<Mutex<Vec<String>>>) -> String {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            loop {
+                let mut head = [0u8; 2];
+                if stream.read_exact(&mut head).is_err() {
+                    break;
+                }
+                let mut len = head[1] as usize;
+                if head[1] & 0x80 != 0 {
+                    let mut bytes = vec![0u8; (head[1] & 0x7f) as usize];
+                    stream.read_exact(&mut bytes).unwrap();
+                    len = bytes.iter().fold(0, |acc, b| acc << 8 | *b as usize);
+                }
+                let mut body = vec![0u8; len];
+                stream.read_exact(&mut body).unwrap();
+                let id = body[2];
+                let reply =
+                    |op: Vec<u8>| [ber(0x30, &[&[0x02, 0x01, id][..], &op].concat())].concat();
+                let done = |tag: u8| ber(tag, &[0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+                match body[2 + body[1] as usize] {
+                    0x60 => stream.write_all(&reply(done(0x61))).unwrap(),
+                    0x63 => {
+                        let text = String::from_utf8_lossy(&body).to_string();
+                        let user = if text.contains("dana") {
+                            "dana"
+                        } else {
+                            "other"
+                        };
+                        searches.lock().unwrap().push(user.to_string());
+                        if user == "dana" {
+                            let attribute = ber(
+                                0x30,
+                                &[
+                                    ber(0x04, b"displayName"),
+                                    ber(0x31, &ber(0x04, b"Dana Scully")),
+                                ]
+                                .concat(),
+                            );
+                            let entry = ber(
+                                0x64,
+                                &[
+                                    ber(
+                                        0x04,
+                                        b"CN=Dana Scully,OU=Finance,OU=Staff,DC=acme,DC=local",
+                                    ),
+                                    ber(0x30, &attribute),
+                                ]
+                                .concat(),
+                            );
+                            stream.write_all(&reply(entry)).unwrap();
+                        }
+                        stream.write_all(&reply(done(0x65))).unwrap();
+                    }
+                    _ => break,
+                }
+            }
+        }
+    });
+    format!("ldap://{}", address)
+}
+
+#[actix_web::test]
+async fn reported_usernames_are_looked_up_in_ldap_and_name_the_client() {
+    let searches = Arc::new(Mutex::new(Vec::new()));
+    let config = serde_json::json!({
+        "url": fake_directory(searches.clone()),
+        "base_dn": "DC=acme,DC=local",
+        "bind_dn": "CN=canigoin,DC=acme,DC=local",
+        "bind_password": "secret",
+        "allow_plaintext_bind": true
+    });
+    let mut app = AppState::simple();
+    app.ldap = web::Data::new(LdapDirectory::new(serde_json::from_value(config).unwrap()).unwrap());
+    let names = app.client_names.clone();
+    let server = TestServer::start(app);
+
+    let mut batch = log_batch("laptop-9", &["https://example.com/"]);
+    batch["username"] = "ACME\\dana".into();
+    server.post_json("/api/logs", &batch, 200).await;
+    // The lookup runs after the batch is answered.
+    let mut named = None;
+    for _ in 0..100 {
+        named = names.get("laptop-9");
+        if named.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let listed = server.get_json("/api/clients/names", 200).await;
+    let entry = &listed["clients"][0];
+    assert!(named.is_some(), "laptop-9 was never named");
+    assert_eq!(entry["name"], "Dana Scully");
+    assert_eq!(entry["owner"], "dana");
+    assert_eq!(entry["department"], "Finance");
+    assert_eq!(entry["ou"], "Staff/Finance");
+    assert_eq!(entry["source"], "ldap");
+    // The username isn't kept with the logs.
+    let logs = server.get_json("/api/logs", 200).await;
+    assert!(logs[0].get("username").is_none());
+
+    // Another client of the same user is named from the cache.
+    let mut batch = log_batch("laptop-10", &["https://example.com/"]);
+    batch["username"] = "dana".into();
+    server.post_json("/api/logs", &batch, 200).await;
+    assert_eq!(names.resolve("laptop-10").as_deref(), Some("Dana Scully"));
+
+    // A name set by an admin wins over the directory.
+    let resp = server
+        .put("/api/admin/clients/laptop-9/name")
+        .json(&serde_json::json!({"name": "Kiosk"}))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    let mut batch = log_batch("laptop-9", &["https://example.com/"]);
+    batch["username"] = "dana".into();
+    server.post_json("/api/logs", &batch, 200).await;
+    assert_eq!(names.resolve("laptop-9").as_deref(), Some("Kiosk"));
+
+    // Unknown users are remembered too and leave the client unnamed.
+    for _ in 0..2 {
+        let mut batch = log_batch("laptop-11", &["https://example.com/"]);
+        batch["username"] = "ghost".into();
+        server.post_json("/api/logs", &batch, 200).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(names.get("laptop-11").is_none());
+    assert_eq!(*searches.lock().unwrap(), ["dana", "other"]);
+
+    // A bind password only goes out in the clear when allowed to.
+    let directory = |config: serde_json::Value| {
+        let mut base = serde_json::json!({
+            "base_dn": "DC=acme", "bind_dn": "CN=canigoin,DC=acme", "bind_password": "secret"
+        });
+        base.as_object_mut()
+            .unwrap()
+            .extend(config.as_object().unwrap().clone());
+        LdapDirectory::new(serde_json::from_value(base).unwrap())
+    };
+    let plain = directory(serde_json::json!({ "url": "ldap://dc1.acme.local" }));
+    assert!(plain.err().unwrap().contains("in the clear"));
+    assert!(directory(serde_json::json!({ "url": "ldaps://dc1.acme.local" })).is_ok());
+    assert!(
+        directory(serde_json::json!({ "url": "ldap://dc1.acme.local", "starttls": true })).is_ok()
+    );
+    assert!(directory(serde_json::json!({ "url": "http://dc1.acme.local" })).is_err());
+}
+
+#[actix_web::test]
+async fn the_log_tail_returns_recent_batches_and_follows_new_ones_as_ndjson() {
+    let server = TestServer::simple();
+    for client in ["laptop-1", "laptop-2", "laptop-1"] {
+        server
+            .post_json(
+                "/api/logs",
+                &log_batch(client, &["https://example.com/"]),
+                200,
+            )
+            .await;
+    }
+
+    let resp = server.get("/api/logs/tail?lines=2").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+    let body = resp.text().await.unwrap();
+    let lines: Vec<serde_json::Value> = body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["client_id"], "laptop-2");
+    assert_eq!(lines[1]["client_id"], "laptop-1");
+
+    let body = server
+        .get("/api/logs/tail?client_id=laptop-1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(body.text().await.unwrap().lines().count(), 2);
+    server.get_json("/api/logs/tail?lines=5000", 400).await;
+
+    let mut follow = server
+        .get("/api/logs/tail?lines=0&follow=true&client_id=laptop-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(follow.status(), 200);
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("laptop-2", &["https://skipped.example/"]),
+            200,
+        )
+        .await;
+    server
+        .post_json(
+            "/api/logs",
+            &log_batch("laptop-3", &["https://new.example/"]),
+            200,
+        )
+        .await;
+    let mut received = Vec::new();
+    while !received.ends_with(b"\n") {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), follow.chunk())
+            .await
+            .expect("no batch followed")
+            .unwrap()
+            .expect("the tail ended");
+        received.extend_from_slice(&chunk);
+    }
+    let line: serde_json::Value = serde_json::from_slice(&received).unwrap();
+    assert_eq!(line["client_id"], "laptop-3");
+    assert_eq!(line["logs"][0]["url"], "https://new.example/");
+}
+
+#[actix_web::test]
+async fn hosts_are_mapped_to_their_asn_for_provider_stats_and_bad_asn_events() {
+    let server = TestServer::simple();
+    server.get_json("/api/stats/providers", 404).await;
+    assert!(asn::AsnDb::parse("10.0.0.0\tnot-an-ip\t64500\n").is_err());
+
+    let db = asn::AsnDb::parse(
+        "# ip2asn\n\
+         127.0.0.0\t127.255.255.255\t64496\tZZ\tLOOPBACK-EXAMPLE\n\
+         ::1\t::1\t64496\tZZ\tLOOPBACK-EXAMPLE\n\
+         10.0.0.0\t10.255.255.255\t64500\tZZ\tBULLETPROOF-HOSTING\n\
+         192.0.2.0\t192.0.2.255\t0\tNone\tNot routed\n",
+    )
+    .unwrap();
+    assert_eq!(db.len(), 3);
+    let mut app = AppState::simple();
+    app.asn = web::Data::new(asn::AsnEnricher::new(db, true, 10, &[64500]));
+    let server = TestServer::start(app);
+    let urls = [
+        "http://10.1.2.3/payload",
+        "http://10.1.2.3/again",
+        "http://192.0.2.1/",
+        "http://localhost:8080/",
+    ];
+    server
+        .post_json("/api/logs", &log_batch("laptop-1", &urls), 200)
+        .await;
+
+    let events = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    assert_eq!(events["events"].as_array().unwrap().len(), 1, "{}", events);
+    let event = &events["events"][0];
+    assert_eq!(event["event_type"], "bad_asn_host");
+    let path = format!(
+        "/api/dashboard/events/{}",
+        event["packet_id"].as_str().unwrap()
+    );
+    let full = server.get_json(&path, 200).await;
+    assert_eq!(full["data"]["ip"], "10.1.2.3");
+    assert_eq!(full["data"]["asn"], 64500);
+    assert_eq!(full["data"]["as_name"], "BULLETPROOF-HOSTING");
+
+    let stats = server.get_json("/api/stats/providers", 200).await;
+    assert_eq!(stats["resolving"], true);
+    assert_eq!(stats["unresolved"], 2);
+    assert_eq!(stats["providers"][0]["asn"], 64500);
+    assert_eq!(stats["providers"][0]["requests"], 2);
+    assert_eq!(stats["providers"][0]["hosts"], 1);
+
+    // localhost is resolved in the background and counted from then on.
+    let mut stats = serde_json::Value::Null;
+    for _ in 0..100 {
+        server
+            .post_json(
+                "/api/logs",
+                &log_batch("laptop-2", &["http://localhost:8080/"]),
+                200,
+            )
+            .await;
+        stats = server.get_json("/api/stats/providers", 200).await;
+        if stats["providers"].as_array().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let providers = stats["providers"].as_array().unwrap();
+    let loopback = providers
+        .iter()
+        .find(|p| p["asn"] == 64496)
+        .expect("localhost resolved");
+    assert_eq!(loopback["name"], "LOOPBACK-EXAMPLE");
+    assert_eq!(loopback["hosts"], 1);
+}
+
+#[actix_web::test]
+async fn upload_sizes_near_the_limit_of_u64_do_not_overflow_the_exfil_window() {
+    let mut app = AppState::simple();
+    app.exfil = web::Data::new(ExfilMonitor::new(
+        u64::MAX.saturating_mul(1024 * 1024),
+        chrono::Duration::hours(1),
+        Vec::new(),
+    ));
+    let server = TestServer::start(app);
+    let mut batch = log_batch(
+        "uploader",
+        &["https://drop.example/a", "https://drop.example/b"],
+    );
+    for log in batch["logs"].as_array_mut().unwrap() {
+        log["requestSize"] = serde_json::json!(u64::MAX);
+    }
+    server.post_json("/api/logs", &batch, 200).await;
+    server.post_json("/api/logs", &batch, 200).await;
+
+    let events = server
+        .get_json("/api/dashboard/events?filter=security", 200)
+        .await;
+    let events = events["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["event_type"], "data_exfiltration_suspected");
+    let path = format!(
+        "/api/dashboard/events/{}",
+        events[0]["packet_id"].as_str().unwrap()
+    );
+    let packet = server.get_json(&path, 200).await;
+    assert_eq!(packet["data"]["bytes_uploaded"], u64::MAX);
+}
+
+#[test]
+fn busy_domains_in_every_batch_are_not_beacons() {
+    let detector = BeaconDetector::new();
+    let start = chrono::DateTime::parse_from_rfc3339("2026-01-01T12:00:00Z").unwrap();
+    let batch = |secs: i64, urls: &[&str]| {
+        let mut batch = log_batch("beacon-host", urls);
+        batch["timestamp"] =
+            serde_json::json!((start + chrono::Duration::seconds(secs)).to_rfc3339());
+        serde_json::from_value(batch).unwrap()
+    };
+    let mut found = Vec::new();
+    // An hour of 5 second batches, each with the busy site, and a C2
+    // check-in every five minutes.
+    for i in 0..720 {
+        let secs = i * 5;
+        let urls: &[&str] = if secs % 300 == 0 {
+            &["https://busy.example/feed", "https://c2.example/ping"]
+        } else {
+            &["https://busy.example/feed"]
+        };
+        found.extend(detector.observe(&batch(secs, urls)));
+    }
+    let domains: Vec<&str> = found.iter().map(|f| f.domain.as_str()).collect();
+    assert_eq!(domains, ["c2.example"]);
+    assert_eq!(found[0].period_secs, 300.0);
+}
+
+#[actix_web::test]
+async fn reputation_lookups_run_together_and_failures_are_cached() {
+    use network_logger_server::reputation::{ReputationService, MAX_LOOKUPS_PER_BATCH};
+
+    let asked: Arc<Mutex<Vec<String>>> = Arc::default();
+    let record = asked.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let api = format!("http://{}/score/", listener.local_addr().unwrap());
+    let fake = actix_web::HttpServer::new(move || {
+        let record = record.clone();
+        actix_web::App::new().route(
+            "/score/{domain}",
+            web::get().to(move |domain: web::Path<String>| {
+                let domain = domain.into_inner();
+                record.lock().unwrap().push(domain.clone());
+                async move {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    if domain.starts_with("broken") {
+                        return actix_web::HttpResponse::InternalServerError().body("oops");
+                    }
+                    actix_web::HttpResponse::Ok().json(serde_json::json!({ "score": 80 }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(fake);
+
+    let mut app = AppState::simple();
+    app.reputation = web::Data::new(ReputationService::new(None, Some(api), 3600).unwrap());
+    let server = TestServer::start(app);
+    let urls: Vec<String> = (0..MAX_LOOKUPS_PER_BATCH + 4)
+        .map(|i| match i % 2 {
+            0 => format!("https://bad{}.example/", i),
+            _ => format!("https://broken{}.example/", i),
+        })
+        .collect();
+    let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+    let mut batch = log_batch("rep-client", &urls);
+    for log in batch["logs"].as_array_mut().unwrap() {
+        log["blocked"] = serde_json::json!(true);
+    }
+
+    // Looked up together: one lookup's latency, not one per domain.
+    let started = std::time::Instant::now();
+    let first = server.post_json("/api/logs", &batch, 200).await;
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let scored = {
+        let asked = asked.lock().unwrap();
+        assert_eq!(asked.len(), MAX_LOOKUPS_PER_BATCH);
+        asked.iter().filter(|d| d.starts_with("bad")).count()
+    };
+    assert_eq!(first["suspicious_count"], scored);
+
+    // Only the domains left over are asked about; failures aren't retried yet.
+    server.post_json("/api/logs", &batch, 200).await;
+    let asked = asked.lock().unwrap().clone();
+    assert_eq!(asked.len(), MAX_LOOKUPS_PER_BATCH + 4);
+    let distinct: std::collections::HashSet<_> = asked.iter().collect();
+    assert_eq!(distinct.len(), asked.len());
+}
+
+#[actix_web::test]
+async fn captures_leave_credentials_out() {
+    let dir = std::env::temp_dir().join(format!("canigoin-captures-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut app = AppState::simple();
+    app.capture = web::Data::new(Capture::new(&dir, 100).unwrap());
+    let server = TestServer::start(app);
+
+    server
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "username": "olga", "password": "hunter2-login" }))
+        .send()
+        .await
+        .unwrap();
+    server
+        .post("/api/admin/users")
+        .json(
+            &serde_json::json!({ "username": "olga", "role": "admin", "password": "hunter2-user" }),
+        )
+        .send()
+        .await
+        .unwrap();
+    server
+        .post("/api/admin/webhooks")
+        .json(&serde_json::json!({
+            "url": "https://hooks.example/",
+            "auth": { "bot_token": "hunter2-token", "Secret": 1234 },
+            "note": "kept as sent"
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let mut captured = Vec::new();
+    for _ in 0..100 {
+        captured = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        if captured.iter().any(|c| c.contains("kept as sent")) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(captured.len(), 1, "{:?}", captured);
+    assert!(captured[0].contains("kept as sent"));
+    assert!(!captured[0].contains("hunter2") && !captured[0].contains("1234"));
+}
@@ -0,0 +1,124 @@
+//! The server binary on the sockets it is given: a Unix socket from `--bind`
+//! and a socket passed by systemd. Each test starts the real binary, since
+//! both happen before the runtime starts.
+
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+const SERVER: &str = env!("CARGO_BIN_EXE_network-logger-server");
+
+/// Kills the server when the test ends, passing or not.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("canigoin-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn health(mut stream: impl Read + Write) -> String {
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn wait_for(what: &str, mut ready: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if ready() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(25));
+    }
+    panic!("{} never happened", what);
+}
+
+fn mode(path: &Path) -> u32 {
+    std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn unix_socket_is_created_with_the_socket_mode() {
+    let dir = scratch_dir("unix-socket");
+    let socket = dir.join("server.sock");
+    let _server = Server(
+        Command::new(SERVER)
+            .current_dir(&dir)
+            .arg("--bind")
+            .arg(format!("unix:{}", socket.display()))
+            .args(["--socket-mode", "600"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    wait_for("the socket", || UnixStream::connect(&socket).is_ok());
+    assert_eq!(mode(&socket), 0o600);
+    let response = health(UnixStream::connect(&socket).unwrap());
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn systemd_sockets_are_served_instead_of_bind() {
+    let dir = scratch_dir("systemd");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new("sh");
+    // LISTEN_PID has to be the server's own pid, which `exec` keeps.
+    command
+        .current_dir(&dir)
+        .args(["-c", r#"LISTEN_PID=$$ exec "$0" "$@""#, SERVER])
+        .args(["--bind", "unix:/nonexistent/canigoin.sock"])
+        .env("LISTEN_FDS", "1")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: dup2 and fcntl are async-signal-safe. They hand the listener
+    // over as fd 3 without close-on-exec, as systemd does; dup2 onto itself
+    // would keep the flag, hence fcntl when it already is fd 3.
+    unsafe {
+        command.pre_exec(move || {
+            let handed_over = match fd {
+                3 => libc::fcntl(3, libc::F_SETFD, 0),
+                _ => libc::dup2(fd, 3),
+            };
+            match handed_over {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        });
+    }
+    let _server = Server(command.spawn().unwrap());
+    drop(listener);
+
+    let mut response = String::new();
+    wait_for("a health response", || {
+        match std::net::TcpStream::connect(addr) {
+            Ok(stream) => response = health(stream),
+            Err(_) => return false,
+        }
+        !response.is_empty()
+    });
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
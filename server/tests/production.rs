@@ -0,0 +1,788 @@
+//! Production-mode tests against a real PostgreSQL. They need
+//! `--features production` and `TEST_DATABASE_URL` pointing at a database with
+//! `schema.sql` loaded (see README, "Running the tests"); without the variable
+//! they are skipped.
+
+#![cfg(feature = "production")]
+
+mod common;
+
+use actix_web::web;
+use common::{expect_json, extension_event, gzip, log_batch, TestServer};
+use network_logger_server::hybrid;
+use network_logger_server::metering::OrgsConfig;
+use network_logger_server::production::ProductionState;
+use network_logger_server::redis_conn::{self, SharedRedis};
+use network_logger_server::sharding::{Shard, ShardedStorage};
+use network_logger_server::simple::SimpleState;
+use network_logger_server::storage::{PoolConfig, PoolStatus, Storage};
+use network_logger_server::types::{
+    Annotation, ArchivedClient, Blocklist, CaseTicket, CategoryStats, ClientStats, DomainStats,
+    EventComment, EventTriage, ExtensionError, ExtensionEvent, LocalUser, LogEntry, ObservedDomain,
+};
+use network_logger_server::{AppState, Backend};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+async fn production_server() -> Option<TestServer> {
+    let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL not set; skipping production test");
+        return None;
+    };
+    let state = ProductionState::new(&database_url, None, None, &PoolConfig::default())
+        .await
+        .expect("cannot connect to TEST_DATABASE_URL");
+    let backend = Backend::production(Arc::new(state), Duration::from_secs(300));
+    Some(TestServer::start(AppState::new(backend)))
+}
+
+/// Unique per run, so tests don't see each other's rows in a shared database.
+fn unique(prefix: &str) -> String {
+    format!(
+        "{}-{}",
+        prefix,
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+#[actix_web::test]
+async fn gzip_batch_is_stored_and_reported_in_domains() {
+    let Some(server) = production_server().await else {
+        return;
+    };
+    let domain = format!("{}.example.com", unique("it"));
+    let batch = log_batch(&unique("client"), &[&format!("https://{}/", domain)]);
+
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .body(gzip(batch.to_string().as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 200).await["logs_count"], 1);
+
+    let domains = server.get_json("/api/domains?new_since=1h", 200).await;
+    let found = domains["domains"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|d| d["domain"] == domain.as_str());
+    assert!(found, "{} not in {}", domain, domains);
+}
+
+#[actix_web::test]
+async fn malformed_json_is_rejected_before_the_database() {
+    let Some(server) = production_server().await else {
+        return;
+    };
+    let resp = server
+        .post("/api/logs")
+        .header("Content-Type", "application/json")
+        .body("not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 400).await["code"], "invalid_json");
+}
+
+#[actix_web::test]
+async fn blocklist_round_trips_through_the_database() {
+    let Some(server) = production_server().await else {
+        return;
+    };
+    let pattern = format!("*://{}.example.com/*", unique("blocked"));
+    let blocklist = serde_json::json!({ "urlPatterns": [pattern], "youtubeChannels": [] });
+
+    server.post_json("/api/blocklist", &blocklist, 200).await;
+    let stored = server.get_json("/api/blocklist", 200).await;
+    assert_eq!(stored["urlPatterns"], blocklist["urlPatterns"]);
+}
+
+#[actix_web::test]
+async fn security_event_gets_a_packet_id() {
+    let Some(server) = production_server().await else {
+        return;
+    };
+    let event = extension_event(
+        &unique("client"),
+        "clickfix_detection",
+        serde_json::json!({}),
+    );
+    let resp = server.post_json("/api/security", &event, 200).await;
+    assert!(resp["packet_id"].as_str().unwrap().starts_with("sec-"));
+}
+
+#[actix_web::test]
+async fn imports_skip_requests_already_in_the_database() {
+    let Some(server) = production_server().await else {
+        return;
+    };
+    let client = unique("import");
+    let upload = format!(
+        "{}\n",
+        log_batch(&client, &["https://a.example/", "https://b.example/"])
+    );
+    for (imported, duplicates) in [(2, 0), (0, 2)] {
+        let resp = server
+            .post("/api/logs/import")
+            .body(upload.clone())
+            .send()
+            .await
+            .unwrap();
+        let report = expect_json(resp, 200).await["report"].clone();
+        assert_eq!(report["imported"], imported);
+        assert_eq!(report["duplicates"], duplicates);
+    }
+}
+
+#[actix_web::test]
+async fn hybrid_reads_reach_past_a_full_hot_buffer() {
+    let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL not set; skipping production test");
+        return;
+    };
+    let warm = ProductionState::new(&database_url, None, None, &PoolConfig::default())
+        .await
+        .expect("cannot connect to TEST_DATABASE_URL");
+    let warm = Arc::new(warm);
+    let writer = hybrid::spawn_warm_writer(warm.clone());
+    let hot = web::Data::new(SimpleState::new().with_warm_tier(chrono::Duration::hours(1), writer));
+    let backend = Backend::hybrid(warm, hot, Duration::from_secs(300));
+    let server = TestServer::start(AppState::new(backend));
+
+    // More batches than the hot tier holds, all well inside its window.
+    let client = unique("hot-overflow");
+    for i in 0..1100 {
+        let url = format!("https://a.example/{}", i);
+        server
+            .post_json("/api/logs", &log_batch(&client, &[&url]), 200)
+            .await;
+    }
+    let path = format!("/api/logs?client_id={}", client);
+    let mut seen = 0;
+    for _ in 0..100 {
+        seen = server.get_json(&path, 200).await.as_array().unwrap().len();
+        if seen == 1100 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(seen, 1100);
+}
+
+/// A database that keeps logs and events in memory and notes which
+/// client's logs it was asked for; it has nothing else.
+#[derive(Clone, Default)]
+struct FakeStorage {
+    logs: Arc<Mutex<Vec<LogEntry>>>,
+    events: Arc<Mutex<Vec<ExtensionEvent>>>,
+    client_reads: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait::async_trait]
+impl Storage for FakeStorage {
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: 0,
+            idle: 0,
+            max: 0,
+        }
+    }
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn add_log(&self, entry: LogEntry) -> Result<(), sqlx::Error> {
+        self.logs.lock().unwrap().push(entry);
+        Ok(())
+    }
+    async fn import_log(&self, entry: LogEntry) -> Result<usize, sqlx::Error> {
+        let n = entry.logs.len();
+        self.logs.lock().unwrap().push(entry);
+        Ok(n)
+    }
+    async fn get_domains(
+        &self,
+        _: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<ObservedDomain>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn get_blocklist(&self) -> Result<Blocklist, sqlx::Error> {
+        Ok(Blocklist {
+            url_patterns: Vec::new(),
+            youtube_channels: Vec::new(),
+            profiles: Default::default(),
+        })
+    }
+    async fn update_blocklist(&self, _: Blocklist) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn add_extension_event(&self, event: ExtensionEvent) -> Result<(), sqlx::Error> {
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+    async fn get_logs_before(
+        &self,
+        _: chrono::DateTime<chrono::Utc>,
+        _: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        Ok(self.logs.lock().unwrap().clone())
+    }
+    async fn get_client_logs(
+        &self,
+        client_id: &str,
+        _: chrono::DateTime<chrono::Utc>,
+        _: chrono::DateTime<chrono::Utc>,
+        _: i64,
+    ) -> Result<Vec<LogEntry>, sqlx::Error> {
+        self.client_reads
+            .lock()
+            .unwrap()
+            .push(client_id.to_string());
+        let logs = self.logs.lock().unwrap();
+        Ok(logs
+            .iter()
+            .filter(|e| e.client_id.as_deref() == Some(client_id))
+            .cloned()
+            .collect())
+    }
+    async fn export_logs(
+        &self,
+        _: i64,
+        _: chrono::DateTime<chrono::Utc>,
+        _: i64,
+    ) -> Result<Vec<(i64, LogEntry)>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn export_extension_events(
+        &self,
+        _: i64,
+        _: chrono::DateTime<chrono::Utc>,
+        _: i64,
+    ) -> Result<Vec<(i64, ExtensionEvent)>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn get_extension_events_before(
+        &self,
+        _: chrono::DateTime<chrono::Utc>,
+        _: i64,
+    ) -> Result<Vec<ExtensionEvent>, sqlx::Error> {
+        Ok(self.events.lock().unwrap().clone())
+    }
+    async fn get_extension_event_by_packet_id(
+        &self,
+        _: &str,
+    ) -> Result<Option<ExtensionEvent>, sqlx::Error> {
+        Ok(None)
+    }
+    async fn refresh_stats(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn get_stats(&self, _: i64) -> Result<(Vec<DomainStats>, Vec<ClientStats>), sqlx::Error> {
+        Ok((Vec::new(), Vec::new()))
+    }
+    async fn get_category_stats(&self) -> Result<Vec<CategoryStats>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn get_archived_clients(&self) -> Result<Vec<ArchivedClient>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn archive_client(&self, _: &ArchivedClient) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn unarchive_client(&self, _: &str) -> Result<bool, sqlx::Error> {
+        Ok(false)
+    }
+    async fn get_annotations(&self) -> Result<Vec<Annotation>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn add_annotation(&self, _: &Annotation) -> Result<i64, sqlx::Error> {
+        Ok(0)
+    }
+    async fn delete_annotation(&self, _: i64) -> Result<bool, sqlx::Error> {
+        Ok(false)
+    }
+    async fn get_event_triage(&self) -> Result<Vec<EventTriage>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn set_event_triage(&self, _: &EventTriage) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn get_event_comments(&self) -> Result<Vec<EventComment>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn add_event_comment(&self, _: &EventComment) -> Result<i64, sqlx::Error> {
+        Ok(0)
+    }
+    async fn get_case_tickets(&self) -> Result<Vec<CaseTicket>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn set_case_ticket(&self, _: &CaseTicket) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn get_extension_errors(&self) -> Result<Vec<ExtensionError>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn record_extension_error(&self, _: &ExtensionError) -> Result<i64, sqlx::Error> {
+        Ok(1)
+    }
+    async fn get_local_users(&self) -> Result<Vec<LocalUser>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+    async fn put_local_user(&self, _: &LocalUser) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+    async fn delete_local_user(&self, _: &str) -> Result<bool, sqlx::Error> {
+        Ok(false)
+    }
+}
+
+#[actix_web::test]
+async fn an_orgs_logs_and_events_reach_its_shard_only() {
+    let orgs: OrgsConfig = serde_json::from_value(serde_json::json!({
+        "shards": {"eu": "postgres://logger@db.eu.example/logs"},
+        "orgs": {
+            "acme-de": {"client_prefixes": ["acme-de-"], "shard": "eu"},
+            "acme": {"client_prefixes": ["acme-"]}
+        }
+    }))
+    .unwrap();
+    let (home, eu) = (FakeStorage::default(), FakeStorage::default());
+    let sharded = ShardedStorage::new(
+        Box::new(home.clone()),
+        vec![Shard {
+            name: "eu".to_string(),
+            storage: Arc::new(eu.clone()),
+        }],
+        orgs,
+    );
+    let entry = |client: &str| -> LogEntry {
+        serde_json::from_value(log_batch(client, &["https://example.com/"])).unwrap()
+    };
+    let event = |client: &str| -> ExtensionEvent {
+        serde_json::from_value(extension_event(
+            client,
+            "clickfix_detected",
+            serde_json::json!({}),
+        ))
+        .unwrap()
+    };
+    let clients = |logs: &Mutex<Vec<LogEntry>>| -> Vec<Option<String>> {
+        logs.lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.client_id.clone())
+            .collect()
+    };
+
+    sharded.add_log(entry("acme-de-1")).await.unwrap();
+    sharded.add_log(entry("acme-us-1")).await.unwrap();
+    sharded.import_log(entry("acme-de-2")).await.unwrap();
+    sharded
+        .add_extension_event(event("acme-de-1"))
+        .await
+        .unwrap();
+    sharded
+        .add_extension_event(event("stranger"))
+        .await
+        .unwrap();
+    assert_eq!(
+        clients(&eu.logs),
+        [Some("acme-de-1".to_string()), Some("acme-de-2".to_string())]
+    );
+    assert_eq!(clients(&home.logs), [Some("acme-us-1".to_string())]);
+    let event_clients = |s: &FakeStorage| -> Vec<Option<String>> {
+        s.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.client_id.clone())
+            .collect()
+    };
+    assert_eq!(event_clients(&eu), [Some("acme-de-1".to_string())]);
+    assert_eq!(event_clients(&home), [Some("stranger".to_string())]);
+
+    // One client's reads go to its database alone.
+    let now = chrono::Utc::now();
+    let logs = sharded
+        .get_client_logs("acme-de-1", now - chrono::Duration::days(1), now, 100)
+        .await
+        .unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(*eu.client_reads.lock().unwrap(), ["acme-de-1"]);
+    assert!(home.client_reads.lock().unwrap().is_empty());
+
+    // Server-wide reads ask both and merge.
+    assert_eq!(sharded.get_logs_before(now, 100).await.unwrap().len(), 3);
+    assert_eq!(
+        sharded
+            .get_extension_events_before(now, 100)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+/// A Redis that accepts connections and never answers.
+fn silent_redis() -> (std::net::TcpListener, Arc<SharedRedis>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    (listener, Arc::new(SharedRedis::open(&url).unwrap()))
+}
+
+#[actix_web::test]
+async fn quotas_fall_back_to_local_counts_when_redis_stops_answering() {
+    use network_logger_server::quotas::{QuotaLimits, Quotas, Usage};
+
+    let (_listener, redis) = silent_redis();
+    let quotas = Quotas::new(QuotaLimits {
+        logs_per_day: 5,
+        ..QuotaLimits::default()
+    })
+    .with_redis(Some(redis));
+    let three = Usage {
+        logs: 3,
+        ..Usage::default()
+    };
+    let started = std::time::Instant::now();
+    quotas.charge(Some("quiet-redis"), three).await.unwrap();
+    assert!(quotas.charge(Some("quiet-redis"), three).await.is_err());
+    assert_eq!(quotas.usage("quiet-redis").await.logs, 3);
+    assert!(
+        started.elapsed() < 6 * redis_conn::TIMEOUT,
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn distinct_counts_come_from_memory_when_redis_stops_answering() {
+    use network_logger_server::distinct::DistinctCounters;
+
+    let (_listener, redis) = silent_redis();
+    let counters = DistinctCounters::new().with_redis(Some(redis));
+    let entry: LogEntry =
+        serde_json::from_value(log_batch("quiet-redis", &["https://a.example/"])).unwrap();
+    counters.observe(&entry);
+    let started = std::time::Instant::now();
+    let snapshot = counters.snapshot().await;
+    assert_eq!(snapshot.source, "memory");
+    assert_eq!(snapshot.total.domains, 1);
+    assert!(
+        started.elapsed() < 3 * redis_conn::TIMEOUT,
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn metering_reports_from_memory_when_redis_stops_answering() {
+    use network_logger_server::metering::{Meter, Metering};
+
+    let (_listener, redis) = silent_redis();
+    let metering = Metering::new(OrgsConfig::default()).with_redis(Some(redis));
+    let started = std::time::Instant::now();
+    metering
+        .record(
+            Some("quiet-redis"),
+            Meter {
+                logs: 3,
+                ..Meter::default()
+            },
+        )
+        .await;
+    let month = chrono::Utc::now().format("%Y-%m").to_string();
+    let report = metering.report(None, &month).await.unwrap();
+    assert_eq!(report.source, "memory");
+    assert_eq!(report.orgs[0].total.logs, 3);
+    assert!(
+        started.elapsed() < 3 * redis_conn::TIMEOUT,
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn reputation_lookups_skip_redis_when_it_stops_answering() {
+    use network_logger_server::reputation::ReputationService;
+
+    let (_listener, redis) = silent_redis();
+    let reputation = ReputationService::new(None, None, 3600)
+        .unwrap()
+        .with_redis(Some(redis));
+    let domains = (0..8).map(|i| format!("d{}.example", i)).collect();
+    let started = std::time::Instant::now();
+    let scores = reputation.scores(domains).await;
+    assert_eq!(scores.len(), 8);
+    assert!(scores.values().all(Option::is_none));
+    assert!(
+        started.elapsed() < 3 * redis_conn::TIMEOUT,
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn bans_hold_locally_when_redis_stops_answering() {
+    use network_logger_server::bans::{BanList, BanPolicy, Offense};
+
+    let (_listener, redis) = silent_redis();
+    let bans = BanList::new(Some(BanPolicy {
+        auth_failures: 1,
+        malformed: 0,
+        window: chrono::Duration::minutes(5),
+        ban_duration: chrono::Duration::minutes(5),
+    }))
+    .with_redis(Some(redis));
+    let started = std::time::Instant::now();
+    assert!(bans.active_ban("192.0.2.7").await.is_none());
+    bans.record("192.0.2.7", Offense::AuthFailure).await;
+    assert!(bans.active_ban("192.0.2.7").await.is_some());
+    assert_eq!(bans.list().await.len(), 1);
+    assert!(
+        started.elapsed() < 6 * redis_conn::TIMEOUT,
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn migrate_storage_copies_a_simple_server_behind_an_admin_token() {
+    use network_logger_server::auth::AdminAuth;
+    use network_logger_server::cli::MigrateArgs;
+
+    let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL not set; skipping production test");
+        return;
+    };
+    let mut app = AppState::simple();
+    app.admin_auth = web::Data::new(AdminAuth::new(Some("migrate-secret".to_string())));
+    let source = TestServer::start(app);
+    let client = unique("migrated");
+    source
+        .post_json(
+            "/api/logs",
+            &log_batch(&client, &["https://migrated.example/"]),
+            200,
+        )
+        .await;
+    let event = extension_event(&client, "clickfix_detection", serde_json::json!({}));
+    let packet_id = source.post_json("/api/security", &event, 200).await["packet_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let archived = unique("migrated-archived");
+    let event = extension_event(&archived, "clickfix_detection", serde_json::json!({}));
+    let archived_packet_id = source.post_json("/api/security", &event, 200).await["packet_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let resp = source
+        .post(&format!("/api/admin/clients/{}/archive", archived))
+        .header("x-admin-token", "migrate-secret")
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+
+    let mut args = MigrateArgs {
+        from: source.url(""),
+        database_url: database_url.clone(),
+        no_events: false,
+        no_blocklist: true,
+        admin_token: None,
+    };
+    assert!(network_logger_server::migrate::run(&args).await.is_err());
+    args.admin_token = Some("migrate-secret".to_string());
+    network_logger_server::migrate::run(&args).await.unwrap();
+
+    let state = ProductionState::new(&database_url, None, None, &PoolConfig::default())
+        .await
+        .unwrap();
+    for id in [&packet_id, &archived_packet_id] {
+        assert!(state
+            .get_extension_event_by_packet_id(id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
+
+/// Two replicas of a simple server sharing one Redis, each set up by `with`.
+fn replicas(with: impl Fn(&mut AppState, Arc<SharedRedis>)) -> (TestServer, TestServer) {
+    let url = common::fake_redis::start();
+    let replica = || {
+        let mut app = AppState::simple();
+        with(&mut app, Arc::new(SharedRedis::open(&url).unwrap()));
+        TestServer::start(app)
+    };
+    (replica(), replica())
+}
+
+fn session_cookie(resp: &reqwest::Response) -> String {
+    resp.headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .find(|c| c.starts_with("canigoin_session="))
+        .and_then(|c| c.split(';').next())
+        .expect("no session cookie")
+        .to_string()
+}
+
+#[actix_web::test]
+async fn dashboard_sessions_and_failed_logins_are_shared_through_redis() {
+    use network_logger_server::auth::AdminAuth;
+
+    let (first, second) = replicas(|app, redis| {
+        app.admin_auth = web::Data::new(
+            AdminAuth::new(Some("replica-secret".to_string())).with_redis(Some(redis)),
+        );
+    });
+    let resp = first
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "token": "replica-secret" }))
+        .send()
+        .await
+        .unwrap();
+    let cookie = session_cookie(&resp);
+    let csrf = expect_json(resp, 200).await["csrf_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Logged in on the first replica, known to the second.
+    let resp = second
+        .get("/api/dashboard/session")
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(expect_json(resp, 200).await["csrf_token"], csrf.as_str());
+    let resp = second
+        .post("/api/dashboard/logout")
+        .header("cookie", &cookie)
+        .header("x-csrf-token", &csrf)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 200).await;
+    // Logged out on the second, gone from the first.
+    let resp = first
+        .get("/api/dashboard/session")
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 401).await;
+
+    // Failed logins count towards the same limit on every replica.
+    let wrong = serde_json::json!({ "token": "wrong" });
+    for i in 0..20 {
+        let replica = if i % 2 == 0 { &first } else { &second };
+        replica.post_json("/api/dashboard/login", &wrong, 401).await;
+    }
+    let resp = first
+        .post("/api/dashboard/login")
+        .json(&serde_json::json!({ "token": "replica-secret" }))
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, 429).await;
+}
+
+#[actix_web::test]
+async fn ingest_totals_add_up_every_replica_through_redis() {
+    use network_logger_server::counters::IngestCounters;
+
+    let (first, second) = replicas(|app, redis| {
+        app.ingest_counters = web::Data::new(IngestCounters::new().with_redis(Some(redis)));
+    });
+    first
+        .post_json(
+            "/api/logs",
+            &log_batch("replica-a", &["https://a.example/", "https://a.example/x"]),
+            200,
+        )
+        .await;
+    second
+        .post_json(
+            "/api/logs",
+            &log_batch("replica-b", &["https://b.example/"]),
+            200,
+        )
+        .await;
+
+    // The replicas add to Redis in the background.
+    let mut ingest = serde_json::Value::Null;
+    for limit in 1..=50 {
+        let stats = first
+            .get_json(&format!("/api/stats?limit={}", 10 + limit), 200)
+            .await;
+        ingest = stats["ingest"].clone();
+        if ingest["batches"] == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(ingest["source"], "redis");
+    assert_eq!(ingest["batches"], 2);
+    assert_eq!(ingest["logs"], 3);
+    assert_eq!(ingest["clients"][0]["client_id"], "replica-a");
+    assert_eq!(ingest["clients"][1]["client_id"], "replica-b");
+    assert_eq!(ingest["clients"][1]["logs"], 1);
+}
+
+#[actix_web::test]
+async fn enrollment_is_shared_through_redis() {
+    use network_logger_server::enrollment::Enrollment;
+
+    let root = std::env::temp_dir().join(format!("canigoin-replicas-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let replica = std::cell::Cell::new(0);
+    let (first, second) = replicas(|app, redis| {
+        replica.set(replica.get() + 1);
+        let dir = root.join(replica.get().to_string());
+        app.enrollment = web::Data::new(Enrollment::new(&dir).unwrap().with_redis(Some(redis)));
+    });
+    first
+        .post_json(
+            "/api/logs",
+            &log_batch("newcomer", &["https://example.com/"]),
+            202,
+        )
+        .await;
+
+    let listed = second.get_json("/api/admin/enrollment", 200).await;
+    assert_eq!(listed["pending_count"], 1);
+    assert_eq!(listed["pending"][0]["client_id"], "newcomer");
+    assert_eq!(listed["pending"][0]["logs"], 1);
+    let approved = second
+        .post_json("/api/clients/newcomer/approve", &serde_json::json!({}), 200)
+        .await;
+    assert_eq!(approved["logs_stored"], 1);
+    first
+        .post_json(
+            "/api/logs",
+            &log_batch("newcomer", &["https://example.com/b"]),
+            200,
+        )
+        .await;
+    first
+        .post_json(
+            "/api/logs",
+            &log_batch("stranger", &["https://example.com/"]),
+            202,
+        )
+        .await;
+    second
+        .post_json("/api/clients/stranger/reject", &serde_json::json!({}), 200)
+        .await;
+    let listed = first.get_json("/api/admin/enrollment", 200).await;
+    assert_eq!(listed["pending_count"], 0);
+    assert_eq!(listed["approved_count"], 1);
+    let _ = std::fs::remove_dir_all(&root);
+}
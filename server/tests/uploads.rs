@@ -0,0 +1,116 @@
+mod common;
+
+use actix_web::web;
+use common::{expect_json, TestServer};
+use network_logger_server::scanning::{ScanQueue, Scanner};
+use network_logger_server::uploads::UploadStore;
+use network_logger_server::AppState;
+use std::path::{Path, PathBuf};
+
+const BOUNDARY: &str = "canigoin-test-boundary";
+
+fn upload_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("canigoin-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn server_with_uploads(dir: &Path, max_bytes: u64) -> TestServer {
+    let mut app = AppState::simple();
+    app.uploads = web::Data::new(UploadStore::new(dir, max_bytes).unwrap());
+    TestServer::start(app)
+}
+
+fn multipart(packet_id: &str, file: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"packet_id\"\r\n\r\n{p}\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"report.pdf\"\r\n\
+         Content-Type: application/pdf\r\n\r\n",
+        b = BOUNDARY,
+        p = packet_id
+    )
+    .into_bytes();
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+async fn upload(server: &TestServer, body: Vec<u8>, status: u16) -> serde_json::Value {
+    let resp = server
+        .post("/api/security/upload")
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    expect_json(resp, status).await
+}
+
+#[actix_web::test]
+async fn upload_is_stored_by_hash_and_listed_for_its_packet() {
+    let dir = upload_dir("uploads");
+    let server = server_with_uploads(&dir, 1024);
+
+    let resp = upload(&server, multipart("sec-test-1", b"%PDF-1.4 test"), 200).await;
+    let sha256 = resp["upload"]["sha256"].as_str().unwrap().to_string();
+    assert_eq!(resp["upload"]["size"], 13);
+    assert_eq!(resp["upload"]["filename"], "report.pdf");
+    assert_eq!(std::fs::read(dir.join(&sha256)).unwrap(), b"%PDF-1.4 test");
+
+    let listed = server
+        .get_json("/api/security/uploads/sec-test-1", 200)
+        .await;
+    assert_eq!(listed["uploads"][0]["sha256"], sha256.as_str());
+    let other = server
+        .get_json("/api/security/uploads/sec-test-2", 200)
+        .await;
+    assert_eq!(other["uploads"], serde_json::json!([]));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn oversized_upload_is_refused_and_not_kept() {
+    let dir = upload_dir("uploads-oversized");
+    let server = server_with_uploads(&dir, 16);
+
+    let resp = upload(&server, multipart("sec-test-1", &[0u8; 64]), 413).await;
+    assert_eq!(resp["max_upload_bytes"], 16);
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    assert!(files.is_empty(), "{:?}", files);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn scanner_verdicts_are_attached_to_the_upload() {
+    let dir = upload_dir("uploads-scanned");
+    let mut app = AppState::simple();
+    app.uploads = web::Data::new(UploadStore::new(&dir, 1024).unwrap());
+    // `false` exits 1, which the command scanner reads as "infected".
+    let scanner = Scanner::command("false").unwrap();
+    app.scan_queue = web::Data::new(ScanQueue::spawn(vec![scanner], app.uploads.clone()));
+    let server = TestServer::start(app);
+
+    let resp = upload(&server, multipart("sec-test-1", b"payload"), 200).await;
+    assert_eq!(resp["scan_queued"], true);
+
+    let mut verdicts = serde_json::Value::Null;
+    for _ in 0..50 {
+        let listed = server
+            .get_json("/api/security/uploads/sec-test-1", 200)
+            .await;
+        verdicts = listed["uploads"][0]["verdicts"].clone();
+        if verdicts.is_array() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(verdicts[0]["scanner"], "command", "{}", verdicts);
+    assert_eq!(verdicts[0]["status"], "infected");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}